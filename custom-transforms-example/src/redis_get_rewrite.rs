@@ -110,3 +110,44 @@ fn rewrite_get(frame: &mut Frame, result: &str) {
     tracing::info!("Replaced {frame:?} with BulkString(\"{result}\")");
     *frame = Frame::Redis(RedisFrame::BulkString(result.to_owned().into()));
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use shotover::message::Message;
+    use shotover::test_utils::{assert_redis_responses, redis_command, MockSink, TestChain};
+
+    #[tokio::test]
+    async fn test_rewrite_get() {
+        let config = RedisGetRewriteConfig {
+            result: "Rewritten".to_owned(),
+        };
+        let mut chain = TestChain::from_config(
+            &config,
+            MessageType::Redis,
+            MockSink::new(|_| {
+                Message::from_frame(Frame::Redis(RedisFrame::BulkString("bar".into())))
+            }),
+        )
+        .await
+        .unwrap();
+
+        let responses = chain
+            .send(vec![
+                redis_command(&["GET", "foo"]),
+                redis_command(&["SET", "foo", "bar"]),
+            ])
+            .await
+            .unwrap();
+
+        assert_redis_responses(
+            responses,
+            &[
+                RedisFrame::BulkString("Rewritten".into()),
+                RedisFrame::BulkString("bar".into()),
+            ],
+        );
+        assert_eq!(chain.take_received().len(), 2);
+    }
+}
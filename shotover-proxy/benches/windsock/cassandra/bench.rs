@@ -423,6 +423,13 @@ impl CassandraBench {
                         rack: "rack1".to_owned(),
                         host_id: "2dd022d6-2937-4754-89d6-02d2933a8f7a".parse().unwrap(),
                     }],
+                    health_check: None,
+                    local_datacenter: None,
+                    local_rack: None,
+                    topology_refresh_interval_secs: None,
+                    compression: None,
+                    tcp: None,
+                    speculative_execution: None,
                 }));
             }
             CassandraTopology::Single => {
@@ -431,6 +438,10 @@ impl CassandraBench {
                     tls: None,
                     connect_timeout_ms: 3000,
                     read_timeout: None,
+                    health_check: None,
+                    compression: None,
+                    response_chunk_size: None,
+                    tcp: None,
                 }));
             }
         }
@@ -443,8 +454,15 @@ impl CassandraBench {
                 hard_connection_limit: None,
                 tls: None,
                 timeout: None,
-                chain: TransformChainConfig(transforms),
+                max_message_size_bytes: None,
+                ip_filter: None,
+                client_throttle: None,
+                tcp: None,
+                chain_error_policy: None,
+                latency_objective: None,
+                chain: TransformChainConfig::new(transforms),
                 transport: None,
+                compression: None,
             },
         ))
     }
@@ -460,7 +478,7 @@ impl CassandraBench {
                 let ip = instance.instance.private_ip().to_string();
                 let topology = self
                     .generate_topology_yaml(format!("{ip}:9042"), format!("{cassandra_ip}:9042"));
-                Some(instance.run_shotover(&topology).await)
+                Some(instance.run_shotover(&topology, "").await)
             }
             Shotover::None => None,
         }
@@ -270,12 +270,12 @@ sudo docker system prune -af"#,
     }
 
     #[cfg(all(feature = "kafka-cpp-driver-tests", feature = "kafka"))]
-    pub async fn run_shotover(self: Arc<Self>, topology: &str) -> RunningShotover {
+    pub async fn run_shotover(self: Arc<Self>, topology: &str, args: &str) -> RunningShotover {
         self.instance
             .ssh()
             .push_file_from_bytes(topology.as_bytes(), Path::new("topology.yaml"))
             .await;
-        RunningShotover::new(&self.instance).await
+        RunningShotover::new(&self.instance, args).await
     }
 }
 
@@ -336,12 +336,12 @@ impl Ec2InstanceWithShotover {
         Ok(())
     }
 
-    pub async fn run_shotover(self: Arc<Self>, topology: &str) -> RunningShotover {
+    pub async fn run_shotover(self: Arc<Self>, topology: &str, args: &str) -> RunningShotover {
         self.instance
             .ssh()
             .push_file_from_bytes(topology.as_bytes(), Path::new("topology.yaml"))
             .await;
-        RunningShotover::new(&self.instance).await
+        RunningShotover::new(&self.instance, args).await
     }
 }
 
@@ -351,14 +351,15 @@ pub struct RunningShotover {
 }
 
 impl RunningShotover {
-    async fn new(instance: &Ec2Instance) -> Self {
+    /// `args` are passed to shotover in addition to the config and topology
+    async fn new(instance: &Ec2Instance, args: &str) -> Self {
         let (shutdown_tx, mut shutdown_rx) = tokio::sync::mpsc::unbounded_channel();
         let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel();
         let mut receiver = instance
                     .ssh()
-                    .shell_stdout_lines(r#"
+                    .shell_stdout_lines(&format!(r#"
         killall -w shotover-bin > /dev/null || true
-        RUST_BACKTRACE=1 ./shotover-bin --config-file config.yaml --topology-file topology.yaml --log-format json"#)
+        RUST_BACKTRACE=1 ./shotover-bin --config-file config.yaml --topology-file topology.yaml --log-format json {args}"#))
                     .await;
         tokio::task::spawn(async move {
             loop {
@@ -20,9 +20,41 @@ impl Shotover {
     }
 }
 
+/// Compares the runtime modes of shotover, benches without a `ShotoverRuntime` use the default multi-threaded runtime.
+#[derive(Clone, Copy)]
+pub enum ShotoverRuntime {
+    PerCoreEpoll,
+    #[cfg(feature = "io-uring")]
+    PerCoreIoUring,
+}
+
+impl ShotoverRuntime {
+    pub fn to_tag(self) -> (String, String) {
+        (
+            "runtime".to_owned(),
+            match self {
+                ShotoverRuntime::PerCoreEpoll => "per-core-epoll".to_owned(),
+                #[cfg(feature = "io-uring")]
+                ShotoverRuntime::PerCoreIoUring => "per-core-io-uring".to_owned(),
+            },
+        )
+    }
+
+    pub fn args(self) -> &'static [&'static str] {
+        match self {
+            ShotoverRuntime::PerCoreEpoll => &["--runtime", "per-core", "--io-backend", "epoll"],
+            #[cfg(feature = "io-uring")]
+            ShotoverRuntime::PerCoreIoUring => {
+                &["--runtime", "per-core", "--io-backend", "io-uring"]
+            }
+        }
+    }
+}
+
 pub fn generate_topology(source: SourceConfig) -> String {
     ShotoverTopology {
         sources: vec![source],
+        notifiers: vec![],
     }
     .serialize()
     .unwrap()
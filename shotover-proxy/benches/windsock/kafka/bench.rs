@@ -93,6 +93,7 @@ impl KafkaBench {
                 connect_timeout_ms: 3000,
                 read_timeout: None,
                 tls: None,
+                tcp: None,
             }),
             KafkaTopology::Cluster1 | KafkaTopology::Cluster3 => Box::new(KafkaSinkClusterConfig {
                 connect_timeout_ms: 3000,
@@ -108,6 +109,7 @@ impl KafkaBench {
                 local_shotover_broker_id: 0,
                 authorize_scram_over_mtls: None,
                 tls: None,
+                tcp: None,
             }),
         });
         common::generate_topology(SourceConfig::Kafka(shotover::sources::kafka::KafkaConfig {
@@ -117,7 +119,12 @@ impl KafkaBench {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(transforms),
+            ip_filter: None,
+            client_throttle: None,
+            tcp: None,
+            chain_error_policy: None,
+            latency_objective: None,
+            chain: TransformChainConfig::new(transforms),
         }))
     }
 
@@ -214,7 +221,7 @@ impl KafkaBench {
                     format!("{shotover_ip}:9092"),
                     format!("{kafka_ip}:9192"),
                 );
-                Some(shotover_instance.run_shotover(&topology).await)
+                Some(shotover_instance.run_shotover(&topology, "").await)
             }
             Shotover::None => None,
         }
@@ -229,7 +236,7 @@ impl KafkaBench {
             Shotover::Standard | Shotover::ForcedMessageParsed => {
                 let topology =
                     self.generate_topology_yaml(format!("{ip}:9092"), format!("{ip}:9192"));
-                Some(instance.run_shotover(&topology).await)
+                Some(instance.run_shotover(&topology, "").await)
             }
             Shotover::None => None,
         }
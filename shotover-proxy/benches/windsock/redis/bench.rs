@@ -3,9 +3,9 @@ use crate::{
         CloudResources, CloudResourcesRequired, Ec2InstanceWithDocker, Ec2InstanceWithShotover,
         RunningShotover,
     },
-    common::{self, Shotover},
+    common::{self, Shotover, ShotoverRuntime},
     profilers::{self, CloudProfilerRunner, ProfilerRunner},
-    shotover::shotover_process_custom_topology,
+    shotover::shotover_process_custom_topology_with_args,
 };
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
@@ -66,6 +66,7 @@ pub struct RedisBench {
     shotover: Shotover,
     operation: RedisOperation,
     encryption: Encryption,
+    runtime: Option<ShotoverRuntime>,
 }
 
 impl RedisBench {
@@ -80,9 +81,19 @@ impl RedisBench {
             shotover,
             operation,
             encryption,
+            runtime: None,
         }
     }
 
+    pub fn with_runtime(mut self, runtime: ShotoverRuntime) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    fn shotover_args(&self) -> &'static [&'static str] {
+        self.runtime.map(|x| x.args()).unwrap_or(&[])
+    }
+
     fn generate_topology_yaml(&self, host_address: String, redis_address: String) -> String {
         let certs = "tests/test-configs/redis/tls/certs";
         let tls_connector = match self.encryption {
@@ -119,6 +130,10 @@ impl RedisBench {
                     tls: tls_connector,
                     connection_count: None,
                     connect_timeout_ms: 3000,
+                    health_check: None,
+                    replica_reads: None,
+                    max_blocking_duration_ms: None,
+                    tcp: None,
                 }));
             }
             RedisTopology::Single => {
@@ -126,6 +141,10 @@ impl RedisBench {
                     address: redis_address,
                     tls: tls_connector,
                     connect_timeout_ms: 3000,
+                    health_check: None,
+                    max_blocking_duration_ms: None,
+                    tcp: None,
+                    response_chunk_size: None,
                 }));
             }
         }
@@ -137,7 +156,13 @@ impl RedisBench {
             hard_connection_limit: None,
             tls: tls_acceptor,
             timeout: None,
-            chain: TransformChainConfig(transforms),
+            max_message_size_bytes: None,
+            ip_filter: None,
+            client_throttle: None,
+            tcp: None,
+            chain_error_policy: None,
+            latency_objective: None,
+            chain: TransformChainConfig::new(transforms),
         }))
     }
 
@@ -150,7 +175,11 @@ impl RedisBench {
             let ip = instance.instance.private_ip().to_string();
             let topology =
                 self.generate_topology_yaml(format!("{ip}:6379"), format!("{redis_ip}:6379"));
-            Some(instance.run_shotover(&topology).await)
+            Some(
+                instance
+                    .run_shotover(&topology, &self.shotover_args().join(" "))
+                    .await,
+            )
         } else {
             None
         }
@@ -189,6 +218,7 @@ impl Bench for RedisBench {
             self.shotover.to_tag(),
         ]
         .into_iter()
+        .chain(self.runtime.map(|x| x.to_tag()))
         .collect()
     }
 
@@ -325,7 +355,14 @@ impl Bench for RedisBench {
             Shotover::Standard | Shotover::ForcedMessageParsed => {
                 let topology_yaml = self
                     .generate_topology_yaml("127.0.0.1:6379".to_owned(), redis_address.to_owned());
-                Some(shotover_process_custom_topology(&topology_yaml, &profiler).await)
+                Some(
+                    shotover_process_custom_topology_with_args(
+                        &topology_yaml,
+                        &profiler,
+                        self.shotover_args(),
+                    )
+                    .await,
+                )
             }
             Shotover::None => None,
         };
@@ -18,5 +18,27 @@ pub fn benches() -> Vec<ShotoverBench> {
     .map(|(topology, shotover, operation, encryption)| {
         Box::new(RedisBench::new(topology, shotover, operation, encryption)) as ShotoverBench
     })
+    // io_uring is only used for connections without TLS, compare it against the multi-threaded runtime of the above benches
+    .chain(
+        itertools::iproduct!(
+            [RedisOperation::Get, RedisOperation::Set],
+            [
+                ShotoverRuntime::PerCoreEpoll,
+                #[cfg(feature = "io-uring")]
+                ShotoverRuntime::PerCoreIoUring,
+            ]
+        )
+        .map(|(operation, runtime)| {
+            Box::new(
+                RedisBench::new(
+                    RedisTopology::Single,
+                    Shotover::Standard,
+                    operation,
+                    Encryption::None,
+                )
+                .with_runtime(runtime),
+            ) as ShotoverBench
+        }),
+    )
     .collect()
 }
@@ -6,6 +6,14 @@ use uuid::Uuid;
 pub async fn shotover_process_custom_topology(
     topology_contents: &str,
     profiler: &ProfilerRunner,
+) -> BinProcess {
+    shotover_process_custom_topology_with_args(topology_contents, profiler, &[]).await
+}
+
+pub async fn shotover_process_custom_topology_with_args(
+    topology_contents: &str,
+    profiler: &ProfilerRunner,
+    args: &[&str],
 ) -> BinProcess {
     let topology_path = std::env::temp_dir().join(Uuid::new_v4().to_string());
     std::fs::write(&topology_path, topology_contents).unwrap();
@@ -13,6 +21,7 @@ pub async fn shotover_process_custom_topology(
         .with_config("config/config.yaml")
         .with_bin(bin_path!("shotover-proxy"))
         .with_profile(profiler.shotover_profile())
+        .with_args(args)
         .start()
         .await
 }
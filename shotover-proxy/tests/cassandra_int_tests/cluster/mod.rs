@@ -2,7 +2,9 @@ use cassandra_protocol::frame::message_startup::BodyReqStartup;
 use cassandra_protocol::frame::Version;
 use shotover::frame::{cassandra::Tracing, CassandraFrame, CassandraOperation, Frame};
 use shotover::message::Message;
+use shotover::tcp::TcpConfig;
 use shotover::tls::{TlsConnector, TlsConnectorConfig};
+use shotover::transforms::cassandra::schema::SchemaCache;
 use shotover::transforms::cassandra::sink_cluster::{
     node::{CassandraNode, ConnectionFactory},
     topology::{create_topology_task, TaskConnectionInfo},
@@ -31,7 +33,13 @@ pub async fn run_topology_task(ca_path: Option<&str>, port: Option<u32>) -> Vec<
         .unwrap()
     });
 
-    let mut connection_factory = ConnectionFactory::new(Duration::from_secs(3), None, tls);
+    let mut connection_factory = ConnectionFactory::new(
+        Duration::from_secs(3),
+        None,
+        tls,
+        TcpConfig::default(),
+        None,
+    );
     for message in create_handshake() {
         connection_factory.push_handshake_message(message);
     }
@@ -39,8 +47,12 @@ pub async fn run_topology_task(ca_path: Option<&str>, port: Option<u32>) -> Vec<
     create_topology_task(
         nodes_tx,
         keyspaces_tx,
+        SchemaCache::default(),
         task_handshake_rx,
         "datacenter1".to_string(),
+        Duration::from_secs(60),
+        None,
+        "test/CassandraSinkCluster".to_owned(),
     );
 
     // Give the handshake task a hardcoded handshake.
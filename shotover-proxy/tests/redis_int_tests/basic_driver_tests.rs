@@ -1296,9 +1296,13 @@ pub async fn test_trigger_transform_failure_driver(client: &RedisClient) {
 pub async fn test_trigger_transform_failure_raw() {
     // Send invalid redis command
     // To correctly handle this shotover should close the connection
-    let mut connection = tcp::tcp_stream(Duration::from_secs(3), "127.0.0.1:6379")
-        .await
-        .unwrap();
+    let mut connection = tcp::tcp_stream(
+        Duration::from_secs(3),
+        "127.0.0.1:6379",
+        &tcp::TcpConfig::default(),
+    )
+    .await
+    .unwrap();
 
     connection.write_all(b"*1\r\n$4\r\nping\r\n").await.unwrap();
 
@@ -1339,9 +1343,13 @@ async fn read_redis_message(connection: &mut TcpStream) -> RedisFrame {
 pub async fn test_invalid_frame() {
     // Send invalid redis command
     // To correctly handle this shotover should close the connection
-    let mut connection = tcp::tcp_stream(Duration::from_secs(3), "127.0.0.1:6379")
-        .await
-        .unwrap();
+    let mut connection = tcp::tcp_stream(
+        Duration::from_secs(3),
+        "127.0.0.1:6379",
+        &tcp::TcpConfig::default(),
+    )
+    .await
+    .unwrap();
 
     connection
         .write_all(b"invalid_redis_frame\r\n")
@@ -1505,11 +1513,7 @@ pub async fn run_all_cluster_hiding(connection: &mut Connection, flusher: &mut F
     test_filtered_scanning(connection, flusher).await;
     test_pipeline(connection).await; // NGET Issues
     test_empty_pipeline(connection).await;
-    // TODO: Pipeline transactions currently don't work (though it tries very hard)
-    // Current each cmd in a pipeline is treated as a single request, which means on a cluster
-    // basis they end up getting routed to different masters. This results in very occasionally will
-    // the transaction resolve (the exec and the multi both go to the right server).
-    // test_pipeline_transaction().await;
+    test_pipeline_transaction(connection).await;
     test_pipeline_reuse_query(connection).await;
     test_pipeline_reuse_query_clear(connection).await;
     // test_real_transaction().await;
@@ -1545,11 +1549,7 @@ pub async fn run_all_cluster_handling(connection: &mut Connection, flusher: &mut
     test_filtered_scanning(connection, flusher).await;
     test_pipeline(connection).await; // NGET Issues
     test_empty_pipeline(connection).await;
-    // TODO: Pipeline transactions currently don't work (though it tries very hard)
-    // Current each cmd in a pipeline is treated as a single request, which means on a cluster
-    // basis they end up getting routed to different masters. This results in very occasionally will
-    // the transaction resolve (the exec and the multi both go to the right server).
-    // test_pipeline_transaction().await;
+    test_pipeline_transaction(connection).await;
     test_pipeline_reuse_query(connection).await;
     test_pipeline_reuse_query_clear(connection).await;
     // test_real_transaction().await;
@@ -15,7 +15,7 @@ use shotover::transforms::filter::{Filter, QueryTypeFilter};
 use shotover::transforms::loopback::Loopback;
 use shotover::transforms::null::NullSink;
 #[cfg(feature = "alpha-transforms")]
-use shotover::transforms::protect::{KeyManagerConfig, ProtectConfig};
+use shotover::transforms::protect::{KeyManagerConfig, ProtectConfig, ProtectedField};
 use shotover::transforms::query_counter::QueryCounter;
 use shotover::transforms::redis::cluster_ports_rewrite::RedisClusterPortsRewrite;
 use shotover::transforms::throttling::RequestThrottlingConfig;
@@ -226,12 +226,16 @@ fn criterion_benchmark(c: &mut Criterion) {
                     ProtectConfig {
                         keyspace_table_columns: [(
                             "test_protect_keyspace".to_string(),
-                            [("protected_table".to_string(), vec!["col1".to_string()])]
-                                .into_iter()
-                                .collect(),
+                            [(
+                                "protected_table".to_string(),
+                                vec![ProtectedField::Name("col1".to_string())],
+                            )]
+                            .into_iter()
+                            .collect(),
                         )]
                         .into_iter()
                         .collect(),
+                        redis_key_patterns: vec![],
                         key_manager: KeyManagerConfig::Local {
                             kek: "Ht8M1nDO/7fay+cft71M2Xy7j30EnLAsA84hSUMCm1k=".to_string(),
                             kek_id: "".to_string(),
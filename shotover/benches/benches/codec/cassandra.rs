@@ -34,7 +34,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V4);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V4)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_query_v4_no_compression", |b| {
@@ -51,7 +53,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V4);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V4)
+            .unwrap();
 
         group.bench_function("decode_system.local_query_v4_no_compression", |b| {
             b.iter_batched(
@@ -81,7 +85,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V4);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V4)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_query_v4_lz4_compression", |b| {
@@ -98,7 +104,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V4);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V4)
+            .unwrap();
 
         group.bench_function("decode_system.local_query_v4_lz4_compression", |b| {
             b.iter_batched(
@@ -125,7 +133,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V5)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_result_v4_no_compression", |b| {
@@ -142,7 +152,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_result_v4_no_compression", |b| {
             b.iter_batched(
@@ -169,7 +181,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_result_v4_lz4_compression", |b| {
@@ -186,7 +200,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_result_v4_lz4_compression", |b| {
             b.iter_batched(
@@ -216,7 +232,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V5)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_query_v5_no_compression", |b| {
@@ -233,7 +251,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_query_v5_no_compression", |b| {
             b.iter_batched(
@@ -263,7 +283,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_query_v5_lz4_compression", |b| {
@@ -280,7 +302,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_query_v5_lz4_compression", |b| {
             b.iter_batched(
@@ -307,7 +331,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V5)
+            .unwrap();
 
         // This bench is disabled because it is incredibly noisy.
         // The noisiness actually indicates a real problem, which is why this bench is commented out instead of removed.
@@ -336,7 +362,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("NONE".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("NONE".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_result_v5_no_compression", |b| {
             b.iter_batched(
@@ -363,7 +391,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (_, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         let mut bytes = BytesMut::new();
         group.bench_function("encode_system.local_result_v5_lz4_compression", |b| {
@@ -380,7 +410,9 @@ fn criterion_benchmark(c: &mut Criterion) {
         let (mut decoder, mut encoder) =
             CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned()).build();
 
-        encoder.set_startup_state_ext("LZ4".to_string(), Version::V5);
+        encoder
+            .set_startup_state_ext("LZ4".to_string(), Version::V5)
+            .unwrap();
 
         group.bench_function("decode_system.local_result_v5_lz4_compression", |b| {
             b.iter_batched(
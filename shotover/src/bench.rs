@@ -0,0 +1,553 @@
+//! A load generator for benchmarking transform chains, run by the `shotover bench` subcommand.
+//!
+//! The topology is run as usual and requests are sent to one of its sources over real connections,
+//! so the results include the cost of the source codec and every transform in the chain.
+//! Each connection sends one request at a time, so the concurrency is the number of connections.
+
+use crate::codec::{CodecBuilder, Direction};
+use crate::config::topology::Topology;
+use crate::connection::SinkConnection;
+use crate::frame::Frame;
+use crate::message::Message;
+use crate::sources::SourceConfig;
+use crate::tcp::TcpConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Notify};
+
+#[cfg(feature = "cassandra")]
+use {
+    crate::codec::cassandra::CassandraCodecBuilder,
+    crate::frame::cassandra::{parse_statement_single, Tracing},
+    crate::frame::{CassandraFrame, CassandraOperation},
+    cassandra_protocol::frame::message_startup::BodyReqStartup,
+    cassandra_protocol::frame::Version,
+    std::collections::HashMap,
+};
+#[cfg(feature = "redis")]
+use {crate::codec::redis::RedisCodecBuilder, crate::frame::RedisFrame, bytes::Bytes};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+/// The zipf distribution holds a probability for every key, so the number of keys is limited to keep its memory usage to 80MB
+const MAX_ZIPF_KEYS: u64 = 10_000_000;
+
+#[derive(clap::Args, Clone)]
+pub struct BenchOpts {
+    /// The name of the source to send requests to, defaults to the first source in the topology.
+    #[clap(long)]
+    pub source: Option<String>,
+
+    /// The commands to send and their relative weights e.g. `GET=80,SET=20`.
+    /// Redis sources support GET, SET, DEL, INCR and EXISTS, defaulting to `GET=80,SET=20`.
+    /// Cassandra sources support SELECT, INSERT and DELETE on the table `shotover_bench.kv`, defaulting to `SELECT=80,INSERT=20`.
+    #[clap(long)]
+    pub mix: Option<String>,
+
+    /// The number of distinct keys accessed, at most 10000000 with the zipf key distribution.
+    #[clap(long, default_value = "100000")]
+    pub keys: u64,
+
+    #[arg(long, value_enum, default_value = "uniform")]
+    pub key_distribution: KeyDistribution,
+
+    /// The size in bytes of the values written.
+    #[clap(long, default_value = "64")]
+    pub value_size: usize,
+
+    /// The number of connections, each of which sends one request at a time.
+    #[clap(long, default_value = "16")]
+    pub concurrency: usize,
+
+    #[clap(long, default_value = "30")]
+    pub duration_secs: u64,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum KeyDistribution {
+    /// Every key is equally likely to be accessed.
+    Uniform,
+    /// The nth most popular key is accessed 1/n as often as the most popular key.
+    Zipf,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Protocol {
+    #[cfg(feature = "redis")]
+    Redis,
+    #[cfg(feature = "cassandra")]
+    Cassandra,
+}
+
+impl Protocol {
+    fn supported_commands(self) -> &'static [&'static str] {
+        match self {
+            #[cfg(feature = "redis")]
+            Protocol::Redis => &["GET", "SET", "DEL", "INCR", "EXISTS"],
+            #[cfg(feature = "cassandra")]
+            Protocol::Cassandra => &["SELECT", "INSERT", "DELETE"],
+        }
+    }
+
+    fn default_mix(self) -> &'static str {
+        match self {
+            #[cfg(feature = "redis")]
+            Protocol::Redis => "GET=80,SET=20",
+            #[cfg(feature = "cassandra")]
+            Protocol::Cassandra => "SELECT=80,INSERT=20",
+        }
+    }
+
+    fn request(self, command: &str, key: u64, value: &str) -> Message {
+        match self {
+            #[cfg(feature = "redis")]
+            Protocol::Redis => {
+                let key = match command {
+                    // keep counters separate from the keys holding non integer values
+                    "INCR" => format!("counter:{key}"),
+                    _ => format!("key:{key}"),
+                };
+                let mut args = vec![
+                    RedisFrame::BulkString(Bytes::copy_from_slice(command.as_bytes())),
+                    RedisFrame::BulkString(key.into()),
+                ];
+                if command == "SET" {
+                    args.push(RedisFrame::BulkString(Bytes::copy_from_slice(
+                        value.as_bytes(),
+                    )));
+                }
+                Message::from_frame(Frame::Redis(RedisFrame::Array(args)))
+            }
+            #[cfg(feature = "cassandra")]
+            Protocol::Cassandra => {
+                let query = match command {
+                    "SELECT" => {
+                        format!("SELECT value FROM shotover_bench.kv WHERE key = 'key:{key}'")
+                    }
+                    "INSERT" => format!(
+                        "INSERT INTO shotover_bench.kv (key, value) VALUES ('key:{key}', '{value}')"
+                    ),
+                    _ => format!("DELETE FROM shotover_bench.kv WHERE key = 'key:{key}'"),
+                };
+                cassandra_query(&query)
+            }
+        }
+    }
+}
+
+/// Returns the protocol and address of the source
+fn target(source: &SourceConfig) -> Result<(Protocol, String)> {
+    match source {
+        #[cfg(feature = "redis")]
+        SourceConfig::Redis(redis) => {
+            if redis.tls.is_some() {
+                bail!("Benchmarking sources with TLS is not supported");
+            }
+            Ok((Protocol::Redis, redis.listen_addr.clone()))
+        }
+        #[cfg(feature = "cassandra")]
+        SourceConfig::Cassandra(cassandra) => {
+            if cassandra.tls.is_some() {
+                bail!("Benchmarking sources with TLS is not supported");
+            }
+            Ok((Protocol::Cassandra, cassandra.listen_addr.clone()))
+        }
+        #[allow(unreachable_patterns)]
+        _ => bail!(
+            "Benchmarking the {} source is not supported, only redis and cassandra sources can be benchmarked",
+            source.get_name()
+        ),
+    }
+}
+
+/// The commands to send, each with the sum of its weight and the weights of the commands before it
+struct Mix {
+    commands: Vec<(String, u32)>,
+}
+
+impl Mix {
+    fn parse(mix: &str, protocol: Protocol) -> Result<Self> {
+        let mut commands = vec![];
+        let mut total: u32 = 0;
+        for entry in mix.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (command, weight) = entry.split_once('=').with_context(|| {
+                format!("mix entry {entry:?} must be of the form COMMAND=WEIGHT")
+            })?;
+            let command = command.trim().to_ascii_uppercase();
+            if !protocol.supported_commands().contains(&command.as_str()) {
+                bail!(
+                    "{command} is not supported, supported commands are {}",
+                    protocol.supported_commands().join(", ")
+                );
+            }
+            let weight: u32 = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("mix entry {entry:?} has an invalid weight"))?;
+            total = total.checked_add(weight).with_context(|| {
+                format!(
+                    "the weights of mix {mix:?} must add up to at most {}",
+                    u32::MAX
+                )
+            })?;
+            commands.push((command, total));
+        }
+        if total == 0 {
+            bail!("mix {mix:?} must contain at least one command with a weight greater than 0");
+        }
+        Ok(Mix { commands })
+    }
+
+    fn choose(&self, rng: &mut SmallRng) -> &str {
+        let total = self.commands.last().unwrap().1;
+        let point = rng.gen_range(0..total);
+        let index = self.commands.partition_point(|(_, sum)| *sum <= point);
+        &self.commands[index].0
+    }
+}
+
+enum KeyChooser {
+    Uniform(u64),
+    /// The cumulative probability of accessing each key and every key before it
+    Zipf(Vec<f64>),
+}
+
+impl KeyChooser {
+    fn new(distribution: KeyDistribution, keys: u64) -> Result<Self> {
+        if keys == 0 {
+            bail!("keys must be greater than 0");
+        }
+        Ok(match distribution {
+            KeyDistribution::Uniform => KeyChooser::Uniform(keys),
+            KeyDistribution::Zipf => {
+                if keys > MAX_ZIPF_KEYS {
+                    bail!("keys must be at most {MAX_ZIPF_KEYS} with the zipf key distribution");
+                }
+                let mut cumulative = Vec::with_capacity(keys as usize);
+                let mut sum = 0.0;
+                for rank in 1..=keys {
+                    sum += 1.0 / rank as f64;
+                    cumulative.push(sum);
+                }
+                for probability in &mut cumulative {
+                    *probability /= sum;
+                }
+                KeyChooser::Zipf(cumulative)
+            }
+        })
+    }
+
+    fn choose(&self, rng: &mut SmallRng) -> u64 {
+        match self {
+            KeyChooser::Uniform(keys) => rng.gen_range(0..*keys),
+            KeyChooser::Zipf(cumulative) => {
+                let point: f64 = rng.gen();
+                (cumulative.partition_point(|x| *x < point) as u64).min(cumulative.len() as u64 - 1)
+            }
+        }
+    }
+}
+
+/// The latency of every request sent by a connection
+#[derive(Default)]
+struct Results {
+    latencies_us: Vec<u64>,
+    errors: u64,
+}
+
+impl Results {
+    fn merge(&mut self, other: Results) {
+        self.latencies_us.extend(other.latencies_us);
+        self.errors += other.errors;
+    }
+
+    fn report(mut self, elapsed: Duration) -> String {
+        self.latencies_us.sort_unstable();
+        let requests = self.latencies_us.len();
+        let mut report = format!(
+            "requests:   {requests}\nerrors:     {}\nthroughput: {:.0} requests per second\nlatency:\n",
+            self.errors,
+            requests as f64 / elapsed.as_secs_f64()
+        );
+        for (name, quantile) in [
+            ("min", 0.0),
+            ("p50", 0.5),
+            ("p90", 0.9),
+            ("p99", 0.99),
+            ("p99.9", 0.999),
+            ("max", 1.0),
+        ] {
+            let latency = percentile(&self.latencies_us, quantile);
+            report.push_str(&format!("  {name:<6}{:.3}ms\n", latency as f64 / 1000.0));
+        }
+        report
+    }
+}
+
+/// Returns the value at the quantile of the sorted values, or 0 if there are no values
+fn percentile(sorted: &[u64], quantile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let index = ((sorted.len() - 1) as f64 * quantile).round() as usize;
+    sorted[index]
+}
+
+/// Runs the topology and sends requests to one of its sources until the duration has elapsed or shotover is shutdown.
+pub(crate) async fn run(
+    topology: Topology,
+    opts: BenchOpts,
+    trigger_shutdown_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    let source = match &opts.source {
+        Some(name) => topology
+            .sources
+            .iter()
+            .find(|x| x.get_name() == name)
+            .with_context(|| format!("The topology has no source named {name:?}"))?,
+        None => topology
+            .sources
+            .first()
+            .context("The topology has no sources")?,
+    };
+    let (protocol, address) = target(source)?;
+    let mix = Arc::new(Mix::parse(
+        opts.mix.as_deref().unwrap_or(protocol.default_mix()),
+        protocol,
+    )?);
+    let keys = Arc::new(KeyChooser::new(opts.key_distribution, opts.keys)?);
+    let value: Arc<str> = "x".repeat(opts.value_size).into();
+    if opts.concurrency == 0 {
+        bail!("concurrency must be greater than 0");
+    }
+
+    let _sources = topology.run_chains(trigger_shutdown_rx.clone()).await?;
+    #[cfg(feature = "cassandra")]
+    if protocol == Protocol::Cassandra {
+        create_cassandra_table(&address).await?;
+    }
+
+    tracing::info!(
+        "Benchmarking the {} source at {address} with {} connections for {}s",
+        source.get_name(),
+        opts.concurrency,
+        opts.duration_secs
+    );
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(opts.duration_secs);
+    let workers: Vec<_> = (0..opts.concurrency)
+        .map(|_| {
+            tokio::spawn(send_requests(
+                protocol,
+                address.clone(),
+                mix.clone(),
+                keys.clone(),
+                value.clone(),
+                deadline,
+                trigger_shutdown_rx.clone(),
+            ))
+        })
+        .collect();
+
+    let mut results = Results::default();
+    for worker in workers {
+        results.merge(worker.await??);
+    }
+    tracing::info!("Benchmark results:\n{}", results.report(started.elapsed()));
+    Ok(())
+}
+
+async fn send_requests(
+    protocol: Protocol,
+    address: String,
+    mix: Arc<Mix>,
+    keys: Arc<KeyChooser>,
+    value: Arc<str>,
+    deadline: Instant,
+    trigger_shutdown_rx: watch::Receiver<bool>,
+) -> Result<Results> {
+    let mut connection = connect(protocol, &address).await?;
+    let mut rng = SmallRng::from_rng(rand::thread_rng()).unwrap();
+    let mut results = Results::default();
+    while Instant::now() < deadline && !*trigger_shutdown_rx.borrow() {
+        let request = protocol.request(mix.choose(&mut rng), keys.choose(&mut rng), &value);
+        let sent = Instant::now();
+        connection.send(vec![request])?;
+        let mut response = recv_response(&mut connection).await?;
+        results.latencies_us.push(sent.elapsed().as_micros() as u64);
+        if is_error(&mut response) {
+            results.errors += 1;
+        }
+    }
+    Ok(results)
+}
+
+async fn connect(protocol: Protocol, address: &str) -> Result<SinkConnection> {
+    match protocol {
+        #[cfg(feature = "redis")]
+        Protocol::Redis => SinkConnection::new(
+            address,
+            RedisCodecBuilder::new(Direction::Sink, "bench".to_owned()),
+            &None,
+            &TcpConfig::default(),
+            CONNECT_TIMEOUT,
+            Arc::new(Notify::new()),
+            None,
+        )
+        .await
+        .with_context(|| format!("Failed to connect to {address}")),
+        #[cfg(feature = "cassandra")]
+        Protocol::Cassandra => {
+            let mut connection = SinkConnection::new(
+                address,
+                CassandraCodecBuilder::new(Direction::Sink, "bench".to_owned()),
+                &None,
+                &TcpConfig::default(),
+                CONNECT_TIMEOUT,
+                Arc::new(Notify::new()),
+                None,
+            )
+            .await
+            .with_context(|| format!("Failed to connect to {address}"))?;
+
+            let startup = HashMap::from([("CQL_VERSION".to_owned(), "3.0.0".to_owned())]);
+            connection.send(vec![cassandra_message(CassandraOperation::Startup(
+                BodyReqStartup { map: startup },
+            ))])?;
+            match recv_response(&mut connection).await?.frame() {
+                Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Ready(_),
+                    ..
+                })) => Ok(connection),
+                Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Authenticate(_),
+                    ..
+                })) => bail!(
+                    "Benchmarking cassandra sources requiring authentication is not supported"
+                ),
+                frame => Err(anyhow!("Unexpected response to STARTUP {frame:?}")),
+            }
+        }
+    }
+}
+
+async fn recv_response(connection: &mut SinkConnection) -> Result<Message> {
+    loop {
+        if let Some(response) = connection.recv().await?.pop() {
+            return Ok(response);
+        }
+    }
+}
+
+fn is_error(response: &mut Message) -> bool {
+    match response.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(RedisFrame::Error(_))) => true,
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Error(_),
+            ..
+        })) => true,
+        _ => false,
+    }
+}
+
+#[cfg(feature = "cassandra")]
+fn cassandra_message(operation: CassandraOperation) -> Message {
+    // Every connection has a single request in flight so the stream id can always be 0
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version: Version::V4,
+        stream_id: 0,
+        tracing: Tracing::Request(false),
+        warnings: vec![],
+        operation,
+    }))
+}
+
+#[cfg(feature = "cassandra")]
+fn cassandra_query(query: &str) -> Message {
+    cassandra_message(CassandraOperation::Query {
+        query: Box::new(parse_statement_single(query)),
+        params: Box::default(),
+    })
+}
+
+/// Creates the table that the benchmark reads from and writes to if it does not already exist
+#[cfg(feature = "cassandra")]
+async fn create_cassandra_table(address: &str) -> Result<()> {
+    let mut connection = connect(Protocol::Cassandra, address).await?;
+    for query in [
+        "CREATE KEYSPACE IF NOT EXISTS shotover_bench WITH REPLICATION = { 'class' : 'SimpleStrategy', 'replication_factor' : 1 }",
+        "CREATE TABLE IF NOT EXISTS shotover_bench.kv (key text PRIMARY KEY, value text)",
+    ] {
+        connection.send(vec![cassandra_query(query)])?;
+        let mut response = recv_response(&mut connection).await?;
+        if is_error(&mut response) {
+            bail!(
+                "Failed to create the benchmark table with {query:?}: {}",
+                response.to_high_level_string()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_mix() {
+        let mix = Mix::parse("get=3, SET=1", Protocol::Redis).unwrap();
+        assert_eq!(
+            mix.commands,
+            vec![("GET".to_owned(), 3), ("SET".to_owned(), 4)]
+        );
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        let gets = (0..10_000)
+            .filter(|_| mix.choose(&mut rng) == "GET")
+            .count();
+        assert!((7000..8000).contains(&gets), "{gets}");
+
+        assert_eq!(
+            Mix::parse("GET=1,FLUSHALL=1", Protocol::Redis)
+                .err()
+                .unwrap()
+                .to_string(),
+            "FLUSHALL is not supported, supported commands are GET, SET, DEL, INCR, EXISTS"
+        );
+        assert!(Mix::parse("GET=0", Protocol::Redis).is_err());
+        assert!(Mix::parse("GET", Protocol::Redis).is_err());
+        assert!(Mix::parse("GET=4294967295,SET=1", Protocol::Redis).is_err());
+    }
+
+    #[test]
+    fn test_zipf() {
+        let keys = KeyChooser::new(KeyDistribution::Zipf, 100).unwrap();
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut counts = vec![0; 100];
+        for _ in 0..100_000 {
+            counts[keys.choose(&mut rng) as usize] += 1;
+        }
+        // the most popular key is accessed about twice as often as the second most popular key
+        assert!(counts[0] > counts[1] * 3 / 2, "{counts:?}");
+        assert!(counts[1] > counts[99], "{counts:?}");
+
+        assert!(KeyChooser::new(KeyDistribution::Zipf, MAX_ZIPF_KEYS + 1).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let sorted: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&sorted, 0.0), 1);
+        assert_eq!(percentile(&sorted, 0.5), 51);
+        assert_eq!(percentile(&sorted, 0.99), 99);
+        assert_eq!(percentile(&sorted, 1.0), 100);
+        assert_eq!(percentile(&[], 0.5), 0);
+    }
+}
@@ -0,0 +1,301 @@
+//! Verbose capture of the messages of selected client connections or keys, for debugging a single misbehaving query in production.
+//!
+//! A capture is started from the observability interface with `PUT /capture`.
+//! While it runs, every batch of requests from a matching client or accessing a matching key is flagged when it enters the chain.
+//! Every transform then records the requests it received and the responses it returned for that batch
+//! into a bounded ring buffer that is retrieved with `GET /capture`.
+//! Comparing the records of consecutive transforms shows exactly how each transform modified the messages.
+//!
+//! Records contain the full contents of messages, including values decrypted by Protect, and are served to anyone able to reach the observability interface.
+//! Only connection setup requests such as Redis `AUTH` are redacted, as they carry the client's credentials.
+
+use crate::message::{Message, Messages};
+use crate::transforms::util::glob_match;
+use crate::transforms::util::setup_replay::is_setup_request;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Allows checking for a running capture without taking the lock, as it is checked for every batch of requests.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Recorded in place of setup requests, which carry the client's credentials.
+const REDACTED: &str = "<setup request redacted>";
+
+static CAPTURE: LazyLock<Mutex<Option<Capture>>> = LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Only capture batches from clients whose IP address matches this glob e.g. `10.0.0.*`
+    pub client: Option<String>,
+    /// Only capture batches containing a request that accesses a key matching this glob e.g. `user:*`
+    /// Cassandra keys are the values of the partition key columns.
+    pub key_pattern: Option<String>,
+    /// The number of records kept, once full the oldest records are dropped. Defaults to 1000.
+    pub capacity: Option<usize>,
+}
+
+struct Capture {
+    config: CaptureConfig,
+    records: VecDeque<CaptureRecord>,
+    /// The number of records dropped because the buffer was full
+    dropped: u64,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum CaptureStage {
+    /// The requests as received by the transform
+    Request,
+    /// The responses as returned by the transform
+    Response,
+    /// The transform returned an error instead of responses
+    Error,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct CaptureRecord {
+    /// Milliseconds since the unix epoch
+    pub timestamp_ms: i64,
+    pub client: String,
+    pub transform: &'static str,
+    pub stage: CaptureStage,
+    /// The id of the request, or for a response the id of the request it responds to
+    pub message_id: Option<u128>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CaptureReport {
+    pub config: CaptureConfig,
+    pub dropped: u64,
+    pub records: Vec<CaptureRecord>,
+}
+
+/// Starts capturing, replacing any running capture and discarding its records.
+pub(crate) fn start(config: CaptureConfig) -> Result<()> {
+    if config.client.is_none() && config.key_pattern.is_none() {
+        return Err(anyhow!(
+            "a capture must set `client` or `key_pattern`, capturing every message would flood the buffer"
+        ));
+    }
+    if config.capacity == Some(0) {
+        return Err(anyhow!("capacity must be greater than 0"));
+    }
+    *CAPTURE.lock().unwrap() = Some(Capture {
+        config,
+        records: VecDeque::new(),
+        dropped: 0,
+    });
+    ACTIVE.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Stops capturing, returning the records of the capture if one was running.
+pub(crate) fn stop() -> Option<CaptureReport> {
+    ACTIVE.store(false, Ordering::Relaxed);
+    CAPTURE.lock().unwrap().take().map(Capture::into_report)
+}
+
+/// Returns the records of the running capture.
+pub(crate) fn report() -> Option<CaptureReport> {
+    CAPTURE
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|capture| CaptureReport {
+            config: capture.config.clone(),
+            dropped: capture.dropped,
+            records: capture.records.iter().cloned().collect(),
+        })
+}
+
+impl Capture {
+    fn into_report(self) -> CaptureReport {
+        CaptureReport {
+            config: self.config,
+            dropped: self.dropped,
+            records: self.records.into(),
+        }
+    }
+}
+
+/// Returns the client to attribute records to when the batch of requests should be captured.
+/// Only parses the requests when a capture with a `key_pattern` is running.
+pub(crate) fn should_capture(client: &str, requests: &mut [Message]) -> Option<Arc<str>> {
+    if !ACTIVE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let (client_pattern, key_pattern) = {
+        let capture = CAPTURE.lock().unwrap();
+        let config = &capture.as_ref()?.config;
+        (config.client.clone(), config.key_pattern.clone())
+    };
+    if let Some(pattern) = client_pattern {
+        if !glob_match(pattern.as_bytes(), client.as_bytes()) {
+            return None;
+        }
+    }
+    if let Some(pattern) = key_pattern {
+        let matched = requests.iter_mut().any(|request| {
+            request
+                .primary_keys()
+                .iter()
+                .any(|key| glob_match(pattern.as_bytes(), key))
+        });
+        if !matched {
+            return None;
+        }
+    }
+    Some(client.into())
+}
+
+/// Records the requests received by a transform.
+pub(crate) fn record_requests(client: &str, transform: &'static str, requests: &mut [Message]) {
+    let records = requests
+        .iter_mut()
+        .map(|request| {
+            record(
+                client,
+                transform,
+                CaptureStage::Request,
+                Some(request.id()),
+                if is_setup_request(request) {
+                    REDACTED.to_owned()
+                } else {
+                    request.to_high_level_string()
+                },
+            )
+        })
+        .collect();
+    push(records);
+}
+
+/// Records the responses returned by a transform, or its error.
+pub(crate) fn record_responses(
+    client: &str,
+    transform: &'static str,
+    result: &mut Result<Messages>,
+) {
+    let records = match result {
+        Ok(responses) => responses
+            .iter_mut()
+            .map(|response| {
+                record(
+                    client,
+                    transform,
+                    CaptureStage::Response,
+                    response.request_id(),
+                    response.to_high_level_string(),
+                )
+            })
+            .collect(),
+        Err(err) => vec![record(
+            client,
+            transform,
+            CaptureStage::Error,
+            None,
+            format!("{err:?}"),
+        )],
+    };
+    push(records);
+}
+
+fn record(
+    client: &str,
+    transform: &'static str,
+    stage: CaptureStage,
+    message_id: Option<u128>,
+    message: String,
+) -> CaptureRecord {
+    CaptureRecord {
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0),
+        client: client.to_owned(),
+        transform,
+        stage,
+        message_id,
+        message,
+    }
+}
+
+fn push(records: Vec<CaptureRecord>) {
+    // The capture may have been stopped while the batch was in the chain
+    if let Some(capture) = CAPTURE.lock().unwrap().as_mut() {
+        let capacity = capture.config.capacity.unwrap_or(1000);
+        for record in records {
+            if capture.records.len() == capacity {
+                capture.records.pop_front();
+                capture.dropped += 1;
+            }
+            capture.records.push_back(record);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use pretty_assertions::assert_eq;
+
+    // The capture is process wide, so every assertion on it lives in this one test
+    #[test]
+    fn test_capture() {
+        assert!(start(CaptureConfig {
+            client: None,
+            key_pattern: None,
+            capacity: None,
+        })
+        .is_err());
+
+        start(CaptureConfig {
+            client: Some("10.0.0.*".to_owned()),
+            key_pattern: Some("user:*".to_owned()),
+            capacity: Some(2),
+        })
+        .unwrap();
+
+        let mut requests = vec![redis_command(&["GET", "user:1"])];
+        assert_eq!(should_capture("10.0.1.5", &mut requests), None);
+        assert_eq!(
+            should_capture("10.0.0.5", &mut requests).as_deref(),
+            Some("10.0.0.5")
+        );
+        let mut other_key = vec![redis_command(&["GET", "admin:1"])];
+        assert_eq!(should_capture("10.0.0.5", &mut other_key), None);
+
+        let mut requests = vec![
+            redis_command(&["GET", "user:1"]),
+            redis_command(&["GET", "user:2"]),
+            redis_command(&["GET", "user:3"]),
+        ];
+        record_requests("10.0.0.5", "Test", &mut requests);
+        let report = report().unwrap();
+        assert_eq!(report.dropped, 1);
+        assert_eq!(
+            report
+                .records
+                .iter()
+                .map(|x| (x.stage, x.message_id))
+                .collect::<Vec<_>>(),
+            vec![
+                (CaptureStage::Request, Some(requests[1].id())),
+                (CaptureStage::Request, Some(requests[2].id())),
+            ]
+        );
+
+        let mut auth = vec![redis_command(&["AUTH", "user", "password"])];
+        record_requests("10.0.0.5", "Test", &mut auth);
+        assert_eq!(super::report().unwrap().records[1].message, REDACTED);
+
+        assert_eq!(stop().unwrap().records.len(), 2);
+        assert!(stop().is_none());
+        assert_eq!(should_capture("10.0.0.5", &mut requests), None);
+    }
+}
@@ -0,0 +1,299 @@
+//! Registry of the active client connections of every source.
+//!
+//! The observability interface lists these connections with `GET /connections`
+//! and terminates a single connection with `DELETE /connections/:id`, allowing operators to
+//! find and remove a misbehaving client during incident response without restarting shotover.
+
+use crate::message::Message;
+use crate::tls::TlsSessionDetails;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+static CONNECTIONS: LazyLock<Mutex<HashMap<u64, Arc<Connection>>>> =
+    LazyLock::new(Default::default);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+struct Connection {
+    id: u64,
+    source: String,
+    remote_address: Option<SocketAddr>,
+    /// Milliseconds since the unix epoch
+    connected_at_ms: i64,
+    /// Requests received from the client that have not yet been responded to
+    in_flight: AtomicU64,
+    state: Mutex<ConnectionState>,
+    terminate: Notify,
+}
+
+struct ConnectionState {
+    tls: Option<TlsSessionDetails>,
+    identity: Option<String>,
+    name: Option<String>,
+    rate: MessageRate,
+}
+
+/// Counts requests in one second windows, reporting the count of the most recently completed window.
+struct MessageRate {
+    window_start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl MessageRate {
+    fn new(now: Instant) -> Self {
+        MessageRate {
+            window_start: now,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    fn advance(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.window_start);
+        if elapsed >= WINDOW * 2 {
+            self.window_start = now;
+            self.current = 0;
+            self.previous = 0;
+        } else if elapsed >= WINDOW {
+            self.window_start += WINDOW;
+            self.previous = self.current;
+            self.current = 0;
+        }
+    }
+
+    fn record(&mut self, count: u64, now: Instant) {
+        self.advance(now);
+        self.current += count;
+    }
+
+    fn per_second(&mut self, now: Instant) -> u64 {
+        self.advance(now);
+        self.previous
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConnectionReport {
+    pub id: u64,
+    pub remote_address: Option<String>,
+    /// Milliseconds since the unix epoch
+    pub connected_at_ms: i64,
+    pub tls: Option<TlsSessionDetails>,
+    /// The identity the client authenticated as, or the common name of its TLS client certificate
+    pub identity: Option<String>,
+    /// The name the client gave the connection, such as with the redis `CLIENT SETNAME` command
+    pub name: Option<String>,
+    /// Requests received over the most recently completed second
+    pub messages_per_second: u64,
+    /// Requests received that have not yet been responded to
+    pub in_flight: u64,
+}
+
+/// Keeps a connection listed for as long as it is held, it is created when the connection is accepted and dropped when the connection closes.
+pub(crate) struct ConnectionRegistration(Arc<Connection>);
+
+/// Lists a newly accepted connection.
+pub(crate) fn register(source: &str, remote_address: Option<SocketAddr>) -> ConnectionRegistration {
+    let connection = Arc::new(Connection {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        source: source.to_owned(),
+        remote_address,
+        connected_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0),
+        in_flight: AtomicU64::new(0),
+        state: Mutex::new(ConnectionState {
+            tls: None,
+            identity: None,
+            name: None,
+            rate: MessageRate::new(Instant::now()),
+        }),
+        terminate: Notify::new(),
+    });
+    CONNECTIONS
+        .lock()
+        .unwrap()
+        .insert(connection.id, connection.clone());
+    ConnectionRegistration(connection)
+}
+
+impl Drop for ConnectionRegistration {
+    fn drop(&mut self) {
+        CONNECTIONS.lock().unwrap().remove(&self.0.id);
+    }
+}
+
+impl ConnectionRegistration {
+    pub(crate) fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    pub(crate) fn set_tls(&self, tls: TlsSessionDetails) {
+        self.0.state.lock().unwrap().tls = Some(tls);
+    }
+
+    /// Called after every batch, as transforms in the chain may authenticate the client at any point.
+    pub(crate) fn set_identity(&self, identity: Option<&str>) {
+        let mut state = self.0.state.lock().unwrap();
+        if state.identity.as_deref() != identity {
+            state.identity = identity.map(|x| x.to_owned());
+        }
+    }
+
+    pub(crate) fn record_requests(&self, requests: &[Message]) {
+        self.0
+            .in_flight
+            .fetch_add(requests.len() as u64, Ordering::Relaxed);
+        self.0
+            .state
+            .lock()
+            .unwrap()
+            .rate
+            .record(requests.len() as u64, Instant::now());
+    }
+
+    pub(crate) fn record_responses(&self, responses: &[Message]) {
+        let responded = responses
+            .iter()
+            .filter(|x| x.request_id().is_some())
+            .count() as u64;
+        // Transforms may respond to requests that were never counted, such as those generated by the chain itself
+        let _ = self
+            .0
+            .in_flight
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| {
+                Some(x.saturating_sub(responded))
+            });
+    }
+
+    /// Completes once an operator has requested that this connection be terminated.
+    pub(crate) async fn terminated(&self) {
+        self.0.terminate.notified().await
+    }
+}
+
+impl Connection {
+    fn report(&self, now: Instant) -> ConnectionReport {
+        let mut state = self.state.lock().unwrap();
+        ConnectionReport {
+            id: self.id,
+            remote_address: self.remote_address.map(|x| x.to_string()),
+            connected_at_ms: self.connected_at_ms,
+            tls: state.tls.clone(),
+            identity: state.identity.clone(),
+            name: state.name.clone(),
+            messages_per_second: state.rate.per_second(now),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Lists the active connections of every source, keyed by source name.
+pub(crate) fn report() -> BTreeMap<String, Vec<ConnectionReport>> {
+    let now = Instant::now();
+    let mut report: BTreeMap<String, Vec<ConnectionReport>> = BTreeMap::new();
+    for connection in CONNECTIONS.lock().unwrap().values() {
+        report
+            .entry(connection.source.clone())
+            .or_default()
+            .push(connection.report(now));
+    }
+    for connections in report.values_mut() {
+        connections.sort_by_key(|x| x.id);
+    }
+    report
+}
+
+/// Lists the active connections of a single source.
+pub(crate) fn source_report(source: &str) -> Vec<ConnectionReport> {
+    let now = Instant::now();
+    let mut report: Vec<ConnectionReport> = CONNECTIONS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|x| x.source == source)
+        .map(|x| x.report(now))
+        .collect();
+    report.sort_by_key(|x| x.id);
+    report
+}
+
+/// Reports the connection with the given id, returning None if no such connection exists.
+pub(crate) fn connection_report(id: u64) -> Option<ConnectionReport> {
+    let connection = CONNECTIONS.lock().unwrap().get(&id).cloned();
+    connection.map(|x| x.report(Instant::now()))
+}
+
+/// Names the connection with the given id, or removes its name when None.
+pub(crate) fn set_name(id: u64, name: Option<String>) {
+    if let Some(connection) = CONNECTIONS.lock().unwrap().get(&id) {
+        connection.state.lock().unwrap().name = name;
+    }
+}
+
+/// Requests that the connection with the given id be closed, returning false if no such connection exists.
+/// The connection is closed once any batch of requests it is currently processing completes.
+pub(crate) fn terminate(id: u64) -> bool {
+    match CONNECTIONS.lock().unwrap().get(&id) {
+        Some(connection) => {
+            // notify_one stores a permit, so the request is not lost if the connection is not currently waiting
+            connection.terminate.notify_one();
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_rate() {
+        let start = Instant::now();
+        let mut rate = MessageRate::new(start);
+        rate.record(5, start);
+        rate.record(5, start + Duration::from_millis(500));
+        assert_eq!(rate.per_second(start + Duration::from_millis(900)), 0);
+        assert_eq!(rate.per_second(start + Duration::from_millis(1100)), 10);
+        rate.record(3, start + Duration::from_millis(1200));
+        assert_eq!(rate.per_second(start + Duration::from_millis(2100)), 3);
+        assert_eq!(rate.per_second(start + Duration::from_secs(10)), 0);
+    }
+
+    #[tokio::test]
+    async fn test_register_and_terminate() {
+        let registration = register("test_register_and_terminate", None);
+        let id = registration.0.id;
+        registration.set_identity(Some("alice"));
+
+        let connections = report();
+        let listed = &connections["test_register_and_terminate"];
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id, id);
+        assert_eq!(listed[0].identity.as_deref(), Some("alice"));
+
+        set_name(id, Some("worker".to_owned()));
+        assert_eq!(
+            connection_report(id).unwrap().name.as_deref(),
+            Some("worker")
+        );
+        assert_eq!(source_report("test_register_and_terminate").len(), 1);
+
+        assert!(terminate(id));
+        registration.terminated().await;
+
+        drop(registration);
+        assert!(!terminate(id));
+        assert!(!report().contains_key("test_register_and_terminate"));
+    }
+}
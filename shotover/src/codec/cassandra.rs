@@ -2,7 +2,7 @@ use super::{CodecBuilder, CodecReadError, CodecWriteError, Direction};
 use crate::codec::CodecState;
 use crate::frame::cassandra::{CassandraOperation, Tracing};
 use crate::frame::{CassandraFrame, Frame, MessageType};
-use crate::message::{Encodable, Message, MessageId, Messages, Metadata};
+use crate::message::{Encodable, Message, MessageId, Messages, Metadata, StreamChunk};
 use anyhow::{anyhow, Result};
 use atomic_enum::atomic_enum;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
@@ -15,6 +15,7 @@ use cql3_parser::cassandra_statement::CassandraStatement;
 use cql3_parser::common::Identifier;
 use lz4_flex::{block::get_maximum_output_size, compress_into, decompress};
 use metrics::{counter, Counter, Histogram};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
@@ -39,6 +40,10 @@ pub enum CheckFrameSizeError {
     UnsupportedOpcode(u8),
     #[error("Unsupported compression: {0}")]
     UnsupportedCompression(String),
+    #[error("Message of {size} bytes exceeds the max message size of {max} bytes")]
+    TooLarge { size: usize, max: usize },
+    #[error("Negative body length: {0}")]
+    NegativeBodyLength(i32),
 }
 
 #[atomic_enum]
@@ -96,11 +101,69 @@ impl From<CompressionState> for Compression {
     }
 }
 
+/// A compression of CQL frame bodies, configured independently for the client and upstream sides of shotover.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CassandraCompression {
+    None,
+    Lz4,
+    Snappy,
+}
+
+impl CassandraCompression {
+    /// The value of the `COMPRESSION` option of STARTUP and SUPPORTED messages
+    fn option_value(self) -> Option<&'static str> {
+        match self {
+            CassandraCompression::None => None,
+            CassandraCompression::Lz4 => Some("lz4"),
+            CassandraCompression::Snappy => Some("snappy"),
+        }
+    }
+}
+
+impl From<CassandraCompression> for Compression {
+    fn from(compression: CassandraCompression) -> Self {
+        match compression {
+            CassandraCompression::None => Compression::None,
+            CassandraCompression::Lz4 => Compression::Lz4,
+            CassandraCompression::Snappy => Compression::Snappy,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct CassandraCodecBuilder {
     direction: Direction,
     version_counter: VersionCounter,
     message_latency: Histogram,
+    max_message_size: Option<usize>,
+    compression: Option<CassandraCompression>,
+    response_chunk_size: Option<usize>,
+}
+
+impl CassandraCodecBuilder {
+    /// Messages larger than `max_message_size` bytes are rejected by the decoder, closing the connection.
+    pub fn with_max_message_size(mut self, max_message_size: Option<usize>) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// When None, the compression requested by the client is used on both sides of shotover.
+    ///
+    /// For a source, clients are only offered `compression` and connections requesting any other compression are closed.
+    /// For a sink, `compression` is negotiated with the upstream cluster regardless of the compression requested by the client.
+    /// Messages are recompressed when the compression of the two sides differ.
+    pub fn with_compression(mut self, compression: Option<CassandraCompression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// For a sink, results larger than `response_chunk_size` bytes are decoded into chunks of at most `response_chunk_size` bytes as they are received,
+    /// instead of into a single message once the whole result is received, see [`StreamChunk`].
+    /// Only results of protocol v3 and v4 that do not need to be recompressed are streamed.
+    pub fn with_response_chunk_size(mut self, response_chunk_size: Option<usize>) -> Self {
+        self.response_chunk_size = response_chunk_size;
+        self
+    }
 }
 
 impl CodecBuilder for CassandraCodecBuilder {
@@ -115,6 +178,9 @@ impl CodecBuilder for CassandraCodecBuilder {
             direction,
             version_counter,
             message_latency,
+            max_message_size: None,
+            compression: None,
+            response_chunk_size: None,
         }
     }
 
@@ -138,6 +204,9 @@ impl CodecBuilder for CassandraCodecBuilder {
                 handshake_complete.clone(),
                 self.version_counter.clone(),
                 stream_id_to_request_id_rx,
+                self.max_message_size,
+                self.compression,
+                self.response_chunk_size,
             ),
             CassandraEncoder::new(
                 version,
@@ -146,6 +215,7 @@ impl CodecBuilder for CassandraCodecBuilder {
                 handshake_complete,
                 self.message_latency.clone(),
                 stream_id_to_request_id_tx,
+                self.compression,
             ),
         )
     }
@@ -192,9 +262,22 @@ pub struct CassandraDecoder {
     payload_buffer: BytesMut,
     stream_id_to_request_id_rx: Option<mpsc::Receiver<StreamIdToRequestId>>,
     stream_id_to_request_id: HashMap<i16, MessageId>,
+    max_message_size: Option<usize>,
+    compression_config: Option<CassandraCompression>,
+    response_chunk_size: Option<usize>,
+    /// The response currently being streamed, its remaining chunks are decoded before any other message
+    streamed_response: Option<StreamedResponse>,
+}
+
+struct StreamedResponse {
+    /// The number of bytes of the response that have not been decoded yet
+    remaining: usize,
+    request_id: Option<MessageId>,
+    codec_state: CodecState,
 }
 
 impl CassandraDecoder {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         version: Arc<AtomicVersionState>,
         compression: Arc<AtomicCompressionState>,
@@ -202,6 +285,9 @@ impl CassandraDecoder {
         handshake_complete: Arc<AtomicBool>,
         version_counter: VersionCounter,
         stream_id_to_request_id_rx: Option<mpsc::Receiver<StreamIdToRequestId>>,
+        max_message_size: Option<usize>,
+        compression_config: Option<CassandraCompression>,
+        response_chunk_size: Option<usize>,
     ) -> CassandraDecoder {
         CassandraDecoder {
             version,
@@ -214,6 +300,10 @@ impl CassandraDecoder {
             expected_payload_len: None,
             stream_id_to_request_id_rx,
             stream_id_to_request_id: HashMap::new(),
+            max_message_size,
+            compression_config,
+            response_chunk_size,
+            streamed_response: None,
         }
     }
 }
@@ -235,7 +325,12 @@ impl CassandraDecoder {
                 ..
             } = CassandraFrame::from_bytes(bytes.clone().freeze(), Compression::None)?
             {
-                set_startup_state(&mut self.compression, &mut self.version, version, &startup);
+                if self.direction == Direction::Source {
+                    if let Some(allowed) = self.compression_config {
+                        check_client_compression(allowed, &startup)?;
+                    }
+                }
+                set_startup_state(&mut self.compression, &mut self.version, version, &startup)?;
 
                 if self.direction == Direction::Source {
                     self.version_counter.increment(version);
@@ -370,7 +465,7 @@ impl CassandraDecoder {
                     pretty_hex::pretty_hex(&bytes)
                 );
 
-                let compressed = self.check_compression(&bytes).unwrap();
+                let compressed = self.check_compression(&bytes)?;
 
                 let message = Message::from_bytes_at_instant(
                     bytes.freeze(),
@@ -445,9 +540,12 @@ impl CassandraDecoder {
                     return Err(CheckFrameSizeError::NotEnoughBytes);
                 }
 
-                let body_len = i32::from_be_bytes(src[5..9].try_into().unwrap()) as usize;
+                let body_len = i32::from_be_bytes(src[5..9].try_into().unwrap());
+                let body_len = usize::try_from(body_len)
+                    .map_err(|_| CheckFrameSizeError::NegativeBodyLength(body_len))?;
 
                 let envelope_len = ENVELOPE_HEADER_LEN + body_len;
+                self.check_max_message_size(envelope_len)?;
                 if src.len() < envelope_len {
                     return Err(CheckFrameSizeError::NotEnoughBytes);
                 }
@@ -470,6 +568,115 @@ impl CassandraDecoder {
         }
     }
 
+    /// Checked as soon as the length of a message is known, so that an oversized message is never buffered.
+    fn check_max_message_size(&self, size: usize) -> Result<(), CheckFrameSizeError> {
+        match self.max_message_size {
+            Some(max) if size > max => Err(CheckFrameSizeError::TooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    fn receive_stream_ids(&mut self) {
+        if let Some(rx) = &self.stream_id_to_request_id_rx {
+            while let Ok(pair) = rx.try_recv() {
+                self.stream_id_to_request_id
+                    .insert(pair.stream_id, pair.request_id);
+            }
+        }
+    }
+
+    /// Starts streaming a result larger than `response_chunk_size` once its first chunk has been received, instead of waiting for the whole result.
+    /// Returns None when the message at the start of `src` is not streamed.
+    fn start_streamed_response(
+        &mut self,
+        src: &mut BytesMut,
+        version: Version,
+        compression: Compression,
+        received_at: Instant,
+    ) -> Option<Messages> {
+        let chunk_size = self.response_chunk_size?;
+        // v5 messages are contained in frames which would need to be reassembled
+        if version == Version::V5 || src.len() < chunk_size.max(ENVELOPE_HEADER_LEN) {
+            return None;
+        }
+        let body_len = usize::try_from(i32::from_be_bytes(src[5..9].try_into().unwrap())).ok()?;
+        let envelope_len = ENVELOPE_HEADER_LEN + body_len;
+        let compressed = Flags::from_bits_truncate(src[1]).contains(Flags::COMPRESSION);
+        if envelope_len <= chunk_size
+            // the whole result has already been received so there is nothing to gain
+            || src.len() >= envelope_len
+            || !matches!(Opcode::try_from(src[4]), Ok(Opcode::Result))
+            // a compressed result is recompressed when the compression of the client differs, which requires the whole result
+            || (compressed && self.compression_config.is_some())
+            // left for check_size to reject
+            || self.check_max_message_size(envelope_len).is_err()
+        {
+            return None;
+        }
+
+        self.receive_stream_ids();
+        let stream_id = i16::from_be_bytes(src[2..4].try_into().unwrap());
+        let codec_state = CodecState::Cassandra {
+            compression: if compressed {
+                compression
+            } else {
+                Compression::None
+            },
+        };
+        self.streamed_response = Some(StreamedResponse {
+            remaining: envelope_len - chunk_size,
+            request_id: self.stream_id_to_request_id.remove(&stream_id),
+            codec_state,
+        });
+        Some(vec![Message::from_stream_chunk_at_instant(
+            src.split_to(chunk_size).freeze(),
+            codec_state,
+            StreamChunk::Start,
+            Some(received_at),
+        )])
+    }
+
+    /// Decodes the next chunk of the response being streamed once it has been received.
+    fn decode_streamed_response(
+        &mut self,
+        src: &mut BytesMut,
+        received_at: Instant,
+    ) -> Option<Messages> {
+        let streamed = self.streamed_response.as_mut().unwrap();
+        // a response is only streamed when response_chunk_size is set
+        let chunk_len = streamed.remaining.min(self.response_chunk_size.unwrap());
+        if src.len() < chunk_len {
+            src.reserve(chunk_len - src.len());
+            return None;
+        }
+        streamed.remaining -= chunk_len;
+
+        let codec_state = streamed.codec_state;
+        let bytes = src.split_to(chunk_len).freeze();
+        let message = if streamed.remaining == 0 {
+            let request_id = streamed.request_id;
+            self.streamed_response = None;
+            let mut message = Message::from_stream_chunk_at_instant(
+                bytes,
+                codec_state,
+                StreamChunk::End,
+                Some(received_at),
+            );
+            if let Some(request_id) = request_id {
+                message.set_request_id(request_id);
+            }
+            message
+        } else {
+            Message::from_stream_chunk_at_instant(
+                bytes,
+                codec_state,
+                StreamChunk::Continuation,
+                Some(received_at),
+            )
+        };
+        Some(vec![message])
+    }
+
     fn extract_envelopes_from_payload(
         &mut self,
         payload: Bytes,
@@ -488,7 +695,10 @@ impl CassandraDecoder {
                     self.parse_full_envelopes_from_payload(payload, received_at)
                 }
             } else {
-                self.expected_payload_len = extract_expected_payload_len(&self.payload_buffer);
+                self.expected_payload_len = extract_expected_payload_len(&self.payload_buffer)?;
+                if let Some(expected_payload_len) = self.expected_payload_len {
+                    self.check_max_message_size(ENVELOPE_HEADER_LEN + expected_payload_len)?;
+                }
                 Ok(vec![])
             }
         } else {
@@ -504,7 +714,13 @@ impl CassandraDecoder {
         let mut envelopes: Vec<Message> = vec![];
 
         while !payload.is_empty() {
-            let body_len = i32::from_be_bytes(payload[5..9].try_into().unwrap()) as usize;
+            if payload.len() < ENVELOPE_HEADER_LEN {
+                return Err(anyhow!(
+                    "payload length {} is too short to contain an envelope header",
+                    payload.len()
+                ));
+            }
+            let body_len = read_body_len(&payload)?;
 
             let envelope_len = ENVELOPE_HEADER_LEN + body_len;
 
@@ -537,12 +753,19 @@ impl CassandraDecoder {
     }
 }
 
-fn extract_expected_payload_len(payload_buffer: &BytesMut) -> Option<usize> {
+fn extract_expected_payload_len(payload_buffer: &BytesMut) -> Result<Option<usize>> {
     if payload_buffer.len() < ENVELOPE_HEADER_LEN {
-        return None;
+        return Ok(None);
     }
 
-    Some(i32::from_be_bytes(payload_buffer[5..9].try_into().unwrap()) as usize)
+    read_body_len(payload_buffer).map(Some)
+}
+
+/// Reads the body length from an envelope header, `envelope` must contain at least [`ENVELOPE_HEADER_LEN`] bytes.
+fn read_body_len(envelope: &[u8]) -> Result<usize> {
+    let body_len = i32::from_be_bytes(envelope[5..9].try_into().unwrap());
+    usize::try_from(body_len)
+        .map_err(|_| anyhow!(CheckFrameSizeError::NegativeBodyLength(body_len)))
 }
 
 fn header_crc_mismatch_error(computed_crc: i32, header_crc24: i32) -> anyhow::Error {
@@ -557,26 +780,43 @@ fn payload_crc_mismatch_error(computed_crc: u32, payload_crc32: u32) -> anyhow::
     ))
 }
 
+fn parse_compression(compression: &str) -> Result<Compression> {
+    match compression {
+        "snappy" | "SNAPPY" => Ok(Compression::Snappy),
+        "lz4" | "LZ4" => Ok(Compression::Lz4),
+        "" | "none" | "NONE" => Ok(Compression::None),
+        compression => Err(anyhow!(CheckFrameSizeError::UnsupportedCompression(
+            compression.to_owned()
+        ))),
+    }
+}
+
+/// Clients may always choose not to compress, but may only compress with the allowed compression.
+fn check_client_compression(allowed: CassandraCompression, startup: &BodyReqStartup) -> Result<()> {
+    let requested = match startup.map.get("COMPRESSION") {
+        Some(compression) => parse_compression(compression)?,
+        None => Compression::None,
+    };
+    if requested != Compression::None && requested != allowed.into() {
+        return Err(anyhow!(CheckFrameSizeError::UnsupportedCompression(
+            format!("{requested:?}, only {allowed:?} is allowed")
+        )));
+    }
+    Ok(())
+}
+
 fn set_startup_state(
     compression_state: &mut Arc<AtomicCompressionState>,
     version_state: &mut Arc<AtomicVersionState>,
     version: Version,
     startup: &BodyReqStartup,
-) {
+) -> Result<()> {
     if let Some(compression) = startup.map.get("COMPRESSION") {
-        compression_state.store(
-            match compression.as_str() {
-                "snappy" | "SNAPPY" => Compression::Snappy,
-                "lz4" | "LZ4" => Compression::Lz4,
-                "" | "none" | "NONE" => Compression::None,
-                _ => unimplemented!(),
-            }
-            .into(),
-            Ordering::Relaxed,
-        );
+        compression_state.store(parse_compression(compression)?.into(), Ordering::Relaxed);
     }
 
     version_state.store(version.into(), Ordering::Relaxed);
+    Ok(())
 }
 
 impl Decoder for CassandraDecoder {
@@ -589,6 +829,17 @@ impl Decoder for CassandraDecoder {
         let handshake_complete = self.handshake_complete.load(Ordering::Relaxed);
         let received_at = Instant::now();
 
+        if self.streamed_response.is_some() {
+            return Ok(self.decode_streamed_response(src, received_at));
+        }
+        if self.direction == Direction::Sink && handshake_complete {
+            if let Some(messages) =
+                self.start_streamed_response(src, version, compression, received_at)
+            {
+                return Ok(Some(messages));
+            }
+        }
+
         match self.check_size(src, version, compression, handshake_complete) {
             Ok(frame_len) => {
                 let mut messages = self
@@ -602,12 +853,7 @@ impl Decoder for CassandraDecoder {
                     )
                     .map_err(CodecReadError::Parser)?;
 
-                if let Some(rx) = &self.stream_id_to_request_id_rx {
-                    while let Ok(pair) = rx.try_recv() {
-                        self.stream_id_to_request_id
-                            .insert(pair.stream_id, pair.request_id);
-                    }
-                }
+                self.receive_stream_ids();
 
                 for message in messages.iter_mut() {
                     let Ok(Metadata::Cassandra(meta)) = message.metadata() else {
@@ -641,6 +887,10 @@ impl Decoder for CassandraDecoder {
             Err(CheckFrameSizeError::UnsupportedCompression(msg)) => {
                 Err(CodecReadError::Parser(anyhow!(msg)))
             }
+            Err(
+                err @ (CheckFrameSizeError::TooLarge { .. }
+                | CheckFrameSizeError::NegativeBodyLength(_)),
+            ) => Err(CodecReadError::Parser(anyhow!(err))),
             err => Err(CodecReadError::Parser(anyhow!(
                 "Failed to parse frame {:?}",
                 err
@@ -747,6 +997,7 @@ pub struct CassandraEncoder {
     handshake_complete: Arc<AtomicBool>,
     message_latency: Histogram,
     stream_id_to_request_id_tx: Option<mpsc::Sender<StreamIdToRequestId>>,
+    compression_config: Option<CassandraCompression>,
 }
 
 impl CassandraEncoder {
@@ -757,6 +1008,7 @@ impl CassandraEncoder {
         handshake_complete: Arc<AtomicBool>,
         message_latency: Histogram,
         stream_id_to_request_id_tx: Option<mpsc::Sender<StreamIdToRequestId>>,
+        compression_config: Option<CassandraCompression>,
     ) -> CassandraEncoder {
         CassandraEncoder {
             message_latency,
@@ -765,6 +1017,7 @@ impl CassandraEncoder {
             direction,
             handshake_complete,
             stream_id_to_request_id_tx,
+            compression_config,
         }
     }
 }
@@ -800,14 +1053,15 @@ impl Encoder<Messages> for CassandraEncoder {
 }
 
 impl CassandraEncoder {
-    pub fn set_startup_state_ext(&mut self, compression: String, version: Version) {
+    pub fn set_startup_state_ext(&mut self, compression: String, version: Version) -> Result<()> {
         let mut startup_map = HashMap::new();
         startup_map.insert("COMPRESSION".into(), compression.to_string());
         let startup = BodyReqStartup { map: startup_map };
 
-        set_startup_state(&mut self.compression, &mut self.version, version, &startup);
+        set_startup_state(&mut self.compression, &mut self.version, version, &startup)?;
         self.handshake_complete
             .store(true, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
     }
 
     fn encode_frame(
@@ -823,6 +1077,23 @@ impl CassandraEncoder {
             return Ok(());
         }
 
+        if m.stream_chunk().is_some() {
+            // Chunks of a streamed response are forwarded exactly as they were received, the first chunk contains the envelope header.
+            // A response is only streamed when it does not need to be recompressed, and shotover always talks to cassandra in the protocol version requested by the client,
+            // so it does not need to be reencoded.
+            if let Encodable::Bytes(bytes) = m.into_encodable() {
+                dst.put(bytes);
+            }
+            return Ok(());
+        }
+
+        let mut m = m;
+        if !handshake_complete {
+            if let Some(configured) = self.compression_config {
+                self.negotiate_configured_compression(&mut m, configured);
+            }
+        }
+
         if let Some(tx) = &self.stream_id_to_request_id_tx {
             let Ok(Metadata::Cassandra(meta)) = m.metadata() else {
                 unreachable!("Guaranteed to be cassandra")
@@ -955,13 +1226,80 @@ impl CassandraEncoder {
             }
             (_, _) => {
                 let message_compression = m.codec_state.as_cassandra();
-                let frame_bytes = self.encode_envelope(m, message_compression)?;
+                // A compressed message must be recompressed when it was received with a different compression than was negotiated on this side of shotover.
+                // Uncompressed messages are valid regardless of the negotiated compression,
+                // but are still compressed when compression is configured, as that is the point of configuring it.
+                let frame_bytes = if message_compression == compression
+                    || (message_compression == Compression::None
+                        && self.compression_config.is_none())
+                {
+                    self.encode_envelope(m, message_compression)?
+                } else {
+                    if m.frame().is_none() {
+                        return Err(anyhow!(
+                            "Failed to parse message to recompress it from {message_compression:?} to {compression:?}"
+                        ));
+                    }
+                    m.invalidate_cache();
+                    self.encode_envelope(m, compression)?
+                };
                 dst.put(frame_bytes);
                 Ok(())
             }
         }
     }
 
+    /// Rewrites the handshake so that the compression negotiated on this side of shotover is the configured compression.
+    /// For a sink the STARTUP message requests the configured compression.
+    /// For a source the SUPPORTED message only offers the configured compression.
+    fn negotiate_configured_compression(&self, m: &mut Message, configured: CassandraCompression) {
+        let Ok(Metadata::Cassandra(meta)) = m.metadata() else {
+            return;
+        };
+        match (self.direction, meta.opcode) {
+            (Direction::Sink, Opcode::Startup) => {
+                if let Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Startup(startup),
+                    version,
+                    ..
+                })) = m.frame()
+                {
+                    // Protocol v5 only supports lz4 compression
+                    let value = match (configured, *version) {
+                        (CassandraCompression::Snappy, Version::V5) => {
+                            tracing::warn!("Snappy compression is not supported by protocol v5, the upstream connection will not be compressed");
+                            None
+                        }
+                        _ => configured.option_value(),
+                    };
+                    match value {
+                        Some(value) => startup
+                            .map
+                            .insert("COMPRESSION".to_owned(), value.to_owned()),
+                        None => startup.map.remove("COMPRESSION"),
+                    };
+                    m.invalidate_cache();
+                }
+            }
+            (Direction::Source, Opcode::Supported) => {
+                if let Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Supported(supported),
+                    ..
+                })) = m.frame()
+                {
+                    match configured.option_value() {
+                        Some(value) => supported
+                            .data
+                            .insert("COMPRESSION".to_owned(), vec![value.to_owned()]),
+                        None => supported.data.remove("COMPRESSION"),
+                    };
+                    m.invalidate_cache();
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn encode_compressed_payload_into_buffer(
         &mut self,
         dst: &mut BytesMut,
@@ -1007,7 +1345,7 @@ impl CassandraEncoder {
                                 &mut self.version,
                                 version,
                                 &startup,
-                            );
+                            )?;
                         };
                     }
 
@@ -1026,7 +1364,7 @@ impl CassandraEncoder {
                     ..
                 }) = &frame
                 {
-                    set_startup_state(&mut self.compression, &mut self.version, *version, startup);
+                    set_startup_state(&mut self.compression, &mut self.version, *version, startup)?;
                 };
 
                 if let Frame::Cassandra(CassandraFrame {
@@ -1047,14 +1385,15 @@ impl CassandraEncoder {
 
 #[cfg(test)]
 mod cassandra_protocol_tests {
-    use crate::codec::cassandra::CassandraCodecBuilder;
-    use crate::codec::{CodecBuilder, Direction};
+    use crate::codec::cassandra::{CassandraCodecBuilder, CassandraCompression};
+    use crate::codec::{CodecBuilder, CodecReadError, Direction};
     use crate::frame::cassandra::{
         parse_statement_single, CassandraFrame, CassandraOperation, CassandraResult, Tracing,
     };
     use crate::frame::Frame;
-    use crate::message::Message;
+    use crate::message::{Message, StreamChunk};
     use bytes::BytesMut;
+    use cassandra_protocol::compression::Compression;
     use cassandra_protocol::events::SimpleServerEvent;
     use cassandra_protocol::frame::message_register::BodyReqRegister;
     use cassandra_protocol::frame::message_result::{
@@ -1324,4 +1663,118 @@ mod cassandra_protocol_tests {
         }))];
         test_frame_codec_roundtrip(&mut codec, &bytes, messages);
     }
+
+    fn decode_error(raw_frame: &[u8]) -> String {
+        decode_error_with_codec(
+            CassandraCodecBuilder::new(Direction::Source, "cassandra".to_owned()),
+            raw_frame,
+        )
+    }
+
+    fn decode_error_with_codec(codec: CassandraCodecBuilder, raw_frame: &[u8]) -> String {
+        let (mut decoder, _) = codec.build();
+        match decoder.decode(&mut BytesMut::from(raw_frame)) {
+            Err(CodecReadError::Parser(err)) => err.to_string(),
+            result => panic!("expected a parser error but was {result:?}"),
+        }
+    }
+
+    #[test]
+    fn test_decode_negative_body_length() {
+        let bytes = hex!("0400000007ffffffff");
+        assert_eq!(decode_error(&bytes), "Negative body length: -1");
+    }
+
+    #[test]
+    fn test_decode_startup_unsupported_compression() {
+        let bytes = hex!("040000000100000015 0001000b434f4d5052455353494f4e00047a737464");
+        assert_eq!(decode_error(&bytes), "Unsupported compression: zstd");
+    }
+
+    #[test]
+    fn test_decode_startup_disallowed_compression() {
+        let bytes = hex!("040000000100000014 0001000b434f4d5052455353494f4e00036c7a34");
+        let codec = CassandraCodecBuilder::new(Direction::Source, "cassandra".to_owned())
+            .with_compression(Some(CassandraCompression::None));
+        assert_eq!(
+            decode_error_with_codec(codec, &bytes),
+            "Unsupported compression: Lz4, only None is allowed"
+        );
+    }
+
+    #[test]
+    fn test_encode_configured_sink_compression() {
+        let codec = CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned())
+            .with_compression(Some(CassandraCompression::Lz4));
+        let (_, mut encoder) = codec.build();
+
+        let mut startup_body: HashMap<String, String> = HashMap::new();
+        startup_body.insert("CQL_VERSION".into(), "3.0.0".into());
+        let startup = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            operation: CassandraOperation::Startup(BodyReqStartup { map: startup_body }),
+            stream_id: 0,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+        }));
+        let mut dest = BytesMut::new();
+        encoder.encode(vec![startup], &mut dest).unwrap();
+        match CassandraFrame::from_bytes(dest.freeze(), Compression::None).unwrap() {
+            CassandraFrame {
+                operation: CassandraOperation::Startup(startup),
+                ..
+            } => assert_eq!(
+                startup.map.get("COMPRESSION").map(|x| x.as_str()),
+                Some("lz4")
+            ),
+            frame => panic!("expected a startup frame but was {frame:?}"),
+        }
+
+        // The client did not compress this request but it is compressed for the upstream connection
+        let options = Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            operation: CassandraOperation::Options(vec![]),
+            stream_id: 1,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+        }));
+        let mut dest = BytesMut::new();
+        encoder.encode(vec![options], &mut dest).unwrap();
+        assert_eq!(dest[1] & 0x01, 0x01, "compression flag should be set");
+        let frame = CassandraFrame::from_bytes(dest.freeze(), Compression::Lz4).unwrap();
+        assert_eq!(frame.operation, CassandraOperation::Options(vec![]));
+    }
+
+    #[test]
+    fn test_decode_streamed_response() {
+        let codec = CassandraCodecBuilder::new(Direction::Sink, "cassandra".to_owned())
+            .with_response_chunk_size(Some(9));
+        let (mut decoder, _) = codec.build();
+
+        // READY completes the handshake
+        let mut src = BytesMut::from(hex!("840000000200000000").as_slice());
+        let ready = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(ready[0].stream_chunk(), None);
+
+        // the first chunk of a void result is forwarded before the rest of the result is received
+        let result = hex!("840000010800000004 00000001");
+        let mut src = BytesMut::from(&result[..10]);
+        let start = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(start.len(), 1);
+        assert_eq!(start[0].stream_chunk(), Some(StreamChunk::Start));
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&result[10..]);
+        let end = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(end[0].stream_chunk(), Some(StreamChunk::End));
+        assert!(src.is_empty());
+
+        let mut chunks = BytesMut::new();
+        let (_, mut encoder) =
+            CassandraCodecBuilder::new(Direction::Source, "cassandra".to_owned()).build();
+        encoder
+            .encode(start.into_iter().chain(end).collect(), &mut chunks)
+            .unwrap();
+        assert_eq!(chunks.as_ref(), result.as_slice());
+    }
 }
@@ -0,0 +1,39 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`, only compiled when building with `--cfg fuzzing`.
+//!
+//! Each function feeds arbitrary bytes to the decoder of a codec as if they were received from a client,
+//! parses every decoded message and then encodes the messages again.
+//! Malformed input must be rejected with an error, so any panic is a bug.
+
+use super::{CodecBuilder, Direction};
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[cfg(feature = "redis")]
+pub fn redis(data: &[u8]) {
+    decode_encode::<super::redis::RedisCodecBuilder>(data);
+}
+
+#[cfg(feature = "cassandra")]
+pub fn cassandra(data: &[u8]) {
+    decode_encode::<super::cassandra::CassandraCodecBuilder>(data);
+}
+
+#[cfg(feature = "kafka")]
+pub fn kafka(data: &[u8]) {
+    decode_encode::<super::kafka::KafkaCodecBuilder>(data);
+}
+
+fn decode_encode<C: CodecBuilder>(data: &[u8]) {
+    let (mut decoder, mut encoder) = C::new(Direction::Source, "fuzz".to_owned()).build();
+    let mut src = BytesMut::from(data);
+    // Keep decoding until the decoder needs more bytes or rejects the input
+    while let Ok(Some(mut messages)) = decoder.decode(&mut src) {
+        for message in &mut messages {
+            // Force the message to be parsed and then encoded from the parsed frame
+            if message.frame().is_some() {
+                message.invalidate_cache();
+            }
+        }
+        encoder.encode(messages, &mut BytesMut::new()).ok();
+    }
+}
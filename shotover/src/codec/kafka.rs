@@ -120,6 +120,22 @@ fn get_length_of_full_message(src: &BytesMut) -> Option<usize> {
     }
 }
 
+/// Reads the api key and version from the header of a request, `bytes` includes the 4 byte length prefix.
+fn parse_request_header(bytes: &[u8]) -> Result<RequestHeader> {
+    if bytes.len() < 8 {
+        return Err(anyhow!(
+            "kafka request of {} bytes is too short to contain a request header",
+            bytes.len()
+        ));
+    }
+    let api_key = i16::from_be_bytes(bytes[4..6].try_into().unwrap());
+    Ok(RequestHeader {
+        api_key: ApiKey::try_from(api_key)
+            .map_err(|_| anyhow!("kafka request has unknown api key {api_key}"))?,
+        version: i16::from_be_bytes(bytes[6..8].try_into().unwrap()),
+    })
+}
+
 impl Decoder for KafkaDecoder {
     type Item = Messages;
     type Error = CodecReadError;
@@ -162,13 +178,7 @@ impl Decoder for KafkaDecoder {
                 }
             } else {
                 Meta {
-                    request_header: RequestHeader {
-                        api_key: ApiKey::try_from(i16::from_be_bytes(
-                            bytes[4..6].try_into().unwrap(),
-                        ))
-                        .unwrap(),
-                        version: i16::from_be_bytes(bytes[6..8].try_into().unwrap()),
-                    },
+                    request_header: parse_request_header(&bytes).map_err(CodecReadError::Parser)?,
                     // This code path is only used for requests, so message_id can be None.
                     message_id: None,
                 }
@@ -330,16 +340,7 @@ impl Encoder<Messages> for KafkaEncoder {
                             version: 0,
                         }
                     } else {
-                        let api_key =
-                            i16::from_be_bytes(dst[start + 4..start + 6].try_into().unwrap());
-                        let version =
-                            i16::from_be_bytes(dst[start + 6..start + 8].try_into().unwrap());
-                        // TODO: handle unknown API key
-                        let api_key = ApiKey::try_from(api_key).map_err(|_| {
-                            CodecWriteError::Encoder(anyhow!("unknown api key {api_key}"))
-                        })?;
-
-                        RequestHeader { api_key, version }
+                        parse_request_header(&dst[start..]).map_err(CodecWriteError::Encoder)?
                     };
 
                     let request_info = RequestInfo {
@@ -376,3 +377,33 @@ pub struct KafkaCodecState {
     /// KafkaFrame will parse this as a SaslHandshake to hide the legacy raw SASL message from transform implementations.
     pub raw_sasl: bool,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn decode(bytes: &[u8]) -> Result<Option<Messages>, CodecReadError> {
+        let (mut decoder, _) =
+            KafkaCodecBuilder::new(Direction::Source, "kafka".to_owned()).build();
+        decoder.decode(&mut BytesMut::from(bytes))
+    }
+
+    #[test]
+    fn test_decode_request_too_short_for_header() {
+        // length prefix of 2 followed by only the api key
+        let err = decode(&[0, 0, 0, 2, 0, 0]).unwrap_err();
+        assert!(matches!(err, CodecReadError::Parser(_)), "{err:?}");
+    }
+
+    #[test]
+    fn test_decode_request_unknown_api_key() {
+        let err = decode(&[0, 0, 0, 4, 0x7f, 0x7f, 0, 0]).unwrap_err();
+        match err {
+            CodecReadError::Parser(err) => {
+                assert_eq!(err.to_string(), "kafka request has unknown api key 32639")
+            }
+            err => panic!("unexpected error {err:?}"),
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use super::{CodecBuilder, CodecReadError, CodecWriteError, Direction};
+use crate::frame::memcached::DEFAULT_MAX_ITEM_SIZE;
+use crate::frame::{Frame, MemcachedFrame, MessageType};
+use crate::message::{Encodable, Message, MessageId, Messages};
+use anyhow::anyhow;
+use bytes::BytesMut;
+use metrics::Histogram;
+use std::sync::mpsc;
+use std::time::Instant;
+use tokio_util::codec::{Decoder, Encoder};
+
+#[derive(Clone)]
+pub struct MemcachedCodecBuilder {
+    direction: Direction,
+    message_latency: Histogram,
+    max_item_size: usize,
+}
+
+impl MemcachedCodecBuilder {
+    /// Requests or responses with values larger than `max_item_size` bytes are rejected by the decoder, closing the connection.
+    /// Defaults to memcached's default of 1MiB.
+    pub fn with_max_item_size(mut self, max_item_size: Option<usize>) -> Self {
+        self.max_item_size = max_item_size.unwrap_or(DEFAULT_MAX_ITEM_SIZE);
+        self
+    }
+}
+
+impl CodecBuilder for MemcachedCodecBuilder {
+    type Decoder = MemcachedDecoder;
+    type Encoder = MemcachedEncoder;
+
+    fn new(direction: Direction, destination_name: String) -> Self {
+        let message_latency = super::message_latency(direction, destination_name);
+        Self {
+            direction,
+            message_latency,
+            max_item_size: DEFAULT_MAX_ITEM_SIZE,
+        }
+    }
+
+    fn build(&self) -> (MemcachedDecoder, MemcachedEncoder) {
+        let (tx, rx) = match self.direction {
+            Direction::Source => (None, None),
+            Direction::Sink => {
+                let (tx, rx) = mpsc::channel();
+                (Some(tx), Some(rx))
+            }
+        };
+        (
+            MemcachedDecoder {
+                request_header_rx: rx,
+                direction: self.direction,
+                max_item_size: self.max_item_size,
+            },
+            MemcachedEncoder {
+                request_header_tx: tx,
+                direction: self.direction,
+                message_latency: self.message_latency.clone(),
+            },
+        )
+    }
+
+    fn protocol(&self) -> MessageType {
+        MessageType::Memcached
+    }
+}
+
+pub struct MemcachedDecoder {
+    // Some when Sink (because it receives responses)
+    request_header_rx: Option<mpsc::Receiver<MessageId>>,
+    direction: Direction,
+    max_item_size: usize,
+}
+
+impl Decoder for MemcachedDecoder {
+    type Item = Messages;
+    type Error = CodecReadError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let received_at = Instant::now();
+        match MemcachedFrame::parse(src, self.max_item_size)
+            .map_err(|e| CodecReadError::Parser(e.context("Error decoding memcached frame")))?
+        {
+            Some((frame, size)) => {
+                let bytes = src.split_to(size).freeze();
+                tracing::debug!(
+                    "{}: incoming memcached message:\n{}",
+                    self.direction,
+                    pretty_hex::pretty_hex(&bytes)
+                );
+                let mut message = Message::from_bytes_and_frame_at_instant(
+                    bytes,
+                    Frame::Memcached(frame),
+                    Some(received_at),
+                );
+                if let Some(rx) = self.request_header_rx.as_ref() {
+                    let id = rx.recv().map_err(|_| {
+                        CodecReadError::Parser(anyhow!("memcached encoder half was lost"))
+                    })?;
+                    message.set_request_id(id);
+                }
+                Ok(Some(vec![message]))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct MemcachedEncoder {
+    // Some when Sink (because it sends requests)
+    request_header_tx: Option<mpsc::Sender<MessageId>>,
+    direction: Direction,
+    message_latency: Histogram,
+}
+
+impl Encoder<Messages> for MemcachedEncoder {
+    type Error = CodecWriteError;
+
+    fn encode(&mut self, item: Messages, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.into_iter().try_for_each(|mut m| {
+            if m.is_dummy() {
+                // skip dummy messages, such as the responses to noreply requests
+                return Ok(());
+            }
+            let start = dst.len();
+            m.ensure_message_type(MessageType::Memcached)
+                .map_err(CodecWriteError::Encoder)?;
+            let received_at = m.received_from_source_or_sink_at;
+            if let Some(tx) = self.request_header_tx.as_ref() {
+                // memcached will not send a response to a noreply request, so there is no response to pair the id with
+                if !m.response_is_dummy() {
+                    tx.send(m.id())
+                        .map_err(|e| CodecWriteError::Encoder(anyhow!(e)))?;
+                }
+            }
+            match m.into_encodable() {
+                Encodable::Bytes(bytes) => dst.extend_from_slice(&bytes),
+                Encodable::Frame(frame) => frame.into_memcached().unwrap().encode(dst),
+            }
+            if let Some(received_at) = received_at {
+                self.message_latency.record(received_at.elapsed());
+            }
+            tracing::debug!(
+                "{}: outgoing memcached message:\n{}",
+                self.direction,
+                pretty_hex::pretty_hex(&&dst[start..])
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod memcached_tests {
+    use crate::codec::{memcached::MemcachedCodecBuilder, CodecBuilder, Direction};
+    use crate::frame::memcached::{MemcachedRequest, MemcachedResponse, MemcachedValue};
+    use crate::frame::{Frame, MemcachedFrame};
+    use crate::message::Message;
+    use bytes::{Bytes, BytesMut};
+    use pretty_assertions::assert_eq;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn test_frame(raw_frame: &[u8], expected: MemcachedFrame) {
+        let (mut decoder, mut encoder) =
+            MemcachedCodecBuilder::new(Direction::Source, "memcached".to_owned()).build();
+        let mut message = decoder
+            .decode(&mut BytesMut::from(raw_frame))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            message[0].frame(),
+            Some(&mut Frame::Memcached(expected.clone()))
+        );
+
+        let mut dest = BytesMut::new();
+        encoder.encode(message, &mut dest).unwrap();
+        assert_eq!(raw_frame, &dest);
+
+        // also check that encoding from the frame matches the original bytes
+        let mut dest = BytesMut::new();
+        encoder
+            .encode(
+                vec![Message::from_frame(Frame::Memcached(expected))],
+                &mut dest,
+            )
+            .unwrap();
+        assert_eq!(raw_frame, &dest);
+    }
+
+    #[test]
+    fn test_get_codec() {
+        test_frame(
+            b"get foo bar\r\n",
+            MemcachedFrame::Request(MemcachedRequest::Get {
+                keys: vec![Bytes::from("foo"), Bytes::from("bar")],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_set_codec() {
+        test_frame(
+            b"set foo 5 100 3 noreply\r\nbar\r\n",
+            MemcachedFrame::Request(MemcachedRequest::Set {
+                key: Bytes::from("foo"),
+                flags: 5,
+                exptime: 100,
+                data: Bytes::from("bar"),
+                noreply: true,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_incr_codec() {
+        test_frame(
+            b"incr counter 10\r\n",
+            MemcachedFrame::Request(MemcachedRequest::Incr {
+                key: Bytes::from("counter"),
+                value: 10,
+                noreply: false,
+            }),
+        );
+    }
+
+    #[test]
+    fn test_values_codec() {
+        test_frame(
+            b"VALUE foo 0 3\r\nbar\r\nVALUE baz 1 0\r\n\r\nEND\r\n",
+            MemcachedFrame::Response(MemcachedResponse::Values(vec![
+                MemcachedValue {
+                    key: Bytes::from("foo"),
+                    flags: 0,
+                    data: Bytes::from("bar"),
+                },
+                MemcachedValue {
+                    key: Bytes::from("baz"),
+                    flags: 1,
+                    data: Bytes::new(),
+                },
+            ])),
+        );
+    }
+
+    #[test]
+    fn test_server_error_codec() {
+        test_frame(
+            b"SERVER_ERROR out of memory\r\n",
+            MemcachedFrame::Response(MemcachedResponse::ServerError("out of memory".to_owned())),
+        );
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let (mut decoder, _) =
+            MemcachedCodecBuilder::new(Direction::Source, "memcached".to_owned()).build();
+        let mut src = BytesMut::from(&b"set foo 0 0 10\r\nbar"[..]);
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+        assert_eq!(src.len(), 19);
+    }
+
+    #[test]
+    fn test_max_item_size() {
+        let (mut decoder, _) =
+            MemcachedCodecBuilder::new(Direction::Source, "memcached".to_owned())
+                .with_max_item_size(Some(3))
+                .build();
+        let mut src = BytesMut::from(&b"set foo 0 0 3\r\nbar\r\n"[..]);
+        assert!(decoder.decode(&mut src).unwrap().is_some());
+
+        let mut src = BytesMut::from(&b"set foo 0 0 4\r\nba"[..]);
+        assert!(decoder.decode(&mut src).is_err());
+
+        let mut src = BytesMut::from(&b"set foo 0 0 18446744073709551615\r\n"[..]);
+        assert!(decoder.decode(&mut src).is_err());
+
+        let (mut decoder, _) =
+            MemcachedCodecBuilder::new(Direction::Source, "memcached".to_owned())
+                .with_max_item_size(Some(usize::MAX))
+                .build();
+        let mut src = BytesMut::from(&b"set foo 0 0 18446744073709551615\r\n"[..]);
+        assert!(decoder.decode(&mut src).is_err());
+    }
+}
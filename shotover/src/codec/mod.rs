@@ -11,8 +11,12 @@ use tokio_util::codec::{Decoder, Encoder};
 
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
+#[cfg(fuzzing)]
+pub mod fuzz;
 #[cfg(feature = "kafka")]
 pub mod kafka;
+#[cfg(feature = "memcached")]
+pub mod memcached;
 #[cfg(feature = "opensearch")]
 pub mod opensearch;
 #[cfg(feature = "redis")]
@@ -65,6 +69,8 @@ pub enum CodecState {
     Dummy,
     #[cfg(feature = "opensearch")]
     OpenSearch,
+    #[cfg(feature = "memcached")]
+    Memcached,
 }
 
 impl CodecState {
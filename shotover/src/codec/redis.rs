@@ -1,21 +1,40 @@
 use std::sync::mpsc;
 use std::time::Instant;
 
-use super::{CodecWriteError, Direction};
+use super::{CodecState, CodecWriteError, Direction};
 use crate::codec::{CodecBuilder, CodecReadError};
 use crate::frame::{Frame, MessageType, RedisFrame};
-use crate::message::{Encodable, Message, MessageId, Messages};
-use anyhow::{anyhow, Result};
-use bytes::BytesMut;
+use crate::message::{Encodable, Message, MessageId, Messages, StreamChunk};
+use anyhow::{anyhow, bail, Result};
+use bytes::{Bytes, BytesMut};
 use metrics::Histogram;
 use redis_protocol::resp2::decode::decode_bytes_mut;
 use redis_protocol::resp2::encode::extend_encode;
+use std::collections::HashSet;
 use tokio_util::codec::{Decoder, Encoder};
 
 #[derive(Clone)]
 pub struct RedisCodecBuilder {
     direction: Direction,
     message_latency: Histogram,
+    max_message_size: Option<usize>,
+    response_chunk_size: Option<usize>,
+}
+
+impl RedisCodecBuilder {
+    /// Messages larger than `max_message_size` bytes are rejected by the decoder, closing the connection.
+    pub fn with_max_message_size(mut self, max_message_size: Option<usize>) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// For a sink, responses larger than `response_chunk_size` bytes are decoded into chunks of at most `response_chunk_size` bytes as they are received,
+    /// instead of into a single message once the whole response is received, see [`StreamChunk`].
+    /// Replies to (un)subscribe requests and responses received while subscribed are never streamed, as they are needed to track the subscriptions.
+    pub fn with_response_chunk_size(mut self, response_chunk_size: Option<usize>) -> Self {
+        self.response_chunk_size = response_chunk_size;
+        self
+    }
 }
 
 impl CodecBuilder for RedisCodecBuilder {
@@ -27,6 +46,8 @@ impl CodecBuilder for RedisCodecBuilder {
         Self {
             direction,
             message_latency,
+            max_message_size: None,
+            response_chunk_size: None,
         }
     }
 
@@ -39,7 +60,12 @@ impl CodecBuilder for RedisCodecBuilder {
             }
         };
         (
-            RedisDecoder::new(rx, self.direction),
+            RedisDecoder::new(
+                rx,
+                self.direction,
+                self.max_message_size,
+                self.response_chunk_size,
+            ),
             RedisEncoder::new(tx, self.direction, self.message_latency.clone()),
         )
     }
@@ -54,16 +80,89 @@ pub struct RequestInfo {
     id: MessageId,
 }
 pub enum RequestType {
-    /// a pubsub subscribe
-    Subscribe,
-    /// a unsubscribe
-    Unsubscribe,
+    /// a pubsub subscribe, redis sends a reply for each of the `channels` subscribed to
+    Subscribe { channels: usize },
+    /// a unsubscribe, redis sends a reply for each of the `channels` unsubscribed from.
+    /// When no channels are given every channel of the `kind` is unsubscribed from.
+    Unsubscribe {
+        kind: SubscriptionKind,
+        channels: usize,
+    },
     /// redis reset
     Reset,
     /// Everything else
     Other,
 }
 
+#[derive(Clone, Copy)]
+pub enum SubscriptionKind {
+    /// SUBSCRIBE
+    Channel,
+    /// PSUBSCRIBE
+    Pattern,
+    /// SSUBSCRIBE
+    ShardChannel,
+}
+
+/// The channels a sink connection is subscribed to, tracked from the replies to its (un)subscribe requests.
+#[derive(Default)]
+struct Subscriptions {
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+    shard_channels: HashSet<Bytes>,
+}
+
+impl Subscriptions {
+    fn is_subscribed(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    fn of_kind(&mut self, kind: SubscriptionKind) -> &mut HashSet<Bytes> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::ShardChannel => &mut self.shard_channels,
+        }
+    }
+
+    /// Returns the number of replies redis sends to a request of type `ty`
+    fn reply_count(&mut self, ty: &RequestType) -> usize {
+        match ty {
+            RequestType::Subscribe { channels } => (*channels).max(1),
+            // Unsubscribing from nothing still returns a single reply
+            RequestType::Unsubscribe { kind, channels: 0 } => self.of_kind(*kind).len().max(1),
+            RequestType::Unsubscribe { channels, .. } => (*channels).max(1),
+            RequestType::Reset | RequestType::Other => 1,
+        }
+    }
+
+    /// Updates the subscriptions from a `subscribe` or `unsubscribe` reply
+    fn update(&mut self, frame: &RedisFrame) {
+        let RedisFrame::Array(array) = frame else {
+            return;
+        };
+        let [RedisFrame::BulkString(ty), channel, RedisFrame::Integer(_)] = array.as_slice() else {
+            return;
+        };
+        let (kind, subscribe) = match ty.as_ref() {
+            b"subscribe" => (SubscriptionKind::Channel, true),
+            b"unsubscribe" => (SubscriptionKind::Channel, false),
+            b"psubscribe" => (SubscriptionKind::Pattern, true),
+            b"punsubscribe" => (SubscriptionKind::Pattern, false),
+            b"ssubscribe" => (SubscriptionKind::ShardChannel, true),
+            b"sunsubscribe" => (SubscriptionKind::ShardChannel, false),
+            _ => return,
+        };
+        if let RedisFrame::BulkString(channel) = channel {
+            if subscribe {
+                self.of_kind(kind).insert(channel.clone());
+            } else {
+                self.of_kind(kind).remove(channel);
+            }
+        }
+    }
+}
+
 pub struct RedisEncoder {
     // Some when Sink (because it sends requests)
     request_header_tx: Option<mpsc::Sender<RequestInfo>>,
@@ -75,18 +174,249 @@ pub struct RedisDecoder {
     // Some when Sink (because it receives responses)
     request_header_rx: Option<mpsc::Receiver<RequestInfo>>,
     direction: Direction,
-    is_subscribed: bool,
+    subscriptions: Subscriptions,
+    /// The number of replies still to be received for the last (un)subscribe request, after its first reply.
+    remaining_replies: usize,
+    max_message_size: Option<usize>,
+    response_chunk_size: Option<usize>,
+    /// The request of the next reply, when it was received from the encoder but its reply was not streamed after all
+    next_request: Option<RequestInfo>,
+    /// The response currently being streamed, its remaining chunks are decoded before any other message
+    streamed_response: Option<StreamedResponse>,
+}
+
+struct StreamedResponse {
+    scanner: MessageScanner,
+    /// The number of bytes of the response that have been decoded so far
+    size: usize,
+    request_id: MessageId,
+}
+
+/// Finds the end of a RESP2 message that is received in pieces, without parsing it into a frame.
+#[derive(Clone, Copy)]
+struct MessageScanner {
+    /// The number of frames that still need to be scanned to complete the message, the elements of arrays are counted as they are scanned
+    pending: usize,
+    state: ScanState,
+}
+
+#[derive(Clone, Copy)]
+enum ScanState {
+    /// At the type byte of the next frame
+    Header,
+    /// Within a simple string, error or integer, which end at the next newline
+    Line,
+    /// Within the contents of a bulk string, holding the number of bytes left including the trailing CRLF
+    Bulk(usize),
+}
+
+impl MessageScanner {
+    fn new() -> Self {
+        MessageScanner {
+            pending: 1,
+            state: ScanState::Header,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.pending == 0
+    }
+
+    /// Scans `src` onwards from where the previous call left off, returning the number of bytes of `src` that belong to the message.
+    /// The header of a bulk string or array is only scanned once the whole header is contained in `src`.
+    fn scan(&mut self, src: &[u8]) -> Result<usize> {
+        let mut i = 0;
+        while !self.is_complete() && i < src.len() {
+            match self.state {
+                ScanState::Header => match src[i] {
+                    b'+' | b'-' | b':' => {
+                        self.state = ScanState::Line;
+                        i += 1;
+                    }
+                    ty @ (b'$' | b'*') => {
+                        let Some(end) = src[i..].iter().position(|x| *x == b'\n') else {
+                            break;
+                        };
+                        let len: i64 = std::str::from_utf8(&src[i + 1..i + end])
+                            .ok()
+                            .and_then(|x| x.trim_end_matches('\r').parse().ok())
+                            .ok_or_else(|| anyhow!("invalid redis length"))?;
+                        i += end + 1;
+                        match usize::try_from(len) {
+                            Ok(len) if ty == b'$' => self.state = ScanState::Bulk(len + 2),
+                            Ok(len) => self.pending = self.pending + len - 1,
+                            // a null bulk string or null array
+                            Err(_) => self.pending -= 1,
+                        }
+                    }
+                    ty => bail!("invalid redis type byte {ty:#x}"),
+                },
+                ScanState::Line => match src[i..].iter().position(|x| *x == b'\n') {
+                    Some(end) => {
+                        i += end + 1;
+                        self.state = ScanState::Header;
+                        self.pending -= 1;
+                    }
+                    None => i = src.len(),
+                },
+                ScanState::Bulk(remaining) => {
+                    let len = remaining.min(src.len() - i);
+                    i += len;
+                    if len == remaining {
+                        self.state = ScanState::Header;
+                        self.pending -= 1;
+                    } else {
+                        self.state = ScanState::Bulk(remaining - len);
+                    }
+                }
+            }
+        }
+        Ok(i)
+    }
 }
 
 impl RedisDecoder {
     pub fn new(
         request_header_rx: Option<mpsc::Receiver<RequestInfo>>,
         direction: Direction,
+        max_message_size: Option<usize>,
+        response_chunk_size: Option<usize>,
     ) -> Self {
         Self {
             direction,
             request_header_rx,
-            is_subscribed: false,
+            subscriptions: Subscriptions::default(),
+            remaining_replies: 0,
+            max_message_size,
+            response_chunk_size,
+            next_request: None,
+            streamed_response: None,
+        }
+    }
+
+    /// Returns the request that the next reply received is for.
+    /// Must only be called by a sink decoder.
+    fn next_request(&mut self) -> Result<RequestInfo, CodecReadError> {
+        if let Some(request) = self.next_request.take() {
+            return Ok(request);
+        }
+        // The request is always sent before its response can be received, so it must be waiting already.
+        // Waiting for it would block the runtime, so any other case is a protocol error.
+        let rx = self.request_header_rx.as_ref().unwrap();
+        rx.try_recv().map_err(|err| match err {
+            mpsc::TryRecvError::Empty => CodecReadError::Parser(anyhow!(
+                "received a redis response without a pending request"
+            )),
+            mpsc::TryRecvError::Disconnected => {
+                CodecReadError::Parser(anyhow!("redis encoder half was lost"))
+            }
+        })
+    }
+
+    /// Starts streaming a response larger than `response_chunk_size` once its first chunk has been received, instead of waiting for the whole response.
+    /// Returns None when the message at the start of `src` is not streamed.
+    fn start_streamed_response(
+        &mut self,
+        src: &mut BytesMut,
+        received_at: Instant,
+    ) -> Result<Option<Messages>, CodecReadError> {
+        let Some(chunk_size) = self.response_chunk_size else {
+            return Ok(None);
+        };
+        if self.request_header_rx.is_none()
+            || src.len() < chunk_size
+            // published messages and the replies to (un)subscribe requests are parsed to track the subscriptions
+            || self.subscriptions.is_subscribed()
+            || self.remaining_replies > 0
+        {
+            return Ok(None);
+        }
+
+        let mut scanner = MessageScanner::new();
+        let len = scanner
+            .scan(&src[..chunk_size])
+            .map_err(CodecReadError::Parser)?;
+        self.check_size(len)?;
+        let mut rest = scanner;
+        rest.scan(&src[len..]).map_err(CodecReadError::Parser)?;
+        // the whole response has already been received so there is nothing to gain
+        if rest.is_complete() || len == 0 {
+            return Ok(None);
+        }
+
+        let request = self.next_request()?;
+        if !matches!(request.ty, RequestType::Other) {
+            self.next_request = Some(request);
+            return Ok(None);
+        }
+        self.streamed_response = Some(StreamedResponse {
+            scanner,
+            size: len,
+            request_id: request.id,
+        });
+        Ok(Some(vec![Message::from_stream_chunk_at_instant(
+            src.split_to(len).freeze(),
+            CodecState::Redis,
+            StreamChunk::Start,
+            Some(received_at),
+        )]))
+    }
+
+    /// Decodes the next chunk of the response being streamed once it has been received.
+    fn decode_streamed_response(
+        &mut self,
+        src: &mut BytesMut,
+        received_at: Instant,
+    ) -> Result<Option<Messages>, CodecReadError> {
+        // a response is only streamed when response_chunk_size is set
+        let chunk_size = self.response_chunk_size.unwrap();
+        let streamed = self.streamed_response.as_mut().unwrap();
+        let mut scanner = streamed.scanner;
+        let mut len = scanner
+            .scan(&src[..src.len().min(chunk_size)])
+            .map_err(CodecReadError::Parser)?;
+        if len == 0 && src.len() >= chunk_size {
+            // the header of a bulk string or array is longer than the chunk size
+            len = scanner.scan(src).map_err(CodecReadError::Parser)?;
+        }
+        // keep waiting until a whole chunk or the end of the response has been received
+        if !scanner.is_complete() && (len == 0 || src.len() < chunk_size) {
+            return Ok(None);
+        }
+        streamed.scanner = scanner;
+        streamed.size += len;
+        let size = streamed.size;
+        let request_id = streamed.request_id;
+        self.check_size(size)?;
+
+        let bytes = src.split_to(len).freeze();
+        let message = if scanner.is_complete() {
+            self.streamed_response = None;
+            let mut message = Message::from_stream_chunk_at_instant(
+                bytes,
+                CodecState::Redis,
+                StreamChunk::End,
+                Some(received_at),
+            );
+            message.set_request_id(request_id);
+            message
+        } else {
+            Message::from_stream_chunk_at_instant(
+                bytes,
+                CodecState::Redis,
+                StreamChunk::Continuation,
+                Some(received_at),
+            )
+        };
+        Ok(Some(vec![message]))
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), CodecReadError> {
+        match self.max_message_size {
+            Some(max) if size > max => Err(CodecReadError::Parser(anyhow!(
+                "redis message of at least {size} bytes exceeds the max message size of {max} bytes"
+            ))),
+            _ => Ok(()),
         }
     }
 }
@@ -97,10 +427,18 @@ impl Decoder for RedisDecoder {
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         let received_at = Instant::now();
+        if self.streamed_response.is_some() {
+            return self.decode_streamed_response(src, received_at);
+        }
+        if let Some(messages) = self.start_streamed_response(src, received_at)? {
+            return Ok(Some(messages));
+        }
+
         match decode_bytes_mut(src)
             .map_err(|e| CodecReadError::Parser(anyhow!(e).context("Error decoding redis frame")))?
         {
             Some((frame, _size, bytes)) => {
+                self.check_size(bytes.len())?;
                 tracing::debug!(
                     "{}: incoming redis message:\n{}",
                     self.direction,
@@ -115,9 +453,12 @@ impl Decoder for RedisDecoder {
                 // Notes on subscription responses
                 //
                 // There are 3 types of pubsub responses and the type is determined by the first value in the array:
-                // * `subscribe` - a response to a SUBSCRIBE, PSUBSCRIBE or SSUBSCRIBE request
-                // * `unsubscribe` - a response to an UNSUBSCRIBE, PUNSUBSCRIBE or SUNSUBSCRIBE request
-                // * `message` - a subscription message
+                // * `subscribe`, `psubscribe` or `ssubscribe` - a reply to a SUBSCRIBE, PSUBSCRIBE or SSUBSCRIBE request
+                // * `unsubscribe`, `punsubscribe` or `sunsubscribe` - a reply to an UNSUBSCRIBE, PUNSUBSCRIBE or SUNSUBSCRIBE request
+                // * `message`, `pmessage` or `smessage` - a message published to a subscribed channel
+                //
+                // A (un)subscribe request receives a reply for every channel it names,
+                // or when no channels are named, for every channel that was unsubscribed from.
                 //
                 // Additionally redis will:
                 // * accept a few regular commands while in pubsub mode: PING, RESET and QUIT
@@ -127,63 +468,56 @@ impl Decoder for RedisDecoder {
                 //       It returns an array ['pong', $pingMessage] instead of directly returning $pingMessage.
                 //       But this doesnt cause any problems for us.
 
-                // Determine if message is a `message` subscription message
+                // Determine if message is a published message
                 //
-                // Because PING, RESET, QUIT and error responses never return a RedisFrame::Array starting with `message`,
-                // they have no way to collide with the `message` value of a subscription message.
+                // Because PING, RESET, QUIT and error responses never return a RedisFrame::Array starting with one of these types,
+                // they have no way to collide with a published message.
                 // So while we are in subscription mode we can use that to determine if an
-                // incoming message is a subscription message.
-                let is_subscription_message = if self.is_subscribed {
-                    if let Some(Frame::Redis(RedisFrame::Array(array))) = message.frame() {
-                        if let [RedisFrame::BulkString(ty), ..] = array.as_slice() {
-                            ty.as_ref() == b"message"
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                };
+                // incoming message is a published message.
+                let is_published_message = self.subscriptions.is_subscribed()
+                    && matches!(
+                        message.frame(),
+                        Some(Frame::Redis(RedisFrame::Array(array)))
+                            if matches!(
+                                array.first(),
+                                Some(RedisFrame::BulkString(ty))
+                                    if matches!(ty.as_ref(), b"message" | b"pmessage" | b"smessage")
+                            )
+                    );
 
-                // Update is_subscribed state
-                //
                 // In order to make sense of a response we need the main task to
                 // send us the type of its corresponding request.
                 //
-                // In order to keep the incoming request MessageTypes in sync with their corresponding responses
-                // we must only process a MessageType when the message is not a subscription message.
-                // This is fine because subscription messages cannot affect the is_subscribed state.
-                if !is_subscription_message {
-                    if let Some(rx) = self.request_header_rx.as_ref() {
-                        let request_info = rx.recv().map_err(|_| {
-                            CodecReadError::Parser(anyhow!("redis encoder half was lost"))
-                        })?;
+                // In order to keep the requests in sync with their corresponding responses
+                // we must only receive a request when the message is the first reply to a request.
+                // Published messages and the additional replies to an (un)subscribe request are passed on
+                // without a request id, the same as any other message pushed by redis.
+                if !is_published_message && self.request_header_rx.is_some() {
+                    if self.remaining_replies > 0 {
+                        self.remaining_replies -= 1;
+                    } else {
+                        let request_info = self.next_request()?;
                         message.set_request_id(request_info.id);
-                        match request_info.ty {
-                            RequestType::Subscribe | RequestType::Unsubscribe => {
-                                if let Some(Frame::Redis(RedisFrame::Array(array))) =
-                                    message.frame()
-                                {
-                                    if let Some(RedisFrame::Integer(
-                                        number_of_subscribed_channels,
-                                    )) = array.get(2)
-                                    {
-                                        self.is_subscribed = *number_of_subscribed_channels != 0;
-                                    }
-                                }
-                            }
-                            RequestType::Reset => {
-                                self.is_subscribed = false;
-                            }
-                            RequestType::Other => {}
+                        let is_error =
+                            matches!(message.frame(), Some(Frame::Redis(RedisFrame::Error(_))));
+                        if let RequestType::Reset = request_info.ty {
+                            self.subscriptions = Subscriptions::default();
+                        } else if !is_error {
+                            self.remaining_replies =
+                                self.subscriptions.reply_count(&request_info.ty) - 1;
                         }
                     }
+                    if let Some(Frame::Redis(frame)) = message.frame() {
+                        self.subscriptions.update(frame);
+                    }
                 }
                 Ok(Some(vec![message]))
             }
-            None => Ok(None),
+            None => {
+                // The incomplete message is already larger than the limit, so reject it before buffering any more of it
+                self.check_size(src.len())?;
+                Ok(None)
+            }
         }
     }
 }
@@ -214,11 +548,23 @@ impl Encoder<Messages> for RedisEncoder {
             if let Some(tx) = self.request_header_tx.as_ref() {
                 let ty = if let Some(Frame::Redis(RedisFrame::Array(array))) = m.frame() {
                     if let Some(RedisFrame::BulkString(bytes)) = array.first() {
+                        let channels = array.len() - 1;
                         match bytes.to_ascii_uppercase().as_slice() {
-                            b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => RequestType::Subscribe,
-                            b"UNSUBSCRIBE" | b"PUNSUBSCRIBE" | b"SUNSUBSCRIBE" => {
-                                RequestType::Unsubscribe
+                            b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => {
+                                RequestType::Subscribe { channels }
                             }
+                            b"UNSUBSCRIBE" => RequestType::Unsubscribe {
+                                kind: SubscriptionKind::Channel,
+                                channels,
+                            },
+                            b"PUNSUBSCRIBE" => RequestType::Unsubscribe {
+                                kind: SubscriptionKind::Pattern,
+                                channels,
+                            },
+                            b"SUNSUBSCRIBE" => RequestType::Unsubscribe {
+                                kind: SubscriptionKind::ShardChannel,
+                                channels,
+                            },
                             b"RESET" => RequestType::Reset,
                             _ => RequestType::Other,
                         }
@@ -260,7 +606,9 @@ impl Encoder<Messages> for RedisEncoder {
 mod redis_tests {
 
     use crate::codec::{redis::RedisCodecBuilder, CodecBuilder, Direction};
-    use bytes::BytesMut;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::message::{Message, StreamChunk};
+    use bytes::{Bytes, BytesMut};
     use hex_literal::hex;
     use pretty_assertions::assert_eq;
     use tokio_util::codec::{Decoder, Encoder};
@@ -344,4 +692,164 @@ mod redis_tests {
     fn test_hset_codec() {
         test_frame(&HSET_MESSAGE);
     }
+
+    fn array(args: &[&'static str]) -> RedisFrame {
+        RedisFrame::Array(
+            args.iter()
+                .map(|x| RedisFrame::BulkString(Bytes::from_static(x.as_bytes())))
+                .collect(),
+        )
+    }
+
+    fn reply(ty: &'static str, channel: &'static str, count: i64) -> RedisFrame {
+        RedisFrame::Array(vec![
+            RedisFrame::BulkString(Bytes::from_static(ty.as_bytes())),
+            RedisFrame::BulkString(Bytes::from_static(channel.as_bytes())),
+            RedisFrame::Integer(count),
+        ])
+    }
+
+    /// Sends `requests` through a sink codec and decodes `replies`,
+    /// returning the index of the request each reply was paired with, or None for pushed messages
+    fn pair_replies(requests: Vec<RedisFrame>, replies: Vec<RedisFrame>) -> Vec<Option<usize>> {
+        let (mut decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        let requests: Vec<Message> = requests
+            .into_iter()
+            .map(|x| Message::from_frame(Frame::Redis(x)))
+            .collect();
+        let ids: Vec<_> = requests.iter().map(|x| x.id()).collect();
+        encoder.encode(requests, &mut BytesMut::new()).unwrap();
+
+        let mut src = BytesMut::new();
+        for reply in replies {
+            redis_protocol::resp2::encode::extend_encode(&mut src, &reply).unwrap();
+        }
+        let mut result = vec![];
+        while let Some(messages) = decoder.decode(&mut src).unwrap() {
+            for message in messages {
+                result.push(
+                    message
+                        .request_id()
+                        .map(|id| ids.iter().position(|x| *x == id).unwrap()),
+                );
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_subscribe_replies() {
+        assert_eq!(
+            pair_replies(
+                vec![
+                    array(&["SUBSCRIBE", "a", "b"]),
+                    array(&["PSUBSCRIBE", "p*"]),
+                    array(&["SSUBSCRIBE", "s"]),
+                    array(&["PING"]),
+                    array(&["UNSUBSCRIBE"]),
+                    array(&["PUNSUBSCRIBE", "p*"]),
+                    array(&["SUNSUBSCRIBE"]),
+                    array(&["UNSUBSCRIBE"]),
+                ],
+                vec![
+                    reply("subscribe", "a", 1),
+                    // published before the second channel was subscribed to
+                    array(&["message", "a", "1"]),
+                    reply("subscribe", "b", 2),
+                    reply("psubscribe", "p*", 3),
+                    array(&["pmessage", "p*", "pa", "2"]),
+                    reply("ssubscribe", "s", 1),
+                    array(&["smessage", "s", "3"]),
+                    array(&["pong", ""]),
+                    // unsubscribes from both channels
+                    reply("unsubscribe", "a", 2),
+                    reply("unsubscribe", "b", 1),
+                    reply("punsubscribe", "p*", 0),
+                    reply("sunsubscribe", "s", 0),
+                    // no channels left to unsubscribe from
+                    RedisFrame::Array(vec![
+                        RedisFrame::BulkString(Bytes::from_static(b"unsubscribe")),
+                        RedisFrame::Null,
+                        RedisFrame::Integer(0),
+                    ]),
+                ]
+            ),
+            vec![
+                Some(0),
+                None,
+                None,
+                Some(1),
+                None,
+                Some(2),
+                None,
+                Some(3),
+                Some(4),
+                None,
+                Some(5),
+                Some(6),
+                Some(7),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_streamed_response() {
+        let (mut decoder, mut encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned())
+                .with_response_chunk_size(Some(8))
+                .build();
+        let requests = vec![
+            Message::from_frame(Frame::Redis(array(&["LRANGE", "list", "0", "-1"]))),
+            Message::from_frame(Frame::Redis(array(&["LRANGE", "list", "0", "-1"]))),
+        ];
+        let ids: Vec<_> = requests.iter().map(|x| x.id()).collect();
+        encoder.encode(requests, &mut BytesMut::new()).unwrap();
+
+        // the first chunk is forwarded before the rest of the response is received
+        let response = b"*2\r\n$5\r\nhello\r\n$5\r\nworld\r\n";
+        let mut src = BytesMut::from(&response[..10]);
+        let mut chunks = decoder.decode(&mut src).unwrap().unwrap();
+        assert!(decoder.decode(&mut src).unwrap().is_none());
+
+        src.extend_from_slice(&response[10..]);
+        while let Some(messages) = decoder.decode(&mut src).unwrap() {
+            chunks.extend(messages);
+        }
+        assert!(src.is_empty());
+        assert_eq!(
+            chunks.iter().map(|x| x.stream_chunk()).collect::<Vec<_>>(),
+            vec![
+                Some(StreamChunk::Start),
+                Some(StreamChunk::Continuation),
+                Some(StreamChunk::Continuation),
+                Some(StreamChunk::End),
+            ]
+        );
+        assert_eq!(
+            chunks.iter().map(|x| x.request_id()).collect::<Vec<_>>(),
+            vec![None, None, None, Some(ids[0])]
+        );
+
+        let mut encoded = BytesMut::new();
+        let (_, mut encoder) =
+            RedisCodecBuilder::new(Direction::Source, "redis".to_owned()).build();
+        encoder.encode(chunks, &mut encoded).unwrap();
+        assert_eq!(encoded.as_ref(), response.as_slice());
+
+        // a response that has already been received in full is not streamed
+        let mut src = BytesMut::from(response.as_slice());
+        let messages = decoder.decode(&mut src).unwrap().unwrap();
+        assert_eq!(messages[0].stream_chunk(), None);
+        assert_eq!(messages[0].request_id(), Some(ids[1]));
+    }
+
+    #[test]
+    fn test_response_without_request() {
+        let (mut decoder, _encoder) =
+            RedisCodecBuilder::new(Direction::Sink, "redis".to_owned()).build();
+        assert!(decoder
+            .decode(&mut BytesMut::from(&OK_MESSAGE[..]))
+            .is_err());
+    }
 }
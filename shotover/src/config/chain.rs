@@ -3,26 +3,71 @@ use crate::transforms::{
     DownChainProtocol, TransformBuilder, TransformConfig, TransformContextConfig, UpChainProtocol,
 };
 use anyhow::{anyhow, Result};
-use serde::de::{DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{
+    DeserializeSeed, Deserializer, Error, IntoDeserializer, MapAccess, SeqAccess, Visitor,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt::{self, Debug};
 use std::iter;
+use std::time::Duration;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct TransformChainConfig(
     #[serde(rename = "TransformChain", deserialize_with = "vec_transform_config")]
-    pub  Vec<Box<dyn TransformConfig>>,
+    pub  Vec<ChainTransformConfig>,
 );
 
+/// A transform in a chain along with the options that the chain applies around it.
+#[derive(Serialize, Debug)]
+pub struct ChainTransformConfig {
+    #[serde(flatten)]
+    pub transform: Box<dyn TransformConfig>,
+    /// When set, the chain fails the request if this transform, including the rest of the chain it calls into,
+    /// does not respond within this many milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
 impl TransformChainConfig {
+    /// Creates a chain of transforms that have no chain level options set.
+    pub fn new(transforms: Vec<Box<dyn TransformConfig>>) -> Self {
+        TransformChainConfig(
+            transforms
+                .into_iter()
+                .map(|transform| ChainTransformConfig {
+                    transform,
+                    timeout_ms: None,
+                })
+                .collect(),
+        )
+    }
+
+    /// Appends a transform to the end of the chain.
+    /// Transforms added this way do not need to be imported with [`crate::import_transform`] as they are never deserialized.
+    pub fn with_transform(mut self, transform: impl TransformConfig + 'static) -> Self {
+        self.0.push(ChainTransformConfig {
+            transform: Box::new(transform),
+            timeout_ms: None,
+        });
+        self
+    }
+
     pub async fn get_builder(
         &self,
         mut transform_context: TransformContextConfig,
     ) -> Result<TransformChainBuilder> {
-        let mut transforms: Vec<Box<dyn TransformBuilder>> = Vec::new();
+        let mut transforms: Vec<(Box<dyn TransformBuilder>, Option<Duration>)> = Vec::new();
         let mut upchain_protocol = transform_context.up_chain_protocol;
-        for (i, tc) in self.0.iter().enumerate() {
+        for (
+            i,
+            ChainTransformConfig {
+                transform: tc,
+                timeout_ms,
+            },
+        ) in self.0.iter().enumerate()
+        {
             let name = tc.typetag_name();
             match tc.up_chain_protocol() {
                 UpChainProtocol::MustBeOneOf(protocols) => {
@@ -35,7 +80,10 @@ impl TransformChainConfig {
                 }
             }
             transform_context.up_chain_protocol = upchain_protocol;
-            transforms.push(tc.get_builder(transform_context.clone()).await?);
+            transforms.push((
+                tc.get_builder(transform_context.clone()).await?,
+                timeout_ms.map(Duration::from_millis),
+            ));
 
             upchain_protocol = match tc.down_chain_protocol() {
                 DownChainProtocol::TransformedTo(new) => new,
@@ -50,7 +98,7 @@ impl TransformChainConfig {
                 }
             }
         }
-        Ok(TransformChainBuilder::new(
+        Ok(TransformChainBuilder::new_with_timeouts(
             transforms,
             transform_context.chain_name.leak(),
         ))
@@ -78,14 +126,25 @@ impl TransformChainConfig {
 ///
 /// With the use of this custom deserializer both cases now deserialize correctly.
 /// The implementation was a suggestion from dtolnay: https://github.com/dtolnay/typetag/pull/40#issuecomment-1454961686
-fn vec_transform_config<'de, D>(deserializer: D) -> Result<Vec<Box<dyn TransformConfig>>, D::Error>
+///
+/// It also separates the chain level options of a transform from the transform itself:
+/// ```yaml
+/// Redis:
+///   ...
+///   chain:
+///     - Tee:
+///         ...
+///       timeout_ms: 500
+///     - NullSink
+/// ```
+fn vec_transform_config<'de, D>(deserializer: D) -> Result<Vec<ChainTransformConfig>, D::Error>
 where
     D: Deserializer<'de>,
 {
     struct VecTransformConfigVisitor;
 
     impl<'de> Visitor<'de> for VecTransformConfigVisitor {
-        type Value = Vec<Box<dyn TransformConfig>>;
+        type Value = Vec<ChainTransformConfig>;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
             formatter.write_str("list of TransformConfig")
@@ -106,18 +165,36 @@ where
     struct TransformConfigVisitor;
 
     impl<'de> Visitor<'de> for TransformConfigVisitor {
-        type Value = Box<dyn TransformConfig>;
+        type Value = ChainTransformConfig;
 
         fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
             formatter.write_str("TransformConfig")
         }
 
-        fn visit_map<M>(self, map: M) -> Result<Self::Value, M::Error>
+        fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
         where
             M: MapAccess<'de>,
         {
-            let de = serde::de::value::MapAccessDeserializer::new(map);
-            Deserialize::deserialize(de)
+            let mut transform = None;
+            let mut timeout_ms = None;
+            while let Some(key) = map.next_key::<String>()? {
+                if key == "timeout_ms" {
+                    if timeout_ms.is_some() {
+                        return Err(M::Error::duplicate_field("timeout_ms"));
+                    }
+                    timeout_ms = Some(map.next_value()?);
+                } else if transform.is_some() {
+                    return Err(M::Error::custom(format!(
+                        "expected a single transform but also found {key}"
+                    )));
+                } else {
+                    transform = Some(map.next_value_seed(NamedTransformConfig { name: key })?);
+                }
+            }
+            Ok(ChainTransformConfig {
+                transform: transform.ok_or_else(|| M::Error::custom("missing transform"))?,
+                timeout_ms,
+            })
         }
 
         fn visit_str<E>(self, string: &str) -> Result<Self::Value, E>
@@ -126,12 +203,15 @@ where
         {
             let singleton_map = iter::once((string, ()));
             let de = serde::de::value::MapDeserializer::new(singleton_map);
-            Deserialize::deserialize(de)
+            Ok(ChainTransformConfig {
+                transform: Deserialize::deserialize(de)?,
+                timeout_ms: None,
+            })
         }
     }
 
     impl<'de> DeserializeSeed<'de> for TransformConfigVisitor {
-        type Value = Box<dyn TransformConfig>;
+        type Value = ChainTransformConfig;
 
         fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
         where
@@ -141,5 +221,103 @@ where
         }
     }
 
+    /// Deserializes the config of the transform named by a key that was already taken from the map.
+    struct NamedTransformConfig {
+        name: String,
+    }
+
+    impl<'de> DeserializeSeed<'de> for NamedTransformConfig {
+        type Value = Box<dyn TransformConfig>;
+
+        fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            Deserialize::deserialize(MapAccessDeserializer::new(SingletonMap {
+                name: Some(self.name),
+                value: Some(deserializer),
+            }))
+        }
+    }
+
+    /// A map containing only the name of a transform and the deserializer for its config.
+    struct SingletonMap<D> {
+        name: Option<String>,
+        value: Option<D>,
+    }
+
+    impl<'de, D> MapAccess<'de> for SingletonMap<D>
+    where
+        D: Deserializer<'de>,
+    {
+        type Error = D::Error;
+
+        fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, D::Error>
+        where
+            K: DeserializeSeed<'de>,
+        {
+            match self.name.take() {
+                Some(name) => seed.deserialize(name.into_deserializer()).map(Some),
+                None => Ok(None),
+            }
+        }
+
+        fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, D::Error>
+        where
+            V: DeserializeSeed<'de>,
+        {
+            match self.value.take() {
+                Some(value) => seed.deserialize(value),
+                None => Err(D::Error::custom("value is missing")),
+            }
+        }
+    }
+
     deserializer.deserialize_seq(VecTransformConfigVisitor)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_deserialize_timeout() {
+        let yaml = r#"
+- DebugPrinter
+- NullSink:
+  timeout_ms: 100
+"#;
+        let chain: TransformChainConfig = serde_yaml::with::singleton_map_recursive::deserialize(
+            serde_yaml::Deserializer::from_str(yaml),
+        )
+        .unwrap();
+        let transforms: Vec<_> = chain
+            .0
+            .iter()
+            .map(|x| (x.transform.typetag_name(), x.timeout_ms))
+            .collect();
+        assert_eq!(
+            transforms,
+            vec![("DebugPrinter", None), ("NullSink", Some(100))]
+        );
+    }
+
+    #[test]
+    fn test_deserialize_multiple_transforms() {
+        let yaml = r#"
+- DebugPrinter:
+  NullSink:
+"#;
+        let err =
+            serde_yaml::with::singleton_map_recursive::deserialize::<TransformChainConfig, _>(
+                serde_yaml::Deserializer::from_str(yaml),
+            )
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("expected a single transform but also found NullSink"),
+            "{err}"
+        );
+    }
+}
@@ -11,6 +11,11 @@ pub mod topology;
 pub struct Config {
     pub main_log_level: String,
     pub observability_interface: Option<String>,
+    /// Allow debug captures to be started through the observability interface.
+    #[serde(default)]
+    pub capture_enabled: bool,
+    /// Path of the unix socket used to hand off listening sockets to a new shotover process during an upgrade.
+    pub upgrade_socket: Option<String>,
 }
 
 impl Config {
@@ -1,3 +1,4 @@
+use crate::events::NotifierConfig;
 use crate::sources::{Source, SourceConfig};
 use anyhow::{anyhow, Context, Result};
 use itertools::Itertools;
@@ -6,10 +7,13 @@ use std::fmt::Write;
 use tokio::sync::watch;
 use tracing::info;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Topology {
     pub sources: Vec<SourceConfig>,
+    /// Deliver the operational events published by transforms, such as a node becoming unhealthy.
+    #[serde(default)]
+    pub notifiers: Vec<NotifierConfig>,
 }
 
 impl Topology {
@@ -23,6 +27,13 @@ impl Topology {
             .with_context(|| format!("Failed to parse topology file {}", filepath))
     }
 
+    /// Parse a topology from the same yaml that is used in a topology.yaml
+    pub fn from_yaml(yaml: &str) -> Result<Topology> {
+        let deserializer = serde_yaml::Deserializer::from_str(yaml);
+        serde_yaml::with::singleton_map_recursive::deserialize(deserializer)
+            .context("Failed to parse topology")
+    }
+
     /// Generate the yaml representation of this instance
     pub fn serialize(&self) -> Result<String> {
         let mut output = vec![];
@@ -53,6 +64,14 @@ impl Topology {
             )?;
         }
 
+        // Notifiers are started first so that they receive events published while the sources are starting
+        for notifier in &self.notifiers {
+            if let Err(notifier_errors) = notifier.start(trigger_shutdown_rx.clone()).await {
+                topology_errors.push_str(&notifier_errors.join("\n"));
+                topology_errors.push('\n');
+            }
+        }
+
         for source in &self.sources {
             match source.get_source(trigger_shutdown_rx.clone()).await {
                 Ok(source) => sources.push(source),
@@ -104,7 +123,13 @@ mod topology_tests {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(chain),
+            max_message_size_bytes: None,
+            ip_filter: None,
+            client_throttle: None,
+            tcp: None,
+            chain_error_policy: None,
+            latency_objective: None,
+            chain: TransformChainConfig::new(chain),
         })]
     }
 
@@ -118,8 +143,15 @@ mod topology_tests {
             hard_connection_limit: None,
             tls: None,
             timeout: None,
-            chain: TransformChainConfig(chain),
+            max_message_size_bytes: None,
+            ip_filter: None,
+            client_throttle: None,
+            tcp: None,
+            chain_error_policy: None,
+            latency_objective: None,
+            chain: TransformChainConfig::new(chain),
             transport: None,
+            compression: None,
         })]
     }
 
@@ -128,7 +160,10 @@ mod topology_tests {
     ) -> anyhow::Result<Vec<Source>> {
         let sources = create_source_from_chain_redis(chain);
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            notifiers: vec![],
+        };
 
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
 
@@ -140,7 +175,10 @@ mod topology_tests {
     ) -> anyhow::Result<Vec<Source>> {
         let sources = create_source_from_chain_cassandra(chain);
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            notifiers: vec![],
+        };
 
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
 
@@ -267,7 +305,7 @@ foo source:
             Box::new(DebugPrinterConfig),
             Box::new(DebugPrinterConfig),
             Box::new(RedisCacheConfig {
-                chain: TransformChainConfig(vec![
+                chain: TransformChainConfig::new(vec![
                     Box::new(DebugPrinterConfig),
                     Box::new(DebugPrinterConfig),
                     Box::new(NullSinkConfig),
@@ -294,7 +332,7 @@ foo source:
             Box::new(DebugPrinterConfig),
             Box::new(DebugPrinterConfig),
             Box::new(RedisCacheConfig {
-                chain: TransformChainConfig(vec![
+                chain: TransformChainConfig::new(vec![
                     Box::new(DebugPrinterConfig),
                     Box::new(NullSinkConfig),
                     Box::new(DebugPrinterConfig),
@@ -318,7 +356,7 @@ foo source:
             Box::new(DebugPrinterConfig),
             Box::new(ParallelMapConfig {
                 parallelism: 1,
-                chain: TransformChainConfig(vec![
+                chain: TransformChainConfig::new(vec![
                     Box::new(DebugPrinterConfig),
                     Box::new(DebugPrinterConfig),
                     Box::new(NullSinkConfig),
@@ -345,7 +383,7 @@ foo source:
             Box::new(DebugPrinterConfig),
             Box::new(ParallelMapConfig {
                 parallelism: 1,
-                chain: TransformChainConfig(vec![
+                chain: TransformChainConfig::new(vec![
                     Box::new(DebugPrinterConfig),
                     Box::new(NullSinkConfig),
                     Box::new(DebugPrinterConfig),
@@ -371,7 +409,7 @@ foo source:
         Terminating transform "NullSink" is not last in chain. Terminating transform must be last in chain.
 "#;
 
-        let subchain = TransformChainConfig(vec![
+        let subchain = TransformChainConfig::new(vec![
             Box::new(DebugPrinterConfig),
             Box::new(NullSinkConfig),
             Box::new(DebugPrinterConfig),
@@ -404,7 +442,7 @@ foo source:
         Non-terminating transform "DebugPrinter" is last in chain. Last transform must be terminating.
 "#;
 
-        let subchain = TransformChainConfig(vec![
+        let subchain = TransformChainConfig::new(vec![
             Box::new(DebugPrinterConfig),
             Box::new(DebugPrinterConfig),
         ]);
@@ -436,7 +474,7 @@ foo source:
         Non-terminating transform "DebugPrinter" is last in chain. Last transform must be terminating.
 "#;
 
-        let subchain = TransformChainConfig(vec![
+        let subchain = TransformChainConfig::new(vec![
             Box::new(DebugPrinterConfig),
             Box::new(NullSinkConfig),
             Box::new(DebugPrinterConfig),
@@ -469,7 +507,10 @@ Source name "foo" occurred more than once. Make sure all source names are unique
             NullSinkConfig,
         )]));
 
-        let topology = Topology { sources };
+        let topology = Topology {
+            sources,
+            notifiers: vec![],
+        };
         let (_sender, trigger_shutdown_rx) = watch::channel::<bool>(false);
         let error = topology
             .run_chains(trigger_shutdown_rx)
@@ -2,8 +2,8 @@
 
 use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError};
 use crate::frame::Frame;
-use crate::message::{Message, MessageId, Messages};
-use crate::tcp;
+use crate::message::{Message, MessageId, Messages, StreamChunk};
+use crate::tcp::{self, TcpConfig};
 use crate::tls::{TlsConnector, ToHostname};
 use futures::{SinkExt, StreamExt};
 use std::io::ErrorKind;
@@ -32,6 +32,7 @@ impl SinkConnection {
         host: A,
         codec_builder: C,
         tls: &Option<TlsConnector>,
+        tcp: &TcpConfig,
         connect_timeout: Duration,
         force_run_chain: Arc<Notify>,
         read_timeout: Option<Duration>,
@@ -42,7 +43,7 @@ impl SinkConnection {
         let (connection_closed_tx, connection_closed_rx) = mpsc::channel(1);
 
         if let Some(tls) = tls.as_ref() {
-            let tls_stream = tls.connect(connect_timeout, host).await?;
+            let tls_stream = tls.connect(connect_timeout, host, tcp).await?;
             let (rx, tx) = split(tls_stream);
             spawn_read_write_tasks(
                 codec_builder,
@@ -56,7 +57,7 @@ impl SinkConnection {
                 read_timeout,
             );
         } else {
-            let tcp_stream = tcp::tcp_stream(connect_timeout, destination).await?;
+            let tcp_stream = tcp::tcp_stream(connect_timeout, destination, tcp).await?;
             let (rx, tx) = tcp_stream.into_split();
             spawn_read_write_tasks(
                 codec_builder,
@@ -488,10 +489,20 @@ impl DummyResponseInserter {
     /// Insert dummy responses into the list of responses.
     /// All elements before the element at index `start_at` is ignored,
     /// those elements should have been already processed by a previous call to process_responses.
+    ///
+    /// The `Start` and `Continuation` chunks of a streamed response are skipped over,
+    /// a streamed response is only counted once its `End` chunk is received so that a dummy response is never inserted within a streamed response.
     fn process_responses(&mut self, responses: &mut Vec<Message>, start_at: usize) {
-        let mut len = responses.len() - start_at;
+        let mut len = responses[start_at..]
+            .iter()
+            .filter(|x| !is_incomplete_stream_chunk(x))
+            .count();
         // responses with no request will invalidate our indexes, so we need to fix them up here.
-        for (response_i, response) in responses[start_at..].iter().enumerate() {
+        for (response_i, response) in responses[start_at..]
+            .iter()
+            .filter(|x| !is_incomplete_stream_chunk(x))
+            .enumerate()
+        {
             if response.request_id().is_none() {
                 for (dummy_request_i, dummy_request) in
                     &mut self.dummy_requests.iter_mut().enumerate()
@@ -513,7 +524,20 @@ impl DummyResponseInserter {
             if dummy_request.request_index <= len {
                 let mut dummy = Message::from_frame(Frame::Dummy);
                 dummy.set_request_id(dummy_request.request_id);
-                responses.insert(dummy_request.request_index + start_at, dummy);
+                let insert_index = if dummy_request.request_index == 0 {
+                    start_at
+                } else {
+                    // insert directly after the last complete response preceding the dummy
+                    start_at
+                        + responses[start_at..]
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, x)| !is_incomplete_stream_chunk(x))
+                            .nth(dummy_request.request_index - 1)
+                            .map(|(i, _)| i + 1)
+                            .unwrap()
+                };
+                responses.insert(insert_index, dummy);
                 len += 1;
                 false
             } else {
@@ -533,11 +557,21 @@ impl DummyResponseInserter {
     }
 }
 
+/// Returns true for the chunks of a streamed response that precede its `End` chunk, these chunks have no request id.
+fn is_incomplete_stream_chunk(message: &Message) -> bool {
+    matches!(
+        message.stream_chunk(),
+        Some(StreamChunk::Start | StreamChunk::Continuation)
+    )
+}
+
 #[cfg(all(test, feature = "redis"))]
 mod tests {
     use super::DummyResponseInserter;
+    use crate::codec::CodecState;
     use crate::frame::{Frame, RedisFrame};
-    use crate::message::Message;
+    use crate::message::{Message, StreamChunk};
+    use bytes::Bytes;
     use pretty_assertions::assert_eq;
 
     fn dummy() -> Message {
@@ -558,6 +592,21 @@ mod tests {
         Message::from_frame(Frame::Redis(RedisFrame::Null))
     }
 
+    fn stream_chunk(stream_chunk: StreamChunk) -> Message {
+        Message::from_stream_chunk_at_instant(
+            Bytes::from_static(b"chunk"),
+            CodecState::Redis,
+            stream_chunk,
+            None,
+        )
+    }
+
+    fn stream_chunk_end(request: &Message) -> Message {
+        let mut message = stream_chunk(StreamChunk::End);
+        message.set_request_id(request.id());
+        message
+    }
+
     #[test]
     fn dummy_response_inserter() {
         let mut inserter = DummyResponseInserter::new();
@@ -687,4 +736,96 @@ mod tests {
             assert_eq!(inserter.pending_requests_count(), 0);
         }
     }
+    #[test]
+    fn dummy_response_inserter_streamed_response() {
+        let mut inserter = DummyResponseInserter::new();
+
+        // send one dummy request and then one request with a streamed response
+        {
+            let mut requests = vec![dummy(), redis_request()];
+            inserter.process_requests(&mut requests);
+            let mut responses = vec![];
+            inserter.process_responses(&mut responses, 0);
+            assert_eq!(responses, vec![dummy()]);
+
+            // received the whole streamed response at once
+            responses.push(stream_chunk(StreamChunk::Start));
+            responses.push(stream_chunk(StreamChunk::Continuation));
+            responses.push(stream_chunk_end(&requests[1]));
+            inserter.process_responses(&mut responses, 1);
+            assert_eq!(
+                responses,
+                vec![
+                    dummy(),
+                    stream_chunk(StreamChunk::Start),
+                    stream_chunk(StreamChunk::Continuation),
+                    stream_chunk(StreamChunk::End),
+                ]
+            );
+            assert_eq!(inserter.pending_requests_count(), 0);
+        }
+
+        // send one request with a streamed response and then one dummy request
+        {
+            let mut requests = vec![redis_request(), dummy()];
+            inserter.process_requests(&mut requests);
+            let mut responses = vec![];
+            inserter.process_responses(&mut responses, 0);
+            assert_eq!(responses, vec![]);
+
+            // received the start of the streamed response, the dummy must not be inserted yet
+            responses.push(stream_chunk(StreamChunk::Start));
+            inserter.process_responses(&mut responses, 0);
+            assert_eq!(responses, vec![stream_chunk(StreamChunk::Start)]);
+            assert_eq!(inserter.pending_requests_count(), 2);
+
+            // received the rest of the streamed response
+            responses.clear();
+            responses.push(stream_chunk(StreamChunk::Continuation));
+            responses.push(stream_chunk_end(&requests[0]));
+            inserter.process_responses(&mut responses, 0);
+            assert_eq!(
+                responses,
+                vec![
+                    stream_chunk(StreamChunk::Continuation),
+                    stream_chunk(StreamChunk::End),
+                    dummy(),
+                ]
+            );
+            assert_eq!(inserter.pending_requests_count(), 0);
+        }
+
+        // send one dummy request, then a streamed response is received in the same batch as an earlier response
+        {
+            let mut requests = vec![redis_request(), dummy(), redis_request()];
+            inserter.process_requests(&mut requests);
+            let mut responses = vec![
+                redis_response(&requests[0]),
+                stream_chunk(StreamChunk::Start),
+            ];
+            inserter.process_responses(&mut responses, 0);
+            assert_eq!(
+                responses,
+                vec![
+                    redis_response(&requests[0]),
+                    dummy(),
+                    stream_chunk(StreamChunk::Start),
+                ]
+            );
+            assert_eq!(inserter.pending_requests_count(), 1);
+
+            responses.push(stream_chunk_end(&requests[2]));
+            inserter.process_responses(&mut responses, 3);
+            assert_eq!(
+                responses,
+                vec![
+                    redis_response(&requests[0]),
+                    dummy(),
+                    stream_chunk(StreamChunk::Start),
+                    stream_chunk(StreamChunk::End),
+                ]
+            );
+            assert_eq!(inserter.pending_requests_count(), 0);
+        }
+    }
 }
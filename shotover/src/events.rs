@@ -0,0 +1,353 @@
+//! Operational events that transforms publish when they detect a condition an operator may need to act on.
+//!
+//! Events are published to a process wide bus with [`publish`].
+//! Each notifier configured in the `notifiers` section of the topology receives every event from the bus
+//! and delivers the kinds it is interested in to its destination, e.g. a webhook that pages on-call.
+
+use anyhow::Result;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+#[cfg(feature = "kafka")]
+use {
+    crate::config::chain::TransformChainConfig,
+    crate::frame::kafka::StrBytes,
+    crate::frame::MessageType,
+    crate::transforms::chain::TransformChain,
+    crate::transforms::kafka::{build_produce_request, check_produce_response},
+    crate::transforms::{ChainState, TransformContextBuilder, TransformContextConfig},
+    kafka_protocol::messages::TopicName,
+    std::net::SocketAddr,
+    std::sync::Arc,
+    tokio::sync::Notify,
+};
+
+/// The number of events buffered for each notifier, once full the oldest undelivered events are dropped.
+const BUFFER_LEN: usize = 1024;
+
+/// Events of the same kind from the same source are only published once within this interval.
+/// This prevents a condition hit by every request, such as a persistent `Tee` mismatch, from flooding the notifiers.
+const SUPPRESSION_INTERVAL: Duration = Duration::from_secs(60);
+
+static BUS: LazyLock<broadcast::Sender<Event>> = LazyLock::new(|| broadcast::channel(BUFFER_LEN).0);
+
+/// When each kind of event was last published by each source.
+static LAST_PUBLISHED: LazyLock<Mutex<HashMap<(EventKind, String), Instant>>> =
+    LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// The responses from a `Tee` subchain and the down-chain did not match.
+    TeeMismatch,
+    /// A node failed enough consecutive health checks to be considered unhealthy and is no longer sent requests.
+    NodeUnhealthy,
+    /// The node a sink depended on could not be reached so the sink switched to another node.
+    Failover,
+    /// The slot map of a redis cluster changed, e.g. because a slot was migrated or a replica was promoted.
+    SlotMapRefreshed,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::TeeMismatch => "TeeMismatch",
+            EventKind::NodeUnhealthy => "NodeUnhealthy",
+            EventKind::Failover => "Failover",
+            EventKind::SlotMapRefreshed => "SlotMapRefreshed",
+        }
+    }
+}
+
+/// An event as delivered to notifiers, serialized as JSON.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Event {
+    pub kind: EventKind,
+    /// What published the event, usually the chain and transform name e.g. `redis_chain/RedisSinkCluster`
+    pub source: String,
+    pub message: String,
+    /// Milliseconds since the unix epoch
+    pub timestamp_ms: i64,
+}
+
+/// Publishes an event to every configured notifier.
+/// Does nothing if the same kind of event was already published by the same source within the last minute.
+pub fn publish(kind: EventKind, source: String, message: String) {
+    let now = Instant::now();
+    match LAST_PUBLISHED.lock().unwrap().entry((kind, source.clone())) {
+        Entry::Occupied(mut entry) => {
+            if now.duration_since(*entry.get()) < SUPPRESSION_INTERVAL {
+                counter!("shotover_events_suppressed_count", "kind" => kind.as_str()).increment(1);
+                return;
+            }
+            entry.insert(now);
+        }
+        Entry::Vacant(entry) => {
+            entry.insert(now);
+        }
+    }
+
+    tracing::debug!(
+        "publishing {} event from {source}: {message}",
+        kind.as_str()
+    );
+    counter!("shotover_events_count", "kind" => kind.as_str()).increment(1);
+    // Sending only fails when no notifiers are configured
+    BUS.send(Event {
+        kind,
+        source,
+        message,
+        timestamp_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0),
+    })
+    .ok();
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+    /// Identifies the notifier in logs and metrics.
+    pub name: String,
+    /// Only events of these kinds are delivered, defaults to every kind.
+    pub events: Option<Vec<EventKind>>,
+    pub destination: NotifierDestinationConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum NotifierDestinationConfig {
+    /// POSTs each event to the url as a JSON body.
+    Webhook {
+        url: String,
+        /// How long to wait for the webhook to respond, defaults to 10000ms.
+        timeout_ms: Option<u64>,
+    },
+    /// Publishes each event to the kafka topic as a JSON record, via a chain ending in a kafka sink.
+    #[cfg(feature = "kafka")]
+    Kafka {
+        topic: String,
+        /// How long kafka should wait for the produce request to be replicated before responding.
+        produce_timeout_ms: Option<i32>,
+        chain: TransformChainConfig,
+    },
+}
+
+impl NotifierConfig {
+    /// Subscribes to the event bus and delivers events in the background until shotover shuts down.
+    pub(crate) async fn start(
+        &self,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<(), Vec<String>> {
+        let destination = match &self.destination {
+            NotifierDestinationConfig::Webhook { url, timeout_ms } => Destination::Webhook {
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_millis(timeout_ms.unwrap_or(10_000)))
+                    .build()
+                    .map_err(|err| vec![format!("{} notifier: {err:?}", self.name)])?,
+                url: url.clone(),
+            },
+            #[cfg(feature = "kafka")]
+            NotifierDestinationConfig::Kafka {
+                topic,
+                produce_timeout_ms,
+                chain,
+            } => {
+                let chain = chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: "notifier_chain".into(),
+                        up_chain_protocol: MessageType::Kafka,
+                    })
+                    .await
+                    .map_err(|err| vec![format!("{} notifier: {err:?}", self.name)])?;
+                let mut errors = chain
+                    .validate()
+                    .iter()
+                    .map(|x| format!("  {x}"))
+                    .collect::<Vec<String>>();
+                if !errors.is_empty() {
+                    errors.insert(0, format!("{} notifier:", self.name));
+                    return Err(errors);
+                }
+                Destination::Kafka {
+                    chain: chain.build(TransformContextBuilder {
+                        force_run_chain: Arc::new(Notify::new()),
+                        client_details: String::new(),
+                        stream_responses: false,
+                        #[cfg(feature = "cassandra")]
+                        schema_cache: None,
+                    }),
+                    topic: TopicName(StrBytes::from_string(topic.clone())),
+                    produce_timeout_ms: produce_timeout_ms.unwrap_or(30_000),
+                }
+            }
+        };
+
+        let notifier = Notifier {
+            name: self.name.clone(),
+            events: self.events.clone(),
+            destination,
+            failed: counter!("shotover_notifier_failed_count", "notifier" => self.name.clone()),
+        };
+        tokio::spawn(notifier.run(BUS.subscribe(), trigger_shutdown_rx));
+        Ok(())
+    }
+}
+
+enum Destination {
+    Webhook {
+        client: reqwest::Client,
+        url: String,
+    },
+    #[cfg(feature = "kafka")]
+    Kafka {
+        chain: TransformChain,
+        topic: TopicName,
+        produce_timeout_ms: i32,
+    },
+}
+
+struct Notifier {
+    name: String,
+    events: Option<Vec<EventKind>>,
+    destination: Destination,
+    /// Counts events that were not delivered
+    failed: Counter,
+}
+
+impl Notifier {
+    async fn run(
+        mut self,
+        mut bus: broadcast::Receiver<Event>,
+        mut trigger_shutdown_rx: watch::Receiver<bool>,
+    ) {
+        loop {
+            tokio::select! {
+                event = bus.recv() => match event {
+                    Ok(event) => {
+                        if self.is_interested(&event) {
+                            if let Err(err) = self.deliver(&event).await {
+                                self.failed.increment(1);
+                                tracing::error!("{} notifier failed to deliver {event:?}: {err:?}", self.name);
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        self.failed.increment(count);
+                        tracing::error!(
+                            "{} notifier dropped {count} events as they were published faster than they could be delivered",
+                            self.name
+                        );
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+                _ = trigger_shutdown_rx.changed() => return,
+            }
+        }
+    }
+
+    fn is_interested(&self, event: &Event) -> bool {
+        self.events
+            .as_ref()
+            .map(|kinds| kinds.contains(&event.kind))
+            .unwrap_or(true)
+    }
+
+    async fn deliver(&mut self, event: &Event) -> Result<()> {
+        match &mut self.destination {
+            Destination::Webhook { client, url } => {
+                client
+                    .post(url.as_str())
+                    .json(event)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(())
+            }
+            #[cfg(feature = "kafka")]
+            Destination::Kafka {
+                chain,
+                topic,
+                produce_timeout_ms,
+            } => {
+                let value = serde_json::to_vec(event)?.into();
+                let request = build_produce_request(
+                    vec![(event.timestamp_ms, value)],
+                    topic,
+                    *produce_timeout_ms,
+                )?;
+                let local_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+                chain
+                    .process_request(&mut ChainState::new_with_addr(vec![request], local_addr))
+                    .await
+                    .and_then(check_produce_response)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn notifier(events: Option<Vec<EventKind>>) -> Notifier {
+        Notifier {
+            name: "test".to_owned(),
+            events,
+            destination: Destination::Webhook {
+                client: reqwest::Client::new(),
+                url: "http://localhost".to_owned(),
+            },
+            failed: Counter::noop(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_suppressed() {
+        let mut bus = BUS.subscribe();
+        let source = format!("test_chain_{}/Tee", rand::random::<u64>());
+        for _ in 0..3 {
+            publish(
+                EventKind::TeeMismatch,
+                source.clone(),
+                "mismatch".to_owned(),
+            );
+        }
+        publish(EventKind::Failover, source.clone(), "failover".to_owned());
+
+        // other tests may publish concurrently so only look at events from this test
+        let mut received = vec![];
+        while let Ok(event) = bus.try_recv() {
+            if event.source == source {
+                received.push((event.kind, event.message));
+            }
+        }
+        assert_eq!(
+            received,
+            vec![
+                (EventKind::TeeMismatch, "mismatch".to_owned()),
+                (EventKind::Failover, "failover".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_interested() {
+        let event = Event {
+            kind: EventKind::NodeUnhealthy,
+            source: "redis_chain/RedisSinkCluster".to_owned(),
+            message: "node 127.0.0.1:6379 is unhealthy".to_owned(),
+            timestamp_ms: 0,
+        };
+        assert!(notifier(None).is_interested(&event));
+        assert!(notifier(Some(vec![EventKind::NodeUnhealthy])).is_interested(&event));
+        assert!(!notifier(Some(vec![EventKind::TeeMismatch])).is_interested(&event));
+    }
+}
@@ -1,6 +1,6 @@
 use crate::frame::value::cassandra::{serialize_len, serialize_with_length_prefix};
 use crate::frame::value::GenericValue;
-use crate::message::QueryType;
+use crate::message::{OperationType, QueryType};
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use cassandra_protocol::compression::Compression;
@@ -33,7 +33,7 @@ use cassandra_protocol::types::{CBytesShort, CLong};
 use cql3_parser::begin_batch::{BatchType as ParserBatchType, BeginBatch};
 use cql3_parser::cassandra_ast::CassandraAST;
 use cql3_parser::cassandra_statement::CassandraStatement;
-use cql3_parser::common::Operand;
+use cql3_parser::common::{Operand, RelationElement, RelationOperator};
 use nonzero_ext::nonzero;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::{Cursor, Write};
@@ -116,13 +116,14 @@ impl CassandraMetadata {
     }
 
     pub fn to_error_response(&self, error: String) -> CassandraFrame {
+        self.to_error_response_of_type(error, ErrorType::Server)
+    }
+
+    pub fn to_error_response_of_type(&self, error: String, ty: ErrorType) -> CassandraFrame {
         CassandraFrame {
             version: self.version,
             stream_id: self.stream_id,
-            operation: CassandraOperation::Error(ErrorBody {
-                message: error,
-                ty: ErrorType::Server,
-            }),
+            operation: CassandraOperation::Error(ErrorBody { message: error, ty }),
             tracing: Tracing::Response(None),
             warnings: vec![],
         }
@@ -402,6 +403,114 @@ impl CassandraFrame {
         }
     }
 
+    pub fn operation_type(&self) -> OperationType {
+        match &self.operation {
+            CassandraOperation::Query { query, .. } => statement_operation_type(query),
+            CassandraOperation::Batch(_) => OperationType::Write,
+            // The contents of a prepared statement are not known from the EXECUTE alone
+            _ => OperationType::Unknown,
+        }
+    }
+
+    /// Returns the fully qualified name of every table referenced by the statements in this frame
+    pub fn tables(&mut self) -> Vec<String> {
+        let mut tables: Vec<String> = vec![];
+        for statement in self.operation.queries() {
+            if let Some(table) = statement.get_table_name() {
+                let table = table.to_string();
+                if !tables.contains(&table) {
+                    tables.push(table);
+                }
+            }
+        }
+        tables
+    }
+
+    /// Returns the statement being prepared if this frame is a PREPARE
+    pub fn prepared_statement(&self) -> Option<CassandraStatement> {
+        let CassandraOperation::Prepare(body) = &self.operation else {
+            return None;
+        };
+        // The body starts with the query as a [long string]
+        let len = usize::try_from(i32::from_be_bytes(body.get(..4)?.try_into().ok()?)).ok()?;
+        let query = std::str::from_utf8(body.get(4..4 + len)?).ok()?;
+        Some(parse_statement_single(query))
+    }
+
+    /// Replaces the statement being prepared if this frame is a PREPARE, keeping the flags that follow it in the body
+    pub fn set_prepared_statement(&mut self, statement: &CassandraStatement) {
+        let CassandraOperation::Prepare(body) = &mut self.operation else {
+            return;
+        };
+        let Some(len) = body
+            .get(..4)
+            .and_then(|x| usize::try_from(i32::from_be_bytes(x.try_into().unwrap())).ok())
+        else {
+            return;
+        };
+        let query = statement.to_string();
+        let mut new_body = (query.len() as i32).to_be_bytes().to_vec();
+        new_body.extend(query.as_bytes());
+        new_body.extend(body.get(4 + len..).unwrap_or_default());
+        *body = new_body;
+    }
+
+    /// Returns true if this frame runs or prepares a conditional write, also known as a lightweight transaction.
+    /// An EXECUTE is never detected since the statement it executes is not known from the EXECUTE alone.
+    pub fn is_conditional_write(&mut self) -> bool {
+        if let Some(statement) = self.prepared_statement() {
+            return is_conditional_write(&statement);
+        }
+        self.operation
+            .queries()
+            .any(|statement| is_conditional_write(statement))
+    }
+
+    /// Returns the ids of the prepared statements executed by this frame
+    pub fn prepared_ids(&self) -> Vec<&CBytesShort> {
+        match &self.operation {
+            CassandraOperation::Execute(execute) => vec![&execute.id],
+            CassandraOperation::Batch(batch) => batch
+                .queries
+                .iter()
+                .filter_map(|query| match &query.ty {
+                    BatchStatementType::PreparedId(id) => Some(id),
+                    BatchStatementType::Statement(_) => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the literal values that columns are restricted to by equality in the WHERE clauses of the statements in this frame.
+    /// Cassandra requires the partition key to be restricted this way, so these values include the partition key.
+    /// INSERT statements and bind markers are not included since identifying them requires the table schema or the bound values.
+    pub fn primary_keys(&mut self) -> Vec<Bytes> {
+        let mut keys = vec![];
+        for statement in self.operation.queries() {
+            let where_clause: &[RelationElement] = match statement {
+                CassandraStatement::Select(select) => &select.where_clause,
+                CassandraStatement::Update(update) => &update.where_clause,
+                CassandraStatement::Delete(delete) => &delete.where_clause,
+                _ => &[],
+            };
+            for relation in where_clause {
+                if relation.oper == RelationOperator::Equal {
+                    if let Operand::Const(value) = &relation.value {
+                        // string literals are quoted with any quotes inside escaped by doubling them
+                        let value =
+                            match value.strip_prefix('\'').and_then(|x| x.strip_suffix('\'')) {
+                                Some(string) => string.replace("''", "'"),
+                                None => value.clone(),
+                            };
+                        keys.push(Bytes::from(value));
+                    }
+                }
+            }
+        }
+        keys
+    }
+
     pub fn encode(self, compression: Compression) -> Vec<u8> {
         let mut buf = Vec::with_capacity(128);
         let mut cursor = Cursor::new(&mut buf);
@@ -502,6 +611,16 @@ impl<'a> Iterator for QueryIterator<'a> {
     }
 }
 
+/// Returns true if the statement is an INSERT, UPDATE or DELETE with an IF condition, which cassandra runs as a Paxos round.
+fn is_conditional_write(statement: &CassandraStatement) -> bool {
+    match statement {
+        CassandraStatement::Insert(insert) => insert.if_not_exists,
+        CassandraStatement::Update(update) => update.if_exists || !update.if_clause.is_empty(),
+        CassandraStatement::Delete(delete) => delete.if_exists || !delete.if_clause.is_empty(),
+        _ => false,
+    }
+}
+
 type FilterFn = fn(&mut BatchStatement) -> Option<&mut CassandraStatement>;
 
 fn filter_batch_queries(batch: &mut BatchStatement) -> Option<&mut CassandraStatement> {
@@ -633,6 +752,21 @@ impl CassandraOperation {
     }
 }
 
+/// Classifies the operation performed by a single statement.
+/// Statements that shotover failed to parse are `Unknown` since they may perform any operation.
+pub fn statement_operation_type(statement: &CassandraStatement) -> OperationType {
+    match statement {
+        CassandraStatement::Unknown(_) => OperationType::Unknown,
+        statement => match get_query_type(statement) {
+            QueryType::Read => OperationType::Read,
+            QueryType::SchemaChange => OperationType::Ddl,
+            QueryType::Write | QueryType::ReadWrite | QueryType::PubSubMessage => {
+                OperationType::Write
+            }
+        },
+    }
+}
+
 fn get_query_type(statement: &CassandraStatement) -> QueryType {
     match statement {
         CassandraStatement::AlterKeyspace(_) => QueryType::SchemaChange,
@@ -817,6 +951,13 @@ pub struct CassandraBatch {
     timestamp: Option<CLong>,
 }
 
+impl CassandraBatch {
+    /// The number of statements in the batch, including prepared statements.
+    pub fn statement_count(&self) -> usize {
+        self.queries.len()
+    }
+}
+
 impl Display for CassandraFrame {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         write!(f, "{} stream:{}", self.version, self.stream_id)?;
@@ -955,7 +1096,12 @@ impl Display for CassandraFrame {
 
 #[cfg(test)]
 mod test {
-    use crate::frame::cassandra::{parse_statement_single, to_cassandra_type};
+    use crate::frame::cassandra::{
+        parse_statement_single, to_cassandra_type, CassandraFrame, CassandraOperation, Tracing,
+    };
+    use crate::message::OperationType;
+    use bytes::Bytes;
+    use cassandra_protocol::frame::Version;
     use cassandra_protocol::types::cassandra_type::CassandraType;
     use cassandra_protocol::types::prelude::Blob;
     use cql3_parser::cassandra_statement::CassandraStatement;
@@ -1286,4 +1432,61 @@ mod test {
     pub fn test_to_cassandra_type_for_misc_operands() {
         assert_eq!(CassandraType::Null, to_cassandra_type(&Operand::Null));
     }
+
+    fn query_frame(query: &str) -> CassandraFrame {
+        CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Query {
+                query: Box::new(parse_statement_single(query)),
+                params: Box::default(),
+            },
+        }
+    }
+
+    #[test]
+    fn query_metadata() {
+        let mut frame = query_frame("SELECT * FROM ks.t WHERE id = 'it''s' AND c = 1 AND d > 2");
+        assert_eq!(frame.operation_type(), OperationType::Read);
+        assert_eq!(frame.tables(), vec!["ks.t"]);
+        assert_eq!(
+            frame.primary_keys(),
+            vec![Bytes::from("it's"), Bytes::from("1")]
+        );
+
+        let mut frame = query_frame("UPDATE ks.t SET x = 1 WHERE id = 5");
+        assert_eq!(frame.operation_type(), OperationType::Write);
+        assert_eq!(frame.primary_keys(), vec![Bytes::from("5")]);
+
+        let frame = query_frame("CREATE TABLE ks.t2 (id int PRIMARY KEY)");
+        assert_eq!(frame.operation_type(), OperationType::Ddl);
+    }
+
+    #[test]
+    fn prepared_statement() {
+        let query = "SELECT * FROM ks.t WHERE id = ?";
+        let mut body = (query.len() as i32).to_be_bytes().to_vec();
+        body.extend(query.as_bytes());
+        let mut frame = CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Prepare(body),
+        };
+        assert_eq!(
+            frame.prepared_statement(),
+            Some(parse_statement_single(query))
+        );
+        assert_eq!(query_frame(query).prepared_statement(), None);
+
+        let new_query = "SELECT * FROM ks.t WHERE id = ? AND x = ?";
+        frame.set_prepared_statement(&parse_statement_single(new_query));
+        assert_eq!(
+            frame.prepared_statement(),
+            Some(parse_statement_single(new_query))
+        );
+    }
 }
@@ -0,0 +1,444 @@
+//! Normalization of queries into a canonical form and a stable fingerprint of that form.
+//!
+//! Two queries that only differ in their literal values, bound values, keyword case, whitespace or comments normalize to the same text,
+//! allowing transforms to agree on when two requests are "the same query".
+//! Fingerprints are stable across shotover instances and restarts so they can be used in metrics and logs.
+//! Combined with the values that normalization replaced, they identify identical queries, see [`NormalizedQuery::key`].
+
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+use crate::frame::Frame;
+use crate::message::Message;
+use bytes::Bytes;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+#[cfg(feature = "cassandra")]
+use {crate::frame::CassandraOperation, cql3_parser::cassandra_statement::CassandraStatement};
+
+/// A stable 64 bit hash of the normalized text of a query.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    /// Hashes already normalized text with FNV-1a.
+    pub fn of(normalized: &str) -> Self {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in normalized.as_bytes() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Fingerprint(hash)
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The normalized text of a query and its fingerprint.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NormalizedQuery {
+    pub text: String,
+    pub fingerprint: Fingerprint,
+    /// The literals, bind markers and redis arguments that were replaced by `?` in the normalized text, in order of appearance.
+    pub values: Vec<Bytes>,
+}
+
+impl NormalizedQuery {
+    fn new(text: String, values: Vec<Bytes>) -> Self {
+        NormalizedQuery {
+            fingerprint: Fingerprint::of(&text),
+            text,
+            values,
+        }
+    }
+
+    /// Returns a key that is equal for two queries only when they have the same fingerprint and the same values.
+    /// Queries that only differ in keyword case, whitespace or comments share a key.
+    pub fn key(&self) -> Vec<u8> {
+        let mut key = self.fingerprint.0.to_be_bytes().to_vec();
+        for value in &self.values {
+            key.extend((value.len() as u32).to_be_bytes());
+            key.extend(value);
+        }
+        key
+    }
+}
+
+/// Returns the normalized queries of a request, one for each statement of a cassandra BATCH.
+/// Requests that do not contain the text of a query, such as a cassandra EXECUTE, return nothing.
+pub fn normalize_request(request: &mut Message) -> Vec<NormalizedQuery> {
+    match request.frame() {
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(frame)) => {
+            if let CassandraOperation::Prepare(_) = &frame.operation {
+                return frame
+                    .prepared_statement()
+                    .map(|statement| normalize_cassandra_statement(&statement))
+                    .into_iter()
+                    .collect();
+            }
+            frame
+                .operation
+                .queries()
+                .map(|statement| normalize_cassandra_statement(statement))
+                .collect()
+        }
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(frame)) => normalize_redis(frame).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+/// Normalizes a parsed cassandra statement.
+#[cfg(feature = "cassandra")]
+pub fn normalize_cassandra_statement(statement: &CassandraStatement) -> NormalizedQuery {
+    normalize_cql_query(&statement.to_string())
+}
+
+/// Normalizes the text of a CQL query, see [`normalize_cql`], keeping the values it replaced.
+pub fn normalize_cql_query(query: &str) -> NormalizedQuery {
+    let (text, values) = tokenize_cql(query);
+    NormalizedQuery::new(text, values)
+}
+
+/// Normalizes the text of a CQL query:
+/// * literals and bind markers are replaced with `?` and lists of them are collapsed into a single `?`
+/// * keywords and unquoted identifiers are lowercased, quoted identifiers are kept as is
+/// * comments and trailing semicolons are removed and whitespace is made uniform
+pub fn normalize_cql(query: &str) -> String {
+    tokenize_cql(query).0
+}
+
+/// Returns the normalized text of a CQL query and the values replaced by `?`
+fn tokenize_cql(query: &str) -> (String, Vec<Bytes>) {
+    let mut tokens: Vec<Token> = vec![];
+    let mut values = vec![];
+    let bytes = query.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+        } else if query[i..].starts_with("--") || query[i..].starts_with("//") {
+            i = query[i..].find('\n').map(|x| i + x).unwrap_or(bytes.len());
+        } else if query[i..].starts_with("/*") {
+            i = query[i + 2..]
+                .find("*/")
+                .map(|x| i + 2 + x + 2)
+                .unwrap_or(bytes.len());
+        } else if c == b'\'' {
+            i += 1;
+            while i < bytes.len() {
+                if bytes[i] == b'\'' {
+                    // Quotes are escaped by doubling them
+                    if bytes.get(i + 1) == Some(&b'\'') {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if query[i..].starts_with("$$") {
+            i = query[i + 2..]
+                .find("$$")
+                .map(|x| i + 2 + x + 2)
+                .unwrap_or(bytes.len());
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if c == b'"' {
+            i += 1;
+            while i < bytes.len() {
+                if bytes[i] == b'"' {
+                    if bytes.get(i + 1) == Some(&b'"') {
+                        i += 2;
+                        continue;
+                    }
+                    i += 1;
+                    break;
+                }
+                i += 1;
+            }
+            tokens.push(Token::Word(query[start..i].to_owned()));
+        } else if let Some(len) = uuid_len(&bytes[i..]) {
+            i += len;
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if c.is_ascii_digit()
+            || ((c == b'-' || c == b'+')
+                && bytes.get(i + 1).is_some_and(|x| x.is_ascii_digit())
+                && !tokens.last().is_some_and(Token::ends_operand))
+        {
+            i += 1;
+            while i < bytes.len() {
+                let x = bytes[i];
+                let exponent_sign = (x == b'-' || x == b'+')
+                    && matches!(bytes[i - 1], b'e' | b'E')
+                    && !query[start..i].contains(['x', 'X']);
+                if x.is_ascii_alphanumeric() || x == b'.' || exponent_sign {
+                    i += 1;
+                } else {
+                    break;
+                }
+            }
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if c == b'?' {
+            i += 1;
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if c == b':' && bytes.get(i + 1).is_some_and(|x| is_word_byte(*x)) {
+            i += 1;
+            while i < bytes.len() && is_word_byte(bytes[i]) {
+                i += 1;
+            }
+            push_literal(&mut tokens, &mut values, &query[start..i]);
+        } else if is_word_byte(c) {
+            while i < bytes.len() && is_word_byte(bytes[i]) {
+                i += 1;
+            }
+            let word = query[start..i].to_ascii_lowercase();
+            match word.as_str() {
+                "true" | "false" | "null" | "nan" | "infinity" => {
+                    push_literal(&mut tokens, &mut values, &word)
+                }
+                _ => tokens.push(Token::Word(word)),
+            }
+        } else {
+            // Operators made of several symbols are kept together
+            i += query[i..].chars().next().map_or(1, char::len_utf8);
+            while i < bytes.len()
+                && matches!(
+                    (bytes[i - 1], bytes[i]),
+                    (b'<' | b'>' | b'!', b'=') | (b'<', b'>')
+                )
+            {
+                i += 1;
+            }
+            tokens.push(Token::Symbol(query[start..i].to_owned()));
+        }
+    }
+
+    while let Some(Token::Symbol(symbol)) = tokens.last() {
+        if symbol != ";" {
+            break;
+        }
+        tokens.pop();
+    }
+
+    let mut text = String::new();
+    let mut previous: Option<&Token> = None;
+    for token in &tokens {
+        if let Some(previous) = previous {
+            let no_space_after =
+                matches!(previous, Token::Symbol(s) if matches!(s.as_str(), "(" | "[" | "{" | "."));
+            let no_space_before = match token {
+                Token::Symbol(s) => {
+                    matches!(s.as_str(), "," | ")" | "]" | "}" | "." | ";" | ":")
+                        || (s == "(" && matches!(previous, Token::Word(_)))
+                }
+                _ => false,
+            };
+            if !no_space_after && !no_space_before {
+                text.push(' ');
+            }
+        }
+        text.push_str(token.as_str());
+        previous = Some(token);
+    }
+    (text, values)
+}
+
+enum Token {
+    Word(String),
+    Symbol(String),
+    Literal,
+}
+
+impl Token {
+    fn as_str(&self) -> &str {
+        match self {
+            Token::Word(x) | Token::Symbol(x) => x,
+            Token::Literal => "?",
+        }
+    }
+
+    /// Returns true if a `-` following this token is a subtraction rather than the sign of a number
+    fn ends_operand(&self) -> bool {
+        match self {
+            Token::Word(_) | Token::Literal => true,
+            Token::Symbol(s) => matches!(s.as_str(), ")" | "]" | "}"),
+        }
+    }
+}
+
+/// Pushes a literal, collapsing a comma separated list of literals into a single literal
+fn push_literal(tokens: &mut Vec<Token>, values: &mut Vec<Bytes>, value: &str) {
+    values.push(Bytes::copy_from_slice(value.as_bytes()));
+    if let [.., Token::Literal, Token::Symbol(comma)] = tokens.as_slice() {
+        if comma == "," {
+            tokens.pop();
+            return;
+        }
+    }
+    tokens.push(Token::Literal);
+}
+
+fn is_word_byte(x: u8) -> bool {
+    x.is_ascii_alphanumeric() || x == b'_'
+}
+
+/// Returns the length of the uuid at the start of `bytes`, uuids must be detected before identifiers since they may start with a letter
+fn uuid_len(bytes: &[u8]) -> Option<usize> {
+    const GROUPS: [usize; 5] = [8, 4, 4, 4, 12];
+    let mut i = 0;
+    for (n, group) in GROUPS.iter().enumerate() {
+        if n > 0 {
+            if bytes.get(i) != Some(&b'-') {
+                return None;
+            }
+            i += 1;
+        }
+        if !bytes.get(i..i + group)?.iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        i += group;
+    }
+    if bytes.get(i).is_some_and(|x| is_word_byte(*x)) {
+        return None;
+    }
+    Some(i)
+}
+
+/// Commands whose first argument is a subcommand that is kept when normalizing
+#[cfg(feature = "redis")]
+const REDIS_CONTAINER_COMMANDS: &[&[u8]] = &[
+    b"ACL",
+    b"CLIENT",
+    b"CLUSTER",
+    b"COMMAND",
+    b"CONFIG",
+    b"FUNCTION",
+    b"LATENCY",
+    b"MEMORY",
+    b"MODULE",
+    b"OBJECT",
+    b"PUBSUB",
+    b"SCRIPT",
+    b"SLOWLOG",
+    b"XGROUP",
+    b"XINFO",
+];
+
+/// Normalizes a redis command into its uppercased name, followed by its uppercased subcommand for commands such as `CLIENT` and `CONFIG`,
+/// followed by a single `?` standing in for all of its arguments.
+/// Returns None if the frame is not a command.
+#[cfg(feature = "redis")]
+pub fn normalize_redis(frame: &RedisFrame) -> Option<NormalizedQuery> {
+    let RedisFrame::Array(args) = frame else {
+        return None;
+    };
+    let mut args = args.iter();
+    let Some(RedisFrame::BulkString(command)) = args.next() else {
+        return None;
+    };
+    let command = command.to_ascii_uppercase();
+    let mut text = String::from_utf8_lossy(&command).into_owned();
+    let mut args = args.peekable();
+    if REDIS_CONTAINER_COMMANDS.contains(&command.as_slice()) {
+        if let Some(RedisFrame::BulkString(subcommand)) = args.next() {
+            text.push(' ');
+            text.push_str(&String::from_utf8_lossy(&subcommand.to_ascii_uppercase()));
+        }
+    }
+    if args.peek().is_some() {
+        text.push_str(" ?");
+    }
+    let values = args
+        .map(|arg| match arg {
+            RedisFrame::BulkString(arg) => arg.clone(),
+            arg => Bytes::from(format!("{arg:?}")),
+        })
+        .collect();
+    Some(NormalizedQuery::new(text, values))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_normalize_cql() {
+        assert_eq!(
+            normalize_cql("SELECT * FROM ks.tbl WHERE id = 1 AND name = 'it''s' LIMIT 10;"),
+            "select * from ks.tbl where id = ? and name = ? limit ?"
+        );
+        assert_eq!(
+            normalize_cql("select  *\nfrom KS.tbl -- comment\n where ID = :id and name=? limit ?"),
+            "select * from ks.tbl where id = ? and name = ? limit ?"
+        );
+        assert_eq!(
+            normalize_cql("INSERT INTO \"Ks\".t (a, b, c) VALUES (-1.5e-3, 0xFF, 123e4567-e89b-12d3-a456-426614174000)"),
+            "insert into \"Ks\".t(a, b, c) values(?)"
+        );
+        assert_eq!(
+            normalize_cql("SELECT a FROM t WHERE a IN (1, 2, 3) AND b = true AND c >= $$x$$"),
+            normalize_cql("select a from t where a in (?) and b = ? and c>=?"),
+        );
+        assert_eq!(
+            normalize_cql(
+                "UPDATE t SET a = a - 1, b = [1, 2] WHERE c = abcdef12-e89b-12d3-a456-426614174000"
+            ),
+            "update t set a = a - ?, b = [?] where c = ?"
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        assert_eq!(Fingerprint::of("").to_string(), "cbf29ce484222325");
+        assert_eq!(Fingerprint::of("a").0, 0xaf63dc4c8601ec8c);
+        assert_ne!(
+            Fingerprint::of("select * from t where a = ?"),
+            Fingerprint::of("select * from t where b = ?")
+        );
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_normalize_redis() {
+        use crate::test_utils::redis_command_frame;
+
+        let normalize = |args: &[&str]| normalize_redis(&redis_command_frame(args)).unwrap().text;
+        assert_eq!(normalize(&["get", "foo"]), "GET ?");
+        assert_eq!(normalize(&["MGET", "a", "b", "c"]), "MGET ?");
+        assert_eq!(normalize(&["ping"]), "PING");
+        assert_eq!(normalize(&["client", "setname", "foo"]), "CLIENT SETNAME ?");
+        assert_eq!(normalize(&["config", "get"]), "CONFIG GET");
+
+        let key = |args: &[&str]| normalize_redis(&redis_command_frame(args)).unwrap().key();
+        assert_eq!(key(&["get", "foo"]), key(&["GET", "foo"]));
+        assert_ne!(key(&["GET", "foo"]), key(&["GET", "bar"]));
+        // the boundaries between arguments are kept
+        assert_ne!(key(&["MGET", "ab", "c"]), key(&["MGET", "a", "bc"]));
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_normalize_request() {
+        use crate::test_utils::cassandra_query;
+
+        let a = normalize_request(&mut cassandra_query("SELECT * FROM ks.t WHERE id = 1"));
+        let b = normalize_request(&mut cassandra_query("select * from ks.t where id = 2"));
+        let c = normalize_request(&mut cassandra_query("select *  from KS.t where id=1;"));
+        assert_eq!(a.len(), 1);
+        assert_eq!(a[0].text, b[0].text);
+        assert_eq!(a[0].fingerprint, b[0].fingerprint);
+        assert_eq!(a[0].values, vec![Bytes::from("1")]);
+        assert_ne!(a[0].key(), b[0].key());
+        assert_eq!(a[0].key(), c[0].key());
+    }
+}
@@ -1,11 +1,32 @@
 use crate::codec::kafka::KafkaCodecState;
 use crate::codec::kafka::RequestHeader as CodecRequestHeader;
+use crate::message::{ErrorKind, OperationType};
 use anyhow::{anyhow, Context, Result};
 use bytes::{BufMut, Bytes, BytesMut};
+use kafka_protocol::messages::describe_groups_response::DescribedGroup;
+use kafka_protocol::messages::fetch_response::{FetchableTopicResponse, PartitionData};
+use kafka_protocol::messages::find_coordinator_response::Coordinator;
+use kafka_protocol::messages::list_offsets_response::{
+    ListOffsetsPartitionResponse, ListOffsetsTopicResponse,
+};
+use kafka_protocol::messages::metadata_response::MetadataResponseTopic;
+use kafka_protocol::messages::offset_commit_response::{
+    OffsetCommitResponsePartition, OffsetCommitResponseTopic,
+};
+use kafka_protocol::messages::offset_fetch_response::OffsetFetchResponseGroup;
+use kafka_protocol::messages::produce_response::{PartitionProduceResponse, TopicProduceResponse};
 use kafka_protocol::messages::{
-    ApiKey, RequestHeader, ResponseHeader, SaslAuthenticateRequest, SaslAuthenticateResponse,
+    ApiKey, DescribeClusterResponse, DescribeGroupsResponse, FetchResponse,
+    FindCoordinatorResponse, HeartbeatResponse, JoinGroupResponse, LeaveGroupResponse,
+    ListGroupsResponse, ListOffsetsResponse, MetadataResponse, OffsetCommitResponse,
+    OffsetFetchResponse, ProduceResponse, RequestHeader, ResponseHeader, SaslAuthenticateRequest,
+    SaslAuthenticateResponse, SyncGroupResponse,
 };
 use kafka_protocol::protocol::{Decodable, Encodable};
+use kafka_protocol::records::{
+    Compression, Record, RecordBatchDecoder, RecordBatchEncoder, RecordEncodeOptions,
+};
+use kafka_protocol::ResponseError;
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
 pub use kafka_protocol::messages::RequestKind as RequestBody;
@@ -143,6 +164,333 @@ impl KafkaFrame {
         })
     }
 
+    /// Classifies the request as reading, writing or altering the schema (topics, configs, acls) of the cluster.
+    pub fn operation_type(&self) -> OperationType {
+        match self {
+            KafkaFrame::Request { body, .. } => match body {
+                RequestBody::Produce(_)
+                | RequestBody::DeleteRecords(_)
+                | RequestBody::OffsetCommit(_)
+                | RequestBody::TxnOffsetCommit(_) => OperationType::Write,
+                RequestBody::Fetch(_)
+                | RequestBody::ListOffsets(_)
+                | RequestBody::Metadata(_)
+                | RequestBody::OffsetFetch(_)
+                | RequestBody::DescribeConfigs(_)
+                | RequestBody::DescribeGroups(_)
+                | RequestBody::ListGroups(_)
+                | RequestBody::FindCoordinator(_) => OperationType::Read,
+                RequestBody::CreateTopics(_)
+                | RequestBody::DeleteTopics(_)
+                | RequestBody::CreatePartitions(_)
+                | RequestBody::AlterConfigs(_)
+                | RequestBody::IncrementalAlterConfigs(_)
+                | RequestBody::CreateAcls(_)
+                | RequestBody::DeleteAcls(_) => OperationType::Ddl,
+                _ => OperationType::Unknown,
+            },
+            KafkaFrame::Response { .. } => OperationType::Unknown,
+        }
+    }
+
+    /// Returns the names of the topics referenced by the request.
+    /// Topics referenced only by id are not included.
+    pub fn topics(&self) -> Vec<String> {
+        let KafkaFrame::Request { body, .. } = self else {
+            return vec![];
+        };
+        let names: Vec<String> = match body {
+            RequestBody::Produce(produce) => produce
+                .topic_data
+                .iter()
+                .map(|x| x.name.as_str().to_owned())
+                .collect(),
+            RequestBody::Fetch(fetch) => fetch
+                .topics
+                .iter()
+                .map(|x| x.topic.as_str().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            RequestBody::ListOffsets(list_offsets) => list_offsets
+                .topics
+                .iter()
+                .map(|x| x.name.as_str().to_owned())
+                .collect(),
+            RequestBody::DeleteRecords(delete) => delete
+                .topics
+                .iter()
+                .map(|x| x.name.as_str().to_owned())
+                .collect(),
+            RequestBody::CreateTopics(create) => create
+                .topics
+                .iter()
+                .map(|x| x.name.as_str().to_owned())
+                .collect(),
+            RequestBody::DeleteTopics(delete) => delete
+                .topic_names
+                .iter()
+                .map(|x| x.as_str().to_owned())
+                .collect(),
+            _ => vec![],
+        };
+        let mut topics = vec![];
+        for name in names {
+            if !topics.contains(&name) {
+                topics.push(name);
+            }
+        }
+        topics
+    }
+
+    /// Returns the keys of the records contained in a produce request.
+    /// Record batches that fail to decode are skipped.
+    pub fn record_keys(&self) -> Vec<Bytes> {
+        let KafkaFrame::Request {
+            body: RequestBody::Produce(produce),
+            ..
+        } = self
+        else {
+            return vec![];
+        };
+        produce
+            .topic_data
+            .iter()
+            .flat_map(|topic| &topic.partition_data)
+            .filter_map(|partition| partition.records.as_ref())
+            .filter_map(|bytes| {
+                // None selects the decompression matching each record batch
+                RecordBatchDecoder::decode(
+                    &mut bytes.clone(),
+                    None::<fn(&mut Bytes, Compression) -> Result<Bytes>>,
+                )
+                .ok()
+            })
+            .flatten()
+            .filter_map(|record| record.key)
+            .collect()
+    }
+
+    /// Returns a response to this request in which every topic, partition or group referenced by the request reports the error.
+    /// Kafka errors are defined per response type, so only the api keys commonly sent by clients are supported.
+    /// `message` is only sent for response types and versions that have an error message field.
+    pub fn to_error_response(&self, kind: ErrorKind, message: &str) -> Result<KafkaFrame> {
+        let KafkaFrame::Request { header, body } = self else {
+            return Err(anyhow!(
+                "An error response can only be formed from a request"
+            ));
+        };
+        let version = header.request_api_version;
+        let code = kafka_error(kind, body).code();
+        let message = Some(StrBytes::from_string(message.to_owned()));
+        let body = match body {
+            RequestBody::Produce(produce) => ResponseBody::Produce(
+                ProduceResponse::default().with_responses(
+                    produce
+                        .topic_data
+                        .iter()
+                        .map(|topic| {
+                            TopicProduceResponse::default()
+                                .with_name(topic.name.clone())
+                                .with_partition_responses(
+                                    topic
+                                        .partition_data
+                                        .iter()
+                                        .map(|partition| {
+                                            PartitionProduceResponse::default()
+                                                .with_index(partition.index)
+                                                .with_error_code(code)
+                                                .with_base_offset(-1)
+                                                .with_error_message(if version >= 8 {
+                                                    message.clone()
+                                                } else {
+                                                    None
+                                                })
+                                        })
+                                        .collect(),
+                                )
+                        })
+                        .collect(),
+                ),
+            ),
+            RequestBody::Fetch(fetch) => ResponseBody::Fetch(
+                FetchResponse::default()
+                    .with_error_code(if version >= 7 { code } else { 0 })
+                    .with_responses(
+                        fetch
+                            .topics
+                            .iter()
+                            .map(|topic| {
+                                FetchableTopicResponse::default()
+                                    .with_topic(topic.topic.clone())
+                                    .with_topic_id(topic.topic_id)
+                                    .with_partitions(
+                                        topic
+                                            .partitions
+                                            .iter()
+                                            .map(|partition| {
+                                                PartitionData::default()
+                                                    .with_partition_index(partition.partition)
+                                                    .with_error_code(code)
+                                            })
+                                            .collect(),
+                                    )
+                            })
+                            .collect(),
+                    ),
+            ),
+            RequestBody::ListOffsets(list_offsets) => ResponseBody::ListOffsets(
+                ListOffsetsResponse::default().with_topics(
+                    list_offsets
+                        .topics
+                        .iter()
+                        .map(|topic| {
+                            ListOffsetsTopicResponse::default()
+                                .with_name(topic.name.clone())
+                                .with_partitions(
+                                    topic
+                                        .partitions
+                                        .iter()
+                                        .map(|partition| {
+                                            ListOffsetsPartitionResponse::default()
+                                                .with_partition_index(partition.partition_index)
+                                                .with_error_code(code)
+                                        })
+                                        .collect(),
+                                )
+                        })
+                        .collect(),
+                ),
+            ),
+            RequestBody::Metadata(metadata) => {
+                let topics = metadata.topics.as_ref().ok_or_else(|| {
+                    anyhow!(
+                        "An error response cannot be formed for a metadata request of all topics"
+                    )
+                })?;
+                ResponseBody::Metadata(
+                    MetadataResponse::default().with_topics(
+                        topics
+                            .iter()
+                            .map(|topic| {
+                                MetadataResponseTopic::default()
+                                    .with_name(topic.name.clone())
+                                    .with_topic_id(topic.topic_id)
+                                    .with_error_code(code)
+                            })
+                            .collect(),
+                    ),
+                )
+            }
+            RequestBody::OffsetCommit(offset_commit) => ResponseBody::OffsetCommit(
+                OffsetCommitResponse::default().with_topics(
+                    offset_commit
+                        .topics
+                        .iter()
+                        .map(|topic| {
+                            OffsetCommitResponseTopic::default()
+                                .with_name(topic.name.clone())
+                                .with_partitions(
+                                    topic
+                                        .partitions
+                                        .iter()
+                                        .map(|partition| {
+                                            OffsetCommitResponsePartition::default()
+                                                .with_partition_index(partition.partition_index)
+                                                .with_error_code(code)
+                                        })
+                                        .collect(),
+                                )
+                        })
+                        .collect(),
+                ),
+            ),
+            RequestBody::OffsetFetch(offset_fetch) => ResponseBody::OffsetFetch(match version {
+                // versions 0 and 1 only report errors per partition
+                0 | 1 => {
+                    return Err(anyhow!(
+                        "An error response cannot be formed for offset fetch version {version}"
+                    ))
+                }
+                2..=7 => OffsetFetchResponse::default().with_error_code(code),
+                _ => OffsetFetchResponse::default().with_groups(
+                    offset_fetch
+                        .groups
+                        .iter()
+                        .map(|group| {
+                            OffsetFetchResponseGroup::default()
+                                .with_group_id(group.group_id.clone())
+                                .with_error_code(code)
+                        })
+                        .collect(),
+                ),
+            }),
+            RequestBody::FindCoordinator(find_coordinator) => {
+                ResponseBody::FindCoordinator(match version {
+                    0 => FindCoordinatorResponse::default().with_error_code(code),
+                    1..=3 => FindCoordinatorResponse::default()
+                        .with_error_code(code)
+                        .with_error_message(message),
+                    _ => FindCoordinatorResponse::default().with_coordinators(
+                        find_coordinator
+                            .coordinator_keys
+                            .iter()
+                            .map(|key| {
+                                Coordinator::default()
+                                    .with_key(key.clone())
+                                    .with_error_code(code)
+                                    .with_error_message(message.clone())
+                            })
+                            .collect(),
+                    ),
+                })
+            }
+            RequestBody::DescribeGroups(describe_groups) => ResponseBody::DescribeGroups(
+                DescribeGroupsResponse::default().with_groups(
+                    describe_groups
+                        .groups
+                        .iter()
+                        .map(|group_id| {
+                            DescribedGroup::default()
+                                .with_group_id(group_id.clone())
+                                .with_error_code(code)
+                        })
+                        .collect(),
+                ),
+            ),
+            RequestBody::JoinGroup(_) => {
+                ResponseBody::JoinGroup(JoinGroupResponse::default().with_error_code(code))
+            }
+            RequestBody::SyncGroup(_) => {
+                ResponseBody::SyncGroup(SyncGroupResponse::default().with_error_code(code))
+            }
+            RequestBody::Heartbeat(_) => {
+                ResponseBody::Heartbeat(HeartbeatResponse::default().with_error_code(code))
+            }
+            RequestBody::LeaveGroup(_) => {
+                ResponseBody::LeaveGroup(LeaveGroupResponse::default().with_error_code(code))
+            }
+            RequestBody::ListGroups(_) => {
+                ResponseBody::ListGroups(ListGroupsResponse::default().with_error_code(code))
+            }
+            RequestBody::DescribeCluster(_) => ResponseBody::DescribeCluster(
+                DescribeClusterResponse::default()
+                    .with_error_code(code)
+                    .with_error_message(message),
+            ),
+            _ => {
+                return Err(anyhow!(
+                    "An error response cannot be formed for kafka api key {}",
+                    header.request_api_key
+                ))
+            }
+        };
+        Ok(KafkaFrame::Response {
+            version,
+            header: ResponseHeader::default().with_correlation_id(header.correlation_id),
+            body,
+        })
+    }
+
     pub fn encode(self, bytes: &mut BytesMut) -> Result<()> {
         // write dummy length
         let length_start = bytes.len();
@@ -177,3 +525,262 @@ impl KafkaFrame {
         Ok(())
     }
 }
+
+/// Kafka has no dedicated overloaded error, so the retriable timeout error is used instead, causing clients to back off and retry.
+fn kafka_error(kind: ErrorKind, body: &RequestBody) -> ResponseError {
+    let group_request = matches!(
+        body,
+        RequestBody::OffsetCommit(_)
+            | RequestBody::OffsetFetch(_)
+            | RequestBody::FindCoordinator(_)
+            | RequestBody::DescribeGroups(_)
+            | RequestBody::JoinGroup(_)
+            | RequestBody::SyncGroup(_)
+            | RequestBody::Heartbeat(_)
+            | RequestBody::LeaveGroup(_)
+            | RequestBody::ListGroups(_)
+    );
+    match kind {
+        ErrorKind::Overloaded | ErrorKind::Timeout => ResponseError::RequestTimedOut,
+        ErrorKind::Unauthorized => match body {
+            RequestBody::ListGroups(_) | RequestBody::DescribeCluster(_) => {
+                ResponseError::ClusterAuthorizationFailed
+            }
+            _ if group_request => ResponseError::GroupAuthorizationFailed,
+            _ => ResponseError::TopicAuthorizationFailed,
+        },
+        ErrorKind::InvalidRequest => ResponseError::InvalidRequest,
+        ErrorKind::Unavailable if group_request => ResponseError::CoordinatorNotAvailable,
+        ErrorKind::Unavailable => ResponseError::LeaderNotAvailable,
+        ErrorKind::Server => ResponseError::UnknownServerError,
+    }
+}
+
+/// The offset of the attributes field within the header of a v2 record batch
+const RECORD_BATCH_ATTRIBUTES_OFFSET: usize = 21;
+/// The offset of the magic byte, which contains the record batch version, within the header of a record batch
+const RECORD_BATCH_MAGIC_OFFSET: usize = 16;
+/// The offset of the length field within the header of a record batch
+const RECORD_BATCH_LENGTH_OFFSET: usize = 8;
+
+/// Produce version 7 is the first version that brokers accept zstd compressed records in.
+const MIN_ZSTD_PRODUCE_VERSION: i16 = 7;
+
+/// Returns the compression of the first record batch in `records`.
+pub fn record_batch_compression(records: &[u8]) -> Result<Compression> {
+    if records.len() < RECORD_BATCH_ATTRIBUTES_OFFSET + 2 {
+        return Err(anyhow!("record batch is too short to contain a header"));
+    }
+    let magic = records[RECORD_BATCH_MAGIC_OFFSET];
+    if magic != 2 {
+        return Err(anyhow!("unsupported record batch version {magic}"));
+    }
+    let attributes = i16::from_be_bytes([
+        records[RECORD_BATCH_ATTRIBUTES_OFFSET],
+        records[RECORD_BATCH_ATTRIBUTES_OFFSET + 1],
+    ]);
+    match attributes & 0x7 {
+        0 => Ok(Compression::None),
+        1 => Ok(Compression::Gzip),
+        2 => Ok(Compression::Snappy),
+        3 => Ok(Compression::Lz4),
+        4 => Ok(Compression::Zstd),
+        other => Err(anyhow!("unknown record batch compression {other}")),
+    }
+}
+
+/// Splits `records` into its record batches without decoding them, so that each batch can be modified separately.
+pub fn split_record_batches(records: &Bytes) -> Result<Vec<Bytes>> {
+    let mut remaining = records.clone();
+    let mut batches = vec![];
+    while !remaining.is_empty() {
+        // Every record batch starts with its i64 base offset followed by the i32 length of the rest of the batch
+        if remaining.len() < RECORD_BATCH_LENGTH_OFFSET + 4 {
+            return Err(anyhow!("record batch is too short to contain its length"));
+        }
+        let length = i32::from_be_bytes(
+            remaining[RECORD_BATCH_LENGTH_OFFSET..RECORD_BATCH_LENGTH_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        );
+        let end = usize::try_from(length)
+            .ok()
+            .and_then(|length| length.checked_add(RECORD_BATCH_LENGTH_OFFSET + 4))
+            .filter(|end| *end <= remaining.len())
+            .ok_or_else(|| anyhow!("record batch length {length} is invalid"))?;
+        batches.push(remaining.split_to(end));
+    }
+    Ok(batches)
+}
+
+/// Decodes the records of every record batch in `records`, decompressing them as needed.
+/// Also returns the compression of the first record batch so that modified records can be encoded with the same compression.
+pub fn decode_records(records: &Bytes) -> Result<(Vec<Record>, Compression)> {
+    let compression = record_batch_compression(records)?;
+    let decoded = RecordBatchDecoder::decode(
+        &mut records.clone(),
+        None::<fn(&mut Bytes, Compression) -> Result<Bytes>>,
+    )
+    .context("Failed to decode record batch")?;
+    Ok((decoded, compression))
+}
+
+/// Encodes the records into a single v2 record batch with the given compression.
+/// The CRC of the record batch is computed over the encoded records.
+pub fn encode_records(records: &[Record], compression: Compression) -> Result<Bytes> {
+    let mut encoded = BytesMut::new();
+    RecordBatchEncoder::encode(
+        &mut encoded,
+        records.iter(),
+        &RecordEncodeOptions {
+            version: 2,
+            compression,
+        },
+        // None selects the compressor matching `compression`
+        None::<fn(&mut BytesMut, &mut BytesMut, Compression) -> Result<()>>,
+    )
+    .context("Failed to encode record batch")?;
+    Ok(encoded.freeze())
+}
+
+/// Returns true if brokers accept records with the given compression in produce requests of the given version.
+pub fn produce_supports_compression(compression: Compression, produce_version: i16) -> bool {
+    compression != Compression::Zstd || produce_version >= MIN_ZSTD_PRODUCE_VERSION
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kafka_protocol::messages::produce_request::{PartitionProduceData, TopicProduceData};
+    use kafka_protocol::messages::{FindCoordinatorRequest, ProduceRequest, TopicName};
+    use kafka_protocol::records::TimestampType;
+
+    fn record(offset: i64) -> Record {
+        Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset,
+            sequence: offset as i32,
+            timestamp: 0,
+            key: Some(Bytes::from(format!("key{offset}"))),
+            value: Some(Bytes::from(vec![b'a'; 100])),
+            headers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_record_compression() {
+        let records = vec![record(0), record(1)];
+        for compression in [
+            Compression::None,
+            Compression::Gzip,
+            Compression::Snappy,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            let encoded = encode_records(&records, compression).unwrap();
+            assert_eq!(record_batch_compression(&encoded).unwrap(), compression);
+            let (decoded, decoded_compression) = decode_records(&encoded).unwrap();
+            assert_eq!(decoded_compression, compression);
+            assert_eq!(
+                decoded.iter().map(|x| &x.key).collect::<Vec<_>>(),
+                records.iter().map(|x| &x.key).collect::<Vec<_>>()
+            );
+        }
+        assert!(record_batch_compression(&[0; 10]).is_err());
+    }
+
+    #[test]
+    fn test_split_record_batches() {
+        let first = encode_records(&[record(0), record(1)], Compression::None).unwrap();
+        let second = encode_records(&[record(2)], Compression::Gzip).unwrap();
+        let mut records = BytesMut::from(first.as_ref());
+        records.extend_from_slice(&second);
+        let records = records.freeze();
+
+        assert_eq!(
+            split_record_batches(&records).unwrap(),
+            vec![first, second.clone()]
+        );
+        assert!(split_record_batches(&records.slice(..records.len() - 1)).is_err());
+        assert!(split_record_batches(&second.slice(..10)).is_err());
+    }
+
+    #[test]
+    fn test_error_response() {
+        let request = KafkaFrame::Request {
+            header: RequestHeader::default()
+                .with_request_api_key(ApiKey::ProduceKey as i16)
+                .with_request_api_version(3)
+                .with_correlation_id(7),
+            body: RequestBody::Produce(ProduceRequest::default().with_acks(-1).with_topic_data(
+                vec![TopicProduceData::default()
+                    .with_name(TopicName(StrBytes::from_static_str("foo")))
+                    .with_partition_data(vec![
+                        PartitionProduceData::default().with_index(0),
+                        PartitionProduceData::default().with_index(1),
+                    ])],
+            )),
+        };
+        let response = request
+            .to_error_response(ErrorKind::Unauthorized, "denied")
+            .unwrap();
+        let KafkaFrame::Response {
+            header,
+            body: ResponseBody::Produce(produce),
+            ..
+        } = &response
+        else {
+            panic!("expected a produce response but was {response:?}");
+        };
+        assert_eq!(header.correlation_id, 7);
+        let partitions = &produce.responses[0].partition_responses;
+        assert_eq!(partitions.len(), 2);
+        for partition in partitions {
+            assert_eq!(
+                partition.error_code,
+                ResponseError::TopicAuthorizationFailed.code()
+            );
+            // produce version 3 has no error message field
+            assert_eq!(partition.error_message, None);
+        }
+        response.encode(&mut BytesMut::new()).unwrap();
+
+        let request = KafkaFrame::Request {
+            header: RequestHeader::default()
+                .with_request_api_key(ApiKey::FindCoordinatorKey as i16)
+                .with_request_api_version(4),
+            body: RequestBody::FindCoordinator(
+                FindCoordinatorRequest::default()
+                    .with_coordinator_keys(vec![StrBytes::from_static_str("group")]),
+            ),
+        };
+        let response = request
+            .to_error_response(ErrorKind::Unavailable, "unavailable")
+            .unwrap();
+        let KafkaFrame::Response {
+            body: ResponseBody::FindCoordinator(find_coordinator),
+            ..
+        } = &response
+        else {
+            panic!("expected a find coordinator response but was {response:?}");
+        };
+        assert_eq!(
+            find_coordinator.coordinators[0].error_code,
+            ResponseError::CoordinatorNotAvailable.code()
+        );
+        response.encode(&mut BytesMut::new()).unwrap();
+
+        let request = KafkaFrame::Request {
+            header: RequestHeader::default().with_request_api_key(ApiKey::CreateTopicsKey as i16),
+            body: RequestBody::CreateTopics(Default::default()),
+        };
+        assert!(request
+            .to_error_response(ErrorKind::Server, "error")
+            .is_err());
+    }
+}
@@ -0,0 +1,386 @@
+//! A parser for the subset of the memcached text protocol supported by shotover.
+
+use crate::message::QueryType;
+use anyhow::{anyhow, bail, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Memcached limits keys to 250 bytes so no valid command line comes anywhere near this length.
+const MAX_LINE_LENGTH: usize = 2048;
+
+/// The largest value memcached stores by default, configured with its `-I` option.
+pub const DEFAULT_MAX_ITEM_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemcachedFrame {
+    Request(MemcachedRequest),
+    Response(MemcachedResponse),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemcachedRequest {
+    Get {
+        keys: Vec<Bytes>,
+    },
+    Set {
+        key: Bytes,
+        flags: u32,
+        exptime: i64,
+        data: Bytes,
+        noreply: bool,
+    },
+    Delete {
+        key: Bytes,
+        noreply: bool,
+    },
+    Incr {
+        key: Bytes,
+        value: u64,
+        noreply: bool,
+    },
+    Decr {
+        key: Bytes,
+        value: u64,
+        noreply: bool,
+    },
+    /// A command not supported by shotover, contains the entire command line.
+    Unknown(Bytes),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemcachedResponse {
+    Values(Vec<MemcachedValue>),
+    Stored,
+    NotStored,
+    Exists,
+    NotFound,
+    Deleted,
+    Number(u64),
+    Error,
+    ClientError(String),
+    ServerError(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemcachedValue {
+    pub key: Bytes,
+    pub flags: u32,
+    pub data: Bytes,
+}
+
+impl MemcachedRequest {
+    /// Returns true when the client has requested that no response be sent
+    pub fn noreply(&self) -> bool {
+        match self {
+            MemcachedRequest::Set { noreply, .. }
+            | MemcachedRequest::Delete { noreply, .. }
+            | MemcachedRequest::Incr { noreply, .. }
+            | MemcachedRequest::Decr { noreply, .. } => *noreply,
+            MemcachedRequest::Get { .. } | MemcachedRequest::Unknown(_) => false,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemcachedRequest::Get { .. } => "get",
+            MemcachedRequest::Set { .. } => "set",
+            MemcachedRequest::Delete { .. } => "delete",
+            MemcachedRequest::Incr { .. } => "incr",
+            MemcachedRequest::Decr { .. } => "decr",
+            MemcachedRequest::Unknown(_) => "unknown",
+        }
+    }
+}
+
+impl MemcachedFrame {
+    pub fn get_query_type(&self) -> QueryType {
+        match self {
+            MemcachedFrame::Request(MemcachedRequest::Get { .. }) => QueryType::Read,
+            MemcachedFrame::Request(_) => QueryType::Write,
+            MemcachedFrame::Response(_) => QueryType::Read,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match Self::parse(bytes, DEFAULT_MAX_ITEM_SIZE)? {
+            Some((frame, _)) => Ok(frame),
+            None => Err(anyhow!("incomplete memcached frame")),
+        }
+    }
+
+    /// Attempts to parse a single frame from the start of `src`.
+    /// Returns the frame along with the number of bytes it occupies, or None if `src` does not yet contain an entire frame.
+    /// Data blocks larger than `max_item_size` bytes are rejected instead of waiting for them to be received.
+    pub fn parse(src: &[u8], max_item_size: usize) -> Result<Option<(Self, usize)>> {
+        let Some((line, line_len)) = read_line(src)? else {
+            return Ok(None);
+        };
+        let mut tokens = line.split(|x| *x == b' ').filter(|x| !x.is_empty());
+        let Some(command) = tokens.next() else {
+            bail!("empty memcached command line");
+        };
+
+        Ok(match command {
+            b"get" => {
+                let keys: Vec<Bytes> = tokens.map(Bytes::copy_from_slice).collect();
+                if keys.is_empty() {
+                    bail!("get requires at least one key");
+                }
+                Some((
+                    MemcachedFrame::Request(MemcachedRequest::Get { keys }),
+                    line_len,
+                ))
+            }
+            b"set" => {
+                let key = Bytes::copy_from_slice(next_token(&mut tokens)?);
+                let flags = parse_number(next_token(&mut tokens)?)?;
+                let exptime = parse_number(next_token(&mut tokens)?)?;
+                let data_len: usize = parse_number(next_token(&mut tokens)?)?;
+                let noreply = parse_noreply(tokens.next())?;
+                read_data(&src[line_len..], data_len, max_item_size)?.map(
+                    |(data, data_frame_len)| {
+                        (
+                            MemcachedFrame::Request(MemcachedRequest::Set {
+                                key,
+                                flags,
+                                exptime,
+                                data,
+                                noreply,
+                            }),
+                            line_len + data_frame_len,
+                        )
+                    },
+                )
+            }
+            b"delete" => {
+                let key = Bytes::copy_from_slice(next_token(&mut tokens)?);
+                let noreply = parse_noreply(tokens.next())?;
+                Some((
+                    MemcachedFrame::Request(MemcachedRequest::Delete { key, noreply }),
+                    line_len,
+                ))
+            }
+            b"incr" | b"decr" => {
+                let key = Bytes::copy_from_slice(next_token(&mut tokens)?);
+                let value = parse_number(next_token(&mut tokens)?)?;
+                let noreply = parse_noreply(tokens.next())?;
+                let request = if command == b"incr" {
+                    MemcachedRequest::Incr {
+                        key,
+                        value,
+                        noreply,
+                    }
+                } else {
+                    MemcachedRequest::Decr {
+                        key,
+                        value,
+                        noreply,
+                    }
+                };
+                Some((MemcachedFrame::Request(request), line_len))
+            }
+            b"VALUE" | b"END" => {
+                let mut values = vec![];
+                let mut offset = 0;
+                loop {
+                    let Some((line, line_len)) = read_line(&src[offset..])? else {
+                        return Ok(None);
+                    };
+                    offset += line_len;
+                    let mut tokens = line.split(|x| *x == b' ').filter(|x| !x.is_empty());
+                    match tokens.next() {
+                        Some(b"END") => break,
+                        Some(b"VALUE") => {
+                            let key = Bytes::copy_from_slice(next_token(&mut tokens)?);
+                            let flags = parse_number(next_token(&mut tokens)?)?;
+                            let data_len: usize = parse_number(next_token(&mut tokens)?)?;
+                            let Some((data, data_frame_len)) =
+                                read_data(&src[offset..], data_len, max_item_size)?
+                            else {
+                                return Ok(None);
+                            };
+                            offset += data_frame_len;
+                            values.push(MemcachedValue { key, flags, data });
+                        }
+                        _ => bail!("expected VALUE or END in memcached get response"),
+                    }
+                }
+                Some((
+                    MemcachedFrame::Response(MemcachedResponse::Values(values)),
+                    offset,
+                ))
+            }
+            b"STORED" => Some(response(MemcachedResponse::Stored, line_len)),
+            b"NOT_STORED" => Some(response(MemcachedResponse::NotStored, line_len)),
+            b"EXISTS" => Some(response(MemcachedResponse::Exists, line_len)),
+            b"NOT_FOUND" => Some(response(MemcachedResponse::NotFound, line_len)),
+            b"DELETED" => Some(response(MemcachedResponse::Deleted, line_len)),
+            b"ERROR" => Some(response(MemcachedResponse::Error, line_len)),
+            b"CLIENT_ERROR" | b"SERVER_ERROR" => {
+                let message =
+                    String::from_utf8_lossy(line.get(command.len() + 1..).unwrap_or_default())
+                        .into_owned();
+                let value = if command == b"CLIENT_ERROR" {
+                    MemcachedResponse::ClientError(message)
+                } else {
+                    MemcachedResponse::ServerError(message)
+                };
+                Some(response(value, line_len))
+            }
+            number if number.iter().all(u8::is_ascii_digit) => Some(response(
+                MemcachedResponse::Number(parse_number(number)?),
+                line_len,
+            )),
+            _ => Some((
+                MemcachedFrame::Request(MemcachedRequest::Unknown(Bytes::copy_from_slice(line))),
+                line_len,
+            )),
+        })
+    }
+
+    pub fn encode(&self, dst: &mut BytesMut) {
+        match self {
+            MemcachedFrame::Request(request) => match request {
+                MemcachedRequest::Get { keys } => {
+                    dst.put_slice(b"get");
+                    for key in keys {
+                        dst.put_u8(b' ');
+                        dst.put_slice(key);
+                    }
+                    dst.put_slice(b"\r\n");
+                }
+                MemcachedRequest::Set {
+                    key,
+                    flags,
+                    exptime,
+                    data,
+                    noreply,
+                } => {
+                    dst.put_slice(b"set ");
+                    dst.put_slice(key);
+                    dst.put_slice(format!(" {flags} {exptime} {}", data.len()).as_bytes());
+                    encode_noreply(dst, *noreply);
+                    dst.put_slice(data);
+                    dst.put_slice(b"\r\n");
+                }
+                MemcachedRequest::Delete { key, noreply } => {
+                    dst.put_slice(b"delete ");
+                    dst.put_slice(key);
+                    encode_noreply(dst, *noreply);
+                }
+                MemcachedRequest::Incr {
+                    key,
+                    value,
+                    noreply,
+                }
+                | MemcachedRequest::Decr {
+                    key,
+                    value,
+                    noreply,
+                } => {
+                    dst.put_slice(request.name().as_bytes());
+                    dst.put_u8(b' ');
+                    dst.put_slice(key);
+                    dst.put_slice(format!(" {value}").as_bytes());
+                    encode_noreply(dst, *noreply);
+                }
+                MemcachedRequest::Unknown(line) => {
+                    dst.put_slice(line);
+                    dst.put_slice(b"\r\n");
+                }
+            },
+            MemcachedFrame::Response(response) => match response {
+                MemcachedResponse::Values(values) => {
+                    for value in values {
+                        dst.put_slice(b"VALUE ");
+                        dst.put_slice(&value.key);
+                        dst.put_slice(
+                            format!(" {} {}\r\n", value.flags, value.data.len()).as_bytes(),
+                        );
+                        dst.put_slice(&value.data);
+                        dst.put_slice(b"\r\n");
+                    }
+                    dst.put_slice(b"END\r\n");
+                }
+                MemcachedResponse::Stored => dst.put_slice(b"STORED\r\n"),
+                MemcachedResponse::NotStored => dst.put_slice(b"NOT_STORED\r\n"),
+                MemcachedResponse::Exists => dst.put_slice(b"EXISTS\r\n"),
+                MemcachedResponse::NotFound => dst.put_slice(b"NOT_FOUND\r\n"),
+                MemcachedResponse::Deleted => dst.put_slice(b"DELETED\r\n"),
+                MemcachedResponse::Number(number) => {
+                    dst.put_slice(format!("{number}\r\n").as_bytes())
+                }
+                MemcachedResponse::Error => dst.put_slice(b"ERROR\r\n"),
+                // memcached errors can not contain newlines at the protocol level
+                MemcachedResponse::ClientError(message) => dst.put_slice(
+                    format!("CLIENT_ERROR {}\r\n", message.replace(['\r', '\n'], " ")).as_bytes(),
+                ),
+                MemcachedResponse::ServerError(message) => dst.put_slice(
+                    format!("SERVER_ERROR {}\r\n", message.replace(['\r', '\n'], " ")).as_bytes(),
+                ),
+            },
+        }
+    }
+}
+
+fn response(response: MemcachedResponse, len: usize) -> (MemcachedFrame, usize) {
+    (MemcachedFrame::Response(response), len)
+}
+
+/// Returns the line without its terminating `\r\n` along with the length of the line including the `\r\n`
+fn read_line(src: &[u8]) -> Result<Option<(&[u8], usize)>> {
+    match src.windows(2).position(|x| x == b"\r\n") {
+        Some(end) => Ok(Some((&src[..end], end + 2))),
+        None if src.len() > MAX_LINE_LENGTH => bail!("memcached command line is too long"),
+        None => Ok(None),
+    }
+}
+
+/// Reads a data block of `len` bytes followed by `\r\n`
+fn read_data(src: &[u8], len: usize, max_item_size: usize) -> Result<Option<(Bytes, usize)>> {
+    if len > max_item_size {
+        bail!("memcached data block of {len} bytes exceeds the max item size of {max_item_size} bytes");
+    }
+    let Some(frame_len) = len.checked_add(2) else {
+        bail!("memcached data block of {len} bytes is too large");
+    };
+    if src.len() < frame_len {
+        return Ok(None);
+    }
+    if &src[len..frame_len] != b"\r\n" {
+        bail!("bad data chunk");
+    }
+    Ok(Some((Bytes::copy_from_slice(&src[..len]), frame_len)))
+}
+
+fn next_token<'a>(tokens: &mut impl Iterator<Item = &'a [u8]>) -> Result<&'a [u8]> {
+    tokens
+        .next()
+        .ok_or_else(|| anyhow!("memcached command is missing arguments"))
+}
+
+fn parse_number<T: std::str::FromStr>(token: &[u8]) -> Result<T> {
+    std::str::from_utf8(token)
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "invalid numeric argument {:?}",
+                String::from_utf8_lossy(token)
+            )
+        })
+}
+
+fn parse_noreply(token: Option<&[u8]>) -> Result<bool> {
+    match token {
+        None => Ok(false),
+        Some(b"noreply") => Ok(true),
+        Some(token) => bail!("unexpected argument {:?}", String::from_utf8_lossy(token)),
+    }
+}
+
+fn encode_noreply(dst: &mut BytesMut, noreply: bool) {
+    if noreply {
+        dst.put_slice(b" noreply");
+    }
+    dst.put_slice(b"\r\n");
+}
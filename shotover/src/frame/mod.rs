@@ -11,6 +11,8 @@ pub use cassandra::{CassandraFrame, CassandraOperation, CassandraResult};
 use cassandra_protocol::compression::Compression;
 #[cfg(feature = "kafka")]
 use kafka::KafkaFrame;
+#[cfg(feature = "memcached")]
+pub use memcached::MemcachedFrame;
 #[cfg(feature = "opensearch")]
 pub use opensearch::OpenSearchFrame;
 #[cfg(feature = "redis")]
@@ -19,8 +21,11 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
+pub mod fingerprint;
 #[cfg(feature = "kafka")]
 pub mod kafka;
+#[cfg(feature = "memcached")]
+pub mod memcached;
 #[cfg(feature = "opensearch")]
 pub mod opensearch;
 #[cfg(feature = "redis")]
@@ -37,6 +42,8 @@ pub enum MessageType {
     Kafka,
     #[cfg(feature = "opensearch")]
     OpenSearch,
+    #[cfg(feature = "memcached")]
+    Memcached,
     Dummy,
 }
 
@@ -51,6 +58,8 @@ impl MessageType {
             MessageType::Kafka => true,
             #[cfg(feature = "opensearch")]
             MessageType::OpenSearch => true,
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached => true,
             MessageType::Dummy => false,
         }
     }
@@ -65,6 +74,8 @@ impl MessageType {
             MessageType::Kafka => "kafka",
             #[cfg(feature = "opensearch")]
             MessageType::OpenSearch => "opensearch",
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached => "memcached",
             MessageType::Dummy => "dummy",
         }
     }
@@ -81,6 +92,8 @@ impl From<&CodecState> for MessageType {
             CodecState::Kafka { .. } => Self::Kafka,
             #[cfg(feature = "opensearch")]
             CodecState::OpenSearch => Self::OpenSearch,
+            #[cfg(feature = "memcached")]
+            CodecState::Memcached => Self::Memcached,
             CodecState::Dummy => Self::Dummy,
         }
     }
@@ -103,6 +116,8 @@ impl Frame {
             Frame::Dummy => CodecState::Dummy,
             #[cfg(feature = "opensearch")]
             Frame::OpenSearch(_) => CodecState::OpenSearch,
+            #[cfg(feature = "memcached")]
+            Frame::Memcached(_) => CodecState::Memcached,
         }
     }
 }
@@ -120,6 +135,8 @@ pub enum Frame {
     Dummy,
     #[cfg(feature = "opensearch")]
     OpenSearch(OpenSearchFrame),
+    #[cfg(feature = "memcached")]
+    Memcached(MemcachedFrame),
 }
 
 impl Frame {
@@ -144,6 +161,8 @@ impl Frame {
             MessageType::Dummy => Ok(Frame::Dummy),
             #[cfg(feature = "opensearch")]
             MessageType::OpenSearch => Ok(Frame::OpenSearch(OpenSearchFrame::from_bytes(&bytes)?)),
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached => MemcachedFrame::from_bytes(&bytes).map(Frame::Memcached),
         }
     }
 
@@ -158,6 +177,8 @@ impl Frame {
             Frame::Dummy => "Dummy",
             #[cfg(feature = "opensearch")]
             Frame::OpenSearch(_) => "OpenSearch",
+            #[cfg(feature = "memcached")]
+            Frame::Memcached(_) => "Memcached",
         }
     }
 
@@ -172,6 +193,8 @@ impl Frame {
             Frame::Dummy => MessageType::Dummy,
             #[cfg(feature = "opensearch")]
             Frame::OpenSearch(_) => MessageType::OpenSearch,
+            #[cfg(feature = "memcached")]
+            Frame::Memcached(_) => MessageType::Memcached,
         }
     }
 
@@ -231,6 +254,17 @@ impl Frame {
             )),
         }
     }
+
+    #[cfg(feature = "memcached")]
+    pub fn into_memcached(self) -> Result<MemcachedFrame> {
+        match self {
+            Frame::Memcached(frame) => Ok(frame),
+            frame => Err(anyhow!(
+                "Expected memcached frame but received {} frame",
+                frame.name()
+            )),
+        }
+    }
 }
 
 impl Display for Frame {
@@ -245,6 +279,8 @@ impl Display for Frame {
             Frame::Dummy => write!(f, "Shotover internal dummy message"),
             #[cfg(feature = "opensearch")]
             Frame::OpenSearch(frame) => write!(f, "OpenSearch: {:?}", frame),
+            #[cfg(feature = "memcached")]
+            Frame::Memcached(frame) => write!(f, "Memcached {:?}", frame),
         }
     }
 }
@@ -1,5 +1,6 @@
 use crate::frame::RedisFrame;
 use crate::message::QueryType;
+use bytes::Bytes;
 
 #[inline]
 pub fn redis_query_type(frame: &RedisFrame) -> QueryType {
@@ -10,7 +11,8 @@ pub fn redis_query_type(frame: &RedisFrame) -> QueryType {
                 | b"LRANGE" | b"LINDEX" | b"LLEN" | b"SCARD" | b"SISMEMBER" | b"SMEMBERS"
                 | b"SUNION" | b"SINTER" | b"ZCARD" | b"ZCOUNT" | b"ZRANGE" | b"ZRANK"
                 | b"ZSCORE" | b"ZRANGEBYSCORE" | b"HGET" | b"HGETALL" | b"HEXISTS" | b"HKEYS"
-                | b"HLEN" | b"HSTRLEN" | b"HVALS" | b"PFCOUNT" => QueryType::Read,
+                | b"HLEN" | b"HSTRLEN" | b"HVALS" | b"PFCOUNT" | b"XRANGE" | b"XREVRANGE"
+                | b"XLEN" | b"XREAD" | b"XINFO" | b"XPENDING" => QueryType::Read,
                 _ => QueryType::Write,
             };
         }
@@ -37,3 +39,372 @@ pub fn redis_query_name(frame: &RedisFrame) -> Option<String> {
     }
     None
 }
+
+/// Returns the keys accessed by a redis command.
+/// Falls back to the first argument for commands whose keys are not known, see [`redis_command_keys`] when that is not acceptable.
+pub fn redis_keys(frame: &RedisFrame) -> Vec<Bytes> {
+    redis_command_keys(frame).unwrap_or_else(|| match frame {
+        RedisFrame::Array(args) => match args.get(1) {
+            Some(RedisFrame::BulkString(key)) => vec![key.clone()],
+            _ => vec![],
+        },
+        _ => vec![],
+    })
+}
+
+/// Returns the keys accessed by a redis command, or `None` if the command is unknown
+/// or accesses keys that cannot be determined from its arguments, e.g. `SORT key GET pattern`.
+///
+/// The key positions of each command match the key specs returned by redis' `COMMAND INFO`.
+pub fn redis_command_keys(frame: &RedisFrame) -> Option<Vec<Bytes>> {
+    let RedisFrame::Array(args) = frame else {
+        return None;
+    };
+    let Some(RedisFrame::BulkString(command)) = args.first() else {
+        return None;
+    };
+    let args: Vec<&Bytes> = args[1..]
+        .iter()
+        .filter_map(|x| match x {
+            RedisFrame::BulkString(x) => Some(x),
+            _ => None,
+        })
+        .collect();
+    // the keys are every `step`th argument from `first` up to and including `last`, counting from the end if `last` is negative
+    let range = |first: usize, last: isize, step: usize| -> Vec<Bytes> {
+        let end = if last < 0 {
+            args.len().saturating_sub(last.unsigned_abs() - 1)
+        } else {
+            args.len().min(last as usize + 1)
+        };
+        args.get(first..end)
+            .unwrap_or_default()
+            .iter()
+            .step_by(step)
+            .map(|x| (*x).clone())
+            .collect()
+    };
+    // `numkeys` keys follow the numkeys argument at `index`
+    let numkeys = |index: usize| -> Option<Vec<Bytes>> {
+        let count = std::str::from_utf8(args.get(index)?)
+            .ok()?
+            .parse::<usize>()
+            .ok()?;
+        let first = index + 1;
+        Some(
+            args.get(first..first.checked_add(count)?)?
+                .iter()
+                .map(|x| (*x).clone())
+                .collect(),
+        )
+    };
+
+    Some(match command.to_ascii_uppercase().as_slice() {
+        b"PING" | b"ECHO" | b"AUTH" | b"HELLO" | b"SELECT" | b"INFO" | b"TIME" | b"DBSIZE"
+        | b"FLUSHALL" | b"FLUSHDB" | b"MULTI" | b"EXEC" | b"DISCARD" | b"UNWATCH" | b"CLIENT"
+        | b"CONFIG" | b"CLUSTER" | b"SCRIPT" | b"KEYS" | b"SCAN" | b"PUBLISH" | b"SUBSCRIBE"
+        | b"PSUBSCRIBE" | b"UNSUBSCRIBE" | b"PUNSUBSCRIBE" | b"QUIT" | b"RESET" | b"RANDOMKEY"
+        | b"LASTSAVE" | b"SAVE" | b"BGSAVE" | b"BGREWRITEAOF" | b"ROLE" | b"READONLY"
+        | b"READWRITE" | b"WAIT" | b"COMMAND" | b"FUNCTION" | b"SLOWLOG" | b"LATENCY"
+        | b"SWAPDB" | b"MONITOR" | b"DEBUG" | b"SHUTDOWN" => vec![],
+
+        b"GET"
+        | b"SET"
+        | b"SETNX"
+        | b"SETEX"
+        | b"PSETEX"
+        | b"GETSET"
+        | b"GETDEL"
+        | b"GETEX"
+        | b"APPEND"
+        | b"STRLEN"
+        | b"INCR"
+        | b"DECR"
+        | b"INCRBY"
+        | b"DECRBY"
+        | b"INCRBYFLOAT"
+        | b"GETRANGE"
+        | b"SETRANGE"
+        | b"SUBSTR"
+        | b"LCS"
+        | b"BITCOUNT"
+        | b"BITPOS"
+        | b"SETBIT"
+        | b"GETBIT"
+        | b"BITFIELD"
+        | b"BITFIELD_RO"
+        | b"LPUSH"
+        | b"RPUSH"
+        | b"LPUSHX"
+        | b"RPUSHX"
+        | b"LPOP"
+        | b"RPOP"
+        | b"LLEN"
+        | b"LRANGE"
+        | b"LINDEX"
+        | b"LSET"
+        | b"LREM"
+        | b"LTRIM"
+        | b"LINSERT"
+        | b"LPOS"
+        | b"SADD"
+        | b"SREM"
+        | b"SCARD"
+        | b"SISMEMBER"
+        | b"SMISMEMBER"
+        | b"SMEMBERS"
+        | b"SPOP"
+        | b"SRANDMEMBER"
+        | b"SSCAN"
+        | b"ZADD"
+        | b"ZREM"
+        | b"ZCARD"
+        | b"ZCOUNT"
+        | b"ZINCRBY"
+        | b"ZRANGE"
+        | b"ZRANGEBYSCORE"
+        | b"ZRANGEBYLEX"
+        | b"ZREVRANGE"
+        | b"ZREVRANGEBYSCORE"
+        | b"ZREVRANGEBYLEX"
+        | b"ZRANK"
+        | b"ZREVRANK"
+        | b"ZSCORE"
+        | b"ZMSCORE"
+        | b"ZREMRANGEBYRANK"
+        | b"ZREMRANGEBYSCORE"
+        | b"ZREMRANGEBYLEX"
+        | b"ZLEXCOUNT"
+        | b"ZPOPMIN"
+        | b"ZPOPMAX"
+        | b"ZRANDMEMBER"
+        | b"ZSCAN"
+        | b"HSET"
+        | b"HSETNX"
+        | b"HGET"
+        | b"HMSET"
+        | b"HMGET"
+        | b"HDEL"
+        | b"HLEN"
+        | b"HSTRLEN"
+        | b"HKEYS"
+        | b"HVALS"
+        | b"HGETALL"
+        | b"HEXISTS"
+        | b"HINCRBY"
+        | b"HINCRBYFLOAT"
+        | b"HRANDFIELD"
+        | b"HSCAN"
+        | b"EXPIRE"
+        | b"PEXPIRE"
+        | b"EXPIREAT"
+        | b"PEXPIREAT"
+        | b"EXPIRETIME"
+        | b"PEXPIRETIME"
+        | b"TTL"
+        | b"PTTL"
+        | b"PERSIST"
+        | b"TYPE"
+        | b"DUMP"
+        | b"RESTORE"
+        | b"PFADD"
+        | b"XADD"
+        | b"XLEN"
+        | b"XRANGE"
+        | b"XREVRANGE"
+        | b"XDEL"
+        | b"XTRIM"
+        | b"XACK"
+        | b"XCLAIM"
+        | b"XAUTOCLAIM"
+        | b"XPENDING"
+        | b"XSETID"
+        | b"GEOADD"
+        | b"GEODIST"
+        | b"GEOHASH"
+        | b"GEOPOS"
+        | b"GEOSEARCH"
+        | b"GEORADIUS_RO"
+        | b"GEORADIUSBYMEMBER_RO" => range(0, 0, 1),
+
+        b"MGET" | b"DEL" | b"UNLINK" | b"EXISTS" | b"TOUCH" | b"WATCH" | b"SINTER" | b"SUNION"
+        | b"SDIFF" | b"PFCOUNT" | b"PFMERGE" | b"SINTERSTORE" | b"SUNIONSTORE" | b"SDIFFSTORE" => {
+            range(0, -1, 1)
+        }
+        b"MSET" | b"MSETNX" => range(0, -1, 2),
+        b"RENAME" | b"RENAMENX" | b"SMOVE" | b"RPOPLPUSH" | b"BRPOPLPUSH" | b"LMOVE"
+        | b"BLMOVE" | b"COPY" | b"ZRANGESTORE" | b"GEOSEARCHSTORE" => range(0, 1, 1),
+        // BITOP operation destkey key [key ...]
+        b"BITOP" => range(1, -1, 1),
+        // BLPOP key [key ...] timeout
+        b"BLPOP" | b"BRPOP" | b"BZPOPMIN" | b"BZPOPMAX" => range(0, -2, 1),
+        // OBJECT ENCODING key, MEMORY USAGE key
+        b"OBJECT" | b"MEMORY" => match args.first() {
+            Some(sub_command) if sub_command.eq_ignore_ascii_case(b"HELP") => vec![],
+            Some(sub_command)
+                if command.eq_ignore_ascii_case(b"MEMORY")
+                    && !sub_command.eq_ignore_ascii_case(b"USAGE") =>
+            {
+                vec![]
+            }
+            _ => range(1, 1, 1),
+        },
+        // ZUNIONSTORE destination numkeys key [key ...]
+        b"ZUNIONSTORE" | b"ZINTERSTORE" | b"ZDIFFSTORE" => {
+            let mut keys = range(0, 0, 1);
+            keys.extend(numkeys(1)?);
+            keys
+        }
+        // ZUNION numkeys key [key ...]
+        b"ZUNION" | b"ZINTER" | b"ZDIFF" | b"ZINTERCARD" | b"SINTERCARD" | b"LMPOP" | b"ZMPOP" => {
+            numkeys(0)?
+        }
+        // BLMPOP timeout numkeys key [key ...]
+        b"BLMPOP" | b"BZMPOP" => numkeys(1)?,
+        // EVAL script numkeys key [key ...] arg [arg ...]
+        b"EVAL" | b"EVALSHA" | b"EVAL_RO" | b"EVALSHA_RO" | b"FCALL" | b"FCALL_RO" => numkeys(1)?,
+        // GEORADIUS key ... [STORE key] [STOREDIST key]
+        b"GEORADIUS" | b"GEORADIUSBYMEMBER" => {
+            let mut keys = range(0, 0, 1);
+            for (i, arg) in args.iter().enumerate() {
+                if arg.eq_ignore_ascii_case(b"STORE") || arg.eq_ignore_ascii_case(b"STOREDIST") {
+                    keys.push((*args.get(i + 1)?).clone());
+                }
+            }
+            keys
+        }
+        // SORT key [BY pattern] [GET pattern ...] [STORE destination]
+        // The keys matched by the BY and GET patterns cannot be known
+        b"SORT" | b"SORT_RO" => {
+            let mut keys = range(0, 0, 1);
+            for (i, arg) in args.iter().enumerate().skip(1) {
+                if arg.eq_ignore_ascii_case(b"BY") || arg.eq_ignore_ascii_case(b"GET") {
+                    return None;
+                }
+                if arg.eq_ignore_ascii_case(b"STORE") {
+                    keys.push((*args.get(i + 1)?).clone());
+                }
+            }
+            keys
+        }
+        // XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+        b"XREAD" | b"XREADGROUP" => {
+            let streams = args
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(b"STREAMS"))?;
+            let streams = &args[streams + 1..];
+            streams[..streams.len() / 2]
+                .iter()
+                .map(|x| (*x).clone())
+                .collect()
+        }
+        // XGROUP CREATE key group id
+        b"XGROUP" | b"XINFO" => match args.first() {
+            Some(sub_command) if sub_command.eq_ignore_ascii_case(b"HELP") => vec![],
+            _ => range(1, 1, 1),
+        },
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command_frame;
+
+    #[test]
+    fn test_redis_keys() {
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["GET", "foo"])),
+            vec!["foo"]
+        );
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["SET", "foo", "bar"])),
+            vec!["foo"]
+        );
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["MSET", "a", "1", "b", "2"])),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            redis_keys(&redis_command_frame(&[
+                "EVAL", "return 1", "2", "a", "b", "arg"
+            ])),
+            vec!["a", "b"]
+        );
+        assert!(redis_keys(&redis_command_frame(&["PING"])).is_empty());
+        assert_eq!(
+            redis_keys(&redis_command_frame(&[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "COUNT",
+                "1",
+                "STREAMS",
+                "a",
+                "b",
+                ">",
+                ">"
+            ])),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["XGROUP", "CREATE", "a", "g", "$"])),
+            vec!["a"]
+        );
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["XADD", "a", "*", "f", "v"])),
+            vec!["a"]
+        );
+        // unknown commands fall back to the first argument
+        assert_eq!(
+            redis_keys(&redis_command_frame(&["NEWCMD", "a", "b"])),
+            vec!["a"]
+        );
+    }
+
+    #[test]
+    fn test_redis_command_keys() {
+        let keys = |args: &[&'static str]| redis_command_keys(&redis_command_frame(args));
+        assert_eq!(
+            keys(&["SUNIONSTORE", "dest", "a", "b"]).unwrap(),
+            vec!["dest", "a", "b"]
+        );
+        assert_eq!(
+            keys(&["ZUNIONSTORE", "dest", "2", "a", "b", "WEIGHTS", "1", "2"]).unwrap(),
+            vec!["dest", "a", "b"]
+        );
+        assert_eq!(
+            keys(&["BITOP", "AND", "dest", "a", "b"]).unwrap(),
+            vec!["dest", "a", "b"]
+        );
+        assert_eq!(
+            keys(&["LMOVE", "a", "b", "LEFT", "RIGHT"]).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(
+            keys(&["COPY", "a", "b", "DB", "1"]).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(keys(&["BLPOP", "a", "b", "0"]).unwrap(), vec!["a", "b"]);
+        assert_eq!(keys(&["OBJECT", "ENCODING", "a"]).unwrap(), vec!["a"]);
+        assert_eq!(
+            keys(&["SORT", "a", "LIMIT", "0", "1", "STORE", "b"]).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(keys(&["SORT", "a", "GET", "secret_*"]), None);
+        assert_eq!(
+            keys(&["GEORADIUS", "a", "0", "0", "1", "km", "STORE", "b"]).unwrap(),
+            vec!["a", "b"]
+        );
+        assert_eq!(keys(&["NEWCMD", "a"]), None);
+        // numkeys larger than the arguments or overflowing
+        assert_eq!(keys(&["EVAL", "return 1", "3", "a"]), None);
+        assert_eq!(
+            keys(&["EVAL", "return 1", "18446744073709551615", "a"]),
+            None
+        );
+        assert_eq!(keys(&["EVAL", "return 1", "x", "a"]), None);
+    }
+}
@@ -0,0 +1,386 @@
+//! Background health checking of the upstream nodes that sink transforms send requests to.
+//!
+//! Sinks create a [`HealthGroup`] for the nodes they send to and register each node with it.
+//! Every registered node is then periodically probed in the background,
+//! allowing sinks to avoid nodes that are down without waiting for a client request to fail,
+//! and allowing the `/ready` endpoint to report when shotover has no healthy upstream to send to.
+
+use crate::events::{self, EventKind};
+use crate::tcp::{self, TcpConfig};
+use crate::tls::TlsConnector;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+use tokio::time::timeout;
+
+/// Every group that has been created and not yet dropped, used to report readiness.
+static GROUPS: LazyLock<Mutex<Vec<Weak<HealthGroup>>>> = LazyLock::new(Default::default);
+
+/// The largest response to a probe that will be read before the node is considered unhealthy.
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+const MAX_PROBE_RESPONSE_LEN: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheckConfig {
+    /// How often each node is probed.
+    pub interval_ms: u64,
+    /// How long a probe, including establishing its connection, may take before it is considered failed.
+    pub timeout_ms: u64,
+    /// The number of consecutive failed probes after which a node is considered unhealthy, defaults to 3.
+    /// An unhealthy node is considered healthy again after a single successful probe.
+    pub failure_threshold: Option<u32>,
+}
+
+/// How a node is checked for health.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// The node is healthy if a TCP connection, and TLS session if configured, can be established.
+    Connect,
+    /// The node is healthy if it responds to a `PING` with `PONG`, or requires authentication first.
+    #[cfg(feature = "redis")]
+    RedisPing,
+    /// The node is healthy if it responds to an `OPTIONS` request with `SUPPORTED`.
+    #[cfg(feature = "cassandra")]
+    CassandraOptions,
+}
+
+/// The nodes checked on behalf of a single sink transform.
+/// The health checks of a group's nodes stop once the group is dropped.
+pub struct HealthGroup {
+    name: String,
+    probe: Probe,
+    config: HealthCheckConfig,
+    tls: Option<TlsConnector>,
+    nodes: Mutex<HashMap<String, Arc<NodeHealth>>>,
+    changed: watch::Sender<()>,
+}
+
+struct NodeHealth {
+    healthy: AtomicBool,
+}
+
+impl HealthGroup {
+    /// Creates a group of nodes whose health is reported by the `/ready` endpoint under `name`, usually the chain and transform names.
+    pub fn new(
+        name: String,
+        probe: Probe,
+        config: HealthCheckConfig,
+        tls: Option<TlsConnector>,
+    ) -> Arc<Self> {
+        let group = Arc::new(HealthGroup {
+            name,
+            probe,
+            config,
+            tls,
+            nodes: Mutex::new(HashMap::new()),
+            changed: watch::Sender::new(()),
+        });
+        let mut groups = GROUPS.lock().unwrap();
+        groups.retain(|x| x.strong_count() > 0);
+        groups.push(Arc::downgrade(&group));
+        group
+    }
+
+    /// Starts checking the health of the node at `address` if it is not already being checked.
+    pub fn register(self: &Arc<Self>, address: &str) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(address) {
+            let node = Arc::new(NodeHealth {
+                healthy: AtomicBool::new(true),
+            });
+            tokio::spawn(check_node(
+                Arc::downgrade(self),
+                Arc::downgrade(&node),
+                address.to_owned(),
+            ));
+            nodes.insert(address.to_owned(), node);
+        }
+    }
+
+    /// Replaces the checked nodes with `addresses`, nodes that are no longer in the list stop being checked.
+    pub fn set_nodes<'a>(self: &Arc<Self>, addresses: impl IntoIterator<Item = &'a str>) {
+        let addresses: Vec<&str> = addresses.into_iter().collect();
+        self.nodes
+            .lock()
+            .unwrap()
+            .retain(|address, _| addresses.contains(&address.as_str()));
+        for address in addresses {
+            self.register(address);
+        }
+    }
+
+    /// Returns false only if the node is registered and its most recent probes have failed.
+    pub fn is_healthy(&self, address: &str) -> bool {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(address)
+            .map(|node| node.healthy.load(Ordering::Relaxed))
+            .unwrap_or(true)
+    }
+
+    /// Returns a receiver that is notified whenever a node in the group changes between healthy and unhealthy.
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    /// Returns the addresses of the unhealthy nodes if none of the group's nodes are healthy.
+    fn unready_nodes(&self) -> Option<Vec<String>> {
+        let nodes = self.nodes.lock().unwrap();
+        if nodes.values().any(|x| x.healthy.load(Ordering::Relaxed)) || nodes.is_empty() {
+            None
+        } else {
+            let mut addresses: Vec<String> = nodes.keys().cloned().collect();
+            addresses.sort();
+            Some(addresses)
+        }
+    }
+}
+
+/// Returns a description of every group without a single healthy node, shotover is ready when this is empty.
+pub(crate) fn unready_groups() -> Vec<String> {
+    GROUPS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|group| group.upgrade())
+        .filter_map(|group| {
+            group.unready_nodes().map(|addresses| {
+                format!(
+                    "{} has no healthy nodes, unhealthy nodes: {}",
+                    group.name,
+                    addresses.join(", ")
+                )
+            })
+        })
+        .collect()
+}
+
+/// Probes the node every interval until either the node or its group is dropped.
+async fn check_node(group: Weak<HealthGroup>, node: Weak<NodeHealth>, address: String) {
+    let mut consecutive_failures = 0;
+    loop {
+        let interval = {
+            let Some(group) = group.upgrade() else { return };
+            let Some(node) = node.upgrade() else { return };
+
+            let probe_timeout = Duration::from_millis(group.config.timeout_ms);
+            let result = match timeout(
+                probe_timeout,
+                probe(group.probe, &address, group.tls.as_ref(), probe_timeout),
+            )
+            .await
+            {
+                Ok(result) => result,
+                Err(_) => Err(anyhow!("probe timed out after {probe_timeout:?}")),
+            };
+
+            let failure_threshold = group.config.failure_threshold.unwrap_or(3);
+            let healthy = match result {
+                Ok(()) => {
+                    consecutive_failures = 0;
+                    true
+                }
+                Err(err) => {
+                    consecutive_failures += 1;
+                    tracing::debug!("health check of {address} failed: {err:?}");
+                    consecutive_failures < failure_threshold
+                }
+            };
+            if node.healthy.swap(healthy, Ordering::Relaxed) != healthy {
+                if healthy {
+                    tracing::info!("{}: node {address} is healthy again", group.name);
+                } else {
+                    tracing::warn!(
+                        "{}: node {address} is unhealthy after {consecutive_failures} failed health checks",
+                        group.name
+                    );
+                    events::publish(
+                        EventKind::NodeUnhealthy,
+                        format!("{}/{address}", group.name),
+                        format!("node {address} is unhealthy after {consecutive_failures} failed health checks"),
+                    );
+                }
+                group.changed.send_replace(());
+            }
+
+            Duration::from_millis(group.config.interval_ms)
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn probe(
+    probe: Probe,
+    address: &str,
+    tls: Option<&TlsConnector>,
+    connect_timeout: Duration,
+) -> Result<()> {
+    // probes are short lived so the tuning of the sink's connections does not apply to them
+    let tcp = TcpConfig::default();
+    match tls {
+        Some(tls) => {
+            let mut stream = tls.connect(connect_timeout, address, &tcp).await?;
+            probe_stream(probe, &mut stream).await
+        }
+        None => {
+            let mut stream = tcp::tcp_stream(connect_timeout, address, &tcp).await?;
+            probe_stream(probe, &mut stream).await
+        }
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "redis", feature = "cassandra")),
+    allow(unused_variables)
+)]
+async fn probe_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    probe: Probe,
+    stream: &mut S,
+) -> Result<()> {
+    match probe {
+        Probe::Connect => Ok(()),
+        #[cfg(feature = "redis")]
+        Probe::RedisPing => {
+            use tokio::io::AsyncWriteExt;
+
+            stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+            let line = read_line(stream).await?;
+            // A node requiring authentication is still able to serve authenticated clients
+            if line.starts_with(b"+PONG") || line.starts_with(b"-NOAUTH") {
+                Ok(())
+            } else {
+                anyhow::bail!(
+                    "PING was responded to with {:?}",
+                    String::from_utf8_lossy(&line)
+                )
+            }
+        }
+        #[cfg(feature = "cassandra")]
+        Probe::CassandraOptions => {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            // An OPTIONS request as per the v4 protocol: version, flags, stream id, opcode and an empty body
+            stream
+                .write_all(&[0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x00])
+                .await?;
+            let mut header = [0; 9];
+            stream.read_exact(&mut header).await?;
+            let opcode = header[4];
+            let len = u32::from_be_bytes(header[5..9].try_into().unwrap()) as usize;
+            if len > MAX_PROBE_RESPONSE_LEN {
+                anyhow::bail!("OPTIONS was responded to with a {len} byte body");
+            }
+            let mut body = vec![0; len];
+            stream.read_exact(&mut body).await?;
+            // SUPPORTED
+            if opcode == 0x06 {
+                Ok(())
+            } else {
+                anyhow::bail!(
+                    "OPTIONS was responded to with opcode {opcode:#x} instead of SUPPORTED"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn read_line<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut line = vec![];
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            return Ok(line);
+        }
+        if line.len() >= MAX_PROBE_RESPONSE_LEN {
+            anyhow::bail!("response to the probe was too long");
+        }
+        line.push(byte);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_redis_ping_probe() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server.write_all(b"+PONG\r\n").await.unwrap();
+        probe_stream(Probe::RedisPing, &mut client).await.unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(b"-LOADING Redis is loading the dataset in memory\r\n")
+            .await
+            .unwrap();
+        assert!(probe_stream(Probe::RedisPing, &mut client).await.is_err());
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[tokio::test]
+    async fn test_cassandra_options_probe() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(&[
+                0x84, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+            ])
+            .await
+            .unwrap();
+        probe_stream(Probe::CassandraOptions, &mut client)
+            .await
+            .unwrap();
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server
+            .write_all(&[0x84, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00])
+            .await
+            .unwrap();
+        assert!(probe_stream(Probe::CassandraOptions, &mut client)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unready_groups() {
+        let group = HealthGroup::new(
+            "test_unready_groups".to_owned(),
+            Probe::Connect,
+            HealthCheckConfig {
+                interval_ms: 60000,
+                timeout_ms: 1000,
+                failure_threshold: None,
+            },
+            None,
+        );
+        let node = Arc::new(NodeHealth {
+            healthy: AtomicBool::new(false),
+        });
+        group
+            .nodes
+            .lock()
+            .unwrap()
+            .insert("127.0.0.1:1".to_owned(), node.clone());
+        assert!(!group.is_healthy("127.0.0.1:1"));
+        assert!(group.is_healthy("127.0.0.1:2"));
+        assert!(unready_groups().contains(
+            &"test_unready_groups has no healthy nodes, unhealthy nodes: 127.0.0.1:1".to_owned()
+        ));
+
+        node.healthy.store(true, Ordering::Relaxed);
+        assert!(!unready_groups()
+            .iter()
+            .any(|x| x.starts_with("test_unready_groups")));
+    }
+}
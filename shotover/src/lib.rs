@@ -1,6 +1,6 @@
-//! This library allows the creation of custom shotover transforms.
+//! This library allows the creation of custom shotover transforms and embedding shotover within another application.
 //!
-//! There are two consumers of this library:
+//! There are three consumers of this library:
 //! ## Custom Transforms
 //!
 //! To create a custom transform you need to implement these traits:
@@ -24,6 +24,15 @@
 //!     shotover::runner::Shotover::new().run_block();
 //! }
 //! ```
+//!
+//! ## Embedding shotover
+//! An application that already has its own tokio runtime and tracing subscriber can run shotover within itself.
+//! The topology can be parsed from yaml with [`config::topology::Topology::from_yaml`] or built in code from the config types in [`sources`] and [`transforms`],
+//! with chains built by [`config::chain::TransformChainConfig::with_transform`] which also accepts custom transforms.
+//! The topology is then started and stopped with [`runner::EmbeddedShotover`].
+//!
+//! The config types of the sources implement `Default` so that they can be constructed with `..Default::default()`,
+//! which means that adding new optional configuration to a source is not a breaking change.
 
 #![deny(
     unsafe_code,
@@ -47,6 +56,7 @@ If we absolutely need unsafe code, it should be isolated within a separate small
         not(feature = "redis"),
         not(feature = "kafka"),
         not(feature = "opensearch"),
+        not(feature = "memcached"),
     ),
     allow(dead_code, unused_imports, unused_variables, unused_mut)
 )]
@@ -55,26 +65,39 @@ If we absolutely need unsafe code, it should be isolated within a separate small
     not(feature = "redis"),
     not(feature = "kafka"),
     not(feature = "opensearch"),
+    not(feature = "memcached"),
 ))]
 compile_error!(
-    "At least one protocol feature must be enabled, e.g. `cassandra`, `redis`, `kafka` or `opensearch`"
+    "At least one protocol feature must be enabled, e.g. `cassandra`, `redis`, `kafka`, `opensearch` or `memcached`"
 );
 
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+mod bench;
+mod capture;
+mod client_connections;
 pub mod codec;
 pub mod config;
 pub mod connection;
 mod connection_span;
+pub mod events;
 pub mod frame;
+pub mod health;
 mod http;
+mod listen_fds;
 pub mod message;
 mod observability;
 pub mod runner;
 mod server;
 pub mod sources;
 pub mod tcp;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
 pub mod tls;
 mod tracing_panic_handler;
 pub mod transforms;
+mod upgrade;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
 /// Imports a custom transform into the shotover binary.
 ///
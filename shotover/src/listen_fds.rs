@@ -0,0 +1,120 @@
+//! Listening sockets passed to shotover by the process that started it.
+//!
+//! Sockets are passed following the systemd socket activation protocol:
+//! `LISTEN_FDS` is the number of sockets, passed as consecutive file descriptors starting at 3,
+//! and `LISTEN_PID`, when set, must be the pid of shotover.
+//! Any supervisor can pass sockets this way, not just systemd.
+//! This allows binding privileged ports without running shotover as root,
+//! and keeps the sockets listening while shotover is restarted.
+//!
+//! Sockets are also received from the previous shotover process during an upgrade, see [`crate::upgrade`].
+//!
+//! A source accepts connections on an inherited socket instead of binding its own when its name
+//! matches the socket's entry in `LISTEN_FDNAMES`, or when its `listen_addr` is the socket's local address.
+
+use anyhow::{anyhow, Context, Result};
+use listenfd::ListenFd;
+use std::net::{SocketAddr, TcpListener};
+use std::os::fd::AsFd;
+use std::sync::Mutex;
+use tracing::info;
+
+/// The file descriptor of the first passed socket
+const FIRST_FD: usize = 3;
+
+pub(crate) struct InheritedListener {
+    pub name: Option<String>,
+    pub address: SocketAddr,
+    pub listener: TcpListener,
+}
+
+/// Inherited sockets along with the sockets bound by sources that are kept for an upgrade
+static INHERITED: Mutex<Vec<InheritedListener>> = Mutex::new(vec![]);
+
+/// Takes ownership of the listening sockets passed to shotover, if any.
+/// Must be called at startup before any sources are created.
+pub(crate) fn inherit() -> Result<()> {
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+        .map(|names| names.split(':').map(|x| x.to_owned()).collect())
+        .unwrap_or_default();
+    let mut listen_fd = ListenFd::from_env();
+
+    let mut inherited = INHERITED.lock().unwrap();
+    for i in 0..listen_fd.len() {
+        let fd = FIRST_FD + i;
+        let listener = listen_fd
+            .take_tcp_listener(i)
+            .with_context(|| format!("Inherited file descriptor {fd} is not a TCP socket"))?
+            .ok_or_else(|| anyhow!("Inherited file descriptor {fd} was already taken"))?;
+        let address = listener.local_addr().with_context(|| {
+            format!("Failed to get the address of inherited file descriptor {fd}")
+        })?;
+        let name = names.get(i).filter(|x| !x.is_empty()).cloned();
+        info!("Inherited listening socket {address} named {name:?} from file descriptor {fd}");
+        inherited.push(InheritedListener {
+            name,
+            address,
+            listener,
+        });
+    }
+    Ok(())
+}
+
+/// Adds a listening socket received from the previous shotover process.
+pub(crate) fn add(listener: InheritedListener) {
+    INHERITED.lock().unwrap().push(listener);
+}
+
+/// Keeps a copy of a socket bound by a source so that it can be handed off during an upgrade.
+/// Once kept, the source takes the socket again whenever it recreates its listener, as it could not bind the same address while the socket is open.
+///
+/// The kept copy is never closed, so when `hard_connection_limit` drops the source's listener the socket keeps listening
+/// and new connections are queued in the kernel's backlog until the source accepts again, rather than being refused.
+pub(crate) fn keep(source_name: &str, listener: &tokio::net::TcpListener) -> std::io::Result<()> {
+    let mut inherited = INHERITED.lock().unwrap();
+    if !inherited
+        .iter()
+        .any(|x| x.name.as_deref() == Some(source_name))
+    {
+        inherited.push(InheritedListener {
+            name: Some(source_name.to_owned()),
+            address: listener.local_addr()?,
+            listener: TcpListener::from(listener.as_fd().try_clone_to_owned()?),
+        });
+    }
+    Ok(())
+}
+
+/// Returns a copy of every inherited and kept socket.
+pub(crate) fn all() -> std::io::Result<Vec<InheritedListener>> {
+    INHERITED
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|x| {
+            Ok(InheritedListener {
+                name: x.name.clone(),
+                address: x.address,
+                listener: x.listener.try_clone()?,
+            })
+        })
+        .collect()
+}
+
+/// Returns a listener for the source if it matches an inherited socket.
+///
+/// The inherited socket is kept open, so the source can take it again after it stops listening due to `hard_connection_limit`,
+/// and so every runtime of `--runtime per-core` can accept connections from it.
+/// As a result, connections made while the limit is hit are queued in the kernel's backlog rather than refused.
+pub(crate) fn take(source_name: &str, listen_addr: &str) -> Option<std::io::Result<TcpListener>> {
+    let address = listen_addr.parse::<SocketAddr>().ok();
+    let inherited = INHERITED.lock().unwrap();
+    let found = inherited
+        .iter()
+        .find(|x| x.name.as_deref() == Some(source_name))
+        .or_else(|| inherited.iter().find(|x| Some(x.address) == address))?;
+    Some(found.listener.try_clone().and_then(|listener| {
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }))
+}
@@ -3,8 +3,16 @@
 use crate::codec::CodecState;
 #[cfg(feature = "cassandra")]
 use crate::frame::{cassandra, cassandra::CassandraMetadata};
+#[cfg(feature = "memcached")]
+use crate::frame::{
+    memcached::{MemcachedRequest, MemcachedResponse},
+    MemcachedFrame,
+};
 #[cfg(feature = "redis")]
-use crate::frame::{redis::redis_query_type, RedisFrame};
+use crate::frame::{
+    redis::{redis_keys, redis_query_type},
+    RedisFrame,
+};
 use crate::frame::{Frame, MessageType};
 use anyhow::{anyhow, Context, Result};
 use bytes::Bytes;
@@ -14,7 +22,7 @@ use nonzero_ext::nonzero;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::num::NonZeroU32;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 
 pub type MessageIdMap<T> = HashMap<MessageId, T, FnvBuildHasher>;
 pub type MessageIdSet = HashSet<MessageId, FnvBuildHasher>;
@@ -28,6 +36,8 @@ pub enum Metadata {
     Kafka,
     #[cfg(feature = "opensearch")]
     OpenSearch,
+    #[cfg(feature = "memcached")]
+    Memcached,
 }
 
 impl Metadata {
@@ -57,6 +67,10 @@ impl Metadata {
             )),
             #[cfg(feature = "opensearch")]
             Metadata::OpenSearch => unimplemented!(),
+            #[cfg(feature = "memcached")]
+            Metadata::Memcached => Frame::Memcached(MemcachedFrame::Response(
+                MemcachedResponse::ServerError(error),
+            )),
         }))
     }
 }
@@ -101,6 +115,74 @@ pub struct Message {
     pub(crate) id: MessageId,
     #[derivative(PartialEq = "ignore")]
     pub(crate) request_id: Option<MessageId>,
+
+    /// When the data accessed by the request this message responds to was last modified, if known.
+    #[derivative(PartialEq = "ignore")]
+    pub(crate) last_modified: Option<SystemTime>,
+
+    /// Some when the message only contains part of a response that is forwarded to the client as it is received.
+    pub(crate) stream_chunk: Option<StreamChunk>,
+
+    /// Decisions made about this message by transforms, see [`Message::annotate`].
+    #[derivative(PartialEq = "ignore")]
+    pub(crate) annotations: Vec<(&'static str, AnnotationValue)>,
+}
+
+/// The value of an annotation attached to a message by a transform, see [`Message::annotate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotationValue {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl std::fmt::Display for AnnotationValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnnotationValue::String(value) => value.fmt(f),
+            AnnotationValue::Integer(value) => value.fmt(f),
+            AnnotationValue::Bool(value) => value.fmt(f),
+        }
+    }
+}
+
+impl From<String> for AnnotationValue {
+    fn from(value: String) -> Self {
+        AnnotationValue::String(value)
+    }
+}
+
+impl From<&str> for AnnotationValue {
+    fn from(value: &str) -> Self {
+        AnnotationValue::String(value.to_owned())
+    }
+}
+
+impl From<i64> for AnnotationValue {
+    fn from(value: i64) -> Self {
+        AnnotationValue::Integer(value)
+    }
+}
+
+impl From<bool> for AnnotationValue {
+    fn from(value: bool) -> Self {
+        AnnotationValue::Bool(value)
+    }
+}
+
+/// The part of a streamed response contained in a message.
+///
+/// A response larger than the chunk size configured on its sink is split into multiple messages as it is received,
+/// so that it can be forwarded to the client without waiting for, or buffering, the whole response.
+/// This only occurs when every transform up chain of the sink accepts streamed responses, see [`crate::transforms::TransformBuilder::accepts_streamed_responses`].
+/// Chunks cannot be parsed into a [`Frame`], only the first chunk has metadata and only the last chunk has a request id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamChunk {
+    /// The first chunk of the response, it contains the header of the response
+    Start,
+    Continuation,
+    /// The last chunk of the response, it is the only chunk with a request id
+    End,
 }
 
 // `from_*` methods for `Message`
@@ -122,6 +204,9 @@ impl Message {
             received_from_source_or_sink_at,
             id: rand::random(),
             request_id: None,
+            last_modified: None,
+            stream_chunk: None,
+            annotations: vec![],
         }
     }
 
@@ -139,6 +224,9 @@ impl Message {
             received_from_source_or_sink_at,
             id: rand::random(),
             request_id: None,
+            last_modified: None,
+            stream_chunk: None,
+            annotations: vec![],
         }
     }
 
@@ -155,6 +243,9 @@ impl Message {
             received_from_source_or_sink_at,
             id: rand::random(),
             request_id: None,
+            last_modified: None,
+            stream_chunk: None,
+            annotations: vec![],
         }
     }
 
@@ -167,6 +258,23 @@ impl Message {
             received_from_source_or_sink_at: diverged_from.received_from_source_or_sink_at,
             id: diverged_from.id(),
             request_id: None,
+            last_modified: None,
+            stream_chunk: None,
+            annotations: diverged_from.annotations.clone(),
+        }
+    }
+
+    /// This method should be called when splitting a large response into chunks as it is received, see [`StreamChunk`].
+    /// This is expected to be used only by codecs that can find the end of a message without parsing it into a frame.
+    pub fn from_stream_chunk_at_instant(
+        bytes: Bytes,
+        codec_state: CodecState,
+        stream_chunk: StreamChunk,
+        received_from_source_or_sink_at: Option<Instant>,
+    ) -> Self {
+        Message {
+            stream_chunk: Some(stream_chunk),
+            ..Self::from_bytes_at_instant(bytes, codec_state, received_from_source_or_sink_at)
         }
     }
 
@@ -190,11 +298,15 @@ impl Message {
     ///
     /// Returns `None` when fails to parse the message.
     /// This failure to parse the message is internally logged as an error.
+    /// Also returns `None` without logging an error when the message is a chunk of a streamed response, see [`StreamChunk`].
     ///
     /// ## Performance implications
     /// Calling frame for the first time on a message may be an expensive operation as the raw bytes might not yet be parsed into a Frame.
     /// Calling frame again is free as the parsed message is cached.
     pub fn frame(&mut self) -> Option<&mut Frame> {
+        if self.stream_chunk.is_some() {
+            return None;
+        }
         let (inner, result) = self.inner.take().unwrap().ensure_parsed(self.codec_state);
         self.inner = Some(inner);
         if let Err(err) = result {
@@ -215,6 +327,9 @@ impl Message {
     /// Same as [`Message::frame`] but consumes the message and returns an owned [`Frame`]
     /// It is useful when the transform generates a request and consumes the response without the involvement of the client.
     pub fn into_frame(mut self) -> Option<Frame> {
+        if self.stream_chunk.is_some() {
+            return None;
+        }
         let (inner, result) = self.inner.take().unwrap().ensure_parsed(self.codec_state);
         if let Err(err) = result {
             // TODO: If we could include a stacktrace in this error it would be really helpful
@@ -231,6 +346,16 @@ impl Message {
         }
     }
 
+    /// Returns the size in bytes of the message as it was received.
+    /// Returns None when the message was generated or modified by shotover and so has no raw bytes.
+    pub fn received_size(&self) -> Option<usize> {
+        match self.inner.as_ref().unwrap() {
+            MessageInner::RawBytes { bytes, .. } => Some(bytes.len()),
+            MessageInner::Parsed { bytes, .. } => Some(bytes.len()),
+            MessageInner::Modified { .. } => None,
+        }
+    }
+
     /// Return the shotover assigned MessageId
     pub fn id(&self) -> MessageId {
         self.id
@@ -248,6 +373,57 @@ impl Message {
         self.request_id = Some(request_id);
     }
 
+    /// Returns the part of a streamed response contained in this message, or None if the message is complete.
+    pub fn stream_chunk(&self) -> Option<StreamChunk> {
+        self.stream_chunk
+    }
+
+    /// Returns when the data accessed by the request this response is for was last modified.
+    /// Only set on responses that have passed through a transform that tags them, such as RedisTimestampTagger.
+    /// RedisTimestampTagger can only observe when a key was last accessed, including by reads, so this may be later than the last modification.
+    pub fn last_modified(&self) -> Option<SystemTime> {
+        self.last_modified
+    }
+
+    pub fn set_last_modified(&mut self, last_modified: SystemTime) {
+        self.last_modified = Some(last_modified);
+    }
+
+    /// Attaches an annotation to the message, replacing any existing annotation with the same key.
+    /// Annotations allow a transform to communicate a decision it made about the message, such as the tenant it belongs to or whether it was served from a cache,
+    /// to later transforms and to observability transforms such as QueryCounter without modifying the frame.
+    ///
+    /// Annotations on a request are visible to the transforms down chain of the transform that attached them,
+    /// annotations on a response are visible to the transforms up chain of it.
+    /// Annotations are never sent to the client or database.
+    pub fn annotate(&mut self, key: &'static str, value: impl Into<AnnotationValue>) {
+        let value = value.into();
+        match self.annotations.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.annotations.push((key, value)),
+        }
+    }
+
+    /// Returns the value of the annotation with the given key, if attached by an earlier transform.
+    pub fn annotation(&self, key: &str) -> Option<&AnnotationValue> {
+        self.annotations
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns every annotation attached to the message in the order they were first attached.
+    pub fn annotations(&self) -> impl Iterator<Item = (&'static str, &AnnotationValue)> {
+        self.annotations.iter().map(|(key, value)| (*key, value))
+    }
+
+    /// Returns an id shared by a request and all responses to it, suitable for correlating the two in logs or in a transform's own request/response maps.
+    /// For requests this is the request's own id and for responses it is the id of the request the response is for.
+    /// Responses that were not created in response to a request use their own id.
+    pub fn correlation_id(&self) -> MessageId {
+        self.request_id.unwrap_or(self.id)
+    }
+
     pub fn clone_with_new_id(&self) -> Self {
         Message {
             inner: self.inner.clone(),
@@ -255,6 +431,9 @@ impl Message {
             codec_state: self.codec_state,
             id: rand::random(),
             request_id: self.request_id,
+            last_modified: self.last_modified,
+            stream_chunk: self.stream_chunk,
+            annotations: self.annotations.clone(),
         }
     }
 
@@ -340,6 +519,8 @@ impl Message {
                 MessageType::Dummy => nonzero!(1u32),
                 #[cfg(feature = "opensearch")]
                 MessageType::OpenSearch => todo!(),
+                #[cfg(feature = "memcached")]
+                MessageType::Memcached => nonzero!(1u32),
             },
             MessageInner::Modified { frame } | MessageInner::Parsed { frame, .. } => match frame {
                 #[cfg(feature = "cassandra")]
@@ -351,6 +532,8 @@ impl Message {
                 Frame::Dummy => nonzero!(1u32),
                 #[cfg(feature = "opensearch")]
                 Frame::OpenSearch(_) => todo!(),
+                #[cfg(feature = "memcached")]
+                Frame::Memcached(_) => nonzero!(1u32),
             },
         })
     }
@@ -382,10 +565,72 @@ impl Message {
             Some(Frame::Dummy) => todo!(),
             #[cfg(feature = "opensearch")]
             Some(Frame::OpenSearch(_)) => todo!(),
+            #[cfg(feature = "memcached")]
+            Some(Frame::Memcached(memcached)) => memcached.get_query_type(),
             None => QueryType::ReadWrite,
         }
     }
 
+    /// Returns whether the request reads, writes or changes the schema of the DB.
+    pub fn operation_type(&mut self) -> OperationType {
+        match self.frame() {
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(cassandra)) => cassandra.operation_type(),
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(redis)) => match redis_query_type(redis) {
+                QueryType::Read => OperationType::Read,
+                QueryType::Write => OperationType::Write,
+                _ => OperationType::Unknown,
+            },
+            #[cfg(feature = "kafka")]
+            Some(Frame::Kafka(kafka)) => kafka.operation_type(),
+            #[cfg(feature = "memcached")]
+            Some(Frame::Memcached(MemcachedFrame::Request(memcached))) => match memcached {
+                MemcachedRequest::Get { .. } => OperationType::Read,
+                MemcachedRequest::Unknown(_) => OperationType::Unknown,
+                _ => OperationType::Write,
+            },
+            _ => OperationType::Unknown,
+        }
+    }
+
+    /// Returns the tables accessed by the request.
+    /// For cassandra these are fully qualified table names and for kafka these are topic names.
+    /// Protocols without the concept of a table return an empty list.
+    pub fn tables(&mut self) -> Vec<String> {
+        match self.frame() {
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(cassandra)) => cassandra.tables(),
+            #[cfg(feature = "kafka")]
+            Some(Frame::Kafka(kafka)) => kafka.topics(),
+            _ => vec![],
+        }
+    }
+
+    /// Returns the keys of the rows, records or values accessed by the request, where they can be determined from the request alone.
+    /// For cassandra these are the values restricted by equality in WHERE clauses, for redis and memcached the keys of the command
+    /// and for kafka the keys of the produced records.
+    pub fn primary_keys(&mut self) -> Vec<Bytes> {
+        match self.frame() {
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(cassandra)) => cassandra.primary_keys(),
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(redis)) => redis_keys(redis),
+            #[cfg(feature = "kafka")]
+            Some(Frame::Kafka(kafka)) => kafka.record_keys(),
+            #[cfg(feature = "memcached")]
+            Some(Frame::Memcached(MemcachedFrame::Request(memcached))) => match memcached {
+                MemcachedRequest::Get { keys } => keys.clone(),
+                MemcachedRequest::Set { key, .. }
+                | MemcachedRequest::Delete { key, .. }
+                | MemcachedRequest::Incr { key, .. }
+                | MemcachedRequest::Decr { key, .. } => vec![key.clone()],
+                MemcachedRequest::Unknown(_) => vec![],
+            },
+            _ => vec![],
+        }
+    }
+
     /// Returns an error response with the provided error message.
     pub fn from_response_to_error_response(&self, error: String) -> Result<Message> {
         let mut response = self
@@ -411,8 +656,97 @@ impl Message {
         Ok(request)
     }
 
+    /// Returns a response to this request reporting an error of the given kind, formatted for the protocol and version of the request:
+    /// * Redis - an error string prefixed with `BUSY` for [`ErrorKind::Overloaded`], `NOPERM` for [`ErrorKind::Unauthorized`] and `ERR` otherwise.
+    /// * Cassandra - an error with the closest matching error code, in the version and stream of the request.
+    /// * Kafka - the response type of the request's api key with the closest matching error code set on every topic, partition or group the request references.
+    ///   Only the api keys commonly sent by clients are supported, an `Err` is returned for any other.
+    /// * Memcached - a `CLIENT_ERROR` for [`ErrorKind::InvalidRequest`] and a `SERVER_ERROR` otherwise.
+    ///
+    /// If the client does not expect a response to the request, a dummy response is returned instead.
+    pub fn error_response(&mut self, error: String, kind: ErrorKind) -> Result<Message> {
+        let frame = if self.response_is_dummy() {
+            Frame::Dummy
+        } else {
+            self.error_frame(error, kind)?
+        };
+        let mut response = Message::from_frame(frame);
+        response.set_request_id(self.id());
+        Ok(response)
+    }
+
+    /// Returns an error response of the given kind to replace this response, formatted as in [`Message::error_response`].
+    /// Kafka error responses are formed from the request, so an `Err` is returned for kafka responses.
+    pub fn error_response_from_response(
+        &mut self,
+        error: String,
+        kind: ErrorKind,
+    ) -> Result<Message> {
+        let mut response = Message::from_frame(self.error_frame(error, kind)?);
+        if let Some(request_id) = self.request_id() {
+            response.set_request_id(request_id)
+        }
+        Ok(response)
+    }
+
+    #[allow(unreachable_code)]
+    fn error_frame(&mut self, error: String, kind: ErrorKind) -> Result<Frame> {
+        let metadata = self
+            .metadata()
+            .context("Failed to parse metadata of request when producing an error")?;
+        Ok(match metadata {
+            #[cfg(feature = "redis")]
+            Metadata::Redis => {
+                let code = match kind {
+                    ErrorKind::Overloaded => "BUSY",
+                    ErrorKind::Unauthorized => "NOPERM",
+                    _ => "ERR",
+                };
+                // Redis errors can not contain newlines at the protocol level
+                let message = format!("{code} {error}")
+                    .replace("\r\n", " ")
+                    .replace('\n', " ");
+                Frame::Redis(RedisFrame::Error(message.into()))
+            }
+            #[cfg(feature = "cassandra")]
+            Metadata::Cassandra(meta) => {
+                use cassandra_protocol::frame::message_error::ErrorType;
+                // Cassandra's unavailable and timeout errors describe the consistency level of the failed request,
+                // which shotover does not know, so the closest errors without those details are used instead.
+                let ty = match kind {
+                    ErrorKind::Overloaded | ErrorKind::Unavailable => ErrorType::Overloaded,
+                    ErrorKind::Unauthorized => ErrorType::Unauthorized,
+                    ErrorKind::InvalidRequest => ErrorType::Invalid,
+                    ErrorKind::Timeout | ErrorKind::Server => ErrorType::Server,
+                };
+                Frame::Cassandra(meta.to_error_response_of_type(error, ty))
+            }
+            #[cfg(feature = "kafka")]
+            Metadata::Kafka => match self.frame() {
+                Some(Frame::Kafka(frame)) => Frame::Kafka(frame.to_error_response(kind, &error)?),
+                _ => return Err(anyhow!("Failed to parse kafka request when producing an error")),
+            },
+            #[cfg(feature = "opensearch")]
+            Metadata::OpenSearch => {
+                return Err(anyhow!(error).context(
+                    "An error response cannot be formed because opensearch errors are not yet supported",
+                ))
+            }
+            #[cfg(feature = "memcached")]
+            Metadata::Memcached => Frame::Memcached(MemcachedFrame::Response(match kind {
+                ErrorKind::InvalidRequest => MemcachedResponse::ClientError(error),
+                _ => MemcachedResponse::ServerError(error),
+            })),
+        })
+    }
+
     /// Get metadata for this `Message`
     pub fn metadata(&self) -> Result<Metadata> {
+        if let Some(StreamChunk::Continuation | StreamChunk::End) = self.stream_chunk {
+            return Err(anyhow!(
+                "Only the first chunk of a streamed response has metadata"
+            ));
+        }
         match self.inner.as_ref().unwrap() {
             MessageInner::RawBytes {
                 #[cfg(feature = "cassandra")]
@@ -431,6 +765,8 @@ impl Message {
                 MessageType::Dummy => Err(anyhow!("Dummy has no metadata")),
                 #[cfg(feature = "opensearch")]
                 MessageType::OpenSearch => Err(anyhow!("OpenSearch has no metadata")),
+                #[cfg(feature = "memcached")]
+                MessageType::Memcached => Ok(Metadata::Memcached),
             },
             MessageInner::Parsed { frame, .. } | MessageInner::Modified { frame } => match frame {
                 #[cfg(feature = "cassandra")]
@@ -442,6 +778,8 @@ impl Message {
                 Frame::Dummy => Err(anyhow!("dummy has no metadata")),
                 #[cfg(feature = "opensearch")]
                 Frame::OpenSearch(_) => Err(anyhow!("OpenSearch has no metadata")),
+                #[cfg(feature = "memcached")]
+                Frame::Memcached(_) => Ok(Metadata::Memcached),
             },
         }
     }
@@ -473,6 +811,12 @@ impl Message {
             },
             #[cfg(feature = "opensearch")]
             MessageType::OpenSearch => false,
+            // memcached does not respond to requests sent with noreply
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached => match self.frame() {
+                Some(Frame::Memcached(MemcachedFrame::Request(request))) => request.noreply(),
+                _ => false,
+            },
             MessageType::Dummy => true,
         }
     }
@@ -487,24 +831,28 @@ impl Message {
     }
 
     /// Set this `Message` to a backpressure response
+    #[allow(unreachable_patterns)]
     pub fn to_backpressure(&mut self) -> Result<Message> {
         let metadata = self.metadata()?;
 
-        Ok(Message::from_frame_at_instant(
+        let mut response = Message::from_frame_at_instant(
             match metadata {
                 #[cfg(feature = "cassandra")]
                 Metadata::Cassandra(metadata) => Frame::Cassandra(metadata.backpressure_response()),
                 #[cfg(feature = "redis")]
-                Metadata::Redis => unimplemented!(),
-                #[cfg(feature = "kafka")]
-                Metadata::Kafka => unimplemented!(),
-                #[cfg(feature = "opensearch")]
-                Metadata::OpenSearch => unimplemented!(),
+                Metadata::Redis => Frame::Redis(RedisFrame::Error(
+                    "BUSY shotover is overloaded, try again later".into(),
+                )),
+                _ if self.response_is_dummy() => Frame::Dummy,
+                _ => self.error_frame(
+                    "shotover is overloaded, try again later".to_owned(),
+                    ErrorKind::Overloaded,
+                )?,
             },
-            // reachable with feature = cassandra
-            #[allow(unreachable_code)]
             self.received_from_source_or_sink_at,
-        ))
+        );
+        response.set_request_id(self.id());
+        Ok(response)
     }
 
     // Retrieves the stream_id without parsing the rest of the frame.
@@ -539,6 +887,8 @@ impl Message {
                     Frame::Dummy => None,
                     #[cfg(feature = "opensearch")]
                     Frame::OpenSearch(_) => None,
+                    #[cfg(feature = "memcached")]
+                    Frame::Memcached(_) => None,
                 }
             }
             None => None,
@@ -546,7 +896,12 @@ impl Message {
     }
 
     pub fn to_high_level_string(&mut self) -> String {
-        if let Some(response) = self.frame() {
+        if let Some(stream_chunk) = self.stream_chunk {
+            format!(
+                "{stream_chunk:?} chunk of a streamed response of {} bytes",
+                self.received_size().unwrap_or_default()
+            )
+        } else if let Some(response) = self.frame() {
             format!("{}", response)
         } else if let Some(MessageInner::RawBytes {
             bytes,
@@ -628,3 +983,33 @@ pub enum QueryType {
     SchemaChange,
     PubSubMessage,
 }
+
+/// A protocol independent classification of what a request does to the data in the DB.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub enum OperationType {
+    Read,
+    Write,
+    /// Changes the schema, e.g. creating a table or a topic
+    Ddl,
+    /// The request cannot be classified, e.g. it is a response or its effect depends on state unknown to shotover
+    Unknown,
+}
+
+/// A protocol independent classification of an error generated by shotover.
+/// [`Message::error_response`] reports it as the closest equivalent error of the message's protocol.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+    /// Shotover or the DB is overloaded or the client exceeded a rate limit, the client should back off and retry
+    Overloaded,
+    /// The client is not permitted to make the request
+    Unauthorized,
+    /// The request is malformed or was rejected by a policy of shotover
+    InvalidRequest,
+    /// The DB cannot currently serve the request
+    Unavailable,
+    /// The request did not complete in time
+    Timeout,
+    /// Any other error
+    Server,
+}
@@ -1,7 +1,14 @@
+use crate::capture::{self, CaptureConfig};
+use crate::client_connections;
+use crate::health;
 use crate::http::HttpServerError;
 use crate::runner::ReloadHandle;
+use crate::transforms::hot_keys;
+use crate::transforms::tee::schedule as tee_schedule;
 use anyhow::{anyhow, Context, Result};
-use axum::{extract::State, response::Html, Router};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::{response::Html, Router};
 use metrics_exporter_prometheus::PrometheusHandle;
 use std::str;
 use std::{net::SocketAddr, sync::Arc};
@@ -12,6 +19,7 @@ pub(crate) struct LogFilterHttpExporter {
     recorder_handle: PrometheusHandle,
     address: SocketAddr,
     tracing_handle: ReloadHandle,
+    capture_enabled: bool,
 }
 
 impl LogFilterHttpExporter {
@@ -22,11 +30,13 @@ impl LogFilterHttpExporter {
         recorder_handle: PrometheusHandle,
         address: SocketAddr,
         tracing_handle: ReloadHandle,
+        capture_enabled: bool,
     ) -> Self {
         LogFilterHttpExporter {
             recorder_handle,
             address,
             tracing_handle,
+            capture_enabled,
         }
     }
 
@@ -42,12 +52,25 @@ impl LogFilterHttpExporter {
         let state = AppState {
             recorder_handle: Arc::new(self.recorder_handle),
             tracing_handle: Arc::new(self.tracing_handle),
+            capture_enabled: self.capture_enabled,
         };
 
         let app = Router::new()
             .route("/", axum::routing::get(root))
             .route("/metrics", axum::routing::get(serve_metrics))
             .route("/filter", axum::routing::put(put_filter))
+            .route("/ready", axum::routing::get(ready))
+            .route("/hot_keys", axum::routing::get(serve_hot_keys))
+            .route(
+                "/capture",
+                axum::routing::get(serve_capture)
+                    .put(put_capture)
+                    .delete(delete_capture),
+            )
+            .route("/connections", axum::routing::get(serve_connections))
+            .route("/connections/:id", axum::routing::delete(delete_connection))
+            .route("/tee", axum::routing::get(serve_tee_schedules))
+            .route("/tee/:chain", axum::routing::put(put_tee_mode))
             .with_state(state);
 
         let address = self.address;
@@ -59,7 +82,97 @@ impl LogFilterHttpExporter {
 }
 
 async fn root() -> Html<&'static str> {
-    Html("try /filter or /metrics")
+    Html("try /filter, /metrics, /ready, /hot_keys, /capture, /connections or /tee")
+}
+
+/// Responds with 503 when any sink with health checks configured has no healthy upstream nodes.
+async fn ready() -> (StatusCode, String) {
+    let unready = health::unready_groups();
+    if unready.is_empty() {
+        (StatusCode::OK, "ready".to_owned())
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, unready.join("\n"))
+    }
+}
+
+/// Lists the hottest keys found by every `HotKeys` transform.
+async fn serve_hot_keys() -> String {
+    hot_keys::report()
+}
+
+/// Starts a debug capture configured by the JSON request body, replacing any running capture.
+/// Responds with 403 unless `capture_enabled` is set in the configuration.
+async fn put_capture(
+    State(state): State<AppState>,
+    body: String,
+) -> Result<(StatusCode, String), HttpServerError> {
+    if !state.capture_enabled {
+        return Ok((
+            StatusCode::FORBIDDEN,
+            "Debug captures are disabled, set capture_enabled in the configuration to allow them"
+                .to_owned(),
+        ));
+    }
+    let config: CaptureConfig = serde_json::from_str(&body)?;
+    trace!("starting capture: {config:?}");
+    capture::start(config)?;
+    tracing::info!("capture started: {body}");
+    Ok((StatusCode::OK, "Capture started".to_owned()))
+}
+
+/// Responds with the records of the running debug capture as JSON.
+async fn serve_capture() -> Result<(StatusCode, String), HttpServerError> {
+    match capture::report() {
+        Some(report) => Ok((StatusCode::OK, serde_json::to_string(&report)?)),
+        None => Ok((StatusCode::NOT_FOUND, "No capture is running".to_owned())),
+    }
+}
+
+/// Stops the running debug capture, responding with its records as JSON.
+async fn delete_capture() -> Result<(StatusCode, String), HttpServerError> {
+    match capture::stop() {
+        Some(report) => {
+            tracing::info!("capture stopped");
+            Ok((StatusCode::OK, serde_json::to_string(&report)?))
+        }
+        None => Ok((StatusCode::NOT_FOUND, "No capture is running".to_owned())),
+    }
+}
+
+/// Lists the active client connections of every source as JSON.
+async fn serve_connections() -> Result<String, HttpServerError> {
+    Ok(serde_json::to_string(&client_connections::report())?)
+}
+
+/// Closes the client connection with the given id.
+async fn delete_connection(Path(id): Path<u64>) -> (StatusCode, String) {
+    if client_connections::terminate(id) {
+        tracing::info!("connection {id} terminated");
+        (StatusCode::OK, format!("Connection {id} terminated"))
+    } else {
+        (StatusCode::NOT_FOUND, format!("No connection with id {id}"))
+    }
+}
+
+/// Lists the mode of every `Tee` transform with a schedule.
+async fn serve_tee_schedules() -> String {
+    tee_schedule::report()
+}
+
+/// Sets the mode of the scheduled `Tee` transforms in the given chain to `scheduled`, `on` or `off`.
+async fn put_tee_mode(
+    Path(chain): Path<String>,
+    mode: String,
+) -> Result<(StatusCode, String), HttpServerError> {
+    if tee_schedule::set_mode(&chain, &mode)? {
+        tracing::info!("tee mode of chain {chain} set to {}", mode.trim());
+        Ok((StatusCode::OK, "Tee mode set".to_owned()))
+    } else {
+        Ok((
+            StatusCode::NOT_FOUND,
+            format!("No Tee with a schedule in chain {chain}"),
+        ))
+    }
 }
 
 async fn serve_metrics(State(state): State<AppState>) -> Html<String> {
@@ -81,4 +194,5 @@ async fn put_filter(
 struct AppState {
     tracing_handle: Arc<ReloadHandle>,
     recorder_handle: Arc<PrometheusHandle>,
+    capture_enabled: bool,
 }
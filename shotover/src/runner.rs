@@ -1,17 +1,24 @@
-//! Tools for initializing shotover in the final binary.
+//! Tools for initializing shotover in the final binary or running it embedded within another application.
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+use crate::bench::{self, BenchOpts};
 use crate::config::topology::Topology;
 use crate::config::Config;
 use crate::observability::LogFilterHttpExporter;
+#[cfg(any(feature = "redis", feature = "memcached"))]
+use crate::transforms::shard_router::{self, ShardRemapOpts};
 use anyhow::Context;
 use anyhow::{anyhow, Result};
 use clap::{crate_version, Parser};
+use core_affinity::CoreId;
+use futures::future::{join_all, try_join_all};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::env;
 use std::net::SocketAddr;
 use tokio::runtime::{self, Runtime};
 use tokio::signal::unix::{signal, SignalKind};
-use tokio::sync::watch;
-use tracing::{error, info};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
 use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
 use tracing_subscriber::filter::Directive;
 use tracing_subscriber::fmt::format::DefaultFields;
@@ -33,17 +40,57 @@ struct ConfigOpts {
     #[clap(short, long, default_value = "config/config.yaml")]
     pub config_file: String,
 
-    // Number of tokio worker threads.
+    // Number of tokio worker threads, or with `--runtime per-core` the number of cores to run a runtime on.
     // By default uses the number of cores on the system.
     #[clap(long)]
     pub core_threads: Option<usize>,
 
+    #[arg(long, value_enum, default_value = "multi-threaded")]
+    pub runtime: RuntimeMode,
+
+    #[arg(long, value_enum, default_value = "epoll")]
+    pub io_backend: IoBackend,
+
     // 2,097,152 = 2 * 1024 * 1024 (2MiB)
     #[clap(long, default_value = "2097152")]
     pub stack_size: usize,
 
     #[arg(long, value_enum, default_value = "human")]
     pub log_format: LogFormat,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Clone)]
+enum Command {
+    /// Runs the topology and benchmarks it by sending synthetic load to one of its sources,
+    /// then reports the throughput and latency percentiles.
+    #[cfg(any(feature = "redis", feature = "cassandra"))]
+    Bench(BenchOpts),
+    /// Lists the keys that belong to a different shard after changing the shards of a ShardRouter transform,
+    /// so that they can be migrated before the change is deployed.
+    #[cfg(any(feature = "redis", feature = "memcached"))]
+    ShardRemap(ShardRemapOpts),
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum RuntimeMode {
+    /// A single multi-threaded runtime where the work for a connection may move between threads.
+    MultiThreaded,
+    /// A single threaded runtime pinned to each core, each running its own copy of the topology.
+    /// The kernel shards accepted connections across the runtimes via SO_REUSEPORT,
+    /// so a connection and everything processing it stays on a single core.
+    PerCore,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum IoBackend {
+    /// Waits for client connections to become readable or writable with epoll before reading or writing them.
+    Epoll,
+    /// Reads and writes client connections with io_uring, requires `--runtime per-core`.
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    IoUring,
 }
 
 #[derive(clap::ValueEnum, Clone, Copy)]
@@ -58,8 +105,11 @@ impl Default for ConfigOpts {
             topology_file: "config/topology.yaml".into(),
             config_file: "config/config.yaml".into(),
             core_threads: None,
+            runtime: RuntimeMode::MultiThreaded,
+            io_backend: IoBackend::Epoll,
             stack_size: 2097152,
             log_format: LogFormat::Human,
+            command: None,
         }
     }
 }
@@ -69,6 +119,17 @@ pub struct Shotover {
     topology: Topology,
     config: Config,
     tracing: TracingState,
+    command: Option<Command>,
+    /// Set when running the topology with [`RuntimeMode::PerCore`]
+    per_core: Option<PerCoreRuntimes>,
+}
+
+struct PerCoreRuntimes {
+    /// Each runtime parses its own copy of the topology as it cannot be sent between threads
+    topology_file: String,
+    cores: Vec<CoreId>,
+    stack_size: usize,
+    io_backend: IoBackend,
 }
 
 impl Shotover {
@@ -107,7 +168,38 @@ impl Shotover {
         let config = Config::from_file(params.config_file)?;
         let topology = Topology::from_file(&params.topology_file)?;
         let tracing = TracingState::new(config.main_log_level.as_str(), params.log_format)?;
-        let runtime = Shotover::create_runtime(params.stack_size, params.core_threads);
+        crate::listen_fds::inherit()?;
+        if let (Some(upgrade_socket), None) = (&config.upgrade_socket, &params.command) {
+            crate::upgrade::take_over(upgrade_socket)?;
+        }
+        let per_core = match (&params.command, params.runtime) {
+            (None, RuntimeMode::PerCore) => {
+                let mut cores = core_affinity::get_core_ids()
+                    .ok_or_else(|| anyhow!("Failed to list the cores available to shotover"))?;
+                if let Some(core_threads) = params.core_threads {
+                    cores.truncate(core_threads);
+                }
+                Some(PerCoreRuntimes {
+                    topology_file: params.topology_file.clone(),
+                    cores,
+                    stack_size: params.stack_size,
+                    io_backend: params.io_backend,
+                })
+            }
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            (None, RuntimeMode::MultiThreaded)
+                if matches!(params.io_backend, IoBackend::IoUring) =>
+            {
+                return Err(anyhow!("--io-backend io-uring requires --runtime per-core"));
+            }
+            _ => None,
+        };
+        // The per core runtimes handle all connections, leaving only signals and the observability interface to the main runtime
+        let worker_threads = match per_core {
+            Some(_) => Some(1),
+            None => params.core_threads,
+        };
+        let runtime = Shotover::create_runtime(params.stack_size, worker_threads);
 
         Shotover::start_observability_interface(&runtime, &config, &tracing)?;
 
@@ -116,6 +208,8 @@ impl Shotover {
             topology,
             config,
             tracing,
+            command: params.command,
+            per_core,
         })
     }
 
@@ -133,7 +227,12 @@ impl Shotover {
             metrics::set_global_recorder(recorder)?;
 
             let socket: SocketAddr = observability_interface.parse()?;
-            let exporter = LogFilterHttpExporter::new(handle, socket, tracing.handle.clone());
+            let exporter = LogFilterHttpExporter::new(
+                handle,
+                socket,
+                tracing.handle.clone(),
+                config.capture_enabled,
+            );
 
             runtime.spawn(exporter.async_run());
         }
@@ -161,15 +260,35 @@ impl Shotover {
                 _ = terminate.recv() => {
                     info!("received SIGTERM");
                 },
+                _ = crate::upgrade::handed_off() => {
+                    info!("handed off listening sockets to the upgraded shotover process");
+                },
             };
 
             trigger_shutdown_tx.send(true).unwrap();
         });
 
-        let code = match self
-            .runtime
-            .block_on(run(self.topology, self.config, trigger_shutdown_rx))
-        {
+        let result = match self.command {
+            #[cfg(any(feature = "redis", feature = "cassandra"))]
+            Some(Command::Bench(opts)) => {
+                self.runtime
+                    .block_on(bench::run(self.topology, opts, trigger_shutdown_rx))
+            }
+            #[cfg(any(feature = "redis", feature = "memcached"))]
+            Some(Command::ShardRemap(opts)) => shard_router::remap(opts),
+            None => match self.per_core {
+                Some(per_core) => self.runtime.block_on(run_per_core(
+                    per_core,
+                    self.topology,
+                    self.config,
+                    trigger_shutdown_rx,
+                )),
+                None => self
+                    .runtime
+                    .block_on(run(self.topology, self.config, trigger_shutdown_rx)),
+            },
+        };
+        let code = match result {
             Ok(()) => {
                 info!("Shotover was shutdown cleanly.");
                 0
@@ -198,6 +317,63 @@ impl Shotover {
     }
 }
 
+/// Runs a topology within an application that embeds shotover as a library.
+///
+/// Unlike [`Shotover`], no command line arguments or config file are read and no tokio runtime, tracing subscriber,
+/// signal handler or observability interface is created, these are left to the embedding application.
+///
+/// ```no_run
+/// # #[cfg(feature = "redis")]
+/// # async fn example() -> anyhow::Result<()> {
+/// use shotover::config::chain::TransformChainConfig;
+/// use shotover::config::topology::Topology;
+/// use shotover::runner::EmbeddedShotover;
+/// use shotover::sources::redis::RedisConfig;
+/// use shotover::sources::SourceConfig;
+/// use shotover::transforms::null::NullSinkConfig;
+///
+/// let topology = Topology {
+///     sources: vec![SourceConfig::Redis(RedisConfig {
+///         name: "redis".to_owned(),
+///         listen_addr: "127.0.0.1:6379".to_owned(),
+///         chain: TransformChainConfig::default().with_transform(NullSinkConfig),
+///         ..Default::default()
+///     })],
+///     ..Default::default()
+/// };
+/// let shotover = EmbeddedShotover::start(topology).await?;
+/// // ...
+/// shotover.shutdown().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct EmbeddedShotover {
+    trigger_shutdown_tx: watch::Sender<bool>,
+    sources: Vec<JoinHandle<()>>,
+}
+
+impl EmbeddedShotover {
+    /// Starts every source of the topology, returning once they are all accepting connections.
+    /// Must be called from within a tokio runtime.
+    ///
+    /// Returns an error describing every invalid source or chain if the topology is invalid, in which case no sources are left running.
+    pub async fn start(topology: Topology) -> Result<Self> {
+        let (trigger_shutdown_tx, trigger_shutdown_rx) = watch::channel(false);
+        let sources = topology.run_chains(trigger_shutdown_rx).await?;
+        Ok(EmbeddedShotover {
+            trigger_shutdown_tx,
+            sources: sources.into_iter().map(|x| x.into_join_handle()).collect(),
+        })
+    }
+
+    /// Stops accepting connections and closes every open connection,
+    /// returning once every source and connection has shut down.
+    pub async fn shutdown(self) {
+        self.trigger_shutdown_tx.send(true).ok();
+        join_all(self.sources).await;
+    }
+}
+
 struct TracingState {
     /// Once this is dropped tracing logs are ignored
     _guard: WorkerGuard,
@@ -304,18 +480,175 @@ async fn run(
 
     match topology.run_chains(trigger_shutdown_rx).await {
         Ok(sources) => {
-            futures::future::join_all(sources.into_iter().map(|x| x.into_join_handle())).await;
+            if let Some(upgrade_socket) = &config.upgrade_socket {
+                crate::upgrade::listen(upgrade_socket)?;
+            }
+            join_all(sources.into_iter().map(|x| x.into_join_handle())).await;
             Ok(())
         }
         Err(err) => Err(err),
     }
 }
 
+async fn run_per_core(
+    per_core: PerCoreRuntimes,
+    topology: Topology,
+    config: Config,
+    trigger_shutdown_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    info!("Starting Shotover {}", crate_version!());
+    info!(configuration = ?config);
+    info!(topology = ?topology);
+    info!(
+        "Sharding connections across runtimes pinned to {} cores",
+        per_core.cores.len()
+    );
+
+    // Every runtime binds the same addresses
+    crate::server::enable_reuse_port();
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if let IoBackend::IoUring = per_core.io_backend {
+        info!("Client connections will be read and written with io_uring");
+        crate::uring::enable();
+    }
+
+    let shard_count = per_core.cores.len();
+    let (started_tx, mut started_rx) = mpsc::unbounded_channel();
+    let mut shards = vec![];
+    for (i, core) in per_core.cores.into_iter().enumerate() {
+        let (result_tx, result_rx) = oneshot::channel();
+        let topology_file = per_core.topology_file.clone();
+        let trigger_shutdown_rx = trigger_shutdown_rx.clone();
+        let io_backend = per_core.io_backend;
+        let started_tx = started_tx.clone();
+        std::thread::Builder::new()
+            .name(format!("shotover-core-{}", core.id))
+            .stack_size(per_core.stack_size)
+            .spawn(move || {
+                result_tx
+                    .send(run_shard(
+                        i,
+                        core,
+                        &topology_file,
+                        io_backend,
+                        trigger_shutdown_rx,
+                        started_tx,
+                    ))
+                    .ok();
+            })?;
+        shards.push(async move {
+            result_rx
+                .await
+                .map_err(|_| anyhow!("The runtime for core {} panicked", core.id))?
+        });
+    }
+
+    // Only take over from a previous shotover process once every runtime is accepting connections
+    if let Some(upgrade_socket) = config.upgrade_socket {
+        tokio::spawn(async move {
+            for _ in 0..shard_count {
+                if started_rx.recv().await.is_none() {
+                    return;
+                }
+            }
+            if let Err(err) = crate::upgrade::listen(&upgrade_socket) {
+                error!("{:?}", err.context("Failed to listen for upgrades"));
+            }
+        });
+    }
+
+    // Return as soon as any runtime fails so that shotover exits instead of running with fewer cores
+    try_join_all(shards).await?;
+    Ok(())
+}
+
+/// Runs a copy of the topology on a single threaded runtime pinned to `core` until shotover shuts down.
+fn run_shard(
+    shard: usize,
+    core: CoreId,
+    topology_file: &str,
+    io_backend: IoBackend,
+    trigger_shutdown_rx: watch::Receiver<bool>,
+    started_tx: mpsc::UnboundedSender<()>,
+) -> Result<()> {
+    if !core_affinity::set_for_current(core) {
+        warn!(
+            "Failed to pin runtime to core {}, it will be scheduled on any core",
+            core.id
+        );
+    }
+
+    let mut topology = Topology::from_file(topology_file)?;
+    // Events are published to a process wide bus so one copy of the notifiers delivers the events of every runtime
+    if shard != 0 {
+        topology.notifiers.clear();
+    }
+
+    let run = async {
+        let sources = topology.run_chains(trigger_shutdown_rx).await?;
+        started_tx.send(()).ok();
+        join_all(sources.into_iter().map(|x| x.into_join_handle())).await;
+        Ok(())
+    };
+    match io_backend {
+        IoBackend::Epoll => runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(run),
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        IoBackend::IoUring => crate::uring::runtime()?.block_on(run),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_embedded_start_shutdown() {
+        use crate::config::chain::TransformChainConfig;
+        use crate::sources::redis::RedisConfig;
+        use crate::sources::SourceConfig;
+        use crate::transforms::null::NullSinkConfig;
+
+        let topology = Topology {
+            sources: vec![SourceConfig::Redis(RedisConfig {
+                name: "redis".to_owned(),
+                listen_addr: "127.0.0.1:0".to_owned(),
+                chain: TransformChainConfig::default().with_transform(NullSinkConfig),
+                ..Default::default()
+            })],
+            ..Default::default()
+        };
+        let shotover = EmbeddedShotover::start(topology).await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(10), shotover.shutdown())
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_embedded_invalid_topology() {
+        use crate::sources::redis::RedisConfig;
+        use crate::sources::SourceConfig;
+
+        let topology = Topology {
+            sources: vec![SourceConfig::Redis(RedisConfig {
+                name: "redis".to_owned(),
+                listen_addr: "127.0.0.1:0".to_owned(),
+                ..Default::default()
+            })],
+            ..Default::default()
+        };
+        let error = EmbeddedShotover::start(topology).await.err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Topology errors\nredis source:\n  redis chain:\n    Chain cannot be empty\n"
+        );
+    }
+
     #[test]
     fn test_try_parse_log_directives() {
         assert_eq!(
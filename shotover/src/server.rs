@@ -1,21 +1,33 @@
+use crate::client_connections::{self, ConnectionRegistration};
 use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError};
 use crate::config::chain::TransformChainConfig;
 use crate::frame::MessageType;
+use crate::listen_fds;
 use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::sources::chain_error_policy::{
+    ChainErrorAction, ChainErrorPolicy, ChainErrorPolicyConfig,
+};
+use crate::sources::client_throttle::{ClientThrottle, ClientThrottleConfig, Verdict};
+use crate::sources::ip_filter::{IpFilter, IpFilterConfig};
+use crate::sources::latency_objective::{LatencyObjective, LatencyObjectiveConfig, LatencyTracker};
 use crate::sources::Transport;
-use crate::tls::{AcceptError, TlsAcceptor};
+use crate::tcp::TcpConfig;
+use crate::tls::{peer_common_name, session_details, AcceptError, TlsAcceptor};
 use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::session::{ClientConnectionId, SessionState, TlsClientIdentity};
 use crate::transforms::{ChainState, TransformContextBuilder, TransformContextConfig};
+use crate::upgrade;
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use futures::future::join_all;
 use futures::{SinkExt, StreamExt};
 use metrics::{counter, gauge, Counter, Gauge};
 use std::io::ErrorKind;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{lookup_host, TcpListener, TcpSocket, TcpStream};
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{mpsc, watch, Notify, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
@@ -27,15 +39,16 @@ use tokio_tungstenite::tungstenite::{
 };
 use tokio_util::codec::{Decoder, Encoder, FramedRead, FramedWrite};
 use tracing::Instrument;
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
 
 pub struct TcpCodecListener<C: CodecBuilder> {
-    chain_builder: TransformChainBuilder,
+    chain_builder: Arc<TransformChainBuilder>,
     source_name: String,
 
     /// TCP listener supplied by the `run` caller.
     listener: Option<TcpListener>,
-    listen_addr: String,
+    /// `None` when connections are accepted elsewhere and handed over through [`TcpCodecListener::handle_connection`].
+    listen_addr: Option<String>,
     hard_connection_limit: bool,
 
     codec: C,
@@ -74,6 +87,21 @@ pub struct TcpCodecListener<C: CodecBuilder> {
     connection_handles: Vec<JoinHandle<()>>,
 
     transport: Transport,
+
+    /// Connections from addresses not permitted by the filter are closed as soon as they are accepted.
+    ip_filter: Option<IpFilter>,
+
+    /// Clients opening connections or sending requests too quickly are banned or tarpitted.
+    client_throttle: Option<ClientThrottle>,
+
+    /// Applied to every accepted connection.
+    tcp: TcpConfig,
+
+    /// Decides what happens to a connection when its chain returns an error.
+    chain_error_policy: Arc<ChainErrorPolicy>,
+
+    /// The latency of every request of every connection is measured against this objective.
+    latency_objective: Option<Arc<LatencyObjective>>,
 }
 
 impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
@@ -81,7 +109,7 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
     pub async fn new(
         chain_config: &TransformChainConfig,
         source_name: String,
-        listen_addr: String,
+        listen_addr: Option<String>,
         hard_connection_limit: bool,
         codec: C,
         limit_connections: Arc<Semaphore>,
@@ -89,6 +117,11 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
         tls: Option<TlsAcceptor>,
         timeout: Option<Duration>,
         transport: Transport,
+        ip_filter: Option<&IpFilterConfig>,
+        client_throttle: Option<&ClientThrottleConfig>,
+        tcp: Option<&TcpConfig>,
+        chain_error_policy: Option<&ChainErrorPolicyConfig>,
+        latency_objective: Option<&LatencyObjectiveConfig>,
     ) -> Result<Self, Vec<String>> {
         let available_connections_gauge =
             gauge!("shotover_available_connections_count", "source" => source_name.clone());
@@ -110,21 +143,84 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
             .map(|x| format!("  {x}"))
             .collect::<Vec<String>>();
 
-        let listener = match create_listener(&listen_addr).await {
-            Ok(listener) => Some(listener),
-            Err(error) => {
-                errors.push(format!("{error:?}"));
+        let ip_filter = match ip_filter
+            .map(|x| IpFilter::new(x, &source_name))
+            .transpose()
+        {
+            Ok(ip_filter) => ip_filter,
+            Err(ip_filter_errors) => {
+                errors.extend(ip_filter_errors.iter().map(|x| format!("  {x}")));
+                None
+            }
+        };
+
+        let client_throttle = match client_throttle
+            .map(|x| ClientThrottle::new(x, &source_name))
+            .transpose()
+        {
+            Ok(client_throttle) => client_throttle,
+            Err(client_throttle_errors) => {
+                errors.extend(client_throttle_errors.iter().map(|x| format!("  {x}")));
                 None
             }
         };
 
+        let chain_error_policy = match ChainErrorPolicy::new(
+            chain_error_policy,
+            &source_name,
+            codec.protocol(),
+        )
+        .await
+        {
+            Ok(chain_error_policy) => {
+                if matches!(chain_error_policy.action, ChainErrorAction::ErrorResponse)
+                    && matches!(
+                        PendingRequests::new(codec.protocol()),
+                        PendingRequests::Unsupported
+                    )
+                {
+                    errors.push(format!(
+                            "  chain_error_policy: ErrorResponse is not supported by the {:?} protocol as it has no error responses",
+                            codec.protocol()
+                        ));
+                }
+                Some(chain_error_policy)
+            }
+            Err(chain_error_policy_errors) => {
+                errors.extend(chain_error_policy_errors.iter().map(|x| format!("  {x}")));
+                None
+            }
+        };
+
+        let latency_objective = match latency_objective
+            .map(|x| LatencyObjective::new(x, &source_name))
+            .transpose()
+        {
+            Ok(latency_objective) => latency_objective.map(Arc::new),
+            Err(latency_objective_error) => {
+                errors.push(format!("  {latency_objective_error}"));
+                None
+            }
+        };
+
+        let listener = match &listen_addr {
+            Some(listen_addr) => match create_listener(&source_name, listen_addr).await {
+                Ok(listener) => Some(listener),
+                Err(error) => {
+                    errors.push(format!("{error:?}"));
+                    None
+                }
+            },
+            None => None,
+        };
+
         if !errors.is_empty() {
             errors.insert(0, format!("{source_name} source:"));
             return Err(errors);
         }
 
         Ok(TcpCodecListener {
-            chain_builder,
+            chain_builder: Arc::new(chain_builder),
             source_name,
             listener,
             listen_addr,
@@ -139,6 +235,15 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
             timeout,
             connection_handles: vec![],
             transport,
+            ip_filter,
+            client_throttle,
+            tcp: TcpConfig {
+                // Nagle's algorithm only adds latency to the request/response traffic of a source
+                nodelay: Some(tcp.and_then(|x| x.nodelay).unwrap_or(true)),
+                ..tcp.cloned().unwrap_or_default()
+            },
+            chain_error_policy: Arc::new(chain_error_policy.unwrap()),
+            latency_objective,
         })
     }
 
@@ -165,6 +270,7 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                     Ok(p) => p,
                     Err(_e) => {
                         //close the socket too full!
+                        // An inherited or kept socket stays open in `listen_fds`, in which case new connections queue in the kernel's backlog instead
                         self.listener = None;
                         tokio::time::sleep(Duration::from_secs(1)).await;
                         continue;
@@ -174,72 +280,120 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
                 self.limit_connections.clone().acquire_owned().await?
             };
             if self.listener.is_none() {
-                self.listener = Some(create_listener(&self.listen_addr).await?);
-            }
-
-            self.connection_count = self.connection_count.wrapping_add(1);
-            let span =
-                crate::connection_span::span(self.connection_count, self.source_name.as_str());
-            let transport = self.transport;
-            async {
-                // Accept a new socket. This will attempt to perform error handling.
-                // The `accept` method internally attempts to recover errors, so an
-                // error here is non-recoverable.
-                let stream = self.accept().await?;
-
-                debug!("got socket");
-                self.available_connections_gauge
-                    .set(self.limit_connections.available_permits() as f64);
-                self.connections_opened.increment(1);
-
-                let client_details = stream
-                    .peer_addr()
-                    .map(|p| p.ip().to_string())
-                    .unwrap_or_else(|_| "Unknown peer".to_string());
-                tracing::debug!("New connection from {}", client_details);
-
-                let force_run_chain = Arc::new(Notify::new());
-                let context = TransformContextBuilder {
-                    force_run_chain: force_run_chain.clone(),
-                    client_details: client_details.clone(),
+                let Some(listen_addr) = &self.listen_addr else {
+                    return Err(anyhow!(
+                        "{} source has no listen address to accept connections on",
+                        self.source_name
+                    ));
                 };
+                self.listener = Some(create_listener(&self.source_name, listen_addr).await?);
+            }
 
-                let handler = Handler {
-                    chain: self.chain_builder.build(context),
-                    codec: self.codec.clone(),
-                    shutdown: Shutdown::new(self.trigger_shutdown_rx.clone()),
-                    tls: self.tls.clone(),
-                    pending_requests: PendingRequests::new(self.codec.protocol()),
-                    timeout: self.timeout,
-                    _permit: permit,
-                };
+            // Accept a new socket. This will attempt to perform error handling.
+            // The `accept` method internally attempts to recover errors, so an
+            // error here is non-recoverable.
+            let stream = self.accept().await?;
+            self.handle_connection(stream, permit);
+        }
+    }
 
-                // Spawn a new task to process the connections.
-                self.connection_handles.push(tokio::spawn(
-                    async move {
-                        // Process the connection. If an error is encountered, log it.
-                        if let Err(err) = handler
-                            .run(stream, transport, force_run_chain, client_details)
-                            .await
-                        {
-                            error!(
-                                "{:?}",
-                                err.context("connection was unexpectedly terminated")
-                            );
-                        }
+    /// Process a connection that has already been accepted, either by [`TcpCodecListener::run`] or by another source that handed it over.
+    /// The connection holds onto `permit` until it is closed.
+    pub(crate) fn handle_connection(&mut self, stream: TcpStream, permit: OwnedSemaphorePermit) {
+        self.connection_count = self.connection_count.wrapping_add(1);
+        let span = crate::connection_span::span(self.connection_count, self.source_name.as_str());
+        let transport = self.transport;
+        span.in_scope(|| {
+            if let (Some(ip_filter), Ok(peer)) = (&self.ip_filter, stream.peer_addr()) {
+                if !ip_filter.check(peer) {
+                    // Dropping the stream closes the connection and dropping the permit makes it available to the next connection
+                    return;
+                }
+            }
+
+            let mut tarpit = None;
+            if let (Some(client_throttle), Ok(peer)) = (&self.client_throttle, stream.peer_addr()) {
+                match client_throttle.on_connection(peer.ip()) {
+                    Verdict::Allow => {}
+                    Verdict::Delay(delay) => tarpit = Some(delay),
+                    Verdict::Reject => return,
+                }
+            }
+
+            debug!("got socket");
+            self.available_connections_gauge
+                .set(self.limit_connections.available_permits() as f64);
+            self.connections_opened.increment(1);
+
+            let client_details = stream
+                .peer_addr()
+                .map(|p| p.ip().to_string())
+                .unwrap_or_else(|_| "Unknown peer".to_string());
+            tracing::debug!("New connection from {}", client_details);
+
+            let force_run_chain = Arc::new(Notify::new());
+            let context = TransformContextBuilder {
+                force_run_chain: force_run_chain.clone(),
+                client_details: client_details.clone(),
+                // A websocket message must contain whole messages, so they cannot be split into chunks
+                stream_responses: matches!(transport, Transport::Tcp),
+                #[cfg(feature = "cassandra")]
+                schema_cache: None,
+            };
+
+            let connection =
+                client_connections::register(&self.source_name, stream.peer_addr().ok());
+            let mut session = SessionState::default();
+            session.insert(ClientConnectionId(connection.id()));
+
+            let handler = Handler {
+                chain: self.chain_builder.build(context.clone()),
+                chain_builder: self.chain_builder.clone(),
+                context,
+                chain_error_policy: self.chain_error_policy.clone(),
+                fallback_chain: None,
+                latency_tracker: self.latency_objective.clone().map(LatencyTracker::new),
+                codec: self.codec.clone(),
+                shutdown: Shutdown::new(self.trigger_shutdown_rx.clone()),
+                tls: self.tls.clone(),
+                tcp: self.tcp.clone(),
+                pending_requests: PendingRequests::new(self.codec.protocol()),
+                timeout: self.timeout,
+                session,
+                client_throttle: self
+                    .client_throttle
+                    .clone()
+                    .zip(stream.peer_addr().ok().map(|peer| peer.ip())),
+                connection,
+                _permit: permit,
+            };
+
+            // Spawn a new task to process the connections.
+            self.connection_handles.push(tokio::spawn(
+                async move {
+                    // A tarpitted connection keeps its permit while it waits, so it still counts towards the connection limit.
+                    if let Some(delay) = tarpit {
+                        tokio::time::sleep(delay).await;
+                    }
+                    // Process the connection. If an error is encountered, log it.
+                    if let Err(err) = handler
+                        .run(stream, transport, force_run_chain, client_details)
+                        .await
+                    {
+                        error!(
+                            "{:?}",
+                            err.context("connection was unexpectedly terminated")
+                        );
                     }
-                    .in_current_span(),
-                ));
-                // Only prune the list every so often
-                // theres no point in doing it every iteration because most likely none of the handles will have completed
-                if self.connection_count % 1000 == 0 {
-                    self.connection_handles.retain(|x| !x.is_finished());
                 }
-                Ok::<(), anyhow::Error>(())
+                .in_current_span(),
+            ));
+            // Only prune the list every so often
+            // theres no point in doing it every iteration because most likely none of the handles will have completed
+            if self.connection_count % 1000 == 0 {
+                self.connection_handles.retain(|x| !x.is_finished());
             }
-            .instrument(span)
-            .await?;
-        }
+        });
     }
 
     pub async fn shutdown(&mut self) {
@@ -279,17 +433,64 @@ impl<C: CodecBuilder + 'static> TcpCodecListener<C> {
     }
 }
 
-async fn create_listener(listen_addr: &str) -> Result<TcpListener> {
-    TcpListener::bind(listen_addr)
-        .await
-        .map_err(|e| anyhow!("{} address={}", e, listen_addr))
+/// Set when every runtime runs its own copy of the topology and so binds the same addresses.
+static REUSE_PORT: AtomicBool = AtomicBool::new(false);
+
+/// Sets SO_REUSEPORT on all listeners created from now on.
+/// This allows multiple listeners to bind the same address, with the kernel distributing accepted connections between them.
+pub(crate) fn enable_reuse_port() {
+    REUSE_PORT.store(true, Ordering::Relaxed);
+}
+
+pub(crate) async fn create_listener(source_name: &str, listen_addr: &str) -> Result<TcpListener> {
+    if let Some(listener) = listen_fds::take(source_name, listen_addr) {
+        return listener
+            .and_then(TcpListener::from_std)
+            .map_err(|e| anyhow!("{} inherited address={}", e, listen_addr));
+    }
+    let listener = if REUSE_PORT.load(Ordering::Relaxed) {
+        bind_reuse_port(listen_addr).await
+    } else {
+        TcpListener::bind(listen_addr).await
+    }
+    .map_err(|e| anyhow!("{} address={}", e, listen_addr))?;
+    if upgrade::is_enabled() {
+        listen_fds::keep(source_name, &listener)
+            .map_err(|e| anyhow!("{} address={}", e, listen_addr))?;
+    }
+    Ok(listener)
+}
+
+async fn bind_reuse_port(listen_addr: &str) -> std::io::Result<TcpListener> {
+    let address = lookup_host(listen_addr).await?.next().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::InvalidInput, "could not resolve to any address")
+    })?;
+    let socket = if address.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // Matches the options set by TcpListener::bind
+    socket.set_reuseaddr(true)?;
+    socket.set_reuseport(true)?;
+    socket.bind(address)?;
+    socket.listen(1024)
 }
 
 pub struct Handler<C: CodecBuilder> {
     chain: TransformChain,
+    /// Used to replace `chain` when it is no longer usable after returning an error.
+    chain_builder: Arc<TransformChainBuilder>,
+    context: TransformContextBuilder,
+    chain_error_policy: Arc<ChainErrorPolicy>,
+    /// Built the first time that requests are retried on the fallback chain of the chain error policy.
+    fallback_chain: Option<TransformChain>,
+    /// Measures the latency of the requests of this connection against the latency objective of the source.
+    latency_tracker: Option<LatencyTracker>,
     codec: C,
     pending_requests: PendingRequests,
     tls: Option<TlsAcceptor>,
+    tcp: TcpConfig,
     /// Listen for shutdown notifications.
     ///
     /// A wrapper around the `broadcast::Receiver` paired with the sender in
@@ -301,6 +502,12 @@ pub struct Handler<C: CodecBuilder> {
     shutdown: Shutdown,
     /// Timeout in seconds after which to kill an idle connection. No timeout means connections will never be timed out.
     timeout: Option<Duration>,
+    /// State shared by the transforms of the chain for the lifetime of this connection
+    session: SessionState,
+    /// The throttle of the source along with the address of this connection's client, every batch of requests is recorded against it.
+    client_throttle: Option<(ClientThrottle, IpAddr)>,
+    /// Lists this connection in the observability interface until the handler is dropped.
+    connection: ConnectionRegistration,
     _permit: OwnedSemaphorePermit,
 }
 
@@ -545,6 +752,23 @@ pub fn spawn_read_write_tasks<
     );
 }
 
+fn spawn_tcp_read_write_tasks<C: CodecBuilder + 'static>(
+    codec: C,
+    stream: TcpStream,
+    in_tx: mpsc::Sender<Messages>,
+    out_rx: UnboundedReceiver<Messages>,
+    out_tx: UnboundedSender<Messages>,
+) -> Result<()> {
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    if crate::uring::is_enabled() {
+        return crate::uring::spawn_read_write_tasks(codec, stream, in_tx, out_rx, out_tx);
+    }
+
+    let (rx, tx) = stream.into_split();
+    spawn_read_write_tasks(codec, rx, tx, in_tx, out_rx, out_tx);
+    Ok(())
+}
+
 impl<C: CodecBuilder + 'static> Handler<C> {
     /// Process a single connection.
     ///
@@ -560,7 +784,7 @@ impl<C: CodecBuilder + 'static> Handler<C> {
         force_run_chain: Arc<Notify>,
         client_details: String,
     ) -> Result<()> {
-        stream.set_nodelay(true)?;
+        self.tcp.apply_to_accepted(&stream)?;
 
         // limit buffered incoming messages to 10,000 per connection.
         // A particular scenario we are concerned about is if it takes longer to send to the server
@@ -583,6 +807,10 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                         Err(AcceptError::Disconnected) => return Ok(()),
                         Err(AcceptError::Failure(err)) => return Err(err),
                     };
+                    self.connection.set_tls(session_details(&tls_stream));
+                    if let Some(identity) = peer_common_name(&tls_stream) {
+                        self.session.insert(TlsClientIdentity(identity));
+                    }
                     spawn_websocket_read_write_tasks(
                         codec_builder,
                         tls_stream,
@@ -611,6 +839,10 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                         Err(AcceptError::Disconnected) => return Ok(()),
                         Err(AcceptError::Failure(err)) => return Err(err),
                     };
+                    self.connection.set_tls(session_details(&tls_stream));
+                    if let Some(identity) = peer_common_name(&tls_stream) {
+                        self.session.insert(TlsClientIdentity(identity));
+                    }
                     let (rx, tx) = tokio::io::split(tls_stream);
                     spawn_read_write_tasks(
                         self.codec.clone(),
@@ -621,15 +853,13 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                         out_tx.clone(),
                     );
                 } else {
-                    let (rx, tx) = stream.into_split();
-                    spawn_read_write_tasks(
+                    spawn_tcp_read_write_tasks(
                         self.codec.clone(),
-                        rx,
-                        tx,
+                        stream,
                         in_tx,
                         out_rx,
                         out_tx.clone(),
-                    );
+                    )?;
                 };
             }
         };
@@ -640,16 +870,13 @@ impl<C: CodecBuilder + 'static> Handler<C> {
 
         // Only flush messages if we are shutting down due to shotover shutdown or client disconnect
         // If a Transform::transform returns an Err the transform is no longer in a usable state and needs to be destroyed without reusing.
-        if let Ok(CloseReason::ShotoverShutdown | CloseReason::ClientClosed) = result {
-            match self.chain.process_request(&mut ChainState::flush()).await {
-                Ok(_) => {}
-                Err(e) => error!(
-                    "{:?}",
-                    e.context(format!(
-                        "encountered an error when flushing the chain {} for shutdown",
-                        self.chain.name,
-                    ))
-                ),
+        if let Ok(
+            CloseReason::ShotoverShutdown | CloseReason::ClientClosed | CloseReason::Terminated,
+        ) = result
+        {
+            self.chain.shutdown().await;
+            if let Some(fallback_chain) = &mut self.fallback_chain {
+                fallback_chain.shutdown().await;
             }
         }
 
@@ -694,13 +921,17 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                     // This will result in the task terminating.
                     return Ok(CloseReason::ShotoverShutdown);
                 }
+                () = self.connection.terminated() => {
+                    info!("Closing connection to {client_details} as it was terminated through the observability interface");
+                    return Ok(CloseReason::Terminated);
+                }
                 () = force_run_chain.notified() => {
                     let mut requests = vec!();
                     while let Ok(x) = in_rx.try_recv() {
                         requests.extend(x);
                     }
                     debug!("A transform in the chain requested that a chain run occur, requests {:?}", requests);
-                    if let Some(close_reason) = self.send_receive_chain(local_addr, &out_tx, requests).await? {
+                    if let Some(close_reason) = self.send_receive_chain(client_details, local_addr, &out_tx, requests).await? {
                         return Ok(close_reason)
                     }
                 },
@@ -711,7 +942,7 @@ impl<C: CodecBuilder + 'static> Handler<C> {
                                 requests.extend(x);
                             }
                             debug!("Received requests from client {:?}", requests);
-                            if let Some(close_reason) = self.send_receive_chain(local_addr, &out_tx, requests).await? {
+                            if let Some(close_reason) = self.send_receive_chain(client_details, local_addr, &out_tx, requests).await? {
                                 return Ok(close_reason)
                             }
                         }
@@ -727,28 +958,78 @@ impl<C: CodecBuilder + 'static> Handler<C> {
 
     async fn send_receive_chain(
         &mut self,
+        client_details: &str,
         local_addr: SocketAddr,
         out_tx: &mpsc::UnboundedSender<Messages>,
         requests: Messages,
     ) -> Result<Option<CloseReason>> {
+        if let Some((client_throttle, address)) = &self.client_throttle {
+            match client_throttle.on_requests(*address, requests.len()) {
+                Verdict::Allow => {}
+                Verdict::Delay(delay) => tokio::time::sleep(delay).await,
+                Verdict::Reject => {
+                    debug!("Closing connection to {client_details} as it was throttled");
+                    return Ok(Some(CloseReason::Throttled));
+                }
+            }
+        }
+
+        self.connection.record_requests(&requests);
         let mut wrapper = ChainState::new_with_addr(requests, local_addr);
+        wrapper.session = std::mem::take(&mut self.session);
+        wrapper.check_capture(client_details);
 
         self.pending_requests.process_requests(&wrapper.requests);
-        let responses = match self.chain.process_request(&mut wrapper).await {
-            Ok(x) => x,
+        if let Some(latency_tracker) = &mut self.latency_tracker {
+            latency_tracker.on_requests(&wrapper.requests);
+        }
+        // The chain consumes the requests, so keep a copy in case they need to be retried on the fallback chain
+        let retry_requests = self
+            .chain_error_policy
+            .retries_requests()
+            .then(|| wrapper.requests.clone());
+        // Fields are only evaluated when debug logging is enabled, so this has no cost otherwise
+        let span = tracing::debug_span!(
+            "requests",
+            correlation_ids = ?wrapper
+                .requests
+                .iter()
+                .map(|x| x.correlation_id())
+                .collect::<Vec<_>>()
+        );
+        let result = self
+            .chain
+            .process_request(&mut wrapper)
+            .instrument(span.clone())
+            .await;
+        self.session = std::mem::take(&mut wrapper.session);
+        let mut close_client_connection = wrapper.close_client_connection;
+        let sink_reached_at = wrapper.sink_reached_at;
+        let responses = match result {
+            Ok(responses) => {
+                self.poll_fallback_chain(local_addr, out_tx, responses)
+                    .instrument(span.clone())
+                    .await?
+            }
             Err(err) => {
-                let err = err.context("Chain failed to send and/or receive messages, the connection will now be closed.");
-                // The connection is going to be closed once we return Err.
-                // So first make a best effort attempt of responding to any pending requests with an error response.
-                out_tx.send(self.pending_requests.to_errors(&err))?;
-                return Err(err);
+                let (responses, close) = self
+                    .handle_chain_error(err, retry_requests, local_addr, out_tx)
+                    .instrument(span.clone())
+                    .await?;
+                close_client_connection |= close;
+                responses
             }
         };
         self.pending_requests.process_responses(&responses);
+        if let Some(latency_tracker) = &mut self.latency_tracker {
+            latency_tracker.on_responses(sink_reached_at, &responses);
+        }
+        self.connection.record_responses(&responses);
+        self.connection.set_identity(self.session.identity());
 
         // send the result of the process up stream
         if !responses.is_empty() {
-            debug!("sending response to client: {:?}", responses);
+            span.in_scope(|| debug!("sending response to client: {:?}", responses));
             if out_tx.send(responses).is_err() {
                 // the client has disconnected so we should terminate this connection
                 return Ok(Some(CloseReason::ClientClosed));
@@ -756,17 +1037,114 @@ impl<C: CodecBuilder + 'static> Handler<C> {
         }
 
         // if requested by a transform, close connection AFTER sending any responses back to the client
-        if wrapper.close_client_connection {
+        if close_client_connection {
             return Ok(Some(CloseReason::TransformRequested));
         }
 
         Ok(None)
     }
+
+    /// Applies the chain error policy of the source to an error returned by the chain.
+    /// Returns the responses to send to the client and whether the connection should be closed after sending them.
+    /// Returns Err if the connection must be closed immediately.
+    async fn handle_chain_error(
+        &mut self,
+        err: anyhow::Error,
+        retry_requests: Option<Messages>,
+        local_addr: SocketAddr,
+        out_tx: &mpsc::UnboundedSender<Messages>,
+    ) -> Result<(Messages, bool)> {
+        let policy = self.chain_error_policy.clone();
+        match (&policy.action, retry_requests) {
+            (ChainErrorAction::ErrorResponse, _) => {
+                let err = err.context("Chain failed to send and/or receive messages, pending requests will be responded to with errors and the chain rebuilt.");
+                let responses = self.pending_requests.drain_to_errors(&err, &[]);
+                error!("{err:?}");
+                self.chain = self.chain_builder.build(self.context.clone());
+                // Error responses say nothing about the latency of the chain
+                if let Some(latency_tracker) = &mut self.latency_tracker {
+                    latency_tracker.clear();
+                }
+                policy.error_response.increment(1);
+                Ok((responses, false))
+            }
+            (ChainErrorAction::Fallback(fallback_builder), Some(requests)) => {
+                let err = err.context("Chain failed to send and/or receive messages, the requests will be retried on the fallback chain and the chain rebuilt.");
+                // Requests sent to the failed chain by earlier chain runs will never be responded to.
+                let mut responses = self.pending_requests.drain_to_errors(&err, &requests);
+                error!("{err:?}");
+                self.chain = self.chain_builder.build(self.context.clone());
+
+                let fallback_chain = self
+                    .fallback_chain
+                    .get_or_insert_with(|| fallback_builder.build(self.context.clone()));
+                let mut wrapper = ChainState::new_with_addr(requests, local_addr);
+                wrapper.session = std::mem::take(&mut self.session);
+                let result = fallback_chain.process_request(&mut wrapper).await;
+                self.session = std::mem::take(&mut wrapper.session);
+                match result {
+                    Ok(fallback_responses) => {
+                        policy.fallback_success.increment(1);
+                        responses.extend(fallback_responses);
+                        Ok((responses, wrapper.close_client_connection))
+                    }
+                    Err(err) => {
+                        policy.fallback_failure.increment(1);
+                        let err = err.context("Fallback chain failed to send and/or receive messages, the connection will now be closed.");
+                        responses.extend(self.pending_requests.to_errors(&err));
+                        out_tx.send(responses)?;
+                        Err(err)
+                    }
+                }
+            }
+            _ => {
+                policy.close_connection.increment(1);
+                let err = err.context("Chain failed to send and/or receive messages, the connection will now be closed.");
+                // The connection is going to be closed once we return Err.
+                // So first make a best effort attempt of responding to any pending requests with an error response.
+                out_tx.send(self.pending_requests.to_errors(&err))?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Once requests have been retried on the fallback chain it may still be holding responses to them,
+    /// so it is run alongside the chain from then on and its responses are sent before those of the chain.
+    async fn poll_fallback_chain(
+        &mut self,
+        local_addr: SocketAddr,
+        out_tx: &mpsc::UnboundedSender<Messages>,
+        responses: Messages,
+    ) -> Result<Messages> {
+        let Some(fallback_chain) = &mut self.fallback_chain else {
+            return Ok(responses);
+        };
+        match fallback_chain
+            .process_request(&mut ChainState::new_with_addr(vec![], local_addr))
+            .await
+        {
+            Ok(mut fallback_responses) => {
+                fallback_responses.extend(responses);
+                Ok(fallback_responses)
+            }
+            Err(err) => {
+                self.chain_error_policy.fallback_failure.increment(1);
+                let err = err.context("Fallback chain failed to send and/or receive messages, the connection will now be closed.");
+                self.pending_requests.process_responses(&responses);
+                let mut responses = responses;
+                responses.extend(self.pending_requests.to_errors(&err));
+                out_tx.send(responses)?;
+                Err(err)
+            }
+        }
+    }
 }
 
 /// Indicates that the connection to the client must be closed.
 enum CloseReason {
     TransformRequested,
+    Throttled,
+    Terminated,
     ClientClosed,
     ShotoverShutdown,
 }
@@ -844,6 +1222,8 @@ impl PendingRequests {
             MessageType::Kafka => PendingRequests::Unsupported,
             #[cfg(feature = "opensearch")]
             MessageType::OpenSearch => PendingRequests::Unsupported,
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached => PendingRequests::Ordered(vec![]),
             MessageType::Dummy => PendingRequests::Unsupported,
         }
     }
@@ -887,6 +1267,30 @@ impl PendingRequests {
         }
     }
 
+    /// Returns error responses for every pending request except for `retried`, which are left pending.
+    fn drain_to_errors(&mut self, err: &anyhow::Error, retried: &[Message]) -> Vec<Message> {
+        let drained = match self {
+            PendingRequests::Ordered(pending_requests) => {
+                // The retried requests are always the most recently sent
+                let count = pending_requests.len().saturating_sub(retried.len());
+                PendingRequests::Ordered(pending_requests.drain(..count).collect())
+            }
+            PendingRequests::Unordered(pending_requests) => {
+                let mut drained = MessageIdMap::default();
+                for (id, pending_request) in std::mem::take(pending_requests) {
+                    if retried.iter().any(|x| x.id() == id) {
+                        pending_requests.insert(id, pending_request);
+                    } else {
+                        drained.insert(id, pending_request);
+                    }
+                }
+                PendingRequests::Unordered(drained)
+            }
+            PendingRequests::Unsupported => PendingRequests::Unsupported,
+        };
+        drained.to_errors(err)
+    }
+
     fn to_errors(&self, err: &anyhow::Error) -> Vec<Message> {
         // An internal error occured and we need to terminate the connection because we can no
         // longer make any guarantees about the state its in.
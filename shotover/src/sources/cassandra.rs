@@ -1,8 +1,14 @@
+use crate::codec::cassandra::{CassandraCodecBuilder, CassandraCompression};
+use crate::codec::CodecBuilder;
 use crate::codec::Direction;
-use crate::codec::{cassandra::CassandraCodecBuilder, CodecBuilder};
 use crate::config::chain::TransformChainConfig;
 use crate::server::TcpCodecListener;
+use crate::sources::chain_error_policy::ChainErrorPolicyConfig;
+use crate::sources::client_throttle::ClientThrottleConfig;
+use crate::sources::ip_filter::IpFilterConfig;
+use crate::sources::latency_objective::LatencyObjectiveConfig;
 use crate::sources::{Source, Transport};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -12,7 +18,7 @@ use tokio::sync::{watch, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct CassandraConfig {
     pub name: String,
@@ -21,7 +27,20 @@ pub struct CassandraConfig {
     pub hard_connection_limit: Option<bool>,
     pub tls: Option<TlsAcceptorConfig>,
     pub timeout: Option<u64>,
+    pub max_message_size_bytes: Option<usize>,
     pub transport: Option<Transport>,
+    /// The only compression that clients may negotiate, when not provided clients may negotiate any compression.
+    pub compression: Option<CassandraCompression>,
+    /// Restricts the IP addresses that clients may connect from.
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Bans or tarpits client IP addresses that open connections or send requests too quickly.
+    pub client_throttle: Option<ClientThrottleConfig>,
+    /// Tuning of the TCP connections accepted from clients.
+    pub tcp: Option<TcpConfig>,
+    /// What happens to a client connection when a transform in the chain returns an error.
+    pub chain_error_policy: Option<ChainErrorPolicyConfig>,
+    /// Measures the latency of every request against a budget.
+    pub latency_objective: Option<LatencyObjectiveConfig>,
     pub chain: TransformChainConfig,
 }
 
@@ -41,6 +60,13 @@ impl CassandraConfig {
                 self.tls.clone(),
                 self.timeout,
                 self.transport,
+                self.max_message_size_bytes,
+                self.compression,
+                self.ip_filter.clone(),
+                self.client_throttle.clone(),
+                self.tcp.clone(),
+                self.chain_error_policy.as_ref(),
+                self.latency_objective.clone(),
             )
             .await?,
         ))
@@ -64,20 +90,34 @@ impl CassandraSource {
         tls: Option<TlsAcceptorConfig>,
         timeout: Option<u64>,
         transport: Option<Transport>,
+        max_message_size_bytes: Option<usize>,
+        compression: Option<CassandraCompression>,
+        ip_filter: Option<IpFilterConfig>,
+        client_throttle: Option<ClientThrottleConfig>,
+        tcp: Option<TcpConfig>,
+        chain_error_policy: Option<&ChainErrorPolicyConfig>,
+        latency_objective: Option<LatencyObjectiveConfig>,
     ) -> Result<Self, Vec<String>> {
         info!("Starting Cassandra source on [{}]", listen_addr);
 
         let mut listener = TcpCodecListener::new(
             chain_config,
             name.to_string(),
-            listen_addr.clone(),
+            Some(listen_addr.clone()),
             hard_connection_limit.unwrap_or(false),
-            CassandraCodecBuilder::new(Direction::Source, name),
+            CassandraCodecBuilder::new(Direction::Source, name)
+                .with_max_message_size(max_message_size_bytes)
+                .with_compression(compression),
             Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
             trigger_shutdown_rx.clone(),
             tls.as_ref().map(TlsAcceptor::new).transpose()?,
             timeout.map(Duration::from_secs),
             transport.unwrap_or(Transport::Tcp),
+            ip_filter.as_ref(),
+            client_throttle.as_ref(),
+            tcp.as_ref(),
+            chain_error_policy,
+            latency_objective.as_ref(),
         )
         .await?;
 
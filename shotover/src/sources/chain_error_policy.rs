@@ -0,0 +1,120 @@
+//! Decides what happens to a client connection when a transform in the chain of its source returns an error.
+
+use crate::config::chain::TransformChainConfig;
+use crate::frame::MessageType;
+use crate::transforms::chain::TransformChainBuilder;
+use crate::transforms::TransformContextConfig;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum ChainErrorPolicyConfig {
+    /// Respond to every pending request with an error and close the connection.
+    CloseConnection,
+    /// Respond to every pending request with an error and keep the connection open.
+    /// Later requests are sent through a newly built chain.
+    ErrorResponse,
+    /// Retry the requests of the failed chain run on this chain and keep the connection open.
+    /// Later requests are sent through a newly built chain.
+    /// If the fallback chain also fails the connection is closed.
+    Fallback { chain: TransformChainConfig },
+}
+
+pub(crate) enum ChainErrorAction {
+    CloseConnection,
+    ErrorResponse,
+    Fallback(TransformChainBuilder),
+}
+
+/// Shared between the listener of a source and all of its connections.
+pub(crate) struct ChainErrorPolicy {
+    pub(crate) action: ChainErrorAction,
+    pub(crate) close_connection: Counter,
+    pub(crate) error_response: Counter,
+    pub(crate) fallback_success: Counter,
+    pub(crate) fallback_failure: Counter,
+}
+
+impl ChainErrorPolicy {
+    pub(crate) async fn new(
+        config: Option<&ChainErrorPolicyConfig>,
+        source_name: &str,
+        protocol: MessageType,
+    ) -> Result<Self, Vec<String>> {
+        let action = match config {
+            None | Some(ChainErrorPolicyConfig::CloseConnection) => {
+                ChainErrorAction::CloseConnection
+            }
+            Some(ChainErrorPolicyConfig::ErrorResponse) => ChainErrorAction::ErrorResponse,
+            Some(ChainErrorPolicyConfig::Fallback { chain }) => {
+                let chain_builder = chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: format!("{source_name}_fallback"),
+                        up_chain_protocol: protocol,
+                    })
+                    .await
+                    .map_err(|x| vec![format!("chain_error_policy: {x:?}")])?;
+                let errors = chain_builder.validate();
+                if !errors.is_empty() {
+                    return Err(errors);
+                }
+                ChainErrorAction::Fallback(chain_builder)
+            }
+        };
+
+        let outcome = |outcome: &'static str| counter!("shotover_chain_error_policy_count", "chain" => source_name.to_owned(), "outcome" => outcome);
+        Ok(ChainErrorPolicy {
+            action,
+            close_connection: outcome("close_connection"),
+            error_response: outcome("error_response"),
+            fallback_success: outcome("fallback_success"),
+            fallback_failure: outcome("fallback_failure"),
+        })
+    }
+
+    /// The requests of a chain run only need to be kept around if they may be retried on the fallback chain.
+    pub(crate) fn retries_requests(&self) -> bool {
+        matches!(self.action, ChainErrorAction::Fallback(_))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_invalid_fallback_chain() {
+        let config = ChainErrorPolicyConfig::Fallback {
+            chain: TransformChainConfig::new(vec![]),
+        };
+        let errors = ChainErrorPolicy::new(Some(&config), "foo", MessageType::Dummy)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(errors, ["foo_fallback chain:", "  Chain cannot be empty"]);
+    }
+
+    #[test]
+    fn test_config() {
+        let config: ChainErrorPolicyConfig = serde_yaml::from_str("ErrorResponse").unwrap();
+        assert!(matches!(config, ChainErrorPolicyConfig::ErrorResponse));
+
+        let config: ChainErrorPolicyConfig =
+            serde_yaml::with::singleton_map_recursive::deserialize(
+                serde_yaml::Deserializer::from_str(
+                    "
+Fallback:
+  chain:
+    - NullSink
+",
+                ),
+            )
+            .unwrap();
+        let ChainErrorPolicyConfig::Fallback { chain } = config else {
+            panic!("expected Fallback")
+        };
+        assert_eq!(chain.0.len(), 1);
+    }
+}
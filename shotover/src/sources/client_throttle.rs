@@ -0,0 +1,317 @@
+//! Tracks the rate of new connections and requests from each client IP address and penalizes clients that exceed the configured limits.
+//!
+//! This protects shotover itself, rather than the backing database, from misbehaving or malicious clients.
+
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ClientThrottleConfig {
+    /// The maximum number of new connections a single IP address may open per second.
+    /// This field is optional, if not provided the connection rate is not limited.
+    pub max_connections_per_second: Option<u32>,
+    /// The maximum number of requests a single IP address may send per second, summed across all of its connections.
+    /// This field is optional, if not provided the request rate is not limited.
+    pub max_requests_per_second: Option<u32>,
+    /// How long in seconds an IP address is penalized for after exceeding one of the limits, defaults to 60.
+    #[serde(default = "default_penalty_seconds")]
+    pub penalty_seconds: u64,
+    /// How clients are penalized, defaults to Ban.
+    #[serde(default)]
+    pub action: ThrottleAction,
+}
+
+fn default_penalty_seconds() -> u64 {
+    60
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum ThrottleAction {
+    /// New connections from the client are closed as soon as they are accepted
+    /// and existing connections are closed the next time they send a request.
+    #[default]
+    Ban,
+    /// New connections from the client and every batch of requests it sends are delayed.
+    Tarpit { delay_ms: u64 },
+}
+
+/// What to do with a connection or batch of requests from a client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Verdict {
+    Allow,
+    Delay(Duration),
+    Reject,
+}
+
+struct ClientState {
+    window_start: Instant,
+    connections: u32,
+    requests: u32,
+    penalized_until: Option<Instant>,
+}
+
+impl ClientState {
+    fn new(now: Instant) -> Self {
+        ClientState {
+            window_start: now,
+            connections: 0,
+            requests: 0,
+            penalized_until: None,
+        }
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now.duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.connections = 0;
+            self.requests = 0;
+        }
+        if self.penalized_until.is_some_and(|until| now >= until) {
+            self.penalized_until = None;
+        }
+    }
+
+    /// A client in this state behaves identically to a client that has never been seen before.
+    fn is_idle(&self, now: Instant) -> bool {
+        self.penalized_until.is_none() && now.duration_since(self.window_start) >= WINDOW
+    }
+}
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Every this many connections, clients that no longer need to be tracked are forgotten.
+const PRUNE_INTERVAL: u64 = 1000;
+
+struct Clients {
+    states: HashMap<IpAddr, ClientState>,
+    connections_seen: u64,
+}
+
+/// Shared between the listener of a source and all of its connections.
+#[derive(Clone)]
+pub(crate) struct ClientThrottle {
+    max_connections_per_second: Option<u32>,
+    max_requests_per_second: Option<u32>,
+    penalty: Duration,
+    action: ThrottleAction,
+    clients: Arc<Mutex<Clients>>,
+    rejected_connections: Counter,
+    penalties: Counter,
+}
+
+impl ClientThrottle {
+    pub(crate) fn new(
+        config: &ClientThrottleConfig,
+        source_name: &str,
+    ) -> Result<Self, Vec<String>> {
+        let mut errors = vec![];
+        let limits = [
+            config.max_connections_per_second,
+            config.max_requests_per_second,
+        ];
+        if limits.iter().all(Option::is_none) {
+            errors.push("client_throttle: at least one of max_connections_per_second or max_requests_per_second must be provided".to_owned());
+        }
+        if limits.contains(&Some(0)) {
+            errors.push("client_throttle: limits must be greater than 0".to_owned());
+        }
+        if config.penalty_seconds == 0 {
+            errors.push("client_throttle: penalty_seconds must be greater than 0".to_owned());
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        Ok(ClientThrottle {
+            max_connections_per_second: config.max_connections_per_second,
+            max_requests_per_second: config.max_requests_per_second,
+            penalty: Duration::from_secs(config.penalty_seconds),
+            action: config.action,
+            clients: Arc::new(Mutex::new(Clients {
+                states: HashMap::new(),
+                connections_seen: 0,
+            })),
+            rejected_connections: counter!("shotover_rejected_connections_count", "source" => source_name.to_owned(), "reason" => "client_throttle"),
+            penalties: counter!("shotover_client_throttle_penalties_count", "source" => source_name.to_owned()),
+        })
+    }
+
+    /// Records a new connection from `address` and decides how it should be handled.
+    pub(crate) fn on_connection(&self, address: IpAddr) -> Verdict {
+        let verdict = self.on_connection_at(address.to_canonical(), Instant::now());
+        if verdict == Verdict::Reject {
+            self.rejected_connections.increment(1);
+        }
+        verdict
+    }
+
+    /// Records `count` new requests from `address` and decides how they should be handled.
+    pub(crate) fn on_requests(&self, address: IpAddr, count: usize) -> Verdict {
+        self.on_requests_at(address.to_canonical(), count, Instant::now())
+    }
+
+    fn on_connection_at(&self, address: IpAddr, now: Instant) -> Verdict {
+        let mut clients = self.clients.lock().unwrap();
+        clients.connections_seen = clients.connections_seen.wrapping_add(1);
+        if clients.connections_seen % PRUNE_INTERVAL == 0 {
+            clients.states.retain(|_, state| !state.is_idle(now));
+        }
+
+        let state = clients
+            .states
+            .entry(address)
+            .or_insert_with(|| ClientState::new(now));
+        state.roll_window(now);
+        state.connections = state.connections.saturating_add(1);
+        let exceeded = self
+            .max_connections_per_second
+            .is_some_and(|max| state.connections > max);
+        self.judge(state, address, exceeded, now, "connections")
+    }
+
+    fn on_requests_at(&self, address: IpAddr, count: usize, now: Instant) -> Verdict {
+        let mut clients = self.clients.lock().unwrap();
+        let state = clients
+            .states
+            .entry(address)
+            .or_insert_with(|| ClientState::new(now));
+        state.roll_window(now);
+        state.requests = state
+            .requests
+            .saturating_add(count.try_into().unwrap_or(u32::MAX));
+        let exceeded = self
+            .max_requests_per_second
+            .is_some_and(|max| state.requests > max);
+        self.judge(state, address, exceeded, now, "requests")
+    }
+
+    fn judge(
+        &self,
+        state: &mut ClientState,
+        address: IpAddr,
+        exceeded: bool,
+        now: Instant,
+        limit: &str,
+    ) -> Verdict {
+        if state.penalized_until.is_none() {
+            if !exceeded {
+                return Verdict::Allow;
+            }
+            state.penalized_until = Some(now + self.penalty);
+            self.penalties.increment(1);
+            tracing::warn!(
+                "Client {address} exceeded the {limit} per second limit of the client_throttle and will be penalized for {:?}",
+                self.penalty
+            );
+        }
+        match self.action {
+            ThrottleAction::Ban => Verdict::Reject,
+            ThrottleAction::Tarpit { delay_ms } => Verdict::Delay(Duration::from_millis(delay_ms)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn throttle(
+        max_connections_per_second: Option<u32>,
+        max_requests_per_second: Option<u32>,
+        action: ThrottleAction,
+    ) -> ClientThrottle {
+        ClientThrottle::new(
+            &ClientThrottleConfig {
+                max_connections_per_second,
+                max_requests_per_second,
+                penalty_seconds: 10,
+                action,
+            },
+            "test",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_connection_rate() {
+        let throttle = throttle(Some(2), None, ThrottleAction::Ban);
+        let offender: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(throttle.on_connection_at(offender, start), Verdict::Allow);
+        assert_eq!(throttle.on_connection_at(offender, start), Verdict::Allow);
+        assert_eq!(throttle.on_connection_at(offender, start), Verdict::Reject);
+        assert_eq!(throttle.on_connection_at(other, start), Verdict::Allow);
+
+        // The ban outlasts the rate window
+        let later = start + Duration::from_secs(5);
+        assert_eq!(throttle.on_connection_at(offender, later), Verdict::Reject);
+        assert_eq!(throttle.on_requests_at(offender, 1, later), Verdict::Reject);
+
+        let after_penalty = start + Duration::from_secs(11);
+        assert_eq!(
+            throttle.on_connection_at(offender, after_penalty),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn test_request_rate_tarpit() {
+        let delay = Duration::from_millis(500);
+        let throttle = throttle(
+            None,
+            Some(100),
+            ThrottleAction::Tarpit {
+                delay_ms: delay.as_millis() as u64,
+            },
+        );
+        let client: IpAddr = "fd00::1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(throttle.on_requests_at(client, 60, start), Verdict::Allow);
+        assert_eq!(
+            throttle.on_requests_at(client, 60, start),
+            Verdict::Delay(delay)
+        );
+        // Connections are still accepted but delayed while the client is penalized
+        assert_eq!(
+            throttle.on_connection_at(client, start),
+            Verdict::Delay(delay)
+        );
+
+        let after_penalty = start + Duration::from_secs(10);
+        assert_eq!(
+            throttle.on_requests_at(client, 60, after_penalty),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let errors = ClientThrottle::new(
+            &ClientThrottleConfig {
+                max_connections_per_second: None,
+                max_requests_per_second: None,
+                penalty_seconds: 0,
+                action: ThrottleAction::Ban,
+            },
+            "test",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "client_throttle: at least one of max_connections_per_second or max_requests_per_second must be provided",
+                "client_throttle: penalty_seconds must be greater than 0",
+            ]
+        );
+    }
+}
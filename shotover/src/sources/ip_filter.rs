@@ -0,0 +1,207 @@
+//! Rejects client connections by the IP address they connect from, as soon as they are accepted.
+
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct IpFilterConfig {
+    /// When provided, only clients connecting from an address within one of these CIDR ranges are accepted e.g. `10.0.0.0/8`
+    /// A plain IP address matches only that address.
+    pub allow: Option<Vec<String>>,
+    /// Clients connecting from an address within one of these CIDR ranges are rejected, even if the address is also allowed.
+    pub deny: Option<Vec<String>>,
+    /// Log the address of every rejected connection, defaults to false.
+    #[serde(default)]
+    pub log_rejected: bool,
+}
+
+/// A CIDR range of IPv4 or IPv6 addresses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct IpRange {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_len) = match s.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| format!("{s:?} is not a valid IP address or CIDR range"))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|x| *x <= max_prefix_len)
+                .ok_or_else(|| {
+                    format!("{s:?} has an invalid prefix length, it must be between 0 and {max_prefix_len}")
+                })?,
+            None => max_prefix_len,
+        };
+        Ok(IpRange {
+            address,
+            prefix_len,
+        })
+    }
+}
+
+impl IpRange {
+    pub(crate) fn contains(&self, address: IpAddr) -> bool {
+        // Clients connecting over IPv4 to a dual stack listener are reported as IPv4-mapped IPv6 addresses
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(range), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(range) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(range), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(range) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub(crate) struct IpFilter {
+    allow: Option<Vec<IpRange>>,
+    deny: Vec<IpRange>,
+    log_rejected: bool,
+    rejected_connections: Counter,
+}
+
+impl IpFilter {
+    pub(crate) fn new(config: &IpFilterConfig, source_name: &str) -> Result<Self, Vec<String>> {
+        let mut errors = vec![];
+        let mut parse = |ranges: &[String]| -> Vec<IpRange> {
+            ranges
+                .iter()
+                .filter_map(|range| match range.parse() {
+                    Ok(range) => Some(range),
+                    Err(err) => {
+                        errors.push(format!("ip_filter: {err}"));
+                        None
+                    }
+                })
+                .collect()
+        };
+        let allow = config.allow.as_deref().map(&mut parse);
+        let deny = parse(config.deny.as_deref().unwrap_or_default());
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(IpFilter {
+            allow,
+            deny,
+            log_rejected: config.log_rejected,
+            rejected_connections: counter!("shotover_rejected_connections_count", "source" => source_name.to_owned(), "reason" => "ip_filter"),
+        })
+    }
+
+    fn permits(&self, address: IpAddr) -> bool {
+        let allowed = self
+            .allow
+            .as_ref()
+            .map(|allow| allow.iter().any(|range| range.contains(address)))
+            .unwrap_or(true);
+        allowed && !self.deny.iter().any(|range| range.contains(address))
+    }
+
+    /// Returns true if the connection from `peer` should be accepted, otherwise records the rejection.
+    pub(crate) fn check(&self, peer: SocketAddr) -> bool {
+        if self.permits(peer.ip()) {
+            return true;
+        }
+        self.rejected_connections.increment(1);
+        if self.log_rejected {
+            tracing::warn!(
+                "Rejected connection from {peer} as its address is not permitted by the ip_filter"
+            );
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip_filter(allow: Option<&[&str]>, deny: &[&str]) -> IpFilter {
+        let to_strings = |x: &[&str]| x.iter().map(|x| x.to_string()).collect();
+        IpFilter::new(
+            &IpFilterConfig {
+                allow: allow.map(to_strings),
+                deny: Some(to_strings(deny)),
+                log_rejected: false,
+            },
+            "test",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.1.0.0/16".parse().unwrap();
+        assert!(range.contains("10.1.200.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("fe80::1".parse().unwrap()));
+
+        let range: IpRange = "fd00::/8".parse().unwrap();
+        assert!(range.contains("fd12::1".parse().unwrap()));
+        assert!(!range.contains("fe80::1".parse().unwrap()));
+
+        assert!("0.0.0.0/0"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("1.2.3.4"
+            .parse::<IpRange>()
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!("1.2.3.0/33".parse::<IpRange>().is_err());
+        assert!("1.2.3/24".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let filter = ip_filter(Some(&["10.0.0.0/8"]), &["10.0.0.66"]);
+        assert!(filter.permits("10.3.2.1".parse().unwrap()));
+        assert!(!filter.permits("10.0.0.66".parse().unwrap()));
+        assert!(!filter.permits("192.168.0.1".parse().unwrap()));
+
+        let filter = ip_filter(None, &["192.168.0.0/16"]);
+        assert!(filter.permits("10.3.2.1".parse().unwrap()));
+        assert!(!filter.permits("192.168.0.1".parse().unwrap()));
+
+        let errors = IpFilter::new(
+            &IpFilterConfig {
+                allow: Some(vec!["10.0.0.0/40".to_owned()]),
+                deny: None,
+                log_rejected: false,
+            },
+            "test",
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                "ip_filter: \"10.0.0.0/40\" has an invalid prefix length, it must be between 0 and 32"
+            ]
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use crate::codec::{kafka::KafkaCodecBuilder, CodecBuilder, Direction};
 use crate::config::chain::TransformChainConfig;
 use crate::server::TcpCodecListener;
+use crate::sources::chain_error_policy::ChainErrorPolicyConfig;
+use crate::sources::client_throttle::ClientThrottleConfig;
+use crate::sources::ip_filter::IpFilterConfig;
+use crate::sources::latency_objective::LatencyObjectiveConfig;
 use crate::sources::{Source, Transport};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
@@ -11,7 +16,7 @@ use tokio::sync::{watch, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct KafkaConfig {
     pub name: String,
@@ -20,6 +25,16 @@ pub struct KafkaConfig {
     pub hard_connection_limit: Option<bool>,
     pub tls: Option<TlsAcceptorConfig>,
     pub timeout: Option<u64>,
+    /// Restricts the IP addresses that clients may connect from.
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Bans or tarpits client IP addresses that open connections or send requests too quickly.
+    pub client_throttle: Option<ClientThrottleConfig>,
+    /// Tuning of the TCP connections accepted from clients.
+    pub tcp: Option<TcpConfig>,
+    /// What happens to a client connection when a transform in the chain returns an error.
+    pub chain_error_policy: Option<ChainErrorPolicyConfig>,
+    /// Measures the latency of every request against a budget.
+    pub latency_objective: Option<LatencyObjectiveConfig>,
     pub chain: TransformChainConfig,
 }
 
@@ -38,6 +53,11 @@ impl KafkaConfig {
                 self.hard_connection_limit,
                 self.tls.clone(),
                 self.timeout,
+                self.ip_filter.clone(),
+                self.client_throttle.clone(),
+                self.tcp.clone(),
+                self.chain_error_policy.as_ref(),
+                self.latency_objective.clone(),
             )
             .await?,
         ))
@@ -60,13 +80,18 @@ impl KafkaSource {
         hard_connection_limit: Option<bool>,
         tls: Option<TlsAcceptorConfig>,
         timeout: Option<u64>,
+        ip_filter: Option<IpFilterConfig>,
+        client_throttle: Option<ClientThrottleConfig>,
+        tcp: Option<TcpConfig>,
+        chain_error_policy: Option<&ChainErrorPolicyConfig>,
+        latency_objective: Option<LatencyObjectiveConfig>,
     ) -> Result<KafkaSource, Vec<String>> {
         info!("Starting Kafka source on [{}]", listen_addr);
 
         let mut listener = TcpCodecListener::new(
             chain_config,
             name.to_string(),
-            listen_addr.clone(),
+            Some(listen_addr.clone()),
             hard_connection_limit.unwrap_or(false),
             KafkaCodecBuilder::new(Direction::Source, name),
             Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
@@ -74,6 +99,11 @@ impl KafkaSource {
             tls.as_ref().map(TlsAcceptor::new).transpose()?,
             timeout.map(Duration::from_secs),
             Transport::Tcp,
+            ip_filter.as_ref(),
+            client_throttle.as_ref(),
+            tcp.as_ref(),
+            chain_error_policy,
+            latency_objective.as_ref(),
         )
         .await?;
 
@@ -0,0 +1,240 @@
+//! Measures the latency of every request against an objective, attributing requests over budget to either shotover or the upstream.
+
+use crate::message::{Message, MessageId, MessageIdMap};
+use metrics::{counter, histogram, Counter, Histogram};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LatencyObjectiveConfig {
+    /// Requests that take longer than this many milliseconds to be responded to, measured from when shotover received them, are over budget.
+    pub budget_ms: u64,
+}
+
+/// Shared between the listener of a source and all of its connections.
+pub(crate) struct LatencyObjective {
+    budget: Duration,
+    requests: Counter,
+    over_budget_shotover: Counter,
+    over_budget_upstream: Counter,
+    over_budget_shotover_seconds: Histogram,
+    over_budget_upstream_seconds: Histogram,
+}
+
+#[derive(Debug, PartialEq)]
+enum Cause {
+    /// The upstream alone took longer than the budget
+    Upstream,
+    /// The time spent in shotover pushed the request over the budget
+    Shotover,
+}
+
+#[derive(Debug, PartialEq)]
+struct Overage {
+    cause: Cause,
+    shotover: Duration,
+    upstream: Duration,
+}
+
+impl LatencyObjective {
+    pub(crate) fn new(config: &LatencyObjectiveConfig, source_name: &str) -> Result<Self, String> {
+        if config.budget_ms == 0 {
+            return Err("latency_objective: budget_ms must be greater than 0".to_owned());
+        }
+        Ok(LatencyObjective {
+            budget: Duration::from_millis(config.budget_ms),
+            requests: counter!("shotover_slo_requests_count", "chain" => source_name.to_owned()),
+            over_budget_shotover: counter!("shotover_slo_over_budget_count", "chain" => source_name.to_owned(), "cause" => "shotover"),
+            over_budget_upstream: counter!("shotover_slo_over_budget_count", "chain" => source_name.to_owned(), "cause" => "upstream"),
+            over_budget_shotover_seconds: histogram!("shotover_slo_over_budget_seconds", "chain" => source_name.to_owned(), "component" => "shotover"),
+            over_budget_upstream_seconds: histogram!("shotover_slo_over_budget_seconds", "chain" => source_name.to_owned(), "component" => "upstream"),
+        })
+    }
+
+    /// Returns how the latency of a request over budget was spent, or None if the request was within budget.
+    fn measure(
+        &self,
+        pending: &PendingRequest,
+        response: &Message,
+        now: Instant,
+    ) -> Option<Overage> {
+        let latency = now.duration_since(pending.received_at);
+        if latency <= self.budget {
+            return None;
+        }
+
+        // Responses generated by shotover, such as cache hits, spent no time upstream
+        let upstream = match (pending.sent_at, response.received_from_source_or_sink_at) {
+            (Some(sent_at), Some(received_at)) => {
+                received_at.saturating_duration_since(sent_at).min(latency)
+            }
+            _ => Duration::ZERO,
+        };
+        Some(Overage {
+            cause: if upstream > self.budget {
+                Cause::Upstream
+            } else {
+                Cause::Shotover
+            },
+            shotover: latency - upstream,
+            upstream,
+        })
+    }
+}
+
+/// Requests that have not been responded to for this long are forgotten, they may never receive a response.
+const PENDING_EXPIRY: Duration = Duration::from_secs(60);
+
+/// The number of pending requests of a connection above which expired requests are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct PendingRequest {
+    received_at: Instant,
+    /// When the request reached the terminating transform of the chain, None until the chain run that the request was sent in completes.
+    sent_at: Option<Instant>,
+}
+
+/// Tracks the requests of a single connection until they are responded to.
+pub(crate) struct LatencyTracker {
+    objective: Arc<LatencyObjective>,
+    pending: MessageIdMap<PendingRequest>,
+    /// Requests of the current chain run.
+    unsent: Vec<MessageId>,
+}
+
+impl LatencyTracker {
+    pub(crate) fn new(objective: Arc<LatencyObjective>) -> Self {
+        LatencyTracker {
+            objective,
+            pending: MessageIdMap::default(),
+            unsent: vec![],
+        }
+    }
+
+    /// Records the requests about to be sent through the chain.
+    pub(crate) fn on_requests(&mut self, requests: &[Message]) {
+        let now = Instant::now();
+        if self.pending.len() > PRUNE_THRESHOLD {
+            self.pending
+                .retain(|_, pending| now.duration_since(pending.received_at) < PENDING_EXPIRY);
+        }
+        for request in requests {
+            self.pending.insert(
+                request.id(),
+                PendingRequest {
+                    received_at: request.received_from_source_or_sink_at.unwrap_or(now),
+                    sent_at: None,
+                },
+            );
+            self.unsent.push(request.id());
+        }
+    }
+
+    /// Records the completion of a chain run, measuring every request that was responded to.
+    pub(crate) fn on_responses(&mut self, sink_reached_at: Option<Instant>, responses: &[Message]) {
+        for id in self.unsent.drain(..) {
+            if let Some(pending) = self.pending.get_mut(&id) {
+                pending.sent_at = sink_reached_at;
+            }
+        }
+
+        let now = Instant::now();
+        for response in responses {
+            let Some(pending) = response
+                .request_id()
+                .and_then(|id| self.pending.remove(&id))
+            else {
+                continue;
+            };
+            self.objective.requests.increment(1);
+            if let Some(overage) = self.objective.measure(&pending, response, now) {
+                match overage.cause {
+                    Cause::Shotover => self.objective.over_budget_shotover.increment(1),
+                    Cause::Upstream => self.objective.over_budget_upstream.increment(1),
+                }
+                self.objective
+                    .over_budget_shotover_seconds
+                    .record(overage.shotover.as_secs_f64());
+                self.objective
+                    .over_budget_upstream_seconds
+                    .record(overage.upstream.as_secs_f64());
+            }
+        }
+    }
+
+    /// Forgets every pending request, for when the chain they were sent to is discarded.
+    pub(crate) fn clear(&mut self) {
+        self.pending.clear();
+        self.unsent.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::Frame;
+    use pretty_assertions::assert_eq;
+
+    fn response(received_at: Option<Instant>) -> Message {
+        Message::from_frame_at_instant(Frame::Dummy, received_at)
+    }
+
+    #[test]
+    fn test_measure() {
+        let objective =
+            LatencyObjective::new(&LatencyObjectiveConfig { budget_ms: 100 }, "foo").unwrap();
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let pending = |received_ago, sent_ago| PendingRequest {
+            received_at: now - ms(received_ago),
+            sent_at: Some(now - ms(sent_ago)),
+        };
+
+        assert_eq!(
+            objective.measure(&pending(50, 40), &response(Some(now)), now),
+            None
+        );
+        assert_eq!(
+            objective.measure(&pending(200, 180), &response(Some(now - ms(30))), now),
+            Some(Overage {
+                cause: Cause::Upstream,
+                shotover: ms(50),
+                upstream: ms(150),
+            })
+        );
+        assert_eq!(
+            objective.measure(&pending(200, 90), &response(Some(now - ms(30))), now),
+            Some(Overage {
+                cause: Cause::Shotover,
+                shotover: ms(140),
+                upstream: ms(60),
+            })
+        );
+        assert_eq!(
+            objective.measure(&pending(200, 180), &response(None), now),
+            Some(Overage {
+                cause: Cause::Shotover,
+                shotover: ms(200),
+                upstream: ms(0),
+            })
+        );
+    }
+
+    #[test]
+    fn test_tracker_forgets_responded_requests() {
+        let objective =
+            LatencyObjective::new(&LatencyObjectiveConfig { budget_ms: 100 }, "foo").unwrap();
+        let mut tracker = LatencyTracker::new(Arc::new(objective));
+        let requests = vec![response(None), response(None)];
+        tracker.on_requests(&requests);
+
+        let mut responses = vec![response(Some(Instant::now()))];
+        responses[0].set_request_id(requests[0].id());
+        tracker.on_responses(Some(Instant::now()), &responses);
+        assert_eq!(tracker.pending.len(), 1);
+        assert!(tracker.pending[&requests[1].id()].sent_at.is_some());
+        assert!(tracker.unsent.is_empty());
+    }
+}
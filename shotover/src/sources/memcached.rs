@@ -0,0 +1,133 @@
+use crate::codec::{memcached::MemcachedCodecBuilder, CodecBuilder, Direction};
+use crate::config::chain::TransformChainConfig;
+use crate::server::TcpCodecListener;
+use crate::sources::chain_error_policy::ChainErrorPolicyConfig;
+use crate::sources::client_throttle::ClientThrottleConfig;
+use crate::sources::ip_filter::IpFilterConfig;
+use crate::sources::latency_objective::LatencyObjectiveConfig;
+use crate::sources::{Source, Transport};
+use crate::tcp::TcpConfig;
+use crate::tls::{TlsAcceptor, TlsAcceptorConfig};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MemcachedConfig {
+    pub name: String,
+    pub listen_addr: String,
+    pub connection_limit: Option<usize>,
+    pub hard_connection_limit: Option<bool>,
+    pub tls: Option<TlsAcceptorConfig>,
+    pub timeout: Option<u64>,
+    /// Requests storing values larger than this many bytes are rejected, closing the connection. Defaults to 1MiB.
+    pub max_item_size_bytes: Option<usize>,
+    /// Restricts the IP addresses that clients may connect from.
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Bans or tarpits client IP addresses that open connections or send requests too quickly.
+    pub client_throttle: Option<ClientThrottleConfig>,
+    /// Tuning of the TCP connections accepted from clients.
+    pub tcp: Option<TcpConfig>,
+    /// What happens to a client connection when a transform in the chain returns an error.
+    pub chain_error_policy: Option<ChainErrorPolicyConfig>,
+    /// Measures the latency of every request against a budget.
+    pub latency_objective: Option<LatencyObjectiveConfig>,
+    pub chain: TransformChainConfig,
+}
+
+impl MemcachedConfig {
+    pub async fn get_source(
+        &self,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Source, Vec<String>> {
+        Ok(Source::Memcached(
+            MemcachedSource::new(
+                self.name.clone(),
+                &self.chain,
+                self.listen_addr.clone(),
+                trigger_shutdown_rx,
+                self.connection_limit,
+                self.hard_connection_limit,
+                self.tls.clone(),
+                self.timeout,
+                self.max_item_size_bytes,
+                self.ip_filter.clone(),
+                self.client_throttle.clone(),
+                self.tcp.clone(),
+                self.chain_error_policy.as_ref(),
+                self.latency_objective.clone(),
+            )
+            .await?,
+        ))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemcachedSource {
+    pub join_handle: JoinHandle<()>,
+}
+
+impl MemcachedSource {
+    #![allow(clippy::too_many_arguments)]
+    pub async fn new(
+        name: String,
+        chain_config: &TransformChainConfig,
+        listen_addr: String,
+        mut trigger_shutdown_rx: watch::Receiver<bool>,
+        connection_limit: Option<usize>,
+        hard_connection_limit: Option<bool>,
+        tls: Option<TlsAcceptorConfig>,
+        timeout: Option<u64>,
+        max_item_size_bytes: Option<usize>,
+        ip_filter: Option<IpFilterConfig>,
+        client_throttle: Option<ClientThrottleConfig>,
+        tcp: Option<TcpConfig>,
+        chain_error_policy: Option<&ChainErrorPolicyConfig>,
+        latency_objective: Option<LatencyObjectiveConfig>,
+    ) -> Result<MemcachedSource, Vec<String>> {
+        info!("Starting Memcached source on [{}]", listen_addr);
+
+        let mut listener = TcpCodecListener::new(
+            chain_config,
+            name.clone(),
+            Some(listen_addr.clone()),
+            hard_connection_limit.unwrap_or(false),
+            MemcachedCodecBuilder::new(Direction::Source, name)
+                .with_max_item_size(max_item_size_bytes),
+            Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
+            trigger_shutdown_rx.clone(),
+            tls.as_ref().map(TlsAcceptor::new).transpose()?,
+            timeout.map(Duration::from_secs),
+            Transport::Tcp,
+            ip_filter.as_ref(),
+            client_throttle.as_ref(),
+            tcp.as_ref(),
+            chain_error_policy,
+            latency_objective.as_ref(),
+        )
+        .await?;
+
+        let join_handle = tokio::spawn(async move {
+            // Check we didn't receive a shutdown signal before the receiver was created
+            if !*trigger_shutdown_rx.borrow() {
+                tokio::select! {
+                    res = listener.run() => {
+                        if let Err(err) = res {
+                            error!(cause = %err, "failed to accept connection");
+                        }
+                    }
+                    _ = trigger_shutdown_rx.changed() => {
+                        listener.shutdown().await;
+                    }
+                }
+            }
+        });
+
+        Ok(MemcachedSource { join_handle })
+    }
+}
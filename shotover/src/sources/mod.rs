@@ -4,6 +4,10 @@
 use crate::sources::cassandra::{CassandraConfig, CassandraSource};
 #[cfg(feature = "kafka")]
 use crate::sources::kafka::{KafkaConfig, KafkaSource};
+#[cfg(feature = "memcached")]
+use crate::sources::memcached::{MemcachedConfig, MemcachedSource};
+#[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+use crate::sources::multi_protocol::{MultiProtocolConfig, MultiProtocolSource};
 #[cfg(feature = "opensearch")]
 use crate::sources::opensearch::{OpenSearchConfig, OpenSearchSource};
 #[cfg(feature = "redis")]
@@ -15,8 +19,16 @@ use tokio::task::JoinHandle;
 
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
+pub mod chain_error_policy;
+pub mod client_throttle;
+pub mod ip_filter;
 #[cfg(feature = "kafka")]
 pub mod kafka;
+pub mod latency_objective;
+#[cfg(feature = "memcached")]
+pub mod memcached;
+#[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+pub mod multi_protocol;
 #[cfg(feature = "opensearch")]
 pub mod opensearch;
 #[cfg(feature = "redis")]
@@ -39,6 +51,10 @@ pub enum Source {
     Kafka(KafkaSource),
     #[cfg(feature = "opensearch")]
     OpenSearch(OpenSearchSource),
+    #[cfg(feature = "memcached")]
+    Memcached(MemcachedSource),
+    #[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+    MultiProtocol(MultiProtocolSource),
 }
 
 impl Source {
@@ -52,6 +68,10 @@ impl Source {
             Source::Kafka(r) => r.join_handle,
             #[cfg(feature = "opensearch")]
             Source::OpenSearch(o) => o.join_handle,
+            #[cfg(feature = "memcached")]
+            Source::Memcached(m) => m.join_handle,
+            #[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+            Source::MultiProtocol(m) => m.join_handle,
         }
     }
 }
@@ -67,6 +87,10 @@ pub enum SourceConfig {
     Kafka(KafkaConfig),
     #[cfg(feature = "opensearch")]
     OpenSearch(OpenSearchConfig),
+    #[cfg(feature = "memcached")]
+    Memcached(MemcachedConfig),
+    #[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+    MultiProtocol(MultiProtocolConfig),
 }
 
 impl SourceConfig {
@@ -83,6 +107,10 @@ impl SourceConfig {
             SourceConfig::Kafka(r) => r.get_source(trigger_shutdown_rx).await,
             #[cfg(feature = "opensearch")]
             SourceConfig::OpenSearch(r) => r.get_source(trigger_shutdown_rx).await,
+            #[cfg(feature = "memcached")]
+            SourceConfig::Memcached(m) => m.get_source(trigger_shutdown_rx).await,
+            #[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+            SourceConfig::MultiProtocol(m) => m.get_source(trigger_shutdown_rx).await,
         }
     }
 
@@ -96,6 +124,10 @@ impl SourceConfig {
             SourceConfig::Kafka(r) => &r.name,
             #[cfg(feature = "opensearch")]
             SourceConfig::OpenSearch(r) => &r.name,
+            #[cfg(feature = "memcached")]
+            SourceConfig::Memcached(m) => &m.name,
+            #[cfg(any(feature = "redis", feature = "cassandra", feature = "kafka"))]
+            SourceConfig::MultiProtocol(m) => &m.name,
         }
     }
 }
@@ -0,0 +1,490 @@
+//! A source that accepts Redis, Cassandra and Kafka clients on a single address.
+//!
+//! The protocol of each connection is detected from the first bytes the client sends,
+//! after which the connection is handed over to the chain configured for that protocol.
+
+#[cfg(feature = "cassandra")]
+use crate::codec::cassandra::CassandraCodecBuilder;
+#[cfg(feature = "kafka")]
+use crate::codec::kafka::KafkaCodecBuilder;
+#[cfg(feature = "redis")]
+use crate::codec::redis::RedisCodecBuilder;
+use crate::codec::{CodecBuilder, Direction};
+use crate::config::chain::TransformChainConfig;
+use crate::server::{create_listener, TcpCodecListener};
+use crate::sources::chain_error_policy::ChainErrorPolicyConfig;
+use crate::sources::client_throttle::ClientThrottleConfig;
+use crate::sources::ip_filter::IpFilterConfig;
+use crate::sources::latency_objective::LatencyObjectiveConfig;
+use crate::sources::{Source, Transport};
+use crate::tcp::TcpConfig;
+use anyhow::{anyhow, Result};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct MultiProtocolConfig {
+    pub name: String,
+    pub listen_addr: String,
+    /// The number of concurrent connections accepted across all protocols.
+    pub connection_limit: Option<usize>,
+    pub timeout: Option<u64>,
+    /// How long a client has to send enough bytes for its protocol to be detected, defaults to 10000.
+    pub sniff_timeout_ms: Option<u64>,
+    /// Restricts the IP addresses that clients may connect from.
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Bans or tarpits client IP addresses that open connections or send requests too quickly.
+    pub client_throttle: Option<ClientThrottleConfig>,
+    /// Tuning of the TCP connections accepted from clients.
+    pub tcp: Option<TcpConfig>,
+    /// What happens to a client connection when a transform in the chain returns an error.
+    pub chain_error_policy: Option<ChainErrorPolicyConfig>,
+    /// Measures the latency of every request against a budget.
+    pub latency_objective: Option<LatencyObjectiveConfig>,
+    #[cfg(feature = "redis")]
+    pub redis: Option<ProtocolChainConfig>,
+    #[cfg(feature = "cassandra")]
+    pub cassandra: Option<ProtocolChainConfig>,
+    #[cfg(feature = "kafka")]
+    pub kafka: Option<ProtocolChainConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ProtocolChainConfig {
+    pub chain: TransformChainConfig,
+}
+
+impl MultiProtocolConfig {
+    pub async fn get_source(
+        &self,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Source, Vec<String>> {
+        Ok(Source::MultiProtocol(
+            MultiProtocolSource::new(self, trigger_shutdown_rx).await?,
+        ))
+    }
+
+    async fn listener<C: CodecBuilder + 'static>(
+        &self,
+        chain: &ProtocolChainConfig,
+        source_name: String,
+        codec: C,
+        limit_connections: Arc<Semaphore>,
+        trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<TcpCodecListener<C>, Vec<String>> {
+        TcpCodecListener::new(
+            &chain.chain,
+            source_name,
+            None,
+            false,
+            codec,
+            limit_connections,
+            trigger_shutdown_rx,
+            None,
+            self.timeout.map(Duration::from_secs),
+            Transport::Tcp,
+            self.ip_filter.as_ref(),
+            self.client_throttle.as_ref(),
+            self.tcp.as_ref(),
+            self.chain_error_policy.as_ref(),
+            self.latency_objective.as_ref(),
+        )
+        .await
+    }
+}
+
+#[derive(Debug)]
+pub struct MultiProtocolSource {
+    pub join_handle: JoinHandle<()>,
+}
+
+impl MultiProtocolSource {
+    pub async fn new(
+        config: &MultiProtocolConfig,
+        mut trigger_shutdown_rx: watch::Receiver<bool>,
+    ) -> Result<Self, Vec<String>> {
+        info!("Starting MultiProtocol source on [{}]", config.listen_addr);
+
+        let name = &config.name;
+        let limit_connections = Arc::new(Semaphore::new(config.connection_limit.unwrap_or(512)));
+        let mut errors = vec![];
+
+        #[cfg(feature = "redis")]
+        let redis = match &config.redis {
+            Some(chain) => {
+                let source_name = format!("{name}-redis");
+                let codec = RedisCodecBuilder::new(Direction::Source, source_name.clone());
+                config
+                    .listener(
+                        chain,
+                        source_name,
+                        codec,
+                        limit_connections.clone(),
+                        trigger_shutdown_rx.clone(),
+                    )
+                    .await
+                    .map_err(|e| errors.extend(e))
+                    .ok()
+            }
+            None => None,
+        };
+        #[cfg(feature = "cassandra")]
+        let cassandra = match &config.cassandra {
+            Some(chain) => {
+                let source_name = format!("{name}-cassandra");
+                let codec = CassandraCodecBuilder::new(Direction::Source, source_name.clone());
+                config
+                    .listener(
+                        chain,
+                        source_name,
+                        codec,
+                        limit_connections.clone(),
+                        trigger_shutdown_rx.clone(),
+                    )
+                    .await
+                    .map_err(|e| errors.extend(e))
+                    .ok()
+            }
+            None => None,
+        };
+        #[cfg(feature = "kafka")]
+        let kafka = match &config.kafka {
+            Some(chain) => {
+                let source_name = format!("{name}-kafka");
+                let codec = KafkaCodecBuilder::new(Direction::Source, source_name.clone());
+                config
+                    .listener(
+                        chain,
+                        source_name,
+                        codec,
+                        limit_connections.clone(),
+                        trigger_shutdown_rx.clone(),
+                    )
+                    .await
+                    .map_err(|e| errors.extend(e))
+                    .ok()
+            }
+            None => None,
+        };
+
+        let mut listeners = Listeners {
+            #[cfg(feature = "redis")]
+            redis,
+            #[cfg(feature = "cassandra")]
+            cassandra,
+            #[cfg(feature = "kafka")]
+            kafka,
+        };
+        if listeners.is_empty() && errors.is_empty() {
+            errors.push(format!("{name} source:"));
+            errors
+                .push("  at least one of redis, cassandra or kafka must be configured".to_owned());
+        }
+
+        let listener = match create_listener(name, &config.listen_addr).await {
+            Ok(listener) => Some(listener),
+            Err(error) => {
+                errors.push(format!("{name} source:"));
+                errors.push(format!("{error:?}"));
+                None
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut sniffer = Sniffer {
+            listener: listener.unwrap(),
+            limit_connections,
+            sniff_timeout: Duration::from_millis(config.sniff_timeout_ms.unwrap_or(10_000)),
+            unrecognized: counter!("shotover_unrecognized_protocol_connections_count", "source" => name.clone()),
+        };
+
+        let join_handle = tokio::spawn(async move {
+            // Check we didn't receive a shutdown signal before the receiver was created
+            if !*trigger_shutdown_rx.borrow() {
+                tokio::select! {
+                    res = sniffer.run(&mut listeners) => {
+                        if let Err(err) = res {
+                            error!(cause = %err, "failed to accept connection");
+                        }
+                    }
+                    _ = trigger_shutdown_rx.changed() => {
+                        listeners.shutdown().await;
+                    }
+                }
+            }
+        });
+
+        Ok(MultiProtocolSource { join_handle })
+    }
+}
+
+/// The listeners that connections are handed over to once their protocol is known.
+struct Listeners {
+    #[cfg(feature = "redis")]
+    redis: Option<TcpCodecListener<RedisCodecBuilder>>,
+    #[cfg(feature = "cassandra")]
+    cassandra: Option<TcpCodecListener<CassandraCodecBuilder>>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<TcpCodecListener<KafkaCodecBuilder>>,
+}
+
+impl Listeners {
+    fn is_empty(&self) -> bool {
+        let mut empty = true;
+        #[cfg(feature = "redis")]
+        {
+            empty &= self.redis.is_none();
+        }
+        #[cfg(feature = "cassandra")]
+        {
+            empty &= self.cassandra.is_none();
+        }
+        #[cfg(feature = "kafka")]
+        {
+            empty &= self.kafka.is_none();
+        }
+        empty
+    }
+
+    /// Returns false if no chain is configured for the protocol, in which case the connection is dropped.
+    fn dispatch(
+        &mut self,
+        protocol: SniffedProtocol,
+        stream: TcpStream,
+        permit: OwnedSemaphorePermit,
+    ) -> bool {
+        match protocol {
+            #[cfg(feature = "redis")]
+            SniffedProtocol::Redis => {
+                if let Some(listener) = &mut self.redis {
+                    listener.handle_connection(stream, permit);
+                    return true;
+                }
+            }
+            #[cfg(feature = "cassandra")]
+            SniffedProtocol::Cassandra => {
+                if let Some(listener) = &mut self.cassandra {
+                    listener.handle_connection(stream, permit);
+                    return true;
+                }
+            }
+            #[cfg(feature = "kafka")]
+            SniffedProtocol::Kafka => {
+                if let Some(listener) = &mut self.kafka {
+                    listener.handle_connection(stream, permit);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    async fn shutdown(&mut self) {
+        #[cfg(feature = "redis")]
+        if let Some(listener) = &mut self.redis {
+            listener.shutdown().await;
+        }
+        #[cfg(feature = "cassandra")]
+        if let Some(listener) = &mut self.cassandra {
+            listener.shutdown().await;
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(listener) = &mut self.kafka {
+            listener.shutdown().await;
+        }
+    }
+}
+
+struct Sniffer {
+    listener: TcpListener,
+    limit_connections: Arc<Semaphore>,
+    sniff_timeout: Duration,
+    unrecognized: Counter,
+}
+
+type Sniffed = (SniffedProtocol, TcpStream, OwnedSemaphorePermit);
+
+impl Sniffer {
+    /// Accepts connections and sniffs each one in its own task so that a slow client cannot hold up other clients.
+    /// Sniffed connections are sent back to this task, which hands them to the listener for their protocol.
+    async fn run(&mut self, listeners: &mut Listeners) -> Result<()> {
+        let (sniffed_tx, mut sniffed_rx) = mpsc::unbounded_channel::<Sniffed>();
+        // Connections that are being sniffed hold a permit, so sniffed connections must still be dispatched while waiting for a permit.
+        let mut permit = None;
+        loop {
+            tokio::select! {
+                new_permit = self.limit_connections.clone().acquire_owned(), if permit.is_none() => {
+                    permit = Some(new_permit?);
+                }
+                accepted = self.listener.accept(), if permit.is_some() => {
+                    let (stream, peer) = accepted?;
+                    let permit = permit.take().unwrap();
+                    let sniffed_tx = sniffed_tx.clone();
+                    let sniff_timeout = self.sniff_timeout;
+                    let unrecognized = self.unrecognized.clone();
+                    tokio::spawn(async move {
+                        match tokio::time::timeout(sniff_timeout, sniff_stream(&stream)).await {
+                            Ok(Ok(Some(protocol))) => {
+                                sniffed_tx.send((protocol, stream, permit)).ok();
+                            }
+                            Ok(Ok(None)) => {
+                                unrecognized.increment(1);
+                                debug!("closing connection from {peer} as its protocol could not be recognized");
+                            }
+                            Ok(Err(err)) => {
+                                debug!("closing connection from {peer} as it failed before its protocol could be recognized: {err}");
+                            }
+                            Err(_) => {
+                                unrecognized.increment(1);
+                                debug!("closing connection from {peer} as it did not send enough bytes to recognize its protocol within {sniff_timeout:?}");
+                            }
+                        }
+                    });
+                }
+                Some((protocol, stream, permit)) = sniffed_rx.recv() => {
+                    if !listeners.dispatch(protocol, stream, permit) {
+                        self.unrecognized.increment(1);
+                        debug!("closing {protocol:?} connection as no chain is configured for it");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Waits until the client has sent enough bytes to recognize its protocol, without consuming them.
+async fn sniff_stream(stream: &TcpStream) -> Result<Option<SniffedProtocol>> {
+    let mut buf = [0; 8];
+    loop {
+        let len = stream.peek(&mut buf).await?;
+        if len == 0 {
+            return Err(anyhow!("connection closed"));
+        }
+        match sniff(&buf[..len]) {
+            Sniff::Protocol(protocol) => return Ok(Some(protocol)),
+            Sniff::Unknown => return Ok(None),
+            // peek returns immediately while any bytes are buffered, so wait for the rest to arrive
+            Sniff::Incomplete => tokio::time::sleep(Duration::from_millis(5)).await,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SniffedProtocol {
+    #[cfg(feature = "redis")]
+    Redis,
+    #[cfg(feature = "cassandra")]
+    Cassandra,
+    #[cfg(feature = "kafka")]
+    Kafka,
+}
+
+#[derive(Debug, PartialEq)]
+enum Sniff {
+    Protocol(SniffedProtocol),
+    Incomplete,
+    Unknown,
+}
+
+/// Recognizes the protocol of a connection from the first message a client sends.
+///
+/// * Redis clients send commands as RESP arrays, which start with `*`.
+/// * Cassandra clients start with an OPTIONS or STARTUP request, whose header is a request version between 1 and 5 followed by the opcode in the 5th byte.
+/// * Kafka clients start with a request whose 4 byte length prefix is well below 16MB and so starts with 0, followed by a known api key.
+fn sniff(bytes: &[u8]) -> Sniff {
+    let Some(&first) = bytes.first() else {
+        return Sniff::Incomplete;
+    };
+    match first {
+        #[cfg(feature = "redis")]
+        b'*' => Sniff::Protocol(SniffedProtocol::Redis),
+        #[cfg(feature = "cassandra")]
+        1..=5 => match bytes.get(4) {
+            // STARTUP or OPTIONS
+            Some(0x01 | 0x05) => Sniff::Protocol(SniffedProtocol::Cassandra),
+            Some(_) => Sniff::Unknown,
+            None => Sniff::Incomplete,
+        },
+        #[cfg(feature = "kafka")]
+        0 => match bytes.get(4..8) {
+            Some(header) => {
+                let api_key = i16::from_be_bytes([header[0], header[1]]);
+                let api_version = i16::from_be_bytes([header[2], header[3]]);
+                if kafka_protocol::messages::ApiKey::try_from(api_key).is_ok() && api_version >= 0 {
+                    Sniff::Protocol(SniffedProtocol::Kafka)
+                } else {
+                    Sniff::Unknown
+                }
+            }
+            None => Sniff::Incomplete,
+        },
+        _ => Sniff::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_sniff_redis() {
+        assert_eq!(
+            sniff(b"*1\r\n$4\r\nPING\r\n"),
+            Sniff::Protocol(SniffedProtocol::Redis)
+        );
+        assert_eq!(sniff(b"PING\r\n"), Sniff::Unknown);
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_sniff_cassandra() {
+        // v4 OPTIONS
+        assert_eq!(
+            sniff(&[0x04, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00]),
+            Sniff::Protocol(SniffedProtocol::Cassandra)
+        );
+        // v5 STARTUP
+        assert_eq!(
+            sniff(&[0x05, 0x00, 0x00, 0x01, 0x01]),
+            Sniff::Protocol(SniffedProtocol::Cassandra)
+        );
+        // QUERY cannot be the first request of a connection
+        assert_eq!(sniff(&[0x04, 0x00, 0x00, 0x00, 0x07]), Sniff::Unknown);
+        assert_eq!(sniff(&[0x04, 0x00, 0x00]), Sniff::Incomplete);
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn test_sniff_kafka() {
+        // ApiVersions v3
+        assert_eq!(
+            sniff(&[0x00, 0x00, 0x00, 0x20, 0x00, 0x12, 0x00, 0x03]),
+            Sniff::Protocol(SniffedProtocol::Kafka)
+        );
+        assert_eq!(
+            sniff(&[0x00, 0x00, 0x00, 0x20, 0x7f, 0x12, 0x00, 0x03]),
+            Sniff::Unknown
+        );
+        assert_eq!(sniff(&[0x00, 0x00, 0x00, 0x20, 0x00]), Sniff::Incomplete);
+    }
+
+    #[test]
+    fn test_sniff_unknown() {
+        assert_eq!(sniff(b""), Sniff::Incomplete);
+        assert_eq!(sniff(b"GET / HTTP/1.1\r\n"), Sniff::Unknown);
+        // TLS client hello
+        assert_eq!(sniff(&[0x16, 0x03, 0x01, 0x02, 0x00]), Sniff::Unknown);
+    }
+}
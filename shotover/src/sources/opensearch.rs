@@ -1,7 +1,12 @@
 use crate::codec::{opensearch::OpenSearchCodecBuilder, CodecBuilder, Direction};
 use crate::config::chain::TransformChainConfig;
 use crate::server::TcpCodecListener;
+use crate::sources::chain_error_policy::ChainErrorPolicyConfig;
+use crate::sources::client_throttle::ClientThrottleConfig;
+use crate::sources::ip_filter::IpFilterConfig;
+use crate::sources::latency_objective::LatencyObjectiveConfig;
 use crate::sources::{Source, Transport};
+use crate::tcp::TcpConfig;
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -10,13 +15,23 @@ use tokio::sync::{watch, Semaphore};
 use tokio::task::JoinHandle;
 use tracing::{error, info};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct OpenSearchConfig {
     pub name: String,
     pub listen_addr: String,
     pub connection_limit: Option<usize>,
     pub hard_connection_limit: Option<bool>,
     pub timeout: Option<u64>,
+    /// Restricts the IP addresses that clients may connect from.
+    pub ip_filter: Option<IpFilterConfig>,
+    /// Bans or tarpits client IP addresses that open connections or send requests too quickly.
+    pub client_throttle: Option<ClientThrottleConfig>,
+    /// Tuning of the TCP connections accepted from clients.
+    pub tcp: Option<TcpConfig>,
+    /// What happens to a client connection when a transform in the chain returns an error.
+    pub chain_error_policy: Option<ChainErrorPolicyConfig>,
+    /// Measures the latency of every request against a budget.
+    pub latency_objective: Option<LatencyObjectiveConfig>,
     pub chain: TransformChainConfig,
 }
 
@@ -34,6 +49,11 @@ impl OpenSearchConfig {
                 self.connection_limit,
                 self.hard_connection_limit,
                 self.timeout,
+                self.ip_filter.clone(),
+                self.client_throttle.clone(),
+                self.tcp.clone(),
+                self.chain_error_policy.as_ref(),
+                self.latency_objective.clone(),
             )
             .await?,
         ))
@@ -46,6 +66,7 @@ pub struct OpenSearchSource {
 }
 
 impl OpenSearchSource {
+    #![allow(clippy::too_many_arguments)]
     pub async fn new(
         name: String,
         chain_config: &TransformChainConfig,
@@ -54,13 +75,18 @@ impl OpenSearchSource {
         connection_limit: Option<usize>,
         hard_connection_limit: Option<bool>,
         timeout: Option<u64>,
+        ip_filter: Option<IpFilterConfig>,
+        client_throttle: Option<ClientThrottleConfig>,
+        tcp: Option<TcpConfig>,
+        chain_error_policy: Option<&ChainErrorPolicyConfig>,
+        latency_objective: Option<LatencyObjectiveConfig>,
     ) -> Result<Self, Vec<String>> {
         info!("Starting OpenSearch source on [{}]", listen_addr);
 
         let mut listener = TcpCodecListener::new(
             chain_config,
             name.to_string(),
-            listen_addr.clone(),
+            Some(listen_addr.clone()),
             hard_connection_limit.unwrap_or(false),
             OpenSearchCodecBuilder::new(Direction::Source, name),
             Arc::new(Semaphore::new(connection_limit.unwrap_or(512))),
@@ -68,6 +94,11 @@ impl OpenSearchSource {
             None,
             timeout.map(Duration::from_secs),
             Transport::Tcp,
+            ip_filter.as_ref(),
+            client_throttle.as_ref(),
+            tcp.as_ref(),
+            chain_error_policy,
+            latency_objective.as_ref(),
         )
         .await?;
 
@@ -1,17 +1,99 @@
-//! Use to establish a TCP connection to a DB in a sink transform
+//! Use to establish a TCP connection to a DB in a sink transform, along with the TCP tuning shared by sources and sinks
 
 use anyhow::{anyhow, Context, Result};
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use itertools::Itertools;
+use rustix::net::sockopt;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::os::fd::AsFd;
 use std::time::Duration;
 use tokio::{
-    net::{TcpStream, ToSocketAddrs},
+    net::{lookup_host, TcpSocket, TcpStream, ToSocketAddrs},
     time::timeout,
 };
 
+/// Tuning of the TCP sockets of a source or sink.
+/// Any option that is not provided is left at its default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TcpConfig {
+    /// Sets TCP_NODELAY, disabling Nagle's algorithm so that small messages are sent without delay.
+    /// Sources default to true, sinks default to the operating system's default.
+    pub nodelay: Option<bool>,
+    /// Enables TCP keepalive probes on idle connections.
+    pub keepalive: Option<TcpKeepaliveConfig>,
+    /// The size in bytes of the receive buffer of each socket (SO_RCVBUF).
+    pub recv_buffer_size: Option<usize>,
+    /// The size in bytes of the send buffer of each socket (SO_SNDBUF).
+    pub send_buffer_size: Option<usize>,
+    /// Only used by sinks. When a destination resolves to multiple addresses, how long to wait on a connection attempt
+    /// before also attempting the next address. Defaults to 250ms as recommended by RFC 8305.
+    pub connection_attempt_delay_ms: Option<u64>,
+    /// Only used by sinks. How long a connection attempt to a single address may take before it is abandoned.
+    /// When not provided, attempts are only limited by the connect timeout of the sink.
+    pub connection_attempt_timeout_ms: Option<u64>,
+}
+
+/// Any option that is not provided uses the operating system's default.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TcpKeepaliveConfig {
+    /// Seconds that a connection must be idle before the first keepalive probe is sent (TCP_KEEPIDLE).
+    pub time_secs: Option<u64>,
+    /// Seconds between keepalive probes (TCP_KEEPINTVL).
+    pub interval_secs: Option<u64>,
+    /// The number of unacknowledged keepalive probes after which the connection is closed (TCP_KEEPCNT).
+    pub probes: Option<u32>,
+}
+
+impl TcpConfig {
+    /// Applies every option to a connection accepted by a source.
+    pub(crate) fn apply_to_accepted(&self, stream: &TcpStream) -> io::Result<()> {
+        self.apply_buffer_sizes(stream)?;
+        self.apply_to_connected(stream)
+    }
+
+    /// The buffer sizes are applied before connecting so that they are accounted for by the TCP window negotiated during the handshake.
+    fn apply_buffer_sizes(&self, socket: impl AsFd) -> io::Result<()> {
+        if let Some(size) = self.recv_buffer_size {
+            sockopt::set_socket_recv_buffer_size(&socket, size)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            sockopt::set_socket_send_buffer_size(&socket, size)?;
+        }
+        Ok(())
+    }
+
+    fn apply_to_connected(&self, socket: impl AsFd) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            sockopt::set_tcp_nodelay(&socket, nodelay)?;
+        }
+        if let Some(keepalive) = &self.keepalive {
+            sockopt::set_socket_keepalive(&socket, true)?;
+            if let Some(time) = keepalive.time_secs {
+                sockopt::set_tcp_keepidle(&socket, Duration::from_secs(time))?;
+            }
+            if let Some(interval) = keepalive.interval_secs {
+                sockopt::set_tcp_keepintvl(&socket, Duration::from_secs(interval))?;
+            }
+            if let Some(probes) = keepalive.probes {
+                sockopt::set_tcp_keepcnt(&socket, probes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub async fn tcp_stream<A: ToSocketAddrs + std::fmt::Debug>(
     connect_timeout: Duration,
     destination: A,
+    config: &TcpConfig,
 ) -> Result<TcpStream> {
-    timeout(connect_timeout, TcpStream::connect(&destination))
+    timeout(connect_timeout, connect(&destination, config))
         .await
         .map_err(|_| {
             anyhow!(
@@ -20,3 +102,182 @@ pub async fn tcp_stream<A: ToSocketAddrs + std::fmt::Debug>(
         })?
         .with_context(|| format!("Failed to connect to destination {destination:?}"))
 }
+
+/// Races connection attempts to every address that the destination resolves to, returning the first connection made.
+///
+/// As described by RFC 8305 (happy eyeballs), the addresses alternate between IPv6 and IPv4, starting with the family of the first address.
+/// Each attempt is started once the previous attempt fails or `connection_attempt_delay_ms` has passed since it started,
+/// so an unreachable address delays the connection by at most the attempt delay.
+async fn connect<A: ToSocketAddrs>(destination: &A, config: &TcpConfig) -> io::Result<TcpStream> {
+    let mut addresses = interleave_families(lookup_host(destination).await?.collect());
+    let attempt_delay = Duration::from_millis(config.connection_attempt_delay_ms.unwrap_or(250));
+
+    let mut attempts = FuturesUnordered::new();
+    let mut last_err = None;
+    loop {
+        if attempts.is_empty() {
+            match addresses.pop_front() {
+                Some(address) => attempts.push(connect_attempt(address, config)),
+                None => break,
+            }
+        }
+        tokio::select! {
+            result = attempts.next() => match result.unwrap() {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    last_err = Some(err);
+                    if let Some(address) = addresses.pop_front() {
+                        attempts.push(connect_attempt(address, config));
+                    }
+                }
+            },
+            _ = tokio::time::sleep(attempt_delay), if !addresses.is_empty() => {
+                attempts.push(connect_attempt(addresses.pop_front().unwrap(), config));
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+/// Orders addresses to alternate between address families, starting with the family of the first address.
+fn interleave_families(addresses: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let Some(first) = addresses.first() else {
+        return VecDeque::new();
+    };
+    let first_is_ipv6 = first.is_ipv6();
+    let (first_family, other_family): (Vec<_>, Vec<_>) = addresses
+        .into_iter()
+        .partition(|x| x.is_ipv6() == first_is_ipv6);
+    first_family.into_iter().interleave(other_family).collect()
+}
+
+async fn connect_attempt(address: SocketAddr, config: &TcpConfig) -> io::Result<TcpStream> {
+    let result = match config.connection_attempt_timeout_ms {
+        Some(attempt_timeout) => {
+            let attempt_timeout = Duration::from_millis(attempt_timeout);
+            timeout(attempt_timeout, connect_address(address, config))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(io::Error::new(
+                        ErrorKind::TimedOut,
+                        format!("connection attempt timed out after {attempt_timeout:?}"),
+                    ))
+                })
+        }
+        None => connect_address(address, config).await,
+    };
+    // include the address in the error since the destination may resolve to many addresses
+    result.map_err(|err| io::Error::new(err.kind(), format!("{address}: {err}")))
+}
+
+async fn connect_address(address: SocketAddr, config: &TcpConfig) -> io::Result<TcpStream> {
+    let socket = match address {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    config.apply_buffer_sizes(&socket)?;
+    let stream = socket.connect(address).await?;
+    config.apply_to_connected(&stream)?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_tcp_config_applied() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = TcpConfig {
+            nodelay: Some(true),
+            keepalive: Some(TcpKeepaliveConfig {
+                time_secs: Some(30),
+                interval_secs: Some(5),
+                probes: Some(3),
+            }),
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            connection_attempt_delay_ms: None,
+            connection_attempt_timeout_ms: None,
+        };
+
+        let stream = tcp_stream(
+            Duration::from_secs(3),
+            listener.local_addr().unwrap(),
+            &config,
+        )
+        .await
+        .unwrap();
+        assert!(sockopt::get_tcp_nodelay(&stream).unwrap());
+        assert!(sockopt::get_socket_keepalive(&stream).unwrap());
+        assert_eq!(
+            sockopt::get_tcp_keepidle(&stream).unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            sockopt::get_tcp_keepintvl(&stream).unwrap(),
+            Duration::from_secs(5)
+        );
+        assert_eq!(sockopt::get_tcp_keepcnt(&stream).unwrap(), 3);
+
+        let (accepted, _) = listener.accept().await.unwrap();
+        TcpConfig::default().apply_to_accepted(&accepted).unwrap();
+        assert!(!sockopt::get_socket_keepalive(&accepted).unwrap());
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let v4_a: SocketAddr = "10.0.0.1:9042".parse().unwrap();
+        let v4_b: SocketAddr = "10.0.0.2:9042".parse().unwrap();
+        let v6_a: SocketAddr = "[fd00::1]:9042".parse().unwrap();
+        let v6_b: SocketAddr = "[fd00::2]:9042".parse().unwrap();
+
+        assert_eq!(
+            interleave_families(vec![v6_a, v6_b, v4_a, v4_b]),
+            [v6_a, v4_a, v6_b, v4_b]
+        );
+        assert_eq!(
+            interleave_families(vec![v4_a, v4_b, v6_a]),
+            [v4_a, v6_a, v4_b]
+        );
+        assert_eq!(interleave_families(vec![v4_a, v4_b]), [v4_a, v4_b]);
+        assert!(interleave_families(vec![]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connect_skips_unreachable_address() {
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap();
+        let config = TcpConfig {
+            connection_attempt_delay_ms: Some(60_000),
+            ..TcpConfig::default()
+        };
+
+        // The refused attempt fails immediately so the next address is attempted without waiting for the attempt delay
+        let stream = tcp_stream(
+            Duration::from_secs(3),
+            [refused, reachable].as_slice(),
+            &config,
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), reachable);
+
+        let err = tcp_stream(Duration::from_secs(3), refused, &config)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:?}").contains(&refused.to_string()));
+    }
+}
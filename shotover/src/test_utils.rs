@@ -0,0 +1,330 @@
+//! Utilities for unit testing custom transforms without running shotover or a database.
+//! Only available with the `test-utils` feature, which is intended to be enabled in `dev-dependencies`.
+//!
+//! [`TestChain`] runs a transform in front of a [`MockSink`] standing in for the database,
+//! so that `Transform::transform` can be called directly on messages built by the helpers in this module.
+//!
+//! ```no_run
+//! # #[cfg(feature = "redis")]
+//! # async fn test() {
+//! # use shotover::transforms::loopback::Loopback as MyTransform;
+//! use shotover::frame::RedisFrame;
+//! use shotover::test_utils::{assert_redis_responses, redis_command, TestChain};
+//!
+//! let mut chain = TestChain::new(Box::new(MyTransform::default()));
+//! let responses = chain.send(vec![redis_command(&["GET", "foo"])]).await.unwrap();
+//! assert_redis_responses(responses, &[RedisFrame::Null]);
+//! # }
+//! ```
+
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageId, Messages};
+use crate::transforms::chain::TransformAndMetrics;
+use crate::transforms::{
+    ChainState, Transform, TransformBuilder, TransformConfig, TransformContextBuilder,
+    TransformContextConfig,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "cassandra")]
+use {
+    crate::frame::cassandra::{parse_statement_single, Tracing},
+    crate::frame::{CassandraFrame, CassandraOperation},
+    cassandra_protocol::frame::Version,
+};
+#[cfg(feature = "kafka")]
+use {
+    crate::frame::kafka::{KafkaFrame, RequestBody},
+    kafka_protocol::messages::{ApiKey, RequestHeader},
+};
+#[cfg(feature = "redis")]
+use {crate::frame::RedisFrame, bytes::Bytes};
+
+/// Runs a transform in front of a [`MockSink`], as if it were the only transform in a chain ending in that sink.
+pub struct TestChain {
+    chain: Vec<TransformAndMetrics>,
+    received: Arc<Mutex<Vec<Message>>>,
+}
+
+impl TestChain {
+    /// Runs `transform` in front of a sink that returns each request as its own response.
+    pub fn new(transform: Box<dyn Transform>) -> Self {
+        TestChain::with_sink(transform, MockSink::echo())
+    }
+
+    pub fn with_sink(transform: Box<dyn Transform>, sink: MockSink) -> Self {
+        TestChain {
+            received: sink.received.clone(),
+            chain: vec![
+                TransformAndMetrics::new(transform),
+                TransformAndMetrics::new(Box::new(sink)),
+            ],
+        }
+    }
+
+    /// Builds the transform the same way shotover does when it is listed in a chain,
+    /// returning an error if the config fails validation.
+    pub async fn from_config(
+        config: &dyn TransformConfig,
+        up_chain_protocol: MessageType,
+        sink: MockSink,
+    ) -> Result<Self> {
+        let builder = config
+            .get_builder(TransformContextConfig {
+                chain_name: "test_chain".into(),
+                up_chain_protocol,
+            })
+            .await?;
+        TestChain::from_builder(builder.as_ref(), sink)
+    }
+
+    /// Builds the transform from its builder, returning an error if the builder fails validation.
+    pub fn from_builder(builder: &dyn TransformBuilder, sink: MockSink) -> Result<Self> {
+        let errors = builder.validate();
+        if !errors.is_empty() {
+            return Err(anyhow!("{}", errors.join("\n")));
+        }
+        Ok(TestChain::with_sink(
+            builder.build(TransformContextBuilder::new_test()),
+            sink,
+        ))
+    }
+
+    /// Sends a batch of requests through the transform, returning the responses it returns.
+    pub async fn send(&mut self, requests: Messages) -> Result<Messages> {
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.reset(&mut self.chain);
+        chain_state.call_next_transform().await
+    }
+
+    /// Runs the transform as it would be run when the client connection closes.
+    pub async fn flush(&mut self) -> Result<Messages> {
+        let mut chain_state = ChainState::flush();
+        chain_state.reset(&mut self.chain);
+        chain_state.call_next_transform().await
+    }
+
+    /// Returns the requests that reached the sink since the last call.
+    pub fn take_received(&self) -> Vec<Message> {
+        std::mem::take(&mut *self.received.lock().unwrap())
+    }
+}
+
+type Responder = Box<dyn FnMut(&mut Message) -> Message + Send>;
+
+/// A terminating transform standing in for the database.
+/// It records every request it receives and answers each request with the message returned by its responder.
+pub struct MockSink {
+    received: Arc<Mutex<Vec<Message>>>,
+    responder: Responder,
+}
+
+impl MockSink {
+    /// Returns each request as its own response.
+    pub fn echo() -> Self {
+        MockSink::new(|request| request.clone())
+    }
+
+    /// Answers each request with the response returned by `responder`, which does not need to set the request id of the response.
+    pub fn new(responder: impl FnMut(&mut Message) -> Message + Send + 'static) -> Self {
+        MockSink {
+            received: Default::default(),
+            responder: Box::new(responder),
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for MockSink {
+    fn get_name(&self) -> &'static str {
+        "MockSink"
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut responses = vec![];
+        for mut request in chain_state.requests.drain(..) {
+            // Like a real sink, dummy requests are not sent to the database and are answered with a dummy response
+            let mut response = if request.is_dummy() {
+                Message::from_frame(Frame::Dummy)
+            } else {
+                (self.responder)(&mut request)
+            };
+            response.set_request_id(request.id());
+            responses.push(response);
+            if !request.is_dummy() {
+                self.received.lock().unwrap().push(request);
+            }
+        }
+        Ok(responses)
+    }
+}
+
+/// Builds a redis request from the command and its arguments e.g. `redis_command(&["SET", "foo", "bar"])`
+#[cfg(feature = "redis")]
+pub fn redis_command(args: &[&str]) -> Message {
+    Message::from_frame(Frame::Redis(redis_command_frame(args)))
+}
+
+/// Builds the frame of a redis request, see [`redis_command`]
+#[cfg(feature = "redis")]
+pub fn redis_command_frame(args: &[&str]) -> RedisFrame {
+    RedisFrame::Array(redis_command_args(args))
+}
+
+/// Builds the array of frames making up a redis request, see [`redis_command`]
+#[cfg(feature = "redis")]
+pub fn redis_command_args(args: &[&str]) -> Vec<RedisFrame> {
+    args.iter()
+        .map(|x| RedisFrame::BulkString(Bytes::copy_from_slice(x.as_bytes())))
+        .collect()
+}
+
+/// Builds a cassandra v4 QUERY request, invalid CQL is kept as an unparsed statement.
+#[cfg(feature = "cassandra")]
+pub fn cassandra_query(query: &str) -> Message {
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version: Version::V4,
+        stream_id: 0,
+        tracing: Tracing::Request(false),
+        warnings: vec![],
+        operation: CassandraOperation::Query {
+            query: Box::new(parse_statement_single(query)),
+            params: Box::default(),
+        },
+    }))
+}
+
+/// Builds a kafka request, `api_key` must match the type of `body`.
+#[cfg(feature = "kafka")]
+pub fn kafka_request(api_key: ApiKey, api_version: i16, body: RequestBody) -> Message {
+    Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+        header: RequestHeader::default()
+            .with_request_api_key(api_key as i16)
+            .with_request_api_version(api_version),
+        body,
+    }))
+}
+
+/// Returns the message of an error response, or None if the message is not an error response.
+pub fn error_message(response: &mut Message) -> Option<String> {
+    match response.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(RedisFrame::Error(err))) => Some(err.to_string()),
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Error(err),
+            ..
+        })) => Some(err.message.clone()),
+        #[cfg(feature = "memcached")]
+        Some(Frame::Memcached(crate::frame::memcached::MemcachedFrame::Response(
+            crate::frame::memcached::MemcachedResponse::ClientError(err)
+            | crate::frame::memcached::MemcachedResponse::ServerError(err),
+        ))) => Some(err.clone()),
+        _ => None,
+    }
+}
+
+/// Asserts that the response is an error whose message contains `expected`.
+pub fn assert_error_response(response: &mut Message, expected: &str) {
+    match error_message(response) {
+        Some(message) => assert!(
+            message.contains(expected),
+            "expected an error containing {expected:?} but the error was {message:?}"
+        ),
+        None => panic!(
+            "expected an error containing {expected:?} but the response was {:?}",
+            response.frame()
+        ),
+    }
+}
+
+/// Asserts that every request received exactly one response, as transforms are required to uphold.
+/// `request_ids` must be taken before the requests are sent.
+pub fn assert_responses_match_requests(request_ids: &[MessageId], responses: &[Message]) {
+    let mut response_ids: Vec<Option<MessageId>> =
+        responses.iter().map(|x| x.request_id()).collect();
+    for request_id in request_ids {
+        match response_ids.iter().position(|x| *x == Some(*request_id)) {
+            Some(index) => {
+                response_ids.swap_remove(index);
+            }
+            None => panic!("request {request_id} did not receive a response"),
+        }
+    }
+    assert!(
+        response_ids.is_empty(),
+        "responses were returned that do not correspond to any request: {response_ids:?}"
+    );
+}
+
+/// Asserts that the responses are the expected redis frames, in order.
+#[cfg(feature = "redis")]
+pub fn assert_redis_responses(responses: Messages, expected: &[RedisFrame]) {
+    let frames: Vec<RedisFrame> = responses
+        .into_iter()
+        .map(|response| match response.into_frame() {
+            Some(Frame::Redis(frame)) => frame,
+            frame => panic!("expected a redis response but was {frame:?}"),
+        })
+        .collect();
+    assert_eq!(frames, expected);
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::size_limit::SizeLimitConfig;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_chain() {
+        let config = SizeLimitConfig {
+            max_request_bytes: Some(1),
+            max_response_bytes: None,
+        };
+        let mut chain = TestChain::from_config(&config, MessageType::Redis, MockSink::echo())
+            .await
+            .unwrap();
+
+        // Messages created from frames have no received size so are never rejected
+        let requests = vec![redis_command(&["GET", "foo"])];
+        let request_ids: Vec<MessageId> = requests.iter().map(|x| x.id()).collect();
+        let responses = chain.send(requests).await.unwrap();
+
+        assert_responses_match_requests(&request_ids, &responses);
+        assert_redis_responses(
+            responses,
+            &[RedisFrame::Array(vec![
+                RedisFrame::BulkString("GET".into()),
+                RedisFrame::BulkString("foo".into()),
+            ])],
+        );
+        assert_eq!(chain.take_received().len(), 1);
+        assert!(chain.take_received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mock_sink() {
+        let mut sink = MockSink::new(|_| {
+            Message::from_frame(Frame::Redis(RedisFrame::Error("ERR nope".into())))
+        });
+        let mut request = redis_command(&["GET", "foo"]);
+        let mut chain_state = ChainState::new_test(vec![request.clone()]);
+        let mut responses = sink.transform(&mut chain_state).await.unwrap();
+        assert_eq!(responses[0].request_id(), Some(request.id()));
+        assert_error_response(&mut responses[0], "nope");
+        assert_eq!(sink.received.lock().unwrap().len(), 1);
+
+        // dummy requests are answered without reaching the database
+        request.replace_with_dummy();
+        let mut chain_state = ChainState::new_test(vec![request]);
+        let responses = sink.transform(&mut chain_state).await.unwrap();
+        assert!(responses[0].is_dummy());
+        assert_eq!(sink.received.lock().unwrap().len(), 1);
+    }
+}
@@ -1,6 +1,6 @@
 //! Use to establish a TLS connection to a DB in a sink transform
 
-use crate::tcp;
+use crate::tcp::{self, TcpConfig};
 use anyhow::{anyhow, bail, Context, Error, Result};
 use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
 use rustls::client::WebPkiServerVerifier;
@@ -130,6 +130,91 @@ impl TlsAcceptor {
     }
 }
 
+/// The DER encoded object identifier of the X.520 commonName attribute, 2.5.4.3
+const COMMON_NAME_OID: &[u8] = &[0x55, 0x04, 0x03];
+
+/// Returns the common name of the certificate presented by the client.
+/// This is only available when the acceptor is configured with a `certificate_authority_path` so that client certificates are required.
+pub fn peer_common_name<IO>(stream: &TlsStreamServer<IO>) -> Option<String> {
+    let (_, connection) = stream.get_ref();
+    certificate_common_name(connection.peer_certificates()?.first()?)
+}
+
+/// The parameters negotiated during the TLS handshake with a client
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TlsSessionDetails {
+    pub protocol_version: Option<String>,
+    pub cipher_suite: Option<String>,
+    /// The SNI server name requested by the client
+    pub server_name: Option<String>,
+    /// The common name of the client certificate
+    pub client_common_name: Option<String>,
+}
+
+pub fn session_details<IO>(stream: &TlsStreamServer<IO>) -> TlsSessionDetails {
+    let (_, connection) = stream.get_ref();
+    TlsSessionDetails {
+        protocol_version: connection.protocol_version().map(|x| format!("{x:?}")),
+        cipher_suite: connection
+            .negotiated_cipher_suite()
+            .map(|x| format!("{:?}", x.suite())),
+        server_name: connection.server_name().map(|x| x.to_owned()),
+        client_common_name: peer_common_name(stream),
+    }
+}
+
+/// Reads a single DER TLV from the start of `input`, returning its tag, value and the remaining input
+fn der_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *input.first()?;
+    let first = *input.get(1)?;
+    let (len, header_len) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7F) as usize;
+        if count > 4 {
+            return None;
+        }
+        let len = input
+            .get(2..2 + count)?
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+        (len, 2 + count)
+    };
+    let value = input.get(header_len..header_len.checked_add(len)?)?;
+    Some((tag, value, &input[header_len + len..]))
+}
+
+/// Extracts the subject common name from a DER encoded X.509 certificate
+fn certificate_common_name(certificate: &[u8]) -> Option<String> {
+    let (_, certificate, _) = der_tlv(certificate)?;
+    let (_, mut tbs_certificate, _) = der_tlv(certificate)?;
+    // skip the optional explicitly tagged version
+    if tbs_certificate.first() == Some(&0xA0) {
+        tbs_certificate = der_tlv(tbs_certificate)?.2;
+    }
+    // skip the serial number, signature algorithm, issuer and validity
+    for _ in 0..4 {
+        tbs_certificate = der_tlv(tbs_certificate)?.2;
+    }
+    let (_, mut subject, _) = der_tlv(tbs_certificate)?;
+
+    // The subject is a SEQUENCE of SETs of (OID, value) SEQUENCEs
+    while !subject.is_empty() {
+        let (_, mut attributes, rest) = der_tlv(subject)?;
+        subject = rest;
+        while !attributes.is_empty() {
+            let (_, attribute, rest) = der_tlv(attributes)?;
+            attributes = rest;
+            let (oid_tag, oid, value) = der_tlv(attribute)?;
+            if oid_tag == 0x06 && oid == COMMON_NAME_OID {
+                let (_, value, _) = der_tlv(value)?;
+                return String::from_utf8(value.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields)]
 pub struct TlsConnectorConfig {
@@ -215,9 +300,10 @@ impl TlsConnector {
         &self,
         connect_timeout: Duration,
         address: A,
+        tcp: &TcpConfig,
     ) -> Result<TlsStreamClient<TcpStream>> {
         let servername = address.to_servername()?;
-        let tcp_stream = tcp::tcp_stream(connect_timeout, address).await?;
+        let tcp_stream = tcp::tcp_stream(connect_timeout, address, tcp).await?;
         self.connector
             .connect(servername, tcp_stream)
             .await
@@ -387,3 +473,50 @@ impl ToHostname for SocketAddr {
         Ok(ServerName::IpAddress(self.ip().into()))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tlv(tag: u8, values: &[&[u8]]) -> Vec<u8> {
+        let value = values.concat();
+        let mut out = vec![tag, value.len() as u8];
+        out.extend(value);
+        out
+    }
+
+    fn name(attributes: &[(&[u8], &str)]) -> Vec<u8> {
+        let rdns: Vec<Vec<u8>> = attributes
+            .iter()
+            .map(|(oid, value)| {
+                let attribute = tlv(0x30, &[&tlv(0x06, &[oid]), &tlv(0x0C, &[value.as_bytes()])]);
+                tlv(0x31, &[&attribute])
+            })
+            .collect();
+        tlv(0x30, &rdns.iter().map(|x| x.as_slice()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_certificate_common_name() {
+        let organization_oid: &[u8] = &[0x55, 0x04, 0x0A];
+        let algorithm = tlv(0x30, &[&tlv(0x06, &[&[0x2A, 0x86, 0x48]])]);
+        let tbs_certificate = tlv(
+            0x30,
+            &[
+                &tlv(0xA0, &[&tlv(0x02, &[&[2]])]),
+                &tlv(0x02, &[&[1]]),
+                &algorithm,
+                &name(&[(COMMON_NAME_OID, "Certificate Authority")]),
+                &tlv(0x30, &[]),
+                &name(&[(organization_oid, "org"), (COMMON_NAME_OID, "client")]),
+            ],
+        );
+        let certificate = tlv(0x30, &[&tbs_certificate, &algorithm, &tlv(0x03, &[&[0]])]);
+        assert_eq!(
+            certificate_common_name(&certificate),
+            Some("client".to_owned())
+        );
+
+        assert_eq!(certificate_common_name(&certificate[..20]), None);
+    }
+}
@@ -0,0 +1,442 @@
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageId, MessageIdMap, Messages, OperationType};
+use crate::transforms::util::glob_match;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[cfg(feature = "redis")]
+use crate::frame::{
+    redis::{redis_command_keys, redis_query_type},
+    RedisFrame,
+};
+#[cfg(feature = "redis")]
+use crate::message::QueryType;
+#[cfg(feature = "cassandra")]
+use {
+    crate::frame::cassandra::{statement_operation_type, CassandraResult, Tracing},
+    crate::frame::{CassandraFrame, CassandraOperation},
+    cassandra_protocol::frame::message_error::{ErrorBody, ErrorType, UnpreparedError},
+    cassandra_protocol::types::CBytesShort,
+    cql3_parser::cassandra_statement::CassandraStatement,
+};
+
+/// Redis commands that only affect the state of the connection, these are permitted for every client.
+#[cfg(feature = "redis")]
+const REDIS_CONNECTION_COMMANDS: &[&[u8]] = &[
+    b"PING", b"ECHO", b"AUTH", b"HELLO", b"SELECT", b"QUIT", b"RESET", b"MULTI", b"EXEC",
+    b"DISCARD", b"UNWATCH",
+];
+
+/// The resource accessed by requests that do not access any particular key or table, e.g. redis `FLUSHALL`
+const ALL_RESOURCES: &str = "*";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AclConfig {
+    /// Client identities mapped to the permissions granted to them.
+    pub users: HashMap<String, Vec<Permission>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Permission {
+    /// The operations that are permitted on the matching resources.
+    pub operations: Vec<OperationType>,
+    /// Patterns matching redis keys or fully qualified cassandra table names.
+    /// `*` matches any sequence of characters and `?` matches any single character.
+    pub resources: Vec<String>,
+}
+
+impl Permission {
+    fn permits(&self, operation: OperationType, resource: &[u8]) -> bool {
+        self.operations.contains(&operation)
+            && self
+                .resources
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), resource))
+    }
+}
+
+const NAME: &str = "Acl";
+#[typetag::serde(name = "Acl")]
+#[async_trait(?Send)]
+impl TransformConfig for AclConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(AclBuilder {
+            users: Arc::new(self.users.clone()),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct AclBuilder {
+    users: Arc<HashMap<String, Vec<Permission>>>,
+}
+
+impl TransformBuilder for AclBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(Acl {
+            users: self.users.clone(),
+            denied_requests: MessageIdMap::default(),
+            #[cfg(feature = "cassandra")]
+            prepared: HashMap::new(),
+            #[cfg(feature = "cassandra")]
+            prepare_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// An operation a request performs on a resource
+#[derive(Clone, Debug, PartialEq)]
+struct Access {
+    operation: OperationType,
+    resource: Bytes,
+}
+
+struct Acl {
+    users: Arc<HashMap<String, Vec<Permission>>>,
+    /// Error responses for the requests that were denied, keyed by the id of the dummy request they respond to
+    denied_requests: MessageIdMap<Message>,
+    /// The accesses performed by the statements prepared on this connection.
+    /// An EXECUTE can only be checked if its statement was prepared on this connection,
+    /// since prepared statement ids are derived from the query and are not a secret.
+    #[cfg(feature = "cassandra")]
+    prepared: HashMap<CBytesShort, Vec<Access>>,
+    #[cfg(feature = "cassandra")]
+    prepare_requests: MessageIdMap<Vec<Access>>,
+}
+
+#[cfg(feature = "redis")]
+fn redis_accesses(frame: &RedisFrame) -> Vec<Access> {
+    if let RedisFrame::Array(args) = frame {
+        if let Some(RedisFrame::BulkString(command)) = args.first() {
+            let command = command.to_ascii_uppercase();
+            if REDIS_CONNECTION_COMMANDS.contains(&command.as_slice()) {
+                return vec![];
+            }
+        }
+    }
+
+    let operation = match redis_query_type(frame) {
+        QueryType::Read => OperationType::Read,
+        _ => OperationType::Write,
+    };
+    // Commands whose keys are not known could access any key, so they are only permitted to clients that can access every key
+    let mut keys = redis_command_keys(frame).unwrap_or_default();
+    if keys.is_empty() {
+        keys.push(Bytes::from_static(ALL_RESOURCES.as_bytes()));
+    }
+    keys.into_iter()
+        .map(|resource| Access {
+            operation,
+            resource,
+        })
+        .collect()
+}
+
+#[cfg(feature = "cassandra")]
+fn statement_access(statement: &CassandraStatement) -> Option<Access> {
+    // USE only changes the keyspace of the connection, unqualified table names are never matched by a keyspace pattern
+    if let CassandraStatement::Use(_) = statement {
+        return None;
+    }
+    let resource = match statement.get_table_name() {
+        Some(table) => Bytes::from(table.to_string()),
+        None => Bytes::from_static(ALL_RESOURCES.as_bytes()),
+    };
+    Some(Access {
+        operation: statement_operation_type(statement),
+        resource,
+    })
+}
+
+#[cfg(feature = "cassandra")]
+fn cassandra_error(frame: &CassandraFrame, error: ErrorBody) -> Frame {
+    Frame::Cassandra(CassandraFrame {
+        version: frame.version,
+        stream_id: frame.stream_id,
+        tracing: Tracing::Response(None),
+        warnings: vec![],
+        operation: CassandraOperation::Error(error),
+    })
+}
+
+impl Acl {
+    /// Returns the access that `identity` is not permitted to perform, if any.
+    fn denied<'a>(&self, identity: Option<&str>, accesses: &'a [Access]) -> Option<&'a Access> {
+        let permissions = identity
+            .and_then(|identity| self.users.get(identity))
+            .map(|x| x.as_slice())
+            .unwrap_or_default();
+        accesses.iter().find(|access| {
+            !permissions
+                .iter()
+                .any(|permission| permission.permits(access.operation, &access.resource))
+        })
+    }
+
+    /// Returns an error response if the request is not permitted.
+    fn check_request(&mut self, request: &mut Message, identity: Option<&str>) -> Option<Message> {
+        let request_id = request.id();
+        let response = match request.frame() {
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(frame)) => {
+                let accesses = redis_accesses(frame);
+                let denied = self.denied(identity, &accesses)?;
+                let user = identity.unwrap_or("anonymous");
+                let error = if denied.resource == ALL_RESOURCES {
+                    format!("NOPERM User {user} has no permissions to run this command")
+                } else {
+                    format!(
+                        "NOPERM User {user} has no permissions to access the '{}' key",
+                        String::from_utf8_lossy(&denied.resource)
+                    )
+                };
+                Frame::Redis(RedisFrame::Error(error.into()))
+            }
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => self.check_cassandra(request_id, frame, identity)?,
+            _ => return None,
+        };
+        let mut response = Message::from_frame(response);
+        response.set_request_id(request_id);
+        Some(response)
+    }
+
+    #[cfg(feature = "cassandra")]
+    fn check_cassandra(
+        &mut self,
+        request_id: MessageId,
+        frame: &mut CassandraFrame,
+        identity: Option<&str>,
+    ) -> Option<Frame> {
+        let prepared_statement = frame.prepared_statement();
+        let mut accesses: Vec<Access> = match &prepared_statement {
+            Some(statement) => statement_access(statement).into_iter().collect(),
+            None => frame
+                .operation
+                .queries()
+                .filter_map(|statement| statement_access(statement))
+                .collect(),
+        };
+        for id in frame.prepared_ids() {
+            match self.prepared.get(id) {
+                Some(prepared) => accesses.extend(prepared.iter().cloned()),
+                None => {
+                    // Force the client to prepare the statement on this connection so that it can be checked
+                    return Some(cassandra_error(
+                        frame,
+                        ErrorBody {
+                            message: "The statement must be prepared on this connection before it can be executed".into(),
+                            ty: ErrorType::Unprepared(UnpreparedError { id: id.clone() }),
+                        },
+                    ));
+                }
+            }
+        }
+
+        if let Some(denied) = self.denied(identity, &accesses) {
+            let user = identity.unwrap_or("anonymous");
+            return Some(cassandra_error(
+                frame,
+                ErrorBody {
+                    message: format!(
+                        "User {user} has no {:?} permission on {}",
+                        denied.operation,
+                        String::from_utf8_lossy(&denied.resource)
+                    ),
+                    ty: ErrorType::Unauthorized,
+                },
+            ));
+        }
+
+        if prepared_statement.is_some() {
+            self.prepare_requests.insert(request_id, accesses);
+        }
+        None
+    }
+
+    #[cfg(feature = "cassandra")]
+    fn process_prepared_response(&mut self, response: &mut Message) {
+        if let Some(accesses) = response
+            .request_id()
+            .and_then(|id| self.prepare_requests.remove(&id))
+        {
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                ..
+            })) = response.frame()
+            {
+                self.prepared.insert(prepared.id.clone(), accesses);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for Acl {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let identity = chain_state.session.identity().map(|x| x.to_owned());
+        for request in chain_state.requests.iter_mut() {
+            if let Some(error) = self.check_request(request, identity.as_deref()) {
+                self.denied_requests.insert(request.id(), error);
+                request.replace_with_dummy();
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            if let Some(error) = response
+                .request_id()
+                .and_then(|id| self.denied_requests.remove(&id))
+            {
+                *response = error;
+                continue;
+            }
+            #[cfg(feature = "cassandra")]
+            self.process_prepared_response(response);
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transforms::session::AuthenticatedUser;
+
+    #[cfg(feature = "redis")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_acl_redis() {
+        use crate::test_utils::redis_command;
+        use crate::transforms::chain::TransformAndMetrics;
+        use crate::transforms::loopback::Loopback;
+        use pretty_assertions::assert_eq;
+
+        let mut acl = Acl {
+            users: Arc::new(HashMap::from([(
+                "app".to_owned(),
+                vec![
+                    Permission {
+                        operations: vec![OperationType::Read, OperationType::Write],
+                        resources: vec!["user:*".to_owned()],
+                    },
+                    Permission {
+                        operations: vec![OperationType::Read],
+                        resources: vec!["config".to_owned()],
+                    },
+                ],
+            )])),
+            denied_requests: MessageIdMap::default(),
+            #[cfg(feature = "cassandra")]
+            prepared: HashMap::new(),
+            #[cfg(feature = "cassandra")]
+            prepare_requests: MessageIdMap::default(),
+        };
+
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(vec![
+            redis_command(&["SET", "user:1", "foo"]),
+            redis_command(&["GET", "config"]),
+            redis_command(&["SET", "config", "foo"]),
+            redis_command(&["MGET", "user:1", "admin"]),
+            redis_command(&["FLUSHALL"]),
+            redis_command(&["PING"]),
+            // Commands that access more than their first key
+            redis_command(&["SUNIONSTORE", "user:1", "secret1", "secret2"]),
+            redis_command(&["SINTERSTORE", "user:1", "secret1"]),
+            redis_command(&["SDIFFSTORE", "user:1", "secret1"]),
+            redis_command(&["ZUNIONSTORE", "user:1", "1", "secret1"]),
+            redis_command(&["BITOP", "AND", "user:1", "secret1"]),
+            redis_command(&["SMOVE", "user:1", "secret1", "member"]),
+            redis_command(&["LMOVE", "user:1", "secret1", "LEFT", "RIGHT"]),
+            redis_command(&["RPOPLPUSH", "user:1", "secret1"]),
+            redis_command(&["COPY", "user:1", "secret1"]),
+            redis_command(&["RENAME", "user:1", "secret1"]),
+            redis_command(&["OBJECT", "ENCODING", "secret1"]),
+            redis_command(&["OBJECT", "ENCODING", "user:1"]),
+            // The keys read by GET patterns and unknown commands cannot be checked
+            redis_command(&["SORT", "user:1", "GET", "secret*"]),
+            redis_command(&["NEWCMD", "user:1"]),
+        ]);
+        chain_state
+            .session
+            .insert(AuthenticatedUser("app".to_owned()));
+        chain_state.reset(&mut chain);
+
+        let responses: Vec<_> = acl
+            .transform(&mut chain_state)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect();
+        let error = |x: &str| Frame::Redis(RedisFrame::Error(x.into()));
+        assert_eq!(
+            responses,
+            vec![
+                redis_command(&["SET", "user:1", "foo"])
+                    .frame()
+                    .cloned()
+                    .unwrap(),
+                redis_command(&["GET", "config"]).frame().cloned().unwrap(),
+                error("NOPERM User app has no permissions to access the 'config' key"),
+                error("NOPERM User app has no permissions to access the 'admin' key"),
+                error("NOPERM User app has no permissions to run this command"),
+                redis_command(&["PING"]).frame().cloned().unwrap(),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                error("NOPERM User app has no permissions to access the 'secret1' key"),
+                redis_command(&["OBJECT", "ENCODING", "user:1"])
+                    .frame()
+                    .cloned()
+                    .unwrap(),
+                error("NOPERM User app has no permissions to run this command"),
+                error("NOPERM User app has no permissions to run this command"),
+            ]
+        );
+    }
+}
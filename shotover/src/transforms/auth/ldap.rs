@@ -0,0 +1,335 @@
+use super::AuthProvider;
+use crate::tcp::{self, TcpConfig};
+use crate::tls::{TlsConnector, TlsConnectorConfig};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The LDAP result code for a successful operation
+const SUCCESS: u8 = 0;
+/// The LDAP result code returned by a bind with an unknown DN or incorrect password
+const INVALID_CREDENTIALS: u8 = 49;
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+/// The default time allowed for the bind exchange with the LDAP server after connecting
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LdapConfig {
+    /// The address of the LDAP server e.g. `ldap.example.com:636`
+    pub address: String,
+    /// The DN to bind as, `{username}` is replaced with the username presented by the client.
+    /// e.g. `uid={username},ou=people,dc=example,dc=com`
+    pub bind_dn_template: String,
+    pub tls: Option<TlsConnectorConfig>,
+    pub connect_timeout_ms: u64,
+    /// The time allowed for the LDAP server to respond to the bind, defaults to 10 seconds.
+    pub request_timeout_ms: Option<u64>,
+    /// When set, successful authentications are remembered for this many seconds.
+    pub cache_ttl_seconds: Option<u64>,
+}
+
+impl LdapConfig {
+    pub(super) fn build(&self) -> Result<LdapProvider> {
+        if !self.bind_dn_template.contains("{username}") {
+            bail!("bind_dn_template must contain {{username}}");
+        }
+        Ok(LdapProvider {
+            address: self.address.clone(),
+            bind_dn_template: self.bind_dn_template.clone(),
+            tls: self.tls.as_ref().map(TlsConnector::new).transpose()?,
+            connect_timeout: Duration::from_millis(self.connect_timeout_ms),
+            request_timeout: self
+                .request_timeout_ms
+                .map(Duration::from_millis)
+                .unwrap_or(REQUEST_TIMEOUT),
+        })
+    }
+}
+
+pub(super) struct LdapProvider {
+    address: String,
+    bind_dn_template: String,
+    tls: Option<TlsConnector>,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+}
+
+#[async_trait]
+impl AuthProvider for LdapProvider {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<String>> {
+        // An LDAP simple bind with an empty password is an unauthenticated bind and succeeds for any DN
+        if username.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let dn = self
+            .bind_dn_template
+            .replace("{username}", &escape_dn_value(username));
+        let request = encode_bind_request(&dn, password);
+        let tcp = TcpConfig::default();
+        let result_code = match &self.tls {
+            Some(tls) => {
+                let mut stream = tls
+                    .connect(self.connect_timeout, self.address.as_str(), &tcp)
+                    .await?;
+                bind(&mut stream, &request, self.request_timeout).await?
+            }
+            None => {
+                let mut stream =
+                    tcp::tcp_stream(self.connect_timeout, self.address.as_str(), &tcp).await?;
+                bind(&mut stream, &request, self.request_timeout).await?
+            }
+        };
+
+        match result_code {
+            SUCCESS => Ok(Some(username.to_owned())),
+            INVALID_CREDENTIALS => Ok(None),
+            code => Err(anyhow!("LDAP bind failed with result code {code}")),
+        }
+    }
+}
+
+async fn bind<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+    request_timeout: Duration,
+) -> Result<u8> {
+    tokio::time::timeout(request_timeout, bind_exchange(stream, request))
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "LDAP server did not respond to the bind within {}ms",
+                request_timeout.as_millis()
+            )
+        })?
+}
+
+async fn bind_exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    request: &[u8],
+) -> Result<u8> {
+    stream.write_all(request).await?;
+
+    let mut header = [0; 2];
+    stream.read_exact(&mut header).await?;
+    if header[0] != 0x30 {
+        bail!("LDAP server responded with an invalid message");
+    }
+    let len = match header[1] {
+        len if len < 0x80 => len as usize,
+        0x81..=0x84 => {
+            let mut len_bytes = vec![0; (header[1] & 0x7F) as usize];
+            stream.read_exact(&mut len_bytes).await?;
+            len_bytes
+                .iter()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize)
+        }
+        _ => bail!("LDAP server responded with an invalid message length"),
+    };
+    // A BindResponse is small, anything larger indicates something has gone wrong
+    if len > MAX_RESPONSE_LEN {
+        bail!("LDAP server responded with a {len} byte message");
+    }
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+    decode_bind_response(&body)
+}
+
+/// Escapes the characters that have special meaning in a DN attribute value as per RFC 4514
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\0' => escaped.push_str("\\00"),
+            ' ' | '#' if i == 0 => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ' ' if i == last => escaped.push_str("\\ "),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn encode_length(len: usize, out: &mut Vec<u8>) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = (len as u32).to_be_bytes();
+        let skip = bytes.iter().take_while(|x| **x == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend(&bytes[skip..]);
+    }
+}
+
+fn encode_tlv(tag: u8, value: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    encode_length(value.len(), out);
+    out.extend(value);
+}
+
+/// Encodes an LDAPMessage containing a simple BindRequest as per RFC 4511
+fn encode_bind_request(dn: &str, password: &str) -> Vec<u8> {
+    let mut bind_request = vec![];
+    // version: INTEGER 3
+    encode_tlv(0x02, &[3], &mut bind_request);
+    // name: LDAPDN
+    encode_tlv(0x04, dn.as_bytes(), &mut bind_request);
+    // authentication: simple [0] OCTET STRING
+    encode_tlv(0x80, password.as_bytes(), &mut bind_request);
+
+    let mut message = vec![];
+    // messageID: INTEGER 1
+    encode_tlv(0x02, &[1], &mut message);
+    // protocolOp: bindRequest [APPLICATION 0]
+    encode_tlv(0x60, &bind_request, &mut message);
+
+    let mut out = vec![];
+    encode_tlv(0x30, &message, &mut out);
+    out
+}
+
+/// Reads a single TLV from the start of `input`, returning its tag, value and the remaining input
+fn decode_tlv(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let tag = *input
+        .first()
+        .ok_or_else(|| anyhow!("truncated LDAP message"))?;
+    let first = *input
+        .get(1)
+        .ok_or_else(|| anyhow!("truncated LDAP message"))?;
+    let (len, header_len) = if first < 0x80 {
+        (first as usize, 2)
+    } else {
+        let count = (first & 0x7F) as usize;
+        // A length that does not fit in 4 bytes is far larger than any message we accept
+        if count > 4 {
+            bail!("LDAP message has an invalid length");
+        }
+        let bytes = input
+            .get(2..2 + count)
+            .ok_or_else(|| anyhow!("truncated LDAP message"))?;
+        (
+            bytes
+                .iter()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize),
+            2 + count,
+        )
+    };
+    let end = header_len
+        .checked_add(len)
+        .ok_or_else(|| anyhow!("LDAP message has an invalid length"))?;
+    let value = input
+        .get(header_len..end)
+        .ok_or_else(|| anyhow!("truncated LDAP message"))?;
+    Ok((tag, value, &input[end..]))
+}
+
+/// Returns the result code of the BindResponse contained in the body of an LDAPMessage
+fn decode_bind_response(message: &[u8]) -> Result<u8> {
+    let (tag, _message_id, rest) = decode_tlv(message)?;
+    if tag != 0x02 {
+        bail!("LDAP message did not start with a message id");
+    }
+    let (tag, bind_response, _) = decode_tlv(rest)?;
+    if tag != 0x61 {
+        bail!("LDAP server responded with {tag:#x} instead of a BindResponse");
+    }
+    match decode_tlv(bind_response)? {
+        (0x0A, [result_code], _) => Ok(*result_code),
+        _ => bail!("LDAP BindResponse did not start with a result code"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_encode_bind_request() {
+        assert_eq!(
+            encode_bind_request("cn=a", "pw"),
+            vec![
+                0x30, 0x12, 0x02, 0x01, 0x01, 0x60, 0x0D, 0x02, 0x01, 0x03, 0x04, 0x04, b'c', b'n',
+                b'=', b'a', 0x80, 0x02, b'p', b'w'
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_long_length() {
+        let mut out = vec![];
+        encode_length(300, &mut out);
+        assert_eq!(out, vec![0x82, 0x01, 0x2C]);
+    }
+
+    #[test]
+    fn test_decode_bind_response() {
+        let success = [
+            0x02, 0x01, 0x01, 0x61, 0x07, 0x0A, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+        ];
+        assert_eq!(decode_bind_response(&success).unwrap(), SUCCESS);
+
+        let invalid = [
+            0x02, 0x01, 0x01, 0x61, 0x07, 0x0A, 0x01, 0x31, 0x04, 0x00, 0x04, 0x00,
+        ];
+        assert_eq!(decode_bind_response(&invalid).unwrap(), INVALID_CREDENTIALS);
+
+        assert!(decode_bind_response(&[0x02, 0x01]).is_err());
+    }
+
+    #[test]
+    fn test_decode_tlv_invalid_length() {
+        // long form length with more bytes than can be represented
+        assert!(decode_tlv(&[0x04, 0x89, 1, 2, 3, 4, 5, 6, 7, 8, 9]).is_err());
+        // long form length far beyond the end of the input
+        assert!(decode_tlv(&[0x04, 0x84, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_timeout() {
+        // accepts connections but never replies
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let _server = tokio::spawn(async move {
+            let mut connections = vec![];
+            loop {
+                let (connection, _) = listener.accept().await.unwrap();
+                connections.push(connection);
+            }
+        });
+
+        let provider = LdapConfig {
+            address,
+            bind_dn_template: "uid={username}".into(),
+            tls: None,
+            connect_timeout_ms: 3000,
+            request_timeout_ms: Some(100),
+            cache_ttl_seconds: None,
+        }
+        .build()
+        .unwrap();
+        let err = provider.authenticate("alice", "pw").await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "LDAP server did not respond to the bind within 100ms"
+        );
+    }
+
+    #[test]
+    fn test_escape_dn_value() {
+        assert_eq!(escape_dn_value("alice"), "alice");
+        assert_eq!(escape_dn_value("a,b=c"), "a\\,b\\=c");
+        assert_eq!(escape_dn_value(" #x "), "\\ #x\\ ");
+        assert_eq!(escape_dn_value("#x"), "\\#x");
+    }
+}
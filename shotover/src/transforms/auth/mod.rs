@@ -0,0 +1,241 @@
+//! Pluggable credential checking shared by the auth termination transforms.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+mod ldap;
+mod oidc;
+
+pub use ldap::LdapConfig;
+pub use oidc::OidcConfig;
+
+/// Configures where the credentials presented by clients are checked.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum AuthProviderConfig {
+    /// A fixed set of users configured inline and/or loaded from a yaml file.
+    Static {
+        /// Usernames mapped to passwords that clients may authenticate with.
+        #[serde(default)]
+        users: HashMap<String, String>,
+        /// Path to a yaml file containing a map of usernames to passwords, merged with `users`.
+        users_file: Option<String>,
+    },
+    /// Credentials are checked by performing an LDAP simple bind with them.
+    Ldap(LdapConfig),
+    /// The password is an OAuth2 access token checked via the OIDC provider's token introspection endpoint.
+    Oidc(OidcConfig),
+}
+
+impl AuthProviderConfig {
+    pub(crate) fn build(
+        &self,
+        chain_name: &str,
+        transform_name: &'static str,
+    ) -> Result<Authenticator> {
+        let (provider, cache_ttl_seconds): (Box<dyn AuthProvider>, _) = match self {
+            AuthProviderConfig::Static { users, users_file } => {
+                let users = StaticUsers::load(users, users_file.as_deref())?;
+                if users.is_empty() {
+                    bail!("at least one user must be configured via users or users_file");
+                }
+                (Box::new(users), None)
+            }
+            AuthProviderConfig::Ldap(config) => {
+                (Box::new(config.build()?), config.cache_ttl_seconds)
+            }
+            AuthProviderConfig::Oidc(config) => {
+                (Box::new(config.build()?), config.cache_ttl_seconds)
+            }
+        };
+        Ok(Authenticator {
+            provider,
+            cache: cache_ttl_seconds.map(|ttl| AuthCache::new(Duration::from_secs(ttl))),
+            failures: counter!("shotover_auth_failures_count", "chain" => chain_name.to_owned(), "transform" => transform_name),
+        })
+    }
+}
+
+#[async_trait]
+trait AuthProvider: Send + Sync {
+    /// Returns the identity of the client if the credentials are valid.
+    /// An Err is returned when the validity of the credentials could not be determined, e.g. the provider is unreachable.
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<String>>;
+}
+
+/// Checks client credentials against the configured provider, caching successful results when configured to.
+/// A single `Authenticator` is shared by all connections of a transform.
+pub struct Authenticator {
+    provider: Box<dyn AuthProvider>,
+    cache: Option<AuthCache>,
+    failures: Counter,
+}
+
+impl Authenticator {
+    /// Returns the identity of the client if the credentials are valid.
+    pub async fn authenticate(&self, username: &str, password: &str) -> Option<String> {
+        if let Some(identity) = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.get(username, password))
+        {
+            return Some(identity);
+        }
+
+        match self.provider.authenticate(username, password).await {
+            Ok(Some(identity)) => {
+                if let Some(cache) = &self.cache {
+                    cache.insert(username, password, identity.clone());
+                }
+                Some(identity)
+            }
+            Ok(None) => {
+                self.failures.increment(1);
+                None
+            }
+            Err(err) => {
+                tracing::warn!("Failed to authenticate {username:?}: {err:?}");
+                self.failures.increment(1);
+                None
+            }
+        }
+    }
+}
+
+/// Remembers successful authentications so that the provider is not contacted for every new connection.
+/// Passwords are never stored, entries are keyed by a hash of the password with a key randomly generated at startup.
+struct AuthCache {
+    ttl: Duration,
+    hasher: RandomState,
+    entries: Mutex<HashMap<(String, u64), (String, Instant)>>,
+}
+
+impl AuthCache {
+    fn new(ttl: Duration) -> Self {
+        AuthCache {
+            ttl,
+            hasher: RandomState::new(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get(&self, username: &str, password: &str) -> Option<String> {
+        let key = (username.to_owned(), self.hasher.hash_one(password));
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(&key) {
+            Some((identity, inserted)) if inserted.elapsed() < self.ttl => Some(identity.clone()),
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, username: &str, password: &str, identity: String) {
+        let key = (username.to_owned(), self.hasher.hash_one(password));
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, inserted)| inserted.elapsed() < self.ttl);
+        entries.insert(key, (identity, Instant::now()));
+    }
+}
+
+/// A fixed set of usernames and passwords that clients may authenticate with.
+#[derive(Debug, Clone, Default)]
+pub struct StaticUsers {
+    users: HashMap<String, String>,
+}
+
+impl StaticUsers {
+    /// Combines the users configured inline with those from `users_file`, a yaml file containing a map of usernames to passwords.
+    pub fn load(users: &HashMap<String, String>, users_file: Option<&str>) -> Result<Self> {
+        let mut users = users.clone();
+        if let Some(path) = users_file {
+            let file = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read users_file {path:?}"))?;
+            let file_users: HashMap<String, String> = serde_yaml::from_str(&file)
+                .with_context(|| format!("Failed to parse users_file {path:?}"))?;
+            users.extend(file_users);
+        }
+        Ok(StaticUsers { users })
+    }
+
+    /// Returns true if `password` is the password of `username`.
+    /// The password is compared in constant time so that response times do not reveal how much of it was correct.
+    pub fn check(&self, username: &str, password: &str) -> bool {
+        self.users
+            .get(username)
+            .is_some_and(|x| x.as_bytes().ct_eq(password.as_bytes()).into())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+#[async_trait]
+impl AuthProvider for StaticUsers {
+    async fn authenticate(&self, username: &str, password: &str) -> Result<Option<String>> {
+        Ok(self.check(username, password).then(|| username.to_owned()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn static_authenticator(users: &[(&str, &str)]) -> Authenticator {
+    AuthProviderConfig::Static {
+        users: users
+            .iter()
+            .map(|(user, pass)| (user.to_string(), pass.to_string()))
+            .collect(),
+        users_file: None,
+    }
+    .build("test_chain", "test_transform")
+    .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_static_users() {
+        let users = StaticUsers::load(
+            &HashMap::from([("user".to_owned(), "pass".to_owned())]),
+            None,
+        )
+        .unwrap();
+        assert!(users.check("user", "pass"));
+        assert!(!users.check("user", "wrong"));
+        assert!(!users.check("other", "pass"));
+    }
+
+    #[test]
+    fn test_auth_cache() {
+        let cache = AuthCache::new(Duration::from_secs(60));
+        cache.insert("user", "pass", "user".to_owned());
+        assert_eq!(cache.get("user", "pass"), Some("user".to_owned()));
+        assert_eq!(cache.get("user", "wrong"), None);
+        assert_eq!(cache.get("other", "pass"), None);
+
+        let cache = AuthCache::new(Duration::ZERO);
+        cache.insert("user", "pass", "user".to_owned());
+        assert_eq!(cache.get("user", "pass"), None);
+    }
+
+    #[tokio::test]
+    async fn test_static_authenticator() {
+        let authenticator = static_authenticator(&[("user", "pass")]);
+        assert_eq!(
+            authenticator.authenticate("user", "pass").await,
+            Some("user".to_owned())
+        );
+        assert_eq!(authenticator.authenticate("user", "wrong").await, None);
+    }
+}
@@ -0,0 +1,129 @@
+use super::AuthProvider;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bounds the time taken by the whole introspection request, as the client's AUTH is held until it completes
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    /// The OAuth2 token introspection endpoint (RFC 7662) of the OIDC provider
+    pub introspection_url: String,
+    /// The client id shotover authenticates to the introspection endpoint with
+    pub client_id: String,
+    /// The client secret shotover authenticates to the introspection endpoint with
+    pub client_secret: String,
+    /// The claim of the introspection response used as the client's identity, defaults to `username`.
+    pub username_claim: Option<String>,
+    /// When set, successful authentications are remembered for this many seconds.
+    /// This should be kept short as a token revoked within this time will continue to be accepted.
+    pub cache_ttl_seconds: Option<u64>,
+}
+
+impl OidcConfig {
+    pub(super) fn build(&self) -> Result<OidcProvider> {
+        Ok(OidcProvider {
+            client: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .context("Failed to create the introspection http client")?,
+            introspection_url: reqwest::Url::parse(&self.introspection_url)
+                .context("Failed to parse introspection_url")?,
+            client_id: self.client_id.clone(),
+            client_secret: self.client_secret.clone(),
+            username_claim: self
+                .username_claim
+                .clone()
+                .unwrap_or_else(|| "username".to_owned()),
+        })
+    }
+}
+
+pub(super) struct OidcProvider {
+    client: reqwest::Client,
+    introspection_url: reqwest::Url,
+    client_id: String,
+    client_secret: String,
+    username_claim: String,
+}
+
+#[derive(Deserialize)]
+struct IntrospectionResponse {
+    active: bool,
+    #[serde(flatten)]
+    claims: HashMap<String, Claim>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Claim {
+    String(String),
+    Other(IgnoredAny),
+}
+
+impl IntrospectionResponse {
+    fn identity(&self, username_claim: &str) -> Option<String> {
+        if !self.active {
+            return None;
+        }
+        match self.claims.get(username_claim) {
+            Some(Claim::String(identity)) => Some(identity.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    /// The password is the access token, the username presented by the client is ignored in favour of the token's claims.
+    async fn authenticate(&self, _username: &str, password: &str) -> Result<Option<String>> {
+        if password.is_empty() {
+            return Ok(None);
+        }
+
+        let response: IntrospectionResponse = self
+            .client
+            .post(self.introspection_url.clone())
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("token", password)])
+            .send()
+            .await
+            .context("Failed to send token introspection request")?
+            .error_for_status()
+            .context("Token introspection request failed")?
+            .json()
+            .await
+            .context("Failed to parse token introspection response")?;
+
+        Ok(response.identity(&self.username_claim))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(json: &str) -> IntrospectionResponse {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_introspection_identity() {
+        let response =
+            parse(r#"{"active": true, "username": "alice", "sub": "1234", "exp": 1700000000}"#);
+        assert_eq!(response.identity("username"), Some("alice".to_owned()));
+        assert_eq!(response.identity("sub"), Some("1234".to_owned()));
+        assert_eq!(response.identity("exp"), None);
+        assert_eq!(response.identity("missing"), None);
+
+        let response = parse(r#"{"active": false}"#);
+        assert_eq!(response.identity("username"), None);
+    }
+}
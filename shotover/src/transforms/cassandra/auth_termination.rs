@@ -0,0 +1,301 @@
+use crate::frame::cassandra::Tracing;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, MessageIdSet, Messages};
+use crate::transforms::auth::{AuthProviderConfig, Authenticator};
+use crate::transforms::session::{AuthenticatedUser, SessionState};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The authenticator advertised to clients, all drivers support SASL PLAIN credentials for it.
+const PASSWORD_AUTHENTICATOR: &str = "org.apache.cassandra.auth.PasswordAuthenticator";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraAuthTerminationConfig {
+    /// Where the credentials presented by clients are checked.
+    pub auth_provider: AuthProviderConfig,
+    /// The username shotover authenticates with upstream.
+    pub upstream_username: String,
+    /// The password shotover authenticates with upstream.
+    pub upstream_password: String,
+    /// When true the client's username is sent upstream as the SASL authorization id, for use in audit logs.
+    #[serde(default)]
+    pub forward_username_as_authzid: bool,
+}
+
+const NAME: &str = "CassandraAuthTermination";
+#[typetag::serde(name = "CassandraAuthTermination")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraAuthTerminationConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let authenticator = self
+            .auth_provider
+            .build(&transform_context.chain_name, NAME)?;
+
+        Ok(Box::new(CassandraAuthTerminationBuilder {
+            authenticator: Arc::new(authenticator),
+            upstream_username: self.upstream_username.clone(),
+            upstream_password: self.upstream_password.clone(),
+            forward_username_as_authzid: self.forward_username_as_authzid,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct CassandraAuthTerminationBuilder {
+    authenticator: Arc<Authenticator>,
+    upstream_username: String,
+    upstream_password: String,
+    forward_username_as_authzid: bool,
+}
+
+impl TransformBuilder for CassandraAuthTerminationBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraAuthTermination {
+            authenticator: self.authenticator.clone(),
+            upstream_username: self.upstream_username.clone(),
+            upstream_password: self.upstream_password.clone(),
+            forward_username_as_authzid: self.forward_username_as_authzid,
+            upstream_requires_auth: None,
+            startup_requests: MessageIdSet::default(),
+            upstream_auth_requests: MessageIdMap::default(),
+            local_responses: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct CassandraAuthTermination {
+    authenticator: Arc<Authenticator>,
+    upstream_username: String,
+    upstream_password: String,
+    forward_username_as_authzid: bool,
+    /// None until the upstream has responded to the STARTUP
+    upstream_requires_auth: Option<bool>,
+    startup_requests: MessageIdSet,
+    /// AUTH_RESPONSEs rewritten to use the upstream credentials, mapped to the user the client authenticated as
+    upstream_auth_requests: MessageIdMap<String>,
+    /// Responses generated by this transform, keyed by the id of the dummy request they respond to
+    local_responses: MessageIdMap<Message>,
+}
+
+/// Decodes the `[bytes]` body of an AUTH_RESPONSE containing a SASL PLAIN token of the form `authzid\0username\0password`
+fn decode_plain_credentials(body: &[u8]) -> Option<(String, String)> {
+    let len = i32::from_be_bytes(body.get(..4)?.try_into().ok()?);
+    let token = body.get(4..4 + usize::try_from(len).ok()?)?;
+    let mut parts = token.split(|x| *x == 0);
+    let _authzid = parts.next()?;
+    let username = String::from_utf8(parts.next()?.to_vec()).ok()?;
+    let password = String::from_utf8(parts.next()?.to_vec()).ok()?;
+    Some((username, password))
+}
+
+/// Encodes the `[bytes]` body of an AUTH_RESPONSE containing a SASL PLAIN token
+fn encode_plain_credentials(authzid: &str, username: &str, password: &str) -> Vec<u8> {
+    let token = [authzid.as_bytes(), username.as_bytes(), password.as_bytes()].join(&0);
+    let mut body = (token.len() as i32).to_be_bytes().to_vec();
+    body.extend(token);
+    body
+}
+
+/// Encodes the `[string]` body of an AUTHENTICATE
+fn encode_authenticator(authenticator: &str) -> Vec<u8> {
+    let mut body = (authenticator.len() as u16).to_be_bytes().to_vec();
+    body.extend(authenticator.as_bytes());
+    body
+}
+
+fn authentication_error(message: &str) -> CassandraOperation {
+    CassandraOperation::Error(ErrorBody {
+        message: message.into(),
+        ty: ErrorType::Authentication,
+    })
+}
+
+impl CassandraAuthTermination {
+    fn respond_locally(&mut self, request: &mut Message, operation: CassandraOperation) {
+        if let Some(Frame::Cassandra(frame)) = request.frame() {
+            let mut response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version: frame.version,
+                stream_id: frame.stream_id,
+                tracing: Tracing::Response(None),
+                warnings: vec![],
+                operation,
+            }));
+            response.set_request_id(request.id());
+            self.local_responses.insert(request.id(), response);
+            request.replace_with_dummy();
+        }
+    }
+
+    async fn process_request(&mut self, request: &mut Message, session: &mut SessionState) {
+        let id = request.id();
+        let body = match request.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Startup(_),
+                ..
+            })) => {
+                self.startup_requests.insert(id);
+                return;
+            }
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Options(_),
+                ..
+            })) => return,
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::AuthResponse(body),
+                ..
+            })) => body,
+            _ => {
+                if session.get::<AuthenticatedUser>().is_none() {
+                    self.respond_locally(
+                        request,
+                        authentication_error("Authentication is required by shotover"),
+                    );
+                }
+                return;
+            }
+        };
+
+        let identity = match decode_plain_credentials(body) {
+            Some((username, password)) => {
+                self.authenticator.authenticate(&username, &password).await
+            }
+            None => None,
+        };
+        let Some(username) = identity else {
+            self.respond_locally(
+                request,
+                authentication_error("Provided username and/or password are incorrect"),
+            );
+            return;
+        };
+
+        if self.upstream_requires_auth == Some(false) {
+            session.insert(AuthenticatedUser(username));
+            // AUTH_SUCCESS with a null token
+            self.respond_locally(request, CassandraOperation::AuthSuccess(vec![0xFF; 4]));
+        } else {
+            let authzid = if self.forward_username_as_authzid {
+                username.as_str()
+            } else {
+                ""
+            };
+            *body =
+                encode_plain_credentials(authzid, &self.upstream_username, &self.upstream_password);
+            request.invalidate_cache();
+            self.upstream_auth_requests.insert(id, username);
+        }
+    }
+
+    fn process_response(&mut self, response: &mut Message, session: &mut SessionState) {
+        let Some(request_id) = response.request_id() else {
+            return;
+        };
+
+        if let Some(local) = self.local_responses.remove(&request_id) {
+            *response = local;
+        } else if self.startup_requests.remove(&request_id) {
+            if let Some(Frame::Cassandra(frame)) = response.frame() {
+                match frame.operation {
+                    CassandraOperation::Authenticate(_) => {
+                        self.upstream_requires_auth = Some(true);
+                    }
+                    CassandraOperation::Ready(_) => {
+                        self.upstream_requires_auth = Some(false);
+                    }
+                    _ => return,
+                }
+                // Clients must always authenticate with shotover, regardless of what upstream requires.
+                frame.operation =
+                    CassandraOperation::Authenticate(encode_authenticator(PASSWORD_AUTHENTICATOR));
+                response.invalidate_cache();
+            }
+        } else if let Some(username) = self.upstream_auth_requests.remove(&request_id) {
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::AuthSuccess(_),
+                ..
+            })) = response.frame()
+            {
+                session.insert(AuthenticatedUser(username));
+            } else {
+                tracing::error!(
+                    "Upstream cassandra rejected the upstream credentials configured for {NAME}"
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraAuthTermination {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut requests = std::mem::take(&mut chain_state.requests);
+        for request in &mut requests {
+            self.process_request(request, &mut chain_state.session)
+                .await;
+        }
+        chain_state.requests = requests;
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in &mut responses {
+            self.process_response(response, &mut chain_state.session);
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_plain_credentials_roundtrip() {
+        let body = encode_plain_credentials("", "user", "pass");
+        assert_eq!(body, [&[0, 0, 0, 10][..], b"\0user\0pass"].concat());
+        assert_eq!(
+            decode_plain_credentials(&body),
+            Some(("user".to_owned(), "pass".to_owned()))
+        );
+
+        let body = encode_plain_credentials("client", "service", "secret");
+        assert_eq!(
+            decode_plain_credentials(&body),
+            Some(("service".to_owned(), "secret".to_owned()))
+        );
+
+        assert_eq!(decode_plain_credentials(&[0, 0, 0, 10, 1]), None);
+    }
+
+    #[test]
+    fn test_encode_authenticator() {
+        assert_eq!(encode_authenticator("abc"), vec![0, 3, b'a', b'b', b'c']);
+    }
+}
@@ -0,0 +1,660 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::kafka::StrBytes;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::kafka::{build_keyed_produce_request, check_produce_response};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{FQName, Identifier, Operand, RelationElement, RelationOperator};
+use cql3_parser::update::AssignmentOperator;
+use kafka_protocol::messages::TopicName;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+/// How long to wait before retrying to publish events that kafka did not acknowledge, when no new events arrive in the meantime.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraCdcConfig {
+    /// The kafka topic that change events are published to.
+    pub topic: String,
+    /// Maps a fully qualified table name e.g. `keyspace1.table1` to its primary key columns.
+    /// Writes to tables not listed here do not generate change events.
+    pub tables: HashMap<String, Vec<String>>,
+    /// The maximum number of change events held while waiting to be acknowledged by kafka.
+    pub buffer_size: Option<usize>,
+    /// How long kafka should wait for the produce request to be replicated before responding.
+    pub produce_timeout_ms: Option<i32>,
+    pub chain: TransformChainConfig,
+}
+
+const NAME: &str = "CassandraCdc";
+#[typetag::serde(name = "CassandraCdc")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraCdcConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let tables = self
+            .tables
+            .iter()
+            .map(|(table, primary_key)| {
+                (
+                    FQName::parse(table),
+                    primary_key.iter().map(|x| Identifier::parse(x)).collect(),
+                )
+            })
+            .collect();
+
+        let transform_context_config = TransformContextConfig {
+            chain_name: "cdc_chain".into(),
+            up_chain_protocol: MessageType::Kafka,
+        };
+
+        Ok(Box::new(CassandraCdcBuilder {
+            kafka_chain: self.chain.get_builder(transform_context_config).await?,
+            topic: self.topic.clone(),
+            tables,
+            buffer_size: self.buffer_size.unwrap_or(10_000),
+            produce_timeout_ms: self.produce_timeout_ms.unwrap_or(30_000),
+            dropped_events: counter!("shotover_cassandra_cdc_dropped_events_count"),
+            skipped_statements: counter!("shotover_cassandra_cdc_skipped_statements_count"),
+            published_events: counter!("shotover_cassandra_cdc_published_events_count"),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct CassandraCdcBuilder {
+    kafka_chain: TransformChainBuilder,
+    topic: String,
+    tables: HashMap<FQName, Vec<Identifier>>,
+    buffer_size: usize,
+    produce_timeout_ms: i32,
+    dropped_events: Counter,
+    skipped_statements: Counter,
+    published_events: Counter,
+}
+
+impl TransformBuilder for CassandraCdcBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraCdc {
+            tables: self.tables.clone(),
+            dropped_events: self.dropped_events.clone(),
+            skipped_statements: self.skipped_statements.clone(),
+            pending_requests: Default::default(),
+            publisher: PublisherState::NotStarted(Publisher {
+                kafka_chain: self.kafka_chain.build(transform_context),
+                topic: TopicName(StrBytes::from_string(self.topic.clone())),
+                buffer_size: self.buffer_size,
+                produce_timeout_ms: self.produce_timeout_ms,
+                dropped_events: self.dropped_events.clone(),
+                published_events: self.published_events.clone(),
+                unpublished_events: VecDeque::new(),
+            }),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .kafka_chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if self.buffer_size == 0 {
+            errors.push("  buffer_size must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+/// A single change to a row, serialized as JSON into the value of a kafka record.
+#[derive(Serialize, Debug, PartialEq)]
+struct ChangeEvent {
+    keyspace: Option<String>,
+    table: String,
+    operation: ChangeOperation,
+    primary_key: BTreeMap<String, String>,
+    columns: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl ChangeEvent {
+    /// The kafka record key is built from the primary key so that all changes to a row land in the same partition.
+    fn record_key(&self) -> Bytes {
+        let mut key = match &self.keyspace {
+            Some(keyspace) => format!("{keyspace}.{}", self.table),
+            None => self.table.clone(),
+        };
+        for value in self.primary_key.values() {
+            key.push(':');
+            key.push_str(value);
+        }
+        key.into()
+    }
+}
+
+struct CassandraCdc {
+    tables: HashMap<FQName, Vec<Identifier>>,
+    dropped_events: Counter,
+    /// Counts writes to captured tables that could not be turned into a change event
+    skipped_statements: Counter,
+    /// Change events generated from requests that have not yet received a response
+    pending_requests: MessageIdMap<Vec<ChangeEvent>>,
+    publisher: PublisherState,
+}
+
+/// Change events are published by a background task so that requests are never held up waiting on kafka.
+/// The task is started when the first event is generated, as the kafka chain needs the address of the connection.
+enum PublisherState {
+    NotStarted(Publisher),
+    Running {
+        events_tx: mpsc::Sender<ChangeEvent>,
+        task: JoinHandle<()>,
+    },
+    Stopped,
+}
+
+impl CassandraCdc {
+    fn event_for_statement(&self, statement: &CassandraStatement) -> Option<ChangeEvent> {
+        let table_name = statement.get_table_name()?;
+        let primary_key_columns = self.tables.get(table_name)?;
+
+        // The values of bound parameters are not part of the statement text, so a change event cannot be built from it
+        let has_bound_params = match statement {
+            CassandraStatement::Insert(insert) => {
+                insert.get_value_map().values().any(|x| is_bound_param(x))
+            }
+            CassandraStatement::Update(update) => {
+                update.assignments.iter().any(|assignment| {
+                    is_bound_param(&assignment.value)
+                        || is_bound_index(&assignment.name.idx)
+                        || matches!(
+                            &assignment.operator,
+                            Some(AssignmentOperator::Plus(x) | AssignmentOperator::Minus(x)) if is_bound_param(x)
+                        )
+                }) || update.where_clause.iter().any(|x| is_bound_param(&x.value))
+            }
+            CassandraStatement::Delete(delete) => {
+                delete.columns.iter().any(|x| is_bound_index(&x.idx))
+                    || delete.where_clause.iter().any(|x| is_bound_param(&x.value))
+            }
+            _ => false,
+        };
+        if has_bound_params {
+            self.skipped_statements.increment(1);
+            return None;
+        }
+
+        let (operation, mut columns, where_clause): (_, BTreeMap<String, String>, &[_]) =
+            match statement {
+                CassandraStatement::Insert(insert) => (
+                    ChangeOperation::Insert,
+                    insert
+                        .get_value_map()
+                        .into_iter()
+                        .map(|(column, operand)| (column.to_string(), operand.to_string()))
+                        .collect(),
+                    &[],
+                ),
+                CassandraStatement::Update(update) => (
+                    ChangeOperation::Update,
+                    update
+                        .assignments
+                        .iter()
+                        .map(|assignment| {
+                            let value = match assignment.operator {
+                                Some(_) => assignment.to_string(),
+                                None => assignment.value.to_string(),
+                            };
+                            (assignment.name.to_string(), value)
+                        })
+                        .collect(),
+                    update.where_clause.as_slice(),
+                ),
+                CassandraStatement::Delete(delete) => (
+                    ChangeOperation::Delete,
+                    delete
+                        .columns
+                        .iter()
+                        .map(|column| (column.to_string(), Operand::Null.to_string()))
+                        .collect(),
+                    delete.where_clause.as_slice(),
+                ),
+                _ => return None,
+            };
+
+        let mut primary_key = BTreeMap::new();
+        for column in primary_key_columns {
+            let name = column.to_string();
+            if let Some(value) = columns.remove(&name) {
+                primary_key.insert(name, value);
+            } else if let Some(value) = primary_key_value_from_where_clause(column, where_clause) {
+                primary_key.insert(name, value);
+            } else {
+                tracing::warn!(
+                    "Skipping change event for write to {table_name}, primary key column {name} could not be determined"
+                );
+                return None;
+            }
+        }
+
+        Some(ChangeEvent {
+            keyspace: table_name.keyspace.as_ref().map(|x| x.to_string()),
+            table: table_name.name.to_string(),
+            operation,
+            primary_key,
+            columns,
+        })
+    }
+
+    fn store_pending_events(&mut self, requests: &mut Messages) {
+        for request in requests.iter_mut() {
+            let id = request.id();
+            if let Some(Frame::Cassandra(CassandraFrame { operation, .. })) = request.frame() {
+                let events: Vec<ChangeEvent> = operation
+                    .queries()
+                    .filter_map(|statement| self.event_for_statement(statement))
+                    .collect();
+                if !events.is_empty() {
+                    self.pending_requests.insert(id, events);
+                }
+            }
+        }
+    }
+
+    fn send_events_for_successful_writes(
+        &mut self,
+        responses: &mut [Message],
+        local_addr: SocketAddr,
+    ) {
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            let Some(events) = self.pending_requests.remove(&request_id) else {
+                continue;
+            };
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(_),
+                ..
+            })) = response.frame()
+            {
+                for event in events {
+                    self.send_event(event, local_addr);
+                }
+            }
+        }
+    }
+
+    /// Queues the event for publishing without waiting, the event is dropped if the queue is full.
+    fn send_event(&mut self, event: ChangeEvent, local_addr: SocketAddr) {
+        if let PublisherState::NotStarted(_) = self.publisher {
+            let PublisherState::NotStarted(publisher) =
+                std::mem::replace(&mut self.publisher, PublisherState::Stopped)
+            else {
+                unreachable!()
+            };
+            let (events_tx, events_rx) = mpsc::channel(publisher.buffer_size);
+            self.publisher = PublisherState::Running {
+                events_tx,
+                task: tokio::spawn(publisher.run(events_rx, local_addr)),
+            };
+        }
+
+        let PublisherState::Running { events_tx, .. } = &self.publisher else {
+            self.dropped_events.increment(1);
+            return;
+        };
+        if let Err(err) = events_tx.try_send(event) {
+            self.dropped_events.increment(1);
+            if let mpsc::error::TrySendError::Closed(_) = err {
+                tracing::error!("Dropped change event as the cdc publisher task has stopped");
+            }
+        }
+    }
+}
+
+struct Publisher {
+    kafka_chain: TransformChain,
+    topic: TopicName,
+    buffer_size: usize,
+    produce_timeout_ms: i32,
+    dropped_events: Counter,
+    published_events: Counter,
+    /// Change events for successful writes that have not yet been acknowledged by kafka.
+    /// Events are only removed once kafka acknowledges them, giving at-least-once delivery.
+    unpublished_events: VecDeque<ChangeEvent>,
+}
+
+impl Publisher {
+    /// Publishes events as they are received until the transform is shutdown.
+    async fn run(mut self, mut events_rx: mpsc::Receiver<ChangeEvent>, local_addr: SocketAddr) {
+        loop {
+            let received = if self.unpublished_events.is_empty() {
+                events_rx.recv().await
+            } else {
+                match timeout(RETRY_INTERVAL, events_rx.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        self.publish_events(local_addr).await;
+                        continue;
+                    }
+                }
+            };
+            let Some(event) = received else {
+                break;
+            };
+            self.buffer_event(event);
+            // Publish every event that has queued up in a single produce request
+            while let Ok(event) = events_rx.try_recv() {
+                self.buffer_event(event);
+            }
+            self.publish_events(local_addr).await;
+        }
+
+        if !self.unpublished_events.is_empty() {
+            self.dropped_events
+                .increment(self.unpublished_events.len() as u64);
+            tracing::error!(
+                "Dropped {} change events that could not be published before shutdown",
+                self.unpublished_events.len()
+            );
+        }
+        self.kafka_chain.shutdown().await;
+    }
+
+    fn buffer_event(&mut self, event: ChangeEvent) {
+        if self.unpublished_events.len() >= self.buffer_size {
+            self.unpublished_events.pop_front();
+            self.dropped_events.increment(1);
+        }
+        self.unpublished_events.push_back(event);
+    }
+
+    fn build_produce_request(&self) -> Result<Message> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0);
+        let values = self
+            .unpublished_events
+            .iter()
+            .map(|event| {
+                Ok((
+                    timestamp,
+                    Some(event.record_key()),
+                    serde_json::to_vec(event)?.into(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        build_keyed_produce_request(values, &self.topic, self.produce_timeout_ms)
+    }
+
+    /// Send all unpublished events to kafka.
+    /// If kafka does not acknowledge the events they are kept and retried along with the next events or after [`RETRY_INTERVAL`].
+    async fn publish_events(&mut self, local_addr: SocketAddr) {
+        if self.unpublished_events.is_empty() {
+            return;
+        }
+
+        let result = match self.build_produce_request() {
+            Ok(request) => {
+                self.kafka_chain
+                    .process_request(&mut ChainState::new_with_addr(vec![request], local_addr))
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result.and_then(check_produce_response) {
+            Ok(()) => {
+                self.published_events
+                    .increment(self.unpublished_events.len() as u64);
+                self.unpublished_events.clear();
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to publish {} change events, they will be retried: {err:?}",
+                    self.unpublished_events.len()
+                );
+            }
+        }
+    }
+}
+
+fn primary_key_value_from_where_clause(
+    column: &Identifier,
+    where_clause: &[RelationElement],
+) -> Option<String> {
+    where_clause
+        .iter()
+        .find(|relation| {
+            relation.oper == RelationOperator::Equal
+                && relation.obj == Operand::Column(column.clone())
+        })
+        .map(|relation| relation.value.to_string())
+}
+
+/// Returns true if the operand is, or contains, a `?` or `:name` bind marker
+fn is_bound_param(operand: &Operand) -> bool {
+    match operand {
+        Operand::Param(_) => true,
+        Operand::Tuple(operands) => operands.iter().any(is_bound_param),
+        _ => false,
+    }
+}
+
+fn is_bound_index(idx: &Option<String>) -> bool {
+    idx.as_deref()
+        .map(|x| x == "?" || x.starts_with(':'))
+        .unwrap_or(false)
+}
+
+#[async_trait]
+impl Transform for CassandraCdc {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        self.store_pending_events(&mut chain_state.requests);
+
+        let local_addr = chain_state.local_addr;
+        let mut responses = chain_state.call_next_transform().await?;
+
+        self.send_events_for_successful_writes(&mut responses, local_addr);
+
+        Ok(responses)
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        match std::mem::replace(&mut self.publisher, PublisherState::Stopped) {
+            PublisherState::NotStarted(mut publisher) => publisher.kafka_chain.shutdown().await,
+            PublisherState::Running { events_tx, task } => {
+                // Closing the channel lets the task finish publishing the queued events and shutdown the kafka chain
+                drop(events_tx);
+                task.await?;
+            }
+            PublisherState::Stopped => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::cassandra::parse_statement_single;
+    use crate::transforms::null::NullSinkConfig;
+    use pretty_assertions::assert_eq;
+
+    fn cdc_transform() -> CassandraCdc {
+        CassandraCdc {
+            tables: [(
+                FQName::parse("ks.table"),
+                vec![Identifier::parse("id"), Identifier::parse("ck")],
+            )]
+            .into_iter()
+            .collect(),
+            dropped_events: counter!("test"),
+            skipped_statements: counter!("test"),
+            pending_requests: Default::default(),
+            publisher: PublisherState::NotStarted(Publisher {
+                kafka_chain: TransformChainBuilder::new(vec![], "test")
+                    .build(TransformContextBuilder::new_test()),
+                topic: TopicName(StrBytes::from_static_str("cdc")),
+                buffer_size: 10,
+                produce_timeout_ms: 1000,
+                dropped_events: counter!("test"),
+                published_events: counter!("test"),
+                unpublished_events: VecDeque::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn insert_event() {
+        let statement =
+            parse_statement_single("INSERT INTO ks.table (id, ck, name) VALUES (1, 2, 'foo')");
+        assert_eq!(
+            cdc_transform().event_for_statement(&statement),
+            Some(ChangeEvent {
+                keyspace: Some("ks".into()),
+                table: "table".into(),
+                operation: ChangeOperation::Insert,
+                primary_key: [("ck".into(), "2".into()), ("id".into(), "1".into())].into(),
+                columns: [("name".into(), "'foo'".into())].into(),
+            })
+        );
+    }
+
+    #[test]
+    fn update_event() {
+        let statement =
+            parse_statement_single("UPDATE ks.table SET name = 'bar' WHERE id = 1 AND ck = 2");
+        assert_eq!(
+            cdc_transform().event_for_statement(&statement),
+            Some(ChangeEvent {
+                keyspace: Some("ks".into()),
+                table: "table".into(),
+                operation: ChangeOperation::Update,
+                primary_key: [("ck".into(), "2".into()), ("id".into(), "1".into())].into(),
+                columns: [("name".into(), "'bar'".into())].into(),
+            })
+        );
+    }
+
+    #[test]
+    fn incomplete_primary_key_skipped() {
+        let statement = parse_statement_single("DELETE FROM ks.table WHERE id = 1");
+        assert_eq!(cdc_transform().event_for_statement(&statement), None);
+    }
+
+    #[test]
+    fn bound_params_skipped() {
+        for query in [
+            "INSERT INTO ks.table (id, ck, name) VALUES (?, ?, ?)",
+            "INSERT INTO ks.table (id, ck, name) VALUES (1, 2, :name)",
+            "UPDATE ks.table SET name = 'bar' WHERE id = ? AND ck = 2",
+            "UPDATE ks.table SET name = ? WHERE id = 1 AND ck = 2",
+            "DELETE FROM ks.table WHERE id = 1 AND ck = ?",
+        ] {
+            let statement = parse_statement_single(query);
+            assert_eq!(
+                cdc_transform().event_for_statement(&statement),
+                None,
+                "{query}"
+            );
+        }
+    }
+
+    #[test]
+    fn unconfigured_table_skipped() {
+        let statement =
+            parse_statement_single("INSERT INTO ks.other (id, ck, name) VALUES (1, 2, 'foo')");
+        assert_eq!(cdc_transform().event_for_statement(&statement), None);
+    }
+
+    #[tokio::test]
+    async fn test_validate_invalid_chain() {
+        let config = CassandraCdcConfig {
+            topic: "cdc".into(),
+            tables: HashMap::new(),
+            buffer_size: Some(0),
+            produce_timeout_ms: None,
+            chain: TransformChainConfig::new(vec![]),
+        };
+        let transform_context_config = TransformContextConfig {
+            chain_name: "".into(),
+            up_chain_protocol: MessageType::Cassandra,
+        };
+        let transform = config.get_builder(transform_context_config).await.unwrap();
+        assert_eq!(
+            transform.validate(),
+            vec![
+                "CassandraCdc:",
+                "  cdc_chain chain:",
+                "    Chain cannot be empty",
+                "  buffer_size must be greater than 0",
+            ]
+        );
+
+        let config = CassandraCdcConfig {
+            topic: "cdc".into(),
+            tables: HashMap::new(),
+            buffer_size: None,
+            produce_timeout_ms: None,
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
+        };
+        let transform_context_config = TransformContextConfig {
+            chain_name: "".into(),
+            up_chain_protocol: MessageType::Cassandra,
+        };
+        let transform = config.get_builder(transform_context_config).await.unwrap();
+        assert_eq!(transform.validate(), Vec::<String>::new());
+    }
+}
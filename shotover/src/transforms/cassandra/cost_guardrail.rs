@@ -0,0 +1,437 @@
+use crate::frame::cassandra::Tracing;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{ErrorBody, ErrorType};
+use cassandra_protocol::frame::Version;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use cql3_parser::common::{FQName, Operand, RelationElement, RelationOperator};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+
+/// Estimates the cost of each CQL request from its statements and acts on requests whose cost exceeds `max_cost`.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraCostGuardrailConfig {
+    /// Requests with an estimated cost above this are acted on.
+    pub max_cost: u64,
+    pub action: GuardrailAction,
+    /// The cost of a SELECT without a WHERE clause, defaults to 100.
+    pub full_table_scan_cost: Option<u64>,
+    /// The cost of a SELECT with ALLOW FILTERING, defaults to 50.
+    pub allow_filtering_cost: Option<u64>,
+    /// The cost of each value listed in an IN restriction, defaults to 1.
+    pub in_value_cost: Option<u64>,
+    /// The cost of each statement in a BATCH, defaults to 1.
+    pub batch_statement_cost: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum GuardrailAction {
+    /// Log a warning and send the request on unchanged.
+    Warn,
+    /// Send the request on and attach a warning to its response, which most drivers log or expose to the application.
+    /// Protocol v3 does not support warnings, so v3 responses are returned unchanged.
+    Tag,
+    /// Respond with an `Invalid` error instead of sending the request on.
+    Reject,
+}
+
+const NAME: &str = "CassandraCostGuardrail";
+#[typetag::serde(name = "CassandraCostGuardrail")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraCostGuardrailConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(CassandraCostGuardrailBuilder {
+            max_cost: self.max_cost,
+            action: self.action,
+            costs: Costs {
+                full_table_scan: self.full_table_scan_cost.unwrap_or(100),
+                allow_filtering: self.allow_filtering_cost.unwrap_or(50),
+                in_value: self.in_value_cost.unwrap_or(1),
+                batch_statement: self.batch_statement_cost.unwrap_or(1),
+            },
+            rule_counts: Rule::ALL.map(|rule| {
+                counter!("shotover_cost_guardrail_rule_count", "chain" => chain_name.clone(), "rule" => rule.as_str())
+            }),
+            exceeded: counter!("shotover_cost_guardrail_exceeded_count", "chain" => chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Costs {
+    full_table_scan: u64,
+    allow_filtering: u64,
+    in_value: u64,
+    batch_statement: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rule {
+    FullTableScan,
+    AllowFiltering,
+    InValues,
+    BatchStatements,
+}
+
+impl Rule {
+    const ALL: [Rule; 4] = [
+        Rule::FullTableScan,
+        Rule::AllowFiltering,
+        Rule::InValues,
+        Rule::BatchStatements,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Rule::FullTableScan => "full_table_scan",
+            Rule::AllowFiltering => "allow_filtering",
+            Rule::InValues => "in_values",
+            Rule::BatchStatements => "batch_statements",
+        }
+    }
+}
+
+/// The estimated cost of a request and the rules that contributed to it.
+#[derive(Default, Debug, PartialEq)]
+struct Estimate {
+    cost: u64,
+    rules: Vec<Rule>,
+}
+
+impl Estimate {
+    fn add(&mut self, rule: Rule, cost: u64) {
+        self.cost = self.cost.saturating_add(cost);
+        if !self.rules.contains(&rule) {
+            self.rules.push(rule);
+        }
+    }
+}
+
+impl Costs {
+    fn estimate(&self, frame: &mut CassandraFrame) -> Estimate {
+        let mut estimate = Estimate::default();
+        // Checking the statement when it is prepared covers every later EXECUTE of it
+        if let Some(statement) = frame.prepared_statement() {
+            self.estimate_statement(&statement, &mut estimate);
+            return estimate;
+        }
+        if let CassandraOperation::Batch(batch) = &frame.operation {
+            let count = batch.statement_count() as u64;
+            estimate.add(
+                Rule::BatchStatements,
+                count.saturating_mul(self.batch_statement),
+            );
+        }
+        for statement in frame.operation.queries() {
+            self.estimate_statement(statement, &mut estimate);
+        }
+        estimate
+    }
+
+    fn estimate_statement(&self, statement: &CassandraStatement, estimate: &mut Estimate) {
+        let where_clause: &[RelationElement] = match statement {
+            CassandraStatement::Select(select) => {
+                // Drivers scan the small system tables when connecting
+                if is_system_table(&select.table_name) {
+                    return;
+                }
+                if select.where_clause.is_empty() {
+                    estimate.add(Rule::FullTableScan, self.full_table_scan);
+                }
+                if select.filtering {
+                    estimate.add(Rule::AllowFiltering, self.allow_filtering);
+                }
+                &select.where_clause
+            }
+            CassandraStatement::Update(update) => &update.where_clause,
+            CassandraStatement::Delete(delete) => &delete.where_clause,
+            _ => &[],
+        };
+        for relation in where_clause {
+            if relation.oper == RelationOperator::In {
+                // A bind marker is counted as a single value since the bound values are not known
+                let count = match &relation.value {
+                    Operand::Tuple(values) => values.len() as u64,
+                    Operand::List(values) | Operand::Set(values) => values.len() as u64,
+                    _ => 1,
+                };
+                if count > 1 {
+                    estimate.add(Rule::InValues, count.saturating_mul(self.in_value));
+                }
+            }
+        }
+    }
+}
+
+fn is_system_table(table: &FQName) -> bool {
+    table
+        .keyspace
+        .as_ref()
+        .map(|keyspace| keyspace.to_string().trim_matches('"').starts_with("system"))
+        .unwrap_or(false)
+}
+
+struct CassandraCostGuardrailBuilder {
+    max_cost: u64,
+    action: GuardrailAction,
+    costs: Costs,
+    /// Counts the requests each rule was applied to, indexed by the position of the rule in `Rule::ALL`
+    rule_counts: [Counter; 4],
+    exceeded: Counter,
+}
+
+impl TransformBuilder for CassandraCostGuardrailBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraCostGuardrail {
+            max_cost: self.max_cost,
+            action: self.action,
+            costs: self.costs,
+            rule_counts: self.rule_counts.clone(),
+            exceeded: self.exceeded.clone(),
+            rejected: MessageIdMap::default(),
+            tagged: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.max_cost == 0 {
+            vec![
+                format!("{}:", self.get_name()),
+                "  max_cost must be greater than 0".to_owned(),
+            ]
+        } else {
+            vec![]
+        }
+    }
+}
+
+struct CassandraCostGuardrail {
+    max_cost: u64,
+    action: GuardrailAction,
+    costs: Costs,
+    rule_counts: [Counter; 4],
+    exceeded: Counter,
+    /// Error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
+    /// Warnings to attach to responses, keyed by the id of the request the response is for
+    tagged: MessageIdMap<String>,
+}
+
+#[async_trait]
+impl Transform for CassandraCostGuardrail {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in &mut chain_state.requests {
+            let id = request.id();
+            let Some(Frame::Cassandra(frame)) = request.frame() else {
+                continue;
+            };
+            let estimate = self.costs.estimate(frame);
+            for rule in &estimate.rules {
+                let index = Rule::ALL.iter().position(|x| x == rule).unwrap();
+                self.rule_counts[index].increment(1);
+            }
+            if estimate.cost <= self.max_cost {
+                continue;
+            }
+
+            self.exceeded.increment(1);
+            let message = format!(
+                "Estimated query cost {} exceeds the limit of {} due to: {}",
+                estimate.cost,
+                self.max_cost,
+                estimate
+                    .rules
+                    .iter()
+                    .map(|x| x.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            match self.action {
+                GuardrailAction::Warn => tracing::warn!("{message}, request: {frame}"),
+                GuardrailAction::Tag => {
+                    self.tagged.insert(id, message);
+                }
+                GuardrailAction::Reject => {
+                    let mut response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+                        version: frame.version,
+                        stream_id: frame.stream_id,
+                        operation: CassandraOperation::Error(ErrorBody {
+                            message,
+                            ty: ErrorType::Invalid,
+                        }),
+                        tracing: Tracing::Response(None),
+                        warnings: vec![],
+                    }));
+                    response.set_request_id(id);
+                    self.rejected.insert(id, response);
+                    request.replace_with_dummy();
+                }
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            if let Some(rejected) = self.rejected.remove(&request_id) {
+                *response = rejected;
+            } else if let Some(warning) = self.tagged.remove(&request_id) {
+                if let Some(Frame::Cassandra(frame)) = response.frame() {
+                    if frame.version != Version::V3 {
+                        frame.warnings.push(warning);
+                    }
+                }
+                response.invalidate_cache();
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{
+        assert_error_response, cassandra_query, error_message, MockSink, TestChain,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn costs() -> Costs {
+        Costs {
+            full_table_scan: 100,
+            allow_filtering: 50,
+            in_value: 1,
+            batch_statement: 1,
+        }
+    }
+
+    fn estimate(query: &str) -> Estimate {
+        match cassandra_query(query).frame() {
+            Some(Frame::Cassandra(frame)) => costs().estimate(frame),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(
+            estimate("SELECT * FROM ks.tbl WHERE id = 1"),
+            Estimate::default()
+        );
+        assert_eq!(
+            estimate("SELECT * FROM ks.tbl"),
+            Estimate {
+                cost: 100,
+                rules: vec![Rule::FullTableScan]
+            }
+        );
+        assert_eq!(
+            estimate("SELECT * FROM ks.tbl WHERE x = 1 ALLOW FILTERING"),
+            Estimate {
+                cost: 50,
+                rules: vec![Rule::AllowFiltering]
+            }
+        );
+        assert_eq!(
+            estimate("SELECT * FROM ks.tbl WHERE id IN (1, 2, 3)"),
+            Estimate {
+                cost: 3,
+                rules: vec![Rule::InValues]
+            }
+        );
+        assert_eq!(estimate("SELECT * FROM system.peers"), Estimate::default());
+    }
+
+    fn config(action: GuardrailAction) -> CassandraCostGuardrailConfig {
+        CassandraCostGuardrailConfig {
+            max_cost: 10,
+            action,
+            full_table_scan_cost: None,
+            allow_filtering_cost: None,
+            in_value_cost: None,
+            batch_statement_cost: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reject() {
+        let mut chain = TestChain::from_config(
+            &config(GuardrailAction::Reject),
+            MessageType::Cassandra,
+            MockSink::echo(),
+        )
+        .await
+        .unwrap();
+
+        let mut responses = chain
+            .send(vec![
+                cassandra_query("SELECT * FROM ks.tbl"),
+                cassandra_query("SELECT * FROM ks.tbl WHERE id = 1"),
+            ])
+            .await
+            .unwrap();
+        assert_error_response(
+            &mut responses[0],
+            "Estimated query cost 100 exceeds the limit of 10 due to: full_table_scan",
+        );
+        assert!(error_message(&mut responses[1]).is_none());
+        assert_eq!(chain.take_received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tag() {
+        let mut chain = TestChain::from_config(
+            &config(GuardrailAction::Tag),
+            MessageType::Cassandra,
+            MockSink::echo(),
+        )
+        .await
+        .unwrap();
+
+        let mut responses = chain
+            .send(vec![cassandra_query("SELECT * FROM ks.tbl")])
+            .await
+            .unwrap();
+        match responses[0].frame() {
+            Some(Frame::Cassandra(frame)) => assert_eq!(
+                frame.warnings,
+                vec!["Estimated query cost 100 exceeds the limit of 10 due to: full_table_scan"]
+            ),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+        assert_eq!(chain.take_received().len(), 1);
+    }
+}
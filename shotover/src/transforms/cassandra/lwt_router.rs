@@ -0,0 +1,423 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::cassandra::CassandraResult;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, MessageIdSet, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::util::setup_replay::is_setup_request;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::types::CBytesShort;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+/// Routes CQL conditional writes (`IF EXISTS`, `IF NOT EXISTS` and `IF <condition>`), also known as lightweight transactions, through their own chain.
+/// Conditional writes run a Paxos round between the replicas which makes them far slower than regular writes,
+/// so routing them separately allows sending them to specific coordinators and limiting how many run at once without slowing down the rest of the traffic.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraLwtRouterConfig {
+    /// The chain that conditional writes are routed through.
+    pub chain: TransformChainConfig,
+    /// The maximum number of conditional writes awaiting a response across all client connections.
+    /// Conditional writes received while at the limit receive an Overloaded error.
+    /// When None there is no limit.
+    pub max_concurrent_requests: Option<usize>,
+}
+
+const NAME: &str = "CassandraLwtRouter";
+#[typetag::serde(name = "CassandraLwtRouter")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraLwtRouterConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain = self
+            .chain
+            .get_builder(TransformContextConfig {
+                chain_name: format!("{}_lwt", transform_context.chain_name),
+                up_chain_protocol: transform_context.up_chain_protocol,
+            })
+            .await?;
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(CassandraLwtRouterBuilder {
+            chain: Arc::new(chain),
+            max_concurrent_requests: self.max_concurrent_requests,
+            shared: Arc::new(Shared {
+                semaphore: self
+                    .max_concurrent_requests
+                    .map(|x| Arc::new(Semaphore::new(x))),
+                prepared: Mutex::default(),
+            }),
+            lwt_requests: counter!("shotover_lwt_requests_count", "chain" => chain_name.clone()),
+            rejected_requests: counter!("shotover_lwt_rejected_requests_count", "chain" => chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// State shared by every connection.
+struct Shared {
+    /// Limits the number of conditional writes awaiting a response
+    semaphore: Option<Arc<Semaphore>>,
+    /// Ids of prepared statements that are conditional writes.
+    /// Ids are derived from the statement and keyspace so they are the same for every connection.
+    prepared: Mutex<HashSet<CBytesShort>>,
+}
+
+struct CassandraLwtRouterBuilder {
+    chain: Arc<TransformChainBuilder>,
+    max_concurrent_requests: Option<usize>,
+    shared: Arc<Shared>,
+    lwt_requests: Counter,
+    rejected_requests: Counter,
+}
+
+impl TransformBuilder for CassandraLwtRouterBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraLwtRouter {
+            chain_builder: self.chain.clone(),
+            chain: None,
+            transform_context,
+            shared: self.shared.clone(),
+            lwt_requests: self.lwt_requests.clone(),
+            rejected_requests: self.rejected_requests.clone(),
+            prepare_requests: MessageIdSet::default(),
+            permits: MessageIdMap::default(),
+            rejected: MessageIdMap::default(),
+            setup_requests: vec![],
+            setup_requests_sent: 0,
+            replayed_requests: MessageIdSet::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.max_concurrent_requests == Some(0) {
+            errors.push("  max_concurrent_requests must be greater than 0".to_owned());
+        }
+        errors.extend(self.chain.validate().iter().map(|x| format!("  {x}")));
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct CassandraLwtRouter {
+    chain_builder: Arc<TransformChainBuilder>,
+    /// Only built once a conditional write is received so that connections are only opened to the coordinators of conditional writes when the client uses them
+    chain: Option<TransformChain>,
+    transform_context: TransformContextBuilder,
+    shared: Arc<Shared>,
+    lwt_requests: Counter,
+    rejected_requests: Counter,
+    /// The ids of PREPARE requests for conditional writes, their responses contain the id of the prepared statement
+    prepare_requests: MessageIdSet,
+    /// The permits of conditional writes awaiting a response, keyed by request id
+    permits: MessageIdMap<OwnedSemaphorePermit>,
+    /// Overloaded error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
+    /// The STARTUP and AUTH_RESPONSE requests of the connection.
+    /// These are replayed to the conditional write chain so that its connection is set up in the same way as the main chain's.
+    setup_requests: Messages,
+    /// The number of `setup_requests` that the conditional write chain has received
+    setup_requests_sent: usize,
+    /// The ids of replayed setup requests, their responses must not reach the client
+    replayed_requests: MessageIdSet,
+}
+
+impl CassandraLwtRouter {
+    fn is_conditional_write(&self, frame: &mut CassandraFrame) -> bool {
+        if frame.is_conditional_write() {
+            return true;
+        }
+        let prepared = self.shared.prepared.lock().unwrap();
+        frame.prepared_ids().iter().any(|id| prepared.contains(*id))
+    }
+
+    /// Returns an error if `max_concurrent_requests` conditional writes are already awaiting a response
+    fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, TryAcquireError> {
+        self.shared
+            .semaphore
+            .as_ref()
+            .map(|semaphore| semaphore.clone().try_acquire_owned())
+            .transpose()
+    }
+
+    /// Adds the request to the requests to be sent to the conditional write chain, preceded by any setup requests the chain has not received yet.
+    fn push_request(&mut self, request: Message, routed: &mut Messages) {
+        for setup_request in &self.setup_requests[self.setup_requests_sent..] {
+            let replay = setup_request.clone_with_new_id();
+            self.replayed_requests.insert(replay.id());
+            routed.push(replay);
+        }
+        self.setup_requests_sent = self.setup_requests.len();
+        routed.push(request);
+    }
+
+    fn process_response(&mut self, response: &mut Message) {
+        let Some(request_id) = response.request_id() else {
+            return;
+        };
+        self.permits.remove(&request_id);
+        if self.prepare_requests.remove(&request_id) {
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                ..
+            })) = response.frame()
+            {
+                self.shared
+                    .prepared
+                    .lock()
+                    .unwrap()
+                    .insert(prepared.id.clone());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraLwtRouter {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        if let Some(chain) = &mut self.chain {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut routed = vec![];
+        for mut request in std::mem::take(&mut chain_state.requests) {
+            if is_setup_request(&mut request) {
+                self.setup_requests.push(request.clone());
+                chain_state.requests.push(request);
+                continue;
+            }
+            let id = request.id();
+            let (conditional_write, prepare) = match request.frame() {
+                Some(Frame::Cassandra(frame)) => (
+                    self.is_conditional_write(frame),
+                    matches!(frame.operation, CassandraOperation::Prepare(_)),
+                ),
+                _ => (false, false),
+            };
+            if !conditional_write {
+                chain_state.requests.push(request);
+                continue;
+            }
+
+            // A PREPARE only stores the statement, so it is not a Paxos round and is not limited
+            if prepare {
+                self.prepare_requests.insert(id);
+                self.push_request(request, &mut routed);
+                continue;
+            }
+
+            self.lwt_requests.increment(1);
+            match self.acquire() {
+                Ok(permit) => {
+                    if let Some(permit) = permit {
+                        self.permits.insert(id, permit);
+                    }
+                    self.push_request(request, &mut routed);
+                }
+                Err(_) => {
+                    self.rejected_requests.increment(1);
+                    self.rejected.insert(id, request.to_backpressure()?);
+                    request.replace_with_dummy();
+                    chain_state.requests.push(request);
+                }
+            }
+        }
+
+        if self.chain.is_none() && !routed.is_empty() {
+            self.chain = Some(self.chain_builder.build(self.transform_context.clone()));
+        }
+
+        let mut responses = match &mut self.chain {
+            // The chain is run even without any requests, to pick up any responses that arrived since the last run.
+            Some(chain) => {
+                let mut sub_chain_state = ChainState::new_with_addr(routed, chain_state.local_addr);
+                sub_chain_state.flush = chain_state.flush;
+                sub_chain_state.session = chain_state.session.clone();
+                let (main, lwt) = futures::join!(
+                    chain_state.call_next_transform(),
+                    chain.process_request(&mut sub_chain_state)
+                );
+                let mut responses = main?;
+                for response in lwt? {
+                    match response.request_id() {
+                        Some(id) if self.replayed_requests.remove(&id) => {}
+                        _ => responses.push(response),
+                    }
+                }
+                responses
+            }
+            None => chain_state.call_next_transform().await?,
+        };
+
+        for response in responses.iter_mut() {
+            self.process_response(response);
+            if let Some(request_id) = response.request_id() {
+                if let Some(error_response) = self.rejected.remove(&request_id) {
+                    *response = error_response;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::message::MessageId;
+    use crate::test_utils::{
+        assert_error_response, assert_responses_match_requests, cassandra_query, error_message,
+        MockSink, TestChain,
+    };
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+
+    fn builder(max_concurrent_requests: Option<usize>) -> CassandraLwtRouterBuilder {
+        CassandraLwtRouterBuilder {
+            chain: Arc::new(TransformChainBuilder::new(
+                vec![Box::new(Loopback::default())],
+                "lwt",
+            )),
+            max_concurrent_requests,
+            shared: Arc::new(Shared {
+                semaphore: max_concurrent_requests.map(|x| Arc::new(Semaphore::new(x))),
+                prepared: Mutex::default(),
+            }),
+            lwt_requests: Counter::noop(),
+            rejected_requests: Counter::noop(),
+        }
+    }
+
+    #[test]
+    fn test_is_conditional_write() {
+        let is_conditional_write = |query| match cassandra_query(query).frame() {
+            Some(Frame::Cassandra(frame)) => frame.is_conditional_write(),
+            frame => panic!("unexpected frame {frame:?}"),
+        };
+        assert!(is_conditional_write(
+            "INSERT INTO ks.tbl (id, x) VALUES (1, 2) IF NOT EXISTS"
+        ));
+        assert!(is_conditional_write(
+            "UPDATE ks.tbl SET x = 2 WHERE id = 1 IF x = 1"
+        ));
+        assert!(is_conditional_write(
+            "DELETE FROM ks.tbl WHERE id = 1 IF EXISTS"
+        ));
+        assert!(!is_conditional_write(
+            "INSERT INTO ks.tbl (id, x) VALUES (1, 2)"
+        ));
+        assert!(!is_conditional_write("SELECT * FROM ks.tbl WHERE id = 1"));
+    }
+
+    #[tokio::test]
+    async fn test_route_conditional_writes() {
+        let mut chain = TestChain::from_builder(&builder(None), MockSink::echo()).unwrap();
+        let requests = vec![
+            cassandra_query("INSERT INTO ks.tbl (id, x) VALUES (1, 2)"),
+            cassandra_query("INSERT INTO ks.tbl (id, x) VALUES (1, 2) IF NOT EXISTS"),
+            cassandra_query("SELECT * FROM ks.tbl WHERE id = 1"),
+        ];
+        let request_ids: Vec<MessageId> = requests.iter().map(|x| x.id()).collect();
+        let responses = chain.send(requests).await.unwrap();
+        assert_responses_match_requests(&request_ids, &responses);
+
+        // only the regular requests reach the main chain
+        assert_eq!(
+            chain
+                .take_received()
+                .iter()
+                .map(|x| x.id())
+                .collect::<Vec<_>>(),
+            vec![request_ids[0], request_ids[2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_requests() {
+        let builder = builder(Some(1));
+        let held = builder
+            .shared
+            .semaphore
+            .clone()
+            .unwrap()
+            .try_acquire_owned()
+            .unwrap();
+        let mut chain = TestChain::from_builder(&builder, MockSink::echo()).unwrap();
+
+        let mut responses = chain
+            .send(vec![cassandra_query(
+                "DELETE FROM ks.tbl WHERE id = 1 IF EXISTS",
+            )])
+            .await
+            .unwrap();
+        assert_error_response(&mut responses[0], "Server overloaded");
+        assert!(chain.take_received().is_empty());
+
+        // the permit is released once the response is received
+        drop(held);
+        let mut responses = chain
+            .send(vec![cassandra_query(
+                "DELETE FROM ks.tbl WHERE id = 1 IF EXISTS",
+            )])
+            .await
+            .unwrap();
+        assert_eq!(responses.len(), 1);
+        assert!(error_message(&mut responses[0]).is_none());
+        assert_eq!(
+            builder
+                .shared
+                .semaphore
+                .as_ref()
+                .unwrap()
+                .available_permits(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            builder(Some(0)).validate(),
+            vec![
+                "CassandraLwtRouter:",
+                "  max_concurrent_requests must be greater than 0",
+            ]
+        );
+    }
+}
@@ -1,3 +1,11 @@
+pub mod auth_termination;
+#[cfg(feature = "kafka")]
+pub mod cdc;
+pub mod cost_guardrail;
+pub mod lwt_router;
+pub mod page_aggregator;
 pub mod peers_rewrite;
+pub mod protocol_shim;
+pub mod schema;
 pub mod sink_cluster;
 pub mod sink_single;
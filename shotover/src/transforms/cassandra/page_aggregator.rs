@@ -0,0 +1,391 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::cassandra::CassandraResult;
+use crate::frame::value::GenericValue;
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_result::RowsMetadataFlags;
+use cassandra_protocol::types::CBytes;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// Transparently fetches all pages of a paged SELECT and returns them to the client as a single result.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraPageAggregatorConfig {
+    /// No more pages are fetched once the merged result contains at least this many rows.
+    pub max_rows: usize,
+    /// No more pages are fetched once the pages received for a result add up to at least this many bytes.
+    pub max_bytes: usize,
+    pub chain: TransformChainConfig,
+}
+
+const NAME: &str = "CassandraPageAggregator";
+#[typetag::serde(name = "CassandraPageAggregator")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraPageAggregatorConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let transform_context_config = TransformContextConfig {
+            chain_name: "page_aggregator_chain".into(),
+            up_chain_protocol: MessageType::Cassandra,
+        };
+
+        Ok(Box::new(CassandraPageAggregatorBuilder {
+            chain: self.chain.get_builder(transform_context_config).await?,
+            max_rows: self.max_rows,
+            max_bytes: self.max_bytes,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+struct CassandraPageAggregatorBuilder {
+    chain: TransformChainBuilder,
+    max_rows: usize,
+    max_bytes: usize,
+}
+
+impl TransformBuilder for CassandraPageAggregatorBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraPageAggregator {
+            chain: self.chain.build(transform_context),
+            max_rows: self.max_rows,
+            max_bytes: self.max_bytes,
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if self.max_rows == 0 {
+            errors.push("  max_rows must be greater than 0".to_owned());
+        }
+        if self.max_bytes == 0 {
+            errors.push("  max_bytes must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct CassandraPageAggregator {
+    chain: TransformChain,
+    max_rows: usize,
+    max_bytes: usize,
+}
+
+enum Pages {
+    Complete {
+        rows: Vec<Vec<GenericValue>>,
+        /// Some when a limit was reached before the last page was fetched
+        paging_state: Option<CBytes>,
+    },
+    /// Cassandra returned something other than rows part way through, this is returned to the client instead.
+    Failed(Message),
+}
+
+/// Returns true if the request is a paged SELECT.
+/// A prepared statement can only be paged if it is a SELECT so all paged EXECUTEs are included.
+fn is_paged_select(request: &mut Message) -> bool {
+    match request.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Query { query, params },
+            ..
+        })) => {
+            matches!(query.as_ref(), CassandraStatement::Select(_)) && params.page_size.is_some()
+        }
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Execute(execute),
+            ..
+        })) => execute.query_parameters.page_size.is_some(),
+        _ => false,
+    }
+}
+
+/// Creates a request for the page following `paging_state` from the original request.
+fn next_page_request(request: &Message, paging_state: CBytes) -> Message {
+    let mut next = request.clone_with_new_id();
+    match next.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Query { params, .. },
+            ..
+        })) => params.paging_state = Some(paging_state),
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Execute(execute),
+            ..
+        })) => execute.query_parameters.paging_state = Some(paging_state),
+        _ => unreachable!("only paged selects are aggregated"),
+    }
+    next.invalidate_cache();
+    next
+}
+
+/// Returns the number of rows and paging state of the response if there are more pages to fetch
+fn more_pages(response: &mut Message) -> Option<(usize, CBytes)> {
+    match response.frame() {
+        Some(Frame::Cassandra(CassandraFrame {
+            operation: CassandraOperation::Result(CassandraResult::Rows { rows, metadata }),
+            ..
+        })) => metadata
+            .paging_state
+            .clone()
+            .map(|paging_state| (rows.len(), paging_state)),
+        _ => None,
+    }
+}
+
+impl CassandraPageAggregator {
+    async fn fetch_pages(
+        &mut self,
+        request: &Message,
+        mut row_count: usize,
+        mut bytes: usize,
+        mut paging_state: CBytes,
+        local_addr: SocketAddr,
+    ) -> Result<Pages> {
+        let mut rows = vec![];
+        loop {
+            if row_count >= self.max_rows || bytes >= self.max_bytes {
+                return Ok(Pages::Complete {
+                    rows,
+                    paging_state: Some(paging_state),
+                });
+            }
+
+            let page = self
+                .chain
+                .process_request(&mut ChainState::new_with_addr(
+                    vec![next_page_request(request, paging_state)],
+                    local_addr,
+                ))
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow!("cassandra did not respond to a page request"))?;
+            bytes += page.received_size().unwrap_or(0);
+
+            match page.into_frame() {
+                Some(Frame::Cassandra(CassandraFrame {
+                    operation:
+                        CassandraOperation::Result(CassandraResult::Rows {
+                            rows: page_rows,
+                            metadata,
+                        }),
+                    ..
+                })) => {
+                    row_count += page_rows.len();
+                    rows.extend(page_rows);
+                    match metadata.paging_state {
+                        Some(next) => paging_state = next,
+                        None => {
+                            return Ok(Pages::Complete {
+                                rows,
+                                paging_state: None,
+                            })
+                        }
+                    }
+                }
+                Some(frame) => return Ok(Pages::Failed(Message::from_frame(frame))),
+                None => return Err(anyhow!("failed to parse page response")),
+            }
+        }
+    }
+
+    async fn aggregate(
+        &mut self,
+        request: &Message,
+        response: &mut Message,
+        local_addr: SocketAddr,
+    ) -> Result<()> {
+        let bytes = response.received_size().unwrap_or(0);
+        let Some((row_count, paging_state)) = more_pages(response) else {
+            return Ok(());
+        };
+
+        match self
+            .fetch_pages(request, row_count, bytes, paging_state, local_addr)
+            .await?
+        {
+            Pages::Complete {
+                rows: new_rows,
+                paging_state,
+            } => {
+                if let Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Result(CassandraResult::Rows { rows, metadata }),
+                    ..
+                })) = response.frame()
+                {
+                    rows.extend(new_rows);
+                    metadata
+                        .flags
+                        .set(RowsMetadataFlags::HAS_MORE_PAGES, paging_state.is_some());
+                    metadata.paging_state = paging_state;
+                }
+                response.invalidate_cache();
+            }
+            Pages::Failed(mut error) => {
+                if let (
+                    Some(Frame::Cassandra(error_frame)),
+                    Some(Frame::Cassandra(response_frame)),
+                ) = (error.frame(), response.frame())
+                {
+                    error_frame.stream_id = response_frame.stream_id;
+                }
+                error.invalidate_cache();
+                error.set_request_id(request.id());
+                *response = error;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraPageAggregator {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        self.chain.shutdown().await;
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let local_addr = chain_state.local_addr;
+        let mut paged_requests = MessageIdMap::default();
+        for request in chain_state.requests.iter_mut() {
+            if is_paged_select(request) {
+                paged_requests.insert(request.id(), request.clone());
+            }
+        }
+
+        let mut responses = self
+            .chain
+            .process_request(&mut ChainState::new_with_addr(
+                std::mem::take(&mut chain_state.requests),
+                local_addr,
+            ))
+            .await?;
+
+        if !paged_requests.is_empty() {
+            for response in responses.iter_mut() {
+                if let Some(request) = response
+                    .request_id()
+                    .and_then(|id| paged_requests.remove(&id))
+                {
+                    self.aggregate(&request, response, local_addr).await?;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::cassandra::{parse_statement_single, Tracing};
+    use cassandra_protocol::frame::Version;
+    use cassandra_protocol::query::QueryParams;
+    use pretty_assertions::assert_eq;
+
+    fn query(query: &str, page_size: Option<i32>) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 1,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Query {
+                query: Box::new(parse_statement_single(query)),
+                params: Box::new(QueryParams {
+                    page_size,
+                    ..Default::default()
+                }),
+            },
+        }))
+    }
+
+    #[test]
+    fn test_is_paged_select() {
+        assert!(is_paged_select(&mut query("SELECT * FROM ks.t", Some(100))));
+        assert!(!is_paged_select(&mut query("SELECT * FROM ks.t", None)));
+        assert!(!is_paged_select(&mut query(
+            "INSERT INTO ks.t (a) VALUES (1)",
+            Some(100)
+        )));
+    }
+
+    #[test]
+    fn test_next_page_request() {
+        let request = query("SELECT * FROM ks.t", Some(100));
+        let mut next = next_page_request(&request, CBytes::new(vec![1, 2, 3]));
+        assert_ne!(next.id(), request.id());
+        match next.frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Query { params, .. },
+                ..
+            })) => {
+                assert_eq!(params.page_size, Some(100));
+                assert_eq!(params.paging_state, Some(CBytes::new(vec![1, 2, 3])));
+            }
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validate_invalid_chain() {
+        let transform = CassandraPageAggregatorBuilder {
+            chain: TransformChainBuilder::new(vec![], "page_aggregator_chain"),
+            max_rows: 0,
+            max_bytes: 1024,
+        };
+
+        assert_eq!(
+            transform.validate(),
+            vec![
+                "CassandraPageAggregator:",
+                "  page_aggregator_chain chain:",
+                "    Chain cannot be empty",
+                "  max_rows must be greater than 0",
+            ]
+        );
+    }
+}
@@ -14,16 +14,23 @@ use crate::{
 };
 use anyhow::Result;
 use async_trait::async_trait;
-use cassandra_protocol::frame::events::{ServerEvent, StatusChange};
+use cassandra_protocol::frame::events::{ServerEvent, StatusChange, TopologyChange};
 use cql3_parser::cassandra_statement::CassandraStatement;
 use cql3_parser::common::{FQName, Identifier};
 use cql3_parser::select::SelectElement;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct CassandraPeersRewriteConfig {
     pub port: u16,
+    /// Maps the address of each cassandra node to the address of the shotover instance in front of it.
+    /// Addresses missing from the mapping are left as is.
+    #[serde(default)]
+    pub address_mapping: HashMap<IpAddr, IpAddr>,
 }
 
 const NAME: &str = "CassandraPeersRewrite";
@@ -34,7 +41,10 @@ impl TransformConfig for CassandraPeersRewriteConfig {
         &self,
         _transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
-        Ok(Box::new(CassandraPeersRewrite::new(self.port)))
+        Ok(Box::new(
+            CassandraPeersRewrite::new(self.port)
+                .with_address_mapping(self.address_mapping.clone()),
+        ))
     }
 
     fn up_chain_protocol(&self) -> UpChainProtocol {
@@ -50,17 +60,53 @@ impl TransformConfig for CassandraPeersRewriteConfig {
 pub struct CassandraPeersRewrite {
     port: u16,
     peer_table: FQName,
-    column_names_to_rewrite: MessageIdMap<Vec<Identifier>>,
+    address_mapping: Arc<HashMap<IpAddr, IpAddr>>,
+    /// The system tables along with their columns that contain the address of a node
+    address_columns: Arc<Vec<(FQName, Vec<Identifier>)>>,
+    column_names_to_rewrite: MessageIdMap<ColumnsToRewrite>,
+}
+
+#[derive(Clone)]
+struct ColumnsToRewrite {
+    ports: Vec<Identifier>,
+    addresses: Vec<Identifier>,
 }
 
 impl CassandraPeersRewrite {
     pub fn new(port: u16) -> Self {
+        let columns = |names: &[&str]| -> Vec<Identifier> {
+            names.iter().map(|x| Identifier::parse(x)).collect()
+        };
         CassandraPeersRewrite {
             port,
             peer_table: FQName::new("system", "peers_v2"),
+            address_mapping: Arc::new(HashMap::new()),
+            address_columns: Arc::new(vec![
+                (
+                    FQName::new("system", "peers_v2"),
+                    columns(&["peer", "native_address", "preferred_ip"]),
+                ),
+                (
+                    FQName::new("system", "peers"),
+                    columns(&["peer", "rpc_address", "preferred_ip"]),
+                ),
+                (
+                    FQName::new("system", "local"),
+                    columns(&["broadcast_address", "listen_address", "rpc_address"]),
+                ),
+            ]),
             column_names_to_rewrite: Default::default(),
         }
     }
+
+    pub fn with_address_mapping(mut self, address_mapping: HashMap<IpAddr, IpAddr>) -> Self {
+        self.address_mapping = Arc::new(address_mapping);
+        self
+    }
+
+    fn map_address(&self, address: IpAddr) -> Option<IpAddr> {
+        self.address_mapping.get(&address).copied()
+    }
 }
 
 impl TransformBuilder for CassandraPeersRewrite {
@@ -86,25 +132,40 @@ impl Transform for CassandraPeersRewrite {
         // Find the indices of queries to system.peers & system.peers_v2
         // we need to know which columns in which CQL queries in which messages have system peers
         for request in &mut chain_state.requests {
-            let sys_peers = extract_native_port_column(&self.peer_table, request);
-            self.column_names_to_rewrite.insert(request.id(), sys_peers);
+            let ports = extract_native_port_column(&self.peer_table, request);
+            let mut addresses = vec![];
+            if !self.address_mapping.is_empty() {
+                for (table, columns) in self.address_columns.iter() {
+                    addresses.extend(extract_columns(table, columns, request));
+                }
+            }
+            self.column_names_to_rewrite
+                .insert(request.id(), ColumnsToRewrite { ports, addresses });
         }
 
         let mut responses = chain_state.call_next_transform().await?;
 
         for response in &mut responses {
             if let Some(Frame::Cassandra(frame)) = response.frame() {
-                if let Event(ServerEvent::StatusChange(StatusChange { addr, .. })) =
-                    &mut frame.operation
+                if let Event(
+                    ServerEvent::StatusChange(StatusChange { addr, .. })
+                    | ServerEvent::TopologyChange(TopologyChange { addr, .. }),
+                ) = &mut frame.operation
                 {
                     addr.set_port(self.port);
+                    if let Some(new_address) = self.map_address(addr.ip()) {
+                        addr.set_ip(new_address);
+                    }
                     response.invalidate_cache();
                 }
             }
 
             if let Some(id) = response.request_id() {
-                let name_list = self.column_names_to_rewrite.remove(&id).unwrap();
-                rewrite_port(response, &name_list, self.port);
+                let columns = self.column_names_to_rewrite.remove(&id).unwrap();
+                rewrite_port(response, &columns.ports, self.port);
+                if !columns.addresses.is_empty() {
+                    rewrite_address(response, &columns.addresses, &self.address_mapping);
+                }
             }
         }
 
@@ -115,19 +176,28 @@ impl Transform for CassandraPeersRewrite {
 /// determine if the message contains a SELECT from `system.peers_v2` that includes the `native_port` column
 /// return a list of column names (or their alias) for each `native_port`.
 fn extract_native_port_column(peer_table: &FQName, message: &mut Message) -> Vec<Identifier> {
+    extract_columns(peer_table, &[Identifier::parse("native_port")], message)
+}
+
+/// determine if the message contains a SELECT from `table` that includes any of `columns`
+/// return a list of column names (or their alias) for each of the selected `columns`.
+fn extract_columns(
+    table: &FQName,
+    columns: &[Identifier],
+    message: &mut Message,
+) -> Vec<Identifier> {
     let mut result = vec![];
-    let native_port = Identifier::parse("native_port");
     if let Some(Frame::Cassandra(cassandra)) = message.frame() {
         // No need to handle Batch as selects can only occur on Query
         if let CassandraOperation::Query { query, .. } = &cassandra.operation {
             if let CassandraStatement::Select(select) = query.as_ref() {
-                if peer_table == &select.table_name {
+                if table == &select.table_name {
                     for select_element in &select.columns {
                         match select_element {
-                            SelectElement::Column(col_name) if col_name.name == native_port => {
+                            SelectElement::Column(col_name) if columns.contains(&col_name.name) => {
                                 result.push(col_name.alias_or_name().clone());
                             }
-                            SelectElement::Star => result.push(native_port.clone()),
+                            SelectElement::Star => result.extend(columns.iter().cloned()),
                             _ => {}
                         }
                     }
@@ -158,6 +228,33 @@ fn rewrite_port(message: &mut Message, column_names: &[Identifier], new_port: u1
     }
 }
 
+/// Rewrite the addresses in the given columns of the results from a query to a system table according to `address_mapping`
+fn rewrite_address(
+    message: &mut Message,
+    column_names: &[Identifier],
+    address_mapping: &HashMap<IpAddr, IpAddr>,
+) {
+    if let Some(Frame::Cassandra(frame)) = message.frame() {
+        // CassandraOperation::Error(_) is another possible case, we should silently ignore such cases
+        if let CassandraOperation::Result(CassandraResult::Rows { rows, metadata }) =
+            &mut frame.operation
+        {
+            for (i, col) in metadata.col_specs.iter().enumerate() {
+                if column_names.contains(&Identifier::parse(&col.name)) {
+                    for row in rows.iter_mut() {
+                        if let GenericValue::Inet(address) = &mut row[i] {
+                            if let Some(new_address) = address_mapping.get(address) {
+                                *address = *new_address;
+                            }
+                        }
+                    }
+                }
+            }
+            message.invalidate_cache();
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -402,4 +499,60 @@ mod test {
 
         assert_eq!(original, expected);
     }
+
+    #[test]
+    fn test_extract_address_columns() {
+        let local = FQName::new("system", "local");
+        let columns = vec![
+            Identifier::parse("broadcast_address"),
+            Identifier::parse("rpc_address"),
+        ];
+
+        assert_eq!(
+            vec![Identifier::parse("rpc_address")],
+            extract_columns(
+                &local,
+                &columns,
+                &mut create_query_message("SELECT rpc_address, host_id FROM system.local"),
+            )
+        );
+    }
+
+    #[test]
+    fn test_rewrite_address() {
+        let col_spec = vec![ColSpec {
+            table_spec: None,
+            name: "peer".into(),
+            col_type: ColTypeOption {
+                id: ColType::Inet,
+                value: None,
+            },
+        }];
+
+        let mut original = create_response_message(
+            &col_spec,
+            vec![
+                vec![GenericValue::Inet("172.16.1.2".parse().unwrap())],
+                vec![GenericValue::Inet("172.16.1.3".parse().unwrap())],
+            ],
+        );
+
+        let expected = create_response_message(
+            &col_spec,
+            vec![
+                vec![GenericValue::Inet("10.0.0.2".parse().unwrap())],
+                vec![GenericValue::Inet("172.16.1.3".parse().unwrap())],
+            ],
+        );
+
+        rewrite_address(
+            &mut original,
+            &[Identifier::parse("peer")],
+            &[("172.16.1.2".parse().unwrap(), "10.0.0.2".parse().unwrap())]
+                .into_iter()
+                .collect(),
+        );
+
+        assert_eq!(original, expected);
+    }
 }
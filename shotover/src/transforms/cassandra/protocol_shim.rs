@@ -0,0 +1,363 @@
+use crate::frame::cassandra::{CassandraResult, Tracing};
+use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use cassandra_protocol::frame::message_error::{
+    ErrorBody, ErrorType, FailureInfo, ReadFailureError, UnpreparedError, WriteFailureError,
+};
+use cassandra_protocol::frame::message_result::RowsMetadataFlags;
+use cassandra_protocol::frame::Version;
+use cassandra_protocol::types::{CBytesShort, CInt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Allows clients speaking an older protocol version to connect to a cassandra cluster through a newer protocol version.
+/// Requests are translated up to `upstream_version` and their responses are translated back down to the version of the client.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CassandraProtocolShimConfig {
+    /// The protocol version used for the connection to cassandra.
+    pub upstream_version: UpstreamVersion,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum UpstreamVersion {
+    V4,
+    V5,
+}
+
+impl From<UpstreamVersion> for Version {
+    fn from(version: UpstreamVersion) -> Self {
+        match version {
+            UpstreamVersion::V4 => Version::V4,
+            UpstreamVersion::V5 => Version::V5,
+        }
+    }
+}
+
+const NAME: &str = "CassandraProtocolShim";
+#[typetag::serde(name = "CassandraProtocolShim")]
+#[async_trait(?Send)]
+impl TransformConfig for CassandraProtocolShimConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(CassandraProtocolShimBuilder {
+            upstream_version: self.upstream_version.into(),
+            result_metadata_ids: Arc::default(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// The result metadata id of each prepared statement, keyed by the id of the prepared statement.
+/// Ids are derived from the statement and keyspace so they are the same for every connection.
+type ResultMetadataIds = Arc<Mutex<HashMap<CBytesShort, CBytesShort>>>;
+
+struct CassandraProtocolShimBuilder {
+    upstream_version: Version,
+    result_metadata_ids: ResultMetadataIds,
+}
+
+impl TransformBuilder for CassandraProtocolShimBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(CassandraProtocolShim {
+            upstream_version: self.upstream_version,
+            client_version: None,
+            result_metadata_ids: self.result_metadata_ids.clone(),
+            execute_requests: MessageIdMap::default(),
+            rejected: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct CassandraProtocolShim {
+    upstream_version: Version,
+    /// The version spoken by the client, known once the first request of the connection is received
+    client_version: Option<Version>,
+    /// Only used when the upstream version is v5, where an EXECUTE must include the result metadata id of the prepared statement
+    result_metadata_ids: ResultMetadataIds,
+    /// The prepared statement id of EXECUTE requests awaiting a response, keyed by request id.
+    /// Used to keep track of the result metadata id when cassandra reports that the result metadata changed.
+    execute_requests: MessageIdMap<CBytesShort>,
+    /// Error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
+}
+
+impl CassandraProtocolShim {
+    /// Returns the version of the client if its messages need to be translated.
+    /// Clients already speaking the upstream version or newer are passed through unchanged.
+    fn translated_version(&self) -> Option<Version> {
+        self.client_version
+            .filter(|version| u8::from(*version) < u8::from(self.upstream_version))
+    }
+
+    /// Rewrites a request of the client to the upstream version.
+    /// Returns the error to respond with instead if the request cannot be translated.
+    fn upgrade_request(&mut self, request: &mut Message) -> Option<Message> {
+        let id = request.id();
+        let Some(Frame::Cassandra(frame)) = request.frame() else {
+            return None;
+        };
+        frame.version = self.upstream_version;
+        if self.upstream_version == Version::V5 {
+            match &mut frame.operation {
+                CassandraOperation::Startup(startup) => {
+                    // Protocol v5 only supports lz4 compression, other compression is only used between the client and shotover
+                    if startup
+                        .map
+                        .get("COMPRESSION")
+                        .is_some_and(|compression| compression != "lz4")
+                    {
+                        startup.map.remove("COMPRESSION");
+                    }
+                }
+                CassandraOperation::Execute(execute) => {
+                    let result_metadata_id = self
+                        .result_metadata_ids
+                        .lock()
+                        .unwrap()
+                        .get(&execute.id)
+                        .cloned();
+                    match result_metadata_id {
+                        Some(result_metadata_id) => {
+                            execute.result_metadata_id = Some(result_metadata_id);
+                            self.execute_requests.insert(id, execute.id.clone());
+                        }
+                        None => {
+                            // Force the client to prepare the statement again so that its result metadata id is known
+                            let mut response = Message::from_frame(Frame::Cassandra(CassandraFrame {
+                                version: self.client_version.unwrap(),
+                                stream_id: frame.stream_id,
+                                operation: CassandraOperation::Error(ErrorBody {
+                                    message: "Shotover does not have the result metadata id of this statement. Please re-prepare before sending again.".into(),
+                                    ty: ErrorType::Unprepared(UnpreparedError { id: execute.id.clone() }),
+                                }),
+                                tracing: Tracing::Response(None),
+                                warnings: vec![],
+                            }));
+                            response.set_request_id(id);
+                            return Some(response);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        request.invalidate_cache();
+        None
+    }
+
+    /// Rewrites a response from cassandra to the version of the client.
+    fn downgrade_response(&mut self, response: &mut Message, client_version: Version) {
+        let execute_id = response
+            .request_id()
+            .and_then(|id| self.execute_requests.remove(&id));
+        let Some(Frame::Cassandra(frame)) = response.frame() else {
+            return;
+        };
+        frame.version = client_version;
+        if client_version == Version::V3 {
+            // Warnings were introduced in v4
+            frame.warnings.clear();
+        }
+        match &mut frame.operation {
+            CassandraOperation::Result(CassandraResult::Prepared(prepared)) => {
+                if let Some(result_metadata_id) = prepared.result_metadata_id.take() {
+                    self.result_metadata_ids
+                        .lock()
+                        .unwrap()
+                        .insert(prepared.id.clone(), result_metadata_id);
+                }
+            }
+            CassandraOperation::Result(CassandraResult::Rows { metadata, .. }) => {
+                if let Some(new_metadata_id) = metadata.new_metadata_id.take() {
+                    metadata.flags.remove(RowsMetadataFlags::METADATA_CHANGED);
+                    if let Some(execute_id) = execute_id {
+                        self.result_metadata_ids
+                            .lock()
+                            .unwrap()
+                            .insert(execute_id, new_metadata_id);
+                    }
+                }
+            }
+            CassandraOperation::Error(error) => downgrade_error(error, client_version),
+            _ => {}
+        }
+        response.invalidate_cache();
+    }
+}
+
+/// Replaces errors that do not exist in the client's version with the closest error the client understands.
+fn downgrade_error(error: &mut ErrorBody, client_version: Version) {
+    match &mut error.ty {
+        ErrorType::ReadFailure(ReadFailureError { failure_info, .. })
+        | ErrorType::WriteFailure(WriteFailureError { failure_info, .. })
+            if client_version == Version::V4 =>
+        {
+            // The reason for the failure of each replica was introduced in v5
+            if let FailureInfo::ReasonMap(reasons) = failure_info {
+                *failure_info = FailureInfo::NumFailures(reasons.len() as CInt);
+            }
+        }
+        // Failure errors were introduced in v4
+        ErrorType::ReadFailure(_) | ErrorType::WriteFailure(_) => error.ty = ErrorType::Server,
+        ErrorType::FunctionFailure(_) if client_version == Version::V3 => {
+            error.ty = ErrorType::Invalid
+        }
+        _ => {}
+    }
+}
+
+#[async_trait]
+impl Transform for CassandraProtocolShim {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        if self.client_version.is_none() {
+            if let Some(Ok(Metadata::Cassandra(metadata))) =
+                chain_state.requests.first().map(|x| x.metadata())
+            {
+                self.client_version = Some(metadata.version);
+            }
+        }
+
+        if self.translated_version().is_some() {
+            for request in chain_state.requests.iter_mut() {
+                if let Some(response) = self.upgrade_request(request) {
+                    self.rejected
+                        .insert(response.request_id().unwrap(), response);
+                    request.replace_with_dummy();
+                }
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        if let Some(client_version) = self.translated_version() {
+            for response in responses.iter_mut() {
+                if let Some(rejected) = response
+                    .request_id()
+                    .and_then(|id| self.rejected.remove(&id))
+                {
+                    *response = rejected;
+                } else {
+                    self.downgrade_response(response, client_version);
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{assert_error_response, cassandra_query, MockSink, TestChain};
+    use cassandra_protocol::frame::message_execute::BodyReqExecuteOwned;
+    use cassandra_protocol::query::QueryParams;
+    use pretty_assertions::assert_eq;
+
+    fn builder() -> CassandraProtocolShimBuilder {
+        CassandraProtocolShimBuilder {
+            upstream_version: Version::V5,
+            result_metadata_ids: Arc::default(),
+        }
+    }
+
+    fn version(message: &Message) -> Version {
+        match message.metadata() {
+            Ok(Metadata::Cassandra(metadata)) => metadata.version,
+            _ => panic!("expected a cassandra message"),
+        }
+    }
+
+    fn execute(id: &[u8]) -> Message {
+        Message::from_frame(Frame::Cassandra(CassandraFrame {
+            version: Version::V4,
+            stream_id: 0,
+            tracing: Tracing::Request(false),
+            warnings: vec![],
+            operation: CassandraOperation::Execute(Box::new(BodyReqExecuteOwned {
+                id: CBytesShort::new(id.to_vec()),
+                result_metadata_id: None,
+                query_parameters: QueryParams::default(),
+            })),
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_translate_versions() {
+        let mut chain = TestChain::from_builder(&builder(), MockSink::echo()).unwrap();
+
+        let responses = chain
+            .send(vec![cassandra_query("SELECT * FROM ks.tbl")])
+            .await
+            .unwrap();
+        let received = chain.take_received();
+        assert_eq!(version(&received[0]), Version::V5);
+        assert_eq!(version(&responses[0]), Version::V4);
+    }
+
+    #[tokio::test]
+    async fn test_execute_result_metadata_id() {
+        let builder = builder();
+        builder
+            .result_metadata_ids
+            .lock()
+            .unwrap()
+            .insert(CBytesShort::new(vec![1]), CBytesShort::new(vec![2]));
+        let mut chain = TestChain::from_builder(&builder, MockSink::echo()).unwrap();
+
+        let mut responses = chain
+            .send(vec![execute(&[1]), execute(&[3])])
+            .await
+            .unwrap();
+
+        // the result metadata id is added to statements prepared through shotover
+        let mut received = chain.take_received();
+        assert_eq!(received.len(), 1);
+        match received[0].frame() {
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Execute(execute),
+                version,
+                ..
+            })) => {
+                assert_eq!(*version, Version::V5);
+                assert_eq!(execute.result_metadata_id, Some(CBytesShort::new(vec![2])));
+            }
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+
+        // other statements must be prepared again
+        assert_error_response(
+            &mut responses[1],
+            "Shotover does not have the result metadata id of this statement. Please re-prepare before sending again.",
+        );
+        assert_eq!(version(&responses[1]), Version::V4);
+    }
+}
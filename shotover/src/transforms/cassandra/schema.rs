@@ -0,0 +1,203 @@
+//! A cache of the cassandra schema, fetched from the `system_schema` tables by the cassandra sinks.
+//!
+//! Transforms can use this to resolve the name and type of the columns a query touches,
+//! for example to map the positional bound values of a prepared statement to named, typed columns.
+//! The cache is owned by the builder of the sink that populates it and is passed to the other transforms in the chain
+//! via [`TransformContextBuilder::schema_cache`](crate::transforms::TransformContextBuilder::schema_cache).
+
+use crate::frame::value::GenericValue;
+use anyhow::{anyhow, Result};
+use cql3_parser::common::{FQName, Identifier};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColumnKind {
+    PartitionKey,
+    Clustering,
+    Regular,
+    Static,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub kind: ColumnKind,
+    /// The position of the column within the partition key or clustering key, -1 for other columns
+    pub position: i64,
+    /// The CQL type of the column as reported by cassandra e.g. `text` or `frozen<list<int>>`
+    pub cql_type: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableSchema {
+    pub columns: Vec<ColumnSchema>,
+}
+
+impl TableSchema {
+    pub fn column(&self, name: &str) -> Option<&ColumnSchema> {
+        self.columns.iter().find(|x| x.name == name)
+    }
+
+    /// Returns the partition key columns in the order they make up the partition key
+    pub fn partition_key(&self) -> Vec<&ColumnSchema> {
+        self.key_columns(ColumnKind::PartitionKey)
+    }
+
+    /// Returns the clustering columns in the order they make up the clustering key
+    pub fn clustering_key(&self) -> Vec<&ColumnSchema> {
+        self.key_columns(ColumnKind::Clustering)
+    }
+
+    fn key_columns(&self, kind: ColumnKind) -> Vec<&ColumnSchema> {
+        let mut columns: Vec<&ColumnSchema> =
+            self.columns.iter().filter(|x| x.kind == kind).collect();
+        columns.sort_by_key(|x| x.position);
+        columns
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schema {
+    /// keyed by keyspace and then by table
+    keyspaces: HashMap<String, HashMap<String, TableSchema>>,
+}
+
+impl Schema {
+    pub fn table(&self, keyspace: &str, table: &str) -> Option<&TableSchema> {
+        self.keyspaces.get(keyspace)?.get(table)
+    }
+
+    /// Returns the schema of the table named in a statement, `None` if the name does not include the keyspace.
+    pub fn table_by_name(&self, name: &FQName) -> Option<&TableSchema> {
+        self.table(
+            &stored_name(name.keyspace.as_ref()?),
+            &stored_name(&name.name),
+        )
+    }
+
+    /// Builds the schema from the rows of:
+    /// `SELECT keyspace_name, table_name, column_name, kind, position, type FROM system_schema.columns`
+    pub(crate) fn from_columns_rows(rows: Vec<Vec<GenericValue>>) -> Result<Schema> {
+        use GenericValue::{Integer, Varchar};
+
+        let mut schema = Schema::default();
+        for row in rows {
+            let row: [GenericValue; 6] = row
+                .try_into()
+                .map_err(|row| anyhow!("unexpected system_schema.columns row {row:?}"))?;
+            let (keyspace, table, name, kind, position, cql_type) = match row {
+                [Varchar(keyspace), Varchar(table), Varchar(name), Varchar(kind), Integer(position, _), Varchar(cql_type)] => {
+                    (keyspace, table, name, kind, position, cql_type)
+                }
+                row => return Err(anyhow!("unexpected system_schema.columns row {row:?}")),
+            };
+            let kind = match kind.as_str() {
+                "partition_key" => ColumnKind::PartitionKey,
+                "clustering" => ColumnKind::Clustering,
+                "static" => ColumnKind::Static,
+                _ => ColumnKind::Regular,
+            };
+            schema
+                .keyspaces
+                .entry(keyspace)
+                .or_default()
+                .entry(table)
+                .or_default()
+                .columns
+                .push(ColumnSchema {
+                    name,
+                    kind,
+                    position,
+                    cql_type,
+                });
+        }
+        Ok(schema)
+    }
+}
+
+/// Returns the name cassandra stores for the identifier, unquoted identifiers are case insensitive and stored in lowercase.
+pub fn stored_name(identifier: &Identifier) -> String {
+    match identifier {
+        Identifier::Quoted(name) => name.clone(),
+        Identifier::Unquoted(name) => name.to_lowercase(),
+    }
+}
+
+/// A handle to the most recently fetched schema of the cassandra cluster a chain is connected to.
+#[derive(Debug, Clone)]
+pub struct SchemaCache(Arc<watch::Sender<Arc<Schema>>>);
+
+impl Default for SchemaCache {
+    fn default() -> Self {
+        SchemaCache(Arc::new(watch::Sender::new(Arc::new(Schema::default()))))
+    }
+}
+
+impl SchemaCache {
+    /// Returns the most recently fetched schema
+    pub fn get(&self) -> Arc<Schema> {
+        self.0.borrow().clone()
+    }
+
+    /// Returns a receiver that is notified whenever the schema is refreshed
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Schema>> {
+        self.0.subscribe()
+    }
+
+    pub(crate) fn set(&self, schema: Schema) {
+        self.0.send_replace(Arc::new(schema));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::value::IntSize;
+    use pretty_assertions::assert_eq;
+
+    fn row(table: &str, name: &str, kind: &str, position: i64, ty: &str) -> Vec<GenericValue> {
+        vec![
+            GenericValue::Varchar("ks".to_owned()),
+            GenericValue::Varchar(table.to_owned()),
+            GenericValue::Varchar(name.to_owned()),
+            GenericValue::Varchar(kind.to_owned()),
+            GenericValue::Integer(position, IntSize::I32),
+            GenericValue::Varchar(ty.to_owned()),
+        ]
+    }
+
+    #[test]
+    fn test_schema_from_rows() {
+        let schema = Schema::from_columns_rows(vec![
+            row("t", "value", "regular", -1, "text"),
+            row("t", "b", "partition_key", 1, "int"),
+            row("t", "a", "partition_key", 0, "uuid"),
+            row("t", "c", "clustering", 0, "timestamp"),
+        ])
+        .unwrap();
+
+        let table = schema.table("ks", "t").unwrap();
+        assert_eq!(
+            table
+                .partition_key()
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_eq!(table.clustering_key()[0].name, "c");
+        assert_eq!(table.column("value").unwrap().cql_type, "text");
+        assert!(schema.table("ks", "missing").is_none());
+    }
+
+    #[test]
+    fn test_table_by_name() {
+        let schema =
+            Schema::from_columns_rows(vec![row("t", "a", "partition_key", 0, "int")]).unwrap();
+        assert!(schema.table_by_name(&FQName::parse("KS.T")).is_some());
+        assert!(schema.table_by_name(&FQName::parse("\"KS\".t")).is_none());
+        assert!(schema.table_by_name(&FQName::parse("t")).is_none());
+    }
+}
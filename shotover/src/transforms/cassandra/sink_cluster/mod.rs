@@ -1,10 +1,14 @@
 use self::connection::CassandraConnection;
 use self::node_pool::{get_accessible_owned_connection, NodePoolBuilder, PreparedMetadata};
 use self::rewrite::{BatchMode, MessageRewriter};
+use crate::codec::cassandra::CassandraCompression;
 use crate::frame::cassandra::{CassandraMetadata, Tracing};
 use crate::frame::{CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType};
+use crate::health::{HealthCheckConfig, HealthGroup, Probe};
 use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::cassandra::schema::SchemaCache;
 use crate::transforms::{
     ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
     TransformContextBuilder, TransformContextConfig, UpChainProtocol,
@@ -23,6 +27,7 @@ use node::{CassandraNode, ConnectionFactory};
 use node_pool::{GetReplicaErr, KeyspaceMetadata, NodePool};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
+use speculative::{SpeculativeExecution, SpeculativeExecutionBuilder, SpeculativeExecutionConfig};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::Duration;
@@ -36,6 +41,7 @@ pub mod node;
 mod node_pool;
 mod rewrite;
 mod routing_key;
+pub mod speculative;
 #[cfg(test)]
 mod test_router;
 mod token_ring;
@@ -62,6 +68,23 @@ pub struct CassandraSinkClusterConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    /// When set, the health of every node in the data center is checked in the background and unhealthy nodes are routed around.
+    pub health_check: Option<HealthCheckConfig>,
+    /// Requests are routed to nodes in this data center, only falling back to nodes in other data centers when no connection can be made to a local node.
+    /// Defaults to the data_center of the local shotover node.
+    pub local_datacenter: Option<String>,
+    /// Requests are routed to nodes in this rack of the local data center, falling back to the rest of the local data center when no connection can be made.
+    /// Defaults to the rack of the local shotover node.
+    pub local_rack: Option<String>,
+    /// How often the nodes of the cluster are refetched from system.local and system.peers, defaults to 60 seconds.
+    /// This picks up any topology changes that were missed by the events sent over the control connection.
+    pub topology_refresh_interval_secs: Option<u64>,
+    /// The compression negotiated with the cassandra nodes, when not provided the compression requested by the client is used.
+    pub compression: Option<CassandraCompression>,
+    /// Tuning of the TCP connections to the cassandra nodes.
+    pub tcp: Option<TcpConfig>,
+    /// When set, reads of prepared SELECT statements that are slow to be responded to are also sent to a different replica.
+    pub speculative_execution: Option<SpeculativeExecutionConfig>,
 }
 
 const NAME: &str = "CassandraSinkCluster";
@@ -85,6 +108,26 @@ impl TransformConfig for CassandraSinkClusterConfig {
                 )
             })?;
         let local_node = shotover_nodes.remove(index);
+        let topology_refresh_interval =
+            Duration::from_secs(self.topology_refresh_interval_secs.unwrap_or(60));
+        if topology_refresh_interval.is_zero() {
+            return Err(anyhow!(
+                "topology_refresh_interval_secs must be greater than 0"
+            ));
+        }
+        let local_data_center = self
+            .local_datacenter
+            .clone()
+            .unwrap_or_else(|| local_node.data_center.clone());
+        let local_rack = self
+            .local_rack
+            .clone()
+            .unwrap_or_else(|| local_node.rack.clone());
+        let speculative_execution = self
+            .speculative_execution
+            .as_ref()
+            .map(|x| SpeculativeExecutionBuilder::new(x, &transform_context.chain_name))
+            .transpose()?;
 
         Ok(Box::new(CassandraSinkClusterBuilder::new(
             self.first_contact_points.clone(),
@@ -92,8 +135,15 @@ impl TransformConfig for CassandraSinkClusterConfig {
             transform_context.chain_name,
             local_node,
             tls,
+            self.tcp.clone().unwrap_or_default(),
             self.connect_timeout_ms,
             self.read_timeout,
+            self.health_check.clone(),
+            local_data_center,
+            local_rack,
+            topology_refresh_interval,
+            self.compression,
+            speculative_execution,
         )))
     }
 
@@ -115,17 +165,27 @@ struct CassandraSinkClusterBuilder {
     keyspaces_rx: KeyspaceChanRx,
     task_handshake_tx: mpsc::Sender<TaskConnectionInfo>,
     pool: NodePoolBuilder,
+    speculative_execution: Option<SpeculativeExecutionBuilder>,
+    schema_cache: SchemaCache,
 }
 
 impl CassandraSinkClusterBuilder {
+    #[expect(clippy::too_many_arguments)]
     fn new(
         contact_points: Vec<String>,
         shotover_peers: Vec<ShotoverNode>,
         chain_name: String,
         local_shotover_node: ShotoverNode,
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
         connect_timeout_ms: u64,
         read_timeout: Option<u64>,
+        health_check: Option<HealthCheckConfig>,
+        local_data_center: String,
+        local_rack: String,
+        topology_refresh_interval: Duration,
+        compression: Option<CassandraCompression>,
+        speculative_execution: Option<SpeculativeExecutionBuilder>,
     ) -> Self {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name.clone(), "transform" => "CassandraSinkCluster");
         let read_timeout = read_timeout.map(Duration::from_secs);
@@ -136,12 +196,25 @@ impl CassandraSinkClusterBuilder {
             watch::channel(HashMap::new());
 
         let (task_handshake_tx, task_handshake_rx) = mpsc::channel(1);
-
+        let schema_cache = SchemaCache::default();
+
+        let health = health_check.map(|config| {
+            HealthGroup::new(
+                format!("{chain_name}/{NAME}"),
+                Probe::CassandraOptions,
+                config,
+                tls.clone(),
+            )
+        });
         create_topology_task(
             local_nodes_tx,
             keyspaces_tx,
+            schema_cache.clone(),
             task_handshake_rx,
-            local_shotover_node.data_center.clone(),
+            local_data_center.clone(),
+            topology_refresh_interval,
+            health,
+            format!("{chain_name}/{NAME}"),
         );
 
         let message_rewriter = MessageRewriter {
@@ -153,19 +226,31 @@ impl CassandraSinkClusterBuilder {
 
         Self {
             contact_points,
-            connection_factory: ConnectionFactory::new(connect_timeout, read_timeout, tls),
+            connection_factory: ConnectionFactory::new(
+                connect_timeout,
+                read_timeout,
+                tls,
+                tcp,
+                compression,
+            ),
             message_rewriter,
             failed_requests,
             nodes_rx: local_nodes_rx,
             keyspaces_rx,
             task_handshake_tx,
-            pool: NodePoolBuilder::new(chain_name),
+            pool: NodePoolBuilder::new(chain_name, local_data_center, local_rack),
+            speculative_execution,
+            schema_cache,
         }
     }
 }
 
 impl TransformBuilder for CassandraSinkClusterBuilder {
     fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        let speculative_execution = self
+            .speculative_execution
+            .as_ref()
+            .map(|x| x.build(transform_context.force_run_chain.clone()));
         let mut connection_factory = self.connection_factory.new_with_same_config();
         connection_factory.set_force_run_chain(transform_context.force_run_chain);
         Box::new(CassandraSinkCluster {
@@ -184,6 +269,7 @@ impl TransformBuilder for CassandraSinkClusterBuilder {
             keyspaces_rx: self.keyspaces_rx.clone(),
             rng: SmallRng::from_rng(rand::thread_rng()).unwrap(),
             task_handshake_tx: self.task_handshake_tx.clone(),
+            speculative_execution,
         })
     }
 
@@ -194,6 +280,10 @@ impl TransformBuilder for CassandraSinkClusterBuilder {
     fn is_terminating(&self) -> bool {
         true
     }
+
+    fn schema_cache(&self) -> Option<SchemaCache> {
+        Some(self.schema_cache.clone())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -229,6 +319,7 @@ struct CassandraSinkCluster {
     keyspaces_rx: KeyspaceChanRx,
     rng: SmallRng,
     task_handshake_tx: mpsc::Sender<TaskConnectionInfo>,
+    speculative_execution: Option<SpeculativeExecution>,
 }
 
 impl CassandraSinkCluster {
@@ -270,6 +361,7 @@ impl CassandraSinkCluster {
                     .any(|x| x.address == address && x.is_up)
                 {
                     let (connection, address) = self.pool.get_random_owned_connection_in_dc_rack(
+                        &self.message_rewriter.local_shotover_node.data_center,
                         &self.message_rewriter.local_shotover_node.rack,
                         &mut self.rng,
                         &self.connection_factory,
@@ -312,14 +404,14 @@ impl CassandraSinkCluster {
         // Create the initial connection.
         // Messages will be sent through this connection until we have extracted the handshake.
         if self.control_connection.is_none() {
-            let (connection, address) = if self
-                .pool
-                .nodes()
-                .iter()
-                .any(|x| x.is_up && x.rack == self.message_rewriter.local_shotover_node.rack)
-            {
+            let (connection, address) = if self.pool.nodes().iter().any(|x| {
+                x.is_up
+                    && x.data_center == self.message_rewriter.local_shotover_node.data_center
+                    && x.rack == self.message_rewriter.local_shotover_node.rack
+            }) {
                 self.pool
                     .get_random_owned_connection_in_dc_rack(
+                        &self.message_rewriter.local_shotover_node.data_center,
                         &self.message_rewriter.local_shotover_node.rack,
                         &mut self.rng,
                         &self.connection_factory,
@@ -333,6 +425,7 @@ impl CassandraSinkCluster {
                         tokio::net::lookup_host(point).await?.next().unwrap(),
                         // All of these fields use the cheapest option because get_accessible_owned_connection does not use them at all
                         String::new(),
+                        String::new(),
                         vec![],
                         Uuid::nil(),
                     ));
@@ -365,6 +458,9 @@ impl CassandraSinkCluster {
             }
         }
 
+        if self.init_handshake_complete {
+            self.send_speculative_attempts().await?;
+        }
         self.route_requests(requests, &mut responses).await?;
 
         // receive messages from all connections
@@ -409,9 +505,15 @@ impl CassandraSinkCluster {
         }
 
         self.message_rewriter.rewrite_responses(&mut responses)?;
+        if let Some(speculative_execution) = self.speculative_execution.as_mut() {
+            speculative_execution.on_responses(&mut responses);
+        }
 
         for response in responses.iter_mut() {
-            if let Some((id, metadata)) = get_prepared_result_message(response) {
+            if let Some((id, mut metadata)) = get_prepared_result_message(response) {
+                if let Some(speculative_execution) = &self.speculative_execution {
+                    metadata.is_read = speculative_execution.is_read_prepare(response);
+                }
                 self.pool.add_prepared_result(id, metadata).await;
             }
             if let Ok(Metadata::Cassandra(CassandraMetadata {
@@ -422,6 +524,9 @@ impl CassandraSinkCluster {
                 self.failed_requests.increment(1);
             }
         }
+        if let Some(speculative_execution) = self.speculative_execution.as_mut() {
+            speculative_execution.clear_read_prepares();
+        }
 
         // remove topology and status change messages since they contain references to real cluster IPs
         // TODO: we should be rewriting them not just deleting them.
@@ -486,6 +591,9 @@ impl CassandraSinkCluster {
                 //
                 // It might be worth doing in the future.
             } else if is_prepare_message(&mut message) {
+                if let Some(speculative_execution) = self.speculative_execution.as_mut() {
+                    speculative_execution.on_prepare(&mut message);
+                }
                 let next_host_id = self.message_rewriter.get_destination_for_prepare(&message);
                 match self
                     .pool
@@ -504,19 +612,22 @@ impl CassandraSinkCluster {
                 }
             } else if let Some((execute, metadata)) = get_execute_message(&mut message) {
                 // If the message is an execute we should perform token aware routing
-                let rack = &self.message_rewriter.local_shotover_node.rack;
+                let speculate = self.speculative_execution.is_some()
+                    && self.pool.is_prepared_read(&execute.id).await;
                 let connection = self
                     .pool
-                    .get_replica_connection_in_dc(
-                        execute,
-                        rack,
-                        &mut self.rng,
-                        &self.connection_factory,
-                    )
+                    .get_replica_connection_in_dc(execute, &mut self.rng, &self.connection_factory)
                     .await;
 
                 match connection {
-                    Ok(connection) => connection.send(vec![message])?,
+                    Ok((connection, host_id)) => {
+                        if let Some(speculative_execution) =
+                            self.speculative_execution.as_mut().filter(|_| speculate)
+                        {
+                            speculative_execution.track_read(&message, host_id);
+                        }
+                        connection.send(vec![message])?
+                    }
                     Err(
                         err @ GetReplicaErr::NoKeyspaceMetadata | err @ GetReplicaErr::NoRoutingKey,
                     ) => {
@@ -531,11 +642,7 @@ impl CassandraSinkCluster {
 
                         match self
                             .pool
-                            .get_random_connection_in_dc_rack(
-                                rack,
-                                &mut self.rng,
-                                &self.connection_factory,
-                            )
+                            .get_random_connection(&mut self.rng, &self.connection_factory)
                             .await
                         {
                             Ok(connection) => connection.send(vec![message])?,
@@ -575,11 +682,7 @@ impl CassandraSinkCluster {
                 // otherwise just send to a random node
                 match self
                     .pool
-                    .get_random_connection_in_dc_rack(
-                        &self.message_rewriter.local_shotover_node.rack,
-                        &mut self.rng,
-                        &self.connection_factory,
-                    )
+                    .get_random_connection(&mut self.rng, &self.connection_factory)
                     .await
                 {
                     Ok(connection) => connection.send(vec![message])?,
@@ -593,6 +696,34 @@ impl CassandraSinkCluster {
         Ok(())
     }
 
+    /// Sends a second attempt of every read that has waited too long for a response to a replica other than the one the first attempt was sent to.
+    async fn send_speculative_attempts(&mut self) -> Result<()> {
+        let Some(speculative_execution) = self.speculative_execution.as_mut() else {
+            return Ok(());
+        };
+        for (id, mut request, host_id) in speculative_execution.take_due() {
+            let Some((execute, _)) = get_execute_message(&mut request) else {
+                continue;
+            };
+            // When there is no other replica to send to, the read just waits for the first attempt
+            if let Ok(connection) = self
+                .pool
+                .get_other_replica_connection_in_dc(
+                    execute,
+                    &mut self.rng,
+                    &self.connection_factory,
+                    host_id,
+                )
+                .await
+            {
+                let speculative_id = request.id();
+                connection.send(vec![request])?;
+                speculative_execution.on_speculated(id, speculative_id);
+            }
+        }
+        Ok(())
+    }
+
     async fn complete_handshake(&mut self) -> Result<()> {
         // Only send a handshake if the task really needs it
         // i.e. when the channel of size 1 is empty
@@ -612,6 +743,7 @@ impl CassandraSinkCluster {
             // may not have been made against a node in the configured data_center/rack.
             // Therefore we need to recreate the control connection to ensure that it is in the configured data_center/rack.
             let (connection, address) = self.pool.get_random_owned_connection_in_dc_rack(
+                &self.message_rewriter.local_shotover_node.data_center,
                 &self.message_rewriter.local_shotover_node.rack,
                 &mut self.rng,
                 &self.connection_factory
@@ -677,6 +809,7 @@ fn get_prepared_result_message(message: &mut Message) -> Option<(CBytesShort, Pr
                     .global_table_spec
                     .as_ref()
                     .map(|x| x.ks_name.clone()),
+                is_read: false,
             },
         ));
     }
@@ -1,9 +1,10 @@
 use super::connection::CassandraConnection;
-use crate::codec::cassandra::CassandraCodecBuilder;
+use crate::codec::cassandra::{CassandraCodecBuilder, CassandraCompression};
 use crate::codec::{CodecBuilder, Direction};
 use crate::connection::SinkConnection;
 use crate::frame::Frame;
 use crate::message::Message;
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, ToHostname};
 use anyhow::{anyhow, Result};
 use cassandra_protocol::frame::Version;
@@ -20,6 +21,7 @@ use uuid::Uuid;
 #[derivative(Debug)]
 pub struct CassandraNode {
     pub address: SocketAddr,
+    pub data_center: String,
     pub rack: String,
     pub host_id: Uuid,
     pub is_up: bool,
@@ -34,6 +36,7 @@ impl Clone for CassandraNode {
     fn clone(&self) -> Self {
         Self {
             address: self.address,
+            data_center: self.data_center.clone(),
             rack: self.rack.clone(),
             outbound: None,
             host_id: self.host_id,
@@ -46,12 +49,14 @@ impl Clone for CassandraNode {
 impl CassandraNode {
     pub fn new(
         address: SocketAddr,
+        data_center: String,
         rack: String,
         tokens: Vec<Murmur3Token>,
         host_id: Uuid,
     ) -> Self {
         Self {
             address,
+            data_center,
             rack,
             tokens,
             host_id,
@@ -102,6 +107,7 @@ pub struct ConnectionFactory {
     use_message: Option<Message>,
     #[derivative(Debug = "ignore")]
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     #[derivative(Debug = "ignore")]
     codec_builder: CassandraCodecBuilder,
     version: Option<Version>,
@@ -116,6 +122,7 @@ impl Clone for ConnectionFactory {
             init_handshake: self.init_handshake.clone(),
             use_message: None,
             tls: self.tls.clone(),
+            tcp: self.tcp.clone(),
             force_run_chain: None,
             codec_builder: self.codec_builder.clone(),
             version: self.version,
@@ -128,6 +135,8 @@ impl ConnectionFactory {
         connect_timeout: Duration,
         read_timeout: Option<Duration>,
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
+        compression: Option<CassandraCompression>,
     ) -> Self {
         Self {
             connect_timeout,
@@ -135,11 +144,13 @@ impl ConnectionFactory {
             init_handshake: vec![],
             use_message: None,
             tls,
+            tcp,
             force_run_chain: None,
             codec_builder: CassandraCodecBuilder::new(
                 Direction::Sink,
                 "CassandraSinkCluster".to_owned(),
-            ),
+            )
+            .with_compression(compression),
             version: None,
         }
     }
@@ -152,6 +163,7 @@ impl ConnectionFactory {
             read_timeout: self.read_timeout,
             use_message: None,
             tls: self.tls.clone(),
+            tcp: self.tcp.clone(),
             force_run_chain: None,
             codec_builder: self.codec_builder.clone(),
             version: None,
@@ -166,6 +178,7 @@ impl ConnectionFactory {
             address,
             self.codec_builder.clone(),
             &self.tls,
+            &self.tcp,
             self.connect_timeout,
             self.force_run_chain.clone().unwrap(),
             self.read_timeout,
@@ -204,6 +217,7 @@ impl ConnectionFactory {
             address,
             self.codec_builder.clone(),
             &self.tls,
+            &self.tcp,
             self.connect_timeout,
             self.force_run_chain.clone().unwrap(),
             self.read_timeout,
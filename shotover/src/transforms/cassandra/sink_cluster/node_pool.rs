@@ -11,11 +11,14 @@ use rand::prelude::*;
 use std::sync::Arc;
 use std::{collections::HashMap, net::SocketAddr};
 use tokio::sync::{watch, RwLock};
+use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct PreparedMetadata {
     pub pk_indexes: Vec<i16>,
     pub keyspace: Option<String>,
+    /// The prepared statement is a SELECT, only known when speculative execution is enabled.
+    pub is_read: bool,
 }
 
 #[derive(Debug)]
@@ -41,17 +44,72 @@ pub struct KeyspaceMetadata {
     pub replication_strategy: ReplicationStrategy,
 }
 
+/// Where a node is relative to the data center and rack that requests are preferably routed to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locality {
+    LocalRack,
+    LocalDataCenter,
+    RemoteDataCenter,
+}
+
+/// The data center and rack that requests are preferably routed to,
+/// along with counters of the [`Locality`] of the nodes that requests were actually routed to.
+pub struct LocalRouting {
+    data_center: String,
+    rack: String,
+    local_rack_requests: Counter,
+    local_data_center_requests: Counter,
+    remote_data_center_requests: Counter,
+}
+
+impl LocalRouting {
+    fn new(chain_name: &str, data_center: String, rack: String) -> Self {
+        let counter = |locality: &'static str| counter!("shotover_cassandra_routed_requests_count", "chain" => chain_name.to_owned(), "locality" => locality);
+        LocalRouting {
+            data_center,
+            rack,
+            local_rack_requests: counter("local_rack"),
+            local_data_center_requests: counter("local_datacenter"),
+            remote_data_center_requests: counter("remote_datacenter"),
+        }
+    }
+
+    fn locality(&self, node: &CassandraNode) -> Locality {
+        if node.data_center != self.data_center {
+            Locality::RemoteDataCenter
+        } else if node.rack != self.rack {
+            Locality::LocalDataCenter
+        } else {
+            Locality::LocalRack
+        }
+    }
+
+    fn record_routed_request(&self, node: &CassandraNode) {
+        match self.locality(node) {
+            Locality::LocalRack => self.local_rack_requests.increment(1),
+            Locality::LocalDataCenter => self.local_data_center_requests.increment(1),
+            Locality::RemoteDataCenter => self.remote_data_center_requests.increment(1),
+        }
+    }
+}
+
 // Values in the builder are shared between transform instances that come from the same transform in the topology.yaml
 #[derive(Clone)]
 pub struct NodePoolBuilder {
     prepared_metadata: Arc<RwLock<HashMap<CBytesShort, Arc<PreparedMetadata>>>>,
     out_of_rack_requests: Counter,
+    local: Arc<LocalRouting>,
 }
 
 impl NodePoolBuilder {
-    pub fn new(chain_name: String) -> Self {
+    pub fn new(chain_name: String, local_data_center: String, local_rack: String) -> Self {
         Self {
             prepared_metadata: Arc::new(RwLock::new(HashMap::new())),
+            local: Arc::new(LocalRouting::new(
+                &chain_name,
+                local_data_center,
+                local_rack,
+            )),
             out_of_rack_requests: counter!("shotover_out_of_rack_requests_count", "chain" => chain_name, "transform" => "CassandraSinkCluster"),
         }
     }
@@ -63,6 +121,7 @@ impl NodePoolBuilder {
             token_map: TokenRing::new(&[]),
             nodes: vec![],
             out_of_rack_requests: self.out_of_rack_requests.clone(),
+            local: self.local.clone(),
         }
     }
 }
@@ -70,9 +129,12 @@ impl NodePoolBuilder {
 pub struct NodePool {
     prepared_metadata: Arc<RwLock<HashMap<CBytesShort, Arc<PreparedMetadata>>>>,
     keyspace_metadata: HashMap<String, KeyspaceMetadata>,
+    /// Only contains the nodes of the local data center, as the replication factors in keyspace_metadata are those of the local data center.
     token_map: TokenRing,
+    /// The nodes of every data center
     nodes: Vec<CassandraNode>,
     out_of_rack_requests: Counter,
+    local: Arc<LocalRouting>,
 }
 
 impl NodePool {
@@ -99,7 +161,13 @@ impl NodePool {
             }
         }
         self.nodes = new_nodes;
-        self.token_map = TokenRing::new(self.nodes.as_slice());
+        let local_nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|node| node.data_center == self.local.data_center)
+            .cloned()
+            .collect();
+        self.token_map = TokenRing::new(&local_nodes);
         tracing::debug!(
             "nodes updated, nodes={:#?}\ntokens={:#?}",
             self.nodes,
@@ -117,8 +185,14 @@ impl NodePool {
         write_lock.insert(id, Arc::new(metadata));
     }
 
+    pub async fn is_prepared_read(&self, id: &CBytesShort) -> bool {
+        let read_lock = self.prepared_metadata.read().await;
+        read_lock.get(id).is_some_and(|x| x.is_read)
+    }
+
     pub async fn get_random_node_in_dc_rack(
         &mut self,
+        data_center: &str,
         rack: &str,
         rng: &mut SmallRng,
         connection_factory: &ConnectionFactory,
@@ -126,38 +200,47 @@ impl NodePool {
         let mut nodes: Vec<_> = self
             .nodes
             .iter_mut()
-            .filter(|node| node.is_up && node.rack == *rack)
+            .filter(|node| node.is_up && node.data_center == data_center && node.rack == *rack)
             .collect();
         nodes.shuffle(rng);
         get_accessible_node(connection_factory, nodes)
             .await
             .with_context(|| {
-                format!("Failed to open a connection to any nodes in the rack {rack:?}")
+                format!("Failed to open a connection to any nodes in the rack {rack:?} of data center {data_center:?}")
             })
     }
 
-    pub async fn get_random_connection_in_dc_rack(
+    /// Get a random node, preferring nodes in the local rack, then the local data center.
+    /// Nodes in remote data centers are only used when no connection can be made to any node in the local data center.
+    pub async fn get_random_connection(
         &mut self,
-        rack: &str,
         rng: &mut SmallRng,
         connection_factory: &ConnectionFactory,
     ) -> Result<&mut CassandraConnection> {
-        self.get_random_node_in_dc_rack(rack, rng, connection_factory)
+        let local = &self.local;
+        let mut nodes: Vec<_> = self.nodes.iter_mut().filter(|node| node.is_up).collect();
+        nodes.shuffle(rng);
+        // stable sort so nodes of the same locality remain shuffled
+        nodes.sort_by_key(|node| local.locality(node) as u8);
+
+        let node = get_accessible_node(connection_factory, nodes)
             .await
-            .map(|x| {
-                x.outbound
-                    .as_mut()
-                    .expect("it is set to Some by get_random_node_in_dc_rack")
-            })
+            .context("Failed to open a connection to any node")?;
+        local.record_routed_request(node);
+        Ok(node
+            .outbound
+            .as_mut()
+            .expect("it is set to Some by get_accessible_node"))
     }
 
     pub async fn get_random_owned_connection_in_dc_rack(
         &mut self,
+        data_center: &str,
         rack: &str,
         rng: &mut SmallRng,
         connection_factory: &ConnectionFactory,
     ) -> Result<(CassandraConnection, SocketAddr)> {
-        self.get_random_node_in_dc_rack(rack, rng, connection_factory)
+        self.get_random_node_in_dc_rack(data_center, rack, rng, connection_factory)
             .await
             .map(|x| {
                 (
@@ -169,13 +252,12 @@ impl NodePool {
             })
     }
 
-    /// Get a token routed replica node for the supplied execute message (if exists)
-    /// Will attempt to get a replica in the supplied rack if exists, otherwise get one in
-    /// the same data center
+    /// Get the token routed replica nodes for the supplied execute message (if exists) in order of preference.
+    /// Replicas in the local rack come first, followed by the other replicas in the local data center.
+    /// Finally the nodes of remote data centers are included, to fall back to as coordinators if no local replica can be connected to.
     pub async fn get_replica_node_in_dc(
         &mut self,
         execute: &BodyReqExecuteOwned,
-        rack: &str,
         rng: &mut SmallRng,
     ) -> Result<Vec<&mut CassandraNode>, GetReplicaErr> {
         let metadata = {
@@ -209,18 +291,25 @@ impl NodePool {
             .iter_replica_nodes(self.nodes(), routing_key, keyspace)
             .collect::<Vec<uuid::Uuid>>();
 
-        let mut nodes: Vec<&mut CassandraNode> = self
-            .nodes
-            .iter_mut()
-            .filter(|node| replica_host_ids.contains(&node.host_id) && node.is_up)
-            .collect();
+        let rack = &self.local.rack;
+        let local_data_center = &self.local.data_center;
+        let (mut nodes, mut remote_nodes): (Vec<&mut CassandraNode>, Vec<&mut CassandraNode>) =
+            self.nodes
+                .iter_mut()
+                .filter(|node| node.is_up)
+                .filter(|node| {
+                    node.data_center != *local_data_center
+                        || replica_host_ids.contains(&node.host_id)
+                })
+                .partition(|node| node.data_center == *local_data_center);
         nodes.shuffle(rng);
+        remote_nodes.shuffle(rng);
 
         // Move all nodes that are in the rack to the front of the list.
         // This way they will be preferred over all other nodes
         let mut nodes_found_in_rack = 0;
         for i in 0..nodes.len() {
-            if nodes[i].rack == rack {
+            if nodes[i].rack == *rack {
                 nodes.swap(i, nodes_found_in_rack);
                 nodes_found_in_rack += 1;
             }
@@ -240,27 +329,56 @@ impl NodePool {
             "Shotover with designated rack {rack:?} found replica nodes {replica_host_ids:?}"
         );
 
+        nodes.extend(remote_nodes);
         Ok(nodes)
     }
 
     pub async fn get_replica_connection_in_dc(
         &mut self,
         execute: &BodyReqExecuteOwned,
-        rack: &str,
         rng: &mut SmallRng,
         connection_factory: &ConnectionFactory,
-    ) -> Result<&mut CassandraConnection, GetReplicaErr> {
-        let nodes = self.get_replica_node_in_dc(execute, rack, rng).await?;
+    ) -> Result<(&mut CassandraConnection, Uuid), GetReplicaErr> {
+        let local = self.local.clone();
+        let nodes = self.get_replica_node_in_dc(execute, rng).await?;
 
-        get_accessible_node(connection_factory, nodes)
+        let node = get_accessible_node(connection_factory, nodes)
             .await
             .context("Failed to open a connection to any replicas of a specific token")
-            .map_err(GetReplicaErr::NoNodeAvailable)
-            .map(|x| {
-                x.outbound
-                    .as_mut()
-                    .expect("it is set to Some by get_accessible_node")
-            })
+            .map_err(GetReplicaErr::NoNodeAvailable)?;
+        local.record_routed_request(node);
+        Ok((
+            node.outbound
+                .as_mut()
+                .expect("it is set to Some by get_accessible_node"),
+            node.host_id,
+        ))
+    }
+
+    /// Get a connection to a replica in the local data center for the supplied execute message, other than the node `excluded_host_id`.
+    /// Used to send a second attempt of a request, so nodes of remote data centers are never returned.
+    pub async fn get_other_replica_connection_in_dc(
+        &mut self,
+        execute: &BodyReqExecuteOwned,
+        rng: &mut SmallRng,
+        connection_factory: &ConnectionFactory,
+        excluded_host_id: Uuid,
+    ) -> Result<&mut CassandraConnection, GetReplicaErr> {
+        let local = self.local.clone();
+        let mut nodes = self.get_replica_node_in_dc(execute, rng).await?;
+        nodes.retain(|node| {
+            node.data_center == local.data_center && node.host_id != excluded_host_id
+        });
+
+        let node = get_accessible_node(connection_factory, nodes)
+            .await
+            .context("Failed to open a connection to any other replicas of a specific token")
+            .map_err(GetReplicaErr::NoNodeAvailable)?;
+        local.record_routed_request(node);
+        Ok(node
+            .outbound
+            .as_mut()
+            .expect("it is set to Some by get_accessible_node"))
     }
 }
 
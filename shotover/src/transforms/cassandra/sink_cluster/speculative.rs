@@ -0,0 +1,347 @@
+//! Speculative execution of idempotent reads.
+//! When a read has not been responded to within a percentile of recent read latencies,
+//! a second attempt is sent to a different replica and whichever response arrives first is returned to the client.
+
+use crate::frame::Frame;
+use crate::message::{Message, MessageId, MessageIdMap, MessageIdSet};
+use anyhow::{anyhow, Result};
+use cql3_parser::cassandra_statement::CassandraStatement;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpeculativeExecutionConfig {
+    /// A second attempt is sent once a read has waited longer than this percentile of recent read latencies, e.g. 99.0
+    pub percentile: f64,
+    /// The second attempt is never sent sooner than this many milliseconds after the first.
+    /// Also used as the delay until enough reads have completed to calculate the percentile.
+    pub min_delay_ms: u64,
+}
+
+/// The number of most recent read latencies that the percentile is calculated from.
+const LATENCY_SAMPLES: usize = 1000;
+
+/// The number of reads that must complete before the percentile is calculated, and how often it is recalculated.
+const RECALCULATE_INTERVAL: usize = 100;
+
+/// Reads and discarded attempts that have not been responded to for this long are forgotten, they may never receive a response.
+const PENDING_EXPIRY: Duration = Duration::from_secs(60);
+
+/// The number of pending reads above which expired reads are forgotten.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+pub(crate) struct SpeculativeExecutionBuilder {
+    percentile: f64,
+    min_delay: Duration,
+    attempts: Counter,
+    wins: Counter,
+}
+
+impl SpeculativeExecutionBuilder {
+    pub(crate) fn new(config: &SpeculativeExecutionConfig, chain_name: &str) -> Result<Self> {
+        if !(config.percentile > 0.0 && config.percentile <= 100.0) {
+            return Err(anyhow!(
+                "speculative_execution: percentile must be greater than 0 and at most 100"
+            ));
+        }
+        Ok(SpeculativeExecutionBuilder {
+            percentile: config.percentile,
+            min_delay: Duration::from_millis(config.min_delay_ms),
+            attempts: counter!("shotover_speculative_attempts_count", "chain" => chain_name.to_owned(), "transform" => "CassandraSinkCluster"),
+            wins: counter!("shotover_speculative_wins_count", "chain" => chain_name.to_owned(), "transform" => "CassandraSinkCluster"),
+        })
+    }
+
+    pub(crate) fn build(&self, force_run_chain: Arc<Notify>) -> SpeculativeExecution {
+        SpeculativeExecution {
+            percentile: self.percentile,
+            min_delay: self.min_delay,
+            latencies: VecDeque::with_capacity(LATENCY_SAMPLES),
+            samples_since_calculation: 0,
+            percentile_latency: None,
+            in_flight: MessageIdMap::default(),
+            speculative_ids: MessageIdMap::default(),
+            discard: MessageIdMap::default(),
+            read_prepares: MessageIdSet::default(),
+            wakeup_at: None,
+            force_run_chain,
+            attempts: self.attempts.clone(),
+            wins: self.wins.clone(),
+        }
+    }
+}
+
+struct InFlight {
+    /// A copy of the request to send as the second attempt, None once the second attempt is sent.
+    request: Option<Message>,
+    sent_at: Instant,
+    /// The node that the first attempt was sent to
+    host_id: Uuid,
+    speculative_id: Option<MessageId>,
+}
+
+/// The speculative execution state of a single CassandraSinkCluster instance.
+pub(crate) struct SpeculativeExecution {
+    percentile: f64,
+    min_delay: Duration,
+    latencies: VecDeque<Duration>,
+    samples_since_calculation: usize,
+    percentile_latency: Option<Duration>,
+    /// The reads that have not been responded to yet
+    in_flight: MessageIdMap<InFlight>,
+    /// Maps the id of a second attempt to the id of the read it was copied from
+    speculative_ids: MessageIdMap<MessageId>,
+    /// The ids of the attempts that lost the race, their responses are discarded when they arrive
+    discard: MessageIdMap<Instant>,
+    /// The ids of the PREPARE requests of the current batch that prepare a SELECT
+    read_prepares: MessageIdSet,
+    wakeup_at: Option<Instant>,
+    force_run_chain: Arc<Notify>,
+    attempts: Counter,
+    wins: Counter,
+}
+
+impl SpeculativeExecution {
+    /// Records the PREPARE request if it prepares a SELECT, so that executions of the resulting prepared statement are speculated.
+    pub(crate) fn on_prepare(&mut self, request: &mut Message) {
+        if let Some(Frame::Cassandra(frame)) = request.frame() {
+            if let Some(CassandraStatement::Select(_)) = frame.prepared_statement() {
+                self.read_prepares.insert(request.id());
+            }
+        }
+    }
+
+    /// Returns true if the response is to a PREPARE of a SELECT.
+    pub(crate) fn is_read_prepare(&self, response: &Message) -> bool {
+        response
+            .request_id()
+            .is_some_and(|id| self.read_prepares.contains(&id))
+    }
+
+    /// PREPARE requests are always sent in an isolated batch, so all of their responses have been processed by the end of the batch.
+    pub(crate) fn clear_read_prepares(&mut self) {
+        self.read_prepares.clear();
+    }
+
+    /// Records a read that was sent to the node `host_id`, it will be sent to another replica if it is not responded to in time.
+    pub(crate) fn track_read(&mut self, request: &Message, host_id: Uuid) {
+        let now = Instant::now();
+        if self.in_flight.len() > PRUNE_THRESHOLD {
+            self.in_flight
+                .retain(|_, in_flight| now.duration_since(in_flight.sent_at) < PENDING_EXPIRY);
+            let in_flight = &self.in_flight;
+            self.speculative_ids
+                .retain(|_, id| in_flight.contains_key(id));
+        }
+        if self.discard.len() > PRUNE_THRESHOLD {
+            self.discard
+                .retain(|_, discarded_at| now.duration_since(*discarded_at) < PENDING_EXPIRY);
+        }
+
+        self.in_flight.insert(
+            request.id(),
+            InFlight {
+                request: Some(request.clone()),
+                sent_at: now,
+                host_id,
+                speculative_id: None,
+            },
+        );
+        self.schedule_wakeup(now + self.delay());
+    }
+
+    /// Returns a second attempt of every read that has waited longer than the delay, along with the id of the read and the node that it was sent to.
+    pub(crate) fn take_due(&mut self) -> Vec<(MessageId, Message, Uuid)> {
+        let now = Instant::now();
+        let delay = self.delay();
+        let mut due = vec![];
+        let mut next_wakeup: Option<Instant> = None;
+        for (id, in_flight) in &mut self.in_flight {
+            if in_flight.request.is_none() {
+                continue;
+            }
+            let deadline = in_flight.sent_at + delay;
+            if deadline <= now {
+                let request = in_flight.request.take().unwrap();
+                due.push((*id, request.clone_with_new_id(), in_flight.host_id));
+            } else {
+                next_wakeup = Some(next_wakeup.map_or(deadline, |x| x.min(deadline)));
+            }
+        }
+        if let Some(next_wakeup) = next_wakeup {
+            self.schedule_wakeup(next_wakeup);
+        }
+        due
+    }
+
+    /// Records that the second attempt of the read `id` was sent with the id `speculative_id`.
+    pub(crate) fn on_speculated(&mut self, id: MessageId, speculative_id: MessageId) {
+        if let Some(in_flight) = self.in_flight.get_mut(&id) {
+            in_flight.speculative_id = Some(speculative_id);
+            self.speculative_ids.insert(speculative_id, id);
+            self.attempts.increment(1);
+        }
+    }
+
+    /// Keeps the first response to each read, discarding the response to the attempt that lost.
+    pub(crate) fn on_responses(&mut self, responses: &mut Vec<Message>) {
+        let now = Instant::now();
+        responses.retain_mut(|response| {
+            let Some(request_id) = response.request_id() else {
+                return true;
+            };
+            if self.discard.remove(&request_id).is_some() {
+                return false;
+            }
+
+            let (id, speculative) = match self.speculative_ids.remove(&request_id) {
+                Some(id) => (id, true),
+                None => (request_id, false),
+            };
+            let Some(in_flight) = self.in_flight.remove(&id) else {
+                return true;
+            };
+            if speculative {
+                response.set_request_id(id);
+                self.wins.increment(1);
+                self.discard.insert(id, now);
+            } else if let Some(speculative_id) = in_flight.speculative_id {
+                self.speculative_ids.remove(&speculative_id);
+                self.discard.insert(speculative_id, now);
+            }
+
+            let received_at = response.received_from_source_or_sink_at.unwrap_or(now);
+            self.record_latency(received_at.saturating_duration_since(in_flight.sent_at));
+            true
+        });
+    }
+
+    fn record_latency(&mut self, latency: Duration) {
+        if self.latencies.len() == LATENCY_SAMPLES {
+            self.latencies.pop_front();
+        }
+        self.latencies.push_back(latency);
+
+        self.samples_since_calculation += 1;
+        if self.samples_since_calculation >= RECALCULATE_INTERVAL {
+            self.samples_since_calculation = 0;
+            let mut sorted: Vec<Duration> = self.latencies.iter().copied().collect();
+            sorted.sort_unstable();
+            let index = ((self.percentile / 100.0 * sorted.len() as f64).ceil() as usize)
+                .clamp(1, sorted.len())
+                - 1;
+            self.percentile_latency = Some(sorted[index]);
+        }
+    }
+
+    /// How long a read waits for a response before its second attempt is sent.
+    fn delay(&self) -> Duration {
+        self.percentile_latency
+            .map_or(self.min_delay, |x| x.max(self.min_delay))
+    }
+
+    /// Ensures the chain is run at `at` so that reads that are due at that time are speculated even if no other requests or responses arrive.
+    fn schedule_wakeup(&mut self, at: Instant) {
+        let now = Instant::now();
+        if let Some(wakeup_at) = self.wakeup_at {
+            if wakeup_at > now && wakeup_at <= at {
+                return;
+            }
+        }
+        self.wakeup_at = Some(at);
+        let force_run_chain = self.force_run_chain.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at.into()).await;
+            force_run_chain.notify_one();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn speculative_execution(min_delay_ms: u64) -> SpeculativeExecution {
+        SpeculativeExecutionBuilder::new(
+            &SpeculativeExecutionConfig {
+                percentile: 99.0,
+                min_delay_ms,
+            },
+            "foo",
+        )
+        .unwrap()
+        .build(Arc::new(Notify::new()))
+    }
+
+    fn response_to(id: MessageId) -> Message {
+        let mut response = Message::from_frame(Frame::Dummy);
+        response.set_request_id(id);
+        response
+    }
+
+    #[tokio::test]
+    async fn test_first_response_wins() {
+        let mut speculative = speculative_execution(0);
+        let host_id = Uuid::new_v4();
+        let won_by_first = Message::from_frame(Frame::Dummy);
+        let won_by_second = Message::from_frame(Frame::Dummy);
+        speculative.track_read(&won_by_first, host_id);
+        speculative.track_read(&won_by_second, host_id);
+
+        let mut due = speculative.take_due();
+        due.sort_by_key(|(id, _, _)| *id != won_by_first.id());
+        assert_eq!(due.len(), 2);
+        for (id, request, due_host_id) in &due {
+            assert_ne!(*id, request.id());
+            assert_eq!(*due_host_id, host_id);
+            speculative.on_speculated(*id, request.id());
+        }
+        // a read is only speculated once
+        assert!(speculative.take_due().is_empty());
+
+        let first_speculative_id = due[0].1.id();
+        let second_speculative_id = due[1].1.id();
+        let mut responses = vec![
+            response_to(won_by_first.id()),
+            response_to(second_speculative_id),
+        ];
+        speculative.on_responses(&mut responses);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].request_id(), Some(won_by_first.id()));
+        assert_eq!(responses[1].request_id(), Some(won_by_second.id()));
+
+        // the responses to the attempts that lost are discarded
+        let mut responses = vec![
+            response_to(first_speculative_id),
+            response_to(won_by_second.id()),
+        ];
+        speculative.on_responses(&mut responses);
+        assert!(responses.is_empty());
+        assert!(speculative.in_flight.is_empty());
+        assert!(speculative.speculative_ids.is_empty());
+        assert!(speculative.discard.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delay() {
+        let mut speculative = speculative_execution(5);
+        assert_eq!(speculative.delay(), Duration::from_millis(5));
+
+        for i in 1..=RECALCULATE_INTERVAL as u64 {
+            speculative.record_latency(Duration::from_millis(i));
+        }
+        assert_eq!(speculative.delay(), Duration::from_millis(99));
+
+        for _ in 0..LATENCY_SAMPLES {
+            speculative.record_latency(Duration::from_millis(1));
+        }
+        assert_eq!(speculative.delay(), Duration::from_millis(5));
+    }
+}
@@ -26,7 +26,8 @@ mod test_token_aware_router {
         let mut rng = SmallRng::from_rng(rand::thread_rng()).unwrap();
 
         let nodes = prepare_nodes();
-        let mut router = NodePoolBuilder::new("chain".to_owned()).build();
+        let mut router =
+            NodePoolBuilder::new("chain".to_owned(), "dc1".to_owned(), "rack1".to_owned()).build();
         let (_nodes_tx, mut nodes_rx) = watch::channel(nodes);
         router.update_nodes(&mut nodes_rx);
 
@@ -77,16 +78,18 @@ mod test_token_aware_router {
 
             assert_eq!(token, test_token);
 
-            let node = router
-                .get_replica_node_in_dc(
-                    &execute_body(id.clone(), query_parameters),
-                    "rack1",
-                    &mut rng,
-                )
+            let nodes = router
+                .get_replica_node_in_dc(&execute_body(id.clone(), query_parameters), &mut rng)
                 .await
-                .unwrap()
-                .remove(0);
+                .unwrap();
 
+            // nodes in other data centers are only used as a last resort
+            assert_eq!(
+                nodes.last().unwrap().address,
+                "172.16.2.1:9042".parse().unwrap()
+            );
+
+            let node = &nodes[0];
             if !rack_replicas.is_empty() {
                 assert!(rack_replicas.contains(&node.address));
             } else {
@@ -149,6 +152,7 @@ mod test_token_aware_router {
         PreparedMetadata {
             pk_indexes: vec![0],
             keyspace: Some("demo_ks".into()),
+            is_read: false,
         }
     }
 
@@ -158,58 +162,74 @@ mod test_token_aware_router {
         vec![
             CassandraNode::new(
                 "172.16.1.10:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack3".into(),
                 tokens.get("172.16.1.10").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.3:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 tokens.get("172.16.1.3").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.8:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack3".into(),
                 tokens.get("172.16.1.8").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.6:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack2".into(),
                 tokens.get("172.16.1.6").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.5:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack2".into(),
                 tokens.get("172.16.1.5").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.2:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 tokens.get("172.16.1.2").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.9:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack3".into(),
                 tokens.get("172.16.1.9").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.4:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 tokens.get("172.16.1.4").unwrap().clone(),
                 Uuid::new_v4(),
             ),
             CassandraNode::new(
                 "172.16.1.7:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack2".into(),
                 tokens.get("172.16.1.7").unwrap().clone(),
                 Uuid::new_v4(),
             ),
+            CassandraNode::new(
+                "172.16.2.1:9042".parse().unwrap(),
+                "dc2".into(),
+                "rack1".into(),
+                vec![],
+                Uuid::new_v4(),
+            ),
         ]
     }
 
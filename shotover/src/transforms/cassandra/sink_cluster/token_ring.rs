@@ -94,6 +94,7 @@ mod test_token_map {
         vec![
             CassandraNode::new(
                 "127.0.0.1:9042".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 vec![
                     Murmur3Token::new(-2),
@@ -104,12 +105,14 @@ mod test_token_map {
             ),
             CassandraNode::new(
                 "127.0.0.1:9043".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 vec![Murmur3Token::new(20)],
                 NODE_2,
             ),
             CassandraNode::new(
                 "127.0.0.1:9044".parse().unwrap(),
+                "dc1".into(),
                 "rack1".into(),
                 vec![
                     Murmur3Token::new(2),
@@ -149,18 +152,21 @@ mod test_token_map {
                 &[
                     CassandraNode::new(
                         "127.0.0.1:9042".parse().unwrap(),
+                        "dc1".to_owned(),
                         "rack1".to_owned(),
                         vec![],
                         NODE_1,
                     ),
                     CassandraNode::new(
                         "127.0.0.2:9042".parse().unwrap(),
+                        "dc1".to_owned(),
                         "rack1".to_owned(),
                         vec![],
                         NODE_2,
                     ),
                     CassandraNode::new(
                         "127.0.0.3:9042".parse().unwrap(),
+                        "dc1".to_owned(),
                         "rack1".to_owned(),
                         vec![],
                         NODE_3,
@@ -2,12 +2,15 @@ use super::node::{CassandraNode, ConnectionFactory};
 use super::node_pool::KeyspaceMetadata;
 use super::KeyspaceChanTx;
 use crate::connection::SinkConnection;
+use crate::events::{self, EventKind};
 use crate::frame::{
     cassandra::{parse_statement_single, Tracing},
     value::GenericValue,
     CassandraFrame, CassandraOperation, CassandraResult, Frame,
 };
+use crate::health::HealthGroup;
 use crate::message::Message;
+use crate::transforms::cassandra::schema::{Schema, SchemaCache};
 use anyhow::{anyhow, Result};
 use cassandra_protocol::events::{ServerEvent, SimpleServerEvent};
 use cassandra_protocol::frame::events::{StatusChangeType, TopologyChangeType};
@@ -17,7 +20,9 @@ use cassandra_protocol::token::Murmur3Token;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, watch, Notify};
+use tokio::time::{Instant, MissedTickBehavior};
 
 #[derive(Debug)]
 pub struct TaskConnectionInfo {
@@ -25,45 +30,92 @@ pub struct TaskConnectionInfo {
     pub address: SocketAddr,
 }
 
+#[expect(clippy::too_many_arguments)]
 pub fn create_topology_task(
     nodes_tx: watch::Sender<Vec<CassandraNode>>,
     keyspaces_tx: KeyspaceChanTx,
+    schema_cache: SchemaCache,
     mut connection_info_rx: mpsc::Receiver<TaskConnectionInfo>,
     data_center: String,
+    refresh_interval: Duration,
+    health: Option<Arc<HealthGroup>>,
+    event_source: String,
 ) {
     tokio::spawn(async move {
         while let Some(mut connection_info) = connection_info_rx.recv().await {
             let mut attempts = 0;
-            match topology_task_process(
-                &nodes_tx,
-                &keyspaces_tx,
-                &mut connection_info,
-                &data_center,
-            )
-            .await
-            {
-                Err(err) => {
-                    tracing::error!("topology task failed, retrying, error was: {err:?}");
-                    attempts += 1;
-                    if attempts > 3 {
-                        // 3 attempts have failed, lets try a new handshake
-                        break;
+            loop {
+                match topology_task_process(
+                    &nodes_tx,
+                    &keyspaces_tx,
+                    &schema_cache,
+                    &mut connection_info,
+                    &data_center,
+                    refresh_interval,
+                    health.as_ref(),
+                )
+                .await
+                {
+                    Err(err) => {
+                        tracing::error!("topology task failed, retrying, error was: {err:?}");
+                        attempts += 1;
+                        if attempts > 3 {
+                            // 3 attempts have failed, lets wait for a new handshake
+                            break;
+                        }
+
+                        // The node the control connection was made against may have been restarted or removed,
+                        // so retry against another node that is still up.
+                        if let Some(address) = next_control_node(
+                            &nodes_tx.borrow(),
+                            connection_info.address,
+                            &data_center,
+                        ) {
+                            events::publish(
+                                EventKind::Failover,
+                                event_source.clone(),
+                                format!(
+                                    "the control connection to {} failed, reconnecting to {address}",
+                                    connection_info.address
+                                ),
+                            );
+                            connection_info.address = address;
+                        }
+                    }
+                    Ok(()) => {
+                        // cleanly shutdown the task
+                        return;
                     }
-                }
-                Ok(()) => {
-                    // cleanly shutdown the task
-                    return;
                 }
             }
         }
     });
 }
 
+/// Picks a node other than `previous` to make the control connection against, preferring nodes in `data_center`.
+fn next_control_node(
+    nodes: &[CassandraNode],
+    previous: SocketAddr,
+    data_center: &str,
+) -> Option<SocketAddr> {
+    let candidates = nodes
+        .iter()
+        .filter(|node| node.is_up && node.address != previous);
+    candidates
+        .clone()
+        .find(|node| node.data_center == data_center)
+        .or_else(|| candidates.clone().next())
+        .map(|node| node.address)
+}
+
 async fn topology_task_process(
     nodes_tx: &watch::Sender<Vec<CassandraNode>>,
     keyspaces_tx: &KeyspaceChanTx,
+    schema_cache: &SchemaCache,
     connection_info: &mut TaskConnectionInfo,
     data_center: &str,
+    refresh_interval: Duration,
+    health: Option<&Arc<HealthGroup>>,
 ) -> Result<()> {
     let force_run_chain = Arc::new(Notify::new());
     connection_info
@@ -77,9 +129,8 @@ async fn topology_task_process(
         .await?
         .into_sink_connection();
 
-    let mut nodes =
-        fetch_current_nodes(&mut connection, connection_info, data_center, version).await?;
-    if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
+    let mut nodes = fetch_current_nodes(&mut connection, connection_info, version).await?;
+    if let Err(watch::error::SendError(_)) = send_nodes(nodes_tx, &nodes, health) {
         return Ok(());
     }
 
@@ -90,14 +141,23 @@ async fn topology_task_process(
         return Ok(());
     }
 
+    let mut events = vec![];
+    let mut schema_fetched =
+        refresh_schema(&mut connection, version, schema_cache, &mut events).await;
+
     register_for_topology_and_status_events(&mut connection, version).await?;
+    let mut health_rx = health.map(|x| x.subscribe());
+
+    // Events can be missed, e.g. while the control connection is being reestablished or when a node changes address,
+    // so the nodes are also periodically refetched to ensure any such changes are eventually picked up.
+    let mut refresh = tokio::time::interval_at(Instant::now() + refresh_interval, refresh_interval);
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
     tracing::info!(
         "Topology task control connection finalized against node at: {:?}",
         connection_info.address
     );
 
-    let mut events = vec![];
     loop {
         if events.is_empty() {
             // Wait for events to come in from the cassandra node.
@@ -107,7 +167,26 @@ async fn topology_task_process(
                     Ok(()) => {}
                     Err(err) => return Err(anyhow!(err).context("topology control connection was closed")),
                 },
-                _ = nodes_tx.closed() => return Ok(())
+                _ = nodes_tx.closed() => return Ok(()),
+                _ = refresh.tick() => {
+                    if !schema_fetched {
+                        schema_fetched =
+                            refresh_schema(&mut connection, version, schema_cache, &mut events).await;
+                    }
+                    let new_nodes = refresh_nodes(&mut connection, connection_info, version, &nodes).await?;
+                    if !same_nodes(&nodes, &new_nodes) {
+                        tracing::info!("Topology refresh found changes to the cassandra nodes");
+                        nodes = new_nodes;
+                        if let Err(watch::error::SendError(_)) = send_nodes(nodes_tx, &nodes, health) {
+                            return Ok(());
+                        }
+                    }
+                }
+                _ = health_changed(&mut health_rx) => {
+                    if let Err(watch::error::SendError(_)) = send_nodes(nodes_tx, &nodes, health) {
+                        return Ok(());
+                    }
+                }
             };
         }
         for mut event in std::mem::take(&mut events) {
@@ -118,42 +197,38 @@ async fn topology_task_process(
             {
                 match event {
                     ServerEvent::TopologyChange(topology) => match topology.change_type {
+                        // cassandra-protocol does not decode MOVED_NODE events, moved tokens are picked up by the periodic refresh instead
                         TopologyChangeType::NewNode => {
-                            let mut new_nodes = fetch_current_nodes(
-                                &mut connection,
-                                connection_info,
-                                data_center,
-                                version,
-                            )
-                            .await?;
-
-                            // is_up state gets carried over to new list
-                            for node in &nodes {
-                                if !node.is_up {
-                                    for new_node in &mut new_nodes {
-                                        if new_node.address == node.address {
-                                            new_node.is_up = false;
-                                        }
-                                    }
-                                }
-                            }
-
-                            nodes = new_nodes;
+                            nodes =
+                                refresh_nodes(&mut connection, connection_info, version, &nodes)
+                                    .await?;
 
-                            if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
+                            if let Err(watch::error::SendError(_)) =
+                                send_nodes(nodes_tx, &nodes, health)
+                            {
                                 return Ok(());
                             }
                         }
                         TopologyChangeType::RemovedNode => {
                             nodes.retain(|node| node.address != topology.addr);
 
-                            if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
+                            if let Err(watch::error::SendError(_)) =
+                                send_nodes(nodes_tx, &nodes, health)
+                            {
                                 return Ok(());
                             }
                         }
                         _ => unreachable!(),
                     },
                     ServerEvent::StatusChange(status) => {
+                        if matches!(status.change_type, StatusChangeType::Up)
+                            && !nodes.iter().any(|node| node.address == status.addr)
+                        {
+                            // A node we do not know about has come up, e.g. a node that restarted with a new address
+                            nodes =
+                                refresh_nodes(&mut connection, connection_info, version, &nodes)
+                                    .await?;
+                        }
                         for node in &mut nodes {
                             if node.address == status.addr {
                                 node.is_up = match status.change_type {
@@ -163,7 +238,9 @@ async fn topology_task_process(
                                 }
                             }
                         }
-                        if let Err(watch::error::SendError(_)) = nodes_tx.send(nodes.clone()) {
+                        if let Err(watch::error::SendError(_)) =
+                            send_nodes(nodes_tx, &nodes, health)
+                        {
                             return Ok(());
                         }
                     }
@@ -174,6 +251,10 @@ async fn topology_task_process(
                         if let Err(watch::error::SendError(_)) = keyspaces_tx.send(keyspaces) {
                             return Ok(());
                         }
+
+                        schema_fetched =
+                            refresh_schema(&mut connection, version, schema_cache, &mut events)
+                                .await;
                     }
                     _ => unreachable!(),
                 }
@@ -182,6 +263,59 @@ async fn topology_task_process(
     }
 }
 
+/// Fetches the schema into the schema cache, returning false if it could not be fetched.
+/// The schema is only used to improve the behavior of other transforms so a failure is logged
+/// and retried on the next topology refresh instead of tearing down the control connection.
+async fn refresh_schema(
+    connection: &mut SinkConnection,
+    version: Version,
+    schema_cache: &SchemaCache,
+    events: &mut Vec<Message>,
+) -> bool {
+    match system_schema_columns::query(connection, version).await {
+        Ok((schema, extra_events)) => {
+            events.extend(extra_events);
+            schema_cache.set(schema);
+            true
+        }
+        Err(err) => {
+            tracing::warn!(
+                "Failed to fetch the schema from system_schema.columns, retrying on the next topology refresh: {err:?}"
+            );
+            false
+        }
+    }
+}
+
+/// Sends the nodes to every transform instance, with any nodes that are failing their health checks marked as down.
+fn send_nodes(
+    nodes_tx: &watch::Sender<Vec<CassandraNode>>,
+    nodes: &[CassandraNode],
+    health: Option<&Arc<HealthGroup>>,
+) -> Result<(), watch::error::SendError<Vec<CassandraNode>>> {
+    let mut nodes = nodes.to_vec();
+    if let Some(health) = health {
+        let addresses: Vec<String> = nodes.iter().map(|x| x.address.to_string()).collect();
+        health.set_nodes(addresses.iter().map(|x| x.as_str()));
+        for (node, address) in nodes.iter_mut().zip(&addresses) {
+            if !health.is_healthy(address) {
+                node.is_up = false;
+            }
+        }
+    }
+    nodes_tx.send(nodes)
+}
+
+/// Completes when the health of any node changes, never completes when health checks are not configured.
+async fn health_changed(health_rx: &mut Option<watch::Receiver<()>>) {
+    if let Some(health_rx) = health_rx {
+        if health_rx.changed().await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
 async fn register_for_topology_and_status_events(
     connection: &mut SinkConnection,
     version: Version,
@@ -215,15 +349,50 @@ async fn register_for_topology_and_status_events(
     }
 }
 
+/// Fetches the current nodes, carrying over which nodes are down from the previous list of nodes.
+async fn refresh_nodes(
+    connection: &mut SinkConnection,
+    connection_info: &TaskConnectionInfo,
+    version: Version,
+    previous_nodes: &[CassandraNode],
+) -> Result<Vec<CassandraNode>> {
+    let mut new_nodes = fetch_current_nodes(connection, connection_info, version).await?;
+    carry_over_is_up(previous_nodes, &mut new_nodes);
+    Ok(new_nodes)
+}
+
+fn same_nodes(a: &[CassandraNode], b: &[CassandraNode]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| {
+            a.address == b.address
+                && a.host_id == b.host_id
+                && a.data_center == b.data_center
+                && a.rack == b.rack
+                && a.is_up == b.is_up
+                && a.tokens == b.tokens
+        })
+}
+
+fn carry_over_is_up(previous_nodes: &[CassandraNode], new_nodes: &mut [CassandraNode]) {
+    for node in previous_nodes {
+        if !node.is_up {
+            for new_node in new_nodes.iter_mut() {
+                if new_node.address == node.address {
+                    new_node.is_up = false;
+                }
+            }
+        }
+    }
+}
+
+/// Fetches the nodes of every data center, so that remote data centers can be fallen back to
 async fn fetch_current_nodes(
     connection: &mut SinkConnection,
     connection_info: &TaskConnectionInfo,
-    data_center: &str,
     version: Version,
 ) -> Result<Vec<CassandraNode>> {
-    let mut new_nodes =
-        system_local::query(connection, data_center, connection_info.address, version).await?;
-    let more_nodes = system_peers::query(connection, data_center, version).await?;
+    let mut new_nodes = system_local::query(connection, connection_info.address, version).await?;
+    let more_nodes = system_peers::query(connection, version).await?;
 
     new_nodes.extend(more_nodes);
 
@@ -387,12 +556,52 @@ async fn send_recv(
     Ok((result.unwrap(), extra_events))
 }
 
+mod system_schema_columns {
+    use super::*;
+
+    pub async fn query(
+        connection: &mut SinkConnection,
+        version: Version,
+    ) -> Result<(Schema, Vec<Message>)> {
+        let (mut response, extra_events) = super::send_recv(
+            connection,
+            Message::from_frame(Frame::Cassandra(CassandraFrame {
+                version,
+                stream_id: 0,
+                tracing: Tracing::Request(false),
+                warnings: vec![],
+                operation: CassandraOperation::Query {
+                    query: Box::new(parse_statement_single(
+                        "SELECT keyspace_name, table_name, column_name, kind, position, type FROM system_schema.columns",
+                    )),
+                    params: Box::default(),
+                },
+            })),
+        )
+        .await?;
+
+        match response.frame() {
+            Some(Frame::Cassandra(frame)) => match &mut frame.operation {
+                CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => {
+                    Schema::from_columns_rows(std::mem::take(rows)).map(|x| (x, extra_events))
+                }
+                operation => Err(anyhow!(
+                    "system_schema.columns query returned unexpected cassandra operation: {:?}",
+                    operation
+                )),
+            },
+            _ => Err(anyhow!(
+                "Failed to parse system_schema.columns query response"
+            )),
+        }
+    }
+}
+
 mod system_local {
     use super::*;
 
     pub async fn query(
         connection: &mut SinkConnection,
-        data_center: &str,
         address: SocketAddr,
         version: Version,
     ) -> Result<Vec<CassandraNode>> {
@@ -414,27 +623,20 @@ mod system_local {
         .await?
         .0;
 
-        into_nodes(response, data_center, address)
+        into_nodes(response, address)
     }
 
-    fn into_nodes(
-        mut response: Message,
-        config_data_center: &str,
-        address: SocketAddr,
-    ) -> Result<Vec<CassandraNode>> {
+    fn into_nodes(mut response: Message, address: SocketAddr) -> Result<Vec<CassandraNode>> {
         if let Some(Frame::Cassandra(frame)) = response.frame() {
             match &mut frame.operation {
                 CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => rows
                     .iter_mut()
-                    .filter(|row| {
-                        if let Some(GenericValue::Varchar(data_center)) = row.last() {
-                            data_center == config_data_center
-                        } else {
-                            false
-                        }
-                    })
                     .map(|row| {
-                        let _data_center = row.pop();
+                        let data_center = if let Some(GenericValue::Varchar(value)) = row.pop() {
+                            value
+                        } else {
+                            return Err(anyhow!("system.local.data_center not a varchar"));
+                        };
 
                         let host_id = if let Some(GenericValue::Uuid(host_id)) = row.pop() {
                             host_id
@@ -459,7 +661,13 @@ mod system_local {
                             return Err(anyhow!("system.local.rack not a varchar"));
                         };
 
-                        Ok(CassandraNode::new(address, rack, tokens, host_id))
+                        Ok(CassandraNode::new(
+                            address,
+                            data_center,
+                            rack,
+                            tokens,
+                            host_id,
+                        ))
                     })
                     .collect(),
                 operation => Err(anyhow!(
@@ -481,7 +689,6 @@ mod system_peers {
 
     pub async fn query(
         connection: &mut SinkConnection,
-        data_center: &str,
         version: Version,
     ) -> Result<Vec<CassandraNode>> {
         let mut response = super::send_recv(connection,
@@ -519,7 +726,7 @@ mod system_peers {
             .0;
         }
 
-        into_nodes(response, data_center)
+        into_nodes(response)
     }
 
     fn is_peers_v2_does_not_exist_error(message: &mut Message) -> bool {
@@ -534,24 +741,21 @@ mod system_peers {
         false
     }
 
-    fn into_nodes(mut response: Message, config_data_center: &str) -> Result<Vec<CassandraNode>> {
+    fn into_nodes(mut response: Message) -> Result<Vec<CassandraNode>> {
         if let Some(Frame::Cassandra(frame)) = response.frame() {
             match &mut frame.operation {
                 CassandraOperation::Result(CassandraResult::Rows { rows, .. }) => rows
                     .iter_mut()
-                    .filter(|row| {
-                        if let Some(GenericValue::Varchar(data_center)) = row.last() {
-                            data_center == config_data_center
-                        } else {
-                            false
-                        }
-                    })
                     .map(|row| {
                         if row.len() != 5 && row.len() != 6 {
                             return Err(anyhow!("expected 5 or 6 columns but was {}", row.len()));
                         }
 
-                        let _data_center = row.pop();
+                        let data_center = if let Some(GenericValue::Varchar(value)) = row.pop() {
+                            value
+                        } else {
+                            return Err(anyhow!("system.peers(v2).data_center not a varchar"));
+                        };
 
                         let host_id = if let Some(GenericValue::Uuid(host_id)) = row.pop() {
                             host_id
@@ -597,6 +801,7 @@ mod system_peers {
 
                         Ok(CassandraNode::new(
                             SocketAddr::new(ip, port.try_into()?),
+                            data_center,
                             rack,
                             tokens,
                             host_id,
@@ -693,3 +898,64 @@ mod test_system_keyspaces {
         )
     }
 }
+
+#[cfg(test)]
+mod test_refresh {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use uuid::Uuid;
+
+    fn node(address: &str, data_center: &str, is_up: bool) -> CassandraNode {
+        let mut node = CassandraNode::new(
+            address.parse().unwrap(),
+            data_center.into(),
+            "rack1".into(),
+            vec![],
+            Uuid::nil(),
+        );
+        node.is_up = is_up;
+        node
+    }
+
+    #[test]
+    fn test_carry_over_is_up() {
+        let previous = vec![
+            node("127.0.0.1:9042", "dc1", false),
+            node("127.0.0.2:9042", "dc1", true),
+        ];
+        let mut new = vec![
+            node("127.0.0.1:9042", "dc1", true),
+            node("127.0.0.2:9042", "dc1", true),
+            node("127.0.0.3:9042", "dc1", true),
+        ];
+        carry_over_is_up(&previous, &mut new);
+        assert_eq!(
+            new.iter().map(|x| x.is_up).collect::<Vec<_>>(),
+            vec![false, true, true]
+        );
+        assert!(!same_nodes(&previous, &new));
+        assert!(same_nodes(&new, &new.clone()));
+    }
+
+    #[test]
+    fn test_next_control_node() {
+        let nodes = vec![
+            node("127.0.0.1:9042", "dc1", true),
+            node("127.0.0.2:9042", "dc2", true),
+            node("127.0.0.3:9042", "dc1", false),
+            node("127.0.0.4:9042", "dc1", true),
+        ];
+        assert_eq!(
+            next_control_node(&nodes, "127.0.0.1:9042".parse().unwrap(), "dc1"),
+            Some("127.0.0.4:9042".parse().unwrap())
+        );
+        assert_eq!(
+            next_control_node(&nodes, "127.0.0.1:9042".parse().unwrap(), "dc2"),
+            Some("127.0.0.2:9042".parse().unwrap())
+        );
+        assert_eq!(
+            next_control_node(&nodes[..1], "127.0.0.1:9042".parse().unwrap(), "dc1"),
+            None
+        );
+    }
+}
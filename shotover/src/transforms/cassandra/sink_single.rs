@@ -1,14 +1,17 @@
-use crate::codec::{cassandra::CassandraCodecBuilder, CodecBuilder, Direction};
+use crate::codec::cassandra::{CassandraCodecBuilder, CassandraCompression};
+use crate::codec::{CodecBuilder, Direction};
 use crate::connection::SinkConnection;
 use crate::frame::cassandra::CassandraMetadata;
 use crate::frame::MessageType;
-use crate::message::{Messages, Metadata};
+use crate::health::{HealthCheckConfig, HealthGroup, Probe};
+use crate::message::{Messages, Metadata, StreamChunk};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
 use crate::transforms::{
     ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
     TransformContextBuilder, TransformContextConfig, UpChainProtocol,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
 use cassandra_protocol::frame::{Opcode, Version};
 use metrics::{counter, Counter};
@@ -27,6 +30,16 @@ pub struct CassandraSinkSingleConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
+    /// When set, the health of the cassandra node is checked in the background.
+    pub health_check: Option<HealthCheckConfig>,
+    /// The compression negotiated with cassandra, when not provided the compression requested by the client is used.
+    pub compression: Option<CassandraCompression>,
+    /// When set, results larger than this many bytes are forwarded to the client in chunks of this size as they are received,
+    /// instead of once the whole result has been received.
+    /// Only takes effect when every transform before this one in the chain accepts streamed responses.
+    pub response_chunk_size: Option<usize>,
+    /// Tuning of the TCP connections to the cassandra node.
+    pub tcp: Option<TcpConfig>,
 }
 
 const NAME: &str = "CassandraSinkSingle";
@@ -38,12 +51,26 @@ impl TransformConfig for CassandraSinkSingleConfig {
         transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
         let tls = self.tls.as_ref().map(TlsConnector::new).transpose()?;
+        let health = self.health_check.as_ref().map(|config| {
+            let group = HealthGroup::new(
+                format!("{}/{NAME}", transform_context.chain_name),
+                Probe::CassandraOptions,
+                config.clone(),
+                tls.clone(),
+            );
+            group.register(&self.address);
+            group
+        });
         Ok(Box::new(CassandraSinkSingleBuilder::new(
             self.address.clone(),
             transform_context.chain_name,
             tls,
+            self.tcp.clone().unwrap_or_default(),
             self.connect_timeout_ms,
             self.read_timeout,
+            health,
+            self.compression,
+            self.response_chunk_size,
         )))
     }
 
@@ -61,32 +88,44 @@ struct CassandraSinkSingleBuilder {
     address: String,
     failed_requests: Counter,
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
     codec_builder: CassandraCodecBuilder,
+    health: Option<Arc<HealthGroup>>,
+    response_chunk_size: Option<usize>,
 }
 
 impl CassandraSinkSingleBuilder {
+    #[expect(clippy::too_many_arguments)]
     fn new(
         address: String,
         chain_name: String,
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
         connect_timeout_ms: u64,
         timeout: Option<u64>,
+        health: Option<Arc<HealthGroup>>,
+        compression: Option<CassandraCompression>,
+        response_chunk_size: Option<usize>,
     ) -> CassandraSinkSingleBuilder {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => "CassandraSinkSingle");
         let receive_timeout = timeout.map(Duration::from_secs);
         let codec_builder =
-            CassandraCodecBuilder::new(Direction::Sink, "CassandraSinkSingle".to_owned());
+            CassandraCodecBuilder::new(Direction::Sink, "CassandraSinkSingle".to_owned())
+                .with_compression(compression);
 
         CassandraSinkSingleBuilder {
             version: None,
             address,
             failed_requests,
             tls,
+            tcp,
             connect_timeout: Duration::from_millis(connect_timeout_ms),
             read_timeout: receive_timeout,
             codec_builder,
+            health,
+            response_chunk_size,
         }
     }
 }
@@ -98,11 +137,16 @@ impl TransformBuilder for CassandraSinkSingleBuilder {
             version: self.version,
             address: self.address.clone(),
             tls: self.tls.clone(),
+            tcp: self.tcp.clone(),
             failed_requests: self.failed_requests.clone(),
             connect_timeout: self.connect_timeout,
             read_timeout: self.read_timeout,
-            codec_builder: self.codec_builder.clone(),
+            codec_builder: self.codec_builder.clone().with_response_chunk_size(
+                self.response_chunk_size
+                    .filter(|_| transform_context.stream_responses),
+            ),
             force_run_chain: transform_context.force_run_chain,
+            health: self.health.clone(),
         })
     }
 
@@ -121,10 +165,12 @@ struct CassandraSinkSingle {
     connection: Option<SinkConnection>,
     failed_requests: Counter,
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
     codec_builder: CassandraCodecBuilder,
     force_run_chain: Arc<Notify>,
+    health: Option<Arc<HealthGroup>>,
 }
 
 impl CassandraSinkSingle {
@@ -149,12 +195,19 @@ impl CassandraSinkSingle {
         }
 
         if self.connection.is_none() {
+            if let Some(health) = &self.health {
+                // fail fast rather than waiting for the connection attempt to time out
+                if !health.is_healthy(&self.address) {
+                    bail!("cassandra node {} failed its health checks", self.address);
+                }
+            }
             trace!("creating outbound connection {:?}", self.address);
             self.connection = Some(
                 SinkConnection::new(
                     self.address.clone(),
                     self.codec_builder.clone(),
                     &self.tls,
+                    &self.tcp,
                     self.connect_timeout,
                     self.force_run_chain.clone(),
                     self.read_timeout,
@@ -189,6 +242,16 @@ impl CassandraSinkSingle {
                         responses_count += 1;
                     }
                 }
+                // Each chunk of a streamed result is returned as soon as it is received.
+                // The remaining responses are picked up by the chain runs triggered as they are received.
+                if responses[responses_len_old..].iter().any(|x| {
+                    matches!(
+                        x.stream_chunk(),
+                        Some(StreamChunk::Start | StreamChunk::Continuation)
+                    )
+                }) {
+                    break;
+                }
             }
         };
 
@@ -5,9 +5,10 @@ use anyhow::{anyhow, Result};
 use futures::TryFutureExt;
 use metrics::{counter, histogram, Counter, Histogram};
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{Duration, Instant};
-use tracing::{debug, error, info, trace, Instrument};
+use tracing::{debug, error, info, trace, warn, Instrument};
 
 type InnerChain = Vec<TransformAndMetrics>;
 
@@ -164,6 +165,7 @@ impl TransformChain {
         chain_state: &'shorter mut ChainState<'longer>,
     ) -> Result<Messages> {
         let start = Instant::now();
+        self.rebuild_timed_out_transforms();
         chain_state.reset(&mut self.chain);
 
         if !chain_state.requests.is_empty() {
@@ -186,6 +188,51 @@ impl TransformChain {
         self.chain_latency_seconds.record(start.elapsed());
         result
     }
+
+    /// A transform cancelled by its timeout may have been partway through sending requests or receiving responses,
+    /// as were the transforms after it that it was waiting on.
+    /// So before the chain is run again they are all replaced with newly built transforms, dropping any connections they held,
+    /// to ensure that the responses to the cancelled requests are never returned for later requests.
+    /// This is required for sub chains whose errors do not reach the chain error policy of the source.
+    fn rebuild_timed_out_transforms(&mut self) {
+        if let Some(i) = self.chain.iter().position(|x| x.timed_out) {
+            warn!(
+                "Rebuilding transform {} and the transforms after it in chain {} as it was cancelled by its timeout",
+                self.chain[i].transform.get_name(),
+                self.name
+            );
+            for transform in &mut self.chain[i..] {
+                transform.rebuild();
+            }
+        }
+    }
+
+    /// Flushes the chain and then calls [`Transform::on_shutdown`] on each of its transforms in order, logging any errors.
+    /// If the flush fails the transforms are no longer in a usable state, so [`Transform::on_shutdown`] is not called.
+    pub async fn shutdown(&mut self) {
+        if let Err(e) = self.process_request(&mut ChainState::flush()).await {
+            error!(
+                "{:?}",
+                e.context(format!(
+                    "encountered an error when flushing the chain {} for shutdown",
+                    self.name,
+                ))
+            );
+            return;
+        }
+        for transform in &mut self.chain {
+            if let Err(e) = transform.transform.on_shutdown().await {
+                error!(
+                    "{:?}",
+                    e.context(format!(
+                        "transform {} of chain {} encountered an error when shutting down",
+                        transform.transform.get_name(),
+                        self.name,
+                    ))
+                );
+            }
+        }
+    }
 }
 
 pub struct TransformAndMetrics {
@@ -193,34 +240,55 @@ pub struct TransformAndMetrics {
     pub transform_total: Counter,
     pub transform_failures: Counter,
     pub transform_latency: Histogram,
+    /// The transform fails if it, including the rest of the chain it calls into, takes longer than this.
+    pub timeout: Option<Duration>,
+    /// Set when the transform was cancelled by its timeout.
+    pub(crate) timed_out: bool,
+    /// Used to replace the transform after it, or a transform before it, was cancelled by its timeout.
+    /// Only present when the chain contains a timeout.
+    rebuild: Option<(Arc<dyn TransformBuilder>, TransformContextBuilder)>,
 }
 
 impl TransformAndMetrics {
-    #[cfg(test)]
+    /// Wraps the transform with metrics that are never reported, for use in tests.
     pub fn new(transform: Box<dyn Transform>) -> Self {
         TransformAndMetrics {
             transform,
             transform_total: Counter::noop(),
             transform_failures: Counter::noop(),
             transform_latency: Histogram::noop(),
+            timeout: None,
+            timed_out: false,
+            rebuild: None,
+        }
+    }
+
+    fn rebuild(&mut self) {
+        if let Some((builder, context)) = &self.rebuild {
+            self.transform = builder.build(context.clone());
         }
+        self.timed_out = false;
     }
 }
 
 pub struct TransformBuilderAndMetrics {
-    pub builder: Box<dyn TransformBuilder>,
+    pub builder: Arc<dyn TransformBuilder>,
     transform_total: Counter,
     transform_failures: Counter,
     transform_latency: Histogram,
+    timeout: Option<Duration>,
 }
 
 impl TransformBuilderAndMetrics {
-    fn build(&self, context: TransformContextBuilder) -> TransformAndMetrics {
+    fn build(&self, context: TransformContextBuilder, rebuildable: bool) -> TransformAndMetrics {
         TransformAndMetrics {
-            transform: self.builder.build(context),
+            transform: self.builder.build(context.clone()),
             transform_total: self.transform_total.clone(),
             transform_failures: self.transform_failures.clone(),
             transform_latency: self.transform_latency.clone(),
+            timeout: self.timeout,
+            timed_out: false,
+            rebuild: rebuildable.then(|| (self.builder.clone(), context)),
         }
     }
 }
@@ -237,12 +305,24 @@ pub struct TransformChainBuilder {
 
 impl TransformChainBuilder {
     pub fn new(chain: Vec<Box<dyn TransformBuilder>>, name: &'static str) -> Self {
-        let chain = chain.into_iter().map(|builder|
+        Self::new_with_timeouts(
+            chain.into_iter().map(|builder| (builder, None)).collect(),
+            name,
+        )
+    }
+
+    /// Creates a chain where each transform may have a timeout that the chain enforces around every call to it.
+    pub fn new_with_timeouts(
+        chain: Vec<(Box<dyn TransformBuilder>, Option<Duration>)>,
+        name: &'static str,
+    ) -> Self {
+        let chain = chain.into_iter().map(|(builder, timeout)|
             TransformBuilderAndMetrics {
                 transform_total: counter!("shotover_transform_total_count", "transform" => builder.get_name()),
                 transform_failures: counter!("shotover_transform_failures_count", "transform" => builder.get_name()),
                 transform_latency: histogram!("shotover_transform_latency_seconds", "transform" => builder.get_name()),
-                builder,
+                builder: Arc::from(builder),
+                timeout,
             }
         ).collect();
 
@@ -295,6 +375,13 @@ impl TransformChainBuilder {
                     ));
                 }
 
+                if transform.timeout == Some(Duration::ZERO) {
+                    errors.push(format!(
+                        "  The timeout of transform {:?} must be greater than 0.",
+                        transform.builder.get_name()
+                    ));
+                }
+
                 errors.extend(transform.builder.validate().iter().map(|x| format!("  {x}")));
 
                 errors
@@ -358,16 +445,8 @@ impl TransformChainBuilder {
 
                 debug!("buffered chain processing thread exiting, stopping chain loop and dropping");
 
-                match chain
-                    .process_request(&mut ChainState::flush())
-                    .await
-                {
-                    Ok(_) => info!("Buffered chain {} was shutdown", chain.name),
-                    Err(e) => error!(
-                        "Buffered chain {} encountered an error when flushing the chain for shutdown: {}",
-                        chain.name, e
-                    ),
-                }
+                chain.shutdown().await;
+                info!("Buffered chain {} was shutdown", chain.name);
             }
             .in_current_span(),
         );
@@ -381,10 +460,22 @@ impl TransformChainBuilder {
 
     /// Build the chain
     pub fn build(&self, context: TransformContextBuilder) -> TransformChain {
+        let mut transform_context = context.clone();
+        #[cfg(feature = "cassandra")]
+        {
+            transform_context.schema_cache =
+                self.chain.iter().find_map(|x| x.builder.schema_cache());
+        }
+        let rebuildable = self.chain.iter().any(|x| x.timeout.is_some());
         let chain = self
             .chain
             .iter()
-            .map(|x| x.build(context.clone()))
+            .map(|x| {
+                let transform = x.build(transform_context.clone(), rebuildable);
+                // responses can only be streamed through the transforms after this one if this transform accepts them too
+                transform_context.stream_responses &= x.builder.accepts_streamed_responses();
+                transform
+            })
             .collect();
 
         TransformChain {
@@ -405,10 +496,18 @@ impl TransformChainBuilder {
 
 #[cfg(test)]
 mod chain_tests {
-    use crate::transforms::chain::TransformChainBuilder;
+    use crate::frame::Frame;
+    use crate::message::{Message, Messages};
+    use crate::test_utils::TestChain;
+    use crate::transforms::chain::{TransformAndMetrics, TransformChainBuilder};
     use crate::transforms::debug::printer::DebugPrinter;
     use crate::transforms::null::NullSink;
+    use crate::transforms::{ChainState, Transform, TransformBuilder, TransformContextBuilder};
+    use anyhow::{bail, Result};
+    use async_trait::async_trait;
     use pretty_assertions::assert_eq;
+    use std::sync::{Arc, Mutex};
+    use tokio::time::Duration;
 
     #[tokio::test]
     async fn test_validate_invalid_chain() {
@@ -431,4 +530,232 @@ mod chain_tests {
         );
         assert_eq!(chain.validate(), Vec::<String>::new());
     }
+
+    #[tokio::test]
+    async fn test_validate_zero_timeout() {
+        let chain = TransformChainBuilder::new_with_timeouts(
+            vec![
+                (Box::<DebugPrinter>::default(), Some(Duration::ZERO)),
+                (Box::<NullSink>::default(), None),
+            ],
+            "test-chain",
+        );
+        assert_eq!(
+            chain.validate(),
+            vec![
+                "test-chain chain:",
+                "  The timeout of transform \"DebugPrinter\" must be greater than 0."
+            ]
+        );
+    }
+
+    struct Stall;
+
+    #[async_trait]
+    impl Transform for Stall {
+        fn get_name(&self) -> &'static str {
+            "Stall"
+        }
+
+        async fn transform<'shorter, 'longer: 'shorter>(
+            &mut self,
+            chain_state: &'shorter mut ChainState<'longer>,
+        ) -> Result<Messages> {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            chain_state.call_next_transform().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transform_timeout() {
+        let mut chain = vec![TransformAndMetrics {
+            timeout: Some(Duration::from_millis(10)),
+            ..TransformAndMetrics::new(Box::new(Stall))
+        }];
+        let mut chain_state = ChainState::new_test(vec![]);
+        chain_state.reset(&mut chain);
+
+        let err = chain_state.call_next_transform().await.unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Stall transform failed: timed out after 10ms"
+        );
+    }
+
+    /// Counts how many times its transform is built, the transform stalls instead of calling the next transform when `stall` is set
+    struct CountBuilds {
+        builds: Arc<Mutex<usize>>,
+        stall: bool,
+    }
+
+    impl TransformBuilder for CountBuilds {
+        fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+            *self.builds.lock().unwrap() += 1;
+            if self.stall {
+                Box::new(Stall)
+            } else {
+                Box::<DebugPrinter>::default()
+            }
+        }
+
+        fn get_name(&self) -> &'static str {
+            "CountBuilds"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transform_timeout_rebuilds_cancelled_transforms() {
+        let builds: Vec<_> = (0..3).map(|_| Arc::new(Mutex::new(0))).collect();
+        let mut chain = TransformChainBuilder::new_with_timeouts(
+            vec![
+                (
+                    Box::new(CountBuilds {
+                        builds: builds[0].clone(),
+                        stall: false,
+                    }),
+                    None,
+                ),
+                (
+                    Box::new(CountBuilds {
+                        builds: builds[1].clone(),
+                        stall: false,
+                    }),
+                    Some(Duration::from_millis(10)),
+                ),
+                (
+                    Box::new(CountBuilds {
+                        builds: builds[2].clone(),
+                        stall: true,
+                    }),
+                    None,
+                ),
+            ],
+            "test-chain",
+        )
+        .build(TransformContextBuilder::new_test());
+
+        chain
+            .process_request(&mut ChainState::new_test(vec![]))
+            .await
+            .unwrap_err();
+        assert!(chain.chain[1].timed_out);
+
+        // The transform that timed out and the transforms after it are rebuilt before the next run,
+        // the transforms before it are kept.
+        chain
+            .process_request(&mut ChainState::new_test(vec![]))
+            .await
+            .unwrap_err();
+        let builds: Vec<usize> = builds.iter().map(|x| *x.lock().unwrap()).collect();
+        assert_eq!(builds, vec![1, 2, 2]);
+    }
+
+    /// Only implements the request and response hooks, relying on the default implementation of `transform`
+    struct Hooks {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Transform for Hooks {
+        fn get_name(&self) -> &'static str {
+            "Hooks"
+        }
+
+        async fn transform_request<'shorter, 'longer: 'shorter>(
+            &mut self,
+            chain_state: &'shorter mut ChainState<'longer>,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("requests: {}", chain_state.requests.len()));
+            Ok(())
+        }
+
+        async fn transform_response<'shorter, 'longer: 'shorter>(
+            &mut self,
+            _chain_state: &'shorter mut ChainState<'longer>,
+            responses: &mut Messages,
+        ) -> Result<()> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(format!("responses: {}", responses.len()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_and_response_hooks() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let mut chain = TestChain::new(Box::new(Hooks {
+            calls: calls.clone(),
+        }));
+        let requests = (0..3).map(|_| Message::from_frame(Frame::Dummy)).collect();
+
+        let responses = chain.send(requests).await.unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(*calls.lock().unwrap(), vec!["requests: 3", "responses: 3"]);
+    }
+
+    /// Records whether `on_shutdown` was called, failing the final flush when `fail_flush` is set
+    #[derive(Clone)]
+    struct Shutdown {
+        fail_flush: bool,
+        shut_down: Arc<Mutex<bool>>,
+    }
+
+    impl TransformBuilder for Shutdown {
+        fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+            Box::new(self.clone())
+        }
+
+        fn get_name(&self) -> &'static str {
+            "Shutdown"
+        }
+
+        fn is_terminating(&self) -> bool {
+            true
+        }
+    }
+
+    #[async_trait]
+    impl Transform for Shutdown {
+        fn get_name(&self) -> &'static str {
+            "Shutdown"
+        }
+
+        async fn on_shutdown(&mut self) -> Result<()> {
+            *self.shut_down.lock().unwrap() = true;
+            Ok(())
+        }
+
+        async fn transform<'shorter, 'longer: 'shorter>(
+            &mut self,
+            chain_state: &'shorter mut ChainState<'longer>,
+        ) -> Result<Messages> {
+            if self.fail_flush && chain_state.flush {
+                bail!("failed to flush");
+            }
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        for fail_flush in [false, true] {
+            let shut_down = Arc::new(Mutex::new(false));
+            let mut chain = TransformChainBuilder::new(
+                vec![Box::new(Shutdown {
+                    fail_flush,
+                    shut_down: shut_down.clone(),
+                })],
+                "test-chain",
+            )
+            .build(TransformContextBuilder::new_test());
+            chain.shutdown().await;
+            // a transform that failed to flush is not usable so it is not shut down
+            assert_eq!(*shut_down.lock().unwrap(), !fail_flush);
+        }
+    }
 }
@@ -1,7 +1,7 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::message::Messages;
 use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::time::Instant;
@@ -107,6 +107,17 @@ impl Transform for Coalesce {
             Ok(vec![])
         }
     }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        // Only possible when a transform earlier in the chain failed to pass on the final flush
+        if !self.buffer.is_empty() {
+            return Err(anyhow!(
+                "{} buffered requests were dropped as they were never flushed",
+                self.buffer.len()
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(all(test, feature = "redis"))]
@@ -192,6 +203,33 @@ mod test {
         assert_responses_len(&mut chain, &mut coalesce, &requests, 0).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown() {
+        let mut coalesce = Coalesce {
+            flush_when_buffered_message_count: Some(100),
+            flush_when_millis_since_last_flush: None,
+            buffer: Vec::with_capacity(100),
+            last_write: Instant::now(),
+        };
+
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+
+        let requests: Vec<_> = (0..25)
+            .map(|_| Message::from_frame(Frame::Redis(RedisFrame::Null)))
+            .collect();
+
+        assert_responses_len(&mut chain, &mut coalesce, &requests, 0).await;
+        assert_eq!(
+            coalesce.on_shutdown().await.unwrap_err().to_string(),
+            "25 buffered requests were dropped as they were never flushed"
+        );
+
+        let mut wrapper = ChainState::flush();
+        wrapper.reset(&mut chain);
+        assert_eq!(coalesce.transform(&mut wrapper).await.unwrap().len(), 25);
+        coalesce.on_shutdown().await.unwrap();
+    }
+
     async fn assert_responses_len(
         chain: &mut [TransformAndMetrics],
         coalesce: &mut Coalesce,
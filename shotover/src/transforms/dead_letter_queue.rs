@@ -0,0 +1,500 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::util::setup_replay::is_setup_request;
+use crate::transforms::util::write_ahead_log::encode_request;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine};
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "kafka")]
+use {
+    crate::config::chain::TransformChainConfig,
+    crate::frame::kafka::StrBytes,
+    crate::transforms::chain::{TransformChain, TransformChainBuilder},
+    crate::transforms::kafka::{build_produce_request, check_produce_response},
+    bytes::Bytes,
+    kafka_protocol::messages::TopicName,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DeadLetterQueueConfig {
+    pub destination: DeadLetterDestinationConfig,
+    /// Also send requests that received an error response to the destination, defaults to false.
+    #[serde(default)]
+    pub error_responses: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum DeadLetterDestinationConfig {
+    /// Appends each dead letter to the file as a line of JSON, the file is created if it does not exist.
+    File { path: String },
+    /// Publishes each dead letter to the kafka topic as a JSON record, via a chain ending in a kafka sink.
+    #[cfg(feature = "kafka")]
+    Kafka {
+        topic: String,
+        /// How long kafka should wait for the produce request to be replicated before responding.
+        produce_timeout_ms: Option<i32>,
+        chain: TransformChainConfig,
+    },
+}
+
+const NAME: &str = "DeadLetterQueue";
+#[typetag::serde(name = "DeadLetterQueue")]
+#[async_trait(?Send)]
+impl TransformConfig for DeadLetterQueueConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        let destination = match &self.destination {
+            DeadLetterDestinationConfig::File { path } => {
+                DestinationBuilder::File(Arc::new(Mutex::new(
+                    OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .with_context(|| format!("Failed to open dead letter file {path:?}"))?,
+                )))
+            }
+            #[cfg(feature = "kafka")]
+            DeadLetterDestinationConfig::Kafka {
+                topic,
+                produce_timeout_ms,
+                chain,
+            } => DestinationBuilder::Kafka {
+                chain: chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: "dead_letter_chain".into(),
+                        up_chain_protocol: MessageType::Kafka,
+                    })
+                    .await?,
+                topic: topic.clone(),
+                produce_timeout_ms: produce_timeout_ms.unwrap_or(30_000),
+            },
+        };
+        Ok(Box::new(DeadLetterQueueBuilder {
+            destination,
+            error_responses: self.error_responses,
+            dead_letters: counter!("shotover_dead_letters_count", "chain" => chain_name.clone()),
+            dropped_dead_letters: counter!("shotover_dead_letters_dropped_count", "chain" => chain_name.clone()),
+            chain_name,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+enum DestinationBuilder {
+    /// Shared by every connection so that whole lines are written at once
+    File(Arc<Mutex<File>>),
+    #[cfg(feature = "kafka")]
+    Kafka {
+        chain: TransformChainBuilder,
+        topic: String,
+        produce_timeout_ms: i32,
+    },
+}
+
+struct DeadLetterQueueBuilder {
+    destination: DestinationBuilder,
+    error_responses: bool,
+    chain_name: String,
+    dead_letters: Counter,
+    dropped_dead_letters: Counter,
+}
+
+impl TransformBuilder for DeadLetterQueueBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(DeadLetterQueue {
+            client: transform_context.client_details.clone(),
+            destination: match &self.destination {
+                DestinationBuilder::File(file) => Destination::File(file.clone()),
+                #[cfg(feature = "kafka")]
+                DestinationBuilder::Kafka {
+                    chain,
+                    topic,
+                    produce_timeout_ms,
+                } => Destination::Kafka {
+                    chain: chain.build(transform_context),
+                    topic: TopicName(StrBytes::from_string(topic.clone())),
+                    produce_timeout_ms: *produce_timeout_ms,
+                },
+            },
+            error_responses: self.error_responses,
+            chain_name: self.chain_name.clone(),
+            dead_letters: self.dead_letters.clone(),
+            dropped_dead_letters: self.dropped_dead_letters.clone(),
+            pending_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        match &self.destination {
+            DestinationBuilder::File(_) => vec![],
+            #[cfg(feature = "kafka")]
+            DestinationBuilder::Kafka { chain, .. } => {
+                let mut errors = chain
+                    .validate()
+                    .iter()
+                    .map(|x| format!("  {x}"))
+                    .collect::<Vec<String>>();
+
+                if !errors.is_empty() {
+                    errors.insert(0, format!("{}:", self.get_name()));
+                }
+
+                errors
+            }
+        }
+    }
+}
+
+enum Destination {
+    File(Arc<Mutex<File>>),
+    #[cfg(feature = "kafka")]
+    Kafka {
+        chain: TransformChain,
+        topic: TopicName,
+        produce_timeout_ms: i32,
+    },
+}
+
+/// A failed request along with why it failed, serialized as JSON.
+#[derive(Serialize, Debug)]
+struct DeadLetter {
+    /// Milliseconds since the unix epoch
+    timestamp_ms: i64,
+    chain: String,
+    /// The IP address of the client that sent the request
+    client: String,
+    protocol: String,
+    error: String,
+    /// The request as sent by the client, base64 encoded so that it can be replayed
+    request: String,
+}
+
+struct DeadLetterQueue {
+    destination: Destination,
+    error_responses: bool,
+    chain_name: String,
+    client: String,
+    dead_letters: Counter,
+    dropped_dead_letters: Counter,
+    /// Copies of the requests that have not yet received a response
+    pending_requests: MessageIdMap<Message>,
+}
+
+impl DeadLetterQueue {
+    fn dead_letter(&self, request: Message, error: String) -> Result<DeadLetter> {
+        let protocol = format!("{:?}", request.message_type()).to_lowercase();
+        let (_, bytes) = encode_request(request)?;
+        Ok(DeadLetter {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as i64)
+                .unwrap_or(0),
+            chain: self.chain_name.clone(),
+            client: self.client.clone(),
+            protocol,
+            error,
+            request: general_purpose::STANDARD.encode(bytes),
+        })
+    }
+
+    #[cfg_attr(not(feature = "kafka"), allow(unused_variables))]
+    async fn send(&mut self, failed: Vec<(Message, String)>, local_addr: SocketAddr) {
+        if failed.is_empty() {
+            return;
+        }
+
+        self.dead_letters.increment(failed.len() as u64);
+        let mut dead_letters = Vec::with_capacity(failed.len());
+        for (request, error) in failed {
+            match self.dead_letter(request, error) {
+                Ok(dead_letter) => dead_letters.push(dead_letter),
+                Err(err) => {
+                    self.dropped_dead_letters.increment(1);
+                    tracing::error!("Failed to encode dead letter: {err:?}");
+                }
+            }
+        }
+
+        let result = match &mut self.destination {
+            Destination::File(file) => write_to_file(file, &dead_letters),
+            #[cfg(feature = "kafka")]
+            Destination::Kafka {
+                chain,
+                topic,
+                produce_timeout_ms,
+            } => match encode_dead_letters(&dead_letters)
+                .and_then(|values| build_produce_request(values, topic, *produce_timeout_ms))
+            {
+                Ok(request) => chain
+                    .process_request(&mut ChainState::new_with_addr(vec![request], local_addr))
+                    .await
+                    .and_then(check_produce_response),
+                Err(err) => Err(err),
+            },
+        };
+        if let Err(err) = result {
+            self.dropped_dead_letters
+                .increment(dead_letters.len() as u64);
+            tracing::error!(
+                "Failed to send {} dead letters: {err:?}",
+                dead_letters.len()
+            );
+        }
+    }
+}
+
+/// Returns the error message of an error response
+fn error_message(response: &mut Message) -> Option<String> {
+    match response.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(crate::frame::RedisFrame::Error(err))) => Some(err.to_string()),
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(crate::frame::CassandraFrame {
+            operation: crate::frame::CassandraOperation::Error(err),
+            ..
+        })) => Some(err.message.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn encode_dead_letters(dead_letters: &[DeadLetter]) -> Result<Vec<(i64, Bytes)>> {
+    dead_letters
+        .iter()
+        .map(|dead_letter| {
+            Ok((
+                dead_letter.timestamp_ms,
+                serde_json::to_vec(dead_letter)?.into(),
+            ))
+        })
+        .collect()
+}
+
+fn write_to_file(file: &Mutex<File>, dead_letters: &[DeadLetter]) -> Result<()> {
+    let mut lines = vec![];
+    for dead_letter in dead_letters {
+        serde_json::to_writer(&mut lines, dead_letter)?;
+        lines.push(b'\n');
+    }
+    file.lock().unwrap().write_all(&lines)?;
+    Ok(())
+}
+
+#[async_trait]
+impl Transform for DeadLetterQueue {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        match &mut self.destination {
+            Destination::File(_) => {}
+            #[cfg(feature = "kafka")]
+            Destination::Kafka { chain, .. } => chain.shutdown().await,
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in &mut chain_state.requests {
+            // Setup requests such as AUTH carry the client's credentials which must not be written out
+            if !request.is_dummy() && !is_setup_request(request) {
+                self.pending_requests.insert(request.id(), request.clone());
+            }
+        }
+        let local_addr = chain_state.local_addr;
+
+        match chain_state.call_next_transform().await {
+            Ok(mut responses) => {
+                let mut failed = vec![];
+                for response in responses.iter_mut() {
+                    let Some(request) = response
+                        .request_id()
+                        .and_then(|id| self.pending_requests.remove(&id))
+                    else {
+                        continue;
+                    };
+                    if self.error_responses {
+                        if let Some(error) = error_message(response) {
+                            failed.push((request, error));
+                        }
+                    }
+                }
+                self.send(failed, local_addr).await;
+                Ok(responses)
+            }
+            Err(err) => {
+                // The connection is closed after an error, so none of the pending requests will receive a response
+                let error = format!("{err:#}");
+                let failed = self
+                    .pending_requests
+                    .drain()
+                    .map(|(_, request)| (request, error.clone()))
+                    .collect();
+                self.send(failed, local_addr).await;
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::RedisFrame;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use pretty_assertions::assert_eq;
+
+    fn dead_letter_queue(path: &std::path::Path, error_responses: bool) -> DeadLetterQueue {
+        DeadLetterQueue {
+            destination: Destination::File(Arc::new(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .unwrap(),
+            ))),
+            error_responses,
+            chain_name: "redis".to_owned(),
+            client: "127.0.0.1".to_owned(),
+            dead_letters: Counter::noop(),
+            dropped_dead_letters: Counter::noop(),
+            pending_requests: MessageIdMap::default(),
+        }
+    }
+
+    fn read_dead_letters(path: &std::path::Path) -> Vec<(String, String)> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: serde_json::Value = serde_json::from_str(line).unwrap();
+                let request = general_purpose::STANDARD
+                    .decode(value["request"].as_str().unwrap())
+                    .unwrap();
+                (
+                    value["error"].as_str().unwrap().to_owned(),
+                    String::from_utf8(request).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_chain_error() {
+        let path = std::env::temp_dir().join(format!("shotover-dlq-{}", rand::random::<u64>()));
+        let mut transform = dead_letter_queue(&path, false);
+        let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+            Response::Fail,
+        )))];
+        let mut chain_state = ChainState::new_test(vec![redis_command(&["SET", "foo", "bar"])]);
+        chain_state.reset(&mut chain);
+        assert!(transform.transform(&mut chain_state).await.is_err());
+
+        assert_eq!(
+            read_dead_letters(&path),
+            vec![(
+                "DebugReturner transform failed: Intentional Fail".to_owned(),
+                "*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$3\r\nbar\r\n".to_owned()
+            )]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_error_responses() {
+        let path = std::env::temp_dir().join(format!("shotover-dlq-{}", rand::random::<u64>()));
+        let error = Message::from_frame(Frame::Redis(RedisFrame::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+        )));
+
+        // error responses are only dead lettered when configured
+        for error_responses in [false, true] {
+            let mut transform = dead_letter_queue(&path, error_responses);
+            let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+                Response::Message(error.clone()),
+            )))];
+            let mut chain_state = ChainState::new_test(vec![redis_command(&["GET", "foo"])]);
+            chain_state.reset(&mut chain);
+            assert_eq!(
+                transform.transform(&mut chain_state).await.unwrap().len(),
+                1
+            );
+            assert!(transform.pending_requests.is_empty());
+        }
+
+        assert_eq!(
+            read_dead_letters(&path),
+            vec![(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_owned(),
+                "*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n".to_owned()
+            )]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_setup_requests_not_dead_lettered() {
+        let path = std::env::temp_dir().join(format!("shotover-dlq-{}", rand::random::<u64>()));
+        let error = Message::from_frame(Frame::Redis(RedisFrame::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".into(),
+        )));
+
+        let mut transform = dead_letter_queue(&path, true);
+        let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+            Response::Message(error),
+        )))];
+        let mut chain_state =
+            ChainState::new_test(vec![redis_command(&["AUTH", "user", "password"])]);
+        chain_state.reset(&mut chain);
+        transform.transform(&mut chain_state).await.unwrap();
+
+        let mut transform = dead_letter_queue(&path, true);
+        let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+            Response::Fail,
+        )))];
+        let mut chain_state =
+            ChainState::new_test(vec![redis_command(&["AUTH", "user", "password"])]);
+        chain_state.reset(&mut chain);
+        assert!(transform.transform(&mut chain_state).await.is_err());
+
+        assert_eq!(read_dead_letters(&path), vec![]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -65,20 +65,27 @@ impl Transform for DebugPrinter {
         NAME
     }
 
-    async fn transform<'shorter, 'longer: 'shorter>(
+    async fn transform_request<'shorter, 'longer: 'shorter>(
         &mut self,
         chain_state: &'shorter mut ChainState<'longer>,
-    ) -> Result<Messages> {
+    ) -> Result<()> {
         for request in &mut chain_state.requests {
-            info!("Request: {}", request.to_high_level_string());
+            let id = request.correlation_id();
+            info!("Request {id}: {}", request.to_high_level_string());
         }
-
         self.counter += 1;
-        let mut responses = chain_state.call_next_transform().await?;
+        Ok(())
+    }
 
-        for response in &mut responses {
-            info!("Response: {}", response.to_high_level_string());
+    async fn transform_response<'shorter, 'longer: 'shorter>(
+        &mut self,
+        _chain_state: &'shorter mut ChainState<'longer>,
+        responses: &mut Messages,
+    ) -> Result<()> {
+        for response in responses {
+            let id = response.correlation_id();
+            info!("Response {id}: {}", response.to_high_level_string());
         }
-        Ok(responses)
+        Ok(())
     }
 }
@@ -0,0 +1,505 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, MessageId, MessageIdMap, MessageIdSet, Messages};
+use crate::transforms::util::in_flight::{remove_in_flight, Follower, InFlight};
+use crate::transforms::util::ordered_responses::OrderedResponses;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[cfg(feature = "redis")]
+use crate::{
+    frame::fingerprint::normalize_redis, frame::redis::redis_query_type, frame::RedisFrame,
+    message::QueryType,
+};
+#[cfg(feature = "cassandra")]
+use {
+    crate::frame::cassandra::{CassandraResult, Tracing},
+    crate::frame::fingerprint::normalize_cassandra_statement,
+    crate::frame::{CassandraFrame, CassandraOperation},
+    cassandra_protocol::compression::Compression,
+    cassandra_protocol::frame::Serialize as FrameSerialize,
+    cassandra_protocol::types::CBytesShort,
+    cql3_parser::cassandra_statement::CassandraStatement,
+    std::collections::HashSet,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct DedupConfig;
+
+const NAME: &str = "Dedup";
+#[typetag::serde(name = "Dedup")]
+#[async_trait(?Send)]
+impl TransformConfig for DedupConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(DedupBuilder {
+            shared: Arc::new(Shared::default()),
+            in_order: transform_context.up_chain_protocol.is_inorder(),
+            coalesced: counter!("shotover_dedup_coalesced_requests_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// State shared by every connection.
+#[derive(Default)]
+struct Shared {
+    /// Read requests awaiting a response from the rest of the chain, keyed by their normalized form
+    in_flight: Mutex<HashMap<Vec<u8>, Arc<InFlight>>>,
+    /// Ids of cassandra prepared statements that are SELECTs.
+    /// Ids are derived from the statement and keyspace so they are the same for every connection.
+    #[cfg(feature = "cassandra")]
+    prepared_reads: Mutex<HashSet<CBytesShort>>,
+    hasher: RandomState,
+}
+
+struct DedupBuilder {
+    shared: Arc<Shared>,
+    in_order: bool,
+    coalesced: Counter,
+}
+
+impl TransformBuilder for DedupBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(Dedup {
+            shared: self.shared.clone(),
+            force_run_chain: transform_context.force_run_chain,
+            coalesced: self.coalesced.clone(),
+            connection_state: 0,
+            #[cfg(feature = "redis")]
+            in_transaction: false,
+            #[cfg(feature = "cassandra")]
+            prepare_requests: MessageIdSet::default(),
+            leaders: MessageIdMap::default(),
+            followers: MessageIdMap::default(),
+            dummy_requests: MessageIdSet::default(),
+            responses: OrderedResponses::new(self.in_order),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct Dedup {
+    shared: Arc<Shared>,
+    force_run_chain: Arc<Notify>,
+    coalesced: Counter,
+    /// A hash of the requests that set up the connection, e.g. `AUTH` and `SELECT`, as the response to a read may depend on them.
+    /// Only requests from connections with the same state are coalesced.
+    connection_state: u64,
+    #[cfg(feature = "redis")]
+    in_transaction: bool,
+    #[cfg(feature = "cassandra")]
+    prepare_requests: MessageIdSet,
+    /// Requests sent down the chain that identical requests may be waiting on, keyed by request id
+    leaders: MessageIdMap<(Vec<u8>, Arc<InFlight>)>,
+    /// Requests waiting on an identical request, keyed by request id
+    followers: MessageIdMap<Follower>,
+    /// Ids of the dummy requests that replaced followers, their responses are discarded
+    dummy_requests: MessageIdSet,
+    responses: OrderedResponses,
+}
+
+impl Dedup {
+    /// Returns the normalized form of the request if it is a read whose response can be shared with identical requests.
+    /// Queries are identified by their [`NormalizedQuery::key`](crate::frame::fingerprint::NormalizedQuery::key),
+    /// so the same query written with different keyword case or whitespace is coalesced.
+    fn dedup_key(&mut self, request: &mut Message) -> Option<Vec<u8>> {
+        #[cfg(feature = "cassandra")]
+        let request_id = request.id();
+        match request.frame() {
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(frame)) => self.redis_key(frame),
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => self.cassandra_key(request_id, frame),
+            _ => None,
+        }
+    }
+
+    fn update_connection_state(&mut self, request: &[u8]) {
+        self.connection_state = self
+            .shared
+            .hasher
+            .hash_one((self.connection_state, request));
+    }
+
+    #[cfg(feature = "redis")]
+    fn redis_key(&mut self, frame: &RedisFrame) -> Option<Vec<u8>> {
+        let RedisFrame::Array(args) = frame else {
+            return None;
+        };
+        if !args
+            .iter()
+            .all(|arg| matches!(arg, RedisFrame::BulkString(_)))
+        {
+            return None;
+        }
+        let mut key = self.connection_state.to_be_bytes().to_vec();
+        key.extend(normalize_redis(frame)?.key());
+
+        match args.first() {
+            Some(RedisFrame::BulkString(command)) => {
+                match command.to_ascii_uppercase().as_slice() {
+                    b"AUTH" | b"HELLO" | b"SELECT" => {
+                        self.update_connection_state(&key[8..]);
+                        None
+                    }
+                    b"MULTI" => {
+                        self.in_transaction = true;
+                        None
+                    }
+                    b"EXEC" | b"DISCARD" => {
+                        self.in_transaction = false;
+                        None
+                    }
+                    // Commands within a transaction are queued rather than executed
+                    _ if self.in_transaction => None,
+                    _ => (redis_query_type(frame) == QueryType::Read).then_some(key),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "cassandra")]
+    fn cassandra_key(&mut self, request_id: MessageId, frame: &CassandraFrame) -> Option<Vec<u8>> {
+        let encoded = || {
+            CassandraFrame {
+                stream_id: 0,
+                ..frame.clone()
+            }
+            .encode(Compression::None)
+        };
+        let read = match &frame.operation {
+            CassandraOperation::Startup(_) | CassandraOperation::AuthResponse(_) => {
+                self.update_connection_state(&encoded());
+                return None;
+            }
+            CassandraOperation::Prepare(_) => {
+                if let Some(CassandraStatement::Select(_)) = frame.prepared_statement() {
+                    self.prepare_requests.insert(request_id);
+                }
+                return None;
+            }
+            // Unqualified table names depend on the keyspace the connection is using
+            CassandraOperation::Query { query, .. } => match query.as_ref() {
+                CassandraStatement::Select(select) => select.table_name.keyspace.is_some(),
+                _ => false,
+            },
+            CassandraOperation::Execute(execute) => self
+                .shared
+                .prepared_reads
+                .lock()
+                .unwrap()
+                .contains(&execute.id),
+            _ => false,
+        };
+        // A traced request receives its own tracing session id
+        if !read || !matches!(frame.tracing, Tracing::Request(false)) {
+            return None;
+        }
+
+        let mut key = self.connection_state.to_be_bytes().to_vec();
+        match &frame.operation {
+            CassandraOperation::Query { query, params } => {
+                key.push(frame.version.into());
+                key.extend(normalize_cassandra_statement(query).key());
+                key.extend(params.serialize_to_vec(frame.version));
+            }
+            _ => key.extend(encoded()),
+        }
+        Some(key)
+    }
+
+    #[cfg(feature = "cassandra")]
+    fn process_prepared_response(&mut self, mut response: Message) -> Message {
+        if response
+            .request_id()
+            .map(|id| self.prepare_requests.remove(&id))
+            .unwrap_or(false)
+        {
+            if let Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Prepared(prepared)),
+                ..
+            })) = response.frame()
+            {
+                self.shared
+                    .prepared_reads
+                    .lock()
+                    .unwrap()
+                    .insert(prepared.id.clone());
+            }
+        }
+        response
+    }
+
+    /// Fails every request waiting on a request sent down the chain by this connection
+    fn fail_leaders(&mut self) {
+        for (_, (key, in_flight)) in self.leaders.drain() {
+            remove_in_flight(&self.shared.in_flight, &key, &in_flight);
+            in_flight.complete(None);
+        }
+    }
+}
+
+impl Drop for Dedup {
+    fn drop(&mut self) {
+        self.fail_leaders();
+    }
+}
+
+#[async_trait]
+impl Transform for Dedup {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in chain_state.requests.iter_mut() {
+            self.responses.push_request(request.id());
+            let Some(key) = self.dedup_key(request) else {
+                continue;
+            };
+            let mut in_flight = self.shared.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(leader) => {
+                    if let Ok(metadata) = request.metadata() {
+                        leader.add_waiter(self.force_run_chain.clone());
+                        self.followers.insert(
+                            request.id(),
+                            Follower {
+                                metadata,
+                                in_flight: leader.clone(),
+                            },
+                        );
+                        request.replace_with_dummy();
+                        self.dummy_requests.insert(request.id());
+                        self.coalesced.increment(1);
+                    }
+                }
+                None => {
+                    let leader = Arc::new(InFlight::default());
+                    in_flight.insert(key.clone(), leader.clone());
+                    self.leaders.insert(request.id(), (key, leader));
+                }
+            }
+        }
+
+        let responses = match chain_state.call_next_transform().await {
+            Ok(responses) => responses,
+            Err(err) => {
+                self.fail_leaders();
+                return Err(err);
+            }
+        };
+
+        let mut ready = vec![];
+        for response in responses {
+            let Some(request_id) = response.request_id() else {
+                // responses not created in response to a request, e.g. cassandra events, are returned immediately
+                ready.push(response);
+                continue;
+            };
+            // The response to a follower's dummy request is discarded, it is replaced by the leader's response
+            if self.dummy_requests.remove(&request_id) {
+                continue;
+            }
+            if let Some((key, leader)) = self.leaders.remove(&request_id) {
+                remove_in_flight(&self.shared.in_flight, &key, &leader);
+                leader.complete(Some(response.clone_with_new_id()));
+            }
+            #[cfg(feature = "cassandra")]
+            let response = self.process_prepared_response(response);
+            self.responses.insert(request_id, response);
+        }
+
+        let mut completed = vec![];
+        for (request_id, follower) in self.followers.iter() {
+            if let Some(response) = follower.in_flight.response() {
+                completed.push((*request_id, follower.response(*request_id, response)?));
+            }
+        }
+        for (request_id, response) in completed {
+            self.followers.remove(&request_id);
+            self.responses.insert(request_id, response);
+        }
+
+        self.responses.take_ready(&mut ready);
+        Ok(ready)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn dedup(shared: Arc<Shared>) -> Dedup {
+        Dedup {
+            shared,
+            force_run_chain: Arc::new(Notify::new()),
+            coalesced: Counter::noop(),
+            connection_state: 0,
+            in_transaction: false,
+            #[cfg(feature = "cassandra")]
+            prepare_requests: MessageIdSet::default(),
+            leaders: MessageIdMap::default(),
+            followers: MessageIdMap::default(),
+            dummy_requests: MessageIdSet::default(),
+            responses: OrderedResponses::new(true),
+        }
+    }
+
+    /// Returns the frame and request id of every response
+    async fn run(transform: &mut Dedup, requests: Messages) -> Vec<(Frame, Option<MessageId>)> {
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.reset(&mut chain);
+        transform
+            .transform(&mut chain_state)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| (x.frame().cloned().unwrap(), x.request_id()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_dedup_within_batch() {
+        let shared = Arc::new(Shared::default());
+        let mut transform = dedup(shared.clone());
+        let requests = vec![
+            redis_command(&["GET", "1"]),
+            redis_command(&["get", "1"]),
+            redis_command(&["SET", "1", "a"]),
+            redis_command(&["GET", "2"]),
+            redis_command(&["GET", "1"]),
+        ];
+        let ids: Vec<_> = requests.iter().map(|x| Some(x.id())).collect();
+
+        let responses = run(&mut transform, requests).await;
+        let get_1 = redis_command(&["GET", "1"]).frame().cloned().unwrap();
+        assert_eq!(
+            responses,
+            vec![
+                (get_1.clone(), ids[0]),
+                (get_1.clone(), ids[1]),
+                (
+                    redis_command(&["SET", "1", "a"]).frame().cloned().unwrap(),
+                    ids[2]
+                ),
+                (
+                    redis_command(&["GET", "2"]).frame().cloned().unwrap(),
+                    ids[3]
+                ),
+                (get_1, ids[4]),
+            ]
+        );
+        assert!(shared.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dedup_across_connections() {
+        let shared = Arc::new(Shared::default());
+        let mut transform = dedup(shared.clone());
+
+        // another connection is awaiting the response to an identical request
+        let key = transform
+            .dedup_key(&mut redis_command(&["GET", "1"]))
+            .unwrap();
+        let leader = Arc::new(InFlight::default());
+        shared.in_flight.lock().unwrap().insert(key, leader.clone());
+
+        let request = redis_command(&["GET", "1"]);
+        let id = request.id();
+        assert_eq!(run(&mut transform, vec![request]).await, vec![]);
+
+        let mut response = Message::from_frame(Frame::Redis(RedisFrame::BulkString("a".into())));
+        response.set_request_id(rand::random());
+        leader.complete(Some(response));
+        tokio::time::timeout(Duration::from_secs(1), transform.force_run_chain.notified())
+            .await
+            .unwrap();
+        assert_eq!(
+            run(&mut transform, vec![]).await,
+            vec![(Frame::Redis(RedisFrame::BulkString("a".into())), Some(id))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedup_connection_state() {
+        let shared = Arc::new(Shared::default());
+        let mut a = dedup(shared.clone());
+        let mut b = dedup(shared.clone());
+        assert_eq!(
+            a.dedup_key(&mut redis_command(&["GET", "1"])),
+            b.dedup_key(&mut redis_command(&["GET", "1"]))
+        );
+
+        // connections using a different database must not share responses
+        assert_eq!(b.dedup_key(&mut redis_command(&["SELECT", "1"])), None);
+        assert_ne!(
+            a.dedup_key(&mut redis_command(&["GET", "1"])),
+            b.dedup_key(&mut redis_command(&["GET", "1"]))
+        );
+
+        // commands queued in a transaction are not executed until EXEC
+        assert_eq!(a.dedup_key(&mut redis_command(&["MULTI"])), None);
+        assert_eq!(a.dedup_key(&mut redis_command(&["GET", "1"])), None);
+        assert_eq!(a.dedup_key(&mut redis_command(&["EXEC"])), None);
+        assert!(a.dedup_key(&mut redis_command(&["GET", "1"])).is_some());
+
+        assert_eq!(a.dedup_key(&mut redis_command(&["SET", "1", "a"])), None);
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_dedup_cassandra_key() {
+        use crate::test_utils::cassandra_query;
+
+        let mut transform = dedup(Arc::new(Shared::default()));
+        let mut key = |query: &str| transform.dedup_key(&mut cassandra_query(query));
+        // the same query written differently is coalesced
+        assert_eq!(
+            key("SELECT * FROM ks.t WHERE id = 1"),
+            key("select *  from KS.t where id=1;")
+        );
+        assert_ne!(
+            key("SELECT * FROM ks.t WHERE id = 1"),
+            key("SELECT * FROM ks.t WHERE id = 2")
+        );
+        assert!(key("SELECT * FROM ks.t WHERE id = 1").is_some());
+        assert_eq!(key("SELECT * FROM t WHERE id = 1"), None);
+    }
+}
@@ -1,5 +1,5 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
-use crate::message::{Message, MessageIdMap, Messages, QueryType};
+use crate::message::{ErrorKind, Message, MessageIdMap, Messages, QueryType};
 use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
 use anyhow::Result;
 use async_trait::async_trait;
@@ -78,8 +78,9 @@ impl Transform for QueryTypeFilter {
                 self.filtered_requests.insert(
                     request.id(),
                     request
-                        .from_request_to_error_response(
+                        .error_response(
                             "Message was filtered out by shotover".to_owned(),
+                            ErrorKind::Server,
                         )
                         .map_err(|e| e.context("Failed to filter message"))?,
                 );
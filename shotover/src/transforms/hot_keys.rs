@@ -0,0 +1,400 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::message::Messages;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{gauge, Gauge};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Every tracker that has been created and not yet dropped, reported by the `/hot_keys` endpoint.
+static TRACKERS: LazyLock<Mutex<Vec<Weak<HotKeyTracker>>>> = LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HotKeysConfig {
+    /// The number of keys counted at once, defaults to 1000.
+    /// Larger values give more accurate counts at the cost of memory and CPU.
+    pub capacity: Option<usize>,
+    /// The number of hottest keys reported, defaults to 10.
+    pub top_k: Option<usize>,
+    /// The length of the window over which accesses are counted, defaults to 10 seconds.
+    pub window_secs: Option<u64>,
+    /// When set, a warning is logged for every key accessed more than this many times per second over a window.
+    pub warn_requests_per_second: Option<u64>,
+}
+
+const NAME: &str = "HotKeys";
+#[typetag::serde(name = "HotKeys")]
+#[async_trait(?Send)]
+impl TransformConfig for HotKeysConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let top_k = self.top_k.unwrap_or(10);
+        let chain_name = transform_context.chain_name;
+        let tracker = Arc::new(HotKeyTracker {
+            rank_gauges: (1..=top_k)
+                .map(|rank| gauge!("shotover_hot_key_requests_per_second", "chain" => chain_name.clone(), "rank" => rank.to_string()))
+                .collect(),
+            chain_name,
+            top_k,
+            window: Duration::from_secs(self.window_secs.unwrap_or(10)),
+            warn_requests_per_second: self.warn_requests_per_second,
+            state: Mutex::new(WindowState {
+                sketch: SpaceSaving::new(self.capacity.unwrap_or(1000)),
+                started: Instant::now(),
+                last_window: vec![],
+            }),
+        });
+        let mut trackers = TRACKERS.lock().unwrap();
+        trackers.retain(|x| x.strong_count() > 0);
+        trackers.push(Arc::downgrade(&tracker));
+
+        Ok(Box::new(HotKeysBuilder { tracker }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// The count of a key in a [`SpaceSaving`] sketch
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Count {
+    /// May overestimate the true count by up to `error`
+    count: u64,
+    error: u64,
+}
+
+/// The space-saving algorithm, which finds the most frequent keys of a stream using a fixed number of counters.
+/// When a key that is not being counted is seen while every counter is in use,
+/// it replaces the least frequent key and inherits its count as an upper bound on how often it may have been seen before.
+struct SpaceSaving {
+    capacity: usize,
+    counts: HashMap<Bytes, Count>,
+    /// The counted keys ordered by their count
+    by_count: BTreeSet<(u64, Bytes)>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        SpaceSaving {
+            capacity,
+            counts: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    fn record(&mut self, key: &Bytes) {
+        if let Some(count) = self.counts.get_mut(key) {
+            self.by_count.remove(&(count.count, key.clone()));
+            count.count += 1;
+            self.by_count.insert((count.count, key.clone()));
+        } else if self.counts.len() < self.capacity {
+            self.counts
+                .insert(key.clone(), Count { count: 1, error: 0 });
+            self.by_count.insert((1, key.clone()));
+        } else if let Some((min, evicted)) = self.by_count.pop_first() {
+            self.counts.remove(&evicted);
+            self.counts.insert(
+                key.clone(),
+                Count {
+                    count: min + 1,
+                    error: min,
+                },
+            );
+            self.by_count.insert((min + 1, key.clone()));
+        }
+    }
+
+    /// Returns the `k` most frequent keys, most frequent first
+    fn top(&self, k: usize) -> Vec<(Bytes, Count)> {
+        self.by_count
+            .iter()
+            .rev()
+            .take(k)
+            .map(|(_, key)| (key.clone(), self.counts[key]))
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        self.counts.clear();
+        self.by_count.clear();
+    }
+}
+
+/// A key found to be hot in a completed window
+#[derive(Debug, PartialEq)]
+struct HotKey {
+    key: Bytes,
+    requests_per_second: f64,
+    /// The requests per second may be overestimated by up to this much
+    error: f64,
+}
+
+struct WindowState {
+    sketch: SpaceSaving,
+    started: Instant,
+    /// The hottest keys of the most recently completed window
+    last_window: Vec<HotKey>,
+}
+
+/// Tracks the hottest keys across every connection of a chain.
+struct HotKeyTracker {
+    chain_name: String,
+    top_k: usize,
+    window: Duration,
+    warn_requests_per_second: Option<u64>,
+    state: Mutex<WindowState>,
+    rank_gauges: Vec<Gauge>,
+}
+
+impl HotKeyTracker {
+    fn record(&self, keys: &[Bytes]) {
+        let mut state = self.state.lock().unwrap();
+        self.rotate_if_complete(&mut state);
+        for key in keys {
+            state.sketch.record(key);
+        }
+    }
+
+    /// Once the current window is complete, its hottest keys are stored and reported, and a new window is started
+    fn rotate_if_complete(&self, state: &mut WindowState) {
+        let elapsed = state.started.elapsed();
+        if elapsed < self.window {
+            return;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        state.last_window = state
+            .sketch
+            .top(self.top_k)
+            .into_iter()
+            .map(|(key, count)| HotKey {
+                key,
+                requests_per_second: count.count as f64 / seconds,
+                error: count.error as f64 / seconds,
+            })
+            .collect();
+        for (i, gauge) in self.rank_gauges.iter().enumerate() {
+            gauge.set(
+                state
+                    .last_window
+                    .get(i)
+                    .map(|x| x.requests_per_second)
+                    .unwrap_or(0.0),
+            );
+        }
+        if let Some(threshold) = self.warn_requests_per_second {
+            for hot_key in &state.last_window {
+                if hot_key.requests_per_second > threshold as f64 {
+                    tracing::warn!(
+                        "{}: key {} was accessed {:.1} times per second, exceeding the threshold of {threshold}",
+                        self.chain_name,
+                        hot_key.key.escape_ascii(),
+                        hot_key.requests_per_second
+                    );
+                }
+            }
+        }
+
+        state.sketch.clear();
+        state.started = Instant::now();
+    }
+
+    fn report(&self, output: &mut String) {
+        let mut state = self.state.lock().unwrap();
+        self.rotate_if_complete(&mut state);
+        writeln!(
+            output,
+            "{} (requests per second over the last {}s):",
+            self.chain_name,
+            self.window.as_secs()
+        )
+        .unwrap();
+        for hot_key in &state.last_window {
+            writeln!(
+                output,
+                "  {} {:.1} (may be overestimated by up to {:.1})",
+                hot_key.key.escape_ascii(),
+                hot_key.requests_per_second,
+                hot_key.error
+            )
+            .unwrap();
+        }
+    }
+}
+
+/// Returns the hottest keys of every chain with a `HotKeys` transform, as served by the `/hot_keys` endpoint.
+pub(crate) fn report() -> String {
+    let mut output = String::new();
+    for tracker in TRACKERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|tracker| tracker.upgrade())
+    {
+        tracker.report(&mut output);
+    }
+    output
+}
+
+struct HotKeysBuilder {
+    tracker: Arc<HotKeyTracker>,
+}
+
+impl TransformBuilder for HotKeysBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(HotKeys {
+            tracker: self.tracker.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.tracker.top_k == 0 {
+            errors.push("  top_k must be greater than 0".to_owned());
+        }
+        if self.tracker.state.lock().unwrap().sketch.capacity < self.tracker.top_k {
+            errors.push("  capacity must be at least top_k".to_owned());
+        }
+        if self.tracker.window.is_zero() {
+            errors.push("  window_secs must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct HotKeys {
+    tracker: Arc<HotKeyTracker>,
+}
+
+#[async_trait]
+impl Transform for HotKeys {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut keys = vec![];
+        for request in &mut chain_state.requests {
+            let tables = request.tables();
+            for key in request.primary_keys() {
+                // Keys of cassandra partitions and kafka records are only unique within their table or topic
+                match tables.as_slice() {
+                    [table] => {
+                        let mut qualified = Vec::with_capacity(table.len() + 1 + key.len());
+                        qualified.extend_from_slice(table.as_bytes());
+                        qualified.push(b':');
+                        qualified.extend_from_slice(&key);
+                        keys.push(Bytes::from(qualified));
+                    }
+                    _ => keys.push(key),
+                }
+            }
+        }
+        if !keys.is_empty() {
+            self.tracker.record(&keys);
+        }
+
+        chain_state.call_next_transform().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn tracker(top_k: usize, capacity: usize) -> HotKeyTracker {
+        HotKeyTracker {
+            chain_name: "test".to_owned(),
+            top_k,
+            window: Duration::from_secs(10),
+            warn_requests_per_second: None,
+            state: Mutex::new(WindowState {
+                sketch: SpaceSaving::new(capacity),
+                started: Instant::now(),
+                last_window: vec![],
+            }),
+            rank_gauges: vec![],
+        }
+    }
+
+    #[test]
+    fn test_space_saving() {
+        let mut sketch = SpaceSaving::new(2);
+        for key in ["a", "a", "a", "b", "c", "a", "c"] {
+            sketch.record(&Bytes::from(key));
+        }
+        // c replaced b, inheriting its count
+        assert_eq!(
+            sketch.top(2),
+            vec![
+                (Bytes::from("a"), Count { count: 4, error: 0 }),
+                (Bytes::from("c"), Count { count: 3, error: 1 }),
+            ]
+        );
+        assert_eq!(sketch.top(1).len(), 1);
+    }
+
+    #[test]
+    fn test_report() {
+        let tracker = tracker(1, 10);
+        tracker.record(&[Bytes::from("hot"), Bytes::from("hot"), Bytes::from("cold")]);
+
+        // the window is not complete so nothing is reported yet
+        let mut output = String::new();
+        tracker.report(&mut output);
+        assert_eq!(output, "test (requests per second over the last 10s):\n");
+
+        tracker.state.lock().unwrap().started -= Duration::from_secs(10);
+        tracker.report(&mut output);
+        let state = tracker.state.lock().unwrap();
+        assert_eq!(state.last_window.len(), 1);
+        assert_eq!(state.last_window[0].key, Bytes::from("hot"));
+        assert!(state.last_window[0].requests_per_second <= 0.2);
+        assert!(state.sketch.counts.is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = HotKeysBuilder {
+            tracker: Arc::new(tracker(0, 0)),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec!["HotKeys:", "  top_k must be greater than 0"]
+        );
+
+        let builder = HotKeysBuilder {
+            tracker: Arc::new(tracker(10, 5)),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec!["HotKeys:", "  capacity must be at least top_k"]
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody, StrBytes};
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, Messages};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use kafka_protocol::messages::GroupId;
+use serde::{Deserialize, Serialize};
+
+/// FindCoordinator key_type value indicating that the key is a group id
+const KEY_TYPE_GROUP: i8 = 0;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaConsumerGroupRewriteConfig {
+    /// The value that is prepended to every consumer group id sent to kafka.
+    pub prefix: String,
+}
+
+const NAME: &str = "KafkaConsumerGroupRewrite";
+#[typetag::serde(name = "KafkaConsumerGroupRewrite")]
+#[async_trait(?Send)]
+impl TransformConfig for KafkaConsumerGroupRewriteConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(KafkaConsumerGroupRewrite {
+            prefix: self.prefix.clone(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Kafka])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Clone)]
+struct KafkaConsumerGroupRewrite {
+    prefix: String,
+}
+
+impl TransformBuilder for KafkaConsumerGroupRewrite {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(self.clone())
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        if self.prefix.is_empty() {
+            vec![
+                format!("{}:", TransformBuilder::get_name(self)),
+                "  prefix must not be empty".to_owned(),
+            ]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl KafkaConsumerGroupRewrite {
+    fn add_prefix_str(&self, value: &mut StrBytes) {
+        *value = StrBytes::from_string(format!("{}{}", self.prefix, value.as_str()));
+    }
+
+    fn add_prefix(&self, group_id: &mut GroupId) {
+        self.add_prefix_str(&mut group_id.0);
+    }
+
+    /// Returns false if the value did not contain the prefix and therefore belongs to another environment
+    fn strip_prefix_str(&self, value: &mut StrBytes) -> bool {
+        match value.as_str().strip_prefix(&self.prefix) {
+            Some(stripped) => {
+                *value = StrBytes::from_string(stripped.to_owned());
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn strip_prefix(&self, group_id: &mut GroupId) -> bool {
+        self.strip_prefix_str(&mut group_id.0)
+    }
+
+    fn rewrite_request(&self, request: &mut Message) {
+        let modified = match request.frame() {
+            Some(Frame::Kafka(KafkaFrame::Request { header, body })) => match body {
+                RequestBody::FindCoordinator(find_coordinator) => {
+                    if find_coordinator.key_type == KEY_TYPE_GROUP {
+                        if header.request_api_version <= 3 {
+                            self.add_prefix_str(&mut find_coordinator.key);
+                        } else {
+                            for key in &mut find_coordinator.coordinator_keys {
+                                self.add_prefix_str(key);
+                            }
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                }
+                RequestBody::JoinGroup(join_group) => {
+                    self.add_prefix(&mut join_group.group_id);
+                    true
+                }
+                RequestBody::SyncGroup(sync_group) => {
+                    self.add_prefix(&mut sync_group.group_id);
+                    true
+                }
+                RequestBody::Heartbeat(heartbeat) => {
+                    self.add_prefix(&mut heartbeat.group_id);
+                    true
+                }
+                RequestBody::LeaveGroup(leave_group) => {
+                    self.add_prefix(&mut leave_group.group_id);
+                    true
+                }
+                RequestBody::OffsetCommit(offset_commit) => {
+                    self.add_prefix(&mut offset_commit.group_id);
+                    true
+                }
+                RequestBody::TxnOffsetCommit(txn_offset_commit) => {
+                    self.add_prefix(&mut txn_offset_commit.group_id);
+                    true
+                }
+                RequestBody::OffsetDelete(offset_delete) => {
+                    self.add_prefix(&mut offset_delete.group_id);
+                    true
+                }
+                RequestBody::OffsetFetch(offset_fetch) => {
+                    if header.request_api_version <= 7 {
+                        self.add_prefix(&mut offset_fetch.group_id);
+                    } else {
+                        for group in &mut offset_fetch.groups {
+                            self.add_prefix(&mut group.group_id);
+                        }
+                    }
+                    true
+                }
+                RequestBody::DescribeGroups(describe_groups) => {
+                    for group_id in &mut describe_groups.groups {
+                        self.add_prefix(group_id);
+                    }
+                    true
+                }
+                RequestBody::DeleteGroups(delete_groups) => {
+                    for group_id in &mut delete_groups.groups_names {
+                        self.add_prefix(group_id);
+                    }
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if modified {
+            request.invalidate_cache();
+        }
+    }
+
+    fn rewrite_response(&self, response: &mut Message) {
+        let modified = match response.frame() {
+            Some(Frame::Kafka(KafkaFrame::Response { body, .. })) => match body {
+                ResponseBody::FindCoordinator(find_coordinator) => {
+                    for coordinator in &mut find_coordinator.coordinators {
+                        self.strip_prefix_str(&mut coordinator.key);
+                    }
+                    !find_coordinator.coordinators.is_empty()
+                }
+                ResponseBody::OffsetFetch(offset_fetch) => {
+                    for group in &mut offset_fetch.groups {
+                        self.strip_prefix(&mut group.group_id);
+                    }
+                    !offset_fetch.groups.is_empty()
+                }
+                ResponseBody::DescribeGroups(describe_groups) => {
+                    for group in &mut describe_groups.groups {
+                        self.strip_prefix(&mut group.group_id);
+                    }
+                    true
+                }
+                ResponseBody::DeleteGroups(delete_groups) => {
+                    for result in &mut delete_groups.results {
+                        self.strip_prefix(&mut result.group_id);
+                    }
+                    true
+                }
+                ResponseBody::ListGroups(list_groups) => {
+                    // Hide groups belonging to other environments
+                    list_groups
+                        .groups
+                        .retain_mut(|group| self.strip_prefix(&mut group.group_id));
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if modified {
+            response.invalidate_cache();
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for KafkaConsumerGroupRewrite {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in chain_state.requests.iter_mut() {
+            self.rewrite_request(request);
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            self.rewrite_response(response);
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kafka_protocol::messages::list_groups_response::ListedGroup;
+    use kafka_protocol::messages::{
+        ApiKey, JoinGroupRequest, ListGroupsResponse, RequestHeader, ResponseHeader,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn transform() -> KafkaConsumerGroupRewrite {
+        KafkaConsumerGroupRewrite {
+            prefix: "staging.".to_owned(),
+        }
+    }
+
+    fn group_id(value: &'static str) -> GroupId {
+        GroupId(StrBytes::from_static_str(value))
+    }
+
+    #[test]
+    fn join_group_request_is_prefixed() {
+        let mut request = Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+            header: RequestHeader::default()
+                .with_request_api_key(ApiKey::JoinGroupKey as i16)
+                .with_request_api_version(5),
+            body: RequestBody::JoinGroup(
+                JoinGroupRequest::default().with_group_id(group_id("consumers")),
+            ),
+        }));
+        transform().rewrite_request(&mut request);
+
+        assert_eq!(
+            request.frame(),
+            Some(&mut Frame::Kafka(KafkaFrame::Request {
+                header: RequestHeader::default()
+                    .with_request_api_key(ApiKey::JoinGroupKey as i16)
+                    .with_request_api_version(5),
+                body: RequestBody::JoinGroup(
+                    JoinGroupRequest::default().with_group_id(group_id("staging.consumers")),
+                ),
+            }))
+        );
+    }
+
+    #[test]
+    fn list_groups_response_hides_other_environments() {
+        let mut response = Message::from_frame(Frame::Kafka(KafkaFrame::Response {
+            version: 4,
+            header: ResponseHeader::default(),
+            body: ResponseBody::ListGroups(ListGroupsResponse::default().with_groups(vec![
+                ListedGroup::default().with_group_id(group_id("staging.consumers")),
+                ListedGroup::default().with_group_id(group_id("prod.consumers")),
+            ])),
+        }));
+        transform().rewrite_response(&mut response);
+
+        assert_eq!(
+            response.frame(),
+            Some(&mut Frame::Kafka(KafkaFrame::Response {
+                version: 4,
+                header: ResponseHeader::default(),
+                body: ResponseBody::ListGroups(ListGroupsResponse::default().with_groups(vec![
+                    ListedGroup::default().with_group_id(group_id("consumers"))
+                ])),
+            }))
+        );
+    }
+}
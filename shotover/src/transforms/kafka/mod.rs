@@ -1,2 +1,119 @@
+use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
+use crate::frame::Frame;
+use crate::message::{Message, Messages};
+use anyhow::{anyhow, Result};
+use bytes::{Bytes, BytesMut};
+use kafka_protocol::messages::produce_request::{PartitionProduceData, TopicProduceData};
+use kafka_protocol::messages::{ApiKey, ProduceRequest, RequestHeader, TopicName};
+use kafka_protocol::records::{
+    Compression, Record, RecordBatchEncoder, RecordEncodeOptions, TimestampType,
+};
+
+pub mod consumer_group_rewrite;
+pub mod recompression;
+pub mod record_mutation;
 pub mod sink_cluster;
 pub mod sink_single;
+pub mod trace_headers;
+
+/// Kafka produce version 3 is the first version to use the v2 record batch format, which is required for record headers.
+pub(crate) const RECORD_BATCH_V2_PRODUCE_VERSION: i16 = 3;
+
+/// Builds a request producing each value to partition 0 of the topic.
+/// Each value is paired with its timestamp in milliseconds since the unix epoch.
+pub(crate) fn build_produce_request(
+    values: Vec<(i64, Bytes)>,
+    topic: &TopicName,
+    produce_timeout_ms: i32,
+) -> Result<Message> {
+    build_keyed_produce_request(
+        values
+            .into_iter()
+            .map(|(timestamp, value)| (timestamp, None, value))
+            .collect(),
+        topic,
+        produce_timeout_ms,
+    )
+}
+
+/// Builds a request producing each value to partition 0 of the topic.
+/// Each value is paired with its timestamp in milliseconds since the unix epoch and its key, if any.
+pub(crate) fn build_keyed_produce_request(
+    values: Vec<(i64, Option<Bytes>, Bytes)>,
+    topic: &TopicName,
+    produce_timeout_ms: i32,
+) -> Result<Message> {
+    let records: Vec<Record> = values
+        .into_iter()
+        .enumerate()
+        .map(|(i, (timestamp, key, value))| Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: i as i64,
+            sequence: i as i32,
+            timestamp,
+            key,
+            value: Some(value),
+            headers: Default::default(),
+        })
+        .collect();
+
+    let mut encoded = BytesMut::new();
+    RecordBatchEncoder::encode(
+        &mut encoded,
+        records.iter(),
+        &RecordEncodeOptions {
+            version: 2,
+            compression: Compression::None,
+        },
+        None::<fn(&mut BytesMut, &mut BytesMut, Compression) -> Result<()>>,
+    )?;
+
+    Ok(Message::from_frame(Frame::Kafka(KafkaFrame::Request {
+        header: RequestHeader::default()
+            .with_request_api_key(ApiKey::ProduceKey as i16)
+            .with_request_api_version(RECORD_BATCH_V2_PRODUCE_VERSION),
+        body: RequestBody::Produce(
+            ProduceRequest::default()
+                .with_acks(-1)
+                .with_timeout_ms(produce_timeout_ms)
+                .with_topic_data(vec![TopicProduceData::default()
+                    .with_name(topic.clone())
+                    .with_partition_data(vec![PartitionProduceData::default()
+                        .with_index(0)
+                        .with_records(Some(encoded.freeze()))])]),
+        ),
+    })))
+}
+
+/// Returns an error if the response to a request built by [`build_produce_request`] is missing or reports a failure.
+pub(crate) fn check_produce_response(mut responses: Messages) -> Result<()> {
+    let response = responses
+        .pop()
+        .ok_or_else(|| anyhow!("No response was received for the produce request"))?;
+    match response.into_frame() {
+        Some(Frame::Kafka(KafkaFrame::Response {
+            body: ResponseBody::Produce(produce),
+            ..
+        })) => {
+            for topic in &produce.responses {
+                for partition in &topic.partition_responses {
+                    if partition.error_code != 0 {
+                        return Err(anyhow!(
+                            "Kafka returned error code {} for partition {} of topic {:?}",
+                            partition.error_code,
+                            partition.index,
+                            topic.name
+                        ));
+                    }
+                }
+            }
+            Ok(())
+        }
+        frame => Err(anyhow!("Unexpected response to produce request {frame:?}")),
+    }
+}
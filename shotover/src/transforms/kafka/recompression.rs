@@ -0,0 +1,231 @@
+use crate::frame::kafka::{
+    decode_records, encode_records, produce_supports_compression, KafkaFrame, RequestBody, StrBytes,
+};
+use crate::frame::{Frame, MessageType};
+use crate::message::Messages;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use kafka_protocol::messages::TopicName;
+use kafka_protocol::records::Compression;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaRecompressionConfig {
+    /// The compression that the records of produce requests are re-encoded with.
+    pub compression: KafkaCompression,
+    /// Only records produced to these topics are recompressed, when not provided records produced to any topic are recompressed.
+    pub topics: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum KafkaCompression {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl From<KafkaCompression> for Compression {
+    fn from(compression: KafkaCompression) -> Self {
+        match compression {
+            KafkaCompression::None => Compression::None,
+            KafkaCompression::Gzip => Compression::Gzip,
+            KafkaCompression::Snappy => Compression::Snappy,
+            KafkaCompression::Lz4 => Compression::Lz4,
+            KafkaCompression::Zstd => Compression::Zstd,
+        }
+    }
+}
+
+const NAME: &str = "KafkaRecompression";
+#[typetag::serde(name = "KafkaRecompression")]
+#[async_trait(?Send)]
+impl TransformConfig for KafkaRecompressionConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(KafkaRecompressionBuilder {
+            compression: self.compression.into(),
+            topics: self.topics.as_ref().map(|topics| {
+                topics
+                    .iter()
+                    .map(|x| TopicName(StrBytes::from_string(x.clone())))
+                    .collect()
+            }),
+            input_bytes: counter!("shotover_kafka_recompression_input_bytes_count", "chain" => chain_name.clone()),
+            output_bytes: counter!("shotover_kafka_recompression_output_bytes_count", "chain" => chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Kafka])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct KafkaRecompressionBuilder {
+    compression: Compression,
+    topics: Option<Vec<TopicName>>,
+    input_bytes: Counter,
+    output_bytes: Counter,
+}
+
+impl TransformBuilder for KafkaRecompressionBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(KafkaRecompression {
+            compression: self.compression,
+            topics: self.topics.clone(),
+            input_bytes: self.input_bytes.clone(),
+            output_bytes: self.output_bytes.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct KafkaRecompression {
+    compression: Compression,
+    topics: Option<Vec<TopicName>>,
+    input_bytes: Counter,
+    output_bytes: Counter,
+}
+
+impl KafkaRecompression {
+    fn applies_to_topic(&self, topic: &TopicName) -> bool {
+        self.topics
+            .as_ref()
+            .map(|topics| topics.contains(topic))
+            .unwrap_or(true)
+    }
+
+    fn recompress_requests(&self, requests: &mut Messages) {
+        for request in requests.iter_mut() {
+            let mut modified = false;
+            if let Some(Frame::Kafka(KafkaFrame::Request {
+                header,
+                body: RequestBody::Produce(produce),
+            })) = request.frame()
+            {
+                // Older produce versions cannot carry the configured compression, so the records are sent as the client encoded them.
+                if !produce_supports_compression(self.compression, header.request_api_version) {
+                    continue;
+                }
+                for topic in &mut produce.topic_data {
+                    if !self.applies_to_topic(&topic.name) {
+                        continue;
+                    }
+                    for partition in &mut topic.partition_data {
+                        let Some(bytes) = &partition.records else {
+                            continue;
+                        };
+                        let records = match decode_records(bytes) {
+                            Ok((_, compression)) if compression == self.compression => continue,
+                            Ok((records, _)) => records,
+                            Err(err) => {
+                                tracing::warn!(
+                                    "Failed to decode records for partition {} of topic {:?}, the records will not be recompressed: {err:?}",
+                                    partition.index,
+                                    topic.name
+                                );
+                                continue;
+                            }
+                        };
+                        match encode_records(&records, self.compression) {
+                            Ok(encoded) => {
+                                self.input_bytes.increment(bytes.len() as u64);
+                                self.output_bytes.increment(encoded.len() as u64);
+                                partition.records = Some(encoded);
+                                modified = true;
+                            }
+                            Err(err) => tracing::error!(
+                                "Failed to encode records for partition {} of topic {:?}: {err:?}",
+                                partition.index,
+                                topic.name
+                            ),
+                        }
+                    }
+                }
+            }
+            if modified {
+                request.invalidate_cache();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for KafkaRecompression {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform_request<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<()> {
+        self.recompress_requests(&mut chain_state.requests);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::kafka::record_batch_compression;
+    use crate::transforms::kafka::build_produce_request;
+    use bytes::Bytes;
+
+    fn recompression(compression: Compression) -> KafkaRecompression {
+        KafkaRecompression {
+            compression,
+            topics: None,
+            input_bytes: Counter::noop(),
+            output_bytes: Counter::noop(),
+        }
+    }
+
+    fn produced_compression(requests: &mut Messages) -> Compression {
+        match requests[0].frame() {
+            Some(Frame::Kafka(KafkaFrame::Request {
+                body: RequestBody::Produce(produce),
+                ..
+            })) => record_batch_compression(
+                produce.topic_data[0].partition_data[0]
+                    .records
+                    .as_ref()
+                    .unwrap(),
+            )
+            .unwrap(),
+            frame => panic!("unexpected frame {frame:?}"),
+        }
+    }
+
+    #[test]
+    fn test_recompress() {
+        let topic = TopicName(StrBytes::from_static_str("topic"));
+        let values = vec![(0, Bytes::from(vec![b'a'; 1000]))];
+        let mut requests = vec![build_produce_request(values, &topic, 1000).unwrap()];
+        assert_eq!(produced_compression(&mut requests), Compression::None);
+
+        recompression(Compression::Gzip).recompress_requests(&mut requests);
+        assert_eq!(produced_compression(&mut requests), Compression::Gzip);
+
+        // zstd is not supported by the produce version used by build_produce_request
+        recompression(Compression::Zstd).recompress_requests(&mut requests);
+        assert_eq!(produced_compression(&mut requests), Compression::Gzip);
+    }
+}
@@ -0,0 +1,544 @@
+use crate::frame::kafka::{
+    decode_records, encode_records, split_record_batches, KafkaFrame, RequestBody, ResponseBody,
+    StrBytes,
+};
+use crate::frame::{Frame, MessageType};
+use crate::message::{MessageIdMap, Messages};
+use crate::transforms::kafka::RECORD_BATCH_V2_PRODUCE_VERSION;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use kafka_protocol::messages::produce_response::{PartitionProduceResponse, TopicProduceResponse};
+use kafka_protocol::messages::TopicName;
+use kafka_protocol::records::Record;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaRecordMutationConfig {
+    /// Only records produced to these topics are modified, when not provided records produced to any topic are modified.
+    pub topics: Option<Vec<String>>,
+    /// Headers that are set on every record, overwriting any existing value.
+    pub set_headers: Option<HashMap<String, String>>,
+    /// Headers that are set on every record that does not already contain the header.
+    pub patch_headers: Option<HashMap<String, String>>,
+    /// Headers that are removed from every record.
+    pub remove_headers: Option<Vec<String>>,
+    pub rewrite_key: Option<KeyRewriteConfig>,
+    pub drop_records: Option<RecordFilterConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum KeyRewriteConfig {
+    /// Prepend the value to the key of every record, records without a key are given the value as their key.
+    AddPrefix(String),
+    /// Replace the key of every record with the value of the specified header.
+    /// Records without the header keep their original key.
+    FromHeader(String),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub enum RecordFilterConfig {
+    /// Drop records whose key starts with the value.
+    KeyPrefix(String),
+    /// Drop records containing a header with the specified name and value.
+    HeaderEquals { name: String, value: String },
+    /// Drop records that do not contain the specified header.
+    HeaderMissing(String),
+}
+
+/// [`KeyRewriteConfig`] converted into the types used by kafka records
+#[derive(Clone)]
+enum KeyRewrite {
+    AddPrefix(Bytes),
+    FromHeader(StrBytes),
+}
+
+impl From<&KeyRewriteConfig> for KeyRewrite {
+    fn from(config: &KeyRewriteConfig) -> Self {
+        match config {
+            KeyRewriteConfig::AddPrefix(prefix) => KeyRewrite::AddPrefix(prefix.clone().into()),
+            KeyRewriteConfig::FromHeader(name) => {
+                KeyRewrite::FromHeader(StrBytes::from_string(name.clone()))
+            }
+        }
+    }
+}
+
+/// [`RecordFilterConfig`] converted into the types used by kafka records
+#[derive(Clone)]
+enum RecordFilter {
+    KeyPrefix(Bytes),
+    HeaderEquals { name: StrBytes, value: Bytes },
+    HeaderMissing(StrBytes),
+}
+
+impl From<&RecordFilterConfig> for RecordFilter {
+    fn from(config: &RecordFilterConfig) -> Self {
+        match config {
+            RecordFilterConfig::KeyPrefix(prefix) => RecordFilter::KeyPrefix(prefix.clone().into()),
+            RecordFilterConfig::HeaderEquals { name, value } => RecordFilter::HeaderEquals {
+                name: StrBytes::from_string(name.clone()),
+                value: value.clone().into(),
+            },
+            RecordFilterConfig::HeaderMissing(name) => {
+                RecordFilter::HeaderMissing(StrBytes::from_string(name.clone()))
+            }
+        }
+    }
+}
+
+impl RecordFilter {
+    fn matches(&self, record: &Record) -> bool {
+        match self {
+            RecordFilter::KeyPrefix(prefix) => record
+                .key
+                .as_ref()
+                .map(|key| key.starts_with(prefix))
+                .unwrap_or(false),
+            RecordFilter::HeaderEquals { name, value } => record
+                .headers
+                .get(name)
+                .map(|header| header.as_ref() == Some(value))
+                .unwrap_or(false),
+            RecordFilter::HeaderMissing(name) => !record.headers.contains_key(name),
+        }
+    }
+}
+
+const NAME: &str = "KafkaRecordMutation";
+#[typetag::serde(name = "KafkaRecordMutation")]
+#[async_trait(?Send)]
+impl TransformConfig for KafkaRecordMutationConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let to_headers = |headers: &Option<HashMap<String, String>>| {
+            headers
+                .iter()
+                .flatten()
+                .map(|(k, v)| (StrBytes::from_string(k.clone()), Bytes::from(v.clone())))
+                .collect()
+        };
+        Ok(Box::new(KafkaRecordMutationBuilder {
+            mutation: RecordMutation {
+                topics: self.topics.as_ref().map(|topics| {
+                    topics
+                        .iter()
+                        .map(|x| TopicName(StrBytes::from_string(x.clone())))
+                        .collect()
+                }),
+                set_headers: to_headers(&self.set_headers),
+                patch_headers: to_headers(&self.patch_headers),
+                remove_headers: self
+                    .remove_headers
+                    .iter()
+                    .flatten()
+                    .map(|x| StrBytes::from_string(x.clone()))
+                    .collect(),
+                rewrite_key: self.rewrite_key.as_ref().map(KeyRewrite::from),
+                drop_records: self.drop_records.as_ref().map(RecordFilter::from),
+            },
+            dropped_records: counter!("shotover_kafka_dropped_records_count", "transform" => NAME),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Kafka])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Clone)]
+struct RecordMutation {
+    topics: Option<Vec<TopicName>>,
+    set_headers: Vec<(StrBytes, Bytes)>,
+    patch_headers: Vec<(StrBytes, Bytes)>,
+    remove_headers: Vec<StrBytes>,
+    rewrite_key: Option<KeyRewrite>,
+    drop_records: Option<RecordFilter>,
+}
+
+impl RecordMutation {
+    fn applies_to_topic(&self, topic: &TopicName) -> bool {
+        self.topics
+            .as_ref()
+            .map(|topics| topics.contains(topic))
+            .unwrap_or(true)
+    }
+
+    /// Modifies the records of a single record batch in place and returns the number of records that were dropped
+    fn apply(&self, records: &mut Vec<Record>) -> usize {
+        let original_len = records.len();
+        if let Some(filter) = &self.drop_records {
+            // The broker checks that the records of idempotent and transactional producers have consecutive sequence numbers,
+            // so records can not be dropped from their batches without the produce request being rejected.
+            if records
+                .first()
+                .is_some_and(|record| record.producer_id >= 0)
+            {
+                if records.iter().any(|record| filter.matches(record)) {
+                    tracing::warn!(
+                        "Records of an idempotent or transactional producer were not dropped as that would break the sequence numbers of the batch"
+                    );
+                }
+            } else {
+                records.retain(|record| !filter.matches(record));
+            }
+        }
+
+        for record in records.iter_mut() {
+            for name in &self.remove_headers {
+                record.headers.shift_remove(name);
+            }
+            for (name, value) in &self.patch_headers {
+                if !record.headers.contains_key(name) {
+                    record.headers.insert(name.clone(), Some(value.clone()));
+                }
+            }
+            for (name, value) in &self.set_headers {
+                record.headers.insert(name.clone(), Some(value.clone()));
+            }
+            match &self.rewrite_key {
+                Some(KeyRewrite::AddPrefix(prefix)) => {
+                    let mut key = BytesMut::from(prefix.as_ref());
+                    if let Some(original) = &record.key {
+                        key.extend_from_slice(original);
+                    }
+                    record.key = Some(key.freeze());
+                }
+                Some(KeyRewrite::FromHeader(name)) => {
+                    if let Some(Some(value)) = record.headers.get(name) {
+                        record.key = Some(value.clone());
+                    }
+                }
+                None => {}
+            }
+        }
+
+        original_len - records.len()
+    }
+
+    /// Applies the mutation to each record batch in `records`.
+    /// Returns the new records and the number of records dropped, or `None` if no record was modified.
+    ///
+    /// Only modified batches are encoded again, each with its original compression,
+    /// since the producer id, epoch and sequence numbers in the header of each batch must be preserved.
+    fn apply_to_batches(&self, records: &Bytes) -> Result<Option<(Bytes, usize)>> {
+        let mut modified = false;
+        let mut dropped = 0;
+        let mut encoded = BytesMut::with_capacity(records.len());
+        for batch in split_record_batches(records)? {
+            let (mut records, compression) = decode_records(&batch)?;
+            let original = records.clone();
+            dropped += self.apply(&mut records);
+            if records == original {
+                encoded.extend_from_slice(&batch);
+            } else {
+                modified = true;
+                // A batch with every record dropped is removed entirely
+                if !records.is_empty() {
+                    encoded.extend_from_slice(&encode_records(&records, compression)?);
+                }
+            }
+        }
+        Ok(modified.then(|| (encoded.freeze(), dropped)))
+    }
+}
+
+struct KafkaRecordMutationBuilder {
+    mutation: RecordMutation,
+    dropped_records: Counter,
+}
+
+impl TransformBuilder for KafkaRecordMutationBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(KafkaRecordMutation {
+            mutation: self.mutation.clone(),
+            dropped_records: self.dropped_records.clone(),
+            removed_partitions: Default::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct KafkaRecordMutation {
+    mutation: RecordMutation,
+    dropped_records: Counter,
+    /// Partitions that had all of their records dropped are removed from the request entirely.
+    /// The client still expects a response for these partitions so we store them here to generate a response later.
+    removed_partitions: MessageIdMap<Vec<(TopicName, i32)>>,
+}
+
+impl KafkaRecordMutation {
+    fn mutate_requests(&mut self, requests: &mut Messages) {
+        for request in requests.iter_mut() {
+            let id = request.id();
+            let mut modified = false;
+            if let Some(Frame::Kafka(KafkaFrame::Request {
+                header,
+                body: RequestBody::Produce(produce),
+            })) = request.frame()
+            {
+                if header.request_api_version < RECORD_BATCH_V2_PRODUCE_VERSION {
+                    continue;
+                }
+                let expects_response = produce.acks != 0;
+                let mut removed = vec![];
+                for topic in &mut produce.topic_data {
+                    if !self.mutation.applies_to_topic(&topic.name) {
+                        continue;
+                    }
+                    topic.partition_data.retain_mut(|partition| {
+                        let Some(bytes) = &partition.records else {
+                            return true;
+                        };
+                        let (records, dropped) = match self.mutation.apply_to_batches(bytes) {
+                            Ok(Some(mutated)) => mutated,
+                            Ok(None) => return true,
+                            Err(err) => {
+                                tracing::warn!(
+                                    "Failed to modify records for partition {} of topic {:?}, the records will be left unmodified: {err:?}",
+                                    partition.index,
+                                    topic.name
+                                );
+                                return true;
+                            }
+                        };
+                        self.dropped_records.increment(dropped as u64);
+                        modified = true;
+
+                        if records.is_empty() {
+                            removed.push((topic.name.clone(), partition.index));
+                            return false;
+                        }
+                        partition.records = Some(records);
+                        true
+                    });
+                }
+                if expects_response && !removed.is_empty() {
+                    self.removed_partitions.insert(id, removed);
+                }
+            }
+            if modified {
+                request.invalidate_cache();
+            }
+        }
+    }
+
+    fn restore_removed_partitions(&mut self, responses: &mut Messages) {
+        if self.removed_partitions.is_empty() {
+            return;
+        }
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            let Some(removed) = self.removed_partitions.remove(&request_id) else {
+                continue;
+            };
+            if let Some(Frame::Kafka(KafkaFrame::Response {
+                body: ResponseBody::Produce(produce),
+                ..
+            })) = response.frame()
+            {
+                for (topic_name, index) in removed {
+                    let partition = PartitionProduceResponse::default()
+                        .with_index(index)
+                        .with_base_offset(-1)
+                        .with_log_append_time_ms(-1)
+                        .with_log_start_offset(-1);
+                    match produce
+                        .responses
+                        .iter_mut()
+                        .find(|topic| topic.name == topic_name)
+                    {
+                        Some(topic) => topic.partition_responses.push(partition),
+                        None => produce.responses.push(
+                            TopicProduceResponse::default()
+                                .with_name(topic_name)
+                                .with_partition_responses(vec![partition]),
+                        ),
+                    }
+                }
+                response.invalidate_cache();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for KafkaRecordMutation {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        self.mutate_requests(&mut chain_state.requests);
+        let mut responses = chain_state.call_next_transform().await?;
+        self.restore_removed_partitions(&mut responses);
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kafka_protocol::records::{Compression, TimestampType};
+    use pretty_assertions::assert_eq;
+
+    fn record(key: &'static str, headers: &[(&'static str, &'static str)]) -> Record {
+        Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset: 0,
+            sequence: 0,
+            timestamp: 0,
+            key: Some(Bytes::from_static(key.as_bytes())),
+            value: None,
+            headers: headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        StrBytes::from_static_str(k),
+                        Some(Bytes::from_static(v.as_bytes())),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn no_mutation() -> RecordMutation {
+        RecordMutation {
+            topics: None,
+            set_headers: vec![],
+            patch_headers: vec![],
+            remove_headers: vec![],
+            rewrite_key: None,
+            drop_records: None,
+        }
+    }
+
+    #[test]
+    fn set_and_patch_headers() {
+        let mutation = RecordMutation {
+            set_headers: vec![(StrBytes::from_static_str("tenant"), "a".into())],
+            patch_headers: vec![(StrBytes::from_static_str("source"), "legacy".into())],
+            remove_headers: vec![StrBytes::from_static_str("secret")],
+            ..no_mutation()
+        };
+        let mut records = vec![
+            record("1", &[("tenant", "b"), ("secret", "x")]),
+            record("2", &[("source", "new")]),
+        ];
+        assert_eq!(mutation.apply(&mut records), 0);
+        assert_eq!(
+            records,
+            vec![
+                record("1", &[("tenant", "a"), ("source", "legacy")]),
+                record("2", &[("source", "new"), ("tenant", "a")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrite_key() {
+        let mutation = RecordMutation {
+            rewrite_key: Some(KeyRewrite::AddPrefix("prod:".into())),
+            ..no_mutation()
+        };
+        let mut records = vec![record("1", &[])];
+        mutation.apply(&mut records);
+        assert_eq!(records, vec![record("prod:1", &[])]);
+
+        let mutation = RecordMutation {
+            rewrite_key: Some(KeyRewrite::FromHeader(StrBytes::from_static_str("id"))),
+            ..no_mutation()
+        };
+        let mut records = vec![record("1", &[("id", "2")]), record("3", &[])];
+        mutation.apply(&mut records);
+        assert_eq!(records, vec![record("2", &[("id", "2")]), record("3", &[])]);
+    }
+
+    #[test]
+    fn drop_records() {
+        let mutation = RecordMutation {
+            drop_records: Some(RecordFilter::HeaderEquals {
+                name: StrBytes::from_static_str("env"),
+                value: "test".into(),
+            }),
+            ..no_mutation()
+        };
+        let mut records = vec![
+            record("1", &[("env", "test")]),
+            record("2", &[("env", "prod")]),
+            record("3", &[]),
+        ];
+        assert_eq!(mutation.apply(&mut records), 1);
+        assert_eq!(
+            records,
+            vec![record("2", &[("env", "prod")]), record("3", &[])]
+        );
+    }
+
+    #[test]
+    fn apply_to_batches() {
+        let mutation = RecordMutation {
+            drop_records: Some(RecordFilter::KeyPrefix("test:".into())),
+            ..no_mutation()
+        };
+        let unmodified = encode_records(&[record("1", &[])], Compression::Gzip).unwrap();
+        let mut idempotent = record("test:2", &[]);
+        idempotent.producer_id = 1;
+        let idempotent = encode_records(&[idempotent], Compression::None).unwrap();
+        let filtered =
+            encode_records(&[record("test:3", &[]), record("4", &[])], Compression::Lz4).unwrap();
+        let concat = |batches: &[&Bytes]| -> Bytes {
+            batches
+                .iter()
+                .flat_map(|batch| batch.iter().copied())
+                .collect()
+        };
+
+        // records of idempotent producers are never dropped
+        assert_eq!(
+            mutation
+                .apply_to_batches(&concat(&[&unmodified, &idempotent]))
+                .unwrap(),
+            None
+        );
+
+        let (records, dropped) = mutation
+            .apply_to_batches(&concat(&[&unmodified, &idempotent, &filtered]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(dropped, 1);
+        let batches = split_record_batches(&records).unwrap();
+        assert_eq!(batches[..2], [unmodified, idempotent]);
+        let (records, compression) = decode_records(&batches[2]).unwrap();
+        assert_eq!(compression, Compression::Lz4);
+        assert_eq!(records, vec![record("4", &[])]);
+    }
+}
@@ -4,6 +4,7 @@ use crate::connection::SinkConnection;
 use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
 use crate::frame::Frame;
 use crate::message::Message;
+use crate::tcp::TcpConfig;
 use crate::tls::TlsConnector;
 use crate::transforms::kafka::sink_cluster::scram_over_mtls::OriginalScramState;
 use crate::transforms::kafka::sink_cluster::SASL_SCRAM_MECHANISMS;
@@ -25,6 +26,7 @@ use tokio::sync::Notify;
 
 pub struct ConnectionFactory {
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
     auth_requests: Vec<Message>,
@@ -34,12 +36,14 @@ pub struct ConnectionFactory {
 impl ConnectionFactory {
     pub fn new(
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
         connect_timeout: Duration,
         read_timeout: Option<Duration>,
         force_run_chain: Arc<Notify>,
     ) -> Self {
         ConnectionFactory {
             tls,
+            tcp,
             connect_timeout,
             auth_requests: vec![],
             force_run_chain,
@@ -62,6 +66,7 @@ impl ConnectionFactory {
             address,
             codec,
             &self.tls,
+            &self.tcp,
             self.connect_timeout,
             self.force_run_chain.clone(),
             self.read_timeout,
@@ -81,6 +86,7 @@ impl ConnectionFactory {
             address,
             codec,
             &self.tls,
+            &self.tcp,
             self.connect_timeout,
             self.force_run_chain.clone(),
             self.read_timeout,
@@ -1,6 +1,7 @@
 use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
 use crate::frame::{Frame, MessageType};
 use crate::message::{Message, Messages};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
 use crate::transforms::kafka::sink_cluster::shotover_node::start_shotover_peers_check;
 use crate::transforms::{
@@ -95,6 +96,8 @@ pub struct KafkaSinkClusterConfig {
     pub check_shotover_peers_delay_ms: Option<u64>,
     pub tls: Option<TlsConnectorConfig>,
     pub authorize_scram_over_mtls: Option<AuthorizeScramOverMtlsConfig>,
+    /// Tuning of the TCP connections to the kafka brokers.
+    pub tcp: Option<TcpConfig>,
 }
 
 const NAME: &str = "KafkaSinkCluster";
@@ -155,6 +158,7 @@ impl TransformConfig for KafkaSinkClusterConfig {
             self.read_timeout,
             self.check_shotover_peers_delay_ms,
             tls,
+            self.tcp.clone().unwrap_or_default(),
         )?))
     }
 
@@ -183,6 +187,7 @@ struct KafkaSinkClusterBuilder {
     nodes_shared: Arc<RwLock<Vec<KafkaNode>>>,
     authorize_scram_over_mtls: Option<AuthorizeScramOverMtlsBuilder>,
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     out_of_rack_requests: Counter,
 }
 
@@ -199,6 +204,7 @@ impl KafkaSinkClusterBuilder {
         timeout: Option<u64>,
         check_shotover_peers_delay_ms: Option<u64>,
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
     ) -> Result<KafkaSinkClusterBuilder> {
         let read_timeout = timeout.map(Duration::from_secs);
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
@@ -235,6 +241,7 @@ impl KafkaSinkClusterBuilder {
             nodes_shared: Arc::new(RwLock::new(vec![])),
             out_of_rack_requests: counter!("shotover_out_of_rack_requests_count", "chain" => chain_name, "transform" => NAME),
             tls,
+            tcp,
         })
     }
 }
@@ -258,6 +265,7 @@ impl TransformBuilder for KafkaSinkClusterBuilder {
             auth_complete: false,
             connection_factory: ConnectionFactory::new(
                 self.tls.clone(),
+                self.tcp.clone(),
                 self.connect_timeout,
                 self.read_timeout,
                 transform_context.force_run_chain,
@@ -1,6 +1,7 @@
 use super::kafka_node::{ConnectionFactory, KafkaAddress};
 use crate::{
     connection::SinkConnection,
+    tcp::TcpConfig,
     tls::{TlsConnector, TlsConnectorConfig},
 };
 use anyhow::{Context, Result};
@@ -216,6 +217,7 @@ impl AuthorizeScramOverMtlsConfig {
     ) -> Result<AuthorizeScramOverMtlsBuilder> {
         let mtls_connection_factory = ConnectionFactory::new(
             Some(TlsConnector::new(&self.tls)?),
+            TcpConfig::default(),
             connect_timeout,
             read_timeout,
             Arc::new(Notify::new()),
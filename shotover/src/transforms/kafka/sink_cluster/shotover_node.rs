@@ -1,4 +1,4 @@
-use crate::tcp::tcp_stream;
+use crate::tcp::{tcp_stream, TcpConfig};
 use crate::transforms::kafka::sink_cluster::kafka_node::KafkaAddress;
 use atomic_enum::atomic_enum;
 use kafka_protocol::messages::BrokerId;
@@ -105,6 +105,7 @@ async fn check_shotover_peers(
                     shotover_peer.address_for_peers.host.as_str(),
                     shotover_peer.address_for_peers.port as u16,
                 ),
+                &TcpConfig::default(),
             )
             .await;
             match tcp_stream {
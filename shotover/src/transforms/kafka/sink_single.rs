@@ -3,6 +3,7 @@ use crate::connection::SinkConnection;
 use crate::frame::kafka::{KafkaFrame, RequestBody, ResponseBody};
 use crate::frame::{Frame, MessageType};
 use crate::message::Messages;
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
 use crate::transforms::{
     ChainState, Transform, TransformBuilder, TransformContextBuilder, TransformContextConfig,
@@ -25,6 +26,8 @@ pub struct KafkaSinkSingleConfig {
     pub connect_timeout_ms: u64,
     pub read_timeout: Option<u64>,
     pub tls: Option<TlsConnectorConfig>,
+    /// Tuning of the TCP connections to the kafka instance.
+    pub tcp: Option<TcpConfig>,
 }
 
 const NAME: &str = "KafkaSinkSingle";
@@ -42,6 +45,7 @@ impl TransformConfig for KafkaSinkSingleConfig {
             self.connect_timeout_ms,
             self.read_timeout,
             tls,
+            self.tcp.clone().unwrap_or_default(),
         )))
     }
 
@@ -60,6 +64,7 @@ struct KafkaSinkSingleBuilder {
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
 }
 
 impl KafkaSinkSingleBuilder {
@@ -69,6 +74,7 @@ impl KafkaSinkSingleBuilder {
         connect_timeout_ms: u64,
         timeout: Option<u64>,
         tls: Option<TlsConnector>,
+        tcp: TcpConfig,
     ) -> KafkaSinkSingleBuilder {
         let receive_timeout = timeout.map(Duration::from_secs);
 
@@ -77,6 +83,7 @@ impl KafkaSinkSingleBuilder {
             connect_timeout: Duration::from_millis(connect_timeout_ms),
             read_timeout: receive_timeout,
             tls,
+            tcp,
         }
     }
 }
@@ -88,6 +95,7 @@ impl TransformBuilder for KafkaSinkSingleBuilder {
             address_port: self.address_port,
             connect_timeout: self.connect_timeout,
             tls: self.tls.clone(),
+            tcp: self.tcp.clone(),
             read_timeout: self.read_timeout,
             force_run_chain: transform_context.force_run_chain,
         })
@@ -108,6 +116,7 @@ struct KafkaSinkSingle {
     connect_timeout: Duration,
     read_timeout: Option<Duration>,
     tls: Option<TlsConnector>,
+    tcp: TcpConfig,
     force_run_chain: Arc<Notify>,
 }
 
@@ -129,6 +138,7 @@ impl Transform for KafkaSinkSingle {
                     address,
                     codec,
                     &self.tls,
+                    &self.tcp,
                     self.connect_timeout,
                     self.force_run_chain.clone(),
                     self.read_timeout,
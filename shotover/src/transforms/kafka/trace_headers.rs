@@ -0,0 +1,473 @@
+use crate::frame::kafka::{
+    decode_records, encode_records, KafkaFrame, RequestBody, ResponseBody, StrBytes,
+};
+use crate::frame::{Frame, MessageType};
+use crate::message::{MessageId, Messages};
+use crate::transforms::kafka::RECORD_BATCH_V2_PRODUCE_VERSION;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use kafka_protocol::messages::TopicName;
+use kafka_protocol::records::Record;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+/// Identifies this shotover process when `instance_id` is not configured.
+static DEFAULT_INSTANCE_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaTraceHeadersConfig {
+    /// Only records produced to these topics are given headers, when not provided records produced to any topic are given headers.
+    pub topics: Option<Vec<String>>,
+    /// The header set to the trace id of the request.
+    pub trace_id_header: Option<String>,
+    /// The header set to the id of this shotover instance.
+    pub instance_id_header: Option<String>,
+    /// The value of `instance_id_header`, defaults to a random UUID generated when shotover starts.
+    pub instance_id: Option<String>,
+    /// The header set to the identity of the client, as established by an auth termination transform or the client's TLS certificate.
+    /// Records produced by clients without an identity are not given this header.
+    pub tenant_header: Option<String>,
+    /// Remove the configured headers from the records of fetch responses so that consumers never see them.
+    #[serde(default)]
+    pub strip_from_fetch: bool,
+}
+
+const NAME: &str = "KafkaTraceHeaders";
+#[typetag::serde(name = "KafkaTraceHeaders")]
+#[async_trait(?Send)]
+impl TransformConfig for KafkaTraceHeadersConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let to_name =
+            |name: &Option<String>| name.as_ref().map(|x| StrBytes::from_string(x.clone()));
+        Ok(Box::new(KafkaTraceHeadersBuilder {
+            headers: TraceHeaders {
+                topics: self.topics.as_ref().map(|topics| {
+                    topics
+                        .iter()
+                        .map(|x| TopicName(StrBytes::from_string(x.clone())))
+                        .collect()
+                }),
+                trace_id: to_name(&self.trace_id_header),
+                instance_id: to_name(&self.instance_id_header),
+                instance_id_value: Bytes::from(
+                    self.instance_id
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_INSTANCE_ID.clone()),
+                ),
+                tenant: to_name(&self.tenant_header),
+            },
+            instance_id_configured: self.instance_id.is_some(),
+            strip_from_fetch: self.strip_from_fetch,
+            stripped_records: counter!("shotover_kafka_trace_headers_stripped_records_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Kafka])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+#[derive(Clone)]
+struct TraceHeaders {
+    topics: Option<Vec<TopicName>>,
+    trace_id: Option<StrBytes>,
+    instance_id: Option<StrBytes>,
+    instance_id_value: Bytes,
+    tenant: Option<StrBytes>,
+}
+
+impl TraceHeaders {
+    fn applies_to_topic(&self, topic: &TopicName) -> bool {
+        self.topics
+            .as_ref()
+            .map(|topics| topics.contains(topic))
+            .unwrap_or(true)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &StrBytes> {
+        [&self.trace_id, &self.instance_id, &self.tenant]
+            .into_iter()
+            .flatten()
+    }
+
+    /// Sets the headers on every record, overwriting any existing value.
+    fn inject(&self, records: &mut [Record], trace_id: &Bytes, tenant: Option<&Bytes>) {
+        for record in records {
+            if let Some(name) = &self.trace_id {
+                record.headers.insert(name.clone(), Some(trace_id.clone()));
+            }
+            if let Some(name) = &self.instance_id {
+                record
+                    .headers
+                    .insert(name.clone(), Some(self.instance_id_value.clone()));
+            }
+            if let (Some(name), Some(tenant)) = (&self.tenant, tenant) {
+                record.headers.insert(name.clone(), Some(tenant.clone()));
+            }
+        }
+    }
+
+    /// Removes the headers from every record and returns the number of records that contained any of them
+    fn strip(&self, records: &mut [Record]) -> usize {
+        let mut stripped = 0;
+        for record in records {
+            let mut modified = false;
+            for name in self.names() {
+                modified |= record.headers.shift_remove(name).is_some();
+            }
+            if modified {
+                stripped += 1;
+            }
+        }
+        stripped
+    }
+}
+
+/// Returns the trace id of the request, which is the id of the current span when one is enabled, otherwise the id of the request.
+fn trace_id(request_id: MessageId) -> Bytes {
+    match tracing::Span::current().id() {
+        Some(id) => format!("{:016x}", id.into_u64()).into(),
+        None => format!("{request_id:032x}").into(),
+    }
+}
+
+/// Fetched records are decoded into a flat list of records, losing the boundaries between record batches.
+/// They can only be re-encoded into a single batch without changing their meaning when
+/// they share the batch level fields and none of them are control records.
+fn can_reencode_as_single_batch(records: &[Record]) -> bool {
+    let Some(first) = records.first() else {
+        return false;
+    };
+    records.iter().all(|record| {
+        !record.control
+            && record.transactional == first.transactional
+            && record.producer_id == first.producer_id
+            && record.producer_epoch == first.producer_epoch
+            && record.partition_leader_epoch == first.partition_leader_epoch
+            && record.timestamp_type == first.timestamp_type
+    })
+}
+
+struct KafkaTraceHeadersBuilder {
+    headers: TraceHeaders,
+    instance_id_configured: bool,
+    strip_from_fetch: bool,
+    stripped_records: Counter,
+}
+
+impl TransformBuilder for KafkaTraceHeadersBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(KafkaTraceHeaders {
+            headers: self.headers.clone(),
+            strip_from_fetch: self.strip_from_fetch,
+            stripped_records: self.stripped_records.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        let names: Vec<&StrBytes> = self.headers.names().collect();
+        if names.is_empty() {
+            errors.push(
+                "at least one of trace_id_header, instance_id_header or tenant_header must be configured"
+                    .to_owned(),
+            );
+        }
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                errors.push(format!(
+                    "the header {:?} is configured more than once",
+                    name.as_str()
+                ));
+            }
+        }
+        if self.instance_id_configured && self.headers.instance_id.is_none() {
+            errors.push("instance_id is configured but instance_id_header is not".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", NAME));
+            for error in errors.iter_mut().skip(1) {
+                *error = format!("  {error}");
+            }
+        }
+        errors
+    }
+}
+
+struct KafkaTraceHeaders {
+    headers: TraceHeaders,
+    strip_from_fetch: bool,
+    stripped_records: Counter,
+}
+
+impl KafkaTraceHeaders {
+    fn inject_headers(&self, requests: &mut Messages, tenant: Option<&Bytes>) {
+        for request in requests.iter_mut() {
+            let request_id = request.id();
+            let mut modified = false;
+            if let Some(Frame::Kafka(KafkaFrame::Request {
+                header,
+                body: RequestBody::Produce(produce),
+            })) = request.frame()
+            {
+                if header.request_api_version < RECORD_BATCH_V2_PRODUCE_VERSION {
+                    continue;
+                }
+                let trace_id = trace_id(request_id);
+                for topic in &mut produce.topic_data {
+                    if !self.headers.applies_to_topic(&topic.name) {
+                        continue;
+                    }
+                    for partition in &mut topic.partition_data {
+                        let Some(bytes) = &partition.records else {
+                            continue;
+                        };
+                        let (mut records, compression) = match decode_records(bytes) {
+                            Ok(decoded) => decoded,
+                            Err(err) => {
+                                tracing::warn!(
+                                    "Failed to decode records for partition {} of topic {:?}, the records will not be given trace headers: {err:?}",
+                                    partition.index,
+                                    topic.name
+                                );
+                                continue;
+                            }
+                        };
+                        self.headers.inject(&mut records, &trace_id, tenant);
+                        match encode_records(&records, compression) {
+                            Ok(encoded) => {
+                                partition.records = Some(encoded);
+                                modified = true;
+                            }
+                            Err(err) => tracing::error!(
+                                "Failed to encode records for partition {} of topic {:?}: {err:?}",
+                                partition.index,
+                                topic.name
+                            ),
+                        }
+                    }
+                }
+            }
+            if modified {
+                request.invalidate_cache();
+            }
+        }
+    }
+
+    fn strip_headers(&self, responses: &mut Messages) {
+        for response in responses.iter_mut() {
+            let mut modified = false;
+            if let Some(Frame::Kafka(KafkaFrame::Response {
+                body: ResponseBody::Fetch(fetch),
+                ..
+            })) = response.frame()
+            {
+                for topic in &mut fetch.responses {
+                    for partition in &mut topic.partitions {
+                        let Some(bytes) = &partition.records else {
+                            continue;
+                        };
+                        // The last record batch of fetched records is frequently truncated, which fails to decode.
+                        // Such records are passed through with their headers intact.
+                        let Ok((mut records, compression)) = decode_records(bytes) else {
+                            continue;
+                        };
+                        if !can_reencode_as_single_batch(&records) {
+                            continue;
+                        }
+                        let stripped = self.headers.strip(&mut records);
+                        if stripped == 0 {
+                            continue;
+                        }
+                        match encode_records(&records, compression) {
+                            Ok(encoded) => {
+                                partition.records = Some(encoded);
+                                self.stripped_records.increment(stripped as u64);
+                                modified = true;
+                            }
+                            Err(err) => tracing::error!(
+                                "Failed to encode records for partition {} of topic {:?}: {err:?}",
+                                partition.partition_index,
+                                topic.topic
+                            ),
+                        }
+                    }
+                }
+            }
+            if modified {
+                response.invalidate_cache();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for KafkaTraceHeaders {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let tenant = chain_state
+            .session
+            .identity()
+            .map(|x| Bytes::from(x.to_owned()));
+        self.inject_headers(&mut chain_state.requests, tenant.as_ref());
+        let mut responses = chain_state.call_next_transform().await?;
+        if self.strip_from_fetch {
+            self.strip_headers(&mut responses);
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kafka_protocol::records::TimestampType;
+    use pretty_assertions::assert_eq;
+
+    fn record(offset: i64, headers: &[(&'static str, &'static str)]) -> Record {
+        Record {
+            transactional: false,
+            control: false,
+            partition_leader_epoch: 0,
+            producer_id: -1,
+            producer_epoch: -1,
+            timestamp_type: TimestampType::Creation,
+            offset,
+            sequence: offset as i32,
+            timestamp: 0,
+            key: None,
+            value: Some(Bytes::from_static(b"value")),
+            headers: headers
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        StrBytes::from_static_str(k),
+                        Some(Bytes::from_static(v.as_bytes())),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn trace_headers() -> TraceHeaders {
+        TraceHeaders {
+            topics: None,
+            trace_id: Some(StrBytes::from_static_str("x-trace-id")),
+            instance_id: Some(StrBytes::from_static_str("x-shotover")),
+            instance_id_value: Bytes::from_static(b"shotover-1"),
+            tenant: Some(StrBytes::from_static_str("x-tenant")),
+        }
+    }
+
+    #[test]
+    fn inject_and_strip() {
+        let headers = trace_headers();
+        let mut records = vec![
+            record(0, &[("x-trace-id", "old")]),
+            record(1, &[("app", "a")]),
+        ];
+        headers.inject(&mut records, &Bytes::from_static(b"abc"), None);
+        assert_eq!(
+            records,
+            vec![
+                record(0, &[("x-trace-id", "abc"), ("x-shotover", "shotover-1")]),
+                record(
+                    1,
+                    &[
+                        ("app", "a"),
+                        ("x-trace-id", "abc"),
+                        ("x-shotover", "shotover-1")
+                    ]
+                ),
+            ]
+        );
+
+        headers.inject(
+            &mut records,
+            &Bytes::from_static(b"def"),
+            Some(&Bytes::from_static(b"team-a")),
+        );
+        assert_eq!(
+            records[0],
+            record(
+                0,
+                &[
+                    ("x-trace-id", "def"),
+                    ("x-shotover", "shotover-1"),
+                    ("x-tenant", "team-a")
+                ]
+            )
+        );
+
+        records.push(record(2, &[]));
+        assert_eq!(headers.strip(&mut records), 2);
+        assert_eq!(
+            records,
+            vec![record(0, &[]), record(1, &[("app", "a")]), record(2, &[])]
+        );
+    }
+
+    #[test]
+    fn reencode_fetched_records() {
+        assert!(can_reencode_as_single_batch(&[
+            record(5, &[]),
+            record(6, &[])
+        ]));
+
+        let mut transactional = record(7, &[]);
+        transactional.transactional = true;
+        transactional.producer_id = 1;
+        assert!(!can_reencode_as_single_batch(&[
+            record(5, &[]),
+            transactional
+        ]));
+        assert!(!can_reencode_as_single_batch(&[]));
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = KafkaTraceHeadersBuilder {
+            headers: TraceHeaders {
+                instance_id: None,
+                tenant: Some(StrBytes::from_static_str("x-trace-id")),
+                ..trace_headers()
+            },
+            instance_id_configured: true,
+            strip_from_fetch: false,
+            stripped_records: Counter::noop(),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "KafkaTraceHeaders:",
+                "  the header \"x-trace-id\" is configured more than once",
+                "  instance_id is configured but instance_id_header is not",
+            ]
+        );
+    }
+}
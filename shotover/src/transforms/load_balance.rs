@@ -1,12 +1,20 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::config::chain::TransformChainConfig;
-use crate::message::Messages;
-use crate::transforms::chain::{BufferedChain, TransformChainBuilder};
+use crate::frame::Frame;
+use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::transforms::chain::{BufferedChain, TransformChain, TransformChainBuilder};
+use crate::transforms::util::ordered_responses::OrderedResponses;
+use crate::transforms::util::setup_replay::{is_setup_request, SetupReplay};
 use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
 use anyhow::Result;
 use async_trait::async_trait;
+use fnv::FnvHasher;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -112,3 +120,640 @@ impl Transform for ConnectionBalanceAndPool {
             .await
     }
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LoadBalanceConfig {
+    /// How each request picks the chain it is sent to.
+    pub strategy: LoadBalanceStrategy,
+    /// The chains that requests are distributed across, these should all be equivalent e.g. each one sending to a different replica.
+    pub chains: Vec<TransformChainConfig>,
+    /// The number of consecutive failures after which a chain is considered unhealthy, defaults to 3.
+    pub failure_threshold: Option<u32>,
+    /// How long an unhealthy chain is avoided for before it is tried again, defaults to 10000.
+    pub recovery_interval_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub enum LoadBalanceStrategy {
+    /// Each request is sent to the next chain in turn.
+    RoundRobin,
+    /// Each request is sent to the chain with the fewest requests awaiting a response across all connections.
+    LeastOutstandingRequests,
+    /// Requests are sent to a chain determined by hashing the key they access, so that requests for the same key always go to the same chain.
+    /// Requests that do not access a key are sent round robin.
+    ConsistentHash,
+}
+
+/// The number of points each chain is given on the consistent hashing ring, more points spreads keys more evenly between chains.
+const VIRTUAL_NODES_PER_CHAIN: u32 = 100;
+
+const LOAD_BALANCE_NAME: &str = "LoadBalance";
+#[typetag::serde(name = "LoadBalance")]
+#[async_trait(?Send)]
+impl TransformConfig for LoadBalanceConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let mut chains = Vec::with_capacity(self.chains.len());
+        for (i, chain) in self.chains.iter().enumerate() {
+            chains.push(
+                chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: format!("load_balance_chain_{i}"),
+                        up_chain_protocol: transform_context.up_chain_protocol,
+                    })
+                    .await?,
+            );
+        }
+
+        Ok(Box::new(LoadBalanceBuilder {
+            strategy: self.strategy,
+            health: Arc::new(chains.iter().map(|_| ChainHealth::default()).collect()),
+            ring: Arc::new(hash_ring(chains.len())),
+            chains: Arc::new(chains),
+            failure_threshold: self.failure_threshold.unwrap_or(3),
+            recovery_interval: Duration::from_millis(self.recovery_interval_ms.unwrap_or(10000)),
+            in_order: transform_context.up_chain_protocol.is_inorder(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+/// Returns the points of the consistent hashing ring sorted by their position on the ring, along with the index of the chain they belong to.
+fn hash_ring(chain_count: usize) -> Vec<(u64, usize)> {
+    let mut ring: Vec<(u64, usize)> = (0..chain_count)
+        .flat_map(|chain| {
+            (0..VIRTUAL_NODES_PER_CHAIN).map(move |node| (hash(&(chain, node)), chain))
+        })
+        .collect();
+    ring.sort();
+    ring
+}
+
+fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = FnvHasher::default();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The health of a chain, shared by every connection so that a failing upstream is avoided by all of them.
+#[derive(Default)]
+struct ChainHealth {
+    consecutive_failures: AtomicU32,
+    last_failure: std::sync::Mutex<Option<Instant>>,
+    /// The number of requests sent to the chain that have not yet received a response
+    outstanding_requests: AtomicUsize,
+}
+
+impl ChainHealth {
+    /// An unhealthy chain is considered healthy again once `recovery_interval` has passed since its last failure,
+    /// if the next request to it fails it is avoided for another `recovery_interval`.
+    fn is_healthy(&self, failure_threshold: u32, recovery_interval: Duration) -> bool {
+        self.consecutive_failures.load(Ordering::Relaxed) < failure_threshold
+            || self
+                .last_failure
+                .lock()
+                .unwrap()
+                .map(|x| x.elapsed() >= recovery_interval)
+                .unwrap_or(true)
+    }
+
+    fn record_failure(&self) {
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        *self.last_failure.lock().unwrap() = Some(Instant::now());
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+}
+
+struct LoadBalanceBuilder {
+    strategy: LoadBalanceStrategy,
+    chains: Arc<Vec<TransformChainBuilder>>,
+    health: Arc<Vec<ChainHealth>>,
+    ring: Arc<Vec<(u64, usize)>>,
+    failure_threshold: u32,
+    recovery_interval: Duration,
+    in_order: bool,
+}
+
+impl TransformBuilder for LoadBalanceBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(LoadBalance {
+            strategy: self.strategy,
+            chain_builders: self.chains.clone(),
+            health: self.health.clone(),
+            ring: self.ring.clone(),
+            failure_threshold: self.failure_threshold,
+            recovery_interval: self.recovery_interval,
+            chains: self.chains.iter().map(|_| None).collect(),
+            transform_context,
+            next: 0,
+            outstanding: MessageIdMap::default(),
+            responses: OrderedResponses::new(self.in_order),
+            setup_replay: SetupReplay::new(self.chains.len()),
+            pinned: Pinned::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        LOAD_BALANCE_NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.chains.is_empty() {
+            errors.push("  at least one chain must be configured".to_owned());
+        }
+        if self.failure_threshold == 0 {
+            errors.push("  failure_threshold must be greater than 0".to_owned());
+        }
+        for chain in self.chains.iter() {
+            errors.extend(chain.validate().iter().map(|x| format!("  {x}")));
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+/// A request that has been sent to a chain and not yet received a response
+struct OutstandingRequest {
+    chain: usize,
+    /// Used to respond to the request if its chain fails, None for dummy requests
+    metadata: Option<Metadata>,
+}
+
+struct LoadBalance {
+    strategy: LoadBalanceStrategy,
+    chain_builders: Arc<Vec<TransformChainBuilder>>,
+    health: Arc<Vec<ChainHealth>>,
+    ring: Arc<Vec<(u64, usize)>>,
+    failure_threshold: u32,
+    recovery_interval: Duration,
+    /// Each chain is only built once a request is sent to it, and is rebuilt after it fails
+    chains: Vec<Option<TransformChain>>,
+    transform_context: TransformContextBuilder,
+    /// The chain that the next round robin request is sent to
+    next: usize,
+    outstanding: MessageIdMap<OutstandingRequest>,
+    responses: OrderedResponses,
+    /// Replays requests such as AUTH, the cassandra STARTUP, USE or PREPARE to each chain so that all of its connections are set up in the same way.
+    setup_replay: SetupReplay,
+    pinned: Pinned,
+}
+
+/// Tracks redis state that only exists on the connection that created it, all requests must go to that connection's chain while it exists.
+#[derive(Default)]
+struct Pinned {
+    chain: Option<usize>,
+    in_multi: bool,
+    watching: bool,
+    subscribed: bool,
+}
+
+impl Pinned {
+    /// Updates the state with a request that is being sent to the chain
+    fn update(&mut self, request: &mut Message, chain: usize) {
+        #[cfg(feature = "redis")]
+        if let Some(Frame::Redis(crate::frame::RedisFrame::Array(args))) = request.frame() {
+            if let Some(crate::frame::RedisFrame::BulkString(command)) = args.first() {
+                match command.to_ascii_uppercase().as_slice() {
+                    b"MULTI" => self.in_multi = true,
+                    b"WATCH" => self.watching = true,
+                    b"EXEC" | b"DISCARD" => {
+                        self.in_multi = false;
+                        self.watching = false;
+                    }
+                    // Within a MULTI the UNWATCH is only queued
+                    b"UNWATCH" if !self.in_multi => self.watching = false,
+                    // The subscriptions are kept for the lifetime of the connection as it is not tracked which channels remain subscribed
+                    b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" => self.subscribed = true,
+                    b"RESET" => *self = Pinned::default(),
+                    _ => {}
+                }
+            }
+        }
+        #[cfg(not(feature = "redis"))]
+        let _ = request;
+
+        self.chain = (self.in_multi || self.watching || self.subscribed).then_some(chain);
+    }
+}
+
+/// Returns true if the request changes state of the connection that later requests may rely on, so it must be replayed to every chain
+fn is_replayed_request(request: &mut Message) -> bool {
+    if is_setup_request(request) {
+        return true;
+    }
+    #[cfg(feature = "cassandra")]
+    if let Some(Frame::Cassandra(crate::frame::CassandraFrame { operation, .. })) = request.frame()
+    {
+        return match operation {
+            crate::frame::CassandraOperation::Query { query, .. } => {
+                matches!(
+                    query.as_ref(),
+                    cql3_parser::cassandra_statement::CassandraStatement::Use(_)
+                )
+            }
+            // Prepared ids are deterministic, so every chain will return the same id for the statement
+            crate::frame::CassandraOperation::Prepare(_) => true,
+            _ => false,
+        };
+    }
+    false
+}
+
+impl LoadBalance {
+    fn is_healthy(&self, chain: usize) -> bool {
+        self.health[chain].is_healthy(self.failure_threshold, self.recovery_interval)
+    }
+
+    fn round_robin(&mut self, healthy: &[bool]) -> usize {
+        let count = self.chain_builders.len();
+        let chain = (0..count)
+            .map(|i| (self.next + i) % count)
+            .find(|chain| healthy[*chain])
+            .unwrap_or(self.next % count);
+        self.next = (chain + 1) % count;
+        chain
+    }
+
+    fn pick_chain(&mut self, request: &mut Message, healthy: &[bool]) -> usize {
+        match self.strategy {
+            LoadBalanceStrategy::RoundRobin => self.round_robin(healthy),
+            LoadBalanceStrategy::LeastOutstandingRequests => {
+                let count = self.chain_builders.len();
+                // start searching from the round robin position so that ties are spread evenly
+                let chain = (0..count)
+                    .map(|i| (self.next + i) % count)
+                    .filter(|chain| healthy[*chain])
+                    .min_by_key(|chain| {
+                        self.health[*chain]
+                            .outstanding_requests
+                            .load(Ordering::Relaxed)
+                    })
+                    .unwrap_or(self.next % count);
+                self.next = (chain + 1) % count;
+                chain
+            }
+            LoadBalanceStrategy::ConsistentHash => match request.primary_keys().first() {
+                Some(key) => {
+                    let key_hash = hash(key.as_ref());
+                    let start = self.ring.partition_point(|(point, _)| *point < key_hash);
+                    self.ring[start..]
+                        .iter()
+                        .chain(&self.ring[..start])
+                        .map(|(_, chain)| *chain)
+                        .find(|chain| healthy[*chain])
+                        .unwrap_or(self.ring[start % self.ring.len()].1)
+                }
+                None => self.round_robin(healthy),
+            },
+        }
+    }
+
+    /// Adds the request to the requests to be sent to the chain, preceded by any setup requests the chain has not received yet.
+    fn push_request(&mut self, chain: usize, mut request: Message, routed: &mut [Messages]) {
+        let is_setup = is_replayed_request(&mut request);
+        self.setup_replay
+            .replay_to(chain, &request, is_setup, &mut routed[chain]);
+
+        self.health[chain]
+            .outstanding_requests
+            .fetch_add(1, Ordering::Relaxed);
+        self.outstanding.insert(
+            request.id(),
+            OutstandingRequest {
+                chain,
+                metadata: request.metadata().ok(),
+            },
+        );
+        routed[chain].push(request);
+    }
+
+    /// Drops the failed chain so that it is rebuilt for its next request and responds to every request it had not responded to with an error.
+    fn fail_chain(&mut self, chain: usize, err: anyhow::Error) -> Result<()> {
+        tracing::warn!("LoadBalance chain {chain} failed: {err:?}");
+        self.health[chain].record_failure();
+        self.chains[chain] = None;
+        self.setup_replay.reset(chain);
+        if self.pinned.chain == Some(chain) {
+            // The state the connection was pinned for was lost along with the chain
+            self.pinned = Pinned::default();
+        }
+
+        let failed: Vec<_> = self
+            .outstanding
+            .iter()
+            .filter(|(_, request)| request.chain == chain)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in failed {
+            let request = self.outstanding.remove(&id).unwrap();
+            self.health[chain]
+                .outstanding_requests
+                .fetch_sub(1, Ordering::Relaxed);
+            let mut response = match request.metadata {
+                Some(metadata) => metadata
+                    .to_error_response(format!("The upstream of the request failed: {err}"))?,
+                None => Message::from_frame(Frame::Dummy),
+            };
+            response.set_request_id(id);
+            self.responses.insert(id, response);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LoadBalance {
+    fn drop(&mut self) {
+        for request in self.outstanding.values() {
+            self.health[request.chain]
+                .outstanding_requests
+                .fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for LoadBalance {
+    fn get_name(&self) -> &'static str {
+        LOAD_BALANCE_NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        for chain in self.chains.iter_mut().flatten() {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut healthy: Vec<bool> = (0..self.chain_builders.len())
+            .map(|chain| self.is_healthy(chain))
+            .collect();
+        // If every chain is unhealthy, still try them rather than failing every request
+        if !healthy.contains(&true) {
+            healthy.iter_mut().for_each(|x| *x = true);
+        }
+
+        let mut routed: Vec<Messages> = vec![vec![]; self.chain_builders.len()];
+        for mut request in std::mem::take(&mut chain_state.requests) {
+            self.responses.push_request(request.id());
+            let chain = match self.pinned.chain {
+                Some(chain) => chain,
+                None => self.pick_chain(&mut request, &healthy),
+            };
+            self.pinned.update(&mut request, chain);
+            self.push_request(chain, request, &mut routed);
+        }
+
+        for (chain, (requests, builder)) in self
+            .chains
+            .iter_mut()
+            .zip(routed.iter().zip(self.chain_builders.iter()))
+        {
+            if chain.is_none() && !requests.is_empty() {
+                *chain = Some(builder.build(self.transform_context.clone()));
+            }
+        }
+
+        // Every built chain is run, even without any requests, to pick up any responses that arrived since the last run.
+        let local_addr = chain_state.local_addr;
+        let flush = chain_state.flush;
+        let session = &chain_state.session;
+        let results = join_all(self.chains.iter_mut().zip(routed).enumerate().filter_map(
+            |(i, (chain, requests))| {
+                let chain = chain.as_mut()?;
+                Some(async move {
+                    let mut sub_chain_state = ChainState::new_with_addr(requests, local_addr);
+                    sub_chain_state.flush = flush;
+                    sub_chain_state.session = session.clone();
+                    (i, chain.process_request(&mut sub_chain_state).await)
+                })
+            },
+        ))
+        .await;
+
+        let mut responses = vec![];
+        for (chain, result) in results {
+            match result {
+                Ok(chain_responses) => {
+                    if !chain_responses.is_empty() {
+                        self.health[chain].record_success();
+                    }
+                    for response in chain_responses {
+                        match response.request_id() {
+                            Some(id) if self.setup_replay.remove_replayed(id) => {}
+                            Some(id) => {
+                                if self.outstanding.remove(&id).is_some() {
+                                    self.health[chain]
+                                        .outstanding_requests
+                                        .fetch_sub(1, Ordering::Relaxed);
+                                }
+                                self.responses.insert(id, response);
+                            }
+                            None => responses.push(response),
+                        }
+                    }
+                }
+                Err(err) => self.fail_chain(chain, err)?,
+            }
+        }
+
+        self.responses.take_ready(&mut responses);
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::RedisFrame;
+    use crate::test_utils::redis_command;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use pretty_assertions::assert_eq;
+
+    fn load_balance(strategy: LoadBalanceStrategy) -> Box<dyn Transform> {
+        let chains: Vec<TransformChainBuilder> = ["a", "b", "c"]
+            .iter()
+            .map(|name| {
+                TransformChainBuilder::new(
+                    vec![Box::new(DebugReturner::new(Response::Redis(
+                        name.to_string(),
+                    )))],
+                    "load_balance_chain",
+                )
+            })
+            .collect();
+        LoadBalanceBuilder {
+            strategy,
+            health: Arc::new(chains.iter().map(|_| ChainHealth::default()).collect()),
+            ring: Arc::new(hash_ring(chains.len())),
+            chains: Arc::new(chains),
+            failure_threshold: 3,
+            recovery_interval: Duration::from_secs(10),
+            in_order: true,
+        }
+        .build(TransformContextBuilder::new_test())
+    }
+
+    async fn run(load_balance: &mut Box<dyn Transform>, requests: Messages) -> Vec<Frame> {
+        load_balance
+            .transform(&mut ChainState::new_test(requests))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect()
+    }
+
+    fn string(x: &'static str) -> Frame {
+        Frame::Redis(RedisFrame::BulkString(x.into()))
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let mut load_balance = load_balance(LoadBalanceStrategy::RoundRobin);
+        let responses = run(
+            &mut load_balance,
+            vec![
+                redis_command(&["AUTH", "pass"]),
+                redis_command(&["GET", "1"]),
+                redis_command(&["GET", "2"]),
+                redis_command(&["GET", "3"]),
+            ],
+        )
+        .await;
+        // the AUTH is replayed to b and c without its responses reaching the client
+        assert_eq!(
+            responses,
+            vec![string("a"), string("b"), string("c"), string("a")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pinned() {
+        let mut load_balance = load_balance(LoadBalanceStrategy::RoundRobin);
+        let responses = run(
+            &mut load_balance,
+            vec![
+                redis_command(&["GET", "1"]),
+                redis_command(&["MULTI"]),
+                redis_command(&["SET", "1", "1"]),
+                redis_command(&["SET", "2", "2"]),
+                redis_command(&["EXEC"]),
+                redis_command(&["GET", "2"]),
+            ],
+        )
+        .await;
+        // the whole transaction is sent to b
+        assert_eq!(
+            responses,
+            vec![
+                string("a"),
+                string("b"),
+                string("b"),
+                string("b"),
+                string("b"),
+                string("c")
+            ]
+        );
+
+        let responses = run(
+            &mut load_balance,
+            vec![
+                redis_command(&["WATCH", "1"]),
+                redis_command(&["GET", "1"]),
+                redis_command(&["UNWATCH"]),
+                redis_command(&["GET", "1"]),
+                redis_command(&["SUBSCRIBE", "channel"]),
+                redis_command(&["PING"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                string("a"),
+                string("a"),
+                string("a"),
+                string("b"),
+                string("c"),
+                string("c")
+            ]
+        );
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_replayed_requests() {
+        use crate::test_utils::cassandra_query;
+
+        assert!(is_replayed_request(&mut cassandra_query("USE ks")));
+        assert!(!is_replayed_request(&mut cassandra_query(
+            "SELECT * FROM ks.table"
+        )));
+        assert!(is_replayed_request(&mut redis_command(&["AUTH", "pass"])));
+        assert!(!is_replayed_request(&mut redis_command(&["GET", "1"])));
+    }
+
+    #[tokio::test]
+    async fn test_consistent_hash() {
+        let mut load_balance = load_balance(LoadBalanceStrategy::ConsistentHash);
+        let requests = || {
+            (0..20)
+                .map(|_| redis_command(&["GET", "key"]))
+                .collect::<Messages>()
+        };
+        let first = run(&mut load_balance, requests()).await;
+        assert!(first.iter().all(|x| *x == first[0]));
+        assert_eq!(run(&mut load_balance, requests()).await, first);
+    }
+
+    #[test]
+    fn test_chain_health() {
+        let health = ChainHealth::default();
+        health.record_failure();
+        health.record_failure();
+        assert!(health.is_healthy(3, Duration::from_secs(10)));
+        health.record_failure();
+        assert!(!health.is_healthy(3, Duration::from_secs(10)));
+        // the chain is tried again once the recovery interval has passed
+        assert!(health.is_healthy(3, Duration::ZERO));
+        health.record_success();
+        assert!(health.is_healthy(3, Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_hash_ring() {
+        let ring = hash_ring(3);
+        assert_eq!(ring.len(), 3 * VIRTUAL_NODES_PER_CHAIN as usize);
+        for chain in 0..3 {
+            assert!(ring.iter().any(|(_, x)| *x == chain));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::MessageType;
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::util::setup_replay::is_setup_request;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LoadSheddingConfig {
+    /// Requests are shed while this many requests, across all connections, are awaiting a response from the rest of the chain.
+    pub max_outstanding_requests: Option<usize>,
+    /// Requests are shed while the average latency of the rest of the chain exceeds this many milliseconds.
+    pub max_latency_ms: Option<u64>,
+}
+
+const NAME: &str = "LoadShedding";
+#[typetag::serde(name = "LoadShedding")]
+#[async_trait(?Send)]
+impl TransformConfig for LoadSheddingConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(LoadSheddingBuilder {
+            max_outstanding_requests: self.max_outstanding_requests,
+            max_latency: self.max_latency_ms.map(Duration::from_millis),
+            load: Arc::new(Load::default()),
+            shed_outstanding: counter!("shotover_shed_requests_count", "chain" => chain_name.clone(), "reason" => "outstanding_requests"),
+            shed_latency: counter!("shotover_shed_requests_count", "chain" => chain_name, "reason" => "latency"),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// The load on the rest of the chain, shared by every connection.
+struct Load {
+    outstanding_requests: AtomicUsize,
+    /// Exponentially weighted moving average of the latency of the rest of the chain
+    latency_micros: AtomicU64,
+    /// When a batch of requests was last sent down the chain, used to keep measuring latency while shedding due to latency.
+    last_admitted: Mutex<Instant>,
+}
+
+impl Default for Load {
+    fn default() -> Self {
+        Load {
+            outstanding_requests: AtomicUsize::new(0),
+            latency_micros: AtomicU64::new(0),
+            last_admitted: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl Load {
+    fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_micros() as u64;
+        let average = self.latency_micros.load(Ordering::Relaxed);
+        let average = if average == 0 {
+            sample
+        } else {
+            average - average / 8 + sample / 8
+        };
+        self.latency_micros.store(average, Ordering::Relaxed);
+    }
+}
+
+struct LoadSheddingBuilder {
+    max_outstanding_requests: Option<usize>,
+    max_latency: Option<Duration>,
+    load: Arc<Load>,
+    shed_outstanding: Counter,
+    shed_latency: Counter,
+}
+
+impl TransformBuilder for LoadSheddingBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(LoadShedding {
+            max_outstanding_requests: self.max_outstanding_requests,
+            max_latency: self.max_latency,
+            load: self.load.clone(),
+            shed_outstanding: self.shed_outstanding.clone(),
+            shed_latency: self.shed_latency.clone(),
+            shed_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.max_outstanding_requests.is_none() && self.max_latency.is_none() {
+            errors.push(
+                "  at least one of max_outstanding_requests or max_latency_ms must be configured"
+                    .to_owned(),
+            );
+        }
+        if self.max_outstanding_requests == Some(0) {
+            errors.push("  max_outstanding_requests must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct LoadShedding {
+    max_outstanding_requests: Option<usize>,
+    max_latency: Option<Duration>,
+    load: Arc<Load>,
+    shed_outstanding: Counter,
+    shed_latency: Counter,
+    /// Backpressure responses keyed by the id of the dummy request they respond to
+    shed_requests: MessageIdMap<Message>,
+}
+
+impl LoadShedding {
+    /// Returns true if the latency of the rest of the chain is too high to send requests to it.
+    /// While this is the case a batch is still let through every `max_latency` so that the latency keeps being measured.
+    fn latency_exceeded(&self) -> bool {
+        match self.max_latency {
+            Some(max_latency) => {
+                Duration::from_micros(self.load.latency_micros.load(Ordering::Relaxed))
+                    > max_latency
+                    && self.load.last_admitted.lock().unwrap().elapsed() < max_latency
+            }
+            None => false,
+        }
+    }
+
+    fn shed(&mut self, request: &mut Message) -> Result<()> {
+        self.shed_requests
+            .insert(request.id(), request.to_backpressure()?);
+        request.replace_with_dummy();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transform for LoadShedding {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let latency_exceeded = self.latency_exceeded();
+        let outstanding = self.load.outstanding_requests.load(Ordering::Relaxed);
+        let mut admitted = 0;
+        for request in &mut chain_state.requests {
+            // Requests setting up the connection are never shed as the client cannot make progress without them
+            if is_setup_request(request) {
+                admitted += 1;
+            } else if latency_exceeded {
+                self.shed(request)?;
+                self.shed_latency.increment(1);
+            } else if self
+                .max_outstanding_requests
+                .map(|max| outstanding + admitted >= max)
+                .unwrap_or(false)
+            {
+                self.shed(request)?;
+                self.shed_outstanding.increment(1);
+            } else {
+                admitted += 1;
+            }
+        }
+
+        self.load
+            .outstanding_requests
+            .fetch_add(admitted, Ordering::Relaxed);
+        if admitted > 0 {
+            *self.load.last_admitted.lock().unwrap() = Instant::now();
+        }
+        let start = Instant::now();
+        let result = chain_state.call_next_transform().await;
+        self.load
+            .outstanding_requests
+            .fetch_sub(admitted, Ordering::Relaxed);
+        if admitted > 0 {
+            self.load.record_latency(start.elapsed());
+        }
+        let mut responses = result?;
+
+        if !self.shed_requests.is_empty() {
+            for response in responses.iter_mut() {
+                if let Some(shed) = response
+                    .request_id()
+                    .and_then(|id| self.shed_requests.remove(&id))
+                {
+                    *response = shed;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+
+    fn load_shedding(
+        max_outstanding_requests: Option<usize>,
+        max_latency: Option<Duration>,
+        load: Arc<Load>,
+    ) -> LoadShedding {
+        LoadShedding {
+            max_outstanding_requests,
+            max_latency,
+            load,
+            shed_outstanding: Counter::noop(),
+            shed_latency: Counter::noop(),
+            shed_requests: MessageIdMap::default(),
+        }
+    }
+
+    async fn run(transform: &mut LoadShedding, requests: Messages) -> Vec<Frame> {
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.reset(&mut chain);
+        transform
+            .transform(&mut chain_state)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect()
+    }
+
+    fn busy() -> Frame {
+        Frame::Redis(RedisFrame::Error(
+            "BUSY shotover is overloaded, try again later".into(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_shed_outstanding_requests() {
+        let load = Arc::new(Load::default());
+        // simulate another connection awaiting a response
+        load.outstanding_requests.store(1, Ordering::Relaxed);
+        let mut transform = load_shedding(Some(3), None, load.clone());
+
+        let responses = run(
+            &mut transform,
+            vec![
+                redis_command(&["GET", "1"]),
+                redis_command(&["AUTH", "pass"]),
+                redis_command(&["GET", "2"]),
+                redis_command(&["GET", "3"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                redis_command(&["GET", "1"]).frame().cloned().unwrap(),
+                redis_command(&["AUTH", "pass"]).frame().cloned().unwrap(),
+                busy(),
+                busy(),
+            ]
+        );
+        assert_eq!(load.outstanding_requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shed_latency() {
+        let load = Arc::new(Load::default());
+        load.record_latency(Duration::from_secs(10));
+        let mut transform = load_shedding(None, Some(Duration::from_millis(500)), load.clone());
+        assert_eq!(
+            run(&mut transform, vec![redis_command(&["GET", "1"])]).await,
+            vec![busy()]
+        );
+
+        // once the latency has been measured as low again requests are no longer shed
+        load.latency_micros.store(0, Ordering::Relaxed);
+        assert_eq!(
+            run(&mut transform, vec![redis_command(&["GET", "1"])]).await,
+            vec![redis_command(&["GET", "1"]).frame().cloned().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = LoadSheddingBuilder {
+            max_outstanding_requests: Some(0),
+            max_latency: None,
+            load: Arc::new(Load::default()),
+            shed_outstanding: Counter::noop(),
+            shed_latency: Counter::noop(),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "LoadShedding:",
+                "  max_outstanding_requests must be greater than 0"
+            ]
+        );
+    }
+}
@@ -0,0 +1,2 @@
+#[cfg(feature = "redis")]
+pub mod to_redis;
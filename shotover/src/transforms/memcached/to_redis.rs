@@ -0,0 +1,381 @@
+use crate::frame::memcached::{MemcachedRequest, MemcachedResponse, MemcachedValue};
+use crate::frame::{Frame, MemcachedFrame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// memcached treats any exptime larger than 30 days as an absolute unix timestamp instead of a relative number of seconds.
+const MAX_RELATIVE_EXPTIME: i64 = 60 * 60 * 24 * 30;
+
+/// Increments the key only if it already exists, memcached does not create missing keys on incr.
+const INCR_SCRIPT: &str = "if redis.call('EXISTS', KEYS[1]) == 0 then return false end \
+    return redis.call('INCRBY', KEYS[1], ARGV[1])";
+
+/// Decrements the key only if it already exists, memcached clamps decrements at 0 instead of going negative.
+const DECR_SCRIPT: &str = "if redis.call('EXISTS', KEYS[1]) == 0 then return false end \
+    local value = redis.call('DECRBY', KEYS[1], ARGV[1]) \
+    if value < 0 then redis.call('SET', KEYS[1], 0, 'KEEPTTL') return 0 end \
+    return value";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct MemcachedToRedisConfig;
+
+const NAME: &str = "MemcachedToRedis";
+#[typetag::serde(name = "MemcachedToRedis")]
+#[async_trait(?Send)]
+impl TransformConfig for MemcachedToRedisConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(MemcachedToRedis::default()))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Memcached])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::TransformedTo(MessageType::Redis)
+    }
+}
+
+/// How the redis response to a translated request should be converted back into a memcached response
+#[derive(Debug, Clone, PartialEq)]
+enum Pending {
+    Get {
+        key: Bytes,
+    },
+    MultiGet {
+        keys: Vec<Bytes>,
+    },
+    Set,
+    Delete,
+    IncrDecr,
+    /// The request was not sent to redis, this response is returned instead.
+    Immediate(MemcachedResponse),
+}
+
+#[derive(Clone, Default)]
+struct MemcachedToRedis {
+    pending: MessageIdMap<(Pending, bool)>,
+}
+
+impl TransformBuilder for MemcachedToRedis {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(self.clone())
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+fn command(args: Vec<Bytes>) -> RedisFrame {
+    RedisFrame::Array(args.into_iter().map(RedisFrame::BulkString).collect())
+}
+
+/// Returns the redis request to send along with how to convert its response.
+/// Returns None for the request if the request should not be sent to redis.
+fn translate_request(request: MemcachedRequest) -> (Option<RedisFrame>, Pending) {
+    match request {
+        MemcachedRequest::Get { mut keys } => {
+            if keys.len() == 1 {
+                let key = keys.pop().unwrap();
+                (
+                    Some(command(vec![Bytes::from_static(b"GET"), key.clone()])),
+                    Pending::Get { key },
+                )
+            } else {
+                let mut args = vec![Bytes::from_static(b"MGET")];
+                args.extend(keys.iter().cloned());
+                (Some(command(args)), Pending::MultiGet { keys })
+            }
+        }
+        MemcachedRequest::Set {
+            key, exptime, data, ..
+        } => {
+            let args = if exptime < 0 {
+                // a negative exptime means the item is immediately expired
+                vec![Bytes::from_static(b"DEL"), key]
+            } else {
+                let mut args = vec![Bytes::from_static(b"SET"), key, data];
+                if exptime > MAX_RELATIVE_EXPTIME {
+                    args.push(Bytes::from_static(b"EXAT"));
+                    args.push(Bytes::from(exptime.to_string()));
+                } else if exptime > 0 {
+                    args.push(Bytes::from_static(b"EX"));
+                    args.push(Bytes::from(exptime.to_string()));
+                }
+                args
+            };
+            (Some(command(args)), Pending::Set)
+        }
+        MemcachedRequest::Delete { key, .. } => (
+            Some(command(vec![Bytes::from_static(b"DEL"), key])),
+            Pending::Delete,
+        ),
+        MemcachedRequest::Incr { key, value, .. } => incr_decr(INCR_SCRIPT, key, value),
+        MemcachedRequest::Decr { key, value, .. } => incr_decr(DECR_SCRIPT, key, value),
+        MemcachedRequest::Unknown(_) => (None, Pending::Immediate(MemcachedResponse::Error)),
+    }
+}
+
+fn incr_decr(script: &'static str, key: Bytes, value: u64) -> (Option<RedisFrame>, Pending) {
+    (
+        Some(command(vec![
+            Bytes::from_static(b"EVAL"),
+            Bytes::from_static(script.as_bytes()),
+            Bytes::from_static(b"1"),
+            key,
+            Bytes::from(value.to_string()),
+        ])),
+        Pending::IncrDecr,
+    )
+}
+
+fn translate_response(pending: Pending, response: Option<&RedisFrame>) -> MemcachedResponse {
+    let response = match pending {
+        Pending::Immediate(response) => return response,
+        _ => match response {
+            Some(RedisFrame::Error(error)) => {
+                return if error.contains("not an integer") {
+                    MemcachedResponse::ClientError(
+                        "cannot increment or decrement non-numeric value".to_owned(),
+                    )
+                } else {
+                    MemcachedResponse::ServerError(error.to_string())
+                };
+            }
+            Some(response) => response,
+            None => {
+                return MemcachedResponse::ServerError(
+                    "redis response could not be parsed".to_owned(),
+                )
+            }
+        },
+    };
+
+    match (pending, response) {
+        (Pending::Get { key }, RedisFrame::BulkString(data)) => {
+            MemcachedResponse::Values(vec![MemcachedValue {
+                key,
+                flags: 0,
+                data: data.clone(),
+            }])
+        }
+        (Pending::Get { .. }, RedisFrame::Null) => MemcachedResponse::Values(vec![]),
+        (Pending::MultiGet { keys }, RedisFrame::Array(values)) => MemcachedResponse::Values(
+            keys.into_iter()
+                .zip(values)
+                .filter_map(|(key, value)| match value {
+                    RedisFrame::BulkString(data) => Some(MemcachedValue {
+                        key,
+                        flags: 0,
+                        data: data.clone(),
+                    }),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        (Pending::Set, _) => MemcachedResponse::Stored,
+        (Pending::Delete, RedisFrame::Integer(0)) => MemcachedResponse::NotFound,
+        (Pending::Delete, RedisFrame::Integer(_)) => MemcachedResponse::Deleted,
+        (Pending::IncrDecr, RedisFrame::Null) => MemcachedResponse::NotFound,
+        (Pending::IncrDecr, RedisFrame::Integer(value)) if *value >= 0 => {
+            MemcachedResponse::Number(*value as u64)
+        }
+        (_, response) => {
+            MemcachedResponse::ServerError(format!("unexpected redis response {response:?}"))
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for MemcachedToRedis {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in chain_state.requests.iter_mut() {
+            let (redis_frame, pending, noreply) = match request.frame() {
+                Some(Frame::Memcached(MemcachedFrame::Request(memcached_request))) => {
+                    let noreply = memcached_request.noreply();
+                    let (redis_frame, pending) = translate_request(memcached_request.clone());
+                    (redis_frame, pending, noreply)
+                }
+                _ => (
+                    None,
+                    Pending::Immediate(MemcachedResponse::ClientError(
+                        "bad command line format".to_owned(),
+                    )),
+                    false,
+                ),
+            };
+            self.pending.insert(request.id(), (pending, noreply));
+
+            // The redis request shares its id with the original request so that the response can be matched back up.
+            *request = match redis_frame {
+                Some(frame) => Message::from_frame_diverged(Frame::Redis(frame), request),
+                None => Message::from_frame_diverged(Frame::Dummy, request),
+            };
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            let Some((pending, noreply)) = self.pending.remove(&request_id) else {
+                continue;
+            };
+            let redis_response = match response.frame() {
+                Some(Frame::Redis(frame)) => Some(&*frame),
+                _ => None,
+            };
+            let memcached_response = translate_response(pending, redis_response);
+
+            let mut new_response = Message::from_frame_at_instant(
+                Frame::Memcached(MemcachedFrame::Response(memcached_response)),
+                response.received_from_source_or_sink_at,
+            );
+            new_response.set_request_id(request_id);
+            if noreply {
+                new_response.replace_with_dummy();
+            }
+            *response = new_response;
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn bulk_strings(frame: RedisFrame) -> Vec<Bytes> {
+        match frame {
+            RedisFrame::Array(values) => values
+                .into_iter()
+                .map(|x| match x {
+                    RedisFrame::BulkString(x) => x,
+                    x => panic!("expected bulk string but was {x:?}"),
+                })
+                .collect(),
+            frame => panic!("expected array but was {frame:?}"),
+        }
+    }
+
+    fn set(exptime: i64) -> MemcachedRequest {
+        MemcachedRequest::Set {
+            key: Bytes::from("foo"),
+            flags: 0,
+            exptime,
+            data: Bytes::from("bar"),
+            noreply: false,
+        }
+    }
+
+    #[test]
+    fn set_expiry_translation() {
+        let (frame, pending) = translate_request(set(0));
+        assert_eq!(pending, Pending::Set);
+        assert_eq!(bulk_strings(frame.unwrap()), vec!["SET", "foo", "bar"]);
+
+        let (frame, _) = translate_request(set(100));
+        assert_eq!(
+            bulk_strings(frame.unwrap()),
+            vec!["SET", "foo", "bar", "EX", "100"]
+        );
+
+        let (frame, _) = translate_request(set(1_900_000_000));
+        assert_eq!(
+            bulk_strings(frame.unwrap()),
+            vec!["SET", "foo", "bar", "EXAT", "1900000000"]
+        );
+
+        let (frame, _) = translate_request(set(-1));
+        assert_eq!(bulk_strings(frame.unwrap()), vec!["DEL", "foo"]);
+    }
+
+    #[test]
+    fn get_translation() {
+        let (frame, pending) = translate_request(MemcachedRequest::Get {
+            keys: vec![Bytes::from("foo"), Bytes::from("bar")],
+        });
+        assert_eq!(bulk_strings(frame.unwrap()), vec!["MGET", "foo", "bar"]);
+
+        assert_eq!(
+            translate_response(
+                pending,
+                Some(&RedisFrame::Array(vec![
+                    RedisFrame::Null,
+                    RedisFrame::BulkString(Bytes::from("value")),
+                ]))
+            ),
+            MemcachedResponse::Values(vec![MemcachedValue {
+                key: Bytes::from("bar"),
+                flags: 0,
+                data: Bytes::from("value"),
+            }])
+        );
+
+        assert_eq!(
+            translate_response(
+                Pending::Get {
+                    key: Bytes::from("foo")
+                },
+                Some(&RedisFrame::Null)
+            ),
+            MemcachedResponse::Values(vec![])
+        );
+    }
+
+    #[test]
+    fn incr_decr_responses() {
+        assert_eq!(
+            translate_response(Pending::IncrDecr, Some(&RedisFrame::Null)),
+            MemcachedResponse::NotFound
+        );
+        assert_eq!(
+            translate_response(Pending::IncrDecr, Some(&RedisFrame::Integer(5))),
+            MemcachedResponse::Number(5)
+        );
+        assert_eq!(
+            translate_response(
+                Pending::IncrDecr,
+                Some(&RedisFrame::Error(
+                    "ERR value is not an integer or out of range".into()
+                ))
+            ),
+            MemcachedResponse::ClientError(
+                "cannot increment or decrement non-numeric value".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn delete_responses() {
+        assert_eq!(
+            translate_response(Pending::Delete, Some(&RedisFrame::Integer(1))),
+            MemcachedResponse::Deleted
+        );
+        assert_eq!(
+            translate_response(Pending::Delete, Some(&RedisFrame::Integer(0))),
+            MemcachedResponse::NotFound
+        );
+    }
+}
@@ -1,6 +1,7 @@
 //! Various types required for defining a transform
 
 use self::chain::TransformAndMetrics;
+use self::session::SessionState;
 use crate::frame::MessageType;
 use crate::message::{Message, MessageIdMap, Messages};
 use anyhow::{anyhow, Result};
@@ -14,28 +15,53 @@ use std::sync::Arc;
 use tokio::sync::Notify;
 use tokio::time::Instant;
 
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod acl;
+pub mod auth;
 #[cfg(feature = "cassandra")]
 pub mod cassandra;
 pub mod chain;
 pub mod coalesce;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod dead_letter_queue;
 pub mod debug;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod dedup;
 pub mod filter;
+pub mod hot_keys;
 #[cfg(feature = "kafka")]
 pub mod kafka;
 pub mod load_balance;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod load_shedding;
 pub mod loopback;
+#[cfg(feature = "memcached")]
+pub mod memcached;
 pub mod null;
 #[cfg(all(feature = "alpha-transforms", feature = "opensearch"))]
 pub mod opensearch;
 pub mod parallel_map;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod priority;
 #[cfg(all(feature = "alpha-transforms", feature = "cassandra"))]
 pub mod protect;
 pub mod query_counter;
 #[cfg(feature = "redis")]
 pub mod redis;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod result_filter;
+pub mod scatter_gather;
+pub mod session;
+#[cfg(any(feature = "redis", feature = "memcached"))]
+pub mod shard_router;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod size_limit;
 pub mod tee;
+pub mod tenant_router;
 #[cfg(feature = "cassandra")]
 pub mod throttling;
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+pub mod ttl_policy;
 pub mod util;
 
 /// Provides extra context that may be needed when creating a Transform
@@ -52,6 +78,15 @@ pub struct TransformContextBuilder {
 
     /// IP address of the client
     pub client_details: String,
+
+    /// True when responses may be forwarded to the client in chunks as they are received, see [`crate::message::StreamChunk`].
+    /// This is only the case when every transform up chain of the transform being built accepts streamed responses.
+    pub stream_responses: bool,
+
+    /// The schema of the cassandra cluster the chain is connected to, populated by the `CassandraSinkCluster` at the end of the chain.
+    /// `None` when the chain does not end in a sink that fetches the schema.
+    #[cfg(feature = "cassandra")]
+    pub schema_cache: Option<cassandra::schema::SchemaCache>,
 }
 
 impl TransformContextBuilder {
@@ -59,6 +94,9 @@ impl TransformContextBuilder {
         TransformContextBuilder {
             force_run_chain: Arc::new(Notify::new()),
             client_details: String::new(),
+            stream_responses: false,
+            #[cfg(feature = "cassandra")]
+            schema_cache: None,
         }
     }
 }
@@ -81,6 +119,20 @@ pub trait TransformBuilder: Send + Sync {
     fn is_terminating(&self) -> bool {
         false
     }
+
+    /// Returns true if the transform passes responses up the chain without reading or modifying their contents.
+    /// When every transform up chain of a sink accepts streamed responses, the sink may forward large responses to the client in chunks as they are received,
+    /// see [`crate::message::StreamChunk`].
+    fn accepts_streamed_responses(&self) -> bool {
+        false
+    }
+
+    /// Returns the schema cache populated by this transform, which is provided to every transform in the same chain
+    /// via [`TransformContextBuilder::schema_cache`].
+    #[cfg(feature = "cassandra")]
+    fn schema_cache(&self) -> Option<cassandra::schema::SchemaCache> {
+        None
+    }
 }
 
 /// Defines the configuration fields of a transform as they appear in the `topology.yaml`,
@@ -161,6 +213,13 @@ pub struct ChainState<'a> {
     /// Transforms can set this to true to force the connection to the client to be closed after the stack of `Transform::transform` calls returns.
     /// When closed in this way, the chain will not be flushed and no further calls to the chain will be made before it is dropped.
     pub close_client_connection: bool,
+    /// State scoped to the client connection, shared by every transform in the chain and persisted between chain runs.
+    pub session: SessionState,
+    /// Set to the client's address when the requests are selected by the running debug capture,
+    /// causing every transform's requests and responses to be recorded.
+    capture: Option<Arc<str>>,
+    /// When the requests were passed to the terminating transform of the chain, the point at which they leave shotover for the upstream.
+    pub(crate) sink_reached_at: Option<std::time::Instant>,
 }
 
 /// [`Wrapper`] will not (cannot) bring the current list of transforms that it needs to traverse with it
@@ -174,6 +233,9 @@ impl<'a> Clone for ChainState<'a> {
             local_addr: self.local_addr,
             flush: self.flush,
             close_client_connection: self.close_client_connection,
+            session: self.session.clone(),
+            capture: self.capture.clone(),
+            sink_reached_at: self.sink_reached_at,
         }
     }
 }
@@ -186,6 +248,10 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
             local_addr: self.local_addr,
             flush: self.flush,
             close_client_connection: self.close_client_connection,
+            // The taken ChainState is sent to a sub-chain, so the session must remain in place for the rest of this chain
+            session: self.session.clone(),
+            capture: self.capture.clone(),
+            sink_reached_at: self.sink_reached_at,
         }
     }
 
@@ -203,24 +269,47 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
             transform_total,
             transform_failures,
             transform_latency,
+            timeout,
+            timed_out,
             ..
         } = match self.transforms.next() {
             Some(transform) => transform,
             None => panic!("The transform chain does not end with a terminating transform. If you want to throw the messages away use a NullSink transform, otherwise use a terminating sink transform to send the messages somewhere.")
         };
 
+        if self.transforms.as_slice().is_empty() {
+            self.sink_reached_at = Some(std::time::Instant::now());
+        }
+
         let transform_name = transform.get_name();
+        let capture = self.capture.clone();
+        if let Some(client) = &capture {
+            crate::capture::record_requests(client, transform_name, &mut self.requests);
+        }
 
         let start = Instant::now();
-        let result = transform
-            .transform(self)
-            .await
-            .map_err(|e| e.context(anyhow!("{transform_name} transform failed")));
+        let mut result = match timeout {
+            Some(timeout) => {
+                match tokio::time::timeout(*timeout, transform.transform(self)).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        // the transform needs to be rebuilt, see `TransformChain::process_request`
+                        *timed_out = true;
+                        Err(anyhow!("timed out after {timeout:?}"))
+                    }
+                }
+            }
+            None => transform.transform(self).await,
+        }
+        .map_err(|e| e.context(anyhow!("{transform_name} transform failed")));
         transform_total.increment(1);
         if result.is_err() {
             transform_failures.increment(1);
         }
         transform_latency.record(start.elapsed());
+        if let Some(client) = &capture {
+            crate::capture::record_responses(client, transform_name, &mut result);
+        }
         result
     }
 
@@ -230,7 +319,6 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
         }
     }
 
-    #[cfg(test)]
     pub fn new_test(requests: Messages) -> Self {
         ChainState {
             requests,
@@ -238,6 +326,9 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
             local_addr: DUMMY_ADDRESS,
             flush: false,
             close_client_connection: false,
+            session: SessionState::default(),
+            capture: None,
+            sink_reached_at: None,
         }
     }
 
@@ -248,6 +339,9 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
             local_addr,
             flush: false,
             close_client_connection: false,
+            session: SessionState::default(),
+            capture: None,
+            sink_reached_at: None,
         }
     }
 
@@ -259,6 +353,9 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
             local_addr: DUMMY_ADDRESS,
             flush: true,
             close_client_connection: false,
+            session: SessionState::default(),
+            capture: None,
+            sink_reached_at: None,
         }
     }
 
@@ -276,10 +373,17 @@ impl<'shorter, 'longer: 'shorter> ChainState<'longer> {
 
     pub fn reset(&mut self, transforms: &'longer mut [TransformAndMetrics]) {
         self.transforms = transforms.iter_mut();
+        self.sink_reached_at = None;
+    }
+
+    /// Flags the requests to be recorded by every transform if they are selected by the running debug capture.
+    pub(crate) fn check_capture(&mut self, client_details: &str) {
+        self.capture = crate::capture::should_capture(client_details, &mut self.requests);
     }
 }
 
-const DUMMY_ADDRESS: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+pub(crate) const DUMMY_ADDRESS: SocketAddr =
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
 
 /// This trait is the primary extension point for Shotover-proxy.
 /// A [`Transform`] is a struct that implements the Transform trait and enables you to modify and observe database
@@ -346,10 +450,54 @@ pub trait Transform: Send {
     /// * Transform that do call subsquent chains via `chain_state.call_next_transform()` are non-terminating transforms.
     ///
     /// You can have have a transform that is both non-terminating and a sink.
+    ///
+    /// # Default implementation
+    /// The default implementation calls [`Transform::transform_request`], then the next transform in the chain,
+    /// then [`Transform::transform_response`] with the responses it returned.
+    /// Non-terminating transforms that only need to inspect or modify the messages can implement those instead of this method.
     async fn transform<'shorter, 'longer: 'shorter>(
         &mut self,
         chain_state: &'shorter mut ChainState<'longer>,
-    ) -> Result<Messages>;
+    ) -> Result<Messages> {
+        self.transform_request(chain_state).await?;
+        let mut responses = chain_state.call_next_transform().await?;
+        self.transform_response(chain_state, &mut responses).await?;
+        Ok(responses)
+    }
+
+    /// Inspects or modifies `chain_state.requests` before the default implementation of [`Transform::transform`] passes them to the next transform in the chain.
+    /// Not called when [`Transform::transform`] is implemented.
+    ///
+    /// The invariants of [`Transform::transform`] still apply,
+    /// e.g. if a request is removed here then a response for it must be inserted by [`Transform::transform_response`].
+    async fn transform_request<'shorter, 'longer: 'shorter>(
+        &mut self,
+        _chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Inspects or modifies the responses returned by the next transform in the chain before the default implementation of [`Transform::transform`] returns them.
+    /// Not called when [`Transform::transform`] is implemented.
+    ///
+    /// `chain_state.requests` has already been taken by the next transform, but the rest of the chain state, such as the session, is still available.
+    async fn transform_response<'shorter, 'longer: 'shorter>(
+        &mut self,
+        _chain_state: &'shorter mut ChainState<'longer>,
+        _responses: &mut Messages,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the chain is shut down due to the client disconnecting or shotover shutting down,
+    /// after the final [`Transform::transform`] call with `chain_state.flush` set.
+    ///
+    /// Transforms that hold messages outside of the chain, such as events not yet published to another system,
+    /// must deliver them here instead of dropping them. Transforms that own a sub chain must shut it down with [`chain::TransformChain::shutdown`].
+    /// Not called if [`Transform::transform`] returned `Err`, including for the final flush.
+    async fn on_shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
 
     /// Name of the transform used in logs and displayed to the user
     fn get_name(&self) -> &'static str;
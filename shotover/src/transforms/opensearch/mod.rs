@@ -1,6 +1,6 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::frame::MessageType;
-use crate::tcp;
+use crate::tcp::{self, TcpConfig};
 use crate::transforms::{ChainState, Messages, Transform, TransformBuilder, TransformConfig};
 use crate::{
     codec::{opensearch::OpenSearchCodecBuilder, CodecBuilder, Direction},
@@ -21,6 +21,8 @@ pub struct OpenSearchSinkSingleConfig {
     #[serde(rename = "remote_address")]
     address: String,
     connect_timeout_ms: u64,
+    /// Tuning of the TCP connections to opensearch.
+    tcp: Option<TcpConfig>,
 }
 
 const NAME: &str = "OpenSearchSinkSingle";
@@ -35,6 +37,7 @@ impl TransformConfig for OpenSearchSinkSingleConfig {
             self.address.clone(),
             transform_context.chain_name,
             self.connect_timeout_ms,
+            self.tcp.clone().unwrap_or_default(),
         )))
     }
 
@@ -50,15 +53,22 @@ impl TransformConfig for OpenSearchSinkSingleConfig {
 pub struct OpenSearchSinkSingleBuilder {
     address: String,
     connect_timeout: Duration,
+    tcp: TcpConfig,
 }
 
 impl OpenSearchSinkSingleBuilder {
-    pub fn new(address: String, _chain_name: String, connect_timeout_ms: u64) -> Self {
+    pub fn new(
+        address: String,
+        _chain_name: String,
+        connect_timeout_ms: u64,
+        tcp: TcpConfig,
+    ) -> Self {
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
 
         Self {
             address,
             connect_timeout,
+            tcp,
         }
     }
 }
@@ -68,6 +78,7 @@ impl TransformBuilder for OpenSearchSinkSingleBuilder {
         Box::new(OpenSearchSinkSingle {
             address: self.address.clone(),
             connect_timeout: self.connect_timeout,
+            tcp: self.tcp.clone(),
             codec_builder: OpenSearchCodecBuilder::new(Direction::Sink, self.get_name().to_owned()),
             connection: None,
         })
@@ -86,6 +97,7 @@ pub struct OpenSearchSinkSingle {
     address: String,
     connection: Option<Connection>,
     connect_timeout: Duration,
+    tcp: TcpConfig,
     codec_builder: OpenSearchCodecBuilder,
 }
 
@@ -108,7 +120,8 @@ impl Transform for OpenSearchSinkSingle {
         if self.connection.is_none() {
             trace!("creating outbound connection {:?}", self.address);
 
-            let tcp_stream = tcp::tcp_stream(self.connect_timeout, self.address.clone()).await?;
+            let tcp_stream =
+                tcp::tcp_stream(self.connect_timeout, self.address.clone(), &self.tcp).await?;
             let (rx, tx) = tcp_stream.into_split();
             self.connection = Some(spawn_read_write_tasks(&self.codec_builder, rx, tx));
         }
@@ -108,6 +108,13 @@ impl Transform for ParallelMap {
         NAME
     }
 
+    async fn on_shutdown(&mut self) -> Result<()> {
+        for chain in &mut self.chains {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
     async fn transform<'shorter, 'longer: 'shorter>(
         &mut self,
         chain_state: &'shorter mut ChainState<'longer>,
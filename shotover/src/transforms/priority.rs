@@ -0,0 +1,540 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::MessageType;
+use crate::message::{Message, Messages, OperationType};
+use crate::transforms::util::glob_match;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use metrics::{counter, histogram, Counter, Histogram};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+
+#[cfg(feature = "redis")]
+use crate::{
+    frame::{redis::redis_keys, Frame, RedisFrame},
+    transforms::redis::command_name,
+};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityConfig {
+    /// The priority classes ordered from highest to lowest priority.
+    /// Requests are assigned to the first class they match, requests matching no class are assigned to the last class.
+    pub classes: Vec<PriorityClassConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityClassConfig {
+    pub name: String,
+    /// The maximum number of requests of this class, across all connections, that may be awaiting a response from the rest of the chain.
+    pub max_concurrent_requests: usize,
+    /// Matches requests performing one of these operations.
+    #[serde(default)]
+    pub operations: Vec<OperationType>,
+    /// Matches redis requests with one of these command names.
+    #[serde(default)]
+    pub commands: Vec<String>,
+    /// Matches requests accessing a redis key or fully qualified cassandra table name matching one of these patterns.
+    /// `*` matches any sequence of characters and `?` matches any single character.
+    #[serde(default)]
+    pub key_patterns: Vec<String>,
+    /// Matches requests from clients with one of these IP addresses, either a single address such as `10.0.0.1` or a CIDR range such as `10.0.0.0/8`.
+    #[serde(default)]
+    pub source_ips: Vec<String>,
+}
+
+const NAME: &str = "Priority";
+#[typetag::serde(name = "Priority")]
+#[async_trait(?Send)]
+impl TransformConfig for PriorityConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        let classes = self
+            .classes
+            .iter()
+            .map(|class| {
+                Ok(PriorityClass {
+                    name: class.name.clone(),
+                    operations: class.operations.clone(),
+                    commands: class
+                        .commands
+                        .iter()
+                        .map(|x| x.to_ascii_uppercase())
+                        .collect(),
+                    key_patterns: class.key_patterns.clone(),
+                    source_ips: class
+                        .source_ips
+                        .iter()
+                        .map(|x| IpRange::parse(x))
+                        .collect::<Result<_>>()
+                        .with_context(|| format!("Invalid source_ips in class {:?}", class.name))?,
+                    requests: counter!("shotover_priority_requests_count", "chain" => chain_name.clone(), "class" => class.name.clone()),
+                    wait_time: histogram!("shotover_priority_wait_time_seconds", "chain" => chain_name.clone(), "class" => class.name.clone()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(PriorityBuilder {
+            scheduler: Arc::new(Scheduler::new(
+                self.classes.iter().map(|x| x.max_concurrent_requests),
+            )),
+            classes: Arc::new(classes),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+/// An IP address or CIDR range of IP addresses.
+struct IpRange {
+    address: IpAddr,
+    prefix_len: u32,
+}
+
+impl IpRange {
+    fn parse(value: &str) -> Result<Self> {
+        let (address, prefix_len) = match value.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (value, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .with_context(|| format!("{value:?} is not a valid IP address or CIDR range"))?;
+        let max_prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|x| *x <= max_prefix_len)
+                .ok_or_else(|| anyhow!("{value:?} has an invalid prefix length"))?,
+            None => max_prefix_len,
+        };
+        Ok(IpRange {
+            address,
+            prefix_len,
+        })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip.to_canonical()) {
+            (IpAddr::V4(address), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(address).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(address), IpAddr::V6(ip)) => {
+                prefix_matches(address.into(), ip.into(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns true if the first `prefix_len` bits of the `bits` long values `a` and `b` are equal
+fn prefix_matches(a: u128, b: u128, bits: u32, prefix_len: u32) -> bool {
+    prefix_len == 0 || (a ^ b) >> (bits - prefix_len) == 0
+}
+
+struct PriorityClass {
+    name: String,
+    operations: Vec<OperationType>,
+    commands: Vec<String>,
+    key_patterns: Vec<String>,
+    source_ips: Vec<IpRange>,
+    requests: Counter,
+    wait_time: Histogram,
+}
+
+impl PriorityClass {
+    /// Returns true if the request meets every criterion configured for this class
+    fn matches(&self, request: &mut Message, source_ip: Option<IpAddr>) -> bool {
+        (self.operations.is_empty() || self.operations.contains(&request.operation_type()))
+            && (self.commands.is_empty()
+                || match request.frame() {
+                    #[cfg(feature = "redis")]
+                    Some(Frame::Redis(RedisFrame::Array(args))) => command_name(args)
+                        .map(|command| self.commands.iter().any(|x| x.as_bytes() == command))
+                        .unwrap_or(false),
+                    _ => false,
+                })
+            && (self.key_patterns.is_empty()
+                || resources(request).iter().any(|resource| {
+                    self.key_patterns
+                        .iter()
+                        .any(|pattern| glob_match(pattern.as_bytes(), resource))
+                }))
+            && (self.source_ips.is_empty()
+                || source_ip
+                    .map(|ip| self.source_ips.iter().any(|range| range.contains(ip)))
+                    .unwrap_or(false))
+    }
+}
+
+/// Returns the redis keys or cassandra tables accessed by the request
+fn resources(request: &mut Message) -> Vec<Bytes> {
+    match request.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(frame)) => redis_keys(frame),
+        _ => request.tables().into_iter().map(Bytes::from).collect(),
+    }
+}
+
+/// Limits the requests of each class sent down the chain, shared by every connection.
+struct Scheduler {
+    classes: Vec<ClassState>,
+    /// Notified whenever a batch stops waiting for permits
+    waiting_changed: Notify,
+}
+
+struct ClassState {
+    max_concurrent_requests: usize,
+    semaphore: Semaphore,
+    /// The number of batches currently waiting for permits of this class
+    waiting: AtomicUsize,
+}
+
+impl Scheduler {
+    fn new(max_concurrent_requests: impl Iterator<Item = usize>) -> Self {
+        Scheduler {
+            classes: max_concurrent_requests
+                .map(|max_concurrent_requests| ClassState {
+                    max_concurrent_requests,
+                    semaphore: Semaphore::new(max_concurrent_requests),
+                    waiting: AtomicUsize::new(0),
+                })
+                .collect(),
+            waiting_changed: Notify::new(),
+        }
+    }
+
+    /// Waits until `requests` requests of the class may be sent down the chain.
+    /// While any higher priority class is at its limit, requests of lower priority classes are held back,
+    /// so that the capacity of the rest of the chain goes to the higher priority requests first.
+    async fn acquire(&self, class: usize, requests: usize) -> SemaphorePermit<'_> {
+        loop {
+            let notified = self.waiting_changed.notified();
+            if self.classes[..class]
+                .iter()
+                .all(|x| x.waiting.load(Ordering::Relaxed) == 0)
+            {
+                break;
+            }
+            notified.await;
+        }
+
+        let state = &self.classes[class];
+        state.waiting.fetch_add(1, Ordering::Relaxed);
+        // A batch larger than the limit is let through once it has every permit, rather than waiting forever
+        let permit = state
+            .semaphore
+            .acquire_many(requests.min(state.max_concurrent_requests) as u32)
+            .await
+            .expect("semaphore is never closed");
+        state.waiting.fetch_sub(1, Ordering::Relaxed);
+        self.waiting_changed.notify_waiters();
+        permit
+    }
+}
+
+struct PriorityBuilder {
+    classes: Arc<Vec<PriorityClass>>,
+    scheduler: Arc<Scheduler>,
+}
+
+impl TransformBuilder for PriorityBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(Priority {
+            classes: self.classes.clone(),
+            scheduler: self.scheduler.clone(),
+            source_ip: transform_context.client_details.parse().ok(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.classes.is_empty() {
+            errors.push("  at least one class must be configured".to_owned());
+        }
+        for (i, class) in self.classes.iter().enumerate() {
+            if self.classes[..i].iter().any(|x| x.name == class.name) {
+                errors.push(format!(
+                    "  class {:?} is configured more than once",
+                    class.name
+                ));
+            }
+            if self.scheduler.classes[i].max_concurrent_requests == 0 {
+                errors.push(format!(
+                    "  max_concurrent_requests of class {:?} must be greater than 0",
+                    class.name
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct Priority {
+    classes: Arc<Vec<PriorityClass>>,
+    scheduler: Arc<Scheduler>,
+    source_ip: Option<IpAddr>,
+}
+
+impl Priority {
+    fn classify(&self, request: &mut Message) -> usize {
+        self.classes
+            .iter()
+            .position(|class| class.matches(request, self.source_ip))
+            .unwrap_or(self.classes.len() - 1)
+    }
+}
+
+#[async_trait]
+impl Transform for Priority {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut requests_per_class = vec![0; self.classes.len()];
+        for request in &mut chain_state.requests {
+            let class = self.classify(request);
+            request.annotate("priority", self.classes[class].name.clone());
+            requests_per_class[class] += 1;
+        }
+
+        // Permits are acquired from the lowest priority class up so that a batch never holds permits of a
+        // higher priority class while waiting on a lower priority class.
+        let scheduler = self.scheduler.clone();
+        let mut permits = vec![];
+        for (i, requests) in requests_per_class.iter().enumerate().rev() {
+            if *requests > 0 {
+                let class = &self.classes[i];
+                let start = Instant::now();
+                permits.push(scheduler.acquire(i, *requests).await);
+                class.wait_time.record(start.elapsed());
+                class.requests.increment(*requests as u64);
+            }
+        }
+
+        let result = chain_state.call_next_transform().await;
+        drop(permits);
+        result
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    fn class(name: &str, max_concurrent_requests: usize) -> PriorityClassConfig {
+        PriorityClassConfig {
+            name: name.to_owned(),
+            max_concurrent_requests,
+            operations: vec![],
+            commands: vec![],
+            key_patterns: vec![],
+            source_ips: vec![],
+        }
+    }
+
+    fn priority(classes: Vec<PriorityClassConfig>, source_ip: Option<IpAddr>) -> Priority {
+        Priority {
+            scheduler: Arc::new(Scheduler::new(
+                classes.iter().map(|x| x.max_concurrent_requests),
+            )),
+            classes: Arc::new(
+                classes
+                    .into_iter()
+                    .map(|class| PriorityClass {
+                        name: class.name,
+                        operations: class.operations,
+                        commands: class.commands,
+                        key_patterns: class.key_patterns,
+                        source_ips: class
+                            .source_ips
+                            .iter()
+                            .map(|x| IpRange::parse(x).unwrap())
+                            .collect(),
+                        requests: Counter::noop(),
+                        wait_time: Histogram::noop(),
+                    })
+                    .collect(),
+            ),
+            source_ip,
+        }
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range = IpRange::parse("10.0.0.0/8").unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+
+        let range = IpRange::parse("10.0.0.1").unwrap();
+        assert!(range.contains("10.0.0.1".parse().unwrap()));
+        assert!(!range.contains("10.0.0.2".parse().unwrap()));
+
+        let range = IpRange::parse("fd00::/16").unwrap();
+        assert!(range.contains("fd00:1::1".parse().unwrap()));
+        assert!(!range.contains("fd01::1".parse().unwrap()));
+        assert!(!range.contains("10.0.0.1".parse().unwrap()));
+
+        assert!(IpRange::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("1.2.3.4".parse().unwrap()));
+        assert!(IpRange::parse("10.0.0.0/33").is_err());
+        assert!(IpRange::parse("not an ip").is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let transform = priority(
+            vec![
+                PriorityClassConfig {
+                    operations: vec![OperationType::Read],
+                    key_patterns: vec!["session:*".to_owned()],
+                    ..class("sessions", 10)
+                },
+                PriorityClassConfig {
+                    source_ips: vec!["10.0.0.0/8".to_owned()],
+                    ..class("internal", 10)
+                },
+                PriorityClassConfig {
+                    commands: vec!["SCAN".to_owned(), "KEYS".to_owned()],
+                    ..class("scans", 10)
+                },
+                class("default", 10),
+            ],
+            Some("10.0.0.1".parse().unwrap()),
+        );
+        assert_eq!(
+            transform.classify(&mut redis_command(&["GET", "session:1"])),
+            0
+        );
+        assert_eq!(
+            transform.classify(&mut redis_command(&["SET", "session:1", "a"])),
+            1
+        );
+        assert_eq!(
+            transform.classify(&mut redis_command(&["GET", "user:1"])),
+            1
+        );
+
+        let transform = Priority {
+            source_ip: Some("192.168.0.1".parse().unwrap()),
+            ..transform
+        };
+        assert_eq!(transform.classify(&mut redis_command(&["SCAN", "0"])), 2);
+        assert_eq!(
+            transform.classify(&mut redis_command(&["GET", "user:1"])),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let scheduler = Arc::new(Scheduler::new([1, 1].into_iter()));
+
+        // the only high priority permit is taken and another high priority batch is waiting for it
+        let high_permit = scheduler.acquire(0, 1).await;
+        let waiting_high = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move {
+                drop(scheduler.acquire(0, 1).await);
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        // so low priority batches are held back
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), scheduler.acquire(1, 1))
+                .await
+                .is_err()
+        );
+
+        // until the high priority batch is no longer waiting
+        drop(high_permit);
+        waiting_high.await.unwrap();
+        let _low_permit = tokio::time::timeout(Duration::from_millis(100), scheduler.acquire(1, 5))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let mut transform = priority(vec![class("high", 1), class("low", 1)], None);
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(vec![redis_command(&["GET", "1"])]);
+        chain_state.reset(&mut chain);
+        let responses: Vec<_> = transform
+            .transform(&mut chain_state)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect();
+        assert_eq!(
+            responses,
+            vec![redis_command(&["GET", "1"]).frame().cloned().unwrap()]
+        );
+        // permits are released once the responses are received
+        assert_eq!(
+            transform.scheduler.classes[1].semaphore.available_permits(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = PriorityBuilder {
+            classes: priority(vec![class("a", 0), class("a", 1)], None).classes,
+            scheduler: Arc::new(Scheduler::new([0, 1].into_iter())),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "Priority:",
+                "  max_concurrent_requests of class \"a\" must be greater than 0",
+                "  class \"a\" is configured more than once",
+            ]
+        );
+    }
+}
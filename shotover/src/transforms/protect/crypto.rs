@@ -1,11 +1,16 @@
 use crate::frame::value::GenericValue;
-use crate::transforms::protect::key_management::KeyManager;
+use crate::transforms::protect::key_management::{DeterministicKey, KeyManager};
+use crate::transforms::protect::Encryption;
 use anyhow::{anyhow, bail, Result};
+#[cfg(feature = "redis")]
+use bytes::{BufMut, Bytes, BytesMut};
 use chacha20poly1305::aead::rand_core::RngCore;
 use chacha20poly1305::aead::Aead;
 use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
 use cql3_parser::common::Operand;
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 
 #[derive(Serialize, Deserialize)]
 struct Protected {
@@ -19,55 +24,188 @@ pub async fn encrypt(
     value: &Operand,
     key_management: &KeyManager,
     key_id: &str,
+    encryption: Encryption,
 ) -> Result<Operand> {
     let value = GenericValue::from(value);
-
-    let sym_key = key_management.cached_get_key(key_id, None, None).await?;
-
-    let ser = bincode::serialize(&value)?;
-    let nonce = gen_nonce();
-    let cipher = ChaCha20Poly1305::new(&sym_key.plaintext);
-    let ciphertext = cipher
-        .encrypt(&nonce, &*ser)
-        .map_err(|_| anyhow!("couldn't encrypt value"))?;
-
-    let protected = Protected {
-        cipher: ciphertext,
-        nonce,
-        enc_dek: sym_key.ciphertext_blob.to_vec(),
-        kek_id: sym_key.key_id,
-    };
-
-    Ok(Operand::Const(format!(
-        "0x{}",
-        hex::encode(bincode::serialize(&protected)?)
-    )))
+    let protected = encrypt_bytes(
+        &bincode::serialize(&value)?,
+        key_management,
+        key_id,
+        encryption,
+    )
+    .await?;
+    Ok(Operand::Const(format!("0x{}", hex::encode(protected))))
 }
 
 pub async fn decrypt(
     value: &GenericValue,
     key_management: &KeyManager,
     key_id: &str,
+    encryption: Encryption,
 ) -> Result<GenericValue> {
     let bytes = match value {
         GenericValue::Bytes(bytes) => bytes,
         _ => bail!("expected varchar to decrypt but was {:?}", value),
     };
-    let protected: Protected = bincode::deserialize(bytes)?;
+    let decrypted_bytes = decrypt_bytes(bytes, key_management, key_id, encryption).await?;
 
-    let sym_key = key_management
-        .cached_get_key(key_id, Some(protected.enc_dek), Some(protected.kek_id))
-        .await?;
+    //TODO make error handing better here - failure here indicates an authenticity failure
+    bincode::deserialize(&decrypted_bytes).map_err(|_| anyhow!("couldn't decrypt value"))
+}
 
-    let nonce = Nonce::from_slice(&protected.nonce);
-    let cipher = ChaCha20Poly1305::new(&sym_key.plaintext);
+/// Encrypts `plaintext`, returning the bincode serialized [`Protected`] value.
+async fn encrypt_bytes(
+    plaintext: &[u8],
+    key_management: &KeyManager,
+    key_id: &str,
+    encryption: Encryption,
+) -> Result<Vec<u8>> {
+    let protected = match encryption {
+        Encryption::Randomized => {
+            let sym_key = key_management.cached_get_key(key_id, None, None).await?;
 
-    let decrypted_bytes = cipher
-        .decrypt(nonce, &*protected.cipher)
-        .map_err(|_| anyhow!("couldn't decrypt value"))?;
+            let nonce = gen_nonce();
+            let cipher = ChaCha20Poly1305::new(&sym_key.plaintext);
+            let ciphertext = cipher
+                .encrypt(&nonce, plaintext)
+                .map_err(|_| anyhow!("couldn't encrypt value"))?;
 
-    //TODO make error handing better here - failure here indicates an authenticity failure
-    bincode::deserialize(&decrypted_bytes).map_err(|_| anyhow!("couldn't decrypt value"))
+            Protected {
+                cipher: ciphertext,
+                nonce,
+                enc_dek: sym_key.ciphertext_blob.to_vec(),
+                kek_id: sym_key.key_id,
+            }
+        }
+        Encryption::Deterministic => {
+            let key = key_management.deterministic_key(key_id)?;
+
+            let nonce = synthetic_nonce(&key, plaintext);
+            let cipher = ChaCha20Poly1305::new(&key.encryption_key);
+            let ciphertext = cipher
+                .encrypt(&nonce, plaintext)
+                .map_err(|_| anyhow!("couldn't encrypt value"))?;
+
+            // the keys are derived from the kek, so there is no dek to store
+            Protected {
+                cipher: ciphertext,
+                nonce,
+                enc_dek: vec![],
+                kek_id: key.key_id,
+            }
+        }
+    };
+    Ok(bincode::serialize(&protected)?)
+}
+
+/// Decrypts a bincode serialized [`Protected`] value created by [`encrypt_bytes`].
+async fn decrypt_bytes(
+    protected: &[u8],
+    key_management: &KeyManager,
+    key_id: &str,
+    encryption: Encryption,
+) -> Result<Vec<u8>> {
+    let protected: Protected = bincode::deserialize(protected)?;
+
+    match encryption {
+        Encryption::Randomized => {
+            let sym_key = key_management
+                .cached_get_key(key_id, Some(protected.enc_dek), Some(protected.kek_id))
+                .await?;
+
+            let cipher = ChaCha20Poly1305::new(&sym_key.plaintext);
+            cipher
+                .decrypt(&protected.nonce, &*protected.cipher)
+                .map_err(|_| anyhow!("couldn't decrypt value"))
+        }
+        Encryption::Deterministic => {
+            let key = key_management.deterministic_key(key_id)?;
+
+            let cipher = ChaCha20Poly1305::new(&key.encryption_key);
+            let plaintext = cipher
+                .decrypt(&protected.nonce, &*protected.cipher)
+                .map_err(|_| anyhow!("couldn't decrypt value"))?;
+            if synthetic_nonce(&key, &plaintext) != protected.nonce {
+                bail!("couldn't decrypt value, the nonce was not derived from the value");
+            }
+            Ok(plaintext)
+        }
+    }
+}
+
+/// Derives the nonce from the plaintext as in SIV mode, so that equal plaintexts are encrypted to equal ciphertexts.
+fn synthetic_nonce(key: &DeterministicKey, plaintext: &[u8]) -> Nonce {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&key.mac_key).unwrap();
+    mac.update(plaintext);
+    *Nonce::from_slice(&mac.finalize().into_bytes()[..12])
+}
+
+/// Marks the start of an encrypted envelope within a redis value.
+/// The NUL bytes keep it from being mistaken for the start of a plaintext value.
+#[cfg(feature = "redis")]
+const ENVELOPE_HEADER: &[u8] = b"\0shotover-protect\0";
+
+/// Encrypts `value` into an envelope: [`ENVELOPE_HEADER`] followed by the length of the protected value as a big endian u32 and then the protected value itself.
+#[cfg(feature = "redis")]
+pub async fn encrypt_envelope(
+    value: &[u8],
+    key_management: &KeyManager,
+    key_id: &str,
+    encryption: Encryption,
+) -> Result<Bytes> {
+    let protected = encrypt_bytes(value, key_management, key_id, encryption).await?;
+    let mut envelope = BytesMut::with_capacity(ENVELOPE_HEADER.len() + 4 + protected.len());
+    envelope.put_slice(ENVELOPE_HEADER);
+    envelope.put_u32(protected.len().try_into()?);
+    envelope.put_slice(&protected);
+    Ok(envelope.freeze())
+}
+
+/// Decrypts every envelope within `value`, leaving any plaintext around them untouched.
+/// A value may contain multiple envelopes when it was built up by APPEND, and may contain plaintext when it was written before encryption was enabled.
+/// Returns `None` if `value` contains no envelopes.
+#[cfg(feature = "redis")]
+pub async fn decrypt_envelopes(
+    value: &[u8],
+    key_management: &KeyManager,
+    key_id: &str,
+    encryption: Encryption,
+) -> Result<Option<Bytes>> {
+    let Some(mut start) = find_envelope(value) else {
+        return Ok(None);
+    };
+    let mut decrypted = BytesMut::with_capacity(value.len());
+    let mut remaining = value;
+    loop {
+        decrypted.put_slice(&remaining[..start]);
+        let envelope = &remaining[start + ENVELOPE_HEADER.len()..];
+        let Some((length, envelope)) = envelope.split_first_chunk::<4>() else {
+            bail!("encrypted value is truncated");
+        };
+        let length = u32::from_be_bytes(*length) as usize;
+        if envelope.len() < length {
+            bail!("encrypted value is truncated");
+        }
+        decrypted.put_slice(
+            &decrypt_bytes(&envelope[..length], key_management, key_id, encryption).await?,
+        );
+
+        remaining = &envelope[length..];
+        match find_envelope(remaining) {
+            Some(next) => start = next,
+            None => {
+                decrypted.put_slice(remaining);
+                return Ok(Some(decrypted.freeze()));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+fn find_envelope(value: &[u8]) -> Option<usize> {
+    value
+        .windows(ENVELOPE_HEADER.len())
+        .position(|x| x == ENVELOPE_HEADER)
 }
 
 pub fn gen_key() -> Key {
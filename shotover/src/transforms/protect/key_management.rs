@@ -88,6 +88,16 @@ impl KeyManager {
 }
 
 impl KeyManager {
+    /// Returns the keys used by deterministic encryption, which are derived from the key encryption key so that they are the same every time.
+    pub fn deterministic_key(&self, key_id: &str) -> Result<DeterministicKey> {
+        match &self {
+            KeyManager::AWSKms(_) => Err(anyhow!(
+                "Deterministic encryption is only supported by the Local key manager"
+            )),
+            KeyManager::Local(local) => Ok(local.deterministic_key(key_id)),
+        }
+    }
+
     pub async fn cached_get_key(
         &self,
         key_id: &str,
@@ -121,6 +131,14 @@ pub struct KeyMaterial {
     pub plaintext: Key,
 }
 
+#[derive(Clone)]
+pub struct DeterministicKey {
+    /// Used to derive the nonce from the plaintext
+    pub mac_key: Key,
+    pub encryption_key: Key,
+    pub key_id: String,
+}
+
 #[cfg(test)]
 mod key_manager_tests {
     use super::*;
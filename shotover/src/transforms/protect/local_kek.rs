@@ -1,10 +1,12 @@
 use crate::transforms::protect::crypto::{gen_key, gen_nonce};
-use crate::transforms::protect::key_management::KeyMaterial;
+use crate::transforms::protect::key_management::{DeterministicKey, KeyMaterial};
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
 use chacha20poly1305::aead::Aead;
 use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 
 #[derive(Clone, Debug)]
 pub struct LocalKeyManagement {
@@ -20,6 +22,22 @@ pub struct DEKStructure {
 }
 
 impl LocalKeyManagement {
+    pub fn deterministic_key(&self, key_id: &str) -> DeterministicKey {
+        DeterministicKey {
+            mac_key: self.derive_key(b"shotover protect deterministic mac key", key_id),
+            encryption_key: self
+                .derive_key(b"shotover protect deterministic encryption key", key_id),
+            key_id: self.kek_id.clone(),
+        }
+    }
+
+    fn derive_key(&self, purpose: &[u8], key_id: &str) -> Key {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.kek).unwrap();
+        mac.update(purpose);
+        mac.update(key_id.as_bytes());
+        mac.finalize().into_bytes()
+    }
+
     pub fn get_key(&self, dek: Option<Vec<u8>>) -> Result<KeyMaterial> {
         match dek {
             None => {
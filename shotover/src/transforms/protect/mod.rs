@@ -1,17 +1,20 @@
 use super::TransformContextBuilder;
 use super::{DownChainProtocol, UpChainProtocol};
 use crate::frame::MessageType;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
 use crate::frame::{
     value::GenericValue, CassandraFrame, CassandraOperation, CassandraResult, Frame,
 };
-use crate::message::{Message, MessageIdMap, Messages};
+use crate::message::{ErrorKind, Message, MessageIdMap, Messages};
+use crate::transforms::cassandra::schema::{stored_name, SchemaCache};
 use crate::transforms::protect::key_management::KeyManager;
 pub use crate::transforms::protect::key_management::KeyManagerConfig;
 use crate::transforms::{ChainState, Transform, TransformBuilder};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use cql3_parser::cassandra_statement::CassandraStatement;
-use cql3_parser::common::Identifier;
+use cql3_parser::common::{Identifier, Operand, RelationOperator};
 use cql3_parser::insert::InsertValues;
 use cql3_parser::select::SelectElement;
 use serde::{Deserialize, Serialize};
@@ -22,14 +25,59 @@ mod crypto;
 mod key_management;
 mod local_kek;
 mod pkcs_11;
+#[cfg(feature = "redis")]
+mod redis;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct ProtectConfig {
-    pub keyspace_table_columns: HashMap<String, HashMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub keyspace_table_columns: HashMap<String, HashMap<String, Vec<ProtectedField>>>,
+    /// Glob patterns of the redis keys whose values are encrypted.
+    /// Only [`Encryption::Randomized`] is supported, as keys are not encrypted.
+    #[cfg(feature = "redis")]
+    #[serde(default)]
+    pub redis_key_patterns: Vec<ProtectedField>,
     pub key_manager: KeyManagerConfig,
 }
 
+/// A column or redis key pattern to protect.
+/// When only the name is given the values are encrypted with [`Encryption::Randomized`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ProtectedField {
+    Name(String),
+    WithEncryption {
+        name: String,
+        encryption: Encryption,
+    },
+}
+
+impl ProtectedField {
+    fn name(&self) -> &str {
+        match self {
+            ProtectedField::Name(name) => name,
+            ProtectedField::WithEncryption { name, .. } => name,
+        }
+    }
+
+    fn encryption(&self) -> Encryption {
+        match self {
+            ProtectedField::Name(_) => Encryption::Randomized,
+            ProtectedField::WithEncryption { encryption, .. } => *encryption,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encryption {
+    /// Every value is encrypted with a random nonce, so encrypting the same value twice gives different results.
+    Randomized,
+    /// The nonce is derived from the value, so encrypting the same value always gives the same result.
+    /// This allows encrypted values to be compared for equality, at the cost of revealing which values are equal.
+    Deterministic,
+}
+
 const NAME: &str = "Protect";
 #[typetag::serde(name = "Protect")]
 #[async_trait(?Send)]
@@ -38,6 +86,34 @@ impl crate::transforms::TransformConfig for ProtectConfig {
         &self,
         _transform_context: crate::transforms::TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
+        #[cfg(feature = "redis")]
+        if let Some(field) = self
+            .redis_key_patterns
+            .iter()
+            .find(|x| x.encryption() == Encryption::Deterministic)
+        {
+            bail!(
+                "redis key pattern {:?} uses Deterministic encryption, which is not supported for redis as only values are encrypted",
+                field.name()
+            );
+        }
+
+        let key_source = self.key_manager.build().await?;
+        let key_id = "XXXXXXX".to_string();
+
+        let fields = self
+            .keyspace_table_columns
+            .values()
+            .flatten()
+            .flat_map(|(_, columns)| columns);
+        // fail on startup if the key manager can not provide deterministic keys, rather than on the first request
+        for field in fields {
+            if field.encryption() == Encryption::Deterministic {
+                key_source.deterministic_key(&key_id)?;
+                break;
+            }
+        }
+
         Ok(Box::new(Protect {
             keyspace_table_columns: self
                 .keyspace_table_columns
@@ -49,21 +125,40 @@ impl crate::transforms::TransformConfig for ProtectConfig {
                             .map(|(k, v)| {
                                 (
                                     Identifier::Quoted(k.clone()),
-                                    v.iter().map(|x| Identifier::Quoted(x.clone())).collect(),
+                                    v.iter()
+                                        .map(|x| {
+                                            (
+                                                Identifier::Quoted(x.name().to_owned()),
+                                                x.encryption(),
+                                            )
+                                        })
+                                        .collect(),
                                 )
                             })
                             .collect(),
                     )
                 })
                 .collect(),
-            key_source: self.key_manager.build().await?,
-            key_id: "XXXXXXX".to_string(),
+            #[cfg(feature = "redis")]
+            redis_key_patterns: self
+                .redis_key_patterns
+                .iter()
+                .map(|x| x.name().to_owned())
+                .collect(),
+            key_source,
+            key_id,
+            schema_cache: None,
             requests: MessageIdMap::default(),
+            rejected: MessageIdMap::default(),
         }))
     }
 
     fn up_chain_protocol(&self) -> UpChainProtocol {
-        UpChainProtocol::MustBeOneOf(vec![MessageType::Cassandra])
+        UpChainProtocol::MustBeOneOf(vec![
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
     }
 
     fn down_chain_protocol(&self) -> DownChainProtocol {
@@ -73,18 +168,27 @@ impl crate::transforms::TransformConfig for ProtectConfig {
 
 #[derive(Clone)]
 struct Protect {
-    /// map of keyspace Identifiers to map of table Identifiers to column Identifiers
-    keyspace_table_columns: HashMap<Identifier, HashMap<Identifier, Vec<Identifier>>>,
+    /// map of keyspace Identifiers to map of table Identifiers to column Identifiers and their encryption
+    keyspace_table_columns: HashMap<Identifier, HashMap<Identifier, Vec<(Identifier, Encryption)>>>,
+    #[cfg(feature = "redis")]
+    redis_key_patterns: Vec<String>,
     key_source: KeyManager,
     // TODO this should be a function to create key_ids based on "something", e.g. primary key
     // for the moment this is just a string
     key_id: String,
+    /// Used to reject writes of protected values to columns that cannot store them
+    schema_cache: Option<SchemaCache>,
     requests: MessageIdMap<Message>,
+    /// Error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
 }
 
 impl TransformBuilder for Protect {
-    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
-        Box::new(self.clone())
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(Protect {
+            schema_cache: transform_context.schema_cache,
+            ..self.clone()
+        })
     }
 
     fn get_name(&self) -> &'static str {
@@ -93,7 +197,7 @@ impl TransformBuilder for Protect {
 }
 
 impl Protect {
-    fn get_protected_columns(&self, statement: &CassandraStatement) -> &[Identifier] {
+    fn get_protected_columns(&self, statement: &CassandraStatement) -> &[(Identifier, Encryption)] {
         // TODO replace `Identifier::default()` with cached keyspace name
         if let Some(table_name) = statement.get_table_name() {
             if let Some(tables) = self.keyspace_table_columns.get(
@@ -110,7 +214,37 @@ impl Protect {
         &[]
     }
 
-    /// Encrypts any values in the insert/update statements that are configured to be encrypted.
+    /// Returns the first protected column written by the statement that the schema reports is not a blob.
+    /// Encrypted values are serialized to bytes so can only be stored in blob columns.
+    fn non_blob_protected_column(&self, statement: &CassandraStatement) -> Option<String> {
+        let columns_to_encrypt = self.get_protected_columns(statement);
+        if columns_to_encrypt.is_empty() {
+            return None;
+        }
+        let schema = self.schema_cache.as_ref()?.get();
+        let table = schema.table_by_name(statement.get_table_name()?)?;
+        let written: Vec<&Identifier> = match statement {
+            CassandraStatement::Insert(insert) => insert.columns.iter().collect(),
+            CassandraStatement::Update(update) => update
+                .assignments
+                .iter()
+                .map(|assignment| &assignment.name.column)
+                .collect(),
+            _ => return None,
+        };
+        written
+            .into_iter()
+            .filter(|name| column_encryption(columns_to_encrypt, name).is_some())
+            .map(stored_name)
+            .find(|name| {
+                table
+                    .column(name)
+                    .is_some_and(|column| column.cql_type != "blob")
+            })
+    }
+
+    /// Encrypts any values in the insert/update statements that are configured to be encrypted,
+    /// along with any literals compared by equality to deterministically encrypted columns in WHERE clauses.
     /// Returns `true` if any columns were changed.
     async fn encrypt_columns(&self, statement: &mut CassandraStatement) -> Result<bool> {
         let mut invalidate_cache = false;
@@ -118,12 +252,17 @@ impl Protect {
         match statement {
             CassandraStatement::Insert(insert) => {
                 for (i, col_name) in insert.columns.iter().enumerate() {
-                    if columns_to_encrypt.contains(col_name) {
+                    if let Some(encryption) = column_encryption(columns_to_encrypt, col_name) {
                         match &mut insert.values {
                             InsertValues::Values(value_operands) => {
                                 if let Some(value) = value_operands.get_mut(i) {
-                                    *value = crypto::encrypt(value, &self.key_source, &self.key_id)
-                                        .await?;
+                                    *value = crypto::encrypt(
+                                        value,
+                                        &self.key_source,
+                                        &self.key_id,
+                                        encryption,
+                                    )
+                                    .await?;
                                     invalidate_cache = true
                                 }
                             }
@@ -134,10 +273,16 @@ impl Protect {
             }
             CassandraStatement::Update(update) => {
                 for assignment in &mut update.assignments {
-                    if columns_to_encrypt.contains(&assignment.name.column) {
-                        assignment.value =
-                            crypto::encrypt(&assignment.value, &self.key_source, &self.key_id)
-                                .await?;
+                    if let Some(encryption) =
+                        column_encryption(columns_to_encrypt, &assignment.name.column)
+                    {
+                        assignment.value = crypto::encrypt(
+                            &assignment.value,
+                            &self.key_source,
+                            &self.key_id,
+                            encryption,
+                        )
+                        .await?;
                         invalidate_cache = true;
                     }
                 }
@@ -146,6 +291,31 @@ impl Protect {
                 // no other statements are modified
             }
         }
+
+        let where_clause = match statement {
+            CassandraStatement::Select(select) => &mut select.where_clause,
+            CassandraStatement::Update(update) => &mut update.where_clause,
+            CassandraStatement::Delete(delete) => &mut delete.where_clause,
+            _ => return Ok(invalidate_cache),
+        };
+        for relation in where_clause {
+            // randomized encryption can never be compared by equality, so those relations are left as is
+            if let (RelationOperator::Equal, Operand::Column(column), Operand::Const(_)) =
+                (&relation.oper, &relation.obj, &relation.value)
+            {
+                if column_encryption(columns_to_encrypt, column) == Some(Encryption::Deterministic)
+                {
+                    relation.value = crypto::encrypt(
+                        &relation.value,
+                        &self.key_source,
+                        &self.key_id,
+                        Encryption::Deterministic,
+                    )
+                    .await?;
+                    invalidate_cache = true;
+                }
+            }
+        }
         Ok(invalidate_cache)
     }
 
@@ -161,12 +331,16 @@ impl Protect {
             let columns_to_decrypt = self.get_protected_columns(statement);
             for (i, col) in select.columns.iter().enumerate() {
                 if let SelectElement::Column(col) = col {
-                    if columns_to_decrypt.contains(&col.name) {
+                    if let Some(encryption) = column_encryption(columns_to_decrypt, &col.name) {
                         for row in &mut *rows {
                             if let Some(message_value) = row.get_mut(i) {
-                                *message_value =
-                                    crypto::decrypt(message_value, &self.key_source, &self.key_id)
-                                        .await?;
+                                *message_value = crypto::decrypt(
+                                    message_value,
+                                    &self.key_source,
+                                    &self.key_id,
+                                    encryption,
+                                )
+                                .await?;
                                 invalidate_cache = true;
                             }
                         }
@@ -178,6 +352,16 @@ impl Protect {
     }
 }
 
+fn column_encryption(
+    columns: &[(Identifier, Encryption)],
+    name: &Identifier,
+) -> Option<Encryption> {
+    columns
+        .iter()
+        .find(|(column, _)| column == name)
+        .map(|(_, encryption)| *encryption)
+}
+
 #[async_trait]
 impl Transform for Protect {
     fn get_name(&self) -> &'static str {
@@ -188,14 +372,45 @@ impl Transform for Protect {
         &mut self,
         chain_state: &'shorter mut ChainState<'longer>,
     ) -> Result<Messages> {
-        // encrypt the values included in any INSERT or UPDATE queries
+        // encrypt the values included in any INSERT or UPDATE queries or redis writes
         for message in chain_state.requests.iter_mut() {
+            let non_blob_column = match message.frame() {
+                Some(Frame::Cassandra(CassandraFrame { operation, .. })) => operation
+                    .queries()
+                    .find_map(|statement| self.non_blob_protected_column(statement)),
+                _ => None,
+            };
+            if let Some(column) = non_blob_column {
+                self.rejected.insert(
+                    message.id(),
+                    message.error_response(
+                        format!("protected column {column} must be of type blob to store encrypted values"),
+                        ErrorKind::InvalidRequest,
+                    )?,
+                );
+                message.replace_with_dummy();
+                continue;
+            }
+
             let mut invalidate_cache = false;
 
-            if let Some(Frame::Cassandra(CassandraFrame { operation, .. })) = message.frame() {
-                for statement in operation.queries() {
-                    invalidate_cache |= self.encrypt_columns(statement).await.unwrap();
+            match message.frame() {
+                Some(Frame::Cassandra(CassandraFrame { operation, .. })) => {
+                    for statement in operation.queries() {
+                        invalidate_cache |= self.encrypt_columns(statement).await.unwrap();
+                    }
+                }
+                #[cfg(feature = "redis")]
+                Some(Frame::Redis(RedisFrame::Array(command))) => {
+                    invalidate_cache |= redis::encrypt_command(
+                        command,
+                        &self.redis_key_patterns,
+                        &self.key_source,
+                        &self.key_id,
+                    )
+                    .await?;
                 }
+                _ => {}
             }
             if invalidate_cache {
                 message.invalidate_cache();
@@ -208,18 +423,39 @@ impl Transform for Protect {
         for response in &mut responses {
             if let Some(request_id) = response.request_id() {
                 let mut request = self.requests.remove(&request_id).unwrap();
+                if let Some(rejected) = self.rejected.remove(&request_id) {
+                    *response = rejected;
+                    continue;
+                }
 
                 let mut invalidate_cache = false;
-                if let Some(Frame::Cassandra(CassandraFrame { operation, .. })) = request.frame() {
-                    if let Some(Frame::Cassandra(CassandraFrame {
-                        operation: CassandraOperation::Result(CassandraResult::Rows { rows, .. }),
-                        ..
-                    })) = response.frame()
-                    {
-                        for statement in operation.queries() {
-                            invalidate_cache |= self.decrypt_results(statement, rows).await?
+                match request.frame() {
+                    Some(Frame::Cassandra(CassandraFrame { operation, .. })) => {
+                        if let Some(Frame::Cassandra(CassandraFrame {
+                            operation:
+                                CassandraOperation::Result(CassandraResult::Rows { rows, .. }),
+                            ..
+                        })) = response.frame()
+                        {
+                            for statement in operation.queries() {
+                                invalidate_cache |= self.decrypt_results(statement, rows).await?
+                            }
                         }
                     }
+                    #[cfg(feature = "redis")]
+                    Some(Frame::Redis(RedisFrame::Array(command))) => {
+                        if let Some(Frame::Redis(response)) = response.frame() {
+                            invalidate_cache |= redis::decrypt_response(
+                                command,
+                                response,
+                                &self.redis_key_patterns,
+                                &self.key_source,
+                                &self.key_id,
+                            )
+                            .await?;
+                        }
+                    }
+                    _ => {}
                 }
                 if invalidate_cache {
                     response.invalidate_cache();
@@ -230,3 +466,37 @@ impl Transform for Protect {
         Ok(responses)
     }
 }
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::transforms::{TransformConfig, TransformContextConfig};
+
+    #[tokio::test]
+    async fn test_redis_deterministic_rejected() {
+        let config = ProtectConfig {
+            keyspace_table_columns: HashMap::new(),
+            redis_key_patterns: vec![ProtectedField::WithEncryption {
+                name: "session:*".to_owned(),
+                encryption: Encryption::Deterministic,
+            }],
+            key_manager: KeyManagerConfig::Local {
+                kek: "Ht8M1nDO/7fay+cft71M2Xy7j30EnLAsA84hSUMCm1k=".to_owned(),
+                kek_id: "".to_owned(),
+            },
+        };
+        let transform_context_config = TransformContextConfig {
+            chain_name: "".into(),
+            up_chain_protocol: MessageType::Redis,
+        };
+        let err = config
+            .get_builder(transform_context_config)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "redis key pattern \"session:*\" uses Deterministic encryption, which is not supported for redis as only values are encrypted"
+        );
+    }
+}
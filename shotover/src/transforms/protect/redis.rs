@@ -0,0 +1,242 @@
+//! Encryption of the values of redis keys that match the configured key patterns.
+//!
+//! Only values are encrypted, keys and hash fields are sent as is.
+//! So values always use [`Encryption::Randomized`], as deterministic encryption would not allow any lookups that randomized encryption does not.
+
+use crate::frame::RedisFrame;
+use crate::transforms::protect::crypto;
+use crate::transforms::protect::key_management::KeyManager;
+use crate::transforms::protect::Encryption;
+use crate::transforms::redis::command_name;
+use crate::transforms::util::glob_match;
+use anyhow::Result;
+
+/// Where the values of a redis response are found.
+enum ResponseValues {
+    /// The response is the value of the key at index 1 of the command
+    Single,
+    /// The response is an array where each element is the value of the key at the same index of the command, offset by 1
+    PerKey,
+    /// The response is an array where every element is a value of the key at index 1 of the command
+    All,
+    /// The response is an array of field value pairs of the key at index 1 of the command
+    FieldValuePairs,
+}
+
+/// Returns the (key index, value index) of every value written by the command.
+fn request_values(name: &[u8], len: usize) -> Vec<(usize, usize)> {
+    match name {
+        // COMMAND key value ...
+        b"SET" | b"SETNX" | b"GETSET" | b"APPEND" if len >= 3 => vec![(1, 2)],
+        // COMMAND key seconds/field value
+        b"SETEX" | b"PSETEX" | b"HSETNX" if len >= 4 => vec![(1, 3)],
+        // COMMAND key value [key value ...]
+        b"MSET" | b"MSETNX" => (1..len.saturating_sub(1))
+            .step_by(2)
+            .map(|i| (i, i + 1))
+            .collect(),
+        // COMMAND key field value [field value ...]
+        b"HSET" | b"HMSET" => (3..len).step_by(2).map(|i| (1, i)).collect(),
+        _ => vec![],
+    }
+}
+
+fn response_values(name: &[u8]) -> Option<ResponseValues> {
+    match name {
+        // SET only returns the old value when the GET option is given
+        b"GET" | b"GETDEL" | b"GETEX" | b"GETSET" | b"SET" | b"HGET" => {
+            Some(ResponseValues::Single)
+        }
+        b"MGET" => Some(ResponseValues::PerKey),
+        b"HMGET" | b"HVALS" => Some(ResponseValues::All),
+        b"HGETALL" => Some(ResponseValues::FieldValuePairs),
+        _ => None,
+    }
+}
+
+/// Returns true if the key at `index` of the command matches any of the patterns.
+fn key_matches(command: &[RedisFrame], index: usize, key_patterns: &[String]) -> bool {
+    match command.get(index) {
+        Some(RedisFrame::BulkString(key)) => key_patterns
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key)),
+        _ => false,
+    }
+}
+
+/// Encrypts any values written by the command to keys that match `key_patterns`.
+/// Returns `true` if the command was changed.
+pub(super) async fn encrypt_command(
+    command: &mut [RedisFrame],
+    key_patterns: &[String],
+    key_management: &KeyManager,
+    key_id: &str,
+) -> Result<bool> {
+    let Some(name) = command_name(command) else {
+        return Ok(false);
+    };
+    let mut invalidate_cache = false;
+    for (key_index, value_index) in request_values(&name, command.len()) {
+        if key_matches(command, key_index, key_patterns) {
+            if let RedisFrame::BulkString(value) = &mut command[value_index] {
+                *value =
+                    crypto::encrypt_envelope(value, key_management, key_id, Encryption::Randomized)
+                        .await?;
+                invalidate_cache = true;
+            }
+        }
+    }
+    Ok(invalidate_cache)
+}
+
+/// Decrypts any values in the response to the command that belong to keys that match `key_patterns`.
+/// Values that were written before encryption was enabled are returned unchanged.
+/// Returns `true` if the response was changed.
+pub(super) async fn decrypt_response(
+    command: &[RedisFrame],
+    response: &mut RedisFrame,
+    key_patterns: &[String],
+    key_management: &KeyManager,
+    key_id: &str,
+) -> Result<bool> {
+    let Some(values) = command_name(command).and_then(|name| response_values(&name)) else {
+        return Ok(false);
+    };
+    let mut invalidate_cache = false;
+    match (values, response) {
+        (ResponseValues::Single, value) => {
+            if key_matches(command, 1, key_patterns) {
+                invalidate_cache |= decrypt_value(value, key_management, key_id).await?;
+            }
+        }
+        (ResponseValues::PerKey, RedisFrame::Array(values)) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                if key_matches(command, i + 1, key_patterns) {
+                    invalidate_cache |= decrypt_value(value, key_management, key_id).await?;
+                }
+            }
+        }
+        (ResponseValues::All, RedisFrame::Array(values)) => {
+            if key_matches(command, 1, key_patterns) {
+                for value in values {
+                    invalidate_cache |= decrypt_value(value, key_management, key_id).await?;
+                }
+            }
+        }
+        (ResponseValues::FieldValuePairs, RedisFrame::Array(values)) => {
+            if key_matches(command, 1, key_patterns) {
+                for value in values.iter_mut().skip(1).step_by(2) {
+                    invalidate_cache |= decrypt_value(value, key_management, key_id).await?;
+                }
+            }
+        }
+        // errors are passed through as is
+        _ => {}
+    }
+    Ok(invalidate_cache)
+}
+
+async fn decrypt_value(
+    value: &mut RedisFrame,
+    key_management: &KeyManager,
+    key_id: &str,
+) -> Result<bool> {
+    if let RedisFrame::BulkString(bytes) = value {
+        if let Some(decrypted) =
+            crypto::decrypt_envelopes(bytes, key_management, key_id, Encryption::Randomized).await?
+        {
+            *bytes = decrypted;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command_args;
+    use crate::transforms::protect::key_management::KeyManagerConfig;
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+
+    async fn key_manager() -> KeyManager {
+        KeyManagerConfig::Local {
+            kek: "Ht8M1nDO/7fay+cft71M2Xy7j30EnLAsA84hSUMCm1k=".to_owned(),
+            kek_id: "".to_owned(),
+        }
+        .build()
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_decrypt_round_trip() {
+        let key_manager = key_manager().await;
+        let patterns = vec!["secret:*".to_owned()];
+
+        let mut mset = redis_command_args(&["MSET", "secret:1", "foo", "public:1", "bar"]);
+        assert!(encrypt_command(&mut mset, &patterns, &key_manager, "id")
+            .await
+            .unwrap());
+        assert_ne!(mset[2], RedisFrame::BulkString(Bytes::from("foo")));
+        assert_eq!(mset[4], RedisFrame::BulkString(Bytes::from("bar")));
+
+        // Appending to a value that was written before encryption was enabled leaves a mix of plaintext and envelopes
+        let RedisFrame::BulkString(encrypted) = &mset[2] else {
+            panic!()
+        };
+        let mut append = redis_command_args(&["APPEND", "secret:1", "baz"]);
+        encrypt_command(&mut append, &patterns, &key_manager, "id")
+            .await
+            .unwrap();
+        let RedisFrame::BulkString(appended) = &append[2] else {
+            panic!()
+        };
+        let stored = [b"plain".as_slice(), encrypted, appended].concat();
+
+        let mut response = RedisFrame::Array(vec![
+            RedisFrame::BulkString(Bytes::from(stored)),
+            RedisFrame::BulkString(Bytes::from("bar")),
+            RedisFrame::Null,
+        ]);
+        assert!(decrypt_response(
+            &redis_command_args(&["MGET", "secret:1", "public:1", "secret:2"]),
+            &mut response,
+            &patterns,
+            &key_manager,
+            "id"
+        )
+        .await
+        .unwrap());
+        assert_eq!(
+            response,
+            RedisFrame::Array(vec![
+                RedisFrame::BulkString(Bytes::from("plainfoobaz")),
+                RedisFrame::BulkString(Bytes::from("bar")),
+                RedisFrame::Null,
+            ])
+        );
+
+        let mut plaintext = RedisFrame::BulkString(Bytes::from("plain"));
+        assert!(!decrypt_response(
+            &redis_command_args(&["GET", "secret:1"]),
+            &mut plaintext,
+            &patterns,
+            &key_manager,
+            "id"
+        )
+        .await
+        .unwrap());
+    }
+
+    #[test]
+    fn test_request_values() {
+        assert_eq!(request_values(b"SET", 5), [(1, 2)]);
+        assert_eq!(request_values(b"SETEX", 4), [(1, 3)]);
+        assert_eq!(request_values(b"MSET", 5), [(1, 2), (3, 4)]);
+        assert_eq!(request_values(b"HSET", 6), [(1, 3), (1, 5)]);
+        assert!(request_values(b"SET", 2).is_empty());
+        assert!(request_values(b"GET", 2).is_empty());
+    }
+}
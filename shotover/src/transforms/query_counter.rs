@@ -1,30 +1,105 @@
+#[cfg(feature = "cassandra")]
+use crate::frame::fingerprint::normalize_cassandra_statement;
+#[cfg(feature = "redis")]
+use crate::frame::fingerprint::normalize_redis;
+use crate::frame::fingerprint::NormalizedQuery;
 use crate::frame::Frame;
-use crate::message::Messages;
+#[cfg(feature = "memcached")]
+use crate::frame::MemcachedFrame;
+use crate::message::{Message, MessageIdMap, Messages};
 use crate::transforms::TransformConfig;
 use crate::transforms::TransformContextBuilder;
 use crate::transforms::{ChainState, Transform, TransformBuilder};
 use anyhow::Result;
 use async_trait::async_trait;
-use metrics::counter;
-use metrics::Counter;
+use metrics::{counter, histogram};
+use metrics::{Counter, Histogram, Label};
 use serde::Deserialize;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use super::DownChainProtocol;
 use super::TransformContextConfig;
 use super::UpChainProtocol;
 
+/// The labels identifying a query: the query name, the protocol, optionally the table it accesses, optionally the fingerprint of the query and the values of the annotation labels
+type QueryLabels = (
+    String,
+    &'static str,
+    Option<String>,
+    Option<String>,
+    Vec<String>,
+);
+
 #[derive(Clone)]
 pub struct QueryCounter {
     counter_name: &'static str,
-    query_to_counter: HashMap<String, Counter>,
+    query_to_counter: HashMap<QueryLabels, Counter>,
+    query_to_histogram: HashMap<QueryLabels, Histogram>,
+    latency: bool,
+    tables: Option<Arc<LabelValues>>,
+    fingerprints: Option<Arc<LabelValues>>,
+    /// The annotation labels, each with the values used for it
+    annotation_labels: Arc<[(String, LabelValues)]>,
+    /// The latency histogram and send time of requests that have not yet received a response
+    pending_requests: MessageIdMap<(Histogram, Instant)>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct QueryCounterConfig {
     pub name: String,
+    /// Also record the latency of each request in the histogram `shotover_query_latency_seconds`.
+    #[serde(default)]
+    pub latency: bool,
+    /// Also label the metrics of cassandra queries with the table they access.
+    #[serde(default)]
+    pub table_label: bool,
+    /// The maximum number of distinct tables used as label values, defaults to 100.
+    /// Queries to any further tables are labelled with the table `other`, limiting the cardinality of the metrics.
+    pub max_tables: Option<usize>,
+    /// Also label the metrics with the fingerprint of the normalized query, see [`crate::frame::fingerprint`].
+    #[serde(default)]
+    pub fingerprint_label: bool,
+    /// The maximum number of distinct fingerprints used as label values, defaults to 100.
+    /// Any further queries are labelled with the fingerprint `other`.
+    pub max_fingerprints: Option<usize>,
+    /// Also label the metrics with the value of each of these annotations, as attached to requests by up chain transforms.
+    /// Requests without an annotation are labelled with the value `none`.
+    #[serde(default)]
+    pub annotation_labels: Vec<String>,
+    /// The maximum number of distinct values used for each annotation label, defaults to 100.
+    /// Any further values are labelled with the value `other`.
+    pub max_annotation_values: Option<usize>,
+}
+
+/// The values used for a label, shared by every connection.
+struct LabelValues {
+    max_values: usize,
+    values: Mutex<HashSet<String>>,
+}
+
+impl LabelValues {
+    fn new(max_values: usize) -> Self {
+        LabelValues {
+            max_values,
+            values: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn label(&self, value: String) -> String {
+        let mut values = self.values.lock().unwrap();
+        if values.contains(&value) {
+            value
+        } else if values.len() < self.max_values {
+            values.insert(value.clone());
+            value
+        } else {
+            "other".to_owned()
+        }
+    }
 }
 
 impl QueryCounter {
@@ -38,14 +113,93 @@ impl QueryCounter {
         QueryCounter {
             counter_name: counter_name_ref,
             query_to_counter: HashMap::new(),
+            query_to_histogram: HashMap::new(),
+            latency: false,
+            tables: None,
+            fingerprints: None,
+            annotation_labels: Arc::new([]),
+            pending_requests: MessageIdMap::default(),
         }
     }
 
-    fn increment_counter(&mut self, query: String, query_type: &'static str) {
-        self.query_to_counter.entry(query)
-            .or_insert_with_key(|query| counter!("shotover_query_count", "name" => self.counter_name, "query" => query.clone(), "type" => query_type))
+    fn labels(
+        &self,
+        query: String,
+        query_type: &'static str,
+        table: Option<String>,
+        normalized: Option<NormalizedQuery>,
+        annotations: Vec<String>,
+    ) -> QueryLabels {
+        let table = self
+            .tables
+            .as_ref()
+            .and_then(|tables| table.map(|table| tables.label(table)));
+        let fingerprint = self.fingerprints.as_ref().and_then(|fingerprints| {
+            normalized.map(|normalized| fingerprints.label(normalized.fingerprint.to_string()))
+        });
+        (query, query_type, table, fingerprint, annotations)
+    }
+
+    /// Returns the values of the annotation labels of the request
+    fn annotations(&self, request: &Message) -> Vec<String> {
+        self.annotation_labels
+            .iter()
+            .map(|(key, values)| match request.annotation(key) {
+                Some(value) => values.label(value.to_string()),
+                None => "none".to_owned(),
+            })
+            .collect()
+    }
+
+    fn increment_counter(&mut self, labels: QueryLabels) {
+        let name = self.counter_name;
+        let annotation_labels = &self.annotation_labels;
+        self.query_to_counter
+            .entry(labels)
+            .or_insert_with_key(|labels| {
+                counter!(
+                    "shotover_query_count",
+                    metric_labels(name, annotation_labels, labels)
+                )
+            })
             .increment(1);
     }
+
+    fn latency_histogram(&mut self, labels: QueryLabels) -> Histogram {
+        let name = self.counter_name;
+        let annotation_labels = &self.annotation_labels;
+        self.query_to_histogram
+            .entry(labels)
+            .or_insert_with_key(|labels| {
+                histogram!(
+                    "shotover_query_latency_seconds",
+                    metric_labels(name, annotation_labels, labels)
+                )
+            })
+            .clone()
+    }
+}
+
+fn metric_labels(
+    name: &'static str,
+    annotation_labels: &[(String, LabelValues)],
+    (query, query_type, table, fingerprint, annotations): &QueryLabels,
+) -> Vec<Label> {
+    let mut labels = vec![
+        Label::new("name", name),
+        Label::new("query", query.clone()),
+        Label::new("type", *query_type),
+    ];
+    if let Some(table) = table {
+        labels.push(Label::new("table", table.clone()));
+    }
+    if let Some(fingerprint) = fingerprint {
+        labels.push(Label::new("fingerprint", fingerprint.clone()));
+    }
+    for ((key, _), value) in annotation_labels.iter().zip(annotations) {
+        labels.push(Label::new(key.clone(), value.clone()));
+    }
+    labels
 }
 
 impl TransformBuilder for QueryCounter {
@@ -56,6 +210,10 @@ impl TransformBuilder for QueryCounter {
     fn get_name(&self) -> &'static str {
         NAME
     }
+
+    fn accepts_streamed_responses(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait]
@@ -69,24 +227,61 @@ impl Transform for QueryCounter {
         chain_state: &'shorter mut ChainState<'longer>,
     ) -> Result<Messages> {
         for m in &mut chain_state.requests {
+            let id = m.id();
+            let annotations = self.annotations(m);
+            // The labels the latency of the whole request is recorded under
+            let mut request_labels = None;
             match m.frame() {
                 #[cfg(feature = "cassandra")]
                 Some(Frame::Cassandra(frame)) => {
+                    let is_batch =
+                        matches!(frame.operation, crate::frame::CassandraOperation::Batch(_));
                     for statement in frame.operation.queries() {
-                        self.increment_counter(statement.short_name().to_string(), "cassandra");
+                        let normalized = self
+                            .fingerprints
+                            .is_some()
+                            .then(|| normalize_cassandra_statement(statement));
+                        let labels = self.labels(
+                            statement.short_name().to_string(),
+                            "cassandra",
+                            statement.get_table_name().map(|x| x.to_string()),
+                            normalized,
+                            annotations.clone(),
+                        );
+                        if request_labels.is_none() && !is_batch {
+                            request_labels = Some(labels.clone());
+                        }
+                        self.increment_counter(labels);
+                    }
+                    if is_batch {
+                        request_labels = Some(self.labels(
+                            "BATCH".to_owned(),
+                            "cassandra",
+                            None,
+                            None,
+                            annotations,
+                        ));
                     }
                 }
                 #[cfg(feature = "redis")]
                 Some(Frame::Redis(frame)) => {
-                    if let Some(query_type) = crate::frame::redis::redis_query_name(frame) {
-                        self.increment_counter(query_type, "redis");
-                    } else {
-                        self.increment_counter("unknown".to_string(), "redis");
-                    }
+                    let query = crate::frame::redis::redis_query_name(frame)
+                        .unwrap_or_else(|| "unknown".to_string());
+                    let normalized = self
+                        .fingerprints
+                        .is_some()
+                        .then(|| normalize_redis(frame))
+                        .flatten();
+                    let labels = self.labels(query, "redis", None, normalized, annotations);
+                    request_labels = Some(labels.clone());
+                    self.increment_counter(labels);
                 }
                 #[cfg(feature = "kafka")]
                 Some(Frame::Kafka(_)) => {
-                    self.increment_counter("unknown".to_string(), "kafka");
+                    let labels =
+                        self.labels("unknown".to_string(), "kafka", None, None, annotations);
+                    request_labels = Some(labels.clone());
+                    self.increment_counter(labels);
                 }
                 Some(Frame::Dummy) => {
                     // Dummy does not count as a message
@@ -95,13 +290,54 @@ impl Transform for QueryCounter {
                 Some(Frame::OpenSearch(_)) => {
                     todo!();
                 }
+                #[cfg(feature = "memcached")]
+                Some(Frame::Memcached(MemcachedFrame::Request(request))) => {
+                    let labels = self.labels(
+                        request.name().to_owned(),
+                        "memcached",
+                        None,
+                        None,
+                        annotations,
+                    );
+                    request_labels = Some(labels.clone());
+                    self.increment_counter(labels);
+                }
+                #[cfg(feature = "memcached")]
+                Some(Frame::Memcached(MemcachedFrame::Response(_))) => {
+                    let labels =
+                        self.labels("unknown".to_string(), "memcached", None, None, annotations);
+                    request_labels = Some(labels.clone());
+                    self.increment_counter(labels);
+                }
                 None => {
-                    self.increment_counter("unknown".to_string(), "none");
+                    let labels =
+                        self.labels("unknown".to_string(), "none", None, None, annotations);
+                    request_labels = Some(labels.clone());
+                    self.increment_counter(labels);
+                }
+            }
+
+            if self.latency {
+                if let Some(labels) = request_labels {
+                    let histogram = self.latency_histogram(labels);
+                    self.pending_requests
+                        .insert(id, (histogram, Instant::now()));
                 }
             }
         }
 
-        chain_state.call_next_transform().await
+        let responses = chain_state.call_next_transform().await?;
+        if self.latency {
+            for response in &responses {
+                if let Some((histogram, sent)) = response
+                    .request_id()
+                    .and_then(|id| self.pending_requests.remove(&id))
+                {
+                    histogram.record(sent.elapsed());
+                }
+            }
+        }
+        Ok(responses)
     }
 }
 
@@ -113,7 +349,23 @@ impl TransformConfig for QueryCounterConfig {
         &self,
         _transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
-        Ok(Box::new(QueryCounter::new(self.name.clone())))
+        let mut query_counter = QueryCounter::new(self.name.clone());
+        query_counter.latency = self.latency;
+        let max_annotation_values = self.max_annotation_values.unwrap_or(100);
+        query_counter.annotation_labels = self
+            .annotation_labels
+            .iter()
+            .map(|key| (key.clone(), LabelValues::new(max_annotation_values)))
+            .collect();
+        if self.table_label {
+            query_counter.tables = Some(Arc::new(LabelValues::new(self.max_tables.unwrap_or(100))));
+        }
+        if self.fingerprint_label {
+            query_counter.fingerprints = Some(Arc::new(LabelValues::new(
+                self.max_fingerprints.unwrap_or(100),
+            )));
+        }
+        Ok(Box::new(query_counter))
     }
 
     fn up_chain_protocol(&self) -> UpChainProtocol {
@@ -124,3 +376,117 @@ impl TransformConfig for QueryCounterConfig {
         DownChainProtocol::SameAsUpChain
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table_labels() {
+        let tables = LabelValues::new(2);
+        assert_eq!(tables.label("ks.a".to_owned()), "ks.a");
+        assert_eq!(tables.label("ks.b".to_owned()), "ks.b");
+        assert_eq!(tables.label("ks.c".to_owned()), "other");
+        assert_eq!(tables.label("ks.a".to_owned()), "ks.a");
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_latency() {
+        use crate::frame::RedisFrame;
+        use crate::message::Message;
+        use crate::transforms::chain::TransformAndMetrics;
+        use crate::transforms::loopback::Loopback;
+
+        let mut query_counter = QueryCounter::new("test".to_owned());
+        query_counter.latency = true;
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(vec![Message::from_frame(Frame::Redis(
+            RedisFrame::Array(vec![RedisFrame::BulkString("PING".into())]),
+        ))]);
+        chain_state.reset(&mut chain);
+        query_counter.transform(&mut chain_state).await.unwrap();
+
+        assert_eq!(query_counter.query_to_histogram.len(), 1);
+        assert!(query_counter.query_to_histogram.contains_key(&(
+            "PING".to_owned(),
+            "redis",
+            None,
+            None,
+            vec![]
+        )));
+        assert!(query_counter.pending_requests.is_empty());
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_annotation_labels() {
+        use crate::frame::RedisFrame;
+        use crate::message::Message;
+        use crate::transforms::chain::TransformAndMetrics;
+        use crate::transforms::loopback::Loopback;
+
+        let mut query_counter = QueryCounter::new("test".to_owned());
+        query_counter.annotation_labels = Arc::new([
+            ("tenant".to_owned(), LabelValues::new(1)),
+            ("cache".to_owned(), LabelValues::new(1)),
+        ]);
+        let request = |tenant: &'static str| {
+            let mut request = Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                RedisFrame::BulkString("GET".into()),
+                RedisFrame::BulkString("foo".into()),
+            ])));
+            request.annotate("tenant", tenant);
+            request
+        };
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(vec![request("acme"), request("initech")]);
+        chain_state.reset(&mut chain);
+        query_counter.transform(&mut chain_state).await.unwrap();
+
+        assert_eq!(query_counter.query_to_counter.len(), 2);
+        assert!(query_counter.query_to_counter.contains_key(&(
+            "GET".to_owned(),
+            "redis",
+            None,
+            None,
+            vec!["acme".to_owned(), "none".to_owned()]
+        )));
+        // annotation values beyond the limit are labelled as other
+        assert!(query_counter.query_to_counter.contains_key(&(
+            "GET".to_owned(),
+            "redis",
+            None,
+            None,
+            vec!["other".to_owned(), "none".to_owned()]
+        )));
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_fingerprint_label() {
+        use crate::frame::fingerprint::Fingerprint;
+        use crate::test_utils::redis_command;
+        use crate::transforms::chain::TransformAndMetrics;
+        use crate::transforms::loopback::Loopback;
+
+        let mut query_counter = QueryCounter::new("test".to_owned());
+        query_counter.fingerprints = Some(Arc::new(LabelValues::new(100)));
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(vec![
+            redis_command(&["get", "foo"]),
+            redis_command(&["GET", "bar"]),
+        ]);
+        chain_state.reset(&mut chain);
+        query_counter.transform(&mut chain_state).await.unwrap();
+
+        assert_eq!(query_counter.query_to_counter.len(), 1);
+        assert!(query_counter.query_to_counter.contains_key(&(
+            "GET".to_owned(),
+            "redis",
+            None,
+            Some(Fingerprint::of("GET ?").to_string()),
+            vec![]
+        )));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageId, MessageIdMap, Messages};
+use crate::transforms::auth::{AuthProviderConfig, Authenticator};
+use crate::transforms::redis::command_name;
+use crate::transforms::session::AuthenticatedUser;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The user that a client `AUTH` without a username authenticates as, matching redis.
+const DEFAULT_USER: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisAuthTerminationConfig {
+    /// Where the credentials presented by clients are checked.
+    pub auth_provider: AuthProviderConfig,
+    /// The username shotover authenticates with upstream, when None the `default` user is used.
+    pub upstream_username: Option<String>,
+    /// The password shotover authenticates with upstream, when None shotover does not authenticate upstream.
+    pub upstream_password: Option<String>,
+}
+
+const NAME: &str = "RedisAuthTermination";
+#[typetag::serde(name = "RedisAuthTermination")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisAuthTerminationConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let authenticator = self
+            .auth_provider
+            .build(&transform_context.chain_name, NAME)?;
+
+        let upstream_auth = self.upstream_password.as_ref().map(|password| {
+            let mut args = vec![RedisFrame::BulkString(Bytes::from_static(b"AUTH"))];
+            if let Some(username) = &self.upstream_username {
+                args.push(RedisFrame::BulkString(Bytes::from(username.clone())));
+            }
+            args.push(RedisFrame::BulkString(Bytes::from(password.clone())));
+            RedisFrame::Array(args)
+        });
+
+        Ok(Box::new(RedisAuthTerminationBuilder {
+            authenticator: Arc::new(authenticator),
+            upstream_auth,
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct RedisAuthTerminationBuilder {
+    authenticator: Arc<Authenticator>,
+    upstream_auth: Option<RedisFrame>,
+}
+
+impl TransformBuilder for RedisAuthTerminationBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisAuthTermination {
+            authenticator: self.authenticator.clone(),
+            upstream_auth: self.upstream_auth.clone(),
+            upstream_authenticated: false,
+            upstream_auth_requests: vec![],
+            local_responses: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+struct RedisAuthTermination {
+    authenticator: Arc<Authenticator>,
+    upstream_auth: Option<RedisFrame>,
+    upstream_authenticated: bool,
+    /// The ids of AUTH requests generated by this transform, their responses must not reach the client
+    upstream_auth_requests: Vec<MessageId>,
+    /// Responses generated by this transform, keyed by the id of the dummy request they respond to
+    local_responses: MessageIdMap<Message>,
+}
+
+fn error(message: &str) -> RedisFrame {
+    RedisFrame::Error(message.to_owned().into())
+}
+
+impl RedisAuthTermination {
+    /// Validates the client's AUTH request, returning the authenticated user on success along with the response to send to the client.
+    async fn authenticate(&self, request: &mut Message) -> (Option<String>, RedisFrame) {
+        let args: Vec<String> = match request.frame() {
+            Some(Frame::Redis(RedisFrame::Array(args))) => args[1..]
+                .iter()
+                .map(|x| match x {
+                    RedisFrame::BulkString(x) => String::from_utf8_lossy(x).into_owned(),
+                    _ => String::new(),
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let (username, password) = match args.as_slice() {
+            [password] => (DEFAULT_USER, password),
+            [username, password] => (username.as_str(), password),
+            _ => {
+                return (
+                    None,
+                    error("ERR wrong number of arguments for 'auth' command"),
+                )
+            }
+        };
+
+        match self.authenticator.authenticate(username, password).await {
+            Some(identity) => (Some(identity), RedisFrame::SimpleString("OK".into())),
+            None => (
+                None,
+                error("WRONGPASS invalid username-password pair or user is disabled."),
+            ),
+        }
+    }
+
+    /// Queues an AUTH with shotover's own credentials to go upstream ahead of the next forwarded request.
+    fn push_upstream_auth(&mut self, requests: &mut Messages) {
+        if let Some(auth) = &self.upstream_auth {
+            let request = Message::from_frame(Frame::Redis(auth.clone()));
+            self.upstream_auth_requests.push(request.id());
+            requests.push(request);
+        }
+        self.upstream_authenticated = true;
+    }
+
+    fn respond_locally(&mut self, mut request: Message, response: RedisFrame) -> Message {
+        let mut response = Message::from_frame(Frame::Redis(response));
+        response.set_request_id(request.id());
+        self.local_responses.insert(request.id(), response);
+        request.replace_with_dummy();
+        request
+    }
+}
+
+#[async_trait]
+impl Transform for RedisAuthTermination {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut requests = Vec::with_capacity(chain_state.requests.len() + 1);
+        for mut request in std::mem::take(&mut chain_state.requests) {
+            let command = match request.frame() {
+                Some(Frame::Redis(RedisFrame::Array(args))) => command_name(args),
+                _ => None,
+            };
+            match command.as_deref() {
+                Some(b"AUTH") => {
+                    let (user, response) = self.authenticate(&mut request).await;
+                    if let Some(user) = user {
+                        chain_state.session.insert(AuthenticatedUser(user));
+                    }
+                    requests.push(self.respond_locally(request, response));
+                }
+                Some(b"QUIT") => requests.push(request),
+                _ if chain_state.session.get::<AuthenticatedUser>().is_none() => {
+                    requests.push(
+                        self.respond_locally(request, error("NOAUTH Authentication required.")),
+                    );
+                }
+                Some(b"RESET") => {
+                    if !self.upstream_authenticated {
+                        self.push_upstream_auth(&mut requests);
+                    }
+                    requests.push(request);
+                    // RESET deauthenticates both the client and the upstream connection
+                    chain_state.session.remove::<AuthenticatedUser>();
+                    self.push_upstream_auth(&mut requests);
+                }
+                _ => {
+                    if !self.upstream_authenticated {
+                        self.push_upstream_auth(&mut requests);
+                    }
+                    requests.push(request);
+                }
+            }
+        }
+        chain_state.requests = requests;
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        if !self.upstream_auth_requests.is_empty() {
+            let mut upstream_error = None;
+            responses.retain_mut(|response| {
+                let Some(index) = response
+                    .request_id()
+                    .and_then(|id| self.upstream_auth_requests.iter().position(|x| *x == id))
+                else {
+                    return true;
+                };
+                self.upstream_auth_requests.swap_remove(index);
+                if let Some(Frame::Redis(RedisFrame::Error(err))) = response.frame() {
+                    upstream_error = Some(err.to_string());
+                }
+                false
+            });
+            if let Some(err) = upstream_error {
+                bail!("Failed to authenticate upstream: {err}");
+            }
+        }
+
+        if !self.local_responses.is_empty() {
+            for response in responses.iter_mut() {
+                if let Some(local) = response
+                    .request_id()
+                    .and_then(|id| self.local_responses.remove(&id))
+                {
+                    *response = local;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::auth::static_authenticator;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use crate::transforms::session::SessionState;
+    use pretty_assertions::assert_eq;
+
+    fn transform() -> RedisAuthTermination {
+        RedisAuthTermination {
+            authenticator: Arc::new(static_authenticator(&[("user", "pass")])),
+            upstream_auth: Some(RedisFrame::Array(vec![
+                RedisFrame::BulkString("AUTH".into()),
+                RedisFrame::BulkString("upstream_pass".into()),
+            ])),
+            upstream_authenticated: false,
+            upstream_auth_requests: vec![],
+            local_responses: MessageIdMap::default(),
+        }
+    }
+
+    async fn run(
+        transform: &mut RedisAuthTermination,
+        session: &mut SessionState,
+        requests: Messages,
+    ) -> Vec<Option<Frame>> {
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.session = std::mem::take(session);
+        chain_state.reset(&mut chain);
+        let responses = transform.transform(&mut chain_state).await.unwrap();
+        *session = std::mem::take(&mut chain_state.session);
+        responses
+            .into_iter()
+            .map(|mut x| x.frame().cloned())
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_auth_termination() {
+        let mut transform = transform();
+        let mut session = SessionState::default();
+
+        let responses = run(
+            &mut transform,
+            &mut session,
+            vec![
+                redis_command(&["GET", "foo"]),
+                redis_command(&["AUTH", "user", "wrong"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                Some(Frame::Redis(error("NOAUTH Authentication required."))),
+                Some(Frame::Redis(error(
+                    "WRONGPASS invalid username-password pair or user is disabled."
+                ))),
+            ]
+        );
+        assert!(!transform.upstream_authenticated);
+
+        // the loopback echoes requests back, so the upstream AUTH must be filtered out and the GET echoed
+        let responses = run(
+            &mut transform,
+            &mut session,
+            vec![
+                redis_command(&["AUTH", "user", "pass"]),
+                redis_command(&["GET", "foo"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                Some(Frame::Redis(RedisFrame::SimpleString("OK".into()))),
+                redis_command(&["GET", "foo"]).frame().cloned(),
+            ]
+        );
+        assert!(transform.upstream_authenticated);
+        assert!(transform.upstream_auth_requests.is_empty());
+        assert_eq!(session.get(), Some(&AuthenticatedUser("user".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_default_user() {
+        let mut transform = transform();
+        transform.authenticator = Arc::new(static_authenticator(&[(DEFAULT_USER, "pass")]));
+        let (user, _) = transform
+            .authenticate(&mut redis_command(&["AUTH", "pass"]))
+            .await;
+        assert_eq!(user.as_deref(), Some(DEFAULT_USER));
+        let (user, response) = transform.authenticate(&mut redis_command(&["AUTH"])).await;
+        assert_eq!(user, None);
+        assert_eq!(
+            response,
+            error("ERR wrong number of arguments for 'auth' command")
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Detection of redis commands that block until data is available or their timeout expires, such as BLPOP or XREAD BLOCK.
+
+use crate::frame::RedisFrame;
+use bytes::Bytes;
+use std::time::Duration;
+
+enum Timeout {
+    /// The index of a timeout argument in seconds, which may be fractional
+    Seconds(usize),
+    /// The index of a timeout argument in milliseconds
+    Milliseconds(usize),
+}
+
+fn timeout_argument(command: &[RedisFrame]) -> Option<Timeout> {
+    let Some(RedisFrame::BulkString(name)) = command.first() else {
+        return None;
+    };
+    match name.to_ascii_uppercase().as_slice() {
+        // COMMAND key [key ...] timeout
+        b"BLPOP" | b"BRPOP" | b"BRPOPLPUSH" | b"BLMOVE" | b"BZPOPMIN" | b"BZPOPMAX"
+            if command.len() >= 3 =>
+        {
+            Some(Timeout::Seconds(command.len() - 1))
+        }
+        // COMMAND timeout numkeys key [key ...] ...
+        b"BLMPOP" | b"BZMPOP" if command.len() >= 2 => Some(Timeout::Seconds(1)),
+        // XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+        b"XREAD" | b"XREADGROUP" => command
+            .iter()
+            .take_while(
+                |x| !matches!(x, RedisFrame::BulkString(x) if x.eq_ignore_ascii_case(b"STREAMS")),
+            )
+            .position(
+                |x| matches!(x, RedisFrame::BulkString(x) if x.eq_ignore_ascii_case(b"BLOCK")),
+            )
+            .filter(|position| position + 1 < command.len())
+            .map(|position| Timeout::Milliseconds(position + 1)),
+        _ => None,
+    }
+}
+
+/// Returns true if the command may block the connection it is sent on until data is available or its timeout expires.
+pub(crate) fn is_blocking(command: &[RedisFrame]) -> bool {
+    timeout_argument(command).is_some()
+}
+
+/// Lowers the timeout of a blocking command to `max`, including a timeout of 0 which blocks indefinitely.
+/// Returns true if the command was modified.
+pub(crate) fn limit_timeout(command: &mut [RedisFrame], max: Duration) -> bool {
+    let (index, timeout, limit) = match timeout_argument(command) {
+        Some(Timeout::Seconds(index)) => (index, max.as_secs_f64(), max.as_secs_f64().to_string()),
+        Some(Timeout::Milliseconds(index)) => {
+            (index, max.as_millis() as f64, max.as_millis().to_string())
+        }
+        None => return false,
+    };
+    let current = match &command[index] {
+        RedisFrame::BulkString(current) => std::str::from_utf8(current)
+            .ok()
+            .and_then(|x| x.parse::<f64>().ok()),
+        _ => None,
+    };
+    match current {
+        // an invalid timeout is left for redis to reject
+        None => false,
+        Some(current) if current > 0.0 && current <= timeout => false,
+        Some(current) if current < 0.0 => false,
+        Some(_) => {
+            command[index] = RedisFrame::BulkString(Bytes::from(limit));
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command_args;
+    use pretty_assertions::assert_eq;
+
+    fn limited(args: &[&str]) -> Vec<RedisFrame> {
+        let mut command = redis_command_args(args);
+        limit_timeout(&mut command, Duration::from_millis(1500));
+        command
+    }
+
+    #[test]
+    fn test_blocking_commands() {
+        assert!(is_blocking(&redis_command_args(&["BLPOP", "a", "b", "0"])));
+        assert!(is_blocking(&redis_command_args(&[
+            "bzmpop", "1", "1", "a", "MIN"
+        ])));
+        assert!(is_blocking(&redis_command_args(&[
+            "XREAD", "COUNT", "1", "BLOCK", "0", "STREAMS", "a", "$"
+        ])));
+        assert!(!is_blocking(&redis_command_args(&[
+            "XREAD", "STREAMS", "BLOCK", "$"
+        ])));
+        assert!(!is_blocking(&redis_command_args(&["LPOP", "a"])));
+
+        assert_eq!(
+            limited(&["BLPOP", "a", "0"]),
+            redis_command_args(&["BLPOP", "a", "1.5"])
+        );
+        assert_eq!(
+            limited(&["BRPOP", "a", "b", "10"]),
+            redis_command_args(&["BRPOP", "a", "b", "1.5"])
+        );
+        assert_eq!(
+            limited(&["BRPOP", "a", "0.5"]),
+            redis_command_args(&["BRPOP", "a", "0.5"])
+        );
+        assert_eq!(
+            limited(&["BLMPOP", "0", "1", "a", "LEFT"]),
+            redis_command_args(&["BLMPOP", "1.5", "1", "a", "LEFT"])
+        );
+        assert_eq!(
+            limited(&["XREAD", "BLOCK", "60000", "STREAMS", "a", "$"]),
+            redis_command_args(&["XREAD", "BLOCK", "1500", "STREAMS", "a", "$"])
+        );
+        assert_eq!(
+            limited(&["BLPOP", "a", "foo"]),
+            redis_command_args(&["BLPOP", "a", "foo"])
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use crate::config::chain::TransformChainConfig;
+use crate::frame::cassandra::CassandraResult;
+use crate::frame::fingerprint::normalize_cql_query;
+use crate::frame::value::GenericValue;
 use crate::frame::{CassandraFrame, CassandraOperation, Frame, MessageType, RedisFrame};
-use crate::message::{Message, MessageIdMap, Messages, Metadata};
+use crate::message::{Message, MessageId, MessageIdMap, Messages, Metadata};
+use crate::transforms::cassandra::schema::{ColumnSchema, Schema, SchemaCache};
 use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::util::in_flight::{remove_in_flight, Follower, InFlight};
 use crate::transforms::{
     ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
     TransformContextBuilder, TransformContextConfig, UpChainProtocol,
@@ -16,13 +21,18 @@ use cql3_parser::select::Select;
 use itertools::Itertools;
 use metrics::{counter, Counter};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
 use tracing::{error, warn};
 
 /// Data is stored in Redis as a Hash (hset/hget) and constructed from the cassandra SELECT statement
 /// * The name of the hash is constructed from: the FROM component and partition + range keys as per the TableCacheSchema configuration
-/// * The name of the field in the hash is constructed from: the SELECT component and the WHERE component excluding the partition + range keys used in the hash name
+/// * The name of the field in the hash is constructed from: the SELECT component and the WHERE component excluding the partition + range keys used in the hash name,
+///   identified by the [`key`](crate::frame::fingerprint::NormalizedQuery::key) of that text so that queries only differing in keyword case or whitespace share a field
 /// * The contents of field in the hash is: the raw bytes of a cassandra response from a SELECT
 ///
 /// The cache is addressed in this way to allow all caches matching a specific partition + range keys to be deleted at once when invalidated via an INSERT or UPDATE
@@ -33,13 +43,23 @@ use tracing::{error, warn};
 ///       keyspace1.table2:
 ///         partition_key: [e]
 ///         range_key: []
+///
+/// When `partition_key` is omitted the partition key and clustering columns of the table are taken from the schema
+/// fetched by the `CassandraSinkCluster` at the end of the chain instead.
 /// then this cassandra query:
 ///     `SELECT a, b, c as g FROM keyspace1.table2 WHERE e='foo' a[2]=3`
 /// will result in this redis command:
-///     `hset "keyspace1.table2:'foo'" "a b c WHERE a[2]=3" $SELECT_RESPONSE_BYTES`
+///     `hset "keyspace1.table2:'foo'" $KEY_OF_NORMALIZED("a b c WHERE a[2]=3") $SELECT_RESPONSE_BYTES`
 
 // TODO: ensure quoted identifiers wont cause collisions in the above described format
 
+/// The first byte of a cached "not found" result, followed by the time it expires in milliseconds since the unix epoch and then the cassandra response.
+/// Cassandra responses start with the protocol version so never start with this byte.
+const NEGATIVE_ENTRY_PREFIX: u8 = 0xFF;
+
+/// The field of the cached result of a `SELECT *` restricted only by the key of the hash, the only result that write-through updates in place
+static FULL_ROW_FIELD: LazyLock<Bytes> = LazyLock::new(|| query_field("* WHERE "));
+
 #[derive(Debug)]
 enum CacheableState {
     // The selected row should be added to the cache
@@ -55,8 +75,30 @@ enum CacheableState {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct TableCacheSchemaConfig {
+    /// When empty the partition key and range key are taken from the cassandra schema.
+    #[serde(default)]
     partition_key: Vec<String>,
+    #[serde(default)]
     range_key: Vec<String>,
+    /// How writes to the table update the cache, defaults to `Invalidate`.
+    #[serde(default)]
+    write_mode: CacheWriteMode,
+    /// When set, SELECTs that return no rows are only cached for this many seconds.
+    /// Otherwise they are cached until invalidated by a write, like any other result.
+    negative_ttl_seconds: Option<u64>,
+    /// When true, identical SELECTs that miss the cache while one of them is already being read from cassandra wait for its response instead of also reading from cassandra.
+    #[serde(default)]
+    single_flight: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum CacheWriteMode {
+    /// Writes delete the cached results of the rows they modify.
+    #[default]
+    Invalidate,
+    /// INSERTs update the cached result of the row they modify before the response is returned to the client.
+    /// Writes whose effect on the cached results cannot be determined fall back to deleting them.
+    WriteThrough,
 }
 
 #[derive(Debug, Clone)]
@@ -65,15 +107,58 @@ pub struct TableCacheSchema {
     range_key: Vec<Identifier>,
 }
 
-impl From<&TableCacheSchemaConfig> for TableCacheSchema {
-    fn from(cfg: &TableCacheSchemaConfig) -> Self {
-        TableCacheSchema {
+impl TableCacheSchema {
+    /// Returns None when the keys are not configured, in which case they are taken from the cassandra schema
+    fn from_config(cfg: &TableCacheSchemaConfig) -> Option<Self> {
+        if cfg.partition_key.is_empty() {
+            return None;
+        }
+        Some(TableCacheSchema {
             partition_key: cfg
                 .partition_key
                 .iter()
                 .map(|s| Identifier::parse(s))
                 .collect(),
             range_key: cfg.range_key.iter().map(|s| Identifier::parse(s)).collect(),
+        })
+    }
+
+    /// Returns None when the table is not in the schema, e.g. because the schema has not been fetched yet
+    fn from_schema(schema: &Schema, table_name: &FQName) -> Option<Self> {
+        let table = schema.table_by_name(table_name)?;
+        Some(TableCacheSchema {
+            partition_key: table.partition_key().into_iter().map(identifier).collect(),
+            range_key: table.clustering_key().into_iter().map(identifier).collect(),
+        })
+    }
+}
+
+/// Returns the identifier that a statement refers to the column by, lowercase names are usually written unquoted
+fn identifier(column: &ColumnSchema) -> Identifier {
+    if column.name.chars().any(|c| c.is_uppercase()) {
+        Identifier::Quoted(column.name.clone())
+    } else {
+        Identifier::Unquoted(column.name.clone())
+    }
+}
+
+/// How the results of a table are cached
+#[derive(Debug, Clone)]
+struct TableCache {
+    /// None when the keys are taken from the cassandra schema
+    schema: Option<TableCacheSchema>,
+    write_mode: CacheWriteMode,
+    negative_ttl_seconds: Option<u64>,
+    single_flight: bool,
+}
+
+impl From<&TableCacheSchemaConfig> for TableCache {
+    fn from(cfg: &TableCacheSchemaConfig) -> Self {
+        TableCache {
+            schema: TableCacheSchema::from_config(cfg),
+            write_mode: cfg.write_mode,
+            negative_ttl_seconds: cfg.negative_ttl_seconds,
+            single_flight: cfg.single_flight,
         }
     }
 }
@@ -95,7 +180,7 @@ impl TransformConfig for RedisConfig {
     ) -> Result<Box<dyn TransformBuilder>> {
         let missed_requests = counter!("shotover_cache_miss_count");
 
-        let caching_schema: HashMap<FQName, TableCacheSchema> = self
+        let caching_schema: HashMap<FQName, TableCache> = self
             .caching_schema
             .iter()
             .map(|(k, v)| (FQName::parse(k), v.into()))
@@ -110,6 +195,7 @@ impl TransformConfig for RedisConfig {
             cache_chain: self.chain.get_builder(transform_context_config).await?,
             caching_schema,
             missed_requests,
+            in_flight: Default::default(),
         }))
     }
 
@@ -124,8 +210,10 @@ impl TransformConfig for RedisConfig {
 
 pub struct SimpleRedisCacheBuilder {
     cache_chain: TransformChainBuilder,
-    caching_schema: HashMap<FQName, TableCacheSchema>,
+    caching_schema: HashMap<FQName, TableCache>,
     missed_requests: Counter,
+    /// Cache misses of `single_flight` tables being read from cassandra by any connection
+    in_flight: Arc<Mutex<HashMap<InFlightKey, Arc<InFlight>>>>,
 }
 
 impl TransformBuilder for SimpleRedisCacheBuilder {
@@ -137,6 +225,11 @@ impl TransformBuilder for SimpleRedisCacheBuilder {
             pending_cache_requests: Default::default(),
             cache_hit_cassandra_responses: vec![],
             cache_miss_cassandra_requests: vec![],
+            in_flight: self.in_flight.clone(),
+            schema_cache: transform_context.schema_cache.clone(),
+            force_run_chain: transform_context.force_run_chain,
+            leaders: Default::default(),
+            followers: Default::default(),
         })
     }
 
@@ -160,37 +253,92 @@ impl TransformBuilder for SimpleRedisCacheBuilder {
     }
 }
 
+/// Identifies identical cache misses, the protocol version is included since the response is shared between them
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+struct InFlightKey {
+    version: u8,
+    address: HashAddress,
+}
+
+/// A cassandra request whose result is being read from the cache
+struct PendingCacheRequest {
+    request: Message,
+    /// Set when the table is `single_flight`
+    in_flight_key: Option<InFlightKey>,
+}
+
+/// An INSERT to a `WriteThrough` table whose values are to be written to the cached result of the row
+struct WriteThrough {
+    key: Bytes,
+    values: Vec<(Identifier, Operand)>,
+}
+
 pub struct SimpleRedisCache {
     cache_chain: TransformChain,
-    caching_schema: HashMap<FQName, TableCacheSchema>,
+    caching_schema: HashMap<FQName, TableCache>,
     missed_requests: Counter,
-    pending_cache_requests: MessageIdMap<Message>,
+    pending_cache_requests: MessageIdMap<PendingCacheRequest>,
 
     /// cleared by the end of every `Transform::transform` call, stored here to avoid reallocation
     cache_hit_cassandra_responses: Vec<Message>,
     /// cleared by the end of every `Transform::transform` call, stored here to avoid reallocation
     cache_miss_cassandra_requests: Vec<Message>,
+
+    in_flight: Arc<Mutex<HashMap<InFlightKey, Arc<InFlight>>>>,
+    /// Provides the keys of tables that do not configure them
+    schema_cache: Option<SchemaCache>,
+    force_run_chain: Arc<Notify>,
+    /// Cache misses sent to cassandra that identical cache misses may be waiting on, keyed by request id
+    leaders: MessageIdMap<(InFlightKey, Arc<InFlight>)>,
+    /// Cache misses waiting on an identical cache miss, keyed by request id
+    followers: MessageIdMap<Follower>,
 }
 
 impl SimpleRedisCache {
-    fn build_cache_query(&mut self, request: &mut Message) -> Option<Message> {
+    /// Returns how the table is cached along with its keys.
+    /// Returns None if the table is not cached or its keys are not yet known.
+    fn table_cache(&self, table_name: &FQName) -> Option<(&TableCache, Cow<'_, TableCacheSchema>)> {
+        let table_cache = self.caching_schema.get(table_name)?;
+        let schema = match &table_cache.schema {
+            Some(schema) => Cow::Borrowed(schema),
+            None => Cow::Owned(TableCacheSchema::from_schema(
+                &self.schema_cache.as_ref()?.get(),
+                table_name,
+            )?),
+        };
+        Some((table_cache, schema))
+    }
+
+    fn build_cache_query(
+        &mut self,
+        request: &mut Message,
+    ) -> Option<(Message, Option<InFlightKey>)> {
         if let Some(Frame::Cassandra(CassandraFrame {
             operation: CassandraOperation::Query { query, .. },
+            version,
             ..
         })) = request.frame()
         {
             if let CacheableState::CacheRow = is_cacheable(query) {
                 if let Some(table_name) = query.get_table_name() {
-                    if let Some(table_cache_schema) = self.caching_schema.get(table_name) {
-                        match build_redis_key_from_cql3(query, table_cache_schema) {
+                    if let Some((table_cache, schema)) = self.table_cache(table_name) {
+                        match build_redis_key_from_cql3(query, &schema) {
                             Ok(address) => {
-                                return Some(Message::from_frame_diverged(
-                                    Frame::Redis(RedisFrame::Array(vec![
-                                        RedisFrame::BulkString("HGET".into()),
-                                        RedisFrame::BulkString(address.key),
-                                        RedisFrame::BulkString(address.field),
-                                    ])),
-                                    request,
+                                let in_flight_key =
+                                    table_cache.single_flight.then(|| InFlightKey {
+                                        version: u8::from(*version),
+                                        address: address.clone(),
+                                    });
+                                return Some((
+                                    Message::from_frame_diverged(
+                                        Frame::Redis(RedisFrame::Array(vec![
+                                            RedisFrame::BulkString("HGET".into()),
+                                            RedisFrame::BulkString(address.key),
+                                            RedisFrame::BulkString(address.field),
+                                        ])),
+                                        request,
+                                    ),
+                                    in_flight_key,
                                 ));
                             }
                             Err(_e) => {} // TODO match Err(()) here or just have build_redis_key_from_cql3 return Option
@@ -205,7 +353,7 @@ impl SimpleRedisCache {
 
     fn unwrap_cache_response(&mut self, redis_responses: Messages) {
         for mut redis_response in redis_responses {
-            let original_request = self
+            let pending = self
                 .pending_cache_requests
                 .remove(
                     &redis_response
@@ -213,6 +361,7 @@ impl SimpleRedisCache {
                         .expect("This must have a request, since we dont use redis pubsub"),
                 )
                 .expect("There must be a pending request, since we store a pending request for all redis requests");
+            let original_request = &pending.request;
             let cassandra_frame = match redis_response.frame() {
                 Some(Frame::Redis(redis_frame)) => {
                     match redis_frame {
@@ -221,9 +370,13 @@ impl SimpleRedisCache {
                             None
                         }
                         RedisFrame::BulkString(redis_bytes) => {
-                            match CassandraFrame::from_bytes(redis_bytes.clone(), Compression::None)
-                            {
-                                Ok(mut response_frame) => {
+                            match decode_cache_entry(redis_bytes) {
+                                // An expired "not found" result is a miss
+                                None => {
+                                    self.missed_requests.increment(1);
+                                    None
+                                }
+                                Some(Ok(mut response_frame)) => {
                                     match original_request.metadata() {
                                         Ok(Metadata::Cassandra(meta)) => {
                                             if response_frame.version == meta.version {
@@ -246,7 +399,7 @@ impl SimpleRedisCache {
                                         }
                                     }
                                 }
-                                Err(err) => {
+                                Some(Err(err)) => {
                                     error!("Failed to decode cached cassandra message {err:?}");
                                     None
                                 }
@@ -263,17 +416,62 @@ impl SimpleRedisCache {
             };
             match cassandra_frame {
                 Some(cassandra_frame) => {
-                    self.cache_hit_cassandra_responses
-                        .push(Message::from_frame_diverged(
-                            Frame::Cassandra(cassandra_frame),
-                            &redis_response,
-                        ));
+                    let mut response = Message::from_frame_diverged(
+                        Frame::Cassandra(cassandra_frame),
+                        &redis_response,
+                    );
+                    response.annotate("cache", "hit");
+                    self.cache_hit_cassandra_responses.push(response);
                 }
-                None => self.cache_miss_cassandra_requests.push(original_request),
+                None => self.cache_miss(pending),
             }
         }
     }
 
+    /// Sends the request to cassandra, unless it is `single_flight` and an identical request is already being sent to cassandra.
+    fn cache_miss(&mut self, pending: PendingCacheRequest) {
+        let PendingCacheRequest {
+            mut request,
+            in_flight_key,
+        } = pending;
+        request.annotate("cache", "miss");
+        let Some(key) = in_flight_key else {
+            self.cache_miss_cassandra_requests.push(request);
+            return;
+        };
+        let in_flight = self.in_flight.clone();
+        let mut in_flight = in_flight.lock().unwrap();
+        match in_flight.get(&key) {
+            Some(leader) => match request.metadata() {
+                Ok(metadata) => {
+                    leader.add_waiter(self.force_run_chain.clone());
+                    self.followers.insert(
+                        request.id(),
+                        Follower {
+                            metadata,
+                            in_flight: leader.clone(),
+                        },
+                    );
+                }
+                Err(_) => self.cache_miss_cassandra_requests.push(request),
+            },
+            None => {
+                let leader = Arc::new(InFlight::default());
+                in_flight.insert(key.clone(), leader.clone());
+                self.leaders.insert(request.id(), (key, leader));
+                self.cache_miss_cassandra_requests.push(request);
+            }
+        }
+    }
+
+    /// Fails every cache miss waiting on a request sent to cassandra by this connection
+    fn fail_leaders(&mut self) {
+        for (_, (key, leader)) in std::mem::take(&mut self.leaders) {
+            remove_in_flight(&self.in_flight, &key, &leader);
+            leader.complete(None);
+        }
+    }
+
     async fn read_from_cache(
         &mut self,
         cassandra_requests: &mut Messages,
@@ -284,9 +482,14 @@ impl SimpleRedisCache {
         for mut cassandra_request in cassandra_requests.drain(..) {
             match self.build_cache_query(&mut cassandra_request) {
                 // The request is cacheable, store the cassandra request for later and send the redis request
-                Some(redis_request) => {
-                    self.pending_cache_requests
-                        .insert(cassandra_request.id(), cassandra_request);
+                Some((redis_request, in_flight_key)) => {
+                    self.pending_cache_requests.insert(
+                        cassandra_request.id(),
+                        PendingCacheRequest {
+                            request: cassandra_request,
+                            in_flight_key,
+                        },
+                    );
                     redis_requests.push(redis_request);
                 }
                 // The request is not cacheable, add it directly to the cache miss list
@@ -322,10 +525,10 @@ impl SimpleRedisCache {
         response: &Message,
     ) -> Option<Message> {
         if let Some(table_name) = statement.get_table_name() {
-            if let Some(table_cache_schema) = self.caching_schema.get(table_name) {
+            if let Some((_, schema)) = self.table_cache(table_name) {
                 if let Ok(address) =
                     // TODO: handle errors
-                    build_redis_key_from_cql3(statement, table_cache_schema)
+                    build_redis_key_from_cql3(statement, &schema)
                 {
                     return Some(Message::from_frame_at_instant(
                         Frame::Redis(RedisFrame::Array(vec![
@@ -340,22 +543,138 @@ impl SimpleRedisCache {
         None
     }
 
+    /// Returns the values to write to the cached result of the row if the statement is a successful INSERT to a `WriteThrough` table
+    fn write_through(
+        &self,
+        statement: &CassandraStatement,
+        response: &mut Message,
+    ) -> Option<WriteThrough> {
+        let CassandraStatement::Insert(insert) = statement else {
+            return None;
+        };
+        // The row may expire or the condition may not have applied, so the result cannot be determined from the INSERT alone
+        if insert.using_ttl.is_some() || insert.if_not_exists {
+            return None;
+        }
+        let (table_cache, schema) = self.table_cache(&insert.table_name)?;
+        if table_cache.write_mode != CacheWriteMode::WriteThrough {
+            return None;
+        }
+        if !matches!(
+            response.frame(),
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Void),
+                ..
+            }))
+        ) {
+            return None;
+        }
+        let address = build_redis_key_from_cql3(statement, &schema).ok()?;
+        Some(WriteThrough {
+            key: address.key,
+            values: insert
+                .get_value_map()
+                .into_iter()
+                .map(|(column_name, operand)| (column_name, operand.clone()))
+                .collect(),
+        })
+    }
+
+    /// Returns the requests that write the INSERTs to the cached results of their rows.
+    /// Cached results that cannot be updated in place are deleted instead.
+    async fn write_through_messages(
+        &mut self,
+        writes: Vec<WriteThrough>,
+        local_addr: SocketAddr,
+    ) -> Messages {
+        let requests: Messages = writes
+            .iter()
+            .map(|write| {
+                Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString("HGETALL".into()),
+                    RedisFrame::BulkString(write.key.clone()),
+                ])))
+            })
+            .collect();
+        let request_ids: Vec<MessageId> = requests.iter().map(|x| x.id()).collect();
+        let mut responses: MessageIdMap<Message> = match self
+            .cache_chain
+            .process_request(&mut ChainState::new_with_addr(requests, local_addr))
+            .await
+        {
+            Ok(responses) => responses
+                .into_iter()
+                .filter_map(|response| Some((response.request_id()?, response)))
+                .collect(),
+            Err(err) => {
+                warn!("Cache error: {err}");
+                MessageIdMap::default()
+            }
+        };
+
+        writes
+            .into_iter()
+            .zip(request_ids)
+            .filter_map(|(write, request_id)| {
+                let patched = responses
+                    .remove(&request_id)
+                    .and_then(|mut response| match response.frame() {
+                        Some(Frame::Redis(RedisFrame::Array(entries))) => {
+                            patch_cached_row(entries, &write.values)
+                        }
+                        _ => None,
+                    });
+                let command = match patched {
+                    // nothing is cached for the row
+                    Some(fields) if fields.is_empty() => return None,
+                    Some(fields) => {
+                        let mut command = vec![
+                            RedisFrame::BulkString("HSET".into()),
+                            RedisFrame::BulkString(write.key),
+                        ];
+                        command.extend(fields);
+                        command
+                    }
+                    None => vec![
+                        RedisFrame::BulkString("DEL".into()),
+                        RedisFrame::BulkString(write.key),
+                    ],
+                };
+                Some(Message::from_frame(Frame::Redis(RedisFrame::Array(
+                    command,
+                ))))
+            })
+            .collect()
+    }
+
     fn cache_row(
         &mut self,
         statement: &CassandraStatement,
         response: &mut Message,
     ) -> Result<Option<Message>> {
         if let Some(table_name) = statement.get_table_name() {
-            if let Some(table_cache_schema) = self.caching_schema.get(table_name) {
+            if let Some((table_cache, schema)) = self.table_cache(table_name) {
                 if let Ok(address) =
                     // TODO: handle errors
-                    build_redis_key_from_cql3(statement, table_cache_schema)
+                    build_redis_key_from_cql3(statement, &schema)
                 {
                     if let Some(Frame::Cassandra(frame)) = response.frame() {
                         // TODO: two performance issues here:
                         // 1. we should be able to generate the encoded bytes without cloning the entire frame
                         // 2. we should be able to directly use the raw bytes when the message has not yet been mutated
-                        let encoded = frame.clone().encode(Compression::None);
+                        let mut encoded = frame.clone().encode(Compression::None);
+
+                        let not_found = matches!(
+                            &frame.operation,
+                            CassandraOperation::Result(CassandraResult::Rows { rows, .. }) if rows.is_empty()
+                        );
+                        if let (true, Some(ttl)) = (not_found, table_cache.negative_ttl_seconds) {
+                            let expires_at = now_ms().saturating_add(ttl.saturating_mul(1000));
+                            let mut entry = vec![NEGATIVE_ENTRY_PREFIX];
+                            entry.extend(expires_at.to_be_bytes());
+                            entry.extend(encoded);
+                            encoded = entry;
+                        }
 
                         return Ok(Some(Message::from_frame_at_instant(
                             Frame::Redis(RedisFrame::Array(vec![
@@ -387,6 +706,7 @@ impl SimpleRedisCache {
         let mut response_messages = chain_state.call_next_transform().await?;
 
         let mut cache_messages = vec![];
+        let mut writes = vec![];
         for (request, response) in request_messages
             .iter_mut()
             .zip(response_messages.iter_mut())
@@ -395,7 +715,9 @@ impl SimpleRedisCache {
                 for statement in operation.queries() {
                     match is_cacheable(statement) {
                         CacheableState::DeleteRow => {
-                            if let Some(message) = self.delete_row(statement, response) {
+                            if let Some(write) = self.write_through(statement, response) {
+                                writes.push(write);
+                            } else if let Some(message) = self.delete_row(statement, response) {
                                 cache_messages.push(message);
                             }
                         }
@@ -412,6 +734,9 @@ impl SimpleRedisCache {
                 }
             }
         }
+        if !writes.is_empty() {
+            cache_messages.extend(self.write_through_messages(writes, local_addr).await);
+        }
         if !cache_messages.is_empty() {
             let result = self
                 .cache_chain
@@ -425,6 +750,108 @@ impl SimpleRedisCache {
     }
 }
 
+impl Drop for SimpleRedisCache {
+    fn drop(&mut self) {
+        self.fail_leaders();
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Decodes a cached result, returning None if it is a "not found" result that has expired
+fn decode_cache_entry(entry: &Bytes) -> Option<Result<CassandraFrame>> {
+    let response = match entry.first() {
+        Some(&NEGATIVE_ENTRY_PREFIX) => {
+            let expires_at = u64::from_be_bytes(entry.get(1..9)?.try_into().unwrap());
+            if expires_at <= now_ms() {
+                return None;
+            }
+            entry.slice(9..)
+        }
+        _ => entry.clone(),
+    };
+    Some(CassandraFrame::from_bytes(response, Compression::None))
+}
+
+/// Applies the INSERTed values to the cached results of a row, given as the field value pairs of its hash.
+/// Returns the updated field value pairs, or None if any of the cached results cannot be updated in place.
+fn patch_cached_row(
+    entries: &[RedisFrame],
+    values: &[(Identifier, Operand)],
+) -> Option<Vec<RedisFrame>> {
+    let mut patched = vec![];
+    for entry in entries.chunks(2) {
+        let [RedisFrame::BulkString(field), RedisFrame::BulkString(cached)] = entry else {
+            return None;
+        };
+        // Other results may be restricted by other columns or only select some columns under an alias
+        if *field != *FULL_ROW_FIELD || cached.first() == Some(&NEGATIVE_ENTRY_PREFIX) {
+            return None;
+        }
+        let mut frame = CassandraFrame::from_bytes(cached.clone(), Compression::None).ok()?;
+        let CassandraOperation::Result(CassandraResult::Rows { rows, metadata }) =
+            &mut frame.operation
+        else {
+            return None;
+        };
+        let [row] = rows.as_mut_slice() else {
+            return None;
+        };
+        for (column, value) in values {
+            let name = column.to_string();
+            let index = metadata
+                .col_specs
+                .iter()
+                .position(|spec| spec.name == name.trim_matches('"'))?;
+            row[index] = literal_to_value(value, &row[index])?;
+        }
+        patched.push(RedisFrame::BulkString(field.clone()));
+        patched.push(RedisFrame::BulkString(
+            frame.encode(Compression::None).into(),
+        ));
+    }
+    Some(patched)
+}
+
+/// Converts a CQL literal to a value of the same type as the cached value it replaces, returning None for unsupported types
+fn literal_to_value(literal: &Operand, cached: &GenericValue) -> Option<GenericValue> {
+    let literal = match literal {
+        Operand::Null => return Some(GenericValue::Null),
+        Operand::Const(literal) => literal,
+        _ => return None,
+    };
+    let unquote = || {
+        literal
+            .strip_prefix('\'')?
+            .strip_suffix('\'')
+            .map(|x| x.replace("''", "'"))
+    };
+    match cached {
+        GenericValue::Integer(_, size) => literal
+            .parse()
+            .ok()
+            .map(|x| GenericValue::Integer(x, size.clone())),
+        GenericValue::Boolean(_) => match literal.to_ascii_lowercase().as_str() {
+            "true" => Some(GenericValue::Boolean(true)),
+            "false" => Some(GenericValue::Boolean(false)),
+            _ => None,
+        },
+        GenericValue::Double(_) => literal
+            .parse::<f64>()
+            .ok()
+            .map(|x| GenericValue::Double(x.into())),
+        GenericValue::Varchar(_) => unquote().map(GenericValue::Varchar),
+        GenericValue::Ascii(_) => unquote().map(GenericValue::Ascii),
+        GenericValue::Strings(_) => unquote().map(GenericValue::Strings),
+        _ => None,
+    }
+}
+
 fn is_cacheable(statement: &CassandraStatement) -> CacheableState {
     match statement {
         CassandraStatement::Select(select) => {
@@ -519,7 +946,12 @@ fn build_query_redis_field_from_value_map(
             .as_str(),
     );
 
-    Bytes::from(str)
+    query_field(&str)
+}
+
+/// The field of the cached result of a query, given the text of its SELECT and WHERE components
+fn query_field(query: &str) -> Bytes {
+    normalize_cql_query(query).key().into()
 }
 
 fn populate_value_map_from_where_clause(
@@ -536,7 +968,7 @@ fn populate_value_map_from_where_clause(
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 struct HashAddress {
     key: Bytes,
     field: Bytes,
@@ -618,6 +1050,11 @@ impl Transform for SimpleRedisCache {
         NAME
     }
 
+    async fn on_shutdown(&mut self) -> Result<()> {
+        self.cache_chain.shutdown().await;
+        Ok(())
+    }
+
     async fn transform<'shorter, 'longer: 'shorter>(
         &mut self,
         chain_state: &'shorter mut ChainState<'longer>,
@@ -633,9 +1070,34 @@ impl Transform for SimpleRedisCache {
             &mut chain_state.requests,
             &mut self.cache_miss_cassandra_requests,
         );
-        let mut responses = self
-            .execute_upstream_and_write_to_cache(chain_state)
-            .await?;
+        let mut responses = match self.execute_upstream_and_write_to_cache(chain_state).await {
+            Ok(responses) => responses,
+            Err(err) => {
+                self.fail_leaders();
+                return Err(err);
+            }
+        };
+
+        // share the responses of single flight cache misses with the identical cache misses waiting on them
+        for response in &responses {
+            if let Some((key, leader)) = response
+                .request_id()
+                .and_then(|id| self.leaders.remove(&id))
+            {
+                remove_in_flight(&self.in_flight, &key, &leader);
+                leader.complete(Some(response.clone_with_new_id()));
+            }
+        }
+        let mut completed = vec![];
+        for (request_id, follower) in self.followers.iter() {
+            if let Some(response) = follower.in_flight.response() {
+                completed.push((*request_id, follower.response(*request_id, response)?));
+            }
+        }
+        for (request_id, response) in completed {
+            self.followers.remove(&request_id);
+            responses.push(response);
+        }
 
         // add the cache hits to the final response
         responses.append(&mut self.cache_hit_cassandra_responses);
@@ -647,15 +1109,20 @@ impl Transform for SimpleRedisCache {
 #[cfg(test)]
 mod test {
     use crate::frame::cassandra::parse_statement_single;
+    use crate::frame::value::GenericValue;
+    use crate::frame::value::IntSize;
+    use crate::frame::RedisFrame;
+    use crate::transforms::cassandra::schema::Schema;
     use crate::transforms::chain::TransformChainBuilder;
     use crate::transforms::debug::printer::DebugPrinter;
     use crate::transforms::null::NullSink;
     use crate::transforms::redis::cache::{
-        build_redis_key_from_cql3, HashAddress, SimpleRedisCacheBuilder, TableCacheSchema,
+        build_redis_key_from_cql3, decode_cache_entry, literal_to_value, patch_cached_row,
+        query_field, HashAddress, SimpleRedisCacheBuilder, TableCacheSchema, NEGATIVE_ENTRY_PREFIX,
     };
     use crate::transforms::TransformBuilder;
     use bytes::Bytes;
-    use cql3_parser::common::Identifier;
+    use cql3_parser::common::{FQName, Identifier, Operand};
     use metrics::counter;
     use pretty_assertions::assert_eq;
     use std::collections::HashMap;
@@ -673,9 +1140,44 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1:123:965"),
-                field: Bytes::from("* WHERE "),
+                field: query_field("* WHERE "),
+            }
+        );
+    }
+
+    #[test]
+    fn schema_keys_test() {
+        let row = |name: &str, kind: &str, position: i64| {
+            vec![
+                GenericValue::Varchar("ks".to_owned()),
+                GenericValue::Varchar("t".to_owned()),
+                GenericValue::Varchar(name.to_owned()),
+                GenericValue::Varchar(kind.to_owned()),
+                GenericValue::Integer(position, IntSize::I32),
+                GenericValue::Varchar("int".to_owned()),
+            ]
+        };
+        let schema = Schema::from_columns_rows(vec![
+            row("y", "clustering", 1),
+            row("z", "partition_key", 0),
+            row("X", "clustering", 0),
+            row("v", "regular", -1),
+        ])
+        .unwrap();
+        let table_cache_schema =
+            TableCacheSchema::from_schema(&schema, &FQName::parse("ks.t")).unwrap();
+
+        let ast =
+            parse_statement_single("SELECT * FROM ks.t WHERE z = 1 AND \"X\" = 123 AND y = 965");
+
+        assert_eq!(
+            build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
+            HashAddress {
+                key: Bytes::from("ks.t:1:123:965"),
+                field: query_field("* WHERE "),
             }
         );
+        assert!(TableCacheSchema::from_schema(&schema, &FQName::parse("t")).is_none());
     }
 
     #[test]
@@ -763,7 +1265,7 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1"),
-                field: Bytes::from("* WHERE x > 123 AND x < 999"),
+                field: query_field("* WHERE x > 123 AND x < 999"),
             }
         );
     }
@@ -781,7 +1283,7 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1"),
-                field: Bytes::from("* WHERE x >= 123 AND x <= 999"),
+                field: query_field("* WHERE x >= 123 AND x <= 999"),
             }
         );
     }
@@ -801,7 +1303,7 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("test_cache_keyspace_simple.test_table:1"),
-                field: Bytes::from("id, x, name WHERE ")
+                field: query_field("id, x, name WHERE ")
             }
         );
     }
@@ -819,7 +1321,7 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1:2"),
-                field: Bytes::from("thing WHERE ")
+                field: query_field("thing WHERE ")
             }
         );
     }
@@ -837,7 +1339,7 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1"),
-                field: Bytes::from("* WHERE x >= 123")
+                field: query_field("* WHERE x >= 123")
             }
         );
 
@@ -847,17 +1349,66 @@ mod test {
             build_redis_key_from_cql3(&ast, &table_cache_schema).unwrap(),
             HashAddress {
                 key: Bytes::from("foo:1"),
-                field: Bytes::from("* WHERE x <= 123")
+                field: query_field("* WHERE x <= 123")
             }
         );
     }
 
+    #[test]
+    fn expired_negative_entry_test() {
+        let mut entry = vec![NEGATIVE_ENTRY_PREFIX];
+        entry.extend(1u64.to_be_bytes());
+        entry.extend(b"ignored");
+        assert!(decode_cache_entry(&Bytes::from(entry)).is_none());
+
+        let mut entry = vec![NEGATIVE_ENTRY_PREFIX];
+        entry.extend(u64::MAX.to_be_bytes());
+        entry.extend(b"not a frame");
+        assert!(matches!(
+            decode_cache_entry(&Bytes::from(entry)),
+            Some(Err(_))
+        ));
+    }
+
+    #[test]
+    fn literal_to_value_test() {
+        let literal = |x: &str| Operand::Const(x.to_owned());
+        assert_eq!(
+            literal_to_value(&literal("5"), &GenericValue::Integer(1, IntSize::I32)),
+            Some(GenericValue::Integer(5, IntSize::I32))
+        );
+        assert_eq!(
+            literal_to_value(&literal("'it''s'"), &GenericValue::Varchar("".to_owned())),
+            Some(GenericValue::Varchar("it's".to_owned()))
+        );
+        assert_eq!(
+            literal_to_value(&Operand::Null, &GenericValue::Boolean(true)),
+            Some(GenericValue::Null)
+        );
+        // the type of a null cached value is not known
+        assert_eq!(literal_to_value(&literal("5"), &GenericValue::Null), None);
+    }
+
+    #[test]
+    fn patch_cached_row_test() {
+        let values = vec![(Identifier::parse("v"), Operand::Const("1".to_owned()))];
+        assert_eq!(patch_cached_row(&[], &values), Some(vec![]));
+
+        // results restricted by other columns cannot be updated in place
+        let entries = vec![
+            RedisFrame::BulkString(query_field("* WHERE x >= 1")),
+            RedisFrame::BulkString("cached".into()),
+        ];
+        assert_eq!(patch_cached_row(&entries, &values), None);
+    }
+
     #[test]
     fn test_validate_invalid_chain() {
         let transform = SimpleRedisCacheBuilder {
             cache_chain: TransformChainBuilder::new(vec![], "test-chain"),
             caching_schema: HashMap::new(),
             missed_requests: counter!("cache_miss"),
+            in_flight: Default::default(),
         };
 
         assert_eq!(
@@ -885,6 +1436,7 @@ mod test {
             cache_chain,
             caching_schema: HashMap::new(),
             missed_requests: counter!("cache_miss"),
+            in_flight: Default::default(),
         };
 
         assert_eq!(transform.validate(), Vec::<String>::new());
@@ -0,0 +1,401 @@
+use crate::client_connections::{self, ConnectionReport};
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::session::ClientConnectionId;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisClientCommandsConfig;
+
+const NAME: &str = "RedisClientCommands";
+#[typetag::serde(name = "RedisClientCommands")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisClientCommandsConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisClientCommandsBuilder {
+            source: transform_context.chain_name.into(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct RedisClientCommandsBuilder {
+    source: Arc<str>,
+}
+
+impl TransformBuilder for RedisClientCommandsBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisClientCommands {
+            source: self.source.clone(),
+            local_responses: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Answers `CLIENT` commands about the client connections of the source, so that clients never see the pooled upstream connections.
+struct RedisClientCommands {
+    /// Only connections to the source of this chain are visible to its clients
+    source: Arc<str>,
+    /// Responses generated by this transform, keyed by the id of the dummy request they respond to
+    local_responses: MessageIdMap<Message>,
+}
+
+/// Matches the connections a `CLIENT KILL` filter applies to
+type ClientFilter = Box<dyn Fn(&ConnectionReport) -> bool>;
+
+fn error(message: &str) -> RedisFrame {
+    RedisFrame::Error(message.to_owned().into())
+}
+
+fn ok() -> RedisFrame {
+    RedisFrame::SimpleString("OK".into())
+}
+
+fn wrong_arguments(sub_command: &str) -> RedisFrame {
+    error(&format!(
+        "ERR wrong number of arguments for 'client|{sub_command}' command"
+    ))
+}
+
+/// Returns the uppercased subcommand and the arguments after it if the request is a `CLIENT` command.
+fn client_command(request: &mut Message) -> Option<(Vec<u8>, Vec<Bytes>)> {
+    let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+        return None;
+    };
+    let mut args = args.iter().map(|x| match x {
+        RedisFrame::BulkString(x) => x.clone(),
+        _ => Bytes::new(),
+    });
+    if !args.next()?.eq_ignore_ascii_case(b"CLIENT") {
+        return None;
+    }
+    let sub_command = args.next()?.to_ascii_uppercase();
+    Some((sub_command, args.collect()))
+}
+
+/// Formats a connection as a line of `CLIENT LIST`, only including the fields that shotover knows.
+fn client_info(connection: &ConnectionReport, now_ms: i64) -> String {
+    format!(
+        "id={} addr={} name={} age={} user={}\n",
+        connection.id,
+        connection.remote_address.as_deref().unwrap_or(""),
+        connection.name.as_deref().unwrap_or(""),
+        (now_ms - connection.connected_at_ms).max(0) / 1000,
+        connection.identity.as_deref().unwrap_or("default"),
+    )
+}
+
+fn now_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Redis only allows the printable ASCII characters other than space in a connection name.
+fn valid_name(name: &[u8]) -> bool {
+    name.iter().all(|x| (b'!'..=b'~').contains(x))
+}
+
+impl RedisClientCommands {
+    /// Returns the response to the `CLIENT` subcommand, or None if it should be passed down the chain.
+    fn handle(&self, id: u64, sub_command: &[u8], args: &[Bytes]) -> Option<RedisFrame> {
+        Some(match sub_command {
+            b"ID" => RedisFrame::Integer(id as i64),
+            b"SETNAME" => match args {
+                [name] if !valid_name(name) => {
+                    error("ERR Client names cannot contain spaces, newlines or special characters.")
+                }
+                [name] => {
+                    let name = (!name.is_empty()).then(|| String::from_utf8_lossy(name).into());
+                    client_connections::set_name(id, name);
+                    ok()
+                }
+                _ => wrong_arguments("setname"),
+            },
+            b"GETNAME" => match args {
+                [] => match client_connections::connection_report(id).and_then(|x| x.name) {
+                    Some(name) => RedisFrame::BulkString(name.into()),
+                    None => RedisFrame::Null,
+                },
+                _ => wrong_arguments("getname"),
+            },
+            b"INFO" => match (args, client_connections::connection_report(id)) {
+                ([], Some(connection)) => {
+                    RedisFrame::BulkString(client_info(&connection, now_ms()).into())
+                }
+                ([], None) => RedisFrame::Null,
+                _ => wrong_arguments("info"),
+            },
+            b"LIST" => self.list(args),
+            b"KILL" => self.kill(id, args),
+            _ => return None,
+        })
+    }
+
+    fn list(&self, args: &[Bytes]) -> RedisFrame {
+        let mut connections = client_connections::source_report(&self.source);
+        match args {
+            [] => {}
+            // every client of shotover is a normal client
+            [ty, kind] if ty.eq_ignore_ascii_case(b"TYPE") => {
+                if !kind.eq_ignore_ascii_case(b"NORMAL") {
+                    connections.clear();
+                }
+            }
+            [id, ids @ ..] if id.eq_ignore_ascii_case(b"ID") && !ids.is_empty() => {
+                let ids: Vec<u64> = ids
+                    .iter()
+                    .filter_map(|x| std::str::from_utf8(x).ok()?.parse().ok())
+                    .collect();
+                connections.retain(|x| ids.contains(&x.id));
+            }
+            _ => return error("ERR syntax error"),
+        }
+        let now_ms = now_ms();
+        let mut list = String::new();
+        for connection in &connections {
+            list.push_str(&client_info(connection, now_ms));
+        }
+        RedisFrame::BulkString(list.into())
+    }
+
+    fn kill(&self, id: u64, args: &[Bytes]) -> RedisFrame {
+        let connections = client_connections::source_report(&self.source);
+
+        // The old form of CLIENT KILL takes only the address of the client to close
+        if let [address] = args {
+            return match connections
+                .iter()
+                .find(|x| x.remote_address.as_deref().map(str::as_bytes) == Some(address.as_ref()))
+            {
+                Some(connection) => {
+                    client_connections::terminate(connection.id);
+                    ok()
+                }
+                None => error("ERR No such client"),
+            };
+        }
+
+        if args.is_empty() || args.len() % 2 != 0 {
+            return error("ERR syntax error");
+        }
+        let mut skip_me = true;
+        let mut filters: Vec<ClientFilter> = vec![];
+        for pair in args.chunks(2) {
+            let value = String::from_utf8_lossy(&pair[1]).into_owned();
+            match pair[0].to_ascii_uppercase().as_slice() {
+                b"ID" => match value.parse::<u64>() {
+                    Ok(kill_id) => filters.push(Box::new(move |x| x.id == kill_id)),
+                    Err(_) => return error("ERR client-id should be greater than 0"),
+                },
+                b"ADDR" => {
+                    filters.push(Box::new(move |x| x.remote_address.as_ref() == Some(&value)))
+                }
+                b"USER" => filters.push(Box::new(move |x| {
+                    x.identity.as_deref().unwrap_or("default") == value
+                })),
+                b"SKIPME" => match value.to_ascii_lowercase().as_str() {
+                    "yes" => skip_me = true,
+                    "no" => skip_me = false,
+                    _ => return error("ERR syntax error"),
+                },
+                filter => {
+                    return error(&format!(
+                        "ERR CLIENT KILL filter {} is not supported by shotover",
+                        String::from_utf8_lossy(filter)
+                    ))
+                }
+            }
+        }
+
+        let mut killed = 0;
+        for connection in &connections {
+            if (skip_me && connection.id == id) || !filters.iter().all(|x| x(connection)) {
+                continue;
+            }
+            if client_connections::terminate(connection.id) {
+                killed += 1;
+            }
+        }
+        RedisFrame::Integer(killed)
+    }
+
+    fn respond_locally(&mut self, mut request: Message, response: RedisFrame) -> Message {
+        let mut response = Message::from_frame(Frame::Redis(response));
+        response.set_request_id(request.id());
+        self.local_responses.insert(request.id(), response);
+        request.replace_with_dummy();
+        request
+    }
+}
+
+#[async_trait]
+impl Transform for RedisClientCommands {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        // Without a client connection, such as in a sub-chain with an empty session, the commands are left to the upstream
+        if let Some(ClientConnectionId(id)) = chain_state.session.get::<ClientConnectionId>() {
+            let id = *id;
+            let mut requests = Vec::with_capacity(chain_state.requests.len());
+            for mut request in std::mem::take(&mut chain_state.requests) {
+                let response = client_command(&mut request)
+                    .and_then(|(sub_command, args)| self.handle(id, &sub_command, &args));
+                match response {
+                    Some(response) => requests.push(self.respond_locally(request, response)),
+                    None => requests.push(request),
+                }
+            }
+            chain_state.requests = requests;
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        if !self.local_responses.is_empty() {
+            for response in responses.iter_mut() {
+                if let Some(local) = response
+                    .request_id()
+                    .and_then(|id| self.local_responses.remove(&id))
+                {
+                    *response = local;
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+
+    async fn run(
+        transform: &mut RedisClientCommands,
+        id: u64,
+        requests: Vec<Message>,
+    ) -> Vec<Option<Frame>> {
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.session.insert(ClientConnectionId(id));
+        chain_state.reset(&mut chain);
+        let responses = transform.transform(&mut chain_state).await.unwrap();
+        responses
+            .into_iter()
+            .map(|mut x| x.frame().cloned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_client_commands() {
+        let source = "test_client_commands";
+        let first = client_connections::register(source, Some("127.0.0.1:5000".parse().unwrap()));
+        let second = client_connections::register(source, Some("127.0.0.1:5001".parse().unwrap()));
+        let mut transform = RedisClientCommands {
+            source: source.into(),
+            local_responses: MessageIdMap::default(),
+        };
+
+        let responses = run(
+            &mut transform,
+            first.id(),
+            vec![
+                redis_command(&["CLIENT", "SETNAME", "worker"]),
+                redis_command(&["CLIENT", "GETNAME"]),
+                redis_command(&["CLIENT", "SETNAME", "bad name"]),
+                redis_command(&["CLIENT", "ID"]),
+                redis_command(&["GET", "foo"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                Some(Frame::Redis(ok())),
+                Some(Frame::Redis(RedisFrame::BulkString("worker".into()))),
+                Some(Frame::Redis(error(
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                ))),
+                Some(Frame::Redis(RedisFrame::Integer(first.id() as i64))),
+                // passed through to the loopback
+                redis_command(&["GET", "foo"]).frame().cloned(),
+            ]
+        );
+
+        let Some(Frame::Redis(RedisFrame::BulkString(list))) = run(
+            &mut transform,
+            second.id(),
+            vec![redis_command(&["CLIENT", "LIST"])],
+        )
+        .await
+        .remove(0) else {
+            panic!("expected a bulk string")
+        };
+        let lines: Vec<&str> = std::str::from_utf8(&list).unwrap().lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!(
+            "id={} addr=127.0.0.1:5000 name=worker age=",
+            first.id()
+        )));
+        assert!(lines[1].starts_with(&format!("id={} addr=127.0.0.1:5001 name= ", second.id())));
+
+        let responses = run(
+            &mut transform,
+            second.id(),
+            vec![
+                redis_command(&["CLIENT", "KILL", "ADDR", "127.0.0.1:5000"]),
+                redis_command(&["CLIENT", "KILL", "ID", &second.id().to_string()]),
+                redis_command(&["CLIENT", "KILL", "127.0.0.1:6000"]),
+                redis_command(&["CLIENT", "KILL", "LADDR", "127.0.0.1:6379"]),
+            ],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                Some(Frame::Redis(RedisFrame::Integer(1))),
+                // a client does not kill itself unless SKIPME is no
+                Some(Frame::Redis(RedisFrame::Integer(0))),
+                Some(Frame::Redis(error("ERR No such client"))),
+                Some(Frame::Redis(error(
+                    "ERR CLIENT KILL filter LADDR is not supported by shotover"
+                ))),
+            ]
+        );
+        first.terminated().await;
+    }
+}
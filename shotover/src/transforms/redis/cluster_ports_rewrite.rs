@@ -12,11 +12,17 @@ use async_trait::async_trait;
 use bytes::{BufMut, Bytes, BytesMut};
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct RedisClusterPortsRewriteConfig {
     pub new_port: u16,
+    /// Maps the host or IP of a redis node to the host or IP that clients should connect to instead.
+    /// Hosts missing from the mapping are left as is.
+    #[serde(default)]
+    pub host_mapping: HashMap<String, String>,
 }
 
 const NAME: &str = "RedisClusterPortsRewrite";
@@ -27,7 +33,10 @@ impl TransformConfig for RedisClusterPortsRewriteConfig {
         &self,
         _transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
-        Ok(Box::new(RedisClusterPortsRewrite::new(self.new_port)))
+        Ok(Box::new(
+            RedisClusterPortsRewrite::new(self.new_port)
+                .with_host_mapping(self.host_mapping.clone()),
+        ))
     }
 
     fn up_chain_protocol(&self) -> UpChainProtocol {
@@ -52,22 +61,30 @@ impl TransformBuilder for RedisClusterPortsRewrite {
 #[derive(Clone)]
 pub struct RedisClusterPortsRewrite {
     new_port: u16,
+    host_mapping: Arc<HashMap<String, String>>,
     request_type: MessageIdMap<RequestType>,
 }
 
 #[derive(Clone)]
 enum RequestType {
-    ClusterSlot,
-    ClusterNodes,
+    Slots,
+    Nodes,
+    Shards,
 }
 
 impl RedisClusterPortsRewrite {
     pub fn new(new_port: u16) -> Self {
         RedisClusterPortsRewrite {
             new_port,
+            host_mapping: Arc::new(HashMap::new()),
             request_type: MessageIdMap::default(),
         }
     }
+
+    pub fn with_host_mapping(mut self, host_mapping: HashMap<String, String>) -> Self {
+        self.host_mapping = Arc::new(host_mapping);
+        self
+    }
 }
 
 #[async_trait]
@@ -84,13 +101,15 @@ impl Transform for RedisClusterPortsRewrite {
             let message_id = message.id();
             if let Some(frame) = message.frame() {
                 if is_cluster_slots(frame) {
-                    self.request_type
-                        .insert(message_id, RequestType::ClusterSlot);
+                    self.request_type.insert(message_id, RequestType::Slots);
                 }
 
                 if is_cluster_nodes(frame) {
-                    self.request_type
-                        .insert(message_id, RequestType::ClusterNodes);
+                    self.request_type.insert(message_id, RequestType::Nodes);
+                }
+
+                if is_cluster_shards(frame) {
+                    self.request_type.insert(message_id, RequestType::Shards);
                 }
             }
         }
@@ -101,32 +120,66 @@ impl Transform for RedisClusterPortsRewrite {
             if let Some(request_id) = response.request_id() {
                 match self.request_type.remove(&request_id) {
                     // Rewrite the ports in the cluster slots responses
-                    Some(RequestType::ClusterSlot) => {
+                    Some(RequestType::Slots) => {
                         if let Some(frame) = response.frame() {
-                            rewrite_port_slot(frame, self.new_port)
+                            rewrite_port_slot(frame, self.new_port, &self.host_mapping)
                                 .context("failed to rewrite CLUSTER SLOTS port")?;
                             response.invalidate_cache();
                         }
                     }
                     // Rewrite the ports in the cluster nodes responses
-                    Some(RequestType::ClusterNodes) => {
+                    Some(RequestType::Nodes) => {
                         if let Some(frame) = response.frame() {
-                            rewrite_port_node(frame, self.new_port)
+                            rewrite_port_node(frame, self.new_port, &self.host_mapping)
                                 .context("failed to rewrite CLUSTER NODES port")?;
                             response.invalidate_cache();
                         }
                     }
+                    // Rewrite the ports in the cluster shards responses
+                    Some(RequestType::Shards) => {
+                        if let Some(frame) = response.frame() {
+                            rewrite_port_shards(frame, self.new_port, &self.host_mapping)
+                                .context("failed to rewrite CLUSTER SHARDS port")?;
+                            response.invalidate_cache();
+                        }
+                    }
                     None => {}
                 }
             }
+
+            // Any request can be redirected to another node, so the address in the redirection must be rewritten too
+            if let Some(Frame::Redis(frame)) = response.frame() {
+                if rewrite_redirection(frame, self.new_port, &self.host_mapping) {
+                    response.invalidate_cache();
+                }
+            }
         }
 
         Ok(responses)
     }
 }
 
+/// Returns the host that clients should use in place of `host`
+fn map_host<'a>(host: &'a str, host_mapping: &'a HashMap<String, String>) -> &'a str {
+    host_mapping.get(host).map(|x| x.as_str()).unwrap_or(host)
+}
+
+/// Rewrites the host of a bulk string according to `host_mapping`
+fn rewrite_host(host: &mut Bytes, host_mapping: &HashMap<String, String>) {
+    if let Some(new_host) = std::str::from_utf8(host)
+        .ok()
+        .and_then(|x| host_mapping.get(x))
+    {
+        *host = Bytes::copy_from_slice(new_host.as_bytes());
+    }
+}
+
 /// Rewrites the ports of a response to a CLUSTER SLOTS message to `new_port`
-fn rewrite_port_slot(frame: &mut Frame, new_port: u16) -> Result<()> {
+fn rewrite_port_slot(
+    frame: &mut Frame,
+    new_port: u16,
+    host_mapping: &HashMap<String, String>,
+) -> Result<()> {
     if let Frame::Redis(RedisFrame::Array(array)) = frame {
         for elem in array.iter_mut() {
             if let RedisFrame::Array(slot) = elem {
@@ -134,7 +187,8 @@ fn rewrite_port_slot(frame: &mut Frame, new_port: u16) -> Result<()> {
                     match (index, &mut frame) {
                         (0..=1, _) => {}
                         (_, RedisFrame::Array(target)) => match target.as_mut_slice() {
-                            [RedisFrame::BulkString(_ip), RedisFrame::Integer(port), ..] => {
+                            [RedisFrame::BulkString(ip), RedisFrame::Integer(port), ..] => {
+                                rewrite_host(ip, host_mapping);
                                 *port = new_port.into();
                             }
                             _ => bail!("expected host-port in slot map but was: {:?}", frame),
@@ -168,7 +222,11 @@ fn get_buffer(frame: &mut Frame) -> Option<&mut Bytes> {
 }
 
 /// Rewrites the ports of a response to a CLUSTER NODES message to `new_port`
-fn rewrite_port_node(frame: &mut Frame, new_port: u16) -> Result<()> {
+fn rewrite_port_node(
+    frame: &mut Frame,
+    new_port: u16,
+    host_mapping: &HashMap<String, String>,
+) -> Result<()> {
     if let Some(buf) = get_buffer(frame) {
         let mut bytes_writer = BytesMut::new().writer();
 
@@ -206,7 +264,12 @@ fn rewrite_port_node(frame: &mut Frame, new_port: u16) -> Result<()> {
                 if split.len() < 3 {
                     bail!("IP address not in valid format: {ip}");
                 }
-                let new_ip = format!("{}:{}@{}", split[0], new_port, split[2]);
+                // Nodes without an address have an empty ip which is left empty
+                let host = match split[0] {
+                    "" => "",
+                    host => map_host(host, host_mapping),
+                };
+                let new_ip = format!("{}:{}@{}", host, new_port, split[2]);
 
                 writer.write_field(&*new_ip)?;
 
@@ -222,6 +285,94 @@ fn rewrite_port_node(frame: &mut Frame, new_port: u16) -> Result<()> {
     Ok(())
 }
 
+/// Rewrites the ports of a response to a CLUSTER SHARDS message to `new_port`.
+/// Each shard is a flat array of `slots` and `nodes` fields, and each node is a flat array of field names followed by their value.
+fn rewrite_port_shards(
+    frame: &mut Frame,
+    new_port: u16,
+    host_mapping: &HashMap<String, String>,
+) -> Result<()> {
+    let Frame::Redis(RedisFrame::Array(shards)) = frame else {
+        return Ok(());
+    };
+    for shard in shards {
+        let RedisFrame::Array(shard) = shard else {
+            bail!("unexpected value in shards: {shard:?}");
+        };
+        for field in shard.chunks_exact_mut(2) {
+            match field {
+                [RedisFrame::BulkString(name), RedisFrame::Array(nodes)]
+                    if name.eq_ignore_ascii_case(b"nodes") =>
+                {
+                    for node in nodes {
+                        let RedisFrame::Array(node) = node else {
+                            bail!("unexpected value in shard nodes: {node:?}");
+                        };
+                        for field in node.chunks_exact_mut(2) {
+                            match field {
+                                [RedisFrame::BulkString(name), RedisFrame::Integer(port)]
+                                    if name.eq_ignore_ascii_case(b"port")
+                                        || name.eq_ignore_ascii_case(b"tls-port") =>
+                                {
+                                    *port = new_port.into();
+                                }
+                                [RedisFrame::BulkString(name), RedisFrame::BulkString(host)]
+                                    if name.eq_ignore_ascii_case(b"ip")
+                                        || name.eq_ignore_ascii_case(b"endpoint")
+                                        || name.eq_ignore_ascii_case(b"hostname") =>
+                                {
+                                    rewrite_host(host, host_mapping);
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the address of a `MOVED` or `ASK` error, returning true if it was rewritten.
+/// The errors are of the form `MOVED <slot> <host>:<port>`.
+fn rewrite_redirection(
+    frame: &mut RedisFrame,
+    new_port: u16,
+    host_mapping: &HashMap<String, String>,
+) -> bool {
+    let RedisFrame::Error(error) = frame else {
+        return false;
+    };
+    let mut parts = error.split(' ');
+    let (Some(kind @ ("MOVED" | "ASK")), Some(slot), Some(address), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let Some((host, _port)) = address.rsplit_once(':') else {
+        return false;
+    };
+    let new_error = format!("{kind} {slot} {}:{new_port}", map_host(host, host_mapping));
+    *frame = RedisFrame::Error(new_error.into());
+    true
+}
+
+/// Determines if the supplied Redis Frame is a `CLUSTER SHARDS` request
+fn is_cluster_shards(frame: &Frame) -> bool {
+    if let Frame::Redis(RedisFrame::Array(array)) = frame {
+        match array.as_slice() {
+            [RedisFrame::BulkString(one), RedisFrame::BulkString(two), ..] => {
+                one.eq_ignore_ascii_case(b"CLUSTER") && two.eq_ignore_ascii_case(b"SHARDS")
+            }
+            [..] => false,
+        }
+    } else {
+        false
+    }
+}
+
 /// Determines if the supplied Redis Frame is a `CLUSTER NODES` request
 /// or `CLUSTER REPLICAS` which returns the same response as `CLUSTER NODES`
 fn is_cluster_nodes(frame: &Frame) -> bool {
@@ -320,7 +471,7 @@ mod test {
     #[test]
     fn test_rewrite_port_slots() {
         let slots_pcap: &[u8] = b"*3\r\n*4\r\n:10923\r\n:16383\r\n*3\r\n$12\r\n192.168.80.6\r\n:6379\r\n$40\r\n3a7c357ed75d2aa01fca1e14ef3735a2b2b8ffac\r\n*3\r\n$12\r\n192.168.80.3\r\n:6379\r\n$40\r\n77c01b0ddd8668fff05e3f6a8aaf5f3ccd454a79\r\n*4\r\n:5461\r\n:10922\r\n*3\r\n$12\r\n192.168.80.5\r\n:6379\r\n$40\r\n969c6215d064e68593d384541ceeb57e9520dbed\r\n*3\r\n$12\r\n192.168.80.2\r\n:6379\r\n$40\r\n3929f69990a75be7b2d49594c57fe620862e6fd6\r\n*4\r\n:0\r\n:5460\r\n*3\r\n$12\r\n192.168.80.7\r\n:6379\r\n$40\r\n15d52a65d1fc7a53e34bf9193415aa39136882b2\r\n*3\r\n$12\r\n192.168.80.4\r\n:6379\r\n$40\r\ncd023916a3528fae7e606a10d8289a665d6c47b0\r\n";
-        let mut codec = RedisDecoder::new(None, Direction::Sink);
+        let mut codec = RedisDecoder::new(None, Direction::Sink, None, None);
         let mut message = codec
             .decode(&mut slots_pcap.into())
             .unwrap()
@@ -328,7 +479,7 @@ mod test {
             .pop()
             .unwrap();
 
-        rewrite_port_slot(message.frame().unwrap(), 6380).unwrap();
+        rewrite_port_slot(message.frame().unwrap(), 6380, &HashMap::new()).unwrap();
 
         let slots_frames = match message.frame().unwrap() {
             Frame::Redis(RedisFrame::Array(frames)) => frames,
@@ -356,9 +507,9 @@ mod test {
         ];
 
         let replicas = vec![
-            (5460u16, "192.168.80.4:6380".to_string()),
-            (10922u16, "192.168.80.2:6380".to_string()),
-            (16383u16, "192.168.80.3:6380".to_string()),
+            (5460u16, vec!["192.168.80.4:6380".to_string()]),
+            (10922u16, vec!["192.168.80.2:6380".to_string()]),
+            (16383u16, vec!["192.168.80.3:6380".to_string()]),
         ];
 
         assert_eq!(slots.nodes, nodes);
@@ -386,11 +537,70 @@ f9553ea7fc23905476efec1f949b4b3e41a44103 :1234@0 slave,noaddr c852007a1c3b726534
 ";
 
         let mut raw_frame = Frame::Redis(RedisFrame::BulkString(Bytes::from_static(bulk_string)));
-        rewrite_port_node(&mut raw_frame, 1234).unwrap();
+        rewrite_port_node(&mut raw_frame, 1234, &HashMap::new()).unwrap();
 
         assert_eq!(
             raw_frame,
             Frame::Redis(RedisFrame::BulkString(Bytes::from_static(expected_string)))
         );
     }
+
+    fn bulk(value: &'static str) -> RedisFrame {
+        RedisFrame::BulkString(Bytes::from_static(value.as_bytes()))
+    }
+
+    #[test]
+    fn test_rewrite_port_shards() {
+        let shard = |ip: &'static str, port: i64| {
+            RedisFrame::Array(vec![
+                bulk("slots"),
+                RedisFrame::Array(vec![RedisFrame::Integer(0), RedisFrame::Integer(5460)]),
+                bulk("nodes"),
+                RedisFrame::Array(vec![RedisFrame::Array(vec![
+                    bulk("id"),
+                    bulk("e10b7051d6bf2d5febd39a2be297bbaea6084111"),
+                    bulk("port"),
+                    RedisFrame::Integer(port),
+                    bulk("ip"),
+                    bulk(ip),
+                    bulk("endpoint"),
+                    bulk(ip),
+                    bulk("role"),
+                    bulk("master"),
+                ])]),
+            ])
+        };
+        let mut frame = Frame::Redis(RedisFrame::Array(vec![shard("10.0.0.1", 6379)]));
+        let host_mapping = [("10.0.0.1".to_owned(), "proxy.example.com".to_owned())]
+            .into_iter()
+            .collect();
+
+        rewrite_port_shards(&mut frame, 6380, &host_mapping).unwrap();
+
+        assert_eq!(
+            frame,
+            Frame::Redis(RedisFrame::Array(vec![shard("proxy.example.com", 6380)]))
+        );
+    }
+
+    #[test]
+    fn test_rewrite_redirection() {
+        let host_mapping = [("10.0.0.1".to_owned(), "proxy.example.com".to_owned())]
+            .into_iter()
+            .collect();
+
+        let mut frame = RedisFrame::Error("MOVED 3999 10.0.0.1:6379".into());
+        assert!(rewrite_redirection(&mut frame, 6380, &host_mapping));
+        assert_eq!(
+            frame,
+            RedisFrame::Error("MOVED 3999 proxy.example.com:6380".into())
+        );
+
+        let mut frame = RedisFrame::Error("ASK 3999 10.0.0.2:6379".into());
+        assert!(rewrite_redirection(&mut frame, 6380, &host_mapping));
+        assert_eq!(frame, RedisFrame::Error("ASK 3999 10.0.0.2:6380".into()));
+
+        let mut frame = RedisFrame::Error("ERR unknown command".into());
+        assert!(!rewrite_redirection(&mut frame, 6380, &host_mapping));
+    }
 }
@@ -1,11 +1,28 @@
+use crate::frame::RedisFrame;
 use crate::transforms::util::ConnectionError;
 
+pub mod auth_termination;
+pub mod blocking;
 #[cfg(all(feature = "redis", feature = "cassandra"))]
 pub mod cache;
+pub mod client_commands;
 pub mod cluster_ports_rewrite;
+pub mod scan;
 pub mod sink_cluster;
 pub mod sink_single;
+#[cfg(feature = "kafka")]
+pub mod stream_mirror;
 pub mod timestamp_tagging;
+#[cfg(all(feature = "redis", feature = "cassandra"))]
+pub mod to_cassandra;
+
+/// Returns the name of the command, uppercased so that it can be compared against known command names
+pub(crate) fn command_name(command: &[RedisFrame]) -> Option<Vec<u8>> {
+    match command.first() {
+        Some(RedisFrame::BulkString(name)) => Some(name.to_ascii_uppercase()),
+        _ => None,
+    }
+}
 
 #[derive(thiserror::Error, Clone, Debug)]
 pub enum RedisError {
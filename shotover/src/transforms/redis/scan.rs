@@ -0,0 +1,146 @@
+//! Presents the keyspaces of multiple upstream redis nodes to the client as a single keyspace for SCAN.
+//!
+//! The client's cursor is virtual, the low bits hold the index of the node being scanned and the high bits hold the cursor of that node.
+//! Each SCAN is sent to a single node with the node's cursor, and once a node's scan completes the returned cursor moves on to the next node.
+//! The returned cursor is only 0 once every node has been scanned, so the client sees a single coherent scan.
+//!
+//! Like a regular SCAN, keys added or removed during the scan may or may not be returned.
+//! Nodes are ordered by address, so when nodes are added or removed during the scan some nodes may be scanned twice or not at all.
+
+use crate::frame::RedisFrame;
+use bytes::Bytes;
+
+/// The number of low bits of the virtual cursor holding the index of the node being scanned
+const NODE_BITS: u32 = 16;
+
+/// Redis's own error for a cursor it did not return
+const INVALID_CURSOR: &str = "ERR invalid cursor";
+
+/// Rewrites the virtual cursor of a `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]` request to the cursor of the node it refers to.
+/// Returns the index of the node that the request must be sent to, or the error to return to the client.
+pub(crate) fn rewrite_request(
+    command: &mut [RedisFrame],
+    nodes: usize,
+) -> Result<usize, RedisFrame> {
+    let cursor = match command.get(1) {
+        Some(RedisFrame::BulkString(cursor)) => std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|x| x.parse::<u64>().ok()),
+        _ => None,
+    }
+    .ok_or_else(|| RedisFrame::Error(INVALID_CURSOR.into()))?;
+
+    let node = (cursor & ((1 << NODE_BITS) - 1)) as usize;
+    if node >= nodes {
+        return Err(RedisFrame::Error(INVALID_CURSOR.into()));
+    }
+    command[1] = RedisFrame::BulkString(Bytes::from((cursor >> NODE_BITS).to_string()));
+    Ok(node)
+}
+
+/// Rewrites the cursor in the response of the node at index `node` to a virtual cursor.
+pub(crate) fn rewrite_response(frame: &mut RedisFrame, node: usize, nodes: usize) {
+    let RedisFrame::Array(response) = frame else {
+        // errors are returned as is
+        return;
+    };
+    let cursor = match response.first() {
+        Some(RedisFrame::BulkString(cursor)) => std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|x| x.parse::<u64>().ok()),
+        _ => None,
+    };
+    let virtual_cursor = match cursor {
+        // The node has been scanned, continue from the start of the next node
+        Some(0) if node + 1 < nodes => node as u64 + 1,
+        Some(0) => 0,
+        Some(cursor) if cursor.leading_zeros() >= NODE_BITS => (cursor << NODE_BITS) | node as u64,
+        _ => {
+            *frame = RedisFrame::Error(
+                "ERR Shotover cannot represent the SCAN cursor returned by the redis node".into(),
+            );
+            return;
+        }
+    };
+    response[0] = RedisFrame::BulkString(Bytes::from(virtual_cursor.to_string()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn scan(cursor: &str) -> Vec<RedisFrame> {
+        vec![
+            RedisFrame::BulkString("SCAN".into()),
+            RedisFrame::BulkString(Bytes::from(cursor.to_owned())),
+            RedisFrame::BulkString("COUNT".into()),
+            RedisFrame::BulkString("10".into()),
+        ]
+    }
+
+    fn response(cursor: &str) -> RedisFrame {
+        RedisFrame::Array(vec![
+            RedisFrame::BulkString(Bytes::from(cursor.to_owned())),
+            RedisFrame::Array(vec![RedisFrame::BulkString("key".into())]),
+        ])
+    }
+
+    /// Returns the node the request is sent to and the cursor sent to the node
+    fn request(cursor: &str, nodes: usize) -> Result<(usize, RedisFrame), RedisFrame> {
+        let mut command = scan(cursor);
+        let node = rewrite_request(&mut command, nodes)?;
+        Ok((node, command[1].clone()))
+    }
+
+    fn next_cursor(cursor: &str, node: usize, nodes: usize) -> RedisFrame {
+        let mut frame = response(cursor);
+        rewrite_response(&mut frame, node, nodes);
+        match frame {
+            RedisFrame::Array(mut frames) => frames.remove(0),
+            frame => frame,
+        }
+    }
+
+    #[test]
+    fn test_scan_cursor() {
+        // a new scan starts at the first node
+        assert_eq!(request("0", 3), Ok((0, RedisFrame::BulkString("0".into()))));
+
+        // the scan of a node continues on the same node
+        let cursor = next_cursor("17", 0, 3);
+        assert_eq!(cursor, RedisFrame::BulkString("1114112".into()));
+        assert_eq!(
+            request("1114112", 3),
+            Ok((0, RedisFrame::BulkString("17".into())))
+        );
+
+        // the scan moves on to the next node once a node is done
+        assert_eq!(next_cursor("0", 0, 3), RedisFrame::BulkString("1".into()));
+        assert_eq!(request("1", 3), Ok((1, RedisFrame::BulkString("0".into()))));
+        assert_eq!(
+            next_cursor("5", 2, 3),
+            RedisFrame::BulkString("327682".into())
+        );
+        assert_eq!(
+            request("327682", 3),
+            Ok((2, RedisFrame::BulkString("5".into())))
+        );
+
+        // the scan is complete once the last node is done
+        assert_eq!(next_cursor("0", 2, 3), RedisFrame::BulkString("0".into()));
+
+        assert_eq!(
+            request("3", 3),
+            Err(RedisFrame::Error(INVALID_CURSOR.into()))
+        );
+        assert_eq!(
+            request("foo", 3),
+            Err(RedisFrame::Error(INVALID_CURSOR.into()))
+        );
+        assert!(matches!(
+            next_cursor(&u64::MAX.to_string(), 0, 3),
+            RedisFrame::Error(_)
+        ));
+    }
+}
@@ -1,8 +1,14 @@
 use crate::codec::redis::RedisCodecBuilder;
 use crate::codec::{CodecBuilder, Direction};
+use crate::connection::SinkConnection;
+use crate::events::{self, EventKind};
 use crate::frame::{Frame, MessageType, RedisFrame};
-use crate::message::{Message, Messages};
-use crate::tls::TlsConnectorConfig;
+use crate::health::{HealthCheckConfig, HealthGroup, Probe};
+use crate::message::{Message, MessageIdSet, Messages};
+use crate::tcp::TcpConfig;
+use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::redis::blocking;
+use crate::transforms::redis::scan;
 use crate::transforms::redis::RedisError;
 use crate::transforms::redis::TransformError;
 use crate::transforms::util::cluster_connection_pool::{Authenticator, ConnectionPool};
@@ -26,10 +32,10 @@ use rand::SeedableRng;
 use redis_protocol::bytes_utils::string::Str;
 use redis_protocol::resp2::types::Resp2Frame;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedSender;
-use tokio::sync::{oneshot, RwLock};
+use tokio::sync::{oneshot, Notify, RwLock};
 use tokio::time::{timeout, Duration};
 use tracing::{debug, trace, warn};
 
@@ -45,6 +51,23 @@ pub struct RedisSinkClusterConfig {
     pub tls: Option<TlsConnectorConfig>,
     pub connection_count: Option<usize>,
     pub connect_timeout_ms: u64,
+    /// When set, the health of every node in the cluster is checked in the background and unhealthy nodes are avoided.
+    pub health_check: Option<HealthCheckConfig>,
+    /// When set, read only commands are sent to a replica of the slot instead of its master.
+    pub replica_reads: Option<ReplicaReadsConfig>,
+    /// When set, the timeout of blocking commands such as BLPOP or XREAD BLOCK is lowered to this duration,
+    /// including blocking commands without a timeout.
+    pub max_blocking_duration_ms: Option<u64>,
+    /// Tuning of the TCP connections to the redis nodes.
+    pub tcp: Option<TcpConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicaReadsConfig {
+    /// Read only commands that are still sent to the master because they must not observe stale data.
+    #[serde(default)]
+    pub master_only_commands: Vec<String>,
 }
 
 const NAME: &str = "RedisSinkCluster";
@@ -55,12 +78,33 @@ impl TransformConfig for RedisSinkClusterConfig {
         &self,
         transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
+        let tcp = self.tcp.clone().unwrap_or_default();
         let connection_pool = ConnectionPool::new_with_auth(
             Duration::from_millis(self.connect_timeout_ms),
             RedisCodecBuilder::new(Direction::Sink, "RedisSinkCluster".to_owned()),
             RedisAuthenticator {},
             self.tls.clone(),
+            tcp.clone(),
         )?;
+        let tls = self.tls.as_ref().map(TlsConnector::new).transpose()?;
+        let health = self.health_check.as_ref().map(|config| {
+            HealthGroup::new(
+                format!("{}/{NAME}", transform_context.chain_name),
+                Probe::RedisPing,
+                config.clone(),
+                tls.clone(),
+            )
+        });
+        let replica_reads = self.replica_reads.as_ref().map(|config| {
+            Arc::new(ReplicaReads {
+                master_only_commands: config
+                    .master_only_commands
+                    .iter()
+                    .map(|x| x.to_ascii_uppercase().into_bytes())
+                    .collect(),
+                fallbacks: counter!("shotover_redis_replica_read_fallback_count", "chain" => transform_context.chain_name.clone()),
+            })
+        });
         Ok(Box::new(RedisSinkClusterBuilder::new(
             self.first_contact_points.clone(),
             self.direct_destination.clone(),
@@ -68,6 +112,12 @@ impl TransformConfig for RedisSinkClusterConfig {
             connection_pool,
             transform_context.chain_name,
             Arc::new(RwLock::new(Topology::new())),
+            tls,
+            tcp,
+            Duration::from_millis(self.connect_timeout_ms),
+            health,
+            replica_reads,
+            self.max_blocking_duration_ms.map(Duration::from_millis),
         )))
     }
 
@@ -87,9 +137,17 @@ pub struct RedisSinkClusterBuilder {
     connection_pool: ConnectionPool<RedisCodecBuilder, RedisAuthenticator, UsernamePasswordToken>,
     shared_topology: Arc<RwLock<Topology>>,
     failed_requests: Counter,
+    tls: Option<TlsConnector>,
+    tcp: TcpConfig,
+    connect_timeout: Duration,
+    health: Option<Arc<HealthGroup>>,
+    replica_reads: Option<Arc<ReplicaReads>>,
+    max_blocking_duration: Option<Duration>,
+    chain_name: String,
 }
 
 impl RedisSinkClusterBuilder {
+    #[expect(clippy::too_many_arguments)]
     fn new(
         first_contact_points: Vec<String>,
         direct_destination: Option<String>,
@@ -101,6 +159,12 @@ impl RedisSinkClusterBuilder {
         >,
         chain_name: String,
         shared_topology: Arc<RwLock<Topology>>,
+        tls: Option<TlsConnector>,
+        tcp: TcpConfig,
+        connect_timeout: Duration,
+        health: Option<Arc<HealthGroup>>,
+        replica_reads: Option<Arc<ReplicaReads>>,
+        max_blocking_duration: Option<Duration>,
     ) -> Self {
         RedisSinkClusterBuilder {
             first_contact_points,
@@ -108,13 +172,20 @@ impl RedisSinkClusterBuilder {
             connection_count,
             connection_pool,
             shared_topology,
-            failed_requests: counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => NAME),
+            failed_requests: counter!("shotover_failed_requests_count", "chain" => chain_name.clone(), "transform" => NAME),
+            tls,
+            tcp,
+            connect_timeout,
+            health,
+            replica_reads,
+            max_blocking_duration,
+            chain_name,
         }
     }
 }
 
 impl TransformBuilder for RedisSinkClusterBuilder {
-    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
         Box::new(RedisSinkCluster::new(
             self.first_contact_points.clone(),
             self.direct_destination.clone(),
@@ -122,6 +193,16 @@ impl TransformBuilder for RedisSinkClusterBuilder {
             self.shared_topology.clone(),
             self.connection_pool.clone(),
             self.failed_requests.clone(),
+            PubSub::new(
+                self.tls.clone(),
+                self.tcp.clone(),
+                self.connect_timeout,
+                transform_context.force_run_chain,
+            ),
+            self.health.clone(),
+            self.replica_reads.clone(),
+            self.max_blocking_duration,
+            self.chain_name.clone(),
         ))
     }
 
@@ -132,6 +213,113 @@ impl TransformBuilder for RedisSinkClusterBuilder {
     fn is_terminating(&self) -> bool {
         true
     }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if let Some(replica_reads) = &self.replica_reads {
+            for command in &replica_reads.master_only_commands {
+                if !is_read_only_command(command) {
+                    errors.push(format!(
+                        "  master_only_commands contains {} which is not a read only command so is always sent to the master",
+                        String::from_utf8_lossy(command)
+                    ));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+/// Routing of read only commands to replicas
+struct ReplicaReads {
+    /// Uppercase names of read only commands that are still sent to the master
+    master_only_commands: HashSet<Vec<u8>>,
+    /// Incremented when a read is sent to the master because none of the replicas of the slot are up
+    fallbacks: Counter,
+}
+
+impl ReplicaReads {
+    fn reads_from_replica(&self, command_name: &[u8]) -> bool {
+        is_read_only_command(command_name) && !self.master_only_commands.contains(command_name)
+    }
+}
+
+/// Returns true if the uppercase command name is for a command that only reads the key it is routed by.
+fn is_read_only_command(command_name: &[u8]) -> bool {
+    matches!(
+        command_name,
+        b"GET"
+            | b"GETRANGE"
+            | b"STRLEN"
+            | b"MGET"
+            | b"LCS"
+            | b"EXISTS"
+            | b"TYPE"
+            | b"TTL"
+            | b"PTTL"
+            | b"EXPIRETIME"
+            | b"PEXPIRETIME"
+            | b"DUMP"
+            | b"GETBIT"
+            | b"BITCOUNT"
+            | b"BITPOS"
+            | b"HGET"
+            | b"HMGET"
+            | b"HGETALL"
+            | b"HKEYS"
+            | b"HVALS"
+            | b"HLEN"
+            | b"HEXISTS"
+            | b"HSTRLEN"
+            | b"HRANDFIELD"
+            | b"HSCAN"
+            | b"LRANGE"
+            | b"LINDEX"
+            | b"LLEN"
+            | b"LPOS"
+            | b"SMEMBERS"
+            | b"SISMEMBER"
+            | b"SMISMEMBER"
+            | b"SCARD"
+            | b"SRANDMEMBER"
+            | b"SSCAN"
+            | b"SINTER"
+            | b"SINTERCARD"
+            | b"SUNION"
+            | b"SDIFF"
+            | b"ZRANGE"
+            | b"ZRANGEBYSCORE"
+            | b"ZRANGEBYLEX"
+            | b"ZREVRANGE"
+            | b"ZREVRANGEBYSCORE"
+            | b"ZREVRANGEBYLEX"
+            | b"ZSCORE"
+            | b"ZMSCORE"
+            | b"ZRANK"
+            | b"ZREVRANK"
+            | b"ZCARD"
+            | b"ZCOUNT"
+            | b"ZLEXCOUNT"
+            | b"ZRANDMEMBER"
+            | b"ZSCAN"
+            | b"XRANGE"
+            | b"XREVRANGE"
+            | b"XLEN"
+            | b"PFCOUNT"
+            | b"GEOPOS"
+            | b"GEODIST"
+            | b"GEOHASH"
+            | b"GEOSEARCH"
+            | b"SORT_RO"
+            | b"EVAL_RO"
+            | b"EVALSHA_RO"
+            | b"FCALL_RO"
+    )
 }
 
 #[derive(Debug, Clone)]
@@ -164,9 +352,232 @@ pub struct RedisSinkCluster {
     direct_destination: Option<String>,
     token: Option<UsernamePasswordToken>,
     failed_requests: Counter,
+    pubsub: PubSub,
+    /// Script bodies loaded via SCRIPT LOAD keyed by their lowercase SHA1 digest.
+    /// Used to transparently resend an EVALSHA as an EVAL when the destination node does not have the script cached,
+    /// e.g. after a failover or when the slot has been migrated to a node that has never seen the script.
+    scripts: HashMap<Bytes, Bytes>,
+    transaction: Transaction,
+    health: Option<Arc<HealthGroup>>,
+    replica_reads: Option<Arc<ReplicaReads>>,
+    /// Blocking commands are sent on these connections to each master instead of the pooled connections,
+    /// so that they do not stall the requests of other clients sharing the pooled connections.
+    /// Created on first use and dedicated to this client connection.
+    blocking_connections: HashMap<String, UnboundedSender<Request>>,
+    /// Transactions are sent on these connections to each master instead of the pooled connections,
+    /// so that the commands of other clients can never land within a MULTI/EXEC block or on a connection with WATCHed keys.
+    /// Created on first use and dedicated to this client connection.
+    transaction_connections: HashMap<String, UnboundedSender<Request>>,
+    max_blocking_duration: Option<Duration>,
+    chain_name: String,
+}
+
+/// State of a MULTI/EXEC transaction on the client connection.
+/// Every command of a transaction must be executed on a single redis connection.
+/// So the commands are queued in shotover and sent as a single block to the node owning their slot once EXEC is received.
+#[derive(Default)]
+struct Transaction {
+    in_multi: bool,
+    /// The slot that every key used within the transaction must belong to.
+    slot: Option<u16>,
+    /// The connection that WATCH was sent to, the transaction must be executed on this same connection.
+    watch_connection: Option<UnboundedSender<Request>>,
+    queued: Vec<Message>,
+    /// Set when a command could not be queued, causing the EXEC to fail with EXECABORT.
+    aborted: bool,
+}
+
+/// Pubsub requests cannot go through the pooled connections since a subscribed connection can no longer be used for regular commands
+/// and the messages published to a subscribed channel must only be delivered to the client that subscribed.
+/// So each client connection is given its own dedicated upstream connection for pubsub.
+struct PubSub {
+    connection: Option<SinkConnection>,
+    /// true when the upstream connection has subscribed to at least one channel
+    subscribed: bool,
+    /// Ids of requests sent down the pubsub connection that are still awaiting a response
+    pending_requests: MessageIdSet,
+    /// Messages received from the pubsub connection that have not yet been returned, in the order they were received.
+    /// Contains both responses and pushed messages, which include published messages and the additional replies to an (un)subscribe of multiple channels.
+    received: VecDeque<Message>,
+    tls: Option<TlsConnector>,
+    tcp: TcpConfig,
+    connect_timeout: Duration,
+    force_run_chain: Arc<Notify>,
+}
+
+enum PubSubRouting {
+    /// The request is not related to pubsub and should be routed as usual
+    Regular,
+    /// The request must be sent down the dedicated pubsub connection
+    PubSub,
+    /// The request is not allowed while the connection is subscribed
+    Rejected(String),
+}
+
+impl PubSub {
+    fn new(
+        tls: Option<TlsConnector>,
+        tcp: TcpConfig,
+        connect_timeout: Duration,
+        force_run_chain: Arc<Notify>,
+    ) -> Self {
+        PubSub {
+            connection: None,
+            subscribed: false,
+            pending_requests: MessageIdSet::default(),
+            received: VecDeque::new(),
+            tls,
+            tcp,
+            connect_timeout,
+            force_run_chain,
+        }
+    }
+
+    fn routing(&self, command: &[RedisFrame]) -> PubSubRouting {
+        let Some(RedisFrame::BulkString(command_name)) = command.first() else {
+            return PubSubRouting::Regular;
+        };
+        match command_name.to_ascii_uppercase().as_slice() {
+            b"SUBSCRIBE" | b"PSUBSCRIBE" | b"SSUBSCRIBE" | b"UNSUBSCRIBE" | b"PUNSUBSCRIBE"
+            | b"SUNSUBSCRIBE" => PubSubRouting::PubSub,
+            // While subscribed, these regular commands are accepted by redis and must go to the subscribed connection
+            b"PING" | b"RESET" | b"QUIT" if self.subscribed => PubSubRouting::PubSub,
+            name if self.subscribed => PubSubRouting::Rejected(format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                String::from_utf8_lossy(name).to_lowercase()
+            )),
+            _ => PubSubRouting::Regular,
+        }
+    }
+
+    async fn connect(
+        &mut self,
+        address: &str,
+        token: &Option<UsernamePasswordToken>,
+    ) -> Result<()> {
+        let mut connection = SinkConnection::new(
+            address,
+            RedisCodecBuilder::new(Direction::Sink, "RedisSinkCluster".to_owned()),
+            &self.tls,
+            &self.tcp,
+            self.connect_timeout,
+            self.force_run_chain.clone(),
+            None,
+        )
+        .await?;
+
+        if let Some(token) = token {
+            let mut auth_args = vec![RedisFrame::BulkString(Bytes::from_static(b"AUTH"))];
+            if let Some(username) = &token.username {
+                auth_args.push(RedisFrame::BulkString(username.clone()));
+            }
+            auth_args.push(RedisFrame::BulkString(token.password.clone()));
+            connection.send(vec![Message::from_frame(Frame::Redis(RedisFrame::Array(
+                auth_args,
+            )))])?;
+
+            let mut response = connection
+                .recv()
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow!("no response to AUTH on pubsub connection"))?;
+            if let Some(Frame::Redis(RedisFrame::Error(err))) = response.frame() {
+                bail!("Failed to authenticate pubsub connection: {err}");
+            }
+        }
+
+        self.connection = Some(connection);
+        Ok(())
+    }
+
+    /// Drops the pubsub connection, it is recreated with the current credentials next time it is needed.
+    fn disconnect(&mut self) {
+        self.connection = None;
+        self.subscribed = false;
+    }
+
+    fn send(&mut self, message: Message) -> Result<()> {
+        let connection = self
+            .connection
+            .as_mut()
+            .ok_or_else(|| anyhow!("pubsub connection was not created"))?;
+        self.pending_requests.insert(message.id());
+        connection.send(vec![message])?;
+        Ok(())
+    }
+
+    fn receive_into_buffer(&mut self, received: Messages) {
+        for mut message in received {
+            if let Some(Frame::Redis(frame)) = message.frame() {
+                self.update_subscribed_state(frame);
+            }
+            self.received.push_back(message);
+        }
+    }
+
+    fn update_subscribed_state(&mut self, frame: &RedisFrame) {
+        match frame {
+            RedisFrame::Array(array) => {
+                if let [RedisFrame::BulkString(ty), _, RedisFrame::Integer(count)] =
+                    array.as_slice()
+                {
+                    if ty.eq_ignore_ascii_case(b"subscribe")
+                        || ty.eq_ignore_ascii_case(b"psubscribe")
+                        || ty.eq_ignore_ascii_case(b"ssubscribe")
+                        || ty.eq_ignore_ascii_case(b"unsubscribe")
+                        || ty.eq_ignore_ascii_case(b"punsubscribe")
+                        || ty.eq_ignore_ascii_case(b"sunsubscribe")
+                    {
+                        self.subscribed = *count != 0;
+                    }
+                }
+            }
+            // RESET returns the connection to its default state, the connection may now be authenticated as a different user,
+            // so drop it and recreate it next time it is needed.
+            RedisFrame::SimpleString(s) if s.as_ref() == b"RESET" => self.disconnect(),
+            _ => {}
+        }
+    }
+
+    /// Moves the response to the next request sent down the pubsub connection into `responses`,
+    /// preceded by any messages that were pushed before it.
+    /// Pushed messages following it, such as the replies for the remaining channels of a SUBSCRIBE, are left for the next call.
+    async fn recv_response(&mut self, responses: &mut Messages) -> Result<()> {
+        loop {
+            while let Some(message) = self.received.pop_front() {
+                let is_response = message.request_id().is_some();
+                responses.push(message);
+                if is_response {
+                    return Ok(());
+                }
+            }
+            let connection = self
+                .connection
+                .as_mut()
+                .ok_or_else(|| anyhow!("pubsub connection was closed while awaiting a response"))?;
+            let received = connection.recv().await?;
+            self.receive_into_buffer(received);
+        }
+    }
+
+    /// Collects any messages pushed by the pubsub connection without awaiting.
+    fn try_recv_pushed(&mut self, pushed_messages: &mut Messages) -> Result<()> {
+        if let Some(connection) = self.connection.as_mut() {
+            let mut received = vec![];
+            if let Err(err) = connection.try_recv_into(&mut received) {
+                self.connection = None;
+                self.subscribed = false;
+                return Err(anyhow!(err).context("pubsub connection failed"));
+            }
+            self.receive_into_buffer(received);
+        }
+        pushed_messages.extend(self.received.drain(..));
+        Ok(())
+    }
 }
 
 impl RedisSinkCluster {
+    #[expect(clippy::too_many_arguments)]
     fn new(
         first_contact_points: Vec<String>,
         direct_destination: Option<String>,
@@ -178,6 +589,11 @@ impl RedisSinkCluster {
             UsernamePasswordToken,
         >,
         failed_requests: Counter,
+        pubsub: PubSub,
+        health: Option<Arc<HealthGroup>>,
+        replica_reads: Option<Arc<ReplicaReads>>,
+        max_blocking_duration: Option<Duration>,
+        chain_name: String,
     ) -> Self {
         RedisSinkCluster {
             has_run_init: false,
@@ -194,7 +610,118 @@ impl RedisSinkCluster {
             rebuild_connections: true,
             token: None,
             failed_requests,
+            pubsub,
+            scripts: HashMap::new(),
+            transaction: Transaction::default(),
+            health,
+            replica_reads,
+            blocking_connections: HashMap::new(),
+            transaction_connections: HashMap::new(),
+            max_blocking_duration,
+            chain_name,
+        }
+    }
+
+    /// Records the script body of a successful SCRIPT LOAD so that an EVALSHA can be resent as an EVAL if needed.
+    fn record_loaded_script(&mut self, request: &mut Message, response: &mut Message) {
+        let Some(Frame::Redis(RedisFrame::BulkString(sha))) = response.frame() else {
+            return;
+        };
+        let sha = Bytes::from(sha.to_ascii_lowercase());
+        if let Some(Frame::Redis(RedisFrame::Array(command))) = request.frame() {
+            if let [RedisFrame::BulkString(name), RedisFrame::BulkString(sub_command), RedisFrame::BulkString(script)] =
+                command.as_slice()
+            {
+                if name.eq_ignore_ascii_case(b"SCRIPT") && sub_command.eq_ignore_ascii_case(b"LOAD")
+                {
+                    self.scripts.insert(sha, script.clone());
+                }
+            }
+        }
+    }
+
+    /// If the request was an EVALSHA for a script we know the body of, rewrite it into the equivalent EVAL.
+    /// Returns true if the request was rewritten and should be retried.
+    fn rewrite_evalsha_to_eval(&self, request: &mut Message) -> bool {
+        let rewritten = match request.frame() {
+            Some(Frame::Redis(RedisFrame::Array(command))) => match command.as_mut_slice() {
+                [RedisFrame::BulkString(name), RedisFrame::BulkString(sha), ..] => {
+                    let eval: &'static [u8] = if name.eq_ignore_ascii_case(b"EVALSHA") {
+                        b"EVAL"
+                    } else if name.eq_ignore_ascii_case(b"EVALSHA_RO") {
+                        b"EVAL_RO"
+                    } else {
+                        return false;
+                    };
+                    match self.scripts.get(sha.to_ascii_lowercase().as_slice()) {
+                        Some(script) => {
+                            *name = Bytes::from_static(eval);
+                            *sha = script.clone();
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+        if rewritten {
+            request.invalidate_cache();
+        }
+        rewritten
+    }
+
+    /// Sends the request down the dedicated pubsub connection, creating the connection if needed.
+    async fn send_to_pubsub_connection(
+        &mut self,
+        command: &[RedisFrame],
+        message: Message,
+    ) -> Result<()> {
+        if self.pubsub.connection.is_none() {
+            let address = match &self.direct_destination {
+                Some(address) => address.clone(),
+                None => {
+                    // Sharded pubsub channels only exist on the node owning the slot of the channel.
+                    // Regular pubsub messages are propagated across the whole cluster, so any node will do.
+                    let is_sharded = matches!(
+                        command.first(),
+                        Some(RedisFrame::BulkString(name)) if name.eq_ignore_ascii_case(b"SSUBSCRIBE")
+                    );
+                    let slot = if is_sharded {
+                        match command.get(1).and_then(RoutingInfo::for_key) {
+                            Some(RoutingInfo::Slot(slot)) => Some(slot),
+                            _ => None,
+                        }
+                    } else {
+                        None
+                    };
+                    match slot {
+                        Some(slot) => self
+                            .topology
+                            .slots
+                            .masters
+                            .range(&slot..)
+                            .next()
+                            .map(|(_, x)| x.clone()),
+                        None => self
+                            .topology
+                            .slots
+                            .masters
+                            .values()
+                            .choose(&mut self.rng)
+                            .cloned(),
+                    }
+                    .ok_or_else(|| {
+                        anyhow!(self
+                            .reason_for_no_nodes
+                            .unwrap_or("Shotover RedisSinkCluster does not know of any nodes"))
+                    })?
+                }
+            };
+            self.pubsub.connect(&address, &self.token).await?;
         }
+        self.pubsub.send(message)
     }
 
     async fn direct_connection(&mut self) -> Result<&UnboundedSender<Request>> {
@@ -225,12 +752,313 @@ impl RedisSinkCluster {
         };
 
         let routing_info = RoutingInfo::for_command_frame(command)?;
+        let command_name = match command.first() {
+            Some(RedisFrame::BulkString(name)) => name.to_ascii_uppercase(),
+            _ => vec![],
+        };
+        let is_blocking = blocking::is_blocking(command);
+        if self.transaction.in_multi
+            || matches!(
+                command_name.as_slice(),
+                b"MULTI" | b"EXEC" | b"DISCARD" | b"WATCH" | b"UNWATCH"
+            )
+        {
+            return self
+                .dispatch_transaction_message(&command_name, routing_info, message)
+                .await;
+        }
+
+        if let (RoutingInfo::Slot(slot), None) = (routing_info, &self.direct_destination) {
+            if self
+                .replica_reads
+                .as_ref()
+                .is_some_and(|x| x.reads_from_replica(&command_name))
+            {
+                return self.send_message_to_replica(slot, message).await;
+            }
+        }
+
+        if let (RoutingInfo::Slot(slot), true) = (routing_info, is_blocking) {
+            return self.send_blocking_message(slot, message).await;
+        }
+
         match self.direct_destination {
             Some(_) => self.dispatch_message_handling(routing_info, message).await,
             None => self.dispatch_message_hiding(routing_info, message).await,
         }
     }
 
+    async fn dispatch_transaction_message(
+        &mut self,
+        command_name: &[u8],
+        routing_info: RoutingInfo,
+        mut message: Message,
+    ) -> Result<ResponseFuture> {
+        match command_name {
+            b"MULTI" => {
+                if self.transaction.in_multi {
+                    return short_circuit(RedisFrame::Error(
+                        "ERR MULTI calls can not be nested".into(),
+                    ));
+                }
+                self.transaction.in_multi = true;
+                short_circuit(RedisFrame::SimpleString(Bytes::from_static(b"OK")))
+            }
+            b"EXEC" => {
+                if !self.transaction.in_multi {
+                    return short_circuit(RedisFrame::Error("ERR EXEC without MULTI".into()));
+                }
+                self.exec_transaction(message).await
+            }
+            b"DISCARD" => {
+                if !self.transaction.in_multi {
+                    return short_circuit(RedisFrame::Error("ERR DISCARD without MULTI".into()));
+                }
+                match std::mem::take(&mut self.transaction).watch_connection {
+                    // The MULTI was never sent to redis, so the DISCARD is replaced with an UNWATCH to release the watched keys.
+                    // Both commands respond with OK.
+                    Some(connection) => {
+                        if let Some(Frame::Redis(RedisFrame::Array(command))) = message.frame() {
+                            *command = vec![RedisFrame::BulkString(Bytes::from_static(b"UNWATCH"))];
+                        }
+                        message.invalidate_cache();
+                        self.send_on_pinned_connection(&connection, vec![message])
+                    }
+                    None => short_circuit(RedisFrame::SimpleString(Bytes::from_static(b"OK"))),
+                }
+            }
+            b"WATCH" if self.transaction.in_multi => short_circuit(RedisFrame::Error(
+                "ERR WATCH inside MULTI is not allowed".into(),
+            )),
+            b"WATCH" => {
+                let routing_info = match message.frame() {
+                    Some(Frame::Redis(RedisFrame::Array(command))) if command.len() > 1 => {
+                        RoutingInfo::for_keys(&command[1..])
+                    }
+                    _ => {
+                        return short_circuit(RedisFrame::Error(
+                            "ERR wrong number of arguments for 'watch' command".into(),
+                        ))
+                    }
+                };
+                let slot = match (routing_info, self.transaction.slot) {
+                    (RoutingInfo::Slot(slot), None) => slot,
+                    (RoutingInfo::Slot(slot), Some(existing)) if slot == existing => slot,
+                    (RoutingInfo::Slot(_) | RoutingInfo::CrossSlot, _) => {
+                        return short_circuit(RedisFrame::Error(
+                            "CROSSSLOT Keys in request don't hash to the same slot".into(),
+                        ))
+                    }
+                    _ => return self.short_circuit_with_error(),
+                };
+                let connection = match self.transaction.watch_connection.clone() {
+                    Some(connection) => connection,
+                    None => self.pinned_connection(Some(slot)).await?,
+                };
+                self.transaction.slot = Some(slot);
+                self.transaction.watch_connection = Some(connection.clone());
+                self.send_on_pinned_connection(&connection, vec![message])
+            }
+            b"UNWATCH" if !self.transaction.in_multi => {
+                let transaction = std::mem::take(&mut self.transaction);
+                match transaction.watch_connection {
+                    Some(connection) => self.send_on_pinned_connection(&connection, vec![message]),
+                    None => short_circuit(RedisFrame::SimpleString(Bytes::from_static(b"OK"))),
+                }
+            }
+            _ => {
+                let error = match (routing_info, self.transaction.slot) {
+                    (RoutingInfo::Slot(slot), None) => {
+                        self.transaction.slot = Some(slot);
+                        None
+                    }
+                    (RoutingInfo::Slot(slot), Some(existing)) if slot == existing => None,
+                    (RoutingInfo::Slot(_) | RoutingInfo::CrossSlot, _) => {
+                        Some("CROSSSLOT Keys in request don't hash to the same slot")
+                    }
+                    // Commands without keys can be run on any node.
+                    (RoutingInfo::Random, _) => None,
+                    _ => Some("ERR Shotover RedisSinkCluster does not support this command within a transaction"),
+                };
+                match error {
+                    Some(error) => {
+                        self.transaction.aborted = true;
+                        self.send_error_response(error)
+                    }
+                    None => {
+                        self.transaction.queued.push(message);
+                        short_circuit(RedisFrame::SimpleString(Bytes::from_static(b"QUEUED")))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends MULTI, the queued commands and the EXEC in a single block to one connection.
+    /// Only the response to EXEC is returned since the client has already received QUEUED for each command.
+    async fn exec_transaction(&mut self, exec: Message) -> Result<ResponseFuture> {
+        let transaction = std::mem::take(&mut self.transaction);
+        if transaction.aborted {
+            if let Some(connection) = &transaction.watch_connection {
+                // release the watched keys since the EXEC is never sent
+                let unwatch = Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"UNWATCH")),
+                ])));
+                connection
+                    .send(Request {
+                        message: unwatch,
+                        return_chan: None,
+                    })
+                    .ok();
+            }
+            return short_circuit(RedisFrame::Error(
+                "EXECABORT Transaction discarded because of previous errors.".into(),
+            ));
+        }
+
+        let connection = match transaction.watch_connection {
+            Some(connection) => connection,
+            None => self.pinned_connection(transaction.slot).await?,
+        };
+
+        let mut messages = vec![Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+            RedisFrame::BulkString(Bytes::from_static(b"MULTI")),
+        ])))];
+        messages.extend(transaction.queued);
+        messages.push(exec);
+        self.send_on_pinned_connection(&connection, messages)
+    }
+
+    /// Returns a connection dedicated to this client to the master owning the slot, or a random master if no slot is provided.
+    /// Unlike `choose_and_send`, the caller keeps hold of the connection so that multiple requests can be sent on it,
+    /// it is held by the transaction until its EXEC, DISCARD or UNWATCH.
+    async fn pinned_connection(&mut self, slot: Option<u16>) -> Result<UnboundedSender<Request>> {
+        let host = match slot {
+            Some(slot) => self
+                .topology
+                .slots
+                .masters
+                .range(&slot..)
+                .next()
+                .map(|(_, host)| host.clone()),
+            None => self
+                .topology
+                .slots
+                .masters
+                .values()
+                .choose(&mut self.rng)
+                .cloned(),
+        }
+        .ok_or_else(|| {
+            anyhow!(self.reason_for_no_nodes.unwrap_or(
+                "Shotover RedisSinkCluster does not know of a node containing the required slot"
+            ))
+        })?;
+
+        match self.transaction_connections.get(&host) {
+            Some(connection) if !connection.is_closed() => Ok(connection.clone()),
+            _ => {
+                let connection = self
+                    .connection_pool
+                    .new_unpooled_connection(&host, &self.token)
+                    .await
+                    .map_err(|e| anyhow!("failed to connect to {host}: {e}"))?;
+                self.transaction_connections
+                    .insert(host, connection.clone());
+                Ok(connection)
+            }
+        }
+    }
+
+    /// Sends a blocking command on a connection to the master owning the slot that is dedicated to this client connection.
+    async fn send_blocking_message(
+        &mut self,
+        slot: u16,
+        mut message: Message,
+    ) -> Result<ResponseFuture> {
+        if let Some(max) = self.max_blocking_duration {
+            if let Some(Frame::Redis(RedisFrame::Array(command))) = message.frame() {
+                if blocking::limit_timeout(command, max) {
+                    message.invalidate_cache();
+                }
+            }
+        }
+
+        let Some(host) = self
+            .topology
+            .slots
+            .masters
+            .range(&slot..)
+            .next()
+            .map(|(_, host)| host.clone())
+        else {
+            return self.send_error_response(
+                self.reason_for_no_nodes
+                    .unwrap_or("ERR Shotover RedisSinkCluster does not know of a node containing the required slot")
+            );
+        };
+
+        let connection = match self.blocking_connections.get(&host) {
+            Some(connection) if !connection.is_closed() => connection.clone(),
+            _ => {
+                let connection = self
+                    .connection_pool
+                    .new_unpooled_connection(&host, &self.token)
+                    .await
+                    .map_err(|e| anyhow!("failed to connect to {host}: {e}"))?;
+                self.blocking_connections
+                    .insert(host.clone(), connection.clone());
+                connection
+            }
+        };
+
+        let (one_tx, one_rx) = oneshot::channel::<Response>();
+        if connection
+            .send(Request {
+                message,
+                return_chan: Some(one_tx),
+            })
+            .is_err()
+        {
+            self.blocking_connections.remove(&host);
+            return self
+                .send_error_response("ERR Connection used by the blocking command was closed");
+        }
+        Ok(Box::pin(one_rx.map_err(|e| anyhow!(e))))
+    }
+
+    /// Sends all messages on the connection, returning the response to the last message.
+    fn send_on_pinned_connection(
+        &mut self,
+        connection: &UnboundedSender<Request>,
+        mut messages: Vec<Message>,
+    ) -> Result<ResponseFuture> {
+        let last = messages
+            .pop()
+            .ok_or_else(|| anyhow!("no messages to send"))?;
+        let mut sent = true;
+        for message in messages {
+            sent &= connection
+                .send(Request {
+                    message,
+                    return_chan: None,
+                })
+                .is_ok();
+        }
+        let (one_tx, one_rx) = oneshot::channel::<Response>();
+        sent &= connection
+            .send(Request {
+                message: last,
+                return_chan: Some(one_tx),
+            })
+            .is_ok();
+        if !sent {
+            // the closed connection is replaced by the next call to `pinned_connection`
+            return self.send_error_response("ERR Connection used by the transaction was closed");
+        }
+        Ok(Box::pin(one_rx.map_err(|e| anyhow!(e))))
+    }
+
     async fn send_message_to_slot(
         &mut self,
         slot: u16,
@@ -250,6 +1078,85 @@ impl RedisSinkCluster {
         }
     }
 
+    /// Sends a read only request to a random replica of the slot.
+    /// Falls back to sending to the master when none of the replicas of the slot are up.
+    async fn send_message_to_replica(
+        &mut self,
+        slot: u16,
+        message: Message,
+    ) -> Result<ResponseFuture> {
+        let replicas: Vec<String> = self
+            .topology
+            .slots
+            .replicas
+            .range(&slot..)
+            .next()
+            .map(|(_, replicas)| {
+                replicas
+                    .iter()
+                    .filter(|replica| self.replica_is_up(replica))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        match replicas.into_iter().choose(&mut self.rng) {
+            Some(replica) => {
+                let one_rx = self.choose_and_send(&replica, message).await?;
+                Ok(Box::pin(
+                    one_rx.map_err(|_| anyhow!("no response from single channel")),
+                ))
+            }
+            None => {
+                if let Some(replica_reads) = &self.replica_reads {
+                    replica_reads.fallbacks.increment(1);
+                }
+                self.send_message_to_slot(slot, message).await
+            }
+        }
+    }
+
+    /// A replica is only considered up when it passes its health checks and we already hold an open READONLY connection to it.
+    fn replica_is_up(&self, replica: &str) -> bool {
+        self.health
+            .as_ref()
+            .map_or(true, |health| health.is_healthy(replica))
+            && self
+                .topology
+                .channels
+                .get(replica)
+                .is_some_and(|channels| channels.iter().any(|channel| !channel.is_closed()))
+    }
+
+    fn is_readonly_replica(&self, node: &str) -> bool {
+        self.replica_reads.is_some()
+            && self
+                .topology
+                .slots
+                .replicas
+                .values()
+                .flatten()
+                .any(|replica| replica == node)
+    }
+
+    /// Gets pooled connections to the node, putting them into READONLY mode when `readonly` is set so that the node can serve reads as a replica.
+    async fn get_connections(
+        &self,
+        node: &str,
+        token: &Option<UsernamePasswordToken>,
+        readonly: bool,
+    ) -> Result<Vec<UnboundedSender<Request>>, TransformError> {
+        let connections = self
+            .connection_pool
+            .get_connections(node, token, self.connection_count)
+            .await?;
+        if readonly {
+            for connection in &connections {
+                set_readonly(connection).await?;
+            }
+        }
+        Ok(connections)
+    }
+
     async fn send_message_to_channels(
         &mut self,
         channels: &[String],
@@ -325,13 +1232,20 @@ impl RedisSinkCluster {
 
     fn latest_contact_points(&self) -> Vec<&str> {
         if !self.topology.slots.nodes.is_empty() {
-            // Use latest node addresses as contact points.
-            self.topology
+            // Use latest node addresses as contact points, avoiding unhealthy nodes unless they are all unhealthy.
+            let nodes: Vec<&str> = self
+                .topology
                 .slots
                 .nodes
                 .iter()
                 .map(|x| x.as_str())
-                .collect()
+                .collect();
+            match &self.health {
+                Some(health) if nodes.iter().any(|x| health.is_healthy(x)) => {
+                    nodes.into_iter().filter(|x| health.is_healthy(x)).collect()
+                }
+                _ => nodes,
+            }
         } else {
             // Fallback to initial contact points.
             self.first_contact_points
@@ -387,11 +1301,31 @@ impl RedisSinkCluster {
         match self.build_connections_inner(&token).await {
             Ok((slots, channels)) => {
                 debug!("connected to cluster: {:?}", channels.keys());
+                // The initial slot map is fetched by every new client connection, so only report later changes
+                if !self.topology.slots.masters.is_empty()
+                    && self.topology.slots.masters != slots.masters
+                {
+                    events::publish(
+                        EventKind::SlotMapRefreshed,
+                        format!("{}/{NAME}", self.chain_name),
+                        format!(
+                            "the slot map changed, the masters are now {:?}",
+                            slots.masters.values().unique().collect::<Vec<_>>()
+                        ),
+                    );
+                }
                 self.topology = Topology { slots, channels };
                 if token.is_none() {
                     // when authentication isnt used we can share topology between connections
                     *self.shared_topology.write().await = self.topology.clone();
                 }
+                if self.token != token {
+                    // the dedicated connections were authenticated with the previous credentials
+                    self.blocking_connections.clear();
+                    self.transaction_connections.clear();
+                    self.transaction = Transaction::default();
+                    self.pubsub.disconnect();
+                }
                 self.token = token;
                 self.reason_for_no_nodes = None;
                 self.rebuild_connections = false;
@@ -413,22 +1347,35 @@ impl RedisSinkCluster {
     ) -> Result<(SlotMap, ChannelMap), TransformError> {
         // NOTE: Fetch slot map uses unpooled connections to check token validity before reusing pooled connections.
         let slots = self.fetch_slot_map(token).await?;
+        if let Some(health) = &self.health {
+            health.set_nodes(slots.nodes.iter().map(|x| x.as_str()));
+        }
 
         let mut channels = ChannelMap::new();
         let mut errors = Vec::new();
-        for node in slots.masters.values().chain(slots.replicas.values()) {
-            match self
-                .connection_pool
-                .get_connections(node, token, self.connection_count)
-                .await
-            {
+        let nodes = slots.masters.values().map(|node| (node, false)).chain(
+            slots
+                .replicas
+                .values()
+                .flatten()
+                .map(|node| (node, self.replica_reads.is_some())),
+        );
+        for (node, readonly) in nodes {
+            if let Some(health) = &self.health {
+                // avoid waiting for connection attempts to nodes known to be down to time out
+                if !health.is_healthy(node) {
+                    debug!("skipping connecting to unhealthy node {node}");
+                    continue;
+                }
+            }
+            match self.get_connections(node, token, readonly).await {
                 Ok(connections) => {
                     channels.insert(node.to_string(), connections);
                 }
                 Err(e) => {
                     // Intentional debug! Some errors should be silently passed through.
                     debug!("failed to connect to {}: {:?}", node, e);
-                    errors.push(e.into());
+                    errors.push(e);
                 }
             }
         }
@@ -479,8 +1426,7 @@ impl RedisSinkCluster {
 
             match timeout(
                 Duration::from_millis(40),
-                self.connection_pool
-                    .get_connections(host, &self.token, self.connection_count),
+                self.get_connections(host, &self.token, self.is_readonly_replica(host)),
             )
             .await
             {
@@ -564,10 +1510,58 @@ impl RedisSinkCluster {
                 ))
             }
             RoutingInfo::ShortCircuitNil => short_circuit(RedisFrame::Null),
+            RoutingInfo::CrossSlot => short_circuit(RedisFrame::Error(
+                "CROSSSLOT Keys in request don't hash to the same slot".into(),
+            )),
             RoutingInfo::ShortCircuitOk => {
                 short_circuit(RedisFrame::SimpleString(Bytes::from_static(b"OK")))
             }
+            RoutingInfo::Scan => self.send_scan(message).await,
+        }
+    }
+
+    /// Sends the SCAN to the master it is scanning, translating between the client's virtual cursor and the cursor of the master.
+    async fn send_scan(&mut self, mut message: Message) -> Result<ResponseFuture> {
+        let masters: Vec<String> = self
+            .topology
+            .slots
+            .masters
+            .values()
+            .cloned()
+            .sorted()
+            .dedup()
+            .collect();
+        if masters.is_empty() {
+            return self.send_error_response(
+                self.reason_for_no_nodes
+                    .unwrap_or("ERR Shotover RedisSinkCluster does not know of any nodes"),
+            );
         }
+
+        let node = match message.frame() {
+            Some(Frame::Redis(RedisFrame::Array(command))) => {
+                scan::rewrite_request(command, masters.len())
+            }
+            _ => bail!("syntax error: bad command"),
+        };
+        let node = match node {
+            Ok(node) => node,
+            Err(error) => return short_circuit(error),
+        };
+        message.invalidate_cache();
+
+        let nodes = masters.len();
+        let response = self.choose_and_send(&masters[node], message).await?;
+        Ok(Box::pin(async move {
+            let mut response = response.await?;
+            if let Ok(message) = &mut response.response {
+                if let Some(Frame::Redis(frame)) = message.frame() {
+                    scan::rewrite_response(frame, node, nodes);
+                    message.invalidate_cache();
+                }
+            }
+            Ok(response)
+        }))
     }
 
     async fn dispatch_message_handling(
@@ -582,7 +1576,9 @@ impl RedisSinkCluster {
             | RoutingInfo::Random
             | RoutingInfo::Unsupported
             | RoutingInfo::ShortCircuitNil
-            | RoutingInfo::ShortCircuitOk => {
+            | RoutingInfo::ShortCircuitOk
+            | RoutingInfo::CrossSlot
+            | RoutingInfo::Scan => {
                 let connection = self.direct_connection().await?;
                 Ok(Box::pin(
                     send_message_request(connection, message)?
@@ -651,7 +1647,7 @@ impl RedisSinkCluster {
 #[derivative(Debug)]
 pub struct SlotMap {
     pub masters: BTreeMap<u16, String>,
-    pub replicas: BTreeMap<u16, String>,
+    pub replicas: BTreeMap<u16, Vec<String>>,
 
     // Hide redundant information.
     #[derivative(Debug = "ignore")]
@@ -685,9 +1681,14 @@ impl SlotMap {
             .cloned()
             .collect();
 
+        let mut replicas: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        for (host, _start, end) in replica_entries {
+            replicas.entry(end).or_default().push(host);
+        }
+
         Self {
             masters: to_interval_map(master_entries),
-            replicas: to_interval_map(replica_entries),
+            replicas,
             nodes,
         }
     }
@@ -709,6 +1710,10 @@ pub enum RoutingInfo {
     ShortCircuitOk,
     /// In handling mode falls back to sending to the destination address
     ShortCircuitNil,
+    /// In handling mode falls back to sending to the destination address
+    CrossSlot,
+    /// In handling mode falls back to sending to the destination address
+    Scan,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -770,37 +1775,45 @@ impl RoutingInfo {
                 _ => RoutingInfo::Random,
             },
             // These commands can not reasonably be supported by shotover, so we just return an error to the client when they are used
-            b"SCAN" | b"SHUTDOWN" | b"SLAVEOF" | b"REPLICAOF" | b"MOVE" | b"BITOP" | b"CONFIG"
+            // Each node is scanned in turn behind a virtual cursor, see the scan module.
+            b"SCAN" => RoutingInfo::Scan,
+            b"SHUTDOWN" | b"SLAVEOF" | b"REPLICAOF" | b"MOVE" | b"BITOP" | b"CONFIG"
             | b"SLOWLOG" | b"INFO" | b"TIME" => RoutingInfo::Unsupported,
-            b"EVALSHA" | b"EVAL" => match args.get(2) {
-                Some(RedisFrame::BulkString(key_count)) => {
-                    if key_count.as_ref() == b"0" {
-                        RoutingInfo::Random
-                    } else {
-                        // For an EVAL to succeed every key must be present on the same node.
-                        // So we use the first key to find the correct destination node and if any of the
-                        // remaining keys are not on the same node as the first key then the destination
-                        // node will correctly handle this for us.
-                        args.get(3)
-                            .and_then(RoutingInfo::for_key)
-                            .unwrap_or(RoutingInfo::Unsupported)
-                    }
+            b"EVALSHA" | b"EVAL" | b"EVALSHA_RO" | b"EVAL_RO" | b"FCALL" | b"FCALL_RO" => {
+                RoutingInfo::for_declared_keys(args)?
+            }
+            // The key follows the sub command, e.g. `OBJECT IDLETIME key`
+            b"XGROUP" | b"XINFO" | b"OBJECT" => match args.get(1) {
+                Some(RedisFrame::BulkString(sub_command))
+                    if sub_command.eq_ignore_ascii_case(b"HELP") =>
+                {
+                    RoutingInfo::Random
                 }
-                _ => RoutingInfo::Unsupported,
+                _ => args
+                    .get(2)
+                    .and_then(RoutingInfo::for_key)
+                    .unwrap_or(RoutingInfo::Unsupported),
             },
-            b"XGROUP" | b"XINFO" => args
-                .get(2)
+            // BLMPOP timeout numkeys key [key ...] LEFT | RIGHT
+            b"BLMPOP" | b"BZMPOP" => args
+                .get(3)
                 .and_then(RoutingInfo::for_key)
                 .unwrap_or(RoutingInfo::Unsupported),
+            // The first half of the arguments after STREAMS are the keys, the second half are the ids to read from.
+            // Every stream must be in the same slot, including the streams of consumer groups.
             b"XREAD" | b"XREADGROUP" => args
                 .iter()
                 .position(|a| match a {
                     RedisFrame::BulkString(a) => a.eq_ignore_ascii_case(b"STREAMS"),
                     _ => false,
                 })
-                .and_then(|streams_position| {
-                    args.get(streams_position + 1)
-                        .and_then(RoutingInfo::for_key)
+                .map(|streams_position| {
+                    let streams = &args[streams_position + 1..];
+                    if streams.is_empty() || streams.len() % 2 != 0 {
+                        RoutingInfo::Unsupported
+                    } else {
+                        RoutingInfo::for_keys(&streams[..streams.len() / 2])
+                    }
                 })
                 .unwrap_or(RoutingInfo::Unsupported),
             b"AUTH" => RoutingInfo::Auth,
@@ -816,6 +1829,48 @@ impl RoutingInfo {
         })
     }
 
+    /// Routes commands of the form `COMMAND script_or_function numkeys key [key ...] arg [arg ...]`.
+    /// For the command to succeed every declared key must be in the same slot,
+    /// so we can return the CROSSSLOT error ourselves instead of sending it to an arbitrary node.
+    fn for_declared_keys(args: &[RedisFrame]) -> Result<RoutingInfo> {
+        let key_count = match args.get(2) {
+            Some(RedisFrame::BulkString(key_count)) => std::str::from_utf8(key_count)
+                .ok()
+                .and_then(|x| x.parse::<usize>().ok()),
+            _ => None,
+        };
+        let Some(key_count) = key_count else {
+            return Ok(RoutingInfo::Unsupported);
+        };
+        if key_count == 0 {
+            return Ok(RoutingInfo::Random);
+        }
+        match 3usize
+            .checked_add(key_count)
+            .and_then(|end| args.get(3..end))
+        {
+            Some(keys) => Ok(RoutingInfo::for_keys(keys)),
+            None => bail!("syntax error: numkeys is greater than the number of arguments"),
+        }
+    }
+
+    /// Routes to the slot shared by all of the keys or returns CrossSlot if they do not share a slot.
+    fn for_keys(keys: &[RedisFrame]) -> RoutingInfo {
+        let mut slot = None;
+        for key in keys {
+            match RoutingInfo::for_key(key) {
+                Some(RoutingInfo::Slot(key_slot)) => match slot {
+                    None => slot = Some(key_slot),
+                    Some(slot) if slot != key_slot => return RoutingInfo::CrossSlot,
+                    Some(_) => {}
+                },
+                _ => return RoutingInfo::Unsupported,
+            }
+        }
+        slot.map(RoutingInfo::Slot)
+            .unwrap_or(RoutingInfo::Unsupported)
+    }
+
     #[inline(always)]
     pub fn for_key(key: &RedisFrame) -> Option<RoutingInfo> {
         if let RedisFrame::BulkString(key) = key {
@@ -956,6 +2011,26 @@ async fn get_topology_from_node(
     }
 }
 
+/// Allows reads on a connection to a replica, otherwise the replica redirects every request to its master.
+async fn set_readonly(sender: &UnboundedSender<Request>) -> Result<(), TransformError> {
+    let return_chan_rx = send_message_request(
+        sender,
+        Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+            RedisFrame::BulkString("READONLY".into()),
+        ]))),
+    )?;
+
+    match receive_frame_response(return_chan_rx).await? {
+        RedisFrame::SimpleString(s) if s == "OK" => Ok(()),
+        RedisFrame::Error(message) => {
+            Err(TransformError::Upstream(RedisError::from_message(&message)))
+        }
+        frame => Err(TransformError::Protocol(format!(
+            "unexpected response for READONLY: {frame:?}"
+        ))),
+    }
+}
+
 #[inline(always)]
 fn get_hashtag(key: &[u8]) -> Option<&[u8]> {
     if let Some(open) = key.iter().position(|v| *v == b'{') {
@@ -1011,6 +2086,31 @@ fn short_circuit(frame: RedisFrame) -> Result<ResponseFuture> {
     }))
 }
 
+fn is_noscript_error(response: &mut Message) -> bool {
+    matches!(
+        response.frame(),
+        Some(Frame::Redis(RedisFrame::Error(err))) if err.starts_with("NOSCRIPT")
+    )
+}
+
+fn pubsub_placeholder(request_id: u128) -> Result<ResponseFuture> {
+    let mut message = Message::from_frame(Frame::Redis(RedisFrame::Null));
+    message.set_request_id(request_id);
+    let (one_tx, one_rx) = oneshot::channel::<Response>();
+
+    one_tx
+        .send(Response {
+            response: Ok(message),
+        })
+        .map_err(|_| anyhow!("Failed to send pubsub placeholder"))?;
+
+    Ok(Box::pin(async {
+        one_rx
+            .await
+            .map_err(|_| panic!("immediate responder must be used"))
+    }))
+}
+
 #[async_trait]
 impl Transform for RedisSinkCluster {
     fn get_name(&self) -> &'static str {
@@ -1055,10 +2155,32 @@ impl Transform for RedisSinkCluster {
 
         let mut requests = chain_state.requests.clone();
         requests.reverse();
-        for message in chain_state.requests.drain(..) {
-            responses.push_back(match self.dispatch_message(message).await {
-                Ok(response) => response,
-                Err(e) => short_circuit(RedisFrame::Error(format!("ERR {e}").into())).unwrap(),
+        for mut message in chain_state.requests.drain(..) {
+            let routing = match message.frame() {
+                Some(Frame::Redis(RedisFrame::Array(command))) => self.pubsub.routing(command),
+                _ => PubSubRouting::Regular,
+            };
+            responses.push_back(match routing {
+                PubSubRouting::Regular => match self.dispatch_message(message).await {
+                    Ok(response) => response,
+                    Err(e) => short_circuit(RedisFrame::Error(format!("ERR {e}").into())).unwrap(),
+                },
+                PubSubRouting::Rejected(err) => short_circuit(RedisFrame::Error(err.into()))?,
+                PubSubRouting::PubSub => {
+                    let id = message.id();
+                    let command = match message.frame() {
+                        Some(Frame::Redis(RedisFrame::Array(command))) => command.clone(),
+                        _ => unreachable!("routing was determined from a redis array"),
+                    };
+                    match self.send_to_pubsub_connection(&command, message).await {
+                        // The real response is received from the pubsub connection while processing responses,
+                        // this placeholder only marks its position among the responses.
+                        Ok(()) => pubsub_placeholder(id)?,
+                        Err(e) => {
+                            short_circuit(RedisFrame::Error(format!("ERR {e}").into())).unwrap()
+                        }
+                    }
+                }
             })
         }
 
@@ -1066,7 +2188,7 @@ impl Transform for RedisSinkCluster {
         let mut response_buffer = vec![];
 
         while let Some(s) = responses.next().await {
-            let original = requests.pop().unwrap();
+            let mut original = requests.pop().unwrap();
 
             trace!("Got resp {:?}", s);
             let Response { response } = s.or_else(|e| -> Result<Response> {
@@ -1078,6 +2200,25 @@ impl Transform for RedisSinkCluster {
             })?;
 
             let mut response = response?;
+            if let Some(request_id) = response.request_id() {
+                if self.pubsub.pending_requests.remove(&request_id) {
+                    self.pubsub.recv_response(&mut response_buffer).await?;
+                    continue;
+                }
+            }
+            if is_noscript_error(&mut response) && self.rewrite_evalsha_to_eval(&mut original) {
+                debug!("Got NOSCRIPT, resending as EVAL");
+                // keep `requests` aligned with `responses` since the retry adds another response
+                requests.push(original.clone());
+                responses.push_front(Box::pin(
+                    self.dispatch_message(original)
+                        .await?
+                        .map_err(|e| e.context("Error while retrying NOSCRIPT")),
+                ));
+                continue;
+            }
+            self.record_loaded_script(&mut original, &mut response);
+
             match response.frame() {
                 Some(Frame::Redis(frame)) => {
                     match Redirection::parse(frame) {
@@ -1110,6 +2251,12 @@ impl Transform for RedisSinkCluster {
                 _ => response_buffer.push(response),
             }
         }
+
+        // Messages published to subscribed channels are not associated with any request,
+        // so we forward whatever has arrived each time the chain is run.
+        // The pubsub connection triggers a chain run when new messages arrive.
+        self.pubsub.try_recv_pushed(&mut response_buffer)?;
+
         Ok(response_buffer)
     }
 }
@@ -1197,15 +2344,303 @@ mod test {
     use super::*;
     use crate::codec::redis::RedisDecoder;
     use crate::codec::Direction;
+    use crate::test_utils::{redis_command, redis_command_args};
+    use bytes::BytesMut;
     use pretty_assertions::assert_eq;
+    use redis_protocol::resp2::decode::decode_bytes_mut;
+    use redis_protocol::resp2::encode::extend_encode;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio_util::codec::Decoder;
 
+    #[tokio::test]
+    async fn test_pubsub_response_order() {
+        fn frame(args: &[&'static str], count: Option<i64>) -> Message {
+            let mut array: Vec<RedisFrame> = args
+                .iter()
+                .map(|x| RedisFrame::BulkString(Bytes::from_static(x.as_bytes())))
+                .collect();
+            array.extend(count.map(RedisFrame::Integer));
+            Message::from_frame(Frame::Redis(RedisFrame::Array(array)))
+        }
+        fn response(args: &[&'static str], count: Option<i64>) -> Message {
+            let mut message = frame(args, count);
+            message.set_request_id(message.id());
+            message
+        }
+
+        let mut pubsub = PubSub::new(
+            None,
+            TcpConfig::default(),
+            Duration::from_secs(1),
+            Arc::new(Notify::new()),
+        );
+        let subscribe_a = response(&["subscribe", "a"], Some(1));
+        let pong = response(&["pong", ""], None);
+        pubsub.receive_into_buffer(vec![
+            frame(&["message", "x", "1"], None),
+            subscribe_a.clone(),
+            frame(&["subscribe", "b"], Some(2)),
+            pong.clone(),
+            frame(&["unsubscribe", "a"], Some(1)),
+        ]);
+
+        // the reply for the second channel must come after the first reply and before the next response
+        let mut responses = vec![];
+        pubsub.recv_response(&mut responses).await.unwrap();
+        assert_eq!(
+            responses,
+            vec![frame(&["message", "x", "1"], None), subscribe_a]
+        );
+        let mut responses = vec![];
+        pubsub.recv_response(&mut responses).await.unwrap();
+        assert_eq!(responses, vec![frame(&["subscribe", "b"], Some(2)), pong]);
+        assert!(pubsub.subscribed);
+
+        let mut responses = vec![];
+        pubsub.try_recv_pushed(&mut responses).unwrap();
+        assert_eq!(responses, vec![frame(&["unsubscribe", "a"], Some(1))]);
+    }
+
+    /// Accepts redis connections, recording the commands received on each connection and acknowledging them as redis would within a transaction.
+    /// Also accepts any AUTH and reports itself as the only node of the cluster.
+    async fn transaction_server(
+        listener: tokio::net::TcpListener,
+        received: Arc<Mutex<Vec<Vec<String>>>>,
+    ) {
+        let port = listener.local_addr().unwrap().port();
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let received = received.clone();
+            let index = {
+                let mut received = received.lock().unwrap();
+                received.push(vec![]);
+                received.len() - 1
+            };
+            tokio::spawn(async move {
+                let mut buffer = BytesMut::new();
+                while socket.read_buf(&mut buffer).await.unwrap() > 0 {
+                    let mut out = BytesMut::new();
+                    while let Some((frame, _, _)) = decode_bytes_mut(&mut buffer).unwrap() {
+                        let RedisFrame::Array(args) = frame else {
+                            panic!("expected a command but got {frame:?}")
+                        };
+                        let command = args
+                            .iter()
+                            .map(|arg| {
+                                String::from_utf8_lossy(arg.as_bytes().unwrap()).into_owned()
+                            })
+                            .join(" ");
+                        let response = match args[0].as_bytes().unwrap() {
+                            b"AUTH" | b"WATCH" | b"MULTI" => RedisFrame::SimpleString("OK".into()),
+                            b"CLUSTER" => RedisFrame::Array(vec![RedisFrame::Array(vec![
+                                RedisFrame::Integer(0),
+                                RedisFrame::Integer(SLOT_SIZE as i64 - 1),
+                                RedisFrame::Array(vec![
+                                    RedisFrame::BulkString("127.0.0.1".into()),
+                                    RedisFrame::Integer(port as i64),
+                                    RedisFrame::BulkString("id".into()),
+                                ]),
+                            ])]),
+                            b"EXEC" => {
+                                RedisFrame::Array(vec![RedisFrame::SimpleString("OK".into())])
+                            }
+                            _ => RedisFrame::SimpleString("QUEUED".into()),
+                        };
+                        received.lock().unwrap()[index].push(command);
+                        extend_encode(&mut out, &response).unwrap();
+                    }
+                    socket.write_all(&out).await.unwrap();
+                }
+            });
+        }
+    }
+
+    fn transaction_client(
+        address: &str,
+        connection_pool: ConnectionPool<
+            RedisCodecBuilder,
+            RedisAuthenticator,
+            UsernamePasswordToken,
+        >,
+    ) -> RedisSinkCluster {
+        let mut cluster = RedisSinkCluster::new(
+            vec![address.to_owned()],
+            None,
+            1,
+            Arc::new(RwLock::new(Topology::new())),
+            connection_pool,
+            Counter::noop(),
+            PubSub::new(
+                None,
+                TcpConfig::default(),
+                Duration::from_secs(1),
+                Arc::new(Notify::new()),
+            ),
+            None,
+            None,
+            None,
+            "test".to_owned(),
+        );
+        cluster
+            .topology
+            .slots
+            .masters
+            .insert(SLOT_SIZE as u16 - 1, address.to_owned());
+        cluster.has_run_init = true;
+        cluster.rebuild_connections = false;
+        cluster
+    }
+
+    async fn run(cluster: &mut RedisSinkCluster, commands: &[&[&str]]) {
+        let requests = commands.iter().map(|x| redis_command(x)).collect();
+        let mut chain_state = ChainState::new_test(requests);
+        let responses = cluster.transform(&mut chain_state).await.unwrap();
+        assert_eq!(responses.len(), commands.len());
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_transactions() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(vec![]));
+        tokio::spawn(transaction_server(listener, received.clone()));
+
+        let connection_pool = ConnectionPool::new_with_auth(
+            Duration::from_secs(1),
+            RedisCodecBuilder::new(Direction::Sink, "test".to_owned()),
+            RedisAuthenticator {},
+            None,
+            TcpConfig::default(),
+        )
+        .unwrap();
+        let mut client_a = transaction_client(&address, connection_pool.clone());
+        let mut client_b = transaction_client(&address, connection_pool);
+
+        // client b runs a whole transaction between the WATCH and MULTI of client a
+        run(&mut client_a, &[&["WATCH", "key"]]).await;
+        run(
+            &mut client_b,
+            &[
+                &["WATCH", "key"],
+                &["MULTI"],
+                &["SET", "key", "b"],
+                &["EXEC"],
+            ],
+        )
+        .await;
+        run(
+            &mut client_a,
+            &[&["MULTI"], &["SET", "key", "a"], &["EXEC"]],
+        )
+        .await;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![
+                vec!["WATCH key", "MULTI", "SET key a", "EXEC"],
+                vec!["WATCH key", "MULTI", "SET key b", "EXEC"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transaction_connection_replaced_on_auth() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(vec![]));
+        tokio::spawn(transaction_server(listener, received.clone()));
+
+        let connection_pool = ConnectionPool::new_with_auth(
+            Duration::from_secs(1),
+            RedisCodecBuilder::new(Direction::Sink, "test".to_owned()),
+            RedisAuthenticator {},
+            None,
+            TcpConfig::default(),
+        )
+        .unwrap();
+        let mut client = transaction_client(&address, connection_pool);
+
+        let transaction: &[&[&str]] = &[
+            &["WATCH", "key"],
+            &["MULTI"],
+            &["SET", "key", "a"],
+            &["EXEC"],
+        ];
+        run(&mut client, &[&["AUTH", "user_a", "password"]]).await;
+        run(&mut client, &[&["WATCH", "key"]]).await;
+        // the WATCH of the previous user is discarded along with its connection
+        run(&mut client, &[&["AUTH", "user_b", "password"]]).await;
+        run(&mut client, transaction).await;
+
+        // the transaction of the second user must run on a new connection authenticated as that user
+        let transaction_connections: Vec<Vec<String>> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.iter().any(|x| x.starts_with("WATCH")))
+            .cloned()
+            .collect();
+        assert_eq!(
+            transaction_connections,
+            vec![
+                vec!["AUTH user_a password", "WATCH key"],
+                vec![
+                    "AUTH user_b password",
+                    "WATCH key",
+                    "MULTI",
+                    "SET key a",
+                    "EXEC"
+                ],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pubsub_connection_replaced_on_auth() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let received = Arc::new(Mutex::new(vec![]));
+        tokio::spawn(transaction_server(listener, received.clone()));
+
+        let connection_pool = ConnectionPool::new_with_auth(
+            Duration::from_secs(1),
+            RedisCodecBuilder::new(Direction::Sink, "test".to_owned()),
+            RedisAuthenticator {},
+            None,
+            TcpConfig::default(),
+        )
+        .unwrap();
+        let mut client = transaction_client(&address, connection_pool);
+
+        run(&mut client, &[&["AUTH", "user_a", "password"]]).await;
+        run(&mut client, &[&["SUBSCRIBE", "channel"]]).await;
+        run(&mut client, &[&["AUTH", "user_b", "password"]]).await;
+        run(&mut client, &[&["SUBSCRIBE", "channel"]]).await;
+
+        // the subscribe of the second user must run on a new connection authenticated as that user
+        let pubsub_connections: Vec<Vec<String>> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.iter().any(|x| x.starts_with("SUBSCRIBE")))
+            .cloned()
+            .collect();
+        assert_eq!(
+            pubsub_connections,
+            vec![
+                vec!["AUTH user_a password", "SUBSCRIBE channel"],
+                vec!["AUTH user_b password", "SUBSCRIBE channel"],
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_slots() {
         // Wireshark capture from a Redis cluster with 3 masters and 3 replicas.
         let slots_pcap: &[u8] = b"*3\r\n*4\r\n:10923\r\n:16383\r\n*3\r\n$12\r\n192.168.80.6\r\n:6379\r\n$40\r\n3a7c357ed75d2aa01fca1e14ef3735a2b2b8ffac\r\n*3\r\n$12\r\n192.168.80.3\r\n:6379\r\n$40\r\n77c01b0ddd8668fff05e3f6a8aaf5f3ccd454a79\r\n*4\r\n:5461\r\n:10922\r\n*3\r\n$12\r\n192.168.80.5\r\n:6379\r\n$40\r\n969c6215d064e68593d384541ceeb57e9520dbed\r\n*3\r\n$12\r\n192.168.80.2\r\n:6379\r\n$40\r\n3929f69990a75be7b2d49594c57fe620862e6fd6\r\n*4\r\n:0\r\n:5460\r\n*3\r\n$12\r\n192.168.80.7\r\n:6379\r\n$40\r\n15d52a65d1fc7a53e34bf9193415aa39136882b2\r\n*3\r\n$12\r\n192.168.80.4\r\n:6379\r\n$40\r\ncd023916a3528fae7e606a10d8289a665d6c47b0\r\n";
 
-        let mut codec = RedisDecoder::new(None, Direction::Sink);
+        let mut codec = RedisDecoder::new(None, Direction::Sink, None, None);
 
         let mut message = codec
             .decode(&mut slots_pcap.into())
@@ -1240,13 +2675,112 @@ mod test {
         ];
 
         let replicas = vec![
-            (5460u16, "192.168.80.4:6379".to_string()),
-            (10922u16, "192.168.80.2:6379".to_string()),
-            (16383u16, "192.168.80.3:6379".to_string()),
+            (5460u16, vec!["192.168.80.4:6379".to_string()]),
+            (10922u16, vec!["192.168.80.2:6379".to_string()]),
+            (16383u16, vec!["192.168.80.3:6379".to_string()]),
         ];
 
         assert_eq!(slots.nodes, nodes);
         assert_eq!(slots.masters.into_iter().collect::<Vec<_>>(), masters);
         assert_eq!(slots.replicas.into_iter().collect::<Vec<_>>(), replicas);
     }
+
+    #[test]
+    fn test_eval_routing() {
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["EVAL", "script", "0"])).unwrap(),
+            RoutingInfo::Random
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&[
+                "EVALSHA", "sha", "2", "{user}a", "{user}b", "arg"
+            ]))
+            .unwrap(),
+            RoutingInfo::Slot(5474)
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["FCALL", "func", "2", "a", "b"]))
+                .unwrap(),
+            RoutingInfo::CrossSlot
+        ));
+        assert!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["EVAL", "script", "3", "a"]))
+                .is_err()
+        );
+        assert!(RoutingInfo::for_command_frame(&redis_command_args(&[
+            "EVAL",
+            "script",
+            "18446744073709551615",
+            "a"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_stream_routing() {
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&[
+                "XREADGROUP",
+                "GROUP",
+                "g",
+                "c",
+                "STREAMS",
+                "{s}a",
+                "{s}b",
+                ">",
+                ">"
+            ]))
+            .unwrap(),
+            RoutingInfo::Slot(_)
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&[
+                "XREAD", "STREAMS", "a", "b", "0", "0"
+            ]))
+            .unwrap(),
+            RoutingInfo::CrossSlot
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&[
+                "XREAD", "STREAMS", "a", "0", "0"
+            ]))
+            .unwrap(),
+            RoutingInfo::Unsupported
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["XGROUP", "HELP"])).unwrap(),
+            RoutingInfo::Random
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["XACK", "a", "g", "1-0"]))
+                .unwrap(),
+            RoutingInfo::Slot(15495)
+        ));
+    }
+
+    #[test]
+    fn test_object_routing() {
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["OBJECT", "IDLETIME", "a"]))
+                .unwrap(),
+            RoutingInfo::Slot(15495)
+        ));
+        assert!(matches!(
+            RoutingInfo::for_command_frame(&redis_command_args(&["OBJECT", "HELP"])).unwrap(),
+            RoutingInfo::Random
+        ));
+    }
+
+    #[test]
+    fn test_reads_from_replica() {
+        let replica_reads = ReplicaReads {
+            master_only_commands: [b"HGETALL".to_vec()].into_iter().collect(),
+            fallbacks: Counter::noop(),
+        };
+        assert!(replica_reads.reads_from_replica(b"GET"));
+        assert!(replica_reads.reads_from_replica(b"EVALSHA_RO"));
+        assert!(!replica_reads.reads_from_replica(b"HGETALL"));
+        assert!(!replica_reads.reads_from_replica(b"SET"));
+        assert!(!replica_reads.reads_from_replica(b"EVALSHA"));
+    }
 }
@@ -1,14 +1,17 @@
 use crate::codec::{CodecBuilder, Direction};
 use crate::connection::SinkConnection;
 use crate::frame::{Frame, MessageType, RedisFrame};
-use crate::message::Messages;
+use crate::health::{HealthCheckConfig, HealthGroup, Probe};
+use crate::message::{Messages, StreamChunk};
+use crate::tcp::TcpConfig;
 use crate::tls::{TlsConnector, TlsConnectorConfig};
+use crate::transforms::redis::blocking;
 use crate::transforms::{
     ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
     TransformContextBuilder, UpChainProtocol,
 };
 use crate::{codec::redis::RedisCodecBuilder, transforms::TransformContextConfig};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use async_trait::async_trait;
 use metrics::{counter, Counter};
 use serde::{Deserialize, Serialize};
@@ -24,6 +27,17 @@ pub struct RedisSinkSingleConfig {
     pub address: String,
     pub tls: Option<TlsConnectorConfig>,
     pub connect_timeout_ms: u64,
+    /// When set, the health of the redis node is checked in the background.
+    pub health_check: Option<HealthCheckConfig>,
+    /// When set, the timeout of blocking commands such as BLPOP or XREAD BLOCK is lowered to this duration,
+    /// including blocking commands without a timeout.
+    pub max_blocking_duration_ms: Option<u64>,
+    /// Tuning of the TCP connections to the redis node.
+    pub tcp: Option<TcpConfig>,
+    /// When set, responses larger than this many bytes are forwarded to the client in chunks of this size as they are received,
+    /// instead of once the whole response has been received.
+    /// Only takes effect when every transform before this one in the chain accepts streamed responses.
+    pub response_chunk_size: Option<usize>,
 }
 
 const NAME: &str = "RedisSinkSingle";
@@ -35,11 +49,25 @@ impl TransformConfig for RedisSinkSingleConfig {
         transform_context: TransformContextConfig,
     ) -> Result<Box<dyn TransformBuilder>> {
         let tls = self.tls.as_ref().map(TlsConnector::new).transpose()?;
+        let health = self.health_check.as_ref().map(|config| {
+            let group = HealthGroup::new(
+                format!("{}/{NAME}", transform_context.chain_name),
+                Probe::RedisPing,
+                config.clone(),
+                tls.clone(),
+            );
+            group.register(&self.address);
+            group
+        });
         Ok(Box::new(RedisSinkSingleBuilder::new(
             self.address.clone(),
             tls,
             transform_context.chain_name,
             self.connect_timeout_ms,
+            health,
+            self.max_blocking_duration_ms.map(Duration::from_millis),
+            self.tcp.clone().unwrap_or_default(),
+            self.response_chunk_size,
         )))
     }
 
@@ -57,14 +85,23 @@ pub struct RedisSinkSingleBuilder {
     tls: Option<TlsConnector>,
     failed_requests: Counter,
     connect_timeout: Duration,
+    health: Option<Arc<HealthGroup>>,
+    max_blocking_duration: Option<Duration>,
+    tcp: TcpConfig,
+    response_chunk_size: Option<usize>,
 }
 
 impl RedisSinkSingleBuilder {
+    #[expect(clippy::too_many_arguments)]
     pub fn new(
         address: String,
         tls: Option<TlsConnector>,
         chain_name: String,
         connect_timeout_ms: u64,
+        health: Option<Arc<HealthGroup>>,
+        max_blocking_duration: Option<Duration>,
+        tcp: TcpConfig,
+        response_chunk_size: Option<usize>,
     ) -> Self {
         let failed_requests = counter!("shotover_failed_requests_count", "chain" => chain_name, "transform" => "RedisSinkSingle");
         let connect_timeout = Duration::from_millis(connect_timeout_ms);
@@ -74,6 +111,10 @@ impl RedisSinkSingleBuilder {
             tls,
             failed_requests,
             connect_timeout,
+            health,
+            max_blocking_duration,
+            tcp,
+            response_chunk_size,
         }
     }
 }
@@ -87,6 +128,12 @@ impl TransformBuilder for RedisSinkSingleBuilder {
             failed_requests: self.failed_requests.clone(),
             connect_timeout: self.connect_timeout,
             force_run_chain: transform_context.force_run_chain,
+            health: self.health.clone(),
+            max_blocking_duration: self.max_blocking_duration,
+            tcp: self.tcp.clone(),
+            response_chunk_size: self
+                .response_chunk_size
+                .filter(|_| transform_context.stream_responses),
         })
     }
 
@@ -106,6 +153,12 @@ pub struct RedisSinkSingle {
     failed_requests: Counter,
     connect_timeout: Duration,
     force_run_chain: Arc<Notify>,
+    health: Option<Arc<HealthGroup>>,
+    /// Each client connection has its own upstream connection, so blocking commands only stall the client that sent them
+    /// and only their timeout needs to be limited.
+    max_blocking_duration: Option<Duration>,
+    tcp: TcpConfig,
+    response_chunk_size: Option<usize>,
 }
 
 #[async_trait]
@@ -119,12 +172,20 @@ impl Transform for RedisSinkSingle {
         chain_state: &'shorter mut ChainState<'longer>,
     ) -> Result<Messages> {
         if self.connection.is_none() {
-            let codec = RedisCodecBuilder::new(Direction::Sink, "RedisSinkSingle".to_owned());
+            if let Some(health) = &self.health {
+                // fail fast rather than waiting for the connection attempt to time out
+                if !health.is_healthy(&self.address) {
+                    bail!("redis node {} failed its health checks", self.address);
+                }
+            }
+            let codec = RedisCodecBuilder::new(Direction::Sink, "RedisSinkSingle".to_owned())
+                .with_response_chunk_size(self.response_chunk_size);
             self.connection = Some(
                 SinkConnection::new(
                     &self.address,
                     codec,
                     &self.tls,
+                    &self.tcp,
                     self.connect_timeout,
                     self.force_run_chain.clone(),
                     None,
@@ -150,6 +211,16 @@ impl Transform for RedisSinkSingle {
                 }
             }
         } else {
+            if let Some(max) = self.max_blocking_duration {
+                for request in &mut chain_state.requests {
+                    if let Some(Frame::Redis(RedisFrame::Array(command))) = request.frame() {
+                        if blocking::limit_timeout(command, max) {
+                            request.invalidate_cache();
+                        }
+                    }
+                }
+            }
+
             let requests_count = chain_state.requests.len();
             self.connection
                 .as_mut()
@@ -173,6 +244,16 @@ impl Transform for RedisSinkSingle {
                         responses_count += 1;
                     }
                 }
+                // Each chunk of a streamed response is returned as soon as it is received.
+                // The remaining responses are picked up by the chain runs triggered as they are received.
+                if responses[responses_len_old..].iter().any(|x| {
+                    matches!(
+                        x.stream_chunk(),
+                        Some(StreamChunk::Start | StreamChunk::Continuation)
+                    )
+                }) {
+                    break;
+                }
             }
         }
         Ok(responses)
@@ -0,0 +1,399 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::kafka::StrBytes;
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageIdMap, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::kafka::{build_produce_request, check_produce_response};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol, DUMMY_ADDRESS,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use kafka_protocol::messages::TopicName;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisStreamMirrorConfig {
+    /// The kafka topic that stream entries are published to.
+    pub topic: String,
+    /// Only entries added to these streams are mirrored, when not provided entries added to any stream are mirrored.
+    pub streams: Option<Vec<String>>,
+    /// The maximum number of entries held while waiting to be acknowledged by kafka.
+    pub buffer_size: Option<usize>,
+    /// How long kafka should wait for the produce request to be replicated before responding.
+    pub produce_timeout_ms: Option<i32>,
+    pub chain: TransformChainConfig,
+}
+
+const NAME: &str = "RedisStreamMirror";
+#[typetag::serde(name = "RedisStreamMirror")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisStreamMirrorConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisStreamMirrorBuilder {
+            kafka_chain: self
+                .chain
+                .get_builder(TransformContextConfig {
+                    chain_name: "stream_mirror_chain".into(),
+                    up_chain_protocol: MessageType::Kafka,
+                })
+                .await?,
+            topic: self.topic.clone(),
+            streams: self.streams.as_ref().map(|streams| {
+                Arc::new(
+                    streams
+                        .iter()
+                        .map(|x| Bytes::from(x.clone().into_bytes()))
+                        .collect(),
+                )
+            }),
+            buffer_size: self.buffer_size.unwrap_or(10_000),
+            produce_timeout_ms: self.produce_timeout_ms.unwrap_or(30_000),
+            dropped_entries: counter!("shotover_redis_stream_mirror_dropped_entries_count", "chain" => transform_context.chain_name.clone()),
+            published_entries: counter!("shotover_redis_stream_mirror_published_entries_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct RedisStreamMirrorBuilder {
+    kafka_chain: TransformChainBuilder,
+    topic: String,
+    streams: Option<Arc<HashSet<Bytes>>>,
+    buffer_size: usize,
+    produce_timeout_ms: i32,
+    dropped_entries: Counter,
+    published_entries: Counter,
+}
+
+impl TransformBuilder for RedisStreamMirrorBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisStreamMirror {
+            kafka_chain: self.kafka_chain.build(transform_context),
+            topic: TopicName(StrBytes::from_string(self.topic.clone())),
+            streams: self.streams.clone(),
+            buffer_size: self.buffer_size,
+            produce_timeout_ms: self.produce_timeout_ms,
+            dropped_entries: self.dropped_entries.clone(),
+            published_entries: self.published_entries.clone(),
+            pending_requests: MessageIdMap::default(),
+            unpublished_entries: VecDeque::new(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .kafka_chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if self.buffer_size == 0 {
+            errors.push("  buffer_size must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+/// An entry added to a stream, serialized as JSON into the value of a kafka record.
+#[derive(Serialize, Debug, PartialEq)]
+struct StreamEntry {
+    stream: String,
+    /// The id of the entry as assigned by redis
+    id: String,
+    /// The field value pairs of the entry in the order they were added, fields may repeat
+    fields: Vec<(String, String)>,
+}
+
+impl StreamEntry {
+    /// Milliseconds since the unix epoch, taken from the id of the entry when it is auto generated
+    fn timestamp_ms(&self) -> i64 {
+        self.id
+            .split_once('-')
+            .and_then(|(ms, _)| ms.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|x| x.as_millis() as i64)
+                    .unwrap_or(0)
+            })
+    }
+}
+
+struct RedisStreamMirror {
+    kafka_chain: TransformChain,
+    topic: TopicName,
+    streams: Option<Arc<HashSet<Bytes>>>,
+    buffer_size: usize,
+    produce_timeout_ms: i32,
+    dropped_entries: Counter,
+    published_entries: Counter,
+    /// Entries of XADD requests that have not yet received a response, missing the id assigned by redis
+    pending_requests: MessageIdMap<StreamEntry>,
+    /// Entries successfully added to a stream that have not yet been acknowledged by kafka.
+    /// Entries are only removed once kafka acknowledges them, giving at-least-once delivery.
+    unpublished_entries: VecDeque<StreamEntry>,
+}
+
+/// Parses `XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] * | id field value [field value ...]`
+/// into the entry it adds, without the id as that is only known once redis responds.
+fn parse_xadd(command: &[RedisFrame]) -> Option<StreamEntry> {
+    let args: Vec<&Bytes> = command
+        .iter()
+        .map(|x| match x {
+            RedisFrame::BulkString(x) => Some(x),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    let (name, stream, mut rest) = match args.as_slice() {
+        [name, stream, rest @ ..] => (name, stream, rest),
+        _ => return None,
+    };
+    if !name.eq_ignore_ascii_case(b"XADD") {
+        return None;
+    }
+
+    loop {
+        match rest.first()?.to_ascii_uppercase().as_slice() {
+            b"NOMKSTREAM" => rest = &rest[1..],
+            b"MAXLEN" | b"MINID" => {
+                rest = &rest[1..];
+                if matches!(&rest.first()?[..], b"=" | b"~") {
+                    rest = &rest[1..];
+                }
+                // the threshold
+                rest = rest.get(1..)?;
+                if rest.first()?.eq_ignore_ascii_case(b"LIMIT") {
+                    rest = rest.get(2..)?;
+                }
+            }
+            // the id
+            _ => break,
+        }
+    }
+
+    let fields = &rest[1..];
+    if fields.is_empty() || fields.len() % 2 != 0 {
+        return None;
+    }
+    Some(StreamEntry {
+        stream: String::from_utf8_lossy(stream).into_owned(),
+        id: String::new(),
+        fields: fields
+            .chunks(2)
+            .map(|pair| {
+                (
+                    String::from_utf8_lossy(pair[0]).into_owned(),
+                    String::from_utf8_lossy(pair[1]).into_owned(),
+                )
+            })
+            .collect(),
+    })
+}
+
+impl RedisStreamMirror {
+    fn store_pending_entries(&mut self, requests: &mut Messages) {
+        for request in requests.iter_mut() {
+            let id = request.id();
+            if let Some(Frame::Redis(RedisFrame::Array(command))) = request.frame() {
+                let Some(entry) = parse_xadd(command) else {
+                    continue;
+                };
+                if let Some(streams) = &self.streams {
+                    if !streams.contains(entry.stream.as_bytes()) {
+                        continue;
+                    }
+                }
+                self.pending_requests.insert(id, entry);
+            }
+        }
+    }
+
+    fn buffer_added_entries(&mut self, responses: &mut [Message]) {
+        for response in responses.iter_mut() {
+            let Some(request_id) = response.request_id() else {
+                continue;
+            };
+            let Some(mut entry) = self.pending_requests.remove(&request_id) else {
+                continue;
+            };
+            // XADD responds with the id of the added entry, or nil when NOMKSTREAM is used and the stream does not exist
+            if let Some(Frame::Redis(RedisFrame::BulkString(id))) = response.frame() {
+                entry.id = String::from_utf8_lossy(id).into_owned();
+                if self.unpublished_entries.len() >= self.buffer_size {
+                    self.unpublished_entries.pop_front();
+                    self.dropped_entries.increment(1);
+                }
+                self.unpublished_entries.push_back(entry);
+            }
+        }
+    }
+
+    /// Send all unpublished entries to kafka.
+    /// If kafka does not acknowledge the entries they are kept and retried on the next batch of requests.
+    async fn publish_entries(&mut self, local_addr: SocketAddr) {
+        if self.unpublished_entries.is_empty() {
+            return;
+        }
+
+        let result = match self
+            .unpublished_entries
+            .iter()
+            .map(|entry| Ok((entry.timestamp_ms(), serde_json::to_vec(entry)?.into())))
+            .collect::<Result<Vec<(i64, Bytes)>>>()
+            .and_then(|values| build_produce_request(values, &self.topic, self.produce_timeout_ms))
+        {
+            Ok(request) => {
+                self.kafka_chain
+                    .process_request(&mut ChainState::new_with_addr(vec![request], local_addr))
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result.and_then(check_produce_response) {
+            Ok(()) => {
+                self.published_entries
+                    .increment(self.unpublished_entries.len() as u64);
+                self.unpublished_entries.clear();
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to publish {} stream entries, they will be retried: {err:?}",
+                    self.unpublished_entries.len()
+                );
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for RedisStreamMirror {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        self.store_pending_entries(&mut chain_state.requests);
+
+        let local_addr = chain_state.local_addr;
+        let mut responses = chain_state.call_next_transform().await?;
+
+        self.buffer_added_entries(&mut responses);
+        self.publish_entries(local_addr).await;
+
+        Ok(responses)
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        // The connection is closed so a fake address is used, the final flush has already tried publishing once
+        self.publish_entries(DUMMY_ADDRESS).await;
+        if !self.unpublished_entries.is_empty() {
+            self.dropped_entries
+                .increment(self.unpublished_entries.len() as u64);
+            tracing::error!(
+                "Dropped {} stream entries that could not be published before shutdown",
+                self.unpublished_entries.len()
+            );
+            self.unpublished_entries.clear();
+        }
+        self.kafka_chain.shutdown().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command_args;
+    use pretty_assertions::assert_eq;
+
+    fn entry(fields: &[(&str, &str)]) -> Option<StreamEntry> {
+        Some(StreamEntry {
+            stream: "events".into(),
+            id: String::new(),
+            fields: fields
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_parse_xadd() {
+        assert_eq!(
+            parse_xadd(&redis_command_args(&[
+                "XADD", "events", "*", "a", "1", "b", "2"
+            ])),
+            entry(&[("a", "1"), ("b", "2")])
+        );
+        assert_eq!(
+            parse_xadd(&redis_command_args(&[
+                "xadd",
+                "events",
+                "NOMKSTREAM",
+                "MAXLEN",
+                "~",
+                "1000",
+                "LIMIT",
+                "10",
+                "1-1",
+                "a",
+                "1"
+            ])),
+            entry(&[("a", "1")])
+        );
+        assert_eq!(
+            parse_xadd(&redis_command_args(&[
+                "XADD", "events", "MINID", "5", "*", "a", "1"
+            ])),
+            entry(&[("a", "1")])
+        );
+        assert_eq!(
+            parse_xadd(&redis_command_args(&["XADD", "events", "*", "a"])),
+            None
+        );
+        assert_eq!(parse_xadd(&redis_command_args(&["XLEN", "events"])), None);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let entry = StreamEntry {
+            stream: "events".into(),
+            id: "1526919030474-55".into(),
+            fields: vec![],
+        };
+        assert_eq!(entry.timestamp_ms(), 1526919030474);
+    }
+}
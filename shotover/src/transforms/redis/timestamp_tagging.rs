@@ -1 +1,186 @@
+use crate::frame::redis::redis_keys;
+use crate::frame::{Frame, MessageType, RedisFrame};
+use crate::message::{Message, MessageId, MessageIdMap, Messages};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisTimestampTaggerConfig;
+
+const NAME: &str = "RedisTimestampTagger";
+#[typetag::serde(name = "RedisTimestampTagger")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisTimestampTaggerConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        Ok(Box::new(RedisTimestampTaggerBuilder))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct RedisTimestampTaggerBuilder;
+
+impl TransformBuilder for RedisTimestampTaggerBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisTimestampTagger::default())
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+}
+
+/// Tags each response to a command accessing a key with when that key was last accessed, see [`Message::last_modified`].
+///
+/// An `OBJECT IDLETIME` for the key is pipelined directly before the original command, which is passed on untouched.
+/// Sending it first means the idle time is measured before the original command itself accesses the key.
+/// The idle time is the time since the key was last accessed by any command, not only writes, so it is only an upper bound on when the key was last modified.
+#[derive(Default)]
+struct RedisTimestampTagger {
+    /// Maps the id of each `OBJECT IDLETIME` request to the id of the request it was sent alongside
+    idle_time_requests: MessageIdMap<MessageId>,
+    /// Last accessed times received for requests whose own response has not yet been received
+    last_modified: MessageIdMap<SystemTime>,
+    /// Commands within a transaction are queued by redis, so an extra command would corrupt the results of the EXEC
+    in_multi: bool,
+}
+
+impl RedisTimestampTagger {
+    /// Returns the key to fetch the idle time of, or None if the request should not be tagged.
+    fn key_to_tag(&mut self, request: &mut Message) -> Option<Bytes> {
+        let Some(Frame::Redis(frame)) = request.frame() else {
+            return None;
+        };
+        let RedisFrame::Array(args) = &*frame else {
+            return None;
+        };
+        let Some(RedisFrame::BulkString(command)) = args.first() else {
+            return None;
+        };
+        match command.to_ascii_uppercase().as_slice() {
+            b"MULTI" => {
+                self.in_multi = true;
+                None
+            }
+            b"EXEC" | b"DISCARD" | b"RESET" => {
+                self.in_multi = false;
+                None
+            }
+            // Only pubsub commands are allowed on a subscribed connection
+            b"SSUBSCRIBE" | b"SUNSUBSCRIBE" => None,
+            _ if self.in_multi => None,
+            _ => redis_keys(frame).into_iter().next(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transform for RedisTimestampTagger {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let requests = std::mem::take(&mut chain_state.requests);
+        for mut request in requests {
+            if let Some(key) = self.key_to_tag(&mut request) {
+                let idle_time = Message::from_frame(Frame::Redis(RedisFrame::Array(vec![
+                    RedisFrame::BulkString(Bytes::from_static(b"OBJECT")),
+                    RedisFrame::BulkString(Bytes::from_static(b"IDLETIME")),
+                    RedisFrame::BulkString(key),
+                ])));
+                self.idle_time_requests.insert(idle_time.id(), request.id());
+                chain_state.requests.push(idle_time);
+            }
+            chain_state.requests.push(request);
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        let now = SystemTime::now();
+        responses.retain_mut(|response| {
+            let Some(request_id) = response
+                .request_id()
+                .and_then(|id| self.idle_time_requests.remove(&id))
+            else {
+                return true;
+            };
+            // The key does not exist or its idle time is unavailable, e.g. due to an LFU maxmemory-policy
+            if let Some(Frame::Redis(RedisFrame::Integer(idle_seconds))) = response.frame() {
+                if let Ok(idle_seconds) = u64::try_from(*idle_seconds) {
+                    self.last_modified
+                        .insert(request_id, now - Duration::from_secs(idle_seconds));
+                }
+            }
+            false
+        });
+
+        for response in &mut responses {
+            if let Some(last_modified) = response
+                .request_id()
+                .and_then(|id| self.last_modified.remove(&id))
+            {
+                response.set_last_modified(last_modified);
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_tagged() {
+        let mut tagger = RedisTimestampTagger::default();
+        // every response, including to OBJECT IDLETIME, is an idle time of 10 seconds
+        let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+            Response::Message(Message::from_frame(Frame::Redis(RedisFrame::Integer(10)))),
+        )))];
+        let mut chain_state = ChainState::new_test(vec![
+            redis_command(&["GET", "foo"]),
+            redis_command(&["MULTI"]),
+            redis_command(&["SET", "foo", "bar"]),
+            redis_command(&["EXEC"]),
+            redis_command(&["PING"]),
+        ]);
+        chain_state.reset(&mut chain);
+
+        let mut responses = tagger.transform(&mut chain_state).await.unwrap();
+
+        assert_eq!(responses.len(), 5);
+        let tagged: Vec<bool> = responses
+            .iter_mut()
+            .map(|x| x.last_modified().is_some())
+            .collect();
+        assert_eq!(tagged, vec![true, false, false, false, false]);
+        assert!(tagger.idle_time_requests.is_empty());
+        assert!(tagger.last_modified.is_empty());
+    }
+}
@@ -0,0 +1,563 @@
+use crate::config::chain::TransformChainConfig;
+use crate::frame::cassandra::{parse_statement_single, Tracing};
+use crate::frame::value::GenericValue;
+use crate::frame::{
+    CassandraFrame, CassandraOperation, CassandraResult, Frame, MessageType, RedisFrame,
+};
+use crate::message::{Message, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use cassandra_protocol::consistency::Consistency;
+use cassandra_protocol::frame::message_result::RowsMetadata;
+use cassandra_protocol::frame::message_startup::BodyReqStartup;
+use cassandra_protocol::frame::Version;
+use cassandra_protocol::query::{QueryParams, QueryValues};
+use cassandra_protocol::types::value::Value;
+use cql3_parser::cassandra_statement::CassandraStatement;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+/// Executes simple redis commands against a cassandra table with a schema like:
+/// `CREATE TABLE cache.kv (key blob PRIMARY KEY, value blob)`
+///
+/// * `GET`/`SET`/`DEL` read and write the value column of the row for the key.
+/// * `EXPIRE` and the `EX`/`PX` options of `SET` are implemented with cassandra's native TTL.
+/// * `HGETALL` returns every non-null column of the row, other than the key, as a field of the hash.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RedisToCassandraConfig {
+    /// The fully qualified name of the table that keys are stored in e.g. `cache.kv`
+    pub table: String,
+    /// Defaults to `key`
+    pub key_column: Option<String>,
+    /// Defaults to `value`
+    pub value_column: Option<String>,
+    /// Credentials used if cassandra requires authentication.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub chain: TransformChainConfig,
+}
+
+const NAME: &str = "RedisToCassandra";
+#[typetag::serde(name = "RedisToCassandra")]
+#[async_trait(?Send)]
+impl TransformConfig for RedisToCassandraConfig {
+    async fn get_builder(
+        &self,
+        _transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let transform_context_config = TransformContextConfig {
+            chain_name: "cassandra_chain".into(),
+            up_chain_protocol: MessageType::Cassandra,
+        };
+
+        Ok(Box::new(RedisToCassandraBuilder {
+            cassandra_chain: self.chain.get_builder(transform_context_config).await?,
+            statements: Statements::new(
+                &self.table,
+                self.key_column.as_deref().unwrap_or("key"),
+                self.value_column.as_deref().unwrap_or("value"),
+            ),
+            credentials: match (&self.username, &self.password) {
+                (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+                _ => None,
+            },
+            credentials_misconfigured: self.username.is_some() != self.password.is_some(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![MessageType::Redis])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+/// The statements are parsed once up front and cloned for each request, values are always provided via bind markers.
+#[derive(Clone)]
+struct Statements {
+    table: String,
+    key_column: String,
+    value_column: String,
+    select_value: CassandraStatement,
+    select_row: CassandraStatement,
+    insert: CassandraStatement,
+    delete: CassandraStatement,
+}
+
+impl Statements {
+    fn new(table: &str, key_column: &str, value_column: &str) -> Self {
+        Statements {
+            table: table.to_owned(),
+            key_column: key_column.to_owned(),
+            value_column: value_column.to_owned(),
+            select_value: parse_statement_single(&format!(
+                "SELECT {value_column} FROM {table} WHERE {key_column} = ?"
+            )),
+            select_row: parse_statement_single(&format!(
+                "SELECT * FROM {table} WHERE {key_column} = ?"
+            )),
+            insert: parse_statement_single(&format!(
+                "INSERT INTO {table} ({key_column}, {value_column}) VALUES (?, ?)"
+            )),
+            delete: parse_statement_single(&format!("DELETE FROM {table} WHERE {key_column} = ?")),
+        }
+    }
+
+    fn insert_with_ttl(&self, ttl_seconds: u64) -> CassandraStatement {
+        parse_statement_single(&format!(
+            "INSERT INTO {} ({}, {}) VALUES (?, ?) USING TTL {ttl_seconds}",
+            self.table, self.key_column, self.value_column
+        ))
+    }
+}
+
+struct RedisToCassandraBuilder {
+    cassandra_chain: TransformChainBuilder,
+    statements: Statements,
+    credentials: Option<(String, String)>,
+    credentials_misconfigured: bool,
+}
+
+impl TransformBuilder for RedisToCassandraBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(RedisToCassandra {
+            cassandra_chain: self.cassandra_chain.build(transform_context),
+            statements: self.statements.clone(),
+            credentials: self.credentials.clone(),
+            handshake_complete: false,
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .cassandra_chain
+            .validate()
+            .iter()
+            .map(|x| format!("  {x}"))
+            .collect::<Vec<String>>();
+
+        if self.credentials_misconfigured {
+            errors.push("  username and password must be configured together".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct RedisToCassandra {
+    cassandra_chain: TransformChain,
+    statements: Statements,
+    credentials: Option<(String, String)>,
+    handshake_complete: bool,
+}
+
+fn cassandra_message(operation: CassandraOperation) -> Message {
+    Message::from_frame(Frame::Cassandra(CassandraFrame {
+        version: Version::V4,
+        stream_id: 0,
+        tracing: Tracing::Request(false),
+        warnings: vec![],
+        operation,
+    }))
+}
+
+fn query_message(statement: CassandraStatement, values: &[&Bytes]) -> Message {
+    cassandra_message(CassandraOperation::Query {
+        query: Box::new(statement),
+        params: Box::new(QueryParams {
+            consistency: Consistency::LocalQuorum,
+            values: Some(QueryValues::SimpleValues(
+                values.iter().map(|x| Value::Some(x.to_vec())).collect(),
+            )),
+            ..Default::default()
+        }),
+    })
+}
+
+fn rows(operation: CassandraOperation) -> Result<(Vec<Vec<GenericValue>>, Box<RowsMetadata>)> {
+    match operation {
+        CassandraOperation::Result(CassandraResult::Rows { rows, metadata }) => {
+            Ok((rows, metadata))
+        }
+        CassandraOperation::Error(err) => bail!("cassandra error: {}", err.message),
+        other => bail!("unexpected cassandra response: {other:?}"),
+    }
+}
+
+fn void(operation: CassandraOperation) -> Result<()> {
+    match operation {
+        CassandraOperation::Result(_) => Ok(()),
+        CassandraOperation::Error(err) => bail!("cassandra error: {}", err.message),
+        other => bail!("unexpected cassandra response: {other:?}"),
+    }
+}
+
+/// Converts a cassandra value into the bytes of a redis bulk string.
+/// Strings and numbers are represented the same way redis would represent them,
+/// other types such as collections are represented as JSON.
+fn value_to_bytes(value: GenericValue) -> Option<Bytes> {
+    Some(match value {
+        GenericValue::Null => return None,
+        GenericValue::Bytes(bytes) | GenericValue::Custom(bytes) => bytes,
+        GenericValue::Ascii(string)
+        | GenericValue::Strings(string)
+        | GenericValue::Varchar(string) => string.into(),
+        GenericValue::Integer(value, _) | GenericValue::Counter(value) => value.to_string().into(),
+        GenericValue::Varint(value) => value.to_string().into(),
+        GenericValue::Decimal(value) => value.to_string().into(),
+        GenericValue::Double(value) => value.to_string().into(),
+        GenericValue::Float(value) => value.to_string().into(),
+        GenericValue::Uuid(value) | GenericValue::Timeuuid(value) => value.to_string().into(),
+        GenericValue::Inet(value) => value.to_string().into(),
+        other => serde_json::to_vec(&other).ok()?.into(),
+    })
+}
+
+/// Parses the `EX seconds` or `PX milliseconds` options of SET into a TTL in seconds.
+fn parse_set_ttl(options: &[&Bytes]) -> Result<Option<u64>, &'static str> {
+    match options {
+        [] => Ok(None),
+        [option, value] => {
+            let value: u64 = std::str::from_utf8(value)
+                .ok()
+                .and_then(|x| x.parse().ok())
+                .filter(|x| *x > 0)
+                .ok_or("ERR invalid expire time in 'set' command")?;
+            if option.eq_ignore_ascii_case(b"EX") {
+                Ok(Some(value))
+            } else if option.eq_ignore_ascii_case(b"PX") {
+                // cassandra TTLs are in seconds, round up so the key never expires early
+                Ok(Some(value.div_ceil(1000)))
+            } else {
+                Err("ERR syntax error")
+            }
+        }
+        _ => Err("ERR syntax error"),
+    }
+}
+
+fn wrong_arguments(command: &str) -> RedisFrame {
+    RedisFrame::Error(format!("ERR wrong number of arguments for '{command}' command").into())
+}
+
+impl RedisToCassandra {
+    async fn execute(
+        &mut self,
+        request: Message,
+        local_addr: SocketAddr,
+    ) -> Result<CassandraOperation> {
+        let response = self
+            .cassandra_chain
+            .process_request(&mut ChainState::new_with_addr(vec![request], local_addr))
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow!("cassandra did not respond"))?;
+        match response.into_frame() {
+            Some(Frame::Cassandra(frame)) => Ok(frame.operation),
+            _ => bail!("cassandra responded with a non-cassandra frame"),
+        }
+    }
+
+    /// The client never talks to cassandra directly so we must perform the connection handshake ourselves.
+    async fn handshake(&mut self, local_addr: SocketAddr) -> Result<()> {
+        let startup = BodyReqStartup {
+            map: HashMap::from([("CQL_VERSION".to_owned(), "3.0.0".to_owned())]),
+        };
+        match self
+            .execute(
+                cassandra_message(CassandraOperation::Startup(startup)),
+                local_addr,
+            )
+            .await?
+        {
+            CassandraOperation::Ready(_) => Ok(()),
+            CassandraOperation::Authenticate(_) => {
+                let Some((username, password)) = &self.credentials else {
+                    bail!("cassandra requires authentication but no username and password are configured");
+                };
+                // SASL PLAIN token
+                let mut token = vec![0];
+                token.extend(username.as_bytes());
+                token.push(0);
+                token.extend(password.as_bytes());
+                match self
+                    .execute(
+                        cassandra_message(CassandraOperation::AuthResponse(token)),
+                        local_addr,
+                    )
+                    .await?
+                {
+                    CassandraOperation::AuthSuccess(_) => Ok(()),
+                    CassandraOperation::Error(err) => {
+                        bail!("cassandra authentication failed: {}", err.message)
+                    }
+                    other => bail!("unexpected response to AUTH_RESPONSE: {other:?}"),
+                }
+            }
+            CassandraOperation::Error(err) => bail!("cassandra STARTUP failed: {}", err.message),
+            other => bail!("unexpected response to STARTUP: {other:?}"),
+        }
+    }
+
+    async fn get_value(&mut self, key: &Bytes, local_addr: SocketAddr) -> Result<Option<Bytes>> {
+        let request = query_message(self.statements.select_value.clone(), &[key]);
+        let (rows, _) = rows(self.execute(request, local_addr).await?)?;
+        Ok(rows
+            .into_iter()
+            .next()
+            .and_then(|row| row.into_iter().next())
+            .and_then(value_to_bytes))
+    }
+
+    async fn set_value(
+        &mut self,
+        key: &Bytes,
+        value: &Bytes,
+        ttl_seconds: Option<u64>,
+        local_addr: SocketAddr,
+    ) -> Result<()> {
+        let statement = match ttl_seconds {
+            Some(ttl_seconds) => self.statements.insert_with_ttl(ttl_seconds),
+            None => self.statements.insert.clone(),
+        };
+        void(
+            self.execute(query_message(statement, &[key, value]), local_addr)
+                .await?,
+        )
+    }
+
+    async fn delete(&mut self, key: &Bytes, local_addr: SocketAddr) -> Result<()> {
+        let request = query_message(self.statements.delete.clone(), &[key]);
+        void(self.execute(request, local_addr).await?)
+    }
+
+    async fn execute_command(
+        &mut self,
+        command: &[RedisFrame],
+        local_addr: SocketAddr,
+    ) -> Result<RedisFrame> {
+        let mut args = Vec::with_capacity(command.len());
+        for arg in command {
+            match arg {
+                RedisFrame::BulkString(arg) => args.push(arg),
+                _ => {
+                    return Ok(RedisFrame::Error(
+                        "ERR Protocol error: expected bulk string".into(),
+                    ))
+                }
+            }
+        }
+        let Some((name, args)) = args.split_first() else {
+            return Ok(RedisFrame::Error("ERR empty command".into()));
+        };
+
+        Ok(match name.to_ascii_uppercase().as_slice() {
+            b"GET" => match args {
+                [key] => match self.get_value(key, local_addr).await? {
+                    Some(value) => RedisFrame::BulkString(value),
+                    None => RedisFrame::Null,
+                },
+                _ => wrong_arguments("get"),
+            },
+            b"SET" => match args {
+                [key, value, options @ ..] => match parse_set_ttl(options) {
+                    Ok(ttl_seconds) => {
+                        self.set_value(key, value, ttl_seconds, local_addr).await?;
+                        RedisFrame::SimpleString(Bytes::from_static(b"OK"))
+                    }
+                    Err(err) => RedisFrame::Error(err.into()),
+                },
+                _ => wrong_arguments("set"),
+            },
+            b"DEL" => {
+                if args.is_empty() {
+                    return Ok(wrong_arguments("del"));
+                }
+                // cassandra does not report whether a DELETE removed anything, so check existence first to return an accurate count.
+                let mut deleted = 0;
+                for key in args {
+                    if self.get_value(key, local_addr).await?.is_some() {
+                        self.delete(key, local_addr).await?;
+                        deleted += 1;
+                    }
+                }
+                RedisFrame::Integer(deleted)
+            }
+            b"EXPIRE" => match args {
+                [key, seconds] => {
+                    let Some(seconds) = std::str::from_utf8(seconds)
+                        .ok()
+                        .and_then(|x| x.parse::<i64>().ok())
+                    else {
+                        return Ok(RedisFrame::Error(
+                            "ERR value is not an integer or out of range".into(),
+                        ));
+                    };
+                    match self.get_value(key, local_addr).await? {
+                        // A TTL can only be applied to an existing row by rewriting its value.
+                        Some(value) if seconds > 0 => {
+                            self.set_value(key, &value, Some(seconds as u64), local_addr)
+                                .await?;
+                            RedisFrame::Integer(1)
+                        }
+                        Some(_) => {
+                            self.delete(key, local_addr).await?;
+                            RedisFrame::Integer(1)
+                        }
+                        None => RedisFrame::Integer(0),
+                    }
+                }
+                _ => wrong_arguments("expire"),
+            },
+            b"HGETALL" => match args {
+                [key] => {
+                    let request = query_message(self.statements.select_row.clone(), &[key]);
+                    let (rows, metadata) = rows(self.execute(request, local_addr).await?)?;
+                    let mut fields = vec![];
+                    if let Some(row) = rows.into_iter().next() {
+                        for (spec, value) in metadata.col_specs.iter().zip(row) {
+                            if spec.name == self.statements.key_column {
+                                continue;
+                            }
+                            if let Some(value) = value_to_bytes(value) {
+                                fields.push(RedisFrame::BulkString(spec.name.clone().into()));
+                                fields.push(RedisFrame::BulkString(value));
+                            }
+                        }
+                    }
+                    RedisFrame::Array(fields)
+                }
+                _ => wrong_arguments("hgetall"),
+            },
+            _ => RedisFrame::Error(
+                format!(
+                    "ERR unknown command '{}' - RedisToCassandra only supports GET, SET, DEL, EXPIRE and HGETALL",
+                    String::from_utf8_lossy(name)
+                )
+                .into(),
+            ),
+        })
+    }
+}
+
+#[async_trait]
+impl Transform for RedisToCassandra {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        self.cassandra_chain.shutdown().await;
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let local_addr = chain_state.local_addr;
+        if !self.handshake_complete && !chain_state.requests.is_empty() {
+            self.handshake(local_addr).await?;
+            self.handshake_complete = true;
+        }
+
+        // Requests are executed one at a time since cassandra does not guarantee that
+        // requests sent concurrently are applied in order, while redis clients rely on this.
+        let mut responses = Vec::with_capacity(chain_state.requests.len());
+        for mut request in chain_state.requests.drain(..) {
+            let frame = match request.frame() {
+                Some(Frame::Redis(RedisFrame::Array(command))) => {
+                    match self.execute_command(command, local_addr).await {
+                        Ok(frame) => frame,
+                        Err(err) => RedisFrame::Error(format!("ERR {err}").into()),
+                    }
+                }
+                _ => RedisFrame::Error("ERR Protocol error: expected an array".into()),
+            };
+            let mut response = Message::from_frame(Frame::Redis(frame));
+            response.set_request_id(request.id());
+            responses.push(response);
+        }
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn set_ttl_options() {
+        let ex = Bytes::from_static(b"EX");
+        let px = Bytes::from_static(b"px");
+        let nx = Bytes::from_static(b"NX");
+        let value = Bytes::from_static(b"1500");
+        let zero = Bytes::from_static(b"0");
+
+        assert_eq!(parse_set_ttl(&[]), Ok(None));
+        assert_eq!(parse_set_ttl(&[&ex, &value]), Ok(Some(1500)));
+        assert_eq!(parse_set_ttl(&[&px, &value]), Ok(Some(2)));
+        assert_eq!(parse_set_ttl(&[&nx, &value]), Err("ERR syntax error"));
+        assert_eq!(
+            parse_set_ttl(&[&ex, &zero]),
+            Err("ERR invalid expire time in 'set' command")
+        );
+        assert_eq!(parse_set_ttl(&[&ex]), Err("ERR syntax error"));
+    }
+
+    #[test]
+    fn values_to_bytes() {
+        assert_eq!(value_to_bytes(GenericValue::Null), None);
+        assert_eq!(
+            value_to_bytes(GenericValue::Varchar("foo".to_owned())),
+            Some(Bytes::from_static(b"foo"))
+        );
+        assert_eq!(
+            value_to_bytes(GenericValue::Integer(42, crate::frame::value::IntSize::I32)),
+            Some(Bytes::from_static(b"42"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_invalid_chain() {
+        let transform = RedisToCassandraBuilder {
+            cassandra_chain: TransformChainBuilder::new(vec![], "cassandra_chain"),
+            statements: Statements::new("cache.kv", "key", "value"),
+            credentials: None,
+            credentials_misconfigured: true,
+        };
+
+        assert_eq!(
+            transform.validate(),
+            vec![
+                "RedisToCassandra:",
+                "  cassandra_chain chain:",
+                "    Chain cannot be empty",
+                "  username and password must be configured together",
+            ]
+        );
+    }
+}
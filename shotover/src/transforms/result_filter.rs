@@ -0,0 +1,668 @@
+use crate::frame::{Frame, MessageType};
+use crate::message::{Message, Messages};
+use crate::transforms::util::glob_match;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[cfg(feature = "cassandra")]
+use {
+    crate::frame::cassandra::CassandraResult,
+    crate::frame::value::GenericValue,
+    crate::frame::{CassandraFrame, CassandraOperation},
+    cassandra_protocol::frame::message_result::{RowsMetadata, RowsMetadataFlags},
+};
+#[cfg(feature = "redis")]
+use {crate::frame::RedisFrame, crate::message::MessageIdMap, bytes::Bytes};
+
+/// Removes columns and rows from cassandra results and fields and members from redis responses, so that clients never see them.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ResultFilterConfig {
+    /// Each response is filtered by the first rule whose pattern matches its key or table, responses matching no rule are unchanged.
+    pub rules: Vec<ResultFilterRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ResultFilterRule {
+    /// A pattern matching redis keys or fully qualified cassandra table names.
+    /// `*` matches any sequence of characters and `?` matches any single character.
+    pub pattern: String,
+    /// Cassandra columns and redis hash fields matching any of these patterns are removed.
+    #[serde(default)]
+    pub drop_columns: Vec<String>,
+    /// When set, only cassandra columns and redis hash fields matching one of these patterns are kept.
+    pub keep_columns: Option<Vec<String>>,
+    /// Cassandra rows and redis list, set and sorted set members matching any of these conditions are removed.
+    #[serde(default)]
+    pub drop_rows: Vec<RowCondition>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RowCondition {
+    /// The cassandra column whose value is matched, a condition without a column matches redis members instead.
+    pub column: Option<String>,
+    /// A pattern matching the value, cassandra values are matched by their text representation.
+    pub value: String,
+}
+
+impl ResultFilterRule {
+    fn drops_column(&self, name: &[u8]) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| glob_match(pattern.as_bytes(), name))
+        };
+        matches(&self.drop_columns)
+            || self
+                .keep_columns
+                .as_ref()
+                .is_some_and(|keep| !matches(keep))
+    }
+
+    fn filters_columns(&self) -> bool {
+        !self.drop_columns.is_empty() || self.keep_columns.is_some()
+    }
+}
+
+const NAME: &str = "ResultFilter";
+#[typetag::serde(name = "ResultFilter")]
+#[async_trait(?Send)]
+impl TransformConfig for ResultFilterConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(ResultFilterBuilder {
+            rules: Arc::new(self.rules.clone()),
+            removed_columns: counter!("shotover_result_filter_removed_count", "chain" => chain_name.clone(), "element" => "column"),
+            removed_rows: counter!("shotover_result_filter_removed_count", "chain" => chain_name, "element" => "row"),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct ResultFilterBuilder {
+    rules: Arc<Vec<ResultFilterRule>>,
+    removed_columns: Counter,
+    removed_rows: Counter,
+}
+
+impl TransformBuilder for ResultFilterBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(ResultFilter {
+            rules: self.rules.clone(),
+            removed_columns: self.removed_columns.clone(),
+            removed_rows: self.removed_rows.clone(),
+            #[cfg(feature = "redis")]
+            redis_requests: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.rules.is_empty() {
+            errors.push("  at least one rule must be configured".to_owned());
+        }
+        for rule in self.rules.iter() {
+            if !rule.filters_columns() && rule.drop_rows.is_empty() {
+                errors.push(format!(
+                    "  rule {:?} must set at least one of drop_columns, keep_columns or drop_rows",
+                    rule.pattern
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct ResultFilter {
+    rules: Arc<Vec<ResultFilterRule>>,
+    removed_columns: Counter,
+    removed_rows: Counter,
+    /// How to filter the response to each redis request that matched a rule, keyed by the id of the request
+    #[cfg(feature = "redis")]
+    redis_requests: MessageIdMap<(usize, RedisResponse)>,
+}
+
+impl ResultFilter {
+    fn rule_index(&self, resource: &[u8]) -> Option<usize> {
+        self.rules
+            .iter()
+            .position(|rule| glob_match(rule.pattern.as_bytes(), resource))
+    }
+
+    fn filter_response(&mut self, response: &mut Message) -> bool {
+        #[cfg(feature = "redis")]
+        let request_id = response.request_id();
+        let (columns, rows) = match response.frame() {
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(frame)) => {
+                let Some((index, kind)) = request_id.and_then(|id| self.redis_requests.remove(&id))
+                else {
+                    return false;
+                };
+                kind.filter(&self.rules[index], frame)
+            }
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(CassandraFrame {
+                operation: CassandraOperation::Result(CassandraResult::Rows { rows, metadata }),
+                ..
+            })) => match table_name(metadata).and_then(|table| self.rule_index(table.as_bytes())) {
+                Some(index) => filter_rows(&self.rules[index], rows, metadata),
+                None => (0, 0),
+            },
+            _ => (0, 0),
+        };
+        self.removed_columns.increment(columns as u64);
+        self.removed_rows.increment(rows as u64);
+        columns > 0 || rows > 0
+    }
+}
+
+/// Returns the fully qualified name of the table that the rows were selected from.
+#[cfg(feature = "cassandra")]
+fn table_name(metadata: &RowsMetadata) -> Option<String> {
+    metadata
+        .global_table_spec
+        .as_ref()
+        .or_else(|| metadata.col_specs.first()?.table_spec.as_ref())
+        .map(|spec| format!("{}.{}", spec.ks_name, spec.table_name))
+}
+
+/// Removes the rows and then the columns that the rule drops, returning the number of (columns, rows) removed.
+#[cfg(feature = "cassandra")]
+fn filter_rows(
+    rule: &ResultFilterRule,
+    rows: &mut Vec<Vec<GenericValue>>,
+    metadata: &mut RowsMetadata,
+) -> (usize, usize) {
+    // Rows without metadata cannot be filtered since the names of their columns are not known,
+    // which is why EXECUTE requests are sent without the skip metadata flag.
+    if metadata.flags.contains(RowsMetadataFlags::NO_METADATA) {
+        return (0, 0);
+    }
+
+    let conditions: Vec<(usize, &str)> = rule
+        .drop_rows
+        .iter()
+        .filter_map(|condition| {
+            let column = condition.column.as_ref()?;
+            let index = metadata
+                .col_specs
+                .iter()
+                .position(|spec| spec.name == *column)?;
+            Some((index, condition.value.as_str()))
+        })
+        .collect();
+    let row_count = rows.len();
+    if !conditions.is_empty() {
+        rows.retain(|row| {
+            !conditions.iter().any(|(index, pattern)| {
+                row.get(*index)
+                    .and_then(value_to_string)
+                    .is_some_and(|value| glob_match(pattern.as_bytes(), value.as_bytes()))
+            })
+        });
+    }
+    let removed_rows = row_count - rows.len();
+
+    let keep: Vec<bool> = metadata
+        .col_specs
+        .iter()
+        .map(|spec| !rule.drops_column(spec.name.as_bytes()))
+        .collect();
+    let removed_columns = keep.iter().filter(|x| !**x).count();
+    if removed_columns > 0 {
+        let mut index = 0;
+        metadata.col_specs.retain(|_| {
+            index += 1;
+            keep[index - 1]
+        });
+        metadata.columns_count = metadata.col_specs.len() as i32;
+        for row in rows.iter_mut() {
+            let mut index = 0;
+            row.retain(|_| {
+                index += 1;
+                keep[index - 1]
+            });
+        }
+    }
+
+    (removed_columns, removed_rows)
+}
+
+/// Returns the text representation of values that conditions can match, collections and binary values are never matched.
+#[cfg(feature = "cassandra")]
+fn value_to_string(value: &GenericValue) -> Option<String> {
+    Some(match value {
+        GenericValue::Ascii(string)
+        | GenericValue::Strings(string)
+        | GenericValue::Varchar(string) => string.clone(),
+        GenericValue::Integer(value, _) | GenericValue::Counter(value) => value.to_string(),
+        GenericValue::Varint(value) => value.to_string(),
+        GenericValue::Decimal(value) => value.to_string(),
+        GenericValue::Boolean(value) => value.to_string(),
+        GenericValue::Uuid(value) | GenericValue::Timeuuid(value) => value.to_string(),
+        GenericValue::Inet(value) => value.to_string(),
+        _ => return None,
+    })
+}
+
+/// The shape of the response to a redis command whose key matched a rule.
+#[cfg(feature = "redis")]
+#[derive(Debug, PartialEq)]
+enum RedisResponse {
+    /// An array of field value pairs e.g. HGETALL
+    FieldValuePairs,
+    /// The response to a HGETALL that was sent in place of a HVALS, it is converted back into just the values
+    Values,
+    /// An array of fields e.g. HKEYS
+    Fields,
+    /// The value of a single field e.g. HGET, true if the field is dropped
+    FieldValue(bool),
+    /// An array of the values of the fields of the command e.g. HMGET, true for each field that is dropped
+    PerFieldValues(Vec<bool>),
+    /// An array of members, or member score pairs when `with_scores` is set, e.g. SMEMBERS and ZRANGE
+    Members { with_scores: bool },
+    /// A cursor followed by an array in the form of the inner response e.g. HSCAN
+    Scan(Box<RedisResponse>),
+}
+
+#[cfg(feature = "redis")]
+impl ResultFilter {
+    /// Records how to filter the response to the command if its key matches a rule.
+    /// Returns true if the command was changed.
+    fn on_redis_request(&mut self, request: &mut Message) -> bool {
+        let id = request.id();
+        let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+            return false;
+        };
+        let (Some(RedisFrame::BulkString(command)), Some(RedisFrame::BulkString(key))) =
+            (args.first(), args.get(1))
+        else {
+            return false;
+        };
+        let Some(index) = self.rule_index(key) else {
+            return false;
+        };
+        let rule = &self.rules[index];
+        let command = command.to_ascii_uppercase();
+        let drops_field = |arg: &RedisFrame| match arg {
+            RedisFrame::BulkString(field) => rule.drops_column(field),
+            _ => false,
+        };
+
+        let columns = rule.filters_columns();
+        let members = rule.drop_rows.iter().any(|x| x.column.is_none());
+        let mut changed = false;
+        let kind = match command.as_slice() {
+            b"HGETALL" if columns => RedisResponse::FieldValuePairs,
+            b"HVALS" if columns => {
+                args[0] = RedisFrame::BulkString(Bytes::from_static(b"HGETALL"));
+                changed = true;
+                RedisResponse::Values
+            }
+            b"HKEYS" if columns => RedisResponse::Fields,
+            b"HGET" if columns => RedisResponse::FieldValue(args.get(2).is_some_and(drops_field)),
+            b"HMGET" if columns => {
+                RedisResponse::PerFieldValues(args[2..].iter().map(drops_field).collect())
+            }
+            b"HSCAN" if columns => RedisResponse::Scan(Box::new(RedisResponse::FieldValuePairs)),
+            b"SMEMBERS" | b"LRANGE" if members => RedisResponse::Members { with_scores: false },
+            b"ZRANGE" | b"ZREVRANGE" | b"ZRANGEBYSCORE" | b"ZREVRANGEBYSCORE" if members => {
+                let with_scores = args[2..].iter().any(|arg| {
+                    matches!(arg, RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(b"WITHSCORES"))
+                });
+                RedisResponse::Members { with_scores }
+            }
+            b"SSCAN" if members => {
+                RedisResponse::Scan(Box::new(RedisResponse::Members { with_scores: false }))
+            }
+            b"ZSCAN" if members => {
+                RedisResponse::Scan(Box::new(RedisResponse::Members { with_scores: true }))
+            }
+            _ => return false,
+        };
+        self.redis_requests.insert(id, (index, kind));
+        changed
+    }
+}
+
+#[cfg(feature = "redis")]
+impl RedisResponse {
+    /// Filters the response according to the rule, returning the number of (fields, members) removed.
+    /// Error responses and responses of an unexpected shape are left unchanged.
+    fn filter(&self, rule: &ResultFilterRule, response: &mut RedisFrame) -> (usize, usize) {
+        let drops_field = |field: &RedisFrame| match field {
+            RedisFrame::BulkString(field) => rule.drops_column(field),
+            _ => false,
+        };
+        let drops_member = |member: &RedisFrame| match member {
+            RedisFrame::BulkString(member) => rule.drop_rows.iter().any(|condition| {
+                condition.column.is_none() && glob_match(condition.value.as_bytes(), member)
+            }),
+            _ => false,
+        };
+
+        match (self, response) {
+            (RedisResponse::FieldValue(true), response) => {
+                let removed = !matches!(response, RedisFrame::Null | RedisFrame::Error(_));
+                if removed {
+                    *response = RedisFrame::Null;
+                }
+                (removed as usize, 0)
+            }
+            (RedisResponse::PerFieldValues(dropped), RedisFrame::Array(values)) => {
+                let mut removed = 0;
+                for (value, dropped) in values.iter_mut().zip(dropped) {
+                    if *dropped && *value != RedisFrame::Null {
+                        *value = RedisFrame::Null;
+                        removed += 1;
+                    }
+                }
+                (removed, 0)
+            }
+            (RedisResponse::Fields, RedisFrame::Array(fields)) => {
+                let count = fields.len();
+                fields.retain(|field| !drops_field(field));
+                (count - fields.len(), 0)
+            }
+            (RedisResponse::FieldValuePairs, RedisFrame::Array(pairs)) => {
+                (retain_pairs(pairs, |field| !drops_field(field)), 0)
+            }
+            (RedisResponse::Values, RedisFrame::Array(pairs)) => {
+                let removed = retain_pairs(pairs, |field| !drops_field(field));
+                *pairs = std::mem::take(pairs)
+                    .into_iter()
+                    .skip(1)
+                    .step_by(2)
+                    .collect();
+                (removed, 0)
+            }
+            (RedisResponse::Members { with_scores: true }, RedisFrame::Array(members)) => {
+                (0, retain_pairs(members, |member| !drops_member(member)))
+            }
+            (RedisResponse::Members { with_scores: false }, RedisFrame::Array(members)) => {
+                let count = members.len();
+                members.retain(|member| !drops_member(member));
+                (0, count - members.len())
+            }
+            (RedisResponse::Scan(inner), RedisFrame::Array(cursor_and_elements)) => {
+                match cursor_and_elements.get_mut(1) {
+                    Some(elements) => inner.filter(rule, elements),
+                    None => (0, 0),
+                }
+            }
+            _ => (0, 0),
+        }
+    }
+}
+
+/// Keeps the pairs of elements whose first element is accepted by `keep`, returning the number of pairs removed.
+#[cfg(feature = "redis")]
+fn retain_pairs(elements: &mut Vec<RedisFrame>, keep: impl Fn(&RedisFrame) -> bool) -> usize {
+    let count = elements.len() / 2;
+    let mut pairs = std::mem::take(elements).into_iter();
+    while let (Some(first), Some(second)) = (pairs.next(), pairs.next()) {
+        if keep(&first) {
+            elements.push(first);
+            elements.push(second);
+        }
+    }
+    count - elements.len() / 2
+}
+
+#[async_trait]
+impl Transform for ResultFilter {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in chain_state.requests.iter_mut() {
+            let changed = match request.frame() {
+                #[cfg(feature = "redis")]
+                Some(Frame::Redis(_)) => self.on_redis_request(request),
+                // The client may have asked for rows without metadata, but the columns of rows can only be filtered by their name.
+                // Shotover never sets the skip metadata flag when encoding an EXECUTE, so encoding it again clears the flag.
+                #[cfg(feature = "cassandra")]
+                Some(Frame::Cassandra(CassandraFrame {
+                    operation: CassandraOperation::Execute(_),
+                    ..
+                })) => true,
+                _ => false,
+            };
+            if changed {
+                request.invalidate_cache();
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            if self.filter_response(response) {
+                response.invalidate_cache();
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn rule(pattern: &str) -> ResultFilterRule {
+        ResultFilterRule {
+            pattern: pattern.to_owned(),
+            drop_columns: vec!["ssn".to_owned(), "internal_*".to_owned()],
+            keep_columns: None,
+            drop_rows: vec![],
+        }
+    }
+
+    fn builder(rules: Vec<ResultFilterRule>) -> ResultFilterBuilder {
+        ResultFilterBuilder {
+            rules: Arc::new(rules),
+            removed_columns: Counter::noop(),
+            removed_rows: Counter::noop(),
+        }
+    }
+
+    #[test]
+    fn test_drops_column() {
+        let rule = rule("*");
+        assert!(rule.drops_column(b"ssn"));
+        assert!(rule.drops_column(b"internal_id"));
+        assert!(!rule.drops_column(b"name"));
+
+        let projection = ResultFilterRule {
+            keep_columns: Some(vec!["id".to_owned(), "name*".to_owned()]),
+            ..rule
+        };
+        assert!(projection.drops_column(b"email"));
+        assert!(!projection.drops_column(b"name"));
+        assert!(!projection.drops_column(b"id"));
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_redis() {
+        use crate::test_utils::{assert_redis_responses, redis_command, MockSink, TestChain};
+
+        fn bulk(value: &str) -> RedisFrame {
+            RedisFrame::BulkString(Bytes::copy_from_slice(value.as_bytes()))
+        }
+        fn array(values: &[&str]) -> RedisFrame {
+            RedisFrame::Array(values.iter().map(|x| bulk(x)).collect())
+        }
+
+        let rule = ResultFilterRule {
+            drop_rows: vec![RowCondition {
+                column: None,
+                value: "_*".to_owned(),
+            }],
+            ..rule("user:*")
+        };
+        let sink = MockSink::new(|request| {
+            let Some(Frame::Redis(RedisFrame::Array(args))) = request.frame() else {
+                panic!("expected a redis command")
+            };
+            let RedisFrame::BulkString(command) = &args[0] else {
+                panic!("expected a redis command")
+            };
+            let response = match command.as_ref() {
+                b"HGETALL" => array(&["name", "foo", "ssn", "123", "internal_id", "5"]),
+                b"HMGET" => array(&["foo", "123"]),
+                b"HGET" => bulk("123"),
+                b"SMEMBERS" => array(&["a", "_hidden", "b"]),
+                b"ZRANGE" => array(&["_hidden", "1", "a", "2"]),
+                b"HSCAN" => {
+                    RedisFrame::Array(vec![bulk("0"), array(&["ssn", "123", "name", "foo"])])
+                }
+                command => panic!("unexpected command {command:?}"),
+            };
+            Message::from_frame(Frame::Redis(response))
+        });
+        let mut chain = TestChain::from_builder(&builder(vec![rule]), sink).unwrap();
+
+        let responses = chain
+            .send(vec![
+                redis_command(&["HGETALL", "user:1"]),
+                redis_command(&["HVALS", "user:1"]),
+                redis_command(&["HMGET", "user:1", "name", "ssn"]),
+                redis_command(&["HGET", "user:1", "ssn"]),
+                redis_command(&["SMEMBERS", "user:1:tags"]),
+                redis_command(&["ZRANGE", "user:1:scores", "0", "-1", "WITHSCORES"]),
+                redis_command(&["HSCAN", "user:1", "0"]),
+                redis_command(&["HGETALL", "other"]),
+            ])
+            .await
+            .unwrap();
+        assert_redis_responses(
+            responses,
+            &[
+                array(&["name", "foo"]),
+                array(&["foo"]),
+                RedisFrame::Array(vec![bulk("foo"), RedisFrame::Null]),
+                RedisFrame::Null,
+                array(&["a", "b"]),
+                array(&["a", "2"]),
+                RedisFrame::Array(vec![bulk("0"), array(&["name", "foo"])]),
+                array(&["name", "foo", "ssn", "123", "internal_id", "5"]),
+            ],
+        );
+
+        // HVALS is sent as HGETALL so that the values of dropped fields can be identified
+        let mut received = chain.take_received();
+        assert_eq!(
+            received[1].frame().cloned(),
+            Some(Frame::Redis(array(&["HGETALL", "user:1"])))
+        );
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_cassandra() {
+        use cassandra_protocol::frame::message_result::{
+            ColSpec, ColType, ColTypeOption, TableSpec,
+        };
+
+        let column = |name: &str| ColSpec {
+            table_spec: None,
+            name: name.to_owned(),
+            col_type: ColTypeOption {
+                id: ColType::Varchar,
+                value: None,
+            },
+        };
+        let mut metadata = RowsMetadata {
+            flags: RowsMetadataFlags::GLOBAL_TABLE_SPACE,
+            columns_count: 3,
+            paging_state: None,
+            new_metadata_id: None,
+            global_table_spec: Some(TableSpec {
+                ks_name: "ks".into(),
+                table_name: "users".into(),
+            }),
+            col_specs: vec![column("name"), column("ssn"), column("tenant")],
+        };
+        let row = |name: &str, tenant: &str| {
+            vec![
+                GenericValue::Varchar(name.to_owned()),
+                GenericValue::Varchar("123".to_owned()),
+                GenericValue::Varchar(tenant.to_owned()),
+            ]
+        };
+        let mut rows = vec![row("foo", "acme"), row("bar", "internal")];
+        assert_eq!(table_name(&metadata).as_deref(), Some("ks.users"));
+
+        let rule = ResultFilterRule {
+            drop_rows: vec![RowCondition {
+                column: Some("tenant".to_owned()),
+                value: "internal".to_owned(),
+            }],
+            ..rule("ks.users")
+        };
+        assert_eq!(filter_rows(&rule, &mut rows, &mut metadata), (1, 1));
+        assert_eq!(
+            rows,
+            vec![vec![
+                GenericValue::Varchar("foo".to_owned()),
+                GenericValue::Varchar("acme".to_owned()),
+            ]]
+        );
+        assert_eq!(metadata.columns_count, 2);
+        assert_eq!(metadata.col_specs, vec![column("name"), column("tenant")]);
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = builder(vec![ResultFilterRule {
+            drop_columns: vec![],
+            ..rule("a")
+        }]);
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "ResultFilter:",
+                "  rule \"a\" must set at least one of drop_columns, keep_columns or drop_rows",
+            ]
+        );
+    }
+}
@@ -0,0 +1,609 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::config::chain::TransformChainConfig;
+use crate::frame::Frame;
+use crate::message::{ErrorKind, Message, MessageId, MessageIdMap, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::util::ordered_responses::OrderedResponses;
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use futures::future::join_all;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ScatterGatherConfig {
+    /// Every request is sent to each of these chains concurrently.
+    pub chains: Vec<TransformChainConfig>,
+    /// How the responses of the chains are combined into the response returned to the client.
+    pub strategy: GatherStrategy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum GatherStrategy {
+    /// Returns the successful response of the first chain, in the order the chains are configured.
+    /// When every chain responds with an error, the error of the first chain is returned.
+    FirstSuccess,
+    /// Returns the response once `quorum` chains have returned identical responses.
+    /// When the chains cannot reach a quorum, an error is returned instead.
+    Quorum { quorum: usize },
+    /// Combines the rows of every chain's response into a single response.
+    /// Supports Cassandra row results and Redis arrays, any other response is taken from the first chain.
+    /// When any chain responds with an error, the error of the first such chain is returned.
+    MergeRows,
+    /// Returns the successful response whose data was most recently accessed, as tagged by a RedisTimestampTagger in each chain.
+    /// Responses that are not tagged are considered older than any tagged response, ties are won by the first chain.
+    /// When every chain responds with an error, the error of the first chain is returned.
+    MostRecent,
+}
+
+const NAME: &str = "ScatterGather";
+#[typetag::serde(name = "ScatterGather")]
+#[async_trait(?Send)]
+impl TransformConfig for ScatterGatherConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        if self.chains.is_empty() {
+            bail!("ScatterGather requires at least one chain");
+        }
+        if let GatherStrategy::Quorum { quorum } = self.strategy {
+            if quorum == 0 || quorum > self.chains.len() {
+                bail!(
+                    "quorum must be between 1 and the number of chains ({}) but was {quorum}",
+                    self.chains.len()
+                );
+            }
+        }
+
+        let mut chains = Vec::with_capacity(self.chains.len());
+        for chain in &self.chains {
+            chains.push(
+                chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: "scatter_gather_chain".into(),
+                        up_chain_protocol: transform_context.up_chain_protocol,
+                    })
+                    .await?,
+            );
+        }
+
+        Ok(Box::new(ScatterGatherBuilder {
+            chains,
+            strategy: self.strategy,
+            in_order: transform_context.up_chain_protocol.is_inorder(),
+            quorum_failures: counter!("shotover_scatter_gather_quorum_failures_count", "chain" => transform_context.chain_name),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+struct ScatterGatherBuilder {
+    chains: Vec<TransformChainBuilder>,
+    strategy: GatherStrategy,
+    in_order: bool,
+    quorum_failures: Counter,
+}
+
+impl TransformBuilder for ScatterGatherBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(ScatterGather {
+            chains: self
+                .chains
+                .iter()
+                .map(|x| x.build(transform_context.clone()))
+                .collect(),
+            strategy: self.strategy,
+            pending: MessageIdMap::default(),
+            responses: OrderedResponses::new(self.in_order),
+            quorum_failures: self.quorum_failures.clone(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = self
+            .chains
+            .iter()
+            .flat_map(|chain| {
+                chain
+                    .validate()
+                    .iter()
+                    .map(|x| format!("  {x}"))
+                    .collect::<Vec<String>>()
+            })
+            .collect::<Vec<String>>();
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+struct ScatterGather {
+    chains: Vec<TransformChain>,
+    strategy: GatherStrategy,
+    /// Requests that have not yet been responded to, keyed by request id
+    pending: MessageIdMap<Pending>,
+    responses: OrderedResponses,
+    quorum_failures: Counter,
+}
+
+struct Pending {
+    request: Message,
+    /// The outcome of the request on each chain, in the order the chains are configured
+    outcomes: Vec<Outcome>,
+}
+
+enum Outcome {
+    Waiting,
+    Response(Message),
+    /// The chain returned an error instead of responses, so it will never respond to the request
+    Failed,
+}
+
+#[async_trait]
+impl Transform for ScatterGather {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        for chain in &mut self.chains {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let requests = std::mem::take(&mut chain_state.requests);
+        for request in &requests {
+            self.responses.push_request(request.id());
+            self.pending.insert(
+                request.id(),
+                Pending {
+                    request: request.clone(),
+                    outcomes: self.chains.iter().map(|_| Outcome::Waiting).collect(),
+                },
+            );
+        }
+
+        // Every chain is run, even without any requests, to pick up any responses that arrived since the last run.
+        let local_addr = chain_state.local_addr;
+        let flush = chain_state.flush;
+        let session = &chain_state.session;
+        let results = join_all(self.chains.iter_mut().map(|chain| {
+            let requests = requests.clone();
+            async move {
+                let mut sub_chain_state = ChainState::new_with_addr(requests, local_addr);
+                sub_chain_state.flush = flush;
+                sub_chain_state.session = session.clone();
+                chain.process_request(&mut sub_chain_state).await
+            }
+        }))
+        .await;
+
+        let mut responses = vec![];
+        let mut errors = vec![];
+        for (i, result) in results.into_iter().enumerate() {
+            match result {
+                Ok(chain_responses) => {
+                    for response in chain_responses {
+                        match response.request_id() {
+                            Some(id) => {
+                                // Responses arriving after their request was already responded to are dropped
+                                if let Some(pending) = self.pending.get_mut(&id) {
+                                    pending.outcomes[i] = Outcome::Response(response);
+                                }
+                            }
+                            // Responses that are not for a request, such as pubsub messages, are only taken from the first chain
+                            None if i == 0 => responses.push(response),
+                            None => {}
+                        }
+                    }
+                }
+                Err(err) => {
+                    for request in &requests {
+                        if let Some(pending) = self.pending.get_mut(&request.id()) {
+                            pending.outcomes[i] = Outcome::Failed;
+                        }
+                    }
+                    errors.push(err);
+                }
+            }
+        }
+        if !errors.is_empty() && errors.len() == self.chains.len() {
+            return Err(errors.remove(0).context("Every ScatterGather chain failed"));
+        }
+        for err in errors {
+            warn!("A ScatterGather chain failed: {err:?}");
+        }
+
+        let ids: Vec<MessageId> = self.pending.keys().copied().collect();
+        for id in ids {
+            let pending = self.pending.get_mut(&id).unwrap();
+            if let Some(response) = self.strategy.gather(pending, &self.quorum_failures)? {
+                self.pending.remove(&id);
+                self.responses.insert(id, response);
+            }
+        }
+        self.responses.take_ready(&mut responses);
+
+        Ok(responses)
+    }
+}
+
+impl GatherStrategy {
+    /// Returns the response to the request if enough chains have responded to decide it.
+    fn gather(self, pending: &mut Pending, quorum_failures: &Counter) -> Result<Option<Message>> {
+        let response = match self {
+            GatherStrategy::FirstSuccess => {
+                for outcome in &mut pending.outcomes {
+                    match outcome {
+                        // An earlier chain may still respond successfully
+                        Outcome::Waiting => return Ok(None),
+                        Outcome::Response(response) => {
+                            if !is_error(response) {
+                                return Ok(Some(response.clone()));
+                            }
+                        }
+                        Outcome::Failed => {}
+                    }
+                }
+                first_response(pending)
+            }
+            GatherStrategy::Quorum { quorum } => {
+                let responses: Vec<&Message> = pending
+                    .outcomes
+                    .iter()
+                    .filter_map(|outcome| match outcome {
+                        Outcome::Response(response) => Some(response),
+                        _ => None,
+                    })
+                    .collect();
+                let mut agreeing = 0;
+                for response in &responses {
+                    let count = responses.iter().filter(|x| **x == *response).count();
+                    if count >= quorum {
+                        return Ok(Some((*response).clone()));
+                    }
+                    agreeing = agreeing.max(count);
+                }
+                if pending
+                    .outcomes
+                    .iter()
+                    .any(|x| matches!(x, Outcome::Waiting))
+                {
+                    return Ok(None);
+                }
+                quorum_failures.increment(1);
+                Some(pending.request.error_response(
+                    format!(
+                        "ScatterGather could not reach a quorum of {quorum} identical responses, at most {agreeing} chains agreed"
+                    ),
+                    ErrorKind::Unavailable,
+                )?)
+            }
+            GatherStrategy::MergeRows => {
+                if pending
+                    .outcomes
+                    .iter()
+                    .any(|x| matches!(x, Outcome::Waiting))
+                {
+                    return Ok(None);
+                }
+                let mut responses =
+                    pending
+                        .outcomes
+                        .iter_mut()
+                        .filter_map(|outcome| match outcome {
+                            Outcome::Response(response) => Some(response),
+                            _ => None,
+                        });
+                match responses.next() {
+                    Some(first) => {
+                        let mut merged = first.clone();
+                        if is_error(&mut merged) {
+                            Some(merged)
+                        } else {
+                            for response in responses {
+                                if is_error(response) {
+                                    return Ok(Some(response.clone()));
+                                }
+                                merge_rows(&mut merged, response);
+                            }
+                            Some(merged)
+                        }
+                    }
+                    None => None,
+                }
+            }
+            GatherStrategy::MostRecent => {
+                if pending
+                    .outcomes
+                    .iter()
+                    .any(|x| matches!(x, Outcome::Waiting))
+                {
+                    return Ok(None);
+                }
+                let mut most_recent: Option<(usize, Option<SystemTime>)> = None;
+                for (i, outcome) in pending.outcomes.iter_mut().enumerate() {
+                    if let Outcome::Response(response) = outcome {
+                        if !is_error(response)
+                            && most_recent.map_or(true, |(_, time)| response.last_modified() > time)
+                        {
+                            most_recent = Some((i, response.last_modified()));
+                        }
+                    }
+                }
+                match most_recent.map(|(i, _)| &pending.outcomes[i]) {
+                    Some(Outcome::Response(response)) => Some(response.clone()),
+                    _ => first_response(pending),
+                }
+            }
+        };
+        match response {
+            Some(response) => Ok(Some(response)),
+            // Every chain failed to respond to the request
+            None => pending
+                .request
+                .error_response(
+                    "Every ScatterGather chain failed to respond".to_owned(),
+                    ErrorKind::Server,
+                )
+                .map(Some),
+        }
+    }
+}
+
+/// Returns the response of the first chain that responded.
+fn first_response(pending: &Pending) -> Option<Message> {
+    pending.outcomes.iter().find_map(|outcome| match outcome {
+        Outcome::Response(response) => Some(response.clone()),
+        _ => None,
+    })
+}
+
+fn is_error(response: &mut Message) -> bool {
+    match response.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(crate::frame::RedisFrame::Error(_))) => true,
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(crate::frame::CassandraFrame {
+            operation: crate::frame::CassandraOperation::Error(_),
+            ..
+        })) => true,
+        #[cfg(feature = "memcached")]
+        Some(Frame::Memcached(crate::frame::MemcachedFrame::Response(
+            crate::frame::memcached::MemcachedResponse::ClientError(_)
+            | crate::frame::memcached::MemcachedResponse::ServerError(_),
+        ))) => true,
+        _ => false,
+    }
+}
+
+/// Appends the rows of `other` to the rows of `merged`, leaving `merged` unchanged if either response does not contain rows.
+fn merge_rows(merged: &mut Message, other: &mut Message) {
+    match (merged.frame(), other.frame()) {
+        #[cfg(feature = "redis")]
+        (
+            Some(Frame::Redis(crate::frame::RedisFrame::Array(rows))),
+            Some(Frame::Redis(crate::frame::RedisFrame::Array(other_rows))),
+        ) => {
+            rows.extend(other_rows.iter().cloned());
+            merged.invalidate_cache();
+        }
+        #[cfg(feature = "cassandra")]
+        (
+            Some(Frame::Cassandra(crate::frame::CassandraFrame {
+                operation:
+                    crate::frame::CassandraOperation::Result(
+                        crate::frame::cassandra::CassandraResult::Rows { rows, .. },
+                    ),
+                ..
+            })),
+            Some(Frame::Cassandra(crate::frame::CassandraFrame {
+                operation:
+                    crate::frame::CassandraOperation::Result(
+                        crate::frame::cassandra::CassandraResult::Rows {
+                            rows: other_rows, ..
+                        },
+                    ),
+                ..
+            })),
+        ) => {
+            rows.extend(other_rows.iter().cloned());
+            merged.invalidate_cache();
+        }
+        _ => {}
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::RedisFrame;
+    use crate::test_utils::{assert_error_response, assert_redis_responses, redis_command};
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use std::time::Duration;
+
+    fn returning(frame: RedisFrame) -> TransformChainBuilder {
+        TransformChainBuilder::new(
+            vec![Box::new(DebugReturner::new(Response::Message(
+                Message::from_frame(Frame::Redis(frame)),
+            )))],
+            "scatter_gather_chain",
+        )
+    }
+
+    fn failing() -> TransformChainBuilder {
+        TransformChainBuilder::new(
+            vec![Box::new(DebugReturner::new(Response::Fail))],
+            "scatter_gather_chain",
+        )
+    }
+
+    fn error() -> RedisFrame {
+        RedisFrame::Error("ERR down".into())
+    }
+
+    async fn run(strategy: GatherStrategy, chains: Vec<TransformChainBuilder>) -> Result<Messages> {
+        let mut transform = ScatterGatherBuilder {
+            chains,
+            strategy,
+            in_order: true,
+            quorum_failures: Counter::noop(),
+        }
+        .build(TransformContextBuilder::new_test());
+        transform
+            .transform(&mut ChainState::new_test(vec![
+                redis_command(&["GET", "foo"]),
+                redis_command(&["GET", "bar"]),
+            ]))
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_first_success() {
+        let responses = run(
+            GatherStrategy::FirstSuccess,
+            vec![
+                failing(),
+                returning(error()),
+                returning(RedisFrame::Integer(1)),
+                returning(RedisFrame::Integer(2)),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[RedisFrame::Integer(1), RedisFrame::Integer(1)]);
+
+        let responses = run(
+            GatherStrategy::FirstSuccess,
+            vec![returning(error()), failing()],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[error(), error()]);
+
+        assert!(
+            run(GatherStrategy::FirstSuccess, vec![failing(), failing()])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_quorum() {
+        let responses = run(
+            GatherStrategy::Quorum { quorum: 2 },
+            vec![
+                returning(RedisFrame::Integer(1)),
+                returning(RedisFrame::Integer(2)),
+                returning(RedisFrame::Integer(2)),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[RedisFrame::Integer(2), RedisFrame::Integer(2)]);
+
+        let mut responses = run(
+            GatherStrategy::Quorum { quorum: 2 },
+            vec![
+                returning(RedisFrame::Integer(1)),
+                returning(RedisFrame::Integer(2)),
+                failing(),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(responses.len(), 2);
+        assert_error_response(
+            &mut responses[0],
+            "could not reach a quorum of 2 identical responses, at most 1 chains agreed",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_rows() {
+        let responses = run(
+            GatherStrategy::MergeRows,
+            vec![
+                returning(RedisFrame::Array(vec![RedisFrame::Integer(1)])),
+                returning(RedisFrame::Array(vec![RedisFrame::Integer(2)])),
+            ],
+        )
+        .await
+        .unwrap();
+        let merged = RedisFrame::Array(vec![RedisFrame::Integer(1), RedisFrame::Integer(2)]);
+        assert_redis_responses(responses, &[merged.clone(), merged]);
+
+        let responses = run(
+            GatherStrategy::MergeRows,
+            vec![
+                returning(RedisFrame::Array(vec![RedisFrame::Integer(1)])),
+                returning(error()),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[error(), error()]);
+    }
+
+    #[tokio::test]
+    async fn test_most_recent() {
+        let tagged = |frame, seconds_ago| {
+            let mut message = Message::from_frame(Frame::Redis(frame));
+            message.set_last_modified(SystemTime::now() - Duration::from_secs(seconds_ago));
+            TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(Response::Message(message)))],
+                "scatter_gather_chain",
+            )
+        };
+        let responses = run(
+            GatherStrategy::MostRecent,
+            vec![
+                returning(RedisFrame::Integer(1)),
+                tagged(RedisFrame::Integer(2), 100),
+                tagged(RedisFrame::Integer(3), 10),
+                tagged(error(), 0),
+                failing(),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[RedisFrame::Integer(3), RedisFrame::Integer(3)]);
+
+        let responses = run(
+            GatherStrategy::MostRecent,
+            vec![returning(error()), failing()],
+        )
+        .await
+        .unwrap();
+        assert_redis_responses(responses, &[error(), error()]);
+    }
+}
@@ -0,0 +1,166 @@
+//! State scoped to a single client connection that is shared by all transforms in the chain.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+
+/// A typed key/value store scoped to the client connection, accessible to transforms via [`ChainState::session`](super::ChainState::session).
+///
+/// Values are keyed by their type, so transforms should define their own type for each value they store.
+/// e.g. a transform tracking the selected redis database would store a `struct SelectedDatabase(i64)`.
+/// This avoids collisions between unrelated transforms storing a value of the same underlying type.
+///
+/// The state is created by the server when the client connects and is dropped when the connection closes.
+/// Sub-chains receive either a copy of the state (e.g. `Tee`) or an empty state (e.g. `ParallelMap`),
+/// in both cases changes made within the sub-chain are not visible to the main chain.
+#[derive(Default)]
+pub struct SessionState {
+    values: HashMap<TypeId, Box<dyn SessionValue>>,
+}
+
+impl SessionState {
+    /// Stores `value`, returning the previously stored value of the same type if there was one.
+    pub fn insert<T: Any + Clone + Send + Sync>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.into_any().downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T: Any + Clone + Send + Sync>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| (**value).as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Clone + Send + Sync>(&mut self) -> Option<&mut T> {
+        self.values
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.as_any_mut().downcast_mut())
+    }
+
+    /// Returns the stored value of type `T`, first storing the result of `default` if there is none.
+    pub fn get_or_insert_with<T: Any + Clone + Send + Sync>(
+        &mut self,
+        default: impl FnOnce() -> T,
+    ) -> &mut T {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(default()))
+            .as_any_mut()
+            .downcast_mut()
+            .unwrap()
+    }
+
+    pub fn remove<T: Any + Clone + Send + Sync>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.into_any().downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The identity of the client, established by an auth termination transform or otherwise by the client's TLS certificate.
+    pub fn identity(&self) -> Option<&str> {
+        self.get::<AuthenticatedUser>()
+            .map(|x| x.0.as_str())
+            .or_else(|| self.get::<TlsClientIdentity>().map(|x| x.0.as_str()))
+    }
+}
+
+impl Clone for SessionState {
+    fn clone(&self) -> Self {
+        SessionState {
+            values: self
+                .values
+                .iter()
+                .map(|(key, value)| (*key, (**value).clone_box()))
+                .collect(),
+        }
+    }
+}
+
+impl Debug for SessionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionState")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
+
+/// The identity the client authenticated as, stored in the [`SessionState`] by the auth termination transforms.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthenticatedUser(pub String);
+
+/// The common name of the certificate the client authenticated with via mTLS, stored in the [`SessionState`] by the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsClientIdentity(pub String);
+
+/// The id of the client connection in the registry of active connections, stored in the [`SessionState`] by the source.
+/// The same id identifies the connection in the observability interface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientConnectionId(pub u64);
+
+/// Allows cloning the type erased values so that `ChainState` can remain `Clone`
+trait SessionValue: Send + Sync {
+    fn clone_box(&self) -> Box<dyn SessionValue>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + Clone + Send + Sync> SessionValue for T {
+    fn clone_box(&self) -> Box<dyn SessionValue> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct SelectedDatabase(i64);
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Username(String);
+
+    #[test]
+    fn test_session_state() {
+        let mut session = SessionState::default();
+        assert_eq!(session.get::<SelectedDatabase>(), None);
+
+        assert_eq!(session.insert(SelectedDatabase(3)), None);
+        session.insert(Username("foo".to_owned()));
+        assert_eq!(
+            session.insert(SelectedDatabase(4)),
+            Some(SelectedDatabase(3))
+        );
+
+        session.get_mut::<SelectedDatabase>().unwrap().0 += 1;
+        assert_eq!(session.get(), Some(&SelectedDatabase(5)));
+
+        let cloned = session.clone();
+        assert_eq!(session.remove(), Some(Username("foo".to_owned())));
+        assert_eq!(session.get::<Username>(), None);
+        assert_eq!(cloned.get(), Some(&Username("foo".to_owned())));
+
+        *session.get_or_insert_with(|| Username("bar".to_owned())) = Username("baz".to_owned());
+        assert_eq!(session.get(), Some(&Username("baz".to_owned())));
+    }
+}
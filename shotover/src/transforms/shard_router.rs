@@ -0,0 +1,806 @@
+//! Shards requests across independent upstream clusters by consistent hashing of the keys they access.
+//!
+//! Each shard is placed at many points, or virtual nodes, on a hash ring and a key belongs to the first shard point at or after the hash of the key.
+//! Adding or removing a shard only moves the keys between that shard and its neighbouring points,
+//! and the `shard-remap` subcommand lists exactly which keys move.
+
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::config::chain::TransformChainConfig;
+use crate::frame::MessageType;
+#[cfg(feature = "redis")]
+use crate::frame::{Frame, RedisFrame};
+use crate::message::{ErrorKind, Message, Messages};
+#[cfg(feature = "redis")]
+use crate::message::{MessageId, MessageIdMap};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+#[cfg(feature = "redis")]
+use crate::transforms::redis::scan;
+use crate::transforms::util::ordered_responses::OrderedResponses;
+use crate::transforms::util::setup_replay::{is_setup_request, SetupReplay};
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use fnv::FnvHasher;
+use futures::future::join_all;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::Arc;
+use tracing::info;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ShardRouterConfig {
+    /// Shard names mapped to the shard's weight and the chain that the shard's requests are routed to.
+    pub shards: HashMap<String, ShardConfig>,
+    /// The number of points on the hash ring for each unit of a shard's weight, defaults to 160.
+    /// More points spread the keys more evenly across the shards at the cost of memory.
+    #[serde(default = "default_virtual_nodes")]
+    pub virtual_nodes: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ShardConfig {
+    /// The share of keys belonging to this shard relative to the other shards, defaults to 1.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    pub chain: TransformChainConfig,
+}
+
+fn default_virtual_nodes() -> u32 {
+    160
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+const NAME: &str = "ShardRouter";
+#[typetag::serde(name = "ShardRouter")]
+#[async_trait(?Send)]
+impl TransformConfig for ShardRouterConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        // sort the shards so that the order of the chains does not depend on HashMap iteration order
+        let mut names: Vec<&String> = self.shards.keys().collect();
+        names.sort();
+
+        let mut shards = Vec::with_capacity(names.len());
+        for name in names {
+            let shard = &self.shards[name];
+            shards.push(ShardBuilder {
+                name: name.clone(),
+                weight: shard.weight,
+                chain: shard
+                    .chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: name.clone(),
+                        up_chain_protocol: transform_context.up_chain_protocol,
+                    })
+                    .await?,
+                requests: counter!("shotover_shard_requests_count", "chain" => transform_context.chain_name.clone(), "shard" => name.clone()),
+            });
+        }
+
+        let ring = HashRing::new(
+            shards.iter().map(|x| (x.name.as_str(), x.weight)),
+            self.virtual_nodes,
+        );
+        Ok(Box::new(ShardRouterBuilder {
+            shards: Arc::new(shards),
+            ring: Arc::new(ring),
+            virtual_nodes: self.virtual_nodes,
+            in_order: transform_context.up_chain_protocol.is_inorder(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+            #[cfg(feature = "memcached")]
+            MessageType::Memcached,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+/// The points of every shard on the hash ring.
+pub(crate) struct HashRing {
+    /// Sorted by hash, paired with the index of the shard the point belongs to
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    /// `shards` are the name and weight of each shard, the index of a shard is its position in `shards`.
+    pub(crate) fn new<'a>(
+        shards: impl Iterator<Item = (&'a str, u32)>,
+        virtual_nodes: u32,
+    ) -> Self {
+        let mut points = vec![];
+        for (index, (name, weight)) in shards.enumerate() {
+            for i in 0..virtual_nodes.saturating_mul(weight) {
+                points.push((hash(format!("{name}#{i}").as_bytes()), index));
+            }
+        }
+        points.sort_unstable();
+        HashRing { points }
+    }
+
+    /// Returns the index of the shard that the key belongs to, or None if there are no shards.
+    /// Like redis cluster, only the hash tag of the key is hashed when it has one, so keys sharing a hash tag belong to the same shard.
+    pub(crate) fn shard(&self, key: &[u8]) -> Option<usize> {
+        let hash = hash(hash_tag(key));
+        let index = self.points.partition_point(|(point, _)| *point < hash);
+        self.points
+            .get(index)
+            .or_else(|| self.points.first())
+            .map(|(_, shard)| *shard)
+    }
+}
+
+/// FNV is stable across releases and platforms, which is required as the location of every key depends on it.
+/// Its output is mixed with the murmur3 finalizer as FNV alone spreads similar inputs poorly.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    let mut hash = hasher.finish();
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Returns the part of the key between the first `{` and the following `}` if it is not empty, otherwise the whole key.
+fn hash_tag(key: &[u8]) -> &[u8] {
+    if let Some(start) = key.iter().position(|x| *x == b'{') {
+        if let Some(len) = key[start + 1..].iter().position(|x| *x == b'}') {
+            if len > 0 {
+                return &key[start + 1..start + 1 + len];
+            }
+        }
+    }
+    key
+}
+
+struct ShardBuilder {
+    name: String,
+    weight: u32,
+    chain: TransformChainBuilder,
+    requests: Counter,
+}
+
+struct ShardRouterBuilder {
+    shards: Arc<Vec<ShardBuilder>>,
+    ring: Arc<HashRing>,
+    virtual_nodes: u32,
+    in_order: bool,
+}
+
+impl TransformBuilder for ShardRouterBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(ShardRouter {
+            chains: self.shards.iter().map(|_| None).collect(),
+            shards: self.shards.clone(),
+            ring: self.ring.clone(),
+            transform_context,
+            responses: OrderedResponses::new(self.in_order),
+            setup_replay: SetupReplay::new(self.shards.len()),
+            #[cfg(feature = "redis")]
+            scans: MessageIdMap::default(),
+            #[cfg(feature = "redis")]
+            fan_out_requests: MessageIdMap::default(),
+            #[cfg(feature = "redis")]
+            fan_outs: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.shards.is_empty() {
+            errors.push("  at least one shard must be configured".to_owned());
+        }
+        if self.virtual_nodes == 0 {
+            errors.push("  virtual_nodes must be greater than 0".to_owned());
+        }
+        for shard in self.shards.iter() {
+            if shard.weight == 0 {
+                errors.push(format!(
+                    "  the weight of shard {:?} must be greater than 0",
+                    shard.name
+                ));
+            }
+            errors.extend(shard.chain.validate().iter().map(|x| format!("  {x}")));
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+struct ShardRouter {
+    shards: Arc<Vec<ShardBuilder>>,
+    ring: Arc<HashRing>,
+    /// The chain of each shard, only built once a request is routed to the shard
+    chains: Vec<Option<TransformChain>>,
+    transform_context: TransformContextBuilder,
+    responses: OrderedResponses,
+    /// Replays requests such as AUTH or SELECT to each shard's chain so that all of the client's upstream connections are set up in the same way.
+    setup_replay: SetupReplay,
+    /// The ids of in flight SCAN requests mapped to the index of the shard they were sent to
+    #[cfg(feature = "redis")]
+    scans: MessageIdMap<usize>,
+    /// The ids of the copies of KEYS requests sent to each shard mapped to the id of the client's request
+    #[cfg(feature = "redis")]
+    fan_out_requests: MessageIdMap<MessageId>,
+    /// The joined responses of KEYS requests that are still waiting on some shards, keyed by the id of the client's request
+    #[cfg(feature = "redis")]
+    fan_outs: MessageIdMap<FanOut>,
+}
+
+#[cfg(feature = "redis")]
+enum KeyspaceCommand {
+    Scan,
+    Keys,
+}
+
+#[cfg(feature = "redis")]
+struct FanOut {
+    remaining: usize,
+    keys: Vec<RedisFrame>,
+    error: Option<RedisFrame>,
+}
+
+/// Returns the command if it accesses the whole keyspace, which is spread across every shard.
+#[cfg(feature = "redis")]
+fn keyspace_command(request: &mut Message) -> Option<KeyspaceCommand> {
+    match request.frame() {
+        Some(Frame::Redis(RedisFrame::Array(command))) => match command.first() {
+            Some(RedisFrame::BulkString(name)) if name.eq_ignore_ascii_case(b"SCAN") => {
+                Some(KeyspaceCommand::Scan)
+            }
+            Some(RedisFrame::BulkString(name)) if name.eq_ignore_ascii_case(b"KEYS") => {
+                Some(KeyspaceCommand::Keys)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl ShardRouter {
+    /// Returns the index of the shard the request belongs to, or None if its keys belong to multiple shards.
+    /// Requests without keys, such as PING, belong to the first shard.
+    fn route(&self, request: &mut Message) -> Option<usize> {
+        let mut route = None;
+        for key in request.primary_keys() {
+            let index = self.ring.shard(&key)?;
+            match route {
+                Some(existing) if existing != index => return None,
+                _ => route = Some(index),
+            }
+        }
+        Some(route.unwrap_or(0))
+    }
+
+    /// Adds the request to the requests to be sent to the shard's chain, preceded by any setup requests the chain has not received yet.
+    fn push_request(&mut self, index: usize, mut request: Message, routed: &mut [Messages]) {
+        let is_setup = is_setup_request(&mut request);
+        self.setup_replay
+            .replay_to(index, &request, is_setup, &mut routed[index]);
+        self.shards[index].requests.increment(1);
+        routed[index].push(request);
+    }
+
+    /// Sends the SCAN to the shard it is scanning, each shard is scanned in turn behind a virtual cursor.
+    #[cfg(feature = "redis")]
+    fn route_scan(&mut self, mut request: Message, routed: &mut [Messages]) {
+        let index = match request.frame() {
+            Some(Frame::Redis(RedisFrame::Array(command))) => {
+                scan::rewrite_request(command, self.shards.len())
+            }
+            _ => unreachable!("keyspace_command only matches redis arrays"),
+        };
+        match index {
+            Ok(index) => {
+                request.invalidate_cache();
+                self.scans.insert(request.id(), index);
+                self.push_request(index, request, routed);
+            }
+            Err(error) => {
+                let mut response = Message::from_frame(Frame::Redis(error));
+                response.set_request_id(request.id());
+                self.responses.insert(request.id(), response);
+            }
+        }
+    }
+
+    /// Sends a copy of the KEYS to every shard, their responses are joined into a single response once every shard has responded.
+    #[cfg(feature = "redis")]
+    fn route_keys(&mut self, request: Message, routed: &mut [Messages]) {
+        for index in 0..self.shards.len() {
+            let copy = request.clone_with_new_id();
+            self.fan_out_requests.insert(copy.id(), request.id());
+            self.push_request(index, copy, routed);
+        }
+        self.fan_outs.insert(
+            request.id(),
+            FanOut {
+                remaining: self.shards.len(),
+                keys: vec![],
+                error: None,
+            },
+        );
+    }
+
+    /// Rewrites the cursor of SCAN responses and joins the responses of KEYS.
+    /// Returns None while the response is one of the KEYS responses still waiting on other shards.
+    #[cfg(feature = "redis")]
+    fn keyspace_response(&mut self, id: MessageId, mut response: Message) -> Option<Message> {
+        if let Some(index) = self.scans.remove(&id) {
+            if let Some(Frame::Redis(frame)) = response.frame() {
+                scan::rewrite_response(frame, index, self.shards.len());
+                response.invalidate_cache();
+            }
+            return Some(response);
+        }
+
+        let Some(request_id) = self.fan_out_requests.remove(&id) else {
+            return Some(response);
+        };
+        let fan_out = self.fan_outs.get_mut(&request_id)?;
+        fan_out.remaining -= 1;
+        match response.frame() {
+            Some(Frame::Redis(RedisFrame::Array(keys))) => {
+                fan_out.keys.extend(std::mem::take(keys))
+            }
+            Some(Frame::Redis(error @ RedisFrame::Error(_))) => {
+                fan_out.error.get_or_insert_with(|| error.clone());
+            }
+            _ => {
+                fan_out.error.get_or_insert_with(|| {
+                    RedisFrame::Error("ERR A shard returned an invalid response to KEYS".into())
+                });
+            }
+        }
+        if fan_out.remaining > 0 {
+            return None;
+        }
+
+        let fan_out = self.fan_outs.remove(&request_id)?;
+        let mut response = Message::from_frame(Frame::Redis(
+            fan_out
+                .error
+                .unwrap_or_else(|| RedisFrame::Array(fan_out.keys)),
+        ));
+        response.set_request_id(request_id);
+        Some(response)
+    }
+}
+
+#[async_trait]
+impl Transform for ShardRouter {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        for chain in self.chains.iter_mut().flatten() {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let mut routed: Vec<Messages> = vec![vec![]; self.shards.len()];
+        for mut request in std::mem::take(&mut chain_state.requests) {
+            self.responses.push_request(request.id());
+            #[cfg(feature = "redis")]
+            match keyspace_command(&mut request) {
+                Some(KeyspaceCommand::Scan) => {
+                    self.route_scan(request, &mut routed);
+                    continue;
+                }
+                Some(KeyspaceCommand::Keys) => {
+                    self.route_keys(request, &mut routed);
+                    continue;
+                }
+                None => {}
+            }
+            match self.route(&mut request) {
+                Some(index) => self.push_request(index, request, &mut routed),
+                None => {
+                    let response = request.error_response(
+                        "The request accesses keys belonging to multiple shards".to_owned(),
+                        ErrorKind::InvalidRequest,
+                    )?;
+                    self.responses.insert(request.id(), response);
+                }
+            }
+        }
+
+        for (chain, (requests, shard)) in self
+            .chains
+            .iter_mut()
+            .zip(routed.iter().zip(self.shards.iter()))
+        {
+            if chain.is_none() && !requests.is_empty() {
+                *chain = Some(shard.chain.build(self.transform_context.clone()));
+            }
+        }
+
+        // Every built chain is run, even without any requests, to pick up any responses that arrived since the last run.
+        let local_addr = chain_state.local_addr;
+        let flush = chain_state.flush;
+        let session = &chain_state.session;
+        let results = join_all(self.chains.iter_mut().zip(routed).filter_map(
+            |(chain, requests)| {
+                let chain = chain.as_mut()?;
+                Some(async move {
+                    let mut sub_chain_state = ChainState::new_with_addr(requests, local_addr);
+                    sub_chain_state.flush = flush;
+                    sub_chain_state.session = session.clone();
+                    chain.process_request(&mut sub_chain_state).await
+                })
+            },
+        ))
+        .await;
+
+        let mut responses = vec![];
+        for result in results {
+            for response in result? {
+                match response.request_id() {
+                    Some(id) if self.setup_replay.remove_replayed(id) => {}
+                    #[cfg(feature = "redis")]
+                    Some(id) => {
+                        if let Some(response) = self.keyspace_response(id, response) {
+                            self.responses
+                                .insert(response.request_id().unwrap(), response);
+                        }
+                    }
+                    #[cfg(not(feature = "redis"))]
+                    Some(id) => self.responses.insert(id, response),
+                    None => responses.push(response),
+                }
+            }
+        }
+
+        self.responses.take_ready(&mut responses);
+
+        Ok(responses)
+    }
+}
+
+#[derive(clap::Args, Clone)]
+pub struct ShardRemapOpts {
+    /// The shards of the ShardRouter before the change, as a comma separated list of `name` or `name=weight` e.g. `a,b,c=2`.
+    #[clap(long)]
+    pub from: String,
+
+    /// The shards of the ShardRouter after the change, in the same format as `--from`.
+    #[clap(long)]
+    pub to: String,
+
+    /// The `virtual_nodes` of the ShardRouter.
+    #[clap(long, default_value = "160")]
+    pub virtual_nodes: u32,
+
+    /// A file listing the keys to check, one per line.
+    #[clap(long)]
+    pub keys: String,
+
+    /// The file to write each key that belongs to a different shard after the change to,
+    /// along with the shard it belongs to before and after the change, separated by tabs.
+    #[clap(long)]
+    pub output: String,
+}
+
+/// Lists the keys that belong to a different shard after changing the shards of a ShardRouter.
+pub(crate) fn remap(opts: ShardRemapOpts) -> Result<()> {
+    let from = parse_shards(&opts.from).context("Invalid --from")?;
+    let to = parse_shards(&opts.to).context("Invalid --to")?;
+    let keys = File::open(&opts.keys).with_context(|| format!("Failed to open {}", opts.keys))?;
+    let mut output = BufWriter::new(
+        File::create(&opts.output).with_context(|| format!("Failed to create {}", opts.output))?,
+    );
+    let (moved, total) = remap_keys(
+        &from,
+        &to,
+        opts.virtual_nodes,
+        BufReader::new(keys),
+        &mut output,
+    )?;
+    output.flush()?;
+    info!("{moved} of {total} keys belong to a different shard after the change, they are listed in {}", opts.output);
+    Ok(())
+}
+
+/// Parses a comma separated list of `name` or `name=weight`, sorted by name to match the order of the ShardRouter's shards.
+fn parse_shards(shards: &str) -> Result<Vec<(String, u32)>> {
+    let mut parsed = shards
+        .split(',')
+        .map(|shard| match shard.split_once('=') {
+            Some((name, weight)) => Ok((
+                name.trim().to_owned(),
+                weight
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid weight for shard {name:?}"))?,
+            )),
+            None => Ok((shard.trim().to_owned(), default_weight())),
+        })
+        .collect::<Result<Vec<(String, u32)>>>()?;
+    if parsed.iter().any(|(name, _)| name.is_empty()) {
+        return Err(anyhow!("Shard names cannot be empty"));
+    }
+    parsed.sort();
+    Ok(parsed)
+}
+
+fn remap_keys(
+    from: &[(String, u32)],
+    to: &[(String, u32)],
+    virtual_nodes: u32,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> Result<(u64, u64)> {
+    let ring = |shards: &[(String, u32)]| {
+        HashRing::new(
+            shards.iter().map(|(name, weight)| (name.as_str(), *weight)),
+            virtual_nodes,
+        )
+    };
+    let (from_ring, to_ring) = (ring(from), ring(to));
+
+    let mut moved = 0;
+    let mut total = 0;
+    for key in input.split(b'\n') {
+        let mut key = key?;
+        if key.last() == Some(&b'\r') {
+            key.pop();
+        }
+        if key.is_empty() {
+            continue;
+        }
+        total += 1;
+        let before = from_ring.shard(&key).map(|x| from[x].0.as_str());
+        let after = to_ring.shard(&key).map(|x| to[x].0.as_str());
+        if before != after {
+            moved += 1;
+            output.write_all(&key)?;
+            writeln!(
+                output,
+                "\t{}\t{}",
+                before.unwrap_or("-"),
+                after.unwrap_or("-")
+            )?;
+        }
+    }
+    Ok((moved, total))
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::test_utils::{assert_error_response, redis_command};
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use pretty_assertions::assert_eq;
+
+    fn ring(shards: &[(&str, u32)]) -> HashRing {
+        HashRing::new(shards.iter().copied(), 160)
+    }
+
+    fn shares(ring: &HashRing, shards: usize) -> Vec<usize> {
+        let mut counts = vec![0; shards];
+        for i in 0..10000 {
+            counts[ring.shard(format!("key:{i}").as_bytes()).unwrap()] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_hash_ring() {
+        let counts = shares(&ring(&[("a", 1), ("b", 1), ("c", 2)]), 3);
+        assert!((1800..3200).contains(&counts[0]), "{counts:?}");
+        assert!((1800..3200).contains(&counts[1]), "{counts:?}");
+        assert!((4000..6000).contains(&counts[2]), "{counts:?}");
+
+        // keys sharing a hash tag belong to the same shard
+        let ring = ring(&[("a", 1), ("b", 1), ("c", 1)]);
+        for i in 0..100 {
+            assert_eq!(
+                ring.shard(format!("{{user1}}:{i}").as_bytes()),
+                ring.shard(b"user1")
+            );
+        }
+        assert_eq!(HashRing::new(std::iter::empty(), 160).shard(b"foo"), None);
+    }
+
+    #[test]
+    fn test_remap_keys() {
+        let from = parse_shards("a,b").unwrap();
+        let to = parse_shards("c, b, a").unwrap();
+        let keys = (0..3000)
+            .map(|i| format!("key:{i}\n"))
+            .collect::<Vec<_>>()
+            .concat();
+        let mut output = vec![];
+        let (moved, total) = remap_keys(&from, &to, 160, keys.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(total, 3000);
+        // adding a third shard only moves keys to the new shard, roughly a third of them
+        assert!((700..1300).contains(&moved), "{moved}");
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count() as u64, moved);
+        assert!(output.lines().all(|x| x.ends_with("\tc")));
+
+        assert!(parse_shards("a,b=x").is_err());
+    }
+
+    fn shard(name: &str) -> ShardBuilder {
+        ShardBuilder {
+            name: name.to_owned(),
+            weight: 1,
+            chain: TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(Response::Redis(
+                    name.to_owned(),
+                )))],
+                "shard",
+            ),
+            requests: Counter::noop(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_by_key() {
+        let shards = vec![shard("a"), shard("b")];
+        let ring = HashRing::new(shards.iter().map(|x| (x.name.as_str(), x.weight)), 160);
+        let shard_of = |key: &str| shards[ring.shard(key.as_bytes()).unwrap()].name.clone();
+        let (key_a, key_b) = {
+            let keys: Vec<String> = (0..100).map(|i| format!("key:{i}")).collect();
+            (
+                keys.iter().find(|x| shard_of(x) == "a").unwrap().clone(),
+                keys.iter().find(|x| shard_of(x) == "b").unwrap().clone(),
+            )
+        };
+
+        let mut router = ShardRouterBuilder {
+            shards: Arc::new(shards),
+            ring: Arc::new(ring),
+            virtual_nodes: 160,
+            in_order: true,
+        }
+        .build(TransformContextBuilder::new_test());
+        let mut responses = router
+            .transform(&mut ChainState::new_test(vec![
+                redis_command(&["AUTH", "pass"]),
+                redis_command(&["GET", &key_b]),
+                redis_command(&["GET", &key_a]),
+                redis_command(&["MGET", &key_a, &key_b]),
+            ]))
+            .await
+            .unwrap();
+
+        assert_error_response(&mut responses[3], "multiple shards");
+        let frames: Vec<Frame> = responses[..3]
+            .iter_mut()
+            .map(|x| x.frame().cloned().unwrap())
+            .collect();
+        let bulk = |x: &str| Frame::Redis(RedisFrame::BulkString(x.to_owned().into()));
+        // AUTH has no keys so belongs to the first shard, and is replayed to shard b before its first request
+        assert_eq!(frames, vec![bulk("a"), bulk("b"), bulk("a")]);
+    }
+
+    /// A shard that responds to every request with `response`
+    fn returning_shard(name: &str, response: RedisFrame) -> ShardBuilder {
+        ShardBuilder {
+            chain: TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(Response::Message(
+                    Message::from_frame(Frame::Redis(response)),
+                )))],
+                "shard",
+            ),
+            ..shard(name)
+        }
+    }
+
+    fn scan_shard(name: &str) -> ShardBuilder {
+        returning_shard(
+            name,
+            RedisFrame::Array(vec![
+                RedisFrame::BulkString("0".into()),
+                RedisFrame::Array(vec![RedisFrame::BulkString(name.to_owned().into())]),
+            ]),
+        )
+    }
+
+    fn keys_shard(name: &str) -> ShardBuilder {
+        returning_shard(
+            name,
+            RedisFrame::Array(vec![RedisFrame::BulkString(name.to_owned().into())]),
+        )
+    }
+
+    fn keyspace_router(shards: Vec<ShardBuilder>) -> Box<dyn Transform> {
+        let ring = HashRing::new(shards.iter().map(|x| (x.name.as_str(), x.weight)), 160);
+        ShardRouterBuilder {
+            shards: Arc::new(shards),
+            ring: Arc::new(ring),
+            virtual_nodes: 160,
+            in_order: true,
+        }
+        .build(TransformContextBuilder::new_test())
+    }
+
+    #[tokio::test]
+    async fn test_keyspace_commands() {
+        let mut router = keyspace_router(vec![scan_shard("a"), scan_shard("b")]);
+        let mut responses = router
+            .transform(&mut ChainState::new_test(vec![
+                redis_command(&["SCAN", "0"]),
+                redis_command(&["SCAN", "1"]),
+                redis_command(&["SCAN", "2"]),
+            ]))
+            .await
+            .unwrap();
+
+        let frames: Vec<Frame> = responses
+            .iter_mut()
+            .map(|x| x.frame().cloned().unwrap())
+            .collect();
+        let bulk = |x: &str| RedisFrame::BulkString(x.to_owned().into());
+        let scan_response = |cursor: &str, key: &str| {
+            Frame::Redis(RedisFrame::Array(vec![
+                bulk(cursor),
+                RedisFrame::Array(vec![bulk(key)]),
+            ]))
+        };
+        assert_eq!(
+            frames,
+            vec![
+                // the first shard is done, so the cursor moves on to the second shard
+                scan_response("1", "a"),
+                // the second shard is done, completing the scan
+                scan_response("0", "b"),
+                Frame::Redis(RedisFrame::Error("ERR invalid cursor".into())),
+            ]
+        );
+
+        let mut router = keyspace_router(vec![keys_shard("a"), keys_shard("b")]);
+        let mut responses = router
+            .transform(&mut ChainState::new_test(vec![redis_command(&[
+                "KEYS", "*",
+            ])]))
+            .await
+            .unwrap();
+        assert_eq!(
+            responses[0].frame().cloned().unwrap(),
+            Frame::Redis(RedisFrame::Array(vec![bulk("a"), bulk("b")]))
+        );
+    }
+}
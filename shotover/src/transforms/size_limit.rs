@@ -0,0 +1,254 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::frame::MessageType;
+use crate::message::{ErrorKind, Message, MessageIdMap, Messages};
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, histogram, Counter, Histogram};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SizeLimitConfig {
+    /// Requests larger than this many bytes are answered with an error instead of being sent down the chain.
+    pub max_request_bytes: Option<usize>,
+    /// Responses larger than this many bytes are replaced with an error before being returned to the client.
+    pub max_response_bytes: Option<usize>,
+}
+
+const NAME: &str = "SizeLimit";
+#[typetag::serde(name = "SizeLimit")]
+#[async_trait(?Send)]
+impl TransformConfig for SizeLimitConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(SizeLimitBuilder {
+            max_request_bytes: self.max_request_bytes,
+            max_response_bytes: self.max_response_bytes,
+            request_size: histogram!("shotover_request_size_bytes", "chain" => chain_name.clone()),
+            response_size: histogram!("shotover_response_size_bytes", "chain" => chain_name.clone()),
+            rejected_requests: counter!("shotover_size_limit_rejected_count", "chain" => chain_name.clone(), "direction" => "request"),
+            rejected_responses: counter!("shotover_size_limit_rejected_count", "chain" => chain_name, "direction" => "response"),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct SizeLimitBuilder {
+    max_request_bytes: Option<usize>,
+    max_response_bytes: Option<usize>,
+    request_size: Histogram,
+    response_size: Histogram,
+    rejected_requests: Counter,
+    rejected_responses: Counter,
+}
+
+impl TransformBuilder for SizeLimitBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(SizeLimit {
+            max_request_bytes: self.max_request_bytes,
+            max_response_bytes: self.max_response_bytes,
+            request_size: self.request_size.clone(),
+            response_size: self.response_size.clone(),
+            rejected_requests: self.rejected_requests.clone(),
+            rejected_responses: self.rejected_responses.clone(),
+            rejected: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.max_request_bytes == Some(0) {
+            errors.push("  max_request_bytes must be greater than 0".to_owned());
+        }
+        if self.max_response_bytes == Some(0) {
+            errors.push("  max_response_bytes must be greater than 0".to_owned());
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct SizeLimit {
+    max_request_bytes: Option<usize>,
+    max_response_bytes: Option<usize>,
+    request_size: Histogram,
+    response_size: Histogram,
+    rejected_requests: Counter,
+    rejected_responses: Counter,
+    /// Error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
+}
+
+#[async_trait]
+impl Transform for SizeLimit {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in &mut chain_state.requests {
+            // The size is only known for messages that have not been modified by an earlier transform
+            if let Some(size) = request.received_size() {
+                self.request_size.record(size as f64);
+                if let Some(max) = self.max_request_bytes.filter(|max| size > *max) {
+                    self.rejected.insert(
+                        request.id(),
+                        request.error_response(
+                            format!("request of {size} bytes exceeds the limit of {max} bytes"),
+                            ErrorKind::InvalidRequest,
+                        )?,
+                    );
+                    request.replace_with_dummy();
+                    self.rejected_requests.increment(1);
+                }
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+
+        for response in responses.iter_mut() {
+            if let Some(rejected) = response
+                .request_id()
+                .and_then(|id| self.rejected.remove(&id))
+            {
+                *response = rejected;
+            } else if let Some(size) = response.received_size() {
+                self.response_size.record(size as f64);
+                if let Some(max) = self.max_response_bytes.filter(|max| size > *max) {
+                    *response = response.error_response_from_response(
+                        format!("response of {size} bytes exceeds the limit of {max} bytes"),
+                        ErrorKind::Server,
+                    )?;
+                    self.rejected_responses.increment(1);
+                }
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::codec::CodecState;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::loopback::Loopback;
+    use bytes::Bytes;
+    use pretty_assertions::assert_eq;
+
+    fn size_limit(
+        max_request_bytes: Option<usize>,
+        max_response_bytes: Option<usize>,
+    ) -> SizeLimit {
+        SizeLimit {
+            max_request_bytes,
+            max_response_bytes,
+            request_size: Histogram::noop(),
+            response_size: Histogram::noop(),
+            rejected_requests: Counter::noop(),
+            rejected_responses: Counter::noop(),
+            rejected: MessageIdMap::default(),
+        }
+    }
+
+    fn raw(bytes: &'static [u8]) -> Message {
+        Message::from_bytes(Bytes::from_static(bytes), CodecState::Redis)
+    }
+
+    async fn run(transform: &mut SizeLimit, requests: Messages) -> Vec<Frame> {
+        let mut chain = vec![TransformAndMetrics::new(Box::new(Loopback::default()))];
+        let mut chain_state = ChainState::new_test(requests);
+        chain_state.reset(&mut chain);
+        transform
+            .transform(&mut chain_state)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect()
+    }
+
+    fn set() -> Message {
+        raw(b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n$10\r\n0123456789\r\n")
+    }
+
+    fn get() -> Message {
+        raw(b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\n")
+    }
+
+    #[tokio::test]
+    async fn test_max_request_bytes() {
+        let mut transform = size_limit(Some(30), None);
+        assert_eq!(
+            run(&mut transform, vec![get(), set(), get()]).await,
+            vec![
+                get().frame().cloned().unwrap(),
+                Frame::Redis(RedisFrame::Error(
+                    "ERR request of 39 bytes exceeds the limit of 30 bytes".into()
+                )),
+                get().frame().cloned().unwrap(),
+            ]
+        );
+        assert!(transform.rejected.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_max_response_bytes() {
+        // Loopback returns each request as its response
+        let mut transform = size_limit(None, Some(30));
+        assert_eq!(
+            run(&mut transform, vec![get(), set()]).await,
+            vec![
+                get().frame().cloned().unwrap(),
+                Frame::Redis(RedisFrame::Error(
+                    "ERR response of 39 bytes exceeds the limit of 30 bytes".into()
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = SizeLimitBuilder {
+            max_request_bytes: Some(0),
+            max_response_bytes: None,
+            request_size: Histogram::noop(),
+            response_size: Histogram::noop(),
+            rejected_requests: Counter::noop(),
+            rejected_responses: Counter::noop(),
+        };
+        assert_eq!(
+            builder.validate(),
+            vec!["SizeLimit:", "  max_request_bytes must be greater than 0"]
+        );
+    }
+}
@@ -1,10 +1,14 @@
 use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
 use crate::config::chain::TransformChainConfig;
+use crate::events::{self, EventKind};
+use crate::frame::MessageType;
 use crate::http::HttpServerError;
-use crate::message::{Message, MessageIdMap, Messages};
+use crate::message::{ErrorKind, Message, MessageIdMap, Messages};
 use crate::transforms::chain::{BufferedChain, TransformChainBuilder};
+use crate::transforms::util::setup_replay::carries_credentials;
+use crate::transforms::util::write_ahead_log::{WriteAheadLog, WriteAheadLogConfig};
 use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
 use atomic_enum::atomic_enum;
 use axum::extract::State;
@@ -18,6 +22,10 @@ use std::sync::atomic::Ordering;
 use std::{net::SocketAddr, str, sync::Arc};
 use tracing::{debug, error, trace, warn};
 
+pub mod schedule;
+
+use schedule::{TeeSchedule, TeeScheduleConfig};
+
 struct TeeBuilder {
     tx: TransformChainBuilder,
     buffer_size: usize,
@@ -26,6 +34,9 @@ struct TeeBuilder {
     dropped_messages: Counter,
     result_source: Arc<AtomicResultSource>,
     protocol_is_inorder: bool,
+    write_ahead_log: Option<Arc<WriteAheadLog>>,
+    schedule: Option<Arc<TeeSchedule>>,
+    chain_name: String,
 }
 
 enum ConsistencyBehaviorBuilder {
@@ -36,6 +47,7 @@ enum ConsistencyBehaviorBuilder {
 }
 
 impl TeeBuilder {
+    #[expect(clippy::too_many_arguments)]
     fn new(
         tx: TransformChainBuilder,
         buffer_size: usize,
@@ -43,6 +55,9 @@ impl TeeBuilder {
         timeout_micros: Option<u64>,
         switch_port: Option<u16>,
         protocol_is_inorder: bool,
+        write_ahead_log: Option<Arc<WriteAheadLog>>,
+        schedule: Option<Arc<TeeSchedule>>,
+        chain_name: String,
     ) -> Self {
         let result_source = Arc::new(AtomicResultSource::new(ResultSource::RegularChain));
 
@@ -62,6 +77,9 @@ impl TeeBuilder {
             dropped_messages,
             result_source,
             protocol_is_inorder,
+            write_ahead_log,
+            schedule,
+            chain_name,
         }
     }
 }
@@ -88,6 +106,11 @@ impl TransformBuilder for TeeBuilder {
             timeout_micros: self.timeout_micros,
             dropped_messages: self.dropped_messages.clone(),
             result_source: self.result_source.clone(),
+            write_ahead_log: self.write_ahead_log.clone(),
+            schedule: self.schedule.clone(),
+            connection_id: rand::random(),
+            authenticated: false,
+            chain_name: self.chain_name.clone(),
             incoming_responses: if self.protocol_is_inorder {
                 IncomingResponses::InOrder {
                     tee: VecDeque::new(),
@@ -137,7 +160,17 @@ struct Tee {
     timeout_micros: Option<u64>,
     dropped_messages: Counter,
     result_source: Arc<AtomicResultSource>,
+    /// When configured, requests for the tee chain are written to the log and sent to the tee chain from there instead
+    write_ahead_log: Option<Arc<WriteAheadLog>>,
+    /// When configured, only the requests selected by the schedule are sent to the tee chain
+    schedule: Option<Arc<TeeSchedule>>,
+    /// Identifies this connection to the write ahead log
+    connection_id: u64,
+    /// Set once this connection has sent credentials, after which its requests are no longer written to the write ahead log.
+    /// Otherwise the credentials would be stored on disk and its requests replayed under the identity of another connection.
+    authenticated: bool,
     incoming_responses: IncomingResponses,
+    chain_name: String,
 }
 
 #[atomic_enum]
@@ -170,6 +203,12 @@ pub struct TeeConfig {
     pub chain: TransformChainConfig,
     pub buffer_size: Option<usize>,
     pub switch_port: Option<u16>,
+    /// Persists the requests sent to the tee chain to disk so that they are delivered even if shotover is restarted.
+    /// Only supported with the `Ignore` behavior.
+    pub write_ahead_log: Option<WriteAheadLogConfig>,
+    /// Limits when and how many requests are sent to the tee chain, they are all sent when not configured.
+    /// Only supported with the `Ignore` behavior.
+    pub schedule: Option<TeeScheduleConfig>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -218,6 +257,58 @@ impl TransformConfig for TeeConfig {
             })
             .await?;
 
+        let write_ahead_log = match &self.write_ahead_log {
+            #[cfg(any(feature = "redis", feature = "cassandra"))]
+            Some(config) => {
+                if !matches!(
+                    self.behavior,
+                    None | Some(ConsistencyBehaviorConfig::Ignore)
+                ) {
+                    bail!("write_ahead_log can only be used with the Ignore behavior");
+                }
+                match transform_context.up_chain_protocol {
+                    #[cfg(feature = "redis")]
+                    MessageType::Redis => {}
+                    #[cfg(feature = "cassandra")]
+                    MessageType::Cassandra => {}
+                    protocol => bail!("write_ahead_log does not support {protocol:?}"),
+                }
+                let log_chain = self
+                    .chain
+                    .get_builder(TransformContextConfig {
+                        chain_name: "tee_chain".to_string(),
+                        up_chain_protocol: transform_context.up_chain_protocol,
+                    })
+                    .await?;
+                Some(
+                    WriteAheadLog::open(config, log_chain, "tee_chain")
+                        .context("Failed to open write_ahead_log")?,
+                )
+            }
+            #[cfg(not(any(feature = "redis", feature = "cassandra")))]
+            Some(_) => bail!(
+                "write_ahead_log does not support {:?}",
+                transform_context.up_chain_protocol
+            ),
+            None => None,
+        };
+
+        let schedule = match &self.schedule {
+            Some(config) => {
+                if !matches!(
+                    self.behavior,
+                    None | Some(ConsistencyBehaviorConfig::Ignore)
+                ) {
+                    bail!("schedule can only be used with the Ignore behavior");
+                }
+                Some(TeeSchedule::new(
+                    config,
+                    transform_context.chain_name.clone(),
+                )?)
+            }
+            None => None,
+        };
+
         Ok(Box::new(TeeBuilder::new(
             tee_chain,
             buffer_size,
@@ -225,6 +316,9 @@ impl TransformConfig for TeeConfig {
             self.timeout_micros,
             self.switch_port,
             transform_context.up_chain_protocol.is_inorder(),
+            write_ahead_log,
+            schedule,
+            transform_context.chain_name,
         )))
     }
 
@@ -257,18 +351,21 @@ impl Transform for Tee {
                 );
 
                 let keep: ResultSource = self.result_source.load(Ordering::Relaxed);
+                let chain_name = &self.chain_name;
                 let responses = self.incoming_responses.new_responses(
                     tee_result?,
                     chain_result?,
                     keep,
                     |keep_message, mut other_message| {
+                        publish_mismatch(chain_name);
                         debug!(
                             "Tee mismatch:\nresult-source response: {}\nother response: {}",
                             keep_message.to_high_level_string(),
                             other_message.to_high_level_string()
                         );
-                        *keep_message = keep_message.from_response_to_error_response(
-                            "ERR The responses from the Tee subchain and down-chain did not match and behavior is set to fail on mismatch".into()
+                        *keep_message = keep_message.error_response_from_response(
+                            "ERR The responses from the Tee subchain and down-chain did not match and behavior is set to fail on mismatch".into(),
+                            ErrorKind::Server,
                         ).unwrap();
                     },
                 );
@@ -288,11 +385,13 @@ impl Transform for Tee {
 
                 let mut mismatched_requests = vec![];
                 let keep: ResultSource = self.result_source.load(Ordering::Relaxed);
+                let chain_name = &self.chain_name;
                 let responses = self.incoming_responses.new_responses(
                     tee_result?,
                     chain_result?,
                     keep,
                     |keep_message, _| {
+                        publish_mismatch(chain_name);
                         if let Some(id) = keep_message.request_id() {
                             mismatched_requests.push(requests.remove(&id).unwrap());
                         }
@@ -315,11 +414,13 @@ impl Transform for Tee {
                 );
 
                 let keep: ResultSource = self.result_source.load(Ordering::Relaxed);
+                let chain_name = &self.chain_name;
                 let responses = self.incoming_responses.new_responses(
                     tee_result?,
                     chain_result?,
                     keep,
                     |keep_message, mut other_message| {
+                        publish_mismatch(chain_name);
                         warn!(
                             "Tee mismatch:\nresult-source response: {}\nother response: {}",
                             keep_message.to_high_level_string(),
@@ -485,6 +586,15 @@ impl IncomingResponses {
     }
 }
 
+/// Publishes a mismatch event, the responses themselves are not included as they may contain sensitive data.
+fn publish_mismatch(chain_name: &str) {
+    events::publish(
+        EventKind::TeeMismatch,
+        format!("{chain_name}/{NAME}"),
+        "The responses from the Tee subchain and down-chain did not match".to_owned(),
+    );
+}
+
 impl Tee {
     async fn ignore_behaviour<'shorter, 'longer: 'shorter>(
         &mut self,
@@ -493,9 +603,39 @@ impl Tee {
         let result_source: ResultSource = self.result_source.load(Ordering::Relaxed);
         match result_source {
             ResultSource::RegularChain => {
+                let mut tee_state = chain_state.clone();
+                if let Some(schedule) = &self.schedule {
+                    schedule.retain_mirrored(&mut tee_state.requests);
+                    if tee_state.requests.is_empty() && !tee_state.flush {
+                        return chain_state.call_next_transform().await;
+                    }
+                }
+                if let Some(write_ahead_log) = &self.write_ahead_log {
+                    if !self.authenticated && tee_state.requests.iter_mut().any(carries_credentials)
+                    {
+                        warn!("A client connection authenticated, its requests will not be written to the tee write ahead log");
+                        self.authenticated = true;
+                    }
+                    if self.authenticated {
+                        return chain_state.call_next_transform().await;
+                    }
+                    let (log_result, chain_result) = tokio::join!(
+                        write_ahead_log.append(
+                            self.connection_id,
+                            chain_state.local_addr,
+                            tee_state.requests,
+                        ),
+                        chain_state.call_next_transform()
+                    );
+                    if let Err(e) = log_result {
+                        self.dropped_messages.increment(1);
+                        error!("Failed to write to tee write ahead log: {e:?}");
+                    }
+                    return chain_result;
+                }
                 let (tee_result, chain_result) = tokio::join!(
                     self.tx
-                        .process_request_no_return(chain_state.clone(), self.timeout_micros),
+                        .process_request_no_return(tee_state, self.timeout_micros),
                     chain_state.call_next_transform()
                 );
                 if let Err(e) = tee_result {
@@ -594,6 +734,9 @@ struct AppState {
 #[cfg(all(test, feature = "redis"))]
 mod tests {
     use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::chain::TransformAndMetrics;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
     use crate::{frame::MessageType, transforms::null::NullSinkConfig};
     use pretty_assertions::assert_eq;
 
@@ -602,9 +745,11 @@ mod tests {
         let config = TeeConfig {
             behavior: None,
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
 
         let transform_context_config = TransformContextConfig {
@@ -621,9 +766,14 @@ mod tests {
         let config = TeeConfig {
             behavior: None,
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig), Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![
+                Box::new(NullSinkConfig),
+                Box::new(NullSinkConfig),
+            ]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
 
         let transform_context_config = TransformContextConfig {
@@ -643,9 +793,11 @@ mod tests {
         let config = TeeConfig {
             behavior: Some(ConsistencyBehaviorConfig::Ignore),
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
         let transform_context_config = TransformContextConfig {
             chain_name: "".into(),
@@ -661,9 +813,11 @@ mod tests {
         let config = TeeConfig {
             behavior: Some(ConsistencyBehaviorConfig::FailOnMismatch),
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
         let transform_context_config = TransformContextConfig {
             chain_name: "".into(),
@@ -678,12 +832,14 @@ mod tests {
     async fn test_validate_behaviour_subchain_on_mismatch_invalid() {
         let config = TeeConfig {
             behavior: Some(ConsistencyBehaviorConfig::SubchainOnMismatch(
-                TransformChainConfig(vec![Box::new(NullSinkConfig), Box::new(NullSinkConfig)]),
+                TransformChainConfig::new(vec![Box::new(NullSinkConfig), Box::new(NullSinkConfig)]),
             )),
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
 
         let transform_context_config = TransformContextConfig {
@@ -702,12 +858,14 @@ mod tests {
     async fn test_validate_behaviour_subchain_on_mismatch_valid() {
         let config = TeeConfig {
             behavior: Some(ConsistencyBehaviorConfig::SubchainOnMismatch(
-                TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+                TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             )),
             timeout_micros: None,
-            chain: TransformChainConfig(vec![Box::new(NullSinkConfig)]),
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
             buffer_size: None,
             switch_port: None,
+            write_ahead_log: None,
+            schedule: None,
         };
 
         let transform_context_config = TransformContextConfig {
@@ -718,4 +876,66 @@ mod tests {
         let result = transform.validate();
         assert_eq!(result, Vec::<String>::new());
     }
+
+    #[tokio::test]
+    async fn test_write_ahead_log_skips_authenticated_connections() {
+        let dir = std::env::temp_dir().join(format!("shotover-tee-wal-{}", rand::random::<u64>()));
+        let config = TeeConfig {
+            behavior: None,
+            timeout_micros: None,
+            chain: TransformChainConfig::new(vec![Box::new(NullSinkConfig)]),
+            buffer_size: None,
+            switch_port: None,
+            write_ahead_log: Some(WriteAheadLogConfig {
+                path: dir.to_str().unwrap().to_owned(),
+                max_disk_usage_bytes: 1024 * 1024,
+            }),
+            schedule: None,
+        };
+        let transform_context_config = TransformContextConfig {
+            chain_name: "".into(),
+            up_chain_protocol: MessageType::Redis,
+        };
+        let builder = config.get_builder(transform_context_config).await.unwrap();
+
+        async fn send(tee: &mut Box<dyn Transform>, requests: Messages) {
+            let mut chain = vec![TransformAndMetrics::new(Box::new(DebugReturner::new(
+                Response::Message(redis_command(&["OK"])),
+            )))];
+            let mut chain_state = ChainState::new_test(requests);
+            chain_state.reset(&mut chain);
+            tee.transform(&mut chain_state).await.unwrap();
+        }
+        let segment_len = || {
+            std::fs::metadata(dir.join(format!("{:020}.log", 0)))
+                .unwrap()
+                .len()
+        };
+
+        let mut authenticated = builder.build(TransformContextBuilder::new_test());
+        send(
+            &mut authenticated,
+            vec![
+                redis_command(&["AUTH", "user", "password"]),
+                redis_command(&["SET", "foo", "bar"]),
+            ],
+        )
+        .await;
+        send(
+            &mut authenticated,
+            vec![redis_command(&["SET", "foo", "baz"])],
+        )
+        .await;
+        assert!(!dir.join("setup").exists());
+        assert_eq!(segment_len(), 0);
+
+        let mut unauthenticated = builder.build(TransformContextBuilder::new_test());
+        send(
+            &mut unauthenticated,
+            vec![redis_command(&["SET", "foo", "bar"])],
+        )
+        .await;
+        assert!(segment_len() > 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,405 @@
+//! Limits when and how much traffic the tee chain receives, for staged mirroring of production traffic.
+
+use crate::message::Message;
+use anyhow::{anyhow, bail, Result};
+use metrics::{gauge, Gauge};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+use crate::frame::Frame;
+#[cfg(feature = "redis")]
+use crate::frame::RedisFrame;
+#[cfg(feature = "cassandra")]
+use crate::{frame::CassandraOperation, message::OperationType};
+
+/// Every schedule that has been created and not yet dropped, controlled by the `/tee` endpoint.
+static SCHEDULES: LazyLock<Mutex<Vec<Weak<TeeSchedule>>>> = LazyLock::new(Default::default);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TeeScheduleConfig {
+    /// Requests are only mirrored within these windows, defaults to always mirroring.
+    #[serde(default)]
+    pub windows: Vec<TimeWindowConfig>,
+    /// The percentage of requests mirrored, defaults to 100.
+    pub percentage: Option<f64>,
+    /// When set, the mirrored percentage increases linearly from 0 to `percentage` over this many seconds each time mirroring starts.
+    pub ramp_up_secs: Option<u64>,
+    /// Whether mirroring follows the schedule when shotover starts, defaults to true.
+    /// When false, mirroring is off until enabled via the observability interface.
+    pub enabled: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TimeWindowConfig {
+    /// The days of the week the window starts on, defaults to every day.
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// The UTC time the window starts at, formatted as `HH:MM`.
+    pub start: String,
+    /// The UTC time the window ends at, formatted as `HH:MM`.
+    /// Windows ending before they start end on the following day.
+    pub end: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+struct TimeWindow {
+    days: Vec<Weekday>,
+    /// Minutes since midnight
+    start: u32,
+    end: u32,
+}
+
+impl TimeWindow {
+    fn new(config: &TimeWindowConfig) -> Result<Self> {
+        let window = TimeWindow {
+            days: config.days.clone(),
+            start: parse_time(&config.start)?,
+            end: parse_time(&config.end)?,
+        };
+        if window.start == window.end {
+            bail!(
+                "the window {} - {} must not start and end at the same time",
+                config.start,
+                config.end
+            );
+        }
+        Ok(window)
+    }
+
+    fn starts_on(&self, day: Weekday) -> bool {
+        self.days.is_empty() || self.days.contains(&day)
+    }
+
+    fn contains(&self, unix_time: Duration) -> bool {
+        let secs = unix_time.as_secs();
+        let days = secs / 86400;
+        // The unix epoch was on a Thursday
+        let day = WEEKDAYS[((days + 3) % 7) as usize];
+        let previous_day = WEEKDAYS[((days + 2) % 7) as usize];
+        let minute = ((secs % 86400) / 60) as u32;
+        if self.start < self.end {
+            self.starts_on(day) && self.start <= minute && minute < self.end
+        } else {
+            (self.starts_on(day) && self.start <= minute)
+                || (self.starts_on(previous_day) && minute < self.end)
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<u32> {
+    let parsed = time.split_once(':').and_then(|(hours, minutes)| {
+        if hours.len() != 2 || minutes.len() != 2 {
+            return None;
+        }
+        let hours: u32 = hours.parse().ok()?;
+        let minutes: u32 = minutes.parse().ok()?;
+        (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+    });
+    parsed.ok_or_else(|| anyhow!("{time:?} is not a valid time, it must be formatted as HH:MM"))
+}
+
+/// How mirroring was last set via the observability interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+enum Mode {
+    /// Mirror according to the configured windows
+    Scheduled,
+    /// Mirror regardless of the configured windows
+    On,
+    Off,
+}
+
+impl Mode {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Mode::Scheduled,
+            1 => Mode::On,
+            _ => Mode::Off,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Scheduled => "scheduled",
+            Mode::On => "on",
+            Mode::Off => "off",
+        }
+    }
+}
+
+/// Shared by every connection of a single Tee transform.
+pub(crate) struct TeeSchedule {
+    chain_name: String,
+    windows: Vec<TimeWindow>,
+    percentage: f64,
+    ramp_up: Option<Duration>,
+    mode: AtomicU8,
+    /// When mirroring last started, None while mirroring is inactive.
+    active_since: Mutex<Option<Instant>>,
+    mirrored_percentage: Gauge,
+}
+
+impl TeeSchedule {
+    pub(crate) fn new(config: &TeeScheduleConfig, chain_name: String) -> Result<Arc<Self>> {
+        let percentage = config.percentage.unwrap_or(100.0);
+        if !(0.0..=100.0).contains(&percentage) {
+            bail!("schedule: percentage must be between 0 and 100 but was {percentage}");
+        }
+        let windows = config
+            .windows
+            .iter()
+            .map(TimeWindow::new)
+            .collect::<Result<_>>()
+            .map_err(|err| anyhow!("schedule: {err}"))?;
+        let mode = if config.enabled.unwrap_or(true) {
+            Mode::Scheduled
+        } else {
+            Mode::Off
+        };
+        let schedule = Arc::new(TeeSchedule {
+            mirrored_percentage: gauge!("shotover_tee_mirrored_percentage", "chain" => chain_name.clone()),
+            chain_name,
+            windows,
+            percentage,
+            ramp_up: config
+                .ramp_up_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            mode: AtomicU8::new(mode as u8),
+            active_since: Mutex::new(None),
+        });
+
+        let mut schedules = SCHEDULES.lock().unwrap();
+        schedules.retain(|x| x.strong_count() > 0);
+        schedules.push(Arc::downgrade(&schedule));
+
+        Ok(schedule)
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::from_u8(self.mode.load(Ordering::Relaxed))
+    }
+
+    fn is_active(&self, unix_time: Duration) -> bool {
+        match self.mode() {
+            Mode::Scheduled => {
+                self.windows.is_empty()
+                    || self.windows.iter().any(|window| window.contains(unix_time))
+            }
+            Mode::On => true,
+            Mode::Off => false,
+        }
+    }
+
+    /// Removes the requests that should not be mirrored right now.
+    pub(crate) fn retain_mirrored(&self, requests: &mut Vec<Message>) {
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let percentage = self.percentage_at(unix_time, Instant::now());
+        self.mirrored_percentage.set(percentage);
+        if percentage < 100.0 {
+            requests.retain_mut(|request| {
+                always_mirrored(request) || rand::random::<f64>() * 100.0 < percentage
+            });
+        }
+    }
+
+    fn percentage_at(&self, unix_time: Duration, now: Instant) -> f64 {
+        let mut active_since = self.active_since.lock().unwrap();
+        if !self.is_active(unix_time) {
+            *active_since = None;
+            return 0.0;
+        }
+        let since = *active_since.get_or_insert(now);
+        match self.ramp_up {
+            Some(ramp_up) => {
+                let progress = now.duration_since(since).as_secs_f64() / ramp_up.as_secs_f64();
+                self.percentage * progress.min(1.0)
+            }
+            None => self.percentage,
+        }
+    }
+}
+
+/// Returns true for requests that set up the state of the connection, such as the handshake or switching database.
+/// These are mirrored regardless of the schedule so that the tee chain can handle the requests mirrored after them.
+fn always_mirrored(request: &mut Message) -> bool {
+    match request.frame() {
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(frame)) => match &frame.operation {
+            CassandraOperation::Query { .. } => frame.operation_type() == OperationType::Unknown,
+            CassandraOperation::Execute(_) | CassandraOperation::Batch(_) => false,
+            _ => true,
+        },
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(RedisFrame::Array(args))) => match args.first() {
+            Some(RedisFrame::BulkString(command)) => matches!(
+                command.to_ascii_uppercase().as_slice(),
+                b"AUTH" | b"HELLO" | b"SELECT"
+            ),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Describes the state of every Tee schedule.
+pub(crate) fn report() -> String {
+    let mut output = String::new();
+    for schedule in SCHEDULES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|schedule| schedule.upgrade())
+    {
+        let active_since = *schedule.active_since.lock().unwrap();
+        writeln!(
+            output,
+            "{}: mode {}, {}",
+            schedule.chain_name,
+            schedule.mode().name(),
+            match active_since {
+                Some(since) => format!("mirroring since {}s ago", since.elapsed().as_secs()),
+                None => "not mirroring".to_owned(),
+            }
+        )
+        .unwrap();
+    }
+    output
+}
+
+/// Sets the mode of the Tee schedules in the named chain, returning false if the chain has none.
+pub(crate) fn set_mode(chain_name: &str, mode: &str) -> Result<bool> {
+    let mode = match mode.trim() {
+        "scheduled" => Mode::Scheduled,
+        "on" => Mode::On,
+        "off" => Mode::Off,
+        mode => bail!("Invalid tee mode: {mode:?}, should be 'scheduled', 'on' or 'off'"),
+    };
+    let mut found = false;
+    for schedule in SCHEDULES
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|schedule| schedule.upgrade())
+        .filter(|schedule| schedule.chain_name == chain_name)
+    {
+        schedule.mode.store(mode as u8, Ordering::Relaxed);
+        found = true;
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn window(days: Vec<Weekday>, start: &str, end: &str) -> TimeWindow {
+        TimeWindow::new(&TimeWindowConfig {
+            days,
+            start: start.to_owned(),
+            end: end.to_owned(),
+        })
+        .unwrap()
+    }
+
+    /// 2024-01-01 was a Monday
+    fn monday_at(hours: u64, minutes: u64) -> Duration {
+        Duration::from_secs(1704067200 + hours * 3600 + minutes * 60)
+    }
+
+    #[test]
+    fn test_window() {
+        let window = window(vec![Weekday::Mon], "02:00", "04:30");
+        assert!(!window.contains(monday_at(1, 59)));
+        assert!(window.contains(monday_at(2, 0)));
+        assert!(window.contains(monday_at(4, 29)));
+        assert!(!window.contains(monday_at(4, 30)));
+        assert!(!window.contains(monday_at(24 + 3, 0)));
+    }
+
+    #[test]
+    fn test_window_past_midnight() {
+        let window = window(vec![Weekday::Sun], "23:00", "01:00");
+        assert!(window.contains(monday_at(0, 30)));
+        assert!(!window.contains(monday_at(1, 0)));
+        assert!(!window.contains(monday_at(23, 30)));
+        assert!(window.contains(monday_at(24 * 6 + 23, 30)));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("00:00").unwrap(), 0);
+        assert_eq!(parse_time("23:59").unwrap(), 1439);
+        assert_eq!(
+            parse_time("24:00").unwrap_err().to_string(),
+            r#""24:00" is not a valid time, it must be formatted as HH:MM"#
+        );
+        assert!(parse_time("1200").is_err());
+    }
+
+    #[test]
+    fn test_ramp_up() {
+        let schedule = TeeSchedule::new(
+            &TeeScheduleConfig {
+                windows: vec![],
+                percentage: Some(50.0),
+                ramp_up_secs: Some(100),
+                enabled: None,
+            },
+            "test_ramp_up".to_owned(),
+        )
+        .unwrap();
+        let start = Instant::now();
+        let unix_time = monday_at(0, 0);
+        assert_eq!(schedule.percentage_at(unix_time, start), 0.0);
+        assert_eq!(
+            schedule.percentage_at(unix_time, start + Duration::from_secs(50)),
+            25.0
+        );
+        assert_eq!(
+            schedule.percentage_at(unix_time, start + Duration::from_secs(200)),
+            50.0
+        );
+
+        // Turning mirroring off and on again restarts the ramp up
+        set_mode("test_ramp_up", "off").unwrap();
+        let later = start + Duration::from_secs(300);
+        assert_eq!(schedule.percentage_at(unix_time, later), 0.0);
+        set_mode("test_ramp_up", "on").unwrap();
+        assert_eq!(schedule.percentage_at(unix_time, later), 0.0);
+        assert_eq!(
+            schedule.percentage_at(unix_time, later + Duration::from_secs(10)),
+            5.0
+        );
+        assert!(!set_mode("no_such_chain", "on").unwrap());
+    }
+}
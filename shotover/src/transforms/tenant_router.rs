@@ -0,0 +1,430 @@
+use super::{DownChainProtocol, TransformContextBuilder, TransformContextConfig, UpChainProtocol};
+use crate::config::chain::TransformChainConfig;
+use crate::message::{ErrorKind, Message, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::util::ordered_responses::OrderedResponses;
+use crate::transforms::util::setup_replay::{is_setup_request, SetupReplay};
+use crate::transforms::{ChainState, Transform, TransformBuilder, TransformConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::join_all;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TenantRouterConfig {
+    /// How the tenant of each request is determined.
+    pub route_by: RouteBy,
+    /// Tenant names mapped to the chain that the tenant's requests are routed to.
+    pub tenants: HashMap<String, TransformChainConfig>,
+    /// The tenant that requests are routed to when their tenant cannot be determined or is not configured.
+    /// When None, such requests receive an error response.
+    pub default_tenant: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub enum RouteBy {
+    /// The tenant is the prefix of the keys accessed by the request, up to the first occurrence of `delimiter`.
+    KeyPrefix { delimiter: String },
+    /// The tenant is the keyspace of the tables accessed by the request.
+    Keyspace,
+    /// The tenant is the identity of the client, as established by an auth termination transform or the client's TLS certificate.
+    Identity,
+}
+
+const NAME: &str = "TenantRouter";
+#[typetag::serde(name = "TenantRouter")]
+#[async_trait(?Send)]
+impl TransformConfig for TenantRouterConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        // sort the tenants so that the order of the chains does not depend on HashMap iteration order
+        let mut names: Vec<&String> = self.tenants.keys().collect();
+        names.sort();
+
+        let mut tenants = Vec::with_capacity(names.len());
+        for name in names {
+            let chain = self.tenants[name]
+                .get_builder(TransformContextConfig {
+                    chain_name: name.clone(),
+                    up_chain_protocol: transform_context.up_chain_protocol,
+                })
+                .await?;
+            tenants.push(TenantBuilder {
+                name: name.clone(),
+                chain,
+                requests: counter!("shotover_tenant_requests_count", "chain" => transform_context.chain_name.clone(), "tenant" => name.clone()),
+            });
+        }
+
+        Ok(Box::new(TenantRouterBuilder {
+            route_by: self.route_by.clone(),
+            default_tenant: self.default_tenant.clone(),
+            tenants: Arc::new(tenants),
+            in_order: transform_context.up_chain_protocol.is_inorder(),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::Any
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::Terminating
+    }
+}
+
+struct TenantBuilder {
+    name: String,
+    chain: TransformChainBuilder,
+    requests: Counter,
+}
+
+struct TenantRouterBuilder {
+    route_by: RouteBy,
+    default_tenant: Option<String>,
+    tenants: Arc<Vec<TenantBuilder>>,
+    in_order: bool,
+}
+
+impl TenantRouterBuilder {
+    fn tenant_index(&self, name: &str) -> Option<usize> {
+        self.tenants.iter().position(|tenant| tenant.name == name)
+    }
+}
+
+impl TransformBuilder for TenantRouterBuilder {
+    fn build(&self, transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(TenantRouter {
+            route_by: self.route_by.clone(),
+            indexes: self
+                .tenants
+                .iter()
+                .enumerate()
+                .map(|(i, tenant)| (tenant.name.clone(), i))
+                .collect(),
+            default_tenant: self
+                .default_tenant
+                .as_ref()
+                .and_then(|name| self.tenant_index(name)),
+            chains: self.tenants.iter().map(|_| None).collect(),
+            tenants: self.tenants.clone(),
+            transform_context,
+            responses: OrderedResponses::new(self.in_order),
+            setup_replay: SetupReplay::new(self.tenants.len()),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.tenants.is_empty() {
+            errors.push("  at least one tenant must be configured".to_owned());
+        }
+        if let Some(name) = &self.default_tenant {
+            if self.tenant_index(name).is_none() {
+                errors.push(format!(
+                    "  default_tenant {name:?} is not one of the configured tenants"
+                ));
+            }
+        }
+        for tenant in self.tenants.iter() {
+            errors.extend(tenant.chain.validate().iter().map(|x| format!("  {x}")));
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+
+    fn is_terminating(&self) -> bool {
+        true
+    }
+}
+
+enum Route {
+    Tenant(usize),
+    /// The request does not identify any tenant, e.g. a redis PING or the cassandra handshake
+    NoTenant,
+    /// The request identifies a tenant that is not configured and there is no default tenant
+    UnknownTenant(String),
+    MultipleTenants,
+}
+
+struct TenantRouter {
+    route_by: RouteBy,
+    tenants: Arc<Vec<TenantBuilder>>,
+    indexes: HashMap<String, usize>,
+    default_tenant: Option<usize>,
+    /// The chain of each tenant, only built once a request is routed to the tenant so that connections are only opened to the clusters that the client uses
+    chains: Vec<Option<TransformChain>>,
+    transform_context: TransformContextBuilder,
+    responses: OrderedResponses,
+    /// Replays requests such as AUTH or the cassandra STARTUP to each tenant's chain so that its connection is set up in the same way as the default tenant's.
+    setup_replay: SetupReplay,
+}
+
+impl TenantRouter {
+    fn route(&self, request: &mut Message, identity: Option<&str>) -> Route {
+        let names: Vec<String> = match &self.route_by {
+            RouteBy::KeyPrefix { delimiter } => request
+                .primary_keys()
+                .iter()
+                .filter_map(|key| {
+                    String::from_utf8_lossy(key)
+                        .split_once(delimiter.as_str())
+                        .map(|(prefix, _)| prefix.to_owned())
+                })
+                .collect(),
+            RouteBy::Keyspace => request
+                .tables()
+                .iter()
+                .filter_map(|table| {
+                    table
+                        .split_once('.')
+                        .map(|(keyspace, _)| keyspace.to_owned())
+                })
+                .collect(),
+            RouteBy::Identity => identity.map(|x| x.to_owned()).into_iter().collect(),
+        };
+
+        let mut route = Route::NoTenant;
+        for name in names {
+            let index = match self.indexes.get(&name).copied().or(self.default_tenant) {
+                Some(index) => index,
+                None => return Route::UnknownTenant(name),
+            };
+            match route {
+                Route::Tenant(existing) if existing != index => return Route::MultipleTenants,
+                _ => route = Route::Tenant(index),
+            }
+        }
+        route
+    }
+
+    /// Adds the request to the requests to be sent to the tenant's chain, preceded by any setup requests the chain has not received yet.
+    fn push_request(&mut self, index: usize, mut request: Message, routed: &mut [Messages]) {
+        let is_setup = is_setup_request(&mut request);
+        self.setup_replay
+            .replay_to(index, &request, is_setup, &mut routed[index]);
+        self.tenants[index].requests.increment(1);
+        request.annotate("tenant", self.tenants[index].name.clone());
+        routed[index].push(request);
+    }
+
+    fn respond_with_error(&mut self, request: &mut Message, error: String) -> Result<()> {
+        let response = request.error_response(error, ErrorKind::InvalidRequest)?;
+        self.responses.insert(request.id(), response);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transform for TenantRouter {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn on_shutdown(&mut self) -> Result<()> {
+        for chain in self.chains.iter_mut().flatten() {
+            chain.shutdown().await;
+        }
+        Ok(())
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        let identity = chain_state.session.identity().map(|x| x.to_owned());
+        let mut routed: Vec<Messages> = vec![vec![]; self.tenants.len()];
+        for mut request in std::mem::take(&mut chain_state.requests) {
+            self.responses.push_request(request.id());
+            match self.route(&mut request, identity.as_deref()) {
+                Route::Tenant(index) => self.push_request(index, request, &mut routed),
+                Route::NoTenant => match self.default_tenant {
+                    Some(index) => self.push_request(index, request, &mut routed),
+                    None => self.respond_with_error(
+                        &mut request,
+                        "The tenant of the request could not be determined".to_owned(),
+                    )?,
+                },
+                Route::UnknownTenant(name) => {
+                    self.respond_with_error(&mut request, format!("Unknown tenant {name:?}"))?
+                }
+                Route::MultipleTenants => self.respond_with_error(
+                    &mut request,
+                    "The request accesses the data of multiple tenants".to_owned(),
+                )?,
+            }
+        }
+
+        for (chain, (requests, tenant)) in self
+            .chains
+            .iter_mut()
+            .zip(routed.iter().zip(self.tenants.iter()))
+        {
+            if chain.is_none() && !requests.is_empty() {
+                *chain = Some(tenant.chain.build(self.transform_context.clone()));
+            }
+        }
+
+        // Every built chain is run, even without any requests, to pick up any responses that arrived since the last run.
+        let local_addr = chain_state.local_addr;
+        let flush = chain_state.flush;
+        let session = &chain_state.session;
+        let results = join_all(self.chains.iter_mut().zip(routed).filter_map(
+            |(chain, requests)| {
+                let chain = chain.as_mut()?;
+                Some(async move {
+                    let mut sub_chain_state = ChainState::new_with_addr(requests, local_addr);
+                    sub_chain_state.flush = flush;
+                    sub_chain_state.session = session.clone();
+                    chain.process_request(&mut sub_chain_state).await
+                })
+            },
+        ))
+        .await;
+
+        let mut responses = vec![];
+        for result in results {
+            for response in result? {
+                match response.request_id() {
+                    Some(id) if self.setup_replay.remove_replayed(id) => {}
+                    Some(id) => {
+                        self.responses.insert(id, response);
+                    }
+                    None => responses.push(response),
+                }
+            }
+        }
+
+        self.responses.take_ready(&mut responses);
+
+        Ok(responses)
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::frame::{Frame, RedisFrame};
+    use crate::test_utils::redis_command;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use pretty_assertions::assert_eq;
+
+    fn tenant(name: &str) -> TenantBuilder {
+        TenantBuilder {
+            name: name.to_owned(),
+            chain: TransformChainBuilder::new(
+                vec![Box::new(DebugReturner::new(Response::Redis(
+                    name.to_owned(),
+                )))],
+                "tenant",
+            ),
+            requests: Counter::noop(),
+        }
+    }
+
+    fn router(default_tenant: Option<&str>) -> Box<dyn Transform> {
+        TenantRouterBuilder {
+            route_by: RouteBy::KeyPrefix {
+                delimiter: ":".to_owned(),
+            },
+            default_tenant: default_tenant.map(|x| x.to_owned()),
+            tenants: Arc::new(vec![tenant("a"), tenant("b")]),
+            in_order: true,
+        }
+        .build(TransformContextBuilder::new_test())
+    }
+
+    async fn run(router: &mut Box<dyn Transform>, requests: Messages) -> Vec<Frame> {
+        router
+            .transform(&mut ChainState::new_test(requests))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_route_by_key_prefix() {
+        let mut router = router(Some("a"));
+        let responses = run(
+            &mut router,
+            vec![
+                redis_command(&["AUTH", "pass"]),
+                redis_command(&["GET", "b:1"]),
+                redis_command(&["GET", "a:1"]),
+                redis_command(&["GET", "c:1"]),
+                redis_command(&["MGET", "a:1", "b:1"]),
+                redis_command(&["PING"]),
+            ],
+        )
+        .await;
+        let string = |x: &'static str| Frame::Redis(RedisFrame::BulkString(x.into()));
+        assert_eq!(
+            responses,
+            vec![
+                // the AUTH is sent to the default tenant and replayed to b without its response reaching the client
+                string("a"),
+                string("b"),
+                string("a"),
+                // unconfigured tenants are routed to the default tenant
+                string("a"),
+                Frame::Redis(RedisFrame::Error(
+                    "ERR The request accesses the data of multiple tenants".into()
+                )),
+                string("a"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_route_without_default_tenant() {
+        let mut router = router(None);
+        let responses = run(
+            &mut router,
+            vec![redis_command(&["GET", "c:1"]), redis_command(&["PING"])],
+        )
+        .await;
+        assert_eq!(
+            responses,
+            vec![
+                Frame::Redis(RedisFrame::Error("ERR Unknown tenant \"c\"".into())),
+                Frame::Redis(RedisFrame::Error(
+                    "ERR The tenant of the request could not be determined".into()
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = TenantRouterBuilder {
+            route_by: RouteBy::Identity,
+            default_tenant: Some("c".to_owned()),
+            tenants: Arc::new(vec![tenant("a")]),
+            in_order: true,
+        };
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "TenantRouter:",
+                "  default_tenant \"c\" is not one of the configured tenants",
+            ]
+        );
+    }
+}
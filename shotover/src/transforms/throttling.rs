@@ -73,6 +73,11 @@ impl TransformBuilder for RequestThrottling {
             vec![]
         }
     }
+
+    fn accepts_streamed_responses(&self) -> bool {
+        // only the responses to throttled requests are replaced, which are never streamed
+        true
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,608 @@
+use crate::frame::{Frame, MessageType};
+use crate::message::{ErrorKind, Message, MessageIdMap, Messages};
+use crate::transforms::util::glob_match;
+use crate::transforms::{
+    ChainState, DownChainProtocol, Transform, TransformBuilder, TransformConfig,
+    TransformContextBuilder, TransformContextConfig, UpChainProtocol,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use metrics::{counter, Counter};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[cfg(feature = "cassandra")]
+use {
+    crate::frame::CassandraFrame,
+    cql3_parser::cassandra_statement::CassandraStatement,
+    cql3_parser::common::{FQName, TtlTimestamp},
+};
+#[cfg(feature = "redis")]
+use {
+    crate::frame::RedisFrame,
+    bytes::Bytes,
+    std::time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Enforces expiry policies on writes, injecting a TTL into writes without one and raising or lowering TTLs outside of the configured bounds.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TtlPolicyConfig {
+    /// Each write is checked against the first rule whose pattern matches its key or table, writes matching no rule are unchanged.
+    pub rules: Vec<TtlRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TtlRule {
+    /// A pattern matching redis keys or fully qualified cassandra table names.
+    /// `*` matches any sequence of characters and `?` matches any single character.
+    pub pattern: String,
+    /// TTLs below this are raised to it.
+    pub min_ttl_seconds: Option<u64>,
+    /// TTLs above this are lowered to it.
+    /// Writes without a TTL are given this TTL when `default_ttl_seconds` is not set.
+    pub max_ttl_seconds: Option<u64>,
+    /// The TTL given to writes without a TTL.
+    pub default_ttl_seconds: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Enforcement {
+    /// A TTL was given to a write without one
+    Injected,
+    Raised,
+    Lowered,
+    /// The write would have removed the expiry of a key that must expire
+    Rejected,
+}
+
+impl Enforcement {
+    const ALL: [Enforcement; 4] = [
+        Enforcement::Injected,
+        Enforcement::Raised,
+        Enforcement::Lowered,
+        Enforcement::Rejected,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Enforcement::Injected => "injected",
+            Enforcement::Raised => "raised",
+            Enforcement::Lowered => "lowered",
+            Enforcement::Rejected => "rejected",
+        }
+    }
+}
+
+impl TtlRule {
+    /// Returns true if the rule requires every matching write to expire
+    fn requires_expiry(&self) -> bool {
+        self.default_ttl_seconds.is_some() || self.max_ttl_seconds.is_some()
+    }
+
+    /// Returns the TTL in milliseconds that replaces `ttl_ms`, or None if it complies with the rule.
+    /// `ttl_ms` is None for writes that do not expire.
+    fn enforce(&self, ttl_ms: Option<u64>) -> Option<(u64, Enforcement)> {
+        let ms = |seconds: u64| seconds.saturating_mul(1000);
+        let Some(ttl_ms) = ttl_ms else {
+            return self
+                .default_ttl_seconds
+                .or(self.max_ttl_seconds)
+                .map(|ttl| (ms(ttl), Enforcement::Injected));
+        };
+        if let Some(min) = self.min_ttl_seconds.map(ms) {
+            if ttl_ms < min {
+                return Some((min, Enforcement::Raised));
+            }
+        }
+        if let Some(max) = self.max_ttl_seconds.map(ms) {
+            if ttl_ms > max {
+                return Some((max, Enforcement::Lowered));
+            }
+        }
+        None
+    }
+}
+
+const NAME: &str = "TtlPolicy";
+#[typetag::serde(name = "TtlPolicy")]
+#[async_trait(?Send)]
+impl TransformConfig for TtlPolicyConfig {
+    async fn get_builder(
+        &self,
+        transform_context: TransformContextConfig,
+    ) -> Result<Box<dyn TransformBuilder>> {
+        let chain_name = transform_context.chain_name;
+        Ok(Box::new(TtlPolicyBuilder {
+            rules: Arc::new(self.rules.clone()),
+            enforced: Enforcement::ALL.map(|enforcement| {
+                counter!("shotover_ttl_policy_enforced_count", "chain" => chain_name.clone(), "enforcement" => enforcement.as_str())
+            }),
+        }))
+    }
+
+    fn up_chain_protocol(&self) -> UpChainProtocol {
+        UpChainProtocol::MustBeOneOf(vec![
+            #[cfg(feature = "redis")]
+            MessageType::Redis,
+            #[cfg(feature = "cassandra")]
+            MessageType::Cassandra,
+        ])
+    }
+
+    fn down_chain_protocol(&self) -> DownChainProtocol {
+        DownChainProtocol::SameAsUpChain
+    }
+}
+
+struct TtlPolicyBuilder {
+    rules: Arc<Vec<TtlRule>>,
+    /// Counts the writes each enforcement was applied to, indexed by the position of the enforcement in `Enforcement::ALL`
+    enforced: [Counter; 4],
+}
+
+impl TransformBuilder for TtlPolicyBuilder {
+    fn build(&self, _transform_context: TransformContextBuilder) -> Box<dyn Transform> {
+        Box::new(TtlPolicy {
+            rules: self.rules.clone(),
+            enforced: self.enforced.clone(),
+            rejected: MessageIdMap::default(),
+        })
+    }
+
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+        if self.rules.is_empty() {
+            errors.push("  at least one rule must be configured".to_owned());
+        }
+        for rule in self.rules.iter() {
+            let pattern = &rule.pattern;
+            if rule.min_ttl_seconds.is_none() && !rule.requires_expiry() {
+                errors.push(format!(
+                    "  rule {pattern:?} must set at least one of min_ttl_seconds, max_ttl_seconds or default_ttl_seconds"
+                ));
+            }
+            if [
+                rule.min_ttl_seconds,
+                rule.max_ttl_seconds,
+                rule.default_ttl_seconds,
+            ]
+            .contains(&Some(0))
+            {
+                errors.push(format!(
+                    "  rule {pattern:?} has a TTL of 0 which would mean the data never expires"
+                ));
+            }
+            if let (Some(min), Some(max)) = (rule.min_ttl_seconds, rule.max_ttl_seconds) {
+                if min > max {
+                    errors.push(format!(
+                        "  rule {pattern:?} has a min_ttl_seconds greater than its max_ttl_seconds"
+                    ));
+                }
+            }
+            if let Some(default) = rule.default_ttl_seconds {
+                if rule.enforce(Some(default.saturating_mul(1000))).is_some() {
+                    errors.push(format!(
+                        "  rule {pattern:?} has a default_ttl_seconds outside of its min_ttl_seconds and max_ttl_seconds"
+                    ));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.insert(0, format!("{}:", self.get_name()));
+        }
+
+        errors
+    }
+}
+
+struct TtlPolicy {
+    rules: Arc<Vec<TtlRule>>,
+    enforced: [Counter; 4],
+    /// Error responses keyed by the id of the dummy request they respond to
+    rejected: MessageIdMap<Message>,
+}
+
+impl TtlPolicy {
+    fn rule(&self, resource: &[u8]) -> Option<&TtlRule> {
+        self.rules
+            .iter()
+            .find(|rule| glob_match(rule.pattern.as_bytes(), resource))
+    }
+
+    fn count(&self, enforcement: Enforcement) {
+        let index = Enforcement::ALL
+            .iter()
+            .position(|x| *x == enforcement)
+            .unwrap();
+        self.enforced[index].increment(1);
+    }
+
+    /// Enforces the rules on the request, returning an error message if the request must be rejected
+    fn enforce(&self, request: &mut Message) -> Result<(), String> {
+        let enforcements = match request.frame() {
+            #[cfg(feature = "redis")]
+            Some(Frame::Redis(RedisFrame::Array(args))) => self.enforce_redis(args)?,
+            #[cfg(feature = "cassandra")]
+            Some(Frame::Cassandra(frame)) => self.enforce_cassandra(frame),
+            _ => vec![],
+        };
+        if !enforcements.is_empty() {
+            request.invalidate_cache();
+        }
+        for enforcement in enforcements {
+            self.count(enforcement);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+impl TtlPolicy {
+    /// Enforces the rules on SET, SETEX, PSETEX, GETEX, the EXPIRE family and PERSIST, other commands are unchanged.
+    /// A TTL that is changed is rewritten in milliseconds e.g. `EXPIRE` is rewritten to `PEXPIRE`.
+    fn enforce_redis(&self, args: &mut Vec<RedisFrame>) -> Result<Vec<Enforcement>, String> {
+        let (Some(RedisFrame::BulkString(command)), Some(RedisFrame::BulkString(key))) =
+            (args.first(), args.get(1))
+        else {
+            return Ok(vec![]);
+        };
+        let command = command.to_ascii_uppercase();
+        let Some(rule) = self.rule(key) else {
+            return Ok(vec![]);
+        };
+
+        let enforcement = match command.as_slice() {
+            b"SET" => enforce_redis_option(rule, args, 3, true)?,
+            b"GETEX" => enforce_redis_option(rule, args, 2, false)?,
+            b"SETEX" | b"PSETEX" => {
+                let ttl_ms = redis_ttl_ms(&command, args.get(2));
+                enforce_redis_ttl(rule, ttl_ms).map(|(ttl_ms, enforcement)| {
+                    args[0] = bulk_string("PSETEX");
+                    args[2] = bulk_string(&ttl_ms.to_string());
+                    enforcement
+                })
+            }
+            b"EXPIRE" | b"PEXPIRE" | b"EXPIREAT" | b"PEXPIREAT" => {
+                let ttl_ms = redis_ttl_ms(&command, args.get(2));
+                enforce_redis_ttl(rule, ttl_ms).map(|(ttl_ms, enforcement)| {
+                    args[0] = bulk_string("PEXPIRE");
+                    args[2] = bulk_string(&ttl_ms.to_string());
+                    enforcement
+                })
+            }
+            b"PERSIST" => check_persist(rule)?,
+            _ => None,
+        };
+        Ok(enforcement.into_iter().collect())
+    }
+}
+
+#[cfg(feature = "redis")]
+fn check_persist(rule: &TtlRule) -> Result<Option<Enforcement>, String> {
+    if rule.requires_expiry() {
+        Err(format!(
+            "The TTL policy requires keys matching {:?} to expire",
+            rule.pattern
+        ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Enforces the rule on a command whose expiry is given by an optional `EX`, `PX`, `EXAT`, `PXAT`, `KEEPTTL` or `PERSIST` argument, starting at `first_option`.
+/// When `inject` is set a command without an expiry argument is given one.
+#[cfg(feature = "redis")]
+fn enforce_redis_option(
+    rule: &TtlRule,
+    args: &mut Vec<RedisFrame>,
+    first_option: usize,
+    inject: bool,
+) -> Result<Option<Enforcement>, String> {
+    for i in first_option..args.len() {
+        let RedisFrame::BulkString(option) = &args[i] else {
+            continue;
+        };
+        let option = option.to_ascii_uppercase();
+        match option.as_slice() {
+            // The existing expiry of the key is kept, which was already enforced when it was set
+            b"KEEPTTL" => return Ok(None),
+            b"PERSIST" => return check_persist(rule),
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" => {
+                let ttl_ms = redis_ttl_ms(&option, args.get(i + 1));
+                return Ok(
+                    enforce_redis_ttl(rule, ttl_ms).map(|(ttl_ms, enforcement)| {
+                        args[i] = bulk_string("PX");
+                        args[i + 1] = bulk_string(&ttl_ms.to_string());
+                        enforcement
+                    }),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    if inject {
+        if let Some((ttl_ms, enforcement)) = rule.enforce(None) {
+            args.push(bulk_string("PX"));
+            args.push(bulk_string(&ttl_ms.to_string()));
+            return Ok(Some(enforcement));
+        }
+    }
+    Ok(None)
+}
+
+/// Enforces the rule on a TTL given by the command, leaving invalid TTLs for redis to reject.
+#[cfg(feature = "redis")]
+fn enforce_redis_ttl(rule: &TtlRule, ttl_ms: Option<i64>) -> Option<(u64, Enforcement)> {
+    match ttl_ms {
+        // An expiry in the past deletes the key
+        Some(ttl_ms) if ttl_ms > 0 => rule.enforce(Some(ttl_ms as u64)),
+        _ => None,
+    }
+}
+
+/// Converts the expiry argument of a command or option to the remaining milliseconds until the key expires
+#[cfg(feature = "redis")]
+fn redis_ttl_ms(unit: &[u8], value: Option<&RedisFrame>) -> Option<i64> {
+    let Some(RedisFrame::BulkString(value)) = value else {
+        return None;
+    };
+    let value: i64 = std::str::from_utf8(value).ok()?.parse().ok()?;
+    let now_ms = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as i64)
+            .unwrap_or(0)
+    };
+    match unit {
+        b"EX" | b"SETEX" | b"EXPIRE" => Some(value.saturating_mul(1000)),
+        b"PX" | b"PSETEX" | b"PEXPIRE" => Some(value),
+        b"EXAT" | b"EXPIREAT" => Some(value.saturating_mul(1000).saturating_sub(now_ms())),
+        b"PXAT" | b"PEXPIREAT" => Some(value.saturating_sub(now_ms())),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "redis")]
+fn bulk_string(value: &str) -> RedisFrame {
+    RedisFrame::BulkString(Bytes::copy_from_slice(value.as_bytes()))
+}
+
+#[cfg(feature = "cassandra")]
+impl TtlPolicy {
+    /// Enforces the rules on the INSERT and UPDATE statements of the frame, including statements being prepared.
+    fn enforce_cassandra(&self, frame: &mut CassandraFrame) -> Vec<Enforcement> {
+        // Enforcing the rules on the statement when it is prepared covers every later EXECUTE of it
+        if let Some(mut statement) = frame.prepared_statement() {
+            let enforcement = self.enforce_statement(&mut statement);
+            if enforcement.is_some() {
+                frame.set_prepared_statement(&statement);
+            }
+            return enforcement.into_iter().collect();
+        }
+        frame
+            .operation
+            .queries()
+            .filter_map(|statement| self.enforce_statement(statement))
+            .collect()
+    }
+
+    fn enforce_statement(&self, statement: &mut CassandraStatement) -> Option<Enforcement> {
+        let (table, using_ttl): (&FQName, &mut Option<TtlTimestamp>) = match statement {
+            CassandraStatement::Insert(insert) => (&insert.table_name, &mut insert.using_ttl),
+            CassandraStatement::Update(update) => (&update.table_name, &mut update.using_ttl),
+            _ => return None,
+        };
+        let rule = self.rule(table.to_string().as_bytes())?;
+        // A TTL of 0 means the data does not expire
+        let ttl_ms = using_ttl
+            .as_ref()
+            .and_then(|x| x.ttl)
+            .filter(|x| *x > 0)
+            .map(|x| x.saturating_mul(1000));
+        let (ttl_ms, enforcement) = rule.enforce(ttl_ms)?;
+        let ttl = Some(ttl_ms / 1000);
+        match using_ttl {
+            Some(using_ttl) => using_ttl.ttl = ttl,
+            None => {
+                *using_ttl = Some(TtlTimestamp {
+                    ttl,
+                    timestamp: None,
+                })
+            }
+        }
+        Some(enforcement)
+    }
+}
+
+#[async_trait]
+impl Transform for TtlPolicy {
+    fn get_name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn transform<'shorter, 'longer: 'shorter>(
+        &mut self,
+        chain_state: &'shorter mut ChainState<'longer>,
+    ) -> Result<Messages> {
+        for request in chain_state.requests.iter_mut() {
+            if let Err(error) = self.enforce(request) {
+                self.count(Enforcement::Rejected);
+                let response = request.error_response(error, ErrorKind::InvalidRequest)?;
+                self.rejected.insert(request.id(), response);
+                request.replace_with_dummy();
+            }
+        }
+
+        let mut responses = chain_state.call_next_transform().await?;
+        for response in responses.iter_mut() {
+            if let Some(error) = response
+                .request_id()
+                .and_then(|id| self.rejected.remove(&id))
+            {
+                *response = error;
+            }
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn rule(pattern: &str) -> TtlRule {
+        TtlRule {
+            pattern: pattern.to_owned(),
+            min_ttl_seconds: Some(60),
+            max_ttl_seconds: Some(3600),
+            default_ttl_seconds: Some(600),
+        }
+    }
+
+    fn builder(rules: Vec<TtlRule>) -> TtlPolicyBuilder {
+        TtlPolicyBuilder {
+            rules: Arc::new(rules),
+            enforced: Enforcement::ALL.map(|_| Counter::noop()),
+        }
+    }
+
+    #[test]
+    fn test_enforce() {
+        let rule = rule("*");
+        assert_eq!(rule.enforce(None), Some((600_000, Enforcement::Injected)));
+        assert_eq!(
+            rule.enforce(Some(1000)),
+            Some((60_000, Enforcement::Raised))
+        );
+        assert_eq!(rule.enforce(Some(60_000)), None);
+        assert_eq!(
+            rule.enforce(Some(7_200_000)),
+            Some((3_600_000, Enforcement::Lowered))
+        );
+
+        let max_only = TtlRule {
+            default_ttl_seconds: None,
+            ..rule
+        };
+        assert_eq!(
+            max_only.enforce(None),
+            Some((3_600_000, Enforcement::Injected))
+        );
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn test_redis() {
+        use crate::test_utils::{assert_error_response, redis_command, MockSink, TestChain};
+
+        let mut chain =
+            TestChain::from_builder(&builder(vec![rule("cache:*")]), MockSink::echo()).unwrap();
+        let mut responses = chain
+            .send(vec![
+                redis_command(&["SET", "cache:1", "foo"]),
+                redis_command(&["SET", "cache:1", "foo", "EX", "10", "NX"]),
+                redis_command(&["SET", "cache:1", "foo", "KEEPTTL"]),
+                redis_command(&["EXPIRE", "cache:1", "86400"]),
+                redis_command(&["SETEX", "cache:1", "120", "foo"]),
+                redis_command(&["SET", "other", "foo"]),
+                redis_command(&["PERSIST", "cache:1"]),
+            ])
+            .await
+            .unwrap();
+        assert_error_response(&mut responses[6], "cache:*");
+
+        let received: Vec<Frame> = chain
+            .take_received()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect();
+        let expected: Vec<Frame> = [
+            redis_command(&["SET", "cache:1", "foo", "PX", "600000"]),
+            redis_command(&["SET", "cache:1", "foo", "PX", "60000", "NX"]),
+            redis_command(&["SET", "cache:1", "foo", "KEEPTTL"]),
+            redis_command(&["PEXPIRE", "cache:1", "3600000"]),
+            redis_command(&["SETEX", "cache:1", "120", "foo"]),
+            redis_command(&["SET", "other", "foo"]),
+        ]
+        .into_iter()
+        .map(|mut x| x.frame().cloned().unwrap())
+        .collect();
+        assert_eq!(received, expected);
+    }
+
+    #[cfg(feature = "cassandra")]
+    #[test]
+    fn test_cassandra() {
+        use crate::frame::cassandra::parse_statement_single;
+
+        let policy = builder(vec![rule("ks.cache")]);
+        let policy = TtlPolicy {
+            rules: policy.rules.clone(),
+            enforced: policy.enforced.clone(),
+            rejected: MessageIdMap::default(),
+        };
+        let enforce = |query: &str| {
+            let mut statement = parse_statement_single(query);
+            let enforcement = policy.enforce_statement(&mut statement);
+            (statement, enforcement)
+        };
+
+        assert_eq!(
+            enforce("INSERT INTO ks.cache (id, x) VALUES (1, 2)"),
+            (
+                parse_statement_single("INSERT INTO ks.cache (id, x) VALUES (1, 2) USING TTL 600"),
+                Some(Enforcement::Injected)
+            )
+        );
+        assert_eq!(
+            enforce("UPDATE ks.cache USING TTL 86400 SET x = 2 WHERE id = 1"),
+            (
+                parse_statement_single("UPDATE ks.cache USING TTL 3600 SET x = 2 WHERE id = 1"),
+                Some(Enforcement::Lowered)
+            )
+        );
+        assert_eq!(
+            enforce("INSERT INTO ks.other (id, x) VALUES (1, 2)"),
+            (
+                parse_statement_single("INSERT INTO ks.other (id, x) VALUES (1, 2)"),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let builder = builder(vec![
+            TtlRule {
+                pattern: "a".to_owned(),
+                min_ttl_seconds: Some(100),
+                max_ttl_seconds: Some(10),
+                default_ttl_seconds: None,
+            },
+            TtlRule {
+                pattern: "b".to_owned(),
+                min_ttl_seconds: None,
+                max_ttl_seconds: None,
+                default_ttl_seconds: None,
+            },
+        ]);
+        assert_eq!(
+            builder.validate(),
+            vec![
+                "TtlPolicy:",
+                "  rule \"a\" has a min_ttl_seconds greater than its max_ttl_seconds",
+                "  rule \"b\" must set at least one of min_ttl_seconds, max_ttl_seconds or default_ttl_seconds",
+            ]
+        );
+    }
+}
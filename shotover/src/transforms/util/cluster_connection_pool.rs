@@ -2,7 +2,7 @@ use super::Response;
 use crate::codec::{CodecBuilder, CodecWriteError, DecoderHalf, EncoderHalf};
 use crate::frame::Frame;
 use crate::message::{Message, MessageId};
-use crate::tcp;
+use crate::tcp::{self, TcpConfig};
 use crate::tls::{TlsConnector, TlsConnectorConfig};
 use crate::transforms::util::{ConnectionError, Request};
 use anyhow::{anyhow, Result};
@@ -63,6 +63,8 @@ pub struct ConnectionPool<C: CodecBuilder, A: Authenticator<T>, T: Token> {
 
     #[derivative(Debug = "ignore")]
     tls: Option<TlsConnector>,
+
+    tcp: TcpConfig,
 }
 
 impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C, A, T> {
@@ -71,11 +73,13 @@ impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C,
         codec: C,
         authenticator: A,
         tls: Option<TlsConnectorConfig>,
+        tcp: TcpConfig,
     ) -> Result<Self> {
         Ok(Self {
             connect_timeout,
             lanes: Arc::new(Mutex::new(HashMap::new())),
             tls: tls.as_ref().map(TlsConnector::new).transpose()?,
+            tcp,
             codec,
             authenticator,
         })
@@ -162,13 +166,13 @@ impl<C: CodecBuilder + 'static, A: Authenticator<T>, T: Token> ConnectionPool<C,
     ) -> Result<Connection, ConnectionError<A::Error>> {
         let mut connection = if let Some(tls) = &self.tls {
             let tls_stream = tls
-                .connect(self.connect_timeout, address)
+                .connect(self.connect_timeout, address, &self.tcp)
                 .await
                 .map_err(ConnectionError::Other)?;
             let (rx, tx) = tokio::io::split(tls_stream);
             spawn_read_write_tasks(&self.codec, rx, tx)
         } else {
-            let tcp_stream = tcp::tcp_stream(self.connect_timeout, address)
+            let tcp_stream = tcp::tcp_stream(self.connect_timeout, address, &self.tcp)
                 .await
                 .map_err(ConnectionError::Other)?;
             let (rx, tx) = tcp_stream.into_split();
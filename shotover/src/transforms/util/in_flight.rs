@@ -0,0 +1,117 @@
+use crate::message::{Message, MessageId, Metadata};
+use anyhow::Result;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[cfg(feature = "cassandra")]
+use crate::frame::Frame;
+
+/// A request sent down the chain whose response is shared with identical requests received while it was in flight.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    state: Mutex<InFlightState>,
+}
+
+enum InFlightState {
+    /// Contains the `force_run_chain` of every connection waiting on the response
+    Pending(Vec<Arc<Notify>>),
+    /// Contains None if the request failed
+    Complete(Option<Message>),
+}
+
+impl Default for InFlightState {
+    fn default() -> Self {
+        InFlightState::Pending(vec![])
+    }
+}
+
+impl InFlight {
+    pub(crate) fn add_waiter(&self, force_run_chain: Arc<Notify>) {
+        if let InFlightState::Pending(waiters) = &mut *self.state.lock().unwrap() {
+            waiters.push(force_run_chain);
+        }
+    }
+
+    pub(crate) fn complete(&self, response: Option<Message>) {
+        let state = std::mem::replace(
+            &mut *self.state.lock().unwrap(),
+            InFlightState::Complete(response),
+        );
+        if let InFlightState::Pending(waiters) = state {
+            for waiter in waiters {
+                waiter.notify_one();
+            }
+        }
+    }
+
+    /// Returns None while the response has not yet been received and Some(None) if the request failed
+    pub(crate) fn response(&self) -> Option<Option<Message>> {
+        match &*self.state.lock().unwrap() {
+            InFlightState::Pending(_) => None,
+            InFlightState::Complete(response) => {
+                Some(response.as_ref().map(|x| x.clone_with_new_id()))
+            }
+        }
+    }
+}
+
+/// Removes the request from the in flight requests unless it has already been replaced by a newer identical request
+pub(crate) fn remove_in_flight<K, Q>(
+    requests: &Mutex<HashMap<K, Arc<InFlight>>>,
+    key: &Q,
+    in_flight: &Arc<InFlight>,
+) where
+    K: Borrow<Q> + Hash + Eq,
+    Q: Hash + Eq + ?Sized,
+{
+    let mut requests = requests.lock().unwrap();
+    if requests
+        .get(key)
+        .map(|x| Arc::ptr_eq(x, in_flight))
+        .unwrap_or(false)
+    {
+        requests.remove(key);
+    }
+}
+
+/// A request that was not sent down the chain as an identical request was already in flight
+pub(crate) struct Follower {
+    pub metadata: Metadata,
+    pub in_flight: Arc<InFlight>,
+}
+
+impl Follower {
+    pub(crate) fn response(
+        &self,
+        request_id: MessageId,
+        response: Option<Message>,
+    ) -> Result<Message> {
+        let mut response = match response {
+            Some(response) => response,
+            None => self.metadata.to_error_response(
+                "Failed to receive a response to the identical in flight request".to_owned(),
+            )?,
+        };
+        response.set_request_id(request_id);
+        #[cfg(feature = "cassandra")]
+        match &self.metadata {
+            Metadata::Cassandra(metadata) => {
+                if let Some(Frame::Cassandra(frame)) = response.frame() {
+                    frame.stream_id = metadata.stream_id;
+                    response.invalidate_cache();
+                }
+            }
+            #[cfg(any(
+                feature = "redis",
+                feature = "kafka",
+                feature = "opensearch",
+                feature = "memcached"
+            ))]
+            _ => {}
+        }
+        Ok(response)
+    }
+}
@@ -4,6 +4,10 @@ use std::fmt;
 use crate::message::Message;
 
 pub mod cluster_connection_pool;
+pub mod in_flight;
+pub mod ordered_responses;
+pub mod setup_replay;
+pub mod write_ahead_log;
 
 /// Represents a `Request` to a connection within Shotover
 #[derive(Debug)]
@@ -29,3 +33,56 @@ pub enum ConnectionError<E: fmt::Debug + fmt::Display> {
     #[error(transparent)]
     Other(#[from] Error),
 }
+
+/// Returns true if `value` matches the glob `pattern`, where `*` matches any sequence of bytes and `?` matches any single byte
+pub(crate) fn glob_match(pattern: &[u8], value: &[u8]) -> bool {
+    let (mut p, mut v) = (0, 0);
+    // The position of the last `*` in the pattern and the position in value it is currently matched up to
+    let mut backtrack = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            }
+            Some(b'?') => {
+                p += 1;
+                v += 1;
+            }
+            Some(x) if *x == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_v)) => {
+                    backtrack = Some((star_p, star_v + 1));
+                    p = star_p + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|x| *x == b'*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"user:*", b"user:1"));
+        assert!(glob_match(b"user:*", b"user:"));
+        assert!(!glob_match(b"user:*", b"admin:1"));
+        assert!(glob_match(b"ks.*", b"ks.table"));
+        assert!(!glob_match(b"ks.*", b"table"));
+        assert!(glob_match(b"a?c", b"abc"));
+        assert!(!glob_match(b"a?c", b"ac"));
+        assert!(glob_match(b"*:*:end", b"a:b:c:end"));
+        assert!(!glob_match(b"*:end", b"a:en"));
+        assert!(!glob_match(b"exact", b"exactly"));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::message::{Message, MessageId, MessageIdMap, Messages};
+use std::collections::VecDeque;
+
+/// Collects the responses of requests that were split across multiple sub-chains,
+/// returning them in the order their requests were received when the protocol requires responses to be in order.
+pub struct OrderedResponses {
+    in_order: bool,
+    /// The ids of the requests that have not yet been returned, in the order they were received
+    pending: VecDeque<MessageId>,
+    /// Responses waiting to be returned, keyed by the id of the request they respond to
+    responses: MessageIdMap<Message>,
+}
+
+impl OrderedResponses {
+    pub fn new(in_order: bool) -> Self {
+        OrderedResponses {
+            in_order,
+            pending: VecDeque::new(),
+            responses: MessageIdMap::default(),
+        }
+    }
+
+    /// Must be called for every request in the order they were received
+    pub fn push_request(&mut self, id: MessageId) {
+        if self.in_order {
+            self.pending.push_back(id);
+        }
+    }
+
+    /// Stores the response to the request with the given id until it can be returned
+    pub fn insert(&mut self, request_id: MessageId, response: Message) {
+        self.responses.insert(request_id, response);
+    }
+
+    /// Appends every response that can be returned to the client to `ready`
+    pub fn take_ready(&mut self, ready: &mut Messages) {
+        if self.in_order {
+            while let Some(response) = self
+                .pending
+                .front()
+                .and_then(|id| self.responses.remove(id))
+            {
+                self.pending.pop_front();
+                ready.push(response);
+            }
+        } else {
+            ready.extend(self.responses.drain().map(|(_, response)| response));
+        }
+    }
+}
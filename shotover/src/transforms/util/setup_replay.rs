@@ -0,0 +1,165 @@
+use crate::frame::Frame;
+use crate::message::{Message, MessageId, MessageIdMap, Messages};
+
+/// Returns true if the request sets up the state of the connection rather than accessing any data
+pub(crate) fn is_setup_request(request: &mut Message) -> bool {
+    match request.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(crate::frame::RedisFrame::Array(args))) => matches!(
+            args.first(),
+            Some(crate::frame::RedisFrame::BulkString(command))
+                if [&b"AUTH"[..], b"HELLO", b"SELECT"].contains(&command.to_ascii_uppercase().as_slice())
+        ),
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(crate::frame::CassandraFrame { operation, .. })) => matches!(
+            operation,
+            crate::frame::CassandraOperation::Startup(_)
+                | crate::frame::CassandraOperation::AuthResponse(_)
+        ),
+        _ => false,
+    }
+}
+
+/// Returns true if the request carries the client's credentials, e.g. redis `AUTH` or the cassandra auth response
+pub(crate) fn carries_credentials(request: &mut Message) -> bool {
+    match request.frame() {
+        #[cfg(feature = "redis")]
+        Some(Frame::Redis(crate::frame::RedisFrame::Array(args))) => match args.first() {
+            Some(crate::frame::RedisFrame::BulkString(command))
+                if command.eq_ignore_ascii_case(b"AUTH") =>
+            {
+                true
+            }
+            // HELLO only carries credentials when given the AUTH option
+            Some(crate::frame::RedisFrame::BulkString(command))
+                if command.eq_ignore_ascii_case(b"HELLO") =>
+            {
+                args.iter().skip(1).any(|arg| {
+                    matches!(arg, crate::frame::RedisFrame::BulkString(arg) if arg.eq_ignore_ascii_case(b"AUTH"))
+                })
+            }
+            _ => false,
+        },
+        #[cfg(feature = "cassandra")]
+        Some(Frame::Cassandra(crate::frame::CassandraFrame { operation, .. })) => {
+            matches!(operation, crate::frame::CassandraOperation::AuthResponse(_))
+        }
+        _ => false,
+    }
+}
+
+/// Replays the requests that set up the state of the client's connection, such as AUTH or the cassandra STARTUP,
+/// to each of the subchains of a routing transform so that all of the client's upstream connections are set up in the same way.
+pub(crate) struct SetupReplay {
+    setup_requests: Messages,
+    /// The number of `setup_requests` that each chain has received
+    sent: Vec<usize>,
+    /// The ids of replayed setup requests mapped to the chain they were sent to, their responses must not reach the client
+    replayed: MessageIdMap<usize>,
+}
+
+impl SetupReplay {
+    pub(crate) fn new(chains: usize) -> Self {
+        SetupReplay {
+            setup_requests: vec![],
+            sent: vec![0; chains],
+            replayed: MessageIdMap::default(),
+        }
+    }
+
+    /// Adds any setup requests that the chain has not received yet to the requests to be sent to the chain, ahead of `request`.
+    /// When `is_setup` is true `request` is also replayed to every other chain before its next request.
+    pub(crate) fn replay_to(
+        &mut self,
+        chain: usize,
+        request: &Message,
+        is_setup: bool,
+        routed: &mut Messages,
+    ) {
+        for setup_request in &self.setup_requests[self.sent[chain]..] {
+            let replay = setup_request.clone_with_new_id();
+            self.replayed.insert(replay.id(), chain);
+            routed.push(replay);
+        }
+        if is_setup {
+            self.setup_requests.push(request.clone());
+        }
+        self.sent[chain] = self.setup_requests.len();
+    }
+
+    /// Returns true if the response is to a replayed setup request and so must be dropped rather than returned to the client.
+    pub(crate) fn remove_replayed(&mut self, request_id: MessageId) -> bool {
+        self.replayed.remove(&request_id).is_some()
+    }
+
+    /// Forgets what was sent to the chain, so that every setup request is replayed to it again once it is rebuilt.
+    pub(crate) fn reset(&mut self, chain: usize) {
+        self.sent[chain] = 0;
+        self.replayed.retain(|_, x| *x != chain);
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use pretty_assertions::assert_eq;
+
+    fn commands(routed: &mut Messages) -> Vec<Vec<Frame>> {
+        routed
+            .iter_mut()
+            .map(|request| match request.frame() {
+                Some(Frame::Redis(crate::frame::RedisFrame::Array(args))) => {
+                    args.iter().cloned().map(Frame::Redis).collect()
+                }
+                _ => panic!("expected a redis array"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_setup_replay() {
+        let mut replay = SetupReplay::new(2);
+        let mut routed = [vec![], vec![]];
+        let mut push = |replay: &mut SetupReplay, chain: usize, request: Message, is_setup| {
+            replay.replay_to(chain, &request, is_setup, &mut routed[chain]);
+            routed[chain].push(request);
+        };
+
+        push(&mut replay, 0, redis_command(&["AUTH", "pass"]), true);
+        push(&mut replay, 0, redis_command(&["GET", "1"]), false);
+        push(&mut replay, 1, redis_command(&["GET", "2"]), false);
+        push(&mut replay, 1, redis_command(&["GET", "3"]), false);
+
+        assert_eq!(routed[0].len(), 2);
+        assert_eq!(
+            commands(&mut routed[1]),
+            commands(&mut vec![
+                redis_command(&["AUTH", "pass"]),
+                redis_command(&["GET", "2"]),
+                redis_command(&["GET", "3"]),
+            ])
+        );
+        let replayed_id = routed[1][0].id();
+        assert_ne!(replayed_id, routed[0][0].id());
+
+        // the replayed AUTH is sent again to a rebuilt chain
+        replay.reset(1);
+        assert!(!replay.remove_replayed(replayed_id));
+        let mut rebuilt = vec![];
+        replay.replay_to(1, &redis_command(&["GET", "4"]), false, &mut rebuilt);
+        assert_eq!(rebuilt.len(), 1);
+        assert!(replay.remove_replayed(rebuilt[0].id()));
+    }
+
+    #[test]
+    fn test_carries_credentials() {
+        assert!(carries_credentials(&mut redis_command(&["AUTH", "pass"])));
+        assert!(carries_credentials(&mut redis_command(&[
+            "HELLO", "3", "AUTH", "user", "pass"
+        ])));
+        assert!(!carries_credentials(&mut redis_command(&["HELLO", "3"])));
+        assert!(is_setup_request(&mut redis_command(&["HELLO", "3"])));
+        assert!(!is_setup_request(&mut redis_command(&["GET", "1"])));
+    }
+}
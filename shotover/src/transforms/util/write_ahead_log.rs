@@ -0,0 +1,845 @@
+use crate::message::{Message, MessageIdSet, Messages};
+use crate::transforms::chain::{TransformChain, TransformChainBuilder};
+use crate::transforms::util::setup_replay::{carries_credentials, is_setup_request};
+use crate::transforms::{ChainState, TransformContextBuilder};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use fnv::FnvHasher;
+use metrics::{counter, gauge, Counter, Gauge};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+#[cfg(any(feature = "redis", feature = "cassandra"))]
+use crate::{codec::CodecState, frame::MessageType};
+#[cfg(feature = "cassandra")]
+use {crate::frame::Frame, cassandra_protocol::compression::Compression};
+#[cfg(feature = "redis")]
+use {crate::message::Encodable, bytes::BytesMut, redis_protocol::resp2::encode::extend_encode};
+
+/// A record is made up of this header followed by the encoded request.
+/// The header contains the length of the encoded request, a checksum of the record type and encoded request, and the record type.
+const HEADER_LEN: usize = 4 + 8 + 1;
+#[cfg(feature = "redis")]
+const RECORD_REDIS: u8 = 0;
+#[cfg(feature = "cassandra")]
+const RECORD_CASSANDRA: u8 = 1;
+
+/// Segments are rolled over once they reach this size, or a quarter of the max disk usage if that is smaller.
+const MAX_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+/// The maximum number of requests sent down the chain at once when replaying the log.
+const MAX_BATCH_LEN: usize = 1000;
+/// How long to wait for responses from the chain before considering the delivery failed.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+/// The log is also checked for new records at this interval in case a notification is missed.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WriteAheadLogConfig {
+    /// The directory the log is stored in, it is created if it does not exist.
+    pub path: String,
+    /// Requests received while the log takes up this many bytes of disk are dropped.
+    pub max_disk_usage_bytes: u64,
+}
+
+/// Requests that set up the connection of the chain the log is replayed into, e.g. redis `AUTH` or cassandra `STARTUP`.
+enum SetupRequests {
+    /// No connection has sent setup requests yet
+    None,
+    /// The setup requests of the connection are being recorded until it sends a request that is not a setup request
+    Recording {
+        connection_id: u64,
+        records: Vec<u8>,
+    },
+    Complete,
+}
+
+struct Writer {
+    file: File,
+    segment: u64,
+    segment_len: u64,
+    setup: SetupRequests,
+}
+
+/// A log of requests stored on disk, that are replayed into a chain in the background.
+/// Requests are only removed from the log once the chain has returned a response for them, so every request is delivered at least once,
+/// even if shotover is restarted before they are delivered.
+///
+/// The log is stored in a directory containing:
+/// * numbered segment files containing the requests
+/// * a `cursor` file containing the segment and offset of the first request that has not been delivered
+/// * a `setup` file containing the requests used to set up the connection of the chain
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    max_disk_usage_bytes: u64,
+    segment_bytes: u64,
+    writer: Mutex<Writer>,
+    /// The bytes used by every segment on disk
+    disk_usage: AtomicU64,
+    /// The segment currently being appended to
+    current_segment: AtomicU64,
+    appended: Notify,
+    local_addr: Mutex<SocketAddr>,
+    dropped_requests: Counter,
+    disk_usage_gauge: Gauge,
+}
+
+impl WriteAheadLog {
+    /// Opens the log stored at the configured path, creating it if it does not exist,
+    /// and starts replaying any requests it contains into the chain.
+    pub fn open(
+        config: &WriteAheadLogConfig,
+        chain: TransformChainBuilder,
+        chain_name: &str,
+    ) -> Result<Arc<Self>> {
+        let dir = PathBuf::from(&config.path);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create write ahead log directory {dir:?}"))?;
+
+        let segments = list_segments(&dir)?;
+        let mut disk_usage = 0;
+        for segment in &segments {
+            disk_usage += fs::metadata(segment_path(&dir, *segment))?.len();
+        }
+
+        let (segment, file, segment_len) = match segments.last() {
+            Some(last) => {
+                // The last record may be incomplete if shotover was stopped while writing it
+                let path = segment_path(&dir, *last);
+                let valid_len = valid_len(&path)?;
+                let file = OpenOptions::new().append(true).open(&path)?;
+                let len = file.metadata()?.len();
+                if valid_len < len {
+                    tracing::warn!(
+                        "Discarding {} bytes of incomplete records from the end of {path:?}",
+                        len - valid_len
+                    );
+                    file.set_len(valid_len)?;
+                    disk_usage -= len - valid_len;
+                }
+                (*last, file, valid_len)
+            }
+            None => (0, create_segment(&dir, 0)?, 0),
+        };
+
+        let first_segment = segments.first().copied().unwrap_or(0);
+        let (cursor_segment, cursor_offset) = match read_cursor(&dir)? {
+            Some((cursor_segment, offset)) if cursor_segment >= first_segment => {
+                (cursor_segment, offset)
+            }
+            _ => (first_segment, 0),
+        };
+
+        let setup = if dir.join("setup").exists() {
+            SetupRequests::Complete
+        } else {
+            SetupRequests::None
+        };
+
+        let disk_usage_gauge =
+            gauge!("shotover_write_ahead_log_disk_usage_bytes", "chain" => chain_name.to_owned());
+        disk_usage_gauge.set(disk_usage as f64);
+        let log = Arc::new(WriteAheadLog {
+            dir,
+            max_disk_usage_bytes: config.max_disk_usage_bytes,
+            segment_bytes: (config.max_disk_usage_bytes / 4).clamp(1, MAX_SEGMENT_BYTES),
+            writer: Mutex::new(Writer {
+                file,
+                segment,
+                segment_len,
+                setup,
+            }),
+            disk_usage: AtomicU64::new(disk_usage),
+            current_segment: AtomicU64::new(segment),
+            appended: Notify::new(),
+            local_addr: Mutex::new(SocketAddr::from(([0, 0, 0, 0], 0))),
+            dropped_requests: counter!("shotover_write_ahead_log_dropped_requests_count", "chain" => chain_name.to_owned()),
+            disk_usage_gauge,
+        });
+
+        let consumer = Consumer {
+            log: log.clone(),
+            chain_builder: chain,
+            chain: None,
+            force_run_chain: Arc::new(Notify::new()),
+            cursor: Cursor {
+                segment: cursor_segment,
+                offset: cursor_offset,
+            },
+        };
+        tokio::spawn(consumer.run());
+
+        Ok(log)
+    }
+
+    /// Appends the requests to the log, returning once they have been synced to disk.
+    /// `connection_id` identifies the client connection the requests were received from.
+    ///
+    /// Requests that set up the connection are not appended to the log.
+    /// Instead the setup requests of the first connection are stored separately and used to set up the connection of the chain the log is replayed into.
+    /// Requests carrying credentials, such as redis `AUTH`, are dropped so that credentials are never stored on disk.
+    pub async fn append(
+        self: &Arc<Self>,
+        connection_id: u64,
+        local_addr: SocketAddr,
+        requests: Messages,
+    ) -> Result<()> {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || {
+            log.append_blocking(connection_id, local_addr, requests)
+        })
+        .await
+        .context("Write ahead log append task panicked")?
+    }
+
+    fn append_blocking(
+        &self,
+        connection_id: u64,
+        local_addr: SocketAddr,
+        requests: Messages,
+    ) -> Result<()> {
+        *self.local_addr.lock().unwrap() = local_addr;
+        let mut writer = self.writer.lock().unwrap();
+        let mut appended = false;
+        for mut request in requests {
+            // The client's credentials must never be stored on disk
+            if request.is_dummy() || carries_credentials(&mut request) {
+                continue;
+            }
+
+            if is_setup_request(&mut request) {
+                let record = encode_record(request)?;
+                match &mut writer.setup {
+                    SetupRequests::None => {
+                        write_file_atomic(&self.dir.join("setup"), &record)?;
+                        writer.setup = SetupRequests::Recording {
+                            connection_id,
+                            records: record,
+                        };
+                    }
+                    SetupRequests::Recording {
+                        connection_id: owner,
+                        records,
+                    } if *owner == connection_id => {
+                        records.extend(record);
+                        write_file_atomic(&self.dir.join("setup"), records)?;
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if let SetupRequests::Recording {
+                connection_id: owner,
+                ..
+            } = writer.setup
+            {
+                if owner == connection_id {
+                    writer.setup = SetupRequests::Complete;
+                }
+            }
+
+            let record = encode_record(request)?;
+            let disk_usage = self.disk_usage.load(Ordering::Relaxed);
+            if disk_usage + record.len() as u64 > self.max_disk_usage_bytes {
+                self.dropped_requests.increment(1);
+                continue;
+            }
+
+            if writer.segment_len >= self.segment_bytes {
+                // The records already written to the full segment must be durable before the consumer can move past it
+                writer.file.sync_data().with_context(|| {
+                    format!(
+                        "Failed to sync segment {} of write ahead log",
+                        writer.segment
+                    )
+                })?;
+                let segment = writer.segment + 1;
+                writer.file = create_segment(&self.dir, segment)?;
+                writer.segment = segment;
+                writer.segment_len = 0;
+                self.current_segment.store(segment, Ordering::Relaxed);
+            }
+            writer.file.write_all(&record).with_context(|| {
+                format!(
+                    "Failed to write to segment {} of write ahead log",
+                    writer.segment
+                )
+            })?;
+            writer.segment_len += record.len() as u64;
+            let disk_usage = self
+                .disk_usage
+                .fetch_add(record.len() as u64, Ordering::Relaxed)
+                + record.len() as u64;
+            self.disk_usage_gauge.set(disk_usage as f64);
+            appended = true;
+        }
+
+        if appended {
+            writer.file.sync_data().with_context(|| {
+                format!(
+                    "Failed to sync segment {} of write ahead log",
+                    writer.segment
+                )
+            })?;
+            self.appended.notify_one();
+        }
+        Ok(())
+    }
+}
+
+/// The position of the first request in the log that has not been delivered
+#[derive(Clone, Copy)]
+struct Cursor {
+    segment: u64,
+    offset: u64,
+}
+
+/// Replays the log into the chain in the background.
+struct Consumer {
+    log: Arc<WriteAheadLog>,
+    chain_builder: TransformChainBuilder,
+    chain: Option<TransformChain>,
+    force_run_chain: Arc<Notify>,
+    cursor: Cursor,
+}
+
+impl Consumer {
+    async fn run(mut self) {
+        loop {
+            // Stop once every transform using the log has been dropped
+            if Arc::strong_count(&self.log) == 1 {
+                return;
+            }
+            let log = self.log.clone();
+            let notified = log.appended.notified();
+            match self.next_batch().await {
+                Ok(Some((records, end))) => match self.deliver(records).await {
+                    Ok(()) => {
+                        self.cursor.offset = end;
+                        let dir = self.log.dir.clone();
+                        let cursor = self.cursor;
+                        let result =
+                            tokio::task::spawn_blocking(move || write_cursor(&dir, cursor))
+                                .await
+                                .map_err(anyhow::Error::from)
+                                .and_then(|x| x);
+                        if let Err(err) = result {
+                            tracing::error!("Failed to write write ahead log cursor: {err:?}");
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            "Failed to deliver requests from write ahead log, retrying: {err:?}"
+                        );
+                        self.chain = None;
+                        tokio::time::sleep(RETRY_INTERVAL).await;
+                    }
+                },
+                Ok(None) => {
+                    tokio::time::timeout(POLL_INTERVAL, notified).await.ok();
+                }
+                Err(err) => {
+                    tracing::error!("Failed to read write ahead log: {err:?}");
+                    tokio::time::sleep(RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Returns the next records that have not been delivered and the offset following them.
+    /// The log is read on a blocking thread so that the disk io does not stall the runtime.
+    async fn next_batch(&mut self) -> Result<Option<Batch>> {
+        let log = self.log.clone();
+        let mut cursor = self.cursor;
+        let (cursor, result) = tokio::task::spawn_blocking(move || {
+            let result = next_batch(&log, &mut cursor);
+            (cursor, result)
+        })
+        .await?;
+        self.cursor = cursor;
+        result
+    }
+
+    async fn deliver(&mut self, records: Vec<(u8, Bytes)>) -> Result<()> {
+        let local_addr = *self.log.local_addr.lock().unwrap();
+        if self.chain.is_none() {
+            let mut chain = self.chain_builder.build(TransformContextBuilder {
+                force_run_chain: self.force_run_chain.clone(),
+                client_details: String::new(),
+                stream_responses: false,
+                #[cfg(feature = "cassandra")]
+                schema_cache: None,
+            });
+            let setup_path = self.log.dir.join("setup");
+            let setup = match tokio::task::spawn_blocking(move || fs::read(setup_path)).await? {
+                Ok(setup) => decode_records(&setup)?,
+                Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+                Err(err) => return Err(err).context("Failed to read setup requests"),
+            };
+            if !setup.is_empty() {
+                send_and_await(&mut chain, &self.force_run_chain, setup, local_addr).await?;
+            }
+            self.chain = Some(chain);
+        }
+
+        let requests = records
+            .into_iter()
+            .filter_map(|(ty, payload)| match decode_request(ty, payload) {
+                Ok(request) => Some(request),
+                Err(err) => {
+                    tracing::error!("Skipping request in write ahead log: {err:?}");
+                    None
+                }
+            })
+            .collect();
+        send_and_await(
+            self.chain.as_mut().unwrap(),
+            &self.force_run_chain,
+            requests,
+            local_addr,
+        )
+        .await
+    }
+}
+
+/// Records read from the log along with the offset following them
+type Batch = (Vec<(u8, Bytes)>, u64);
+
+/// Returns the records following the cursor, moving the cursor past any segments that have been completely delivered.
+fn next_batch(log: &WriteAheadLog, cursor: &mut Cursor) -> Result<Option<Batch>> {
+    loop {
+        // Must be loaded before reading so that no records can be appended to this segment after it is found to be complete
+        let current_segment = log.current_segment.load(Ordering::Relaxed);
+        let path = segment_path(&log.dir, cursor.segment);
+        let mut file = File::open(&path)
+            .with_context(|| format!("Failed to open write ahead log segment {path:?}"))?;
+        file.seek(SeekFrom::Start(cursor.offset))?;
+        let mut reader = BufReader::new(file);
+
+        let mut records = vec![];
+        let mut end = cursor.offset;
+        while records.len() < MAX_BATCH_LEN {
+            match read_record(&mut reader)? {
+                Some((ty, payload)) => {
+                    end += (HEADER_LEN + payload.len()) as u64;
+                    records.push((ty, payload));
+                }
+                None => break,
+            }
+        }
+
+        if !records.is_empty() {
+            return Ok(Some((records, end)));
+        }
+        if current_segment == cursor.segment {
+            return Ok(None);
+        }
+
+        // Every record in this segment has been delivered and no more will be appended to it
+        let len = fs::metadata(&path)?.len();
+        fs::remove_file(&path)?;
+        let disk_usage = log.disk_usage.fetch_sub(len, Ordering::Relaxed) - len;
+        log.disk_usage_gauge.set(disk_usage as f64);
+        cursor.segment += 1;
+        cursor.offset = 0;
+        write_cursor(&log.dir, *cursor)?;
+    }
+}
+
+/// Sends the requests down the chain and waits until a response to every request is received
+async fn send_and_await(
+    chain: &mut TransformChain,
+    force_run_chain: &Notify,
+    requests: Messages,
+    local_addr: SocketAddr,
+) -> Result<()> {
+    let mut pending: MessageIdSet = requests.iter().map(|x| x.id()).collect();
+    let mut requests = requests;
+    loop {
+        let responses = chain
+            .process_request(&mut ChainState::new_with_addr(
+                std::mem::take(&mut requests),
+                local_addr,
+            ))
+            .await?;
+        for response in responses {
+            if let Some(request_id) = response.request_id() {
+                pending.remove(&request_id);
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        tokio::time::timeout(DELIVERY_TIMEOUT, force_run_chain.notified())
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for {} responses", pending.len()))?;
+    }
+}
+
+fn segment_path(dir: &Path, segment: u64) -> PathBuf {
+    dir.join(format!("{segment:020}.log"))
+}
+
+fn create_segment(dir: &Path, segment: u64) -> Result<File> {
+    let path = segment_path(dir, segment);
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to create write ahead log segment {path:?}"))?;
+    sync_dir(dir)?;
+    Ok(file)
+}
+
+/// Returns the ids of every segment in the directory in ascending order
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut segments = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        if let Some(segment) = name
+            .to_str()
+            .and_then(|x| x.strip_suffix(".log"))
+            .and_then(|x| x.parse().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Returns the length of the segment up to the end of its last complete record
+fn valid_len(path: &Path) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut len = 0;
+    while let Some((_, payload)) = read_record(&mut reader)? {
+        len += (HEADER_LEN + payload.len()) as u64;
+    }
+    Ok(len)
+}
+
+/// Returns None if there is no cursor, in which case the log is replayed from the start of the oldest segment
+fn read_cursor(dir: &Path) -> Result<Option<(u64, u64)>> {
+    match fs::read(dir.join("cursor")) {
+        Ok(bytes) => {
+            if bytes.len() != 16 {
+                // Replaying from the start may deliver some requests again, which at-least-once delivery allows
+                tracing::warn!(
+                    "Write ahead log cursor is corrupt, replaying from the start of the oldest segment"
+                );
+                return Ok(None);
+            }
+            Ok(Some((
+                u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+                u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+            )))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err).context("Failed to read write ahead log cursor"),
+    }
+}
+
+fn write_cursor(dir: &Path, cursor: Cursor) -> Result<()> {
+    let mut bytes = cursor.segment.to_be_bytes().to_vec();
+    bytes.extend(cursor.offset.to_be_bytes());
+    write_file_atomic(&dir.join("cursor"), &bytes)
+}
+
+/// Writes the file such that it is never observed partially written, even if the machine crashes while writing it
+fn write_file_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_data()?;
+    fs::rename(&tmp, path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// Makes the creation, removal or renaming of files in the directory durable
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync write ahead log directory {dir:?}"))
+}
+
+fn checksum(ty: u8, payload: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write_u8(ty);
+    hasher.write(payload);
+    hasher.finish()
+}
+
+fn encode_record(request: Message) -> Result<Vec<u8>> {
+    let (ty, payload) = encode_request(request)?;
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend((payload.len() as u32).to_be_bytes());
+    record.extend(checksum(ty, &payload).to_be_bytes());
+    record.push(ty);
+    record.extend(payload);
+    Ok(record)
+}
+
+/// Returns None if there are no more complete records
+fn read_record(reader: &mut impl Read) -> Result<Option<(u8, Bytes)>> {
+    let mut header = [0; HEADER_LEN];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    let expected_checksum = u64::from_be_bytes(header[4..12].try_into().unwrap());
+    let ty = header[12];
+
+    // The length is not yet verified by the checksum, so rather than allocating it upfront,
+    // the payload only grows as bytes are read, bounding it by the bytes remaining in the segment.
+    let mut payload = vec![];
+    reader.take(len as u64).read_to_end(&mut payload)?;
+    if payload.len() < len {
+        return Ok(None);
+    }
+    if checksum(ty, &payload) != expected_checksum {
+        return Ok(None);
+    }
+    Ok(Some((ty, payload.into())))
+}
+
+fn decode_records(mut bytes: &[u8]) -> Result<Messages> {
+    let mut requests = vec![];
+    while let Some((ty, payload)) = read_record(&mut bytes)? {
+        requests.push(decode_request(ty, payload)?);
+    }
+    Ok(requests)
+}
+
+/// Encodes the request as it would be sent by a client, returning the type of record it is stored as
+pub(crate) fn encode_request(request: Message) -> Result<(u8, Bytes)> {
+    match request.message_type() {
+        #[cfg(feature = "redis")]
+        MessageType::Redis => {
+            let bytes = match request.into_encodable() {
+                Encodable::Bytes(bytes) => bytes,
+                Encodable::Frame(frame) => {
+                    let mut bytes = BytesMut::new();
+                    extend_encode(&mut bytes, &frame.into_redis()?)
+                        .map_err(|e| anyhow!("Redis encoding error: {e}"))?;
+                    bytes.freeze()
+                }
+            };
+            Ok((RECORD_REDIS, bytes))
+        }
+        // Stored uncompressed as the compression used by the client connection may differ from that of the chain the log is replayed into
+        #[cfg(feature = "cassandra")]
+        MessageType::Cassandra => match request.into_frame() {
+            Some(Frame::Cassandra(frame)) => {
+                Ok((RECORD_CASSANDRA, frame.encode(Compression::None).into()))
+            }
+            _ => Err(anyhow!("Failed to parse cassandra request")),
+        },
+        message_type => Err(anyhow!(
+            "{message_type:?} requests cannot be written to the write ahead log"
+        )),
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "redis", feature = "cassandra")),
+    allow(unused_variables)
+)]
+fn decode_request(ty: u8, payload: Bytes) -> Result<Message> {
+    match ty {
+        #[cfg(feature = "redis")]
+        RECORD_REDIS => Ok(Message::from_bytes(payload, CodecState::Redis)),
+        #[cfg(feature = "cassandra")]
+        RECORD_CASSANDRA => Ok(Message::from_bytes(
+            payload,
+            CodecState::Cassandra {
+                compression: Compression::None,
+            },
+        )),
+        ty => Err(anyhow!("Unknown record type {ty}")),
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod test {
+    use super::*;
+    use crate::test_utils::redis_command;
+    use crate::transforms::debug::returner::{DebugReturner, Response};
+    use crate::transforms::loopback::Loopback;
+    use pretty_assertions::assert_eq;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("shotover-wal-test-{}", rand::random::<u64>()))
+    }
+
+    fn config(dir: &Path, max_disk_usage_bytes: u64) -> WriteAheadLogConfig {
+        WriteAheadLogConfig {
+            path: dir.to_str().unwrap().to_owned(),
+            max_disk_usage_bytes,
+        }
+    }
+
+    async fn wait_for_cursor(dir: &Path, expected: (u64, u64)) {
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while read_cursor(dir).unwrap() != Some(expected) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_cursor() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        write_cursor(
+            &dir,
+            Cursor {
+                segment: 1,
+                offset: 2,
+            },
+        )
+        .unwrap();
+        assert_eq!(read_cursor(&dir).unwrap(), Some((1, 2)));
+
+        // a torn cursor is treated as though there were no cursor
+        fs::write(dir.join("cursor"), [0; 5]).unwrap();
+        assert_eq!(read_cursor(&dir).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_roundtrip() {
+        let mut bytes = encode_record(redis_command(&["SET", "foo", "bar"])).unwrap();
+        bytes.extend(encode_record(redis_command(&["GET", "foo"])).unwrap());
+        let complete_len = bytes.len();
+
+        // an incomplete record at the end is ignored
+        bytes.extend(&encode_record(redis_command(&["GET", "bar"])).unwrap()[..HEADER_LEN + 2]);
+        let requests: Vec<_> = decode_records(&bytes)
+            .unwrap()
+            .into_iter()
+            .map(|mut x| x.frame().cloned().unwrap())
+            .collect();
+        assert_eq!(
+            requests,
+            vec![
+                redis_command(&["SET", "foo", "bar"])
+                    .frame()
+                    .cloned()
+                    .unwrap(),
+                redis_command(&["GET", "foo"]).frame().cloned().unwrap(),
+            ]
+        );
+
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let path = segment_path(&dir, 0);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(valid_len(&path).unwrap(), complete_len as u64);
+
+        // a corrupted record is ignored
+        bytes[complete_len - 1] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(decode_records(&bytes).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_record_corrupt_len() {
+        // a corrupted header claiming a huge record is treated as an incomplete record
+        let mut bytes = encode_record(redis_command(&["GET", "foo"])).unwrap();
+        bytes[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(decode_records(&bytes).unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_delivered() {
+        let dir = temp_dir();
+        let chain = TransformChainBuilder::new(vec![Box::new(Loopback::default())], "wal");
+        let log = WriteAheadLog::open(&config(&dir, 1024 * 1024), chain, "test").unwrap();
+        log.append(
+            1,
+            "127.0.0.1:6379".parse().unwrap(),
+            vec![
+                redis_command(&["SELECT", "1"]),
+                redis_command(&["SET", "foo", "bar"]),
+                redis_command(&["SET", "foo", "baz"]),
+            ],
+        )
+        .await
+        .unwrap();
+
+        // the setup request is stored separately from the log
+        assert_eq!(
+            decode_records(&fs::read(dir.join("setup")).unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+        let segment_len = fs::metadata(segment_path(&dir, 0)).unwrap().len();
+        wait_for_cursor(&dir, (0, segment_len)).await;
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_retained_until_delivered() {
+        let dir = temp_dir();
+        let chain =
+            TransformChainBuilder::new(vec![Box::new(DebugReturner::new(Response::Fail))], "wal");
+        let log = WriteAheadLog::open(&config(&dir, 1024 * 1024), chain, "test").unwrap();
+        log.append(
+            1,
+            "127.0.0.1:6379".parse().unwrap(),
+            vec![redis_command(&["SET", "foo", "bar"])],
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(read_cursor(&dir).unwrap(), None);
+        drop(log);
+
+        // the request is delivered once shotover is restarted with a working chain
+        let chain = TransformChainBuilder::new(vec![Box::new(Loopback::default())], "wal");
+        let _log = WriteAheadLog::open(&config(&dir, 1024 * 1024), chain, "test").unwrap();
+        let segment_len = fs::metadata(segment_path(&dir, 0)).unwrap().len();
+        wait_for_cursor(&dir, (0, segment_len)).await;
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_disk_usage() {
+        let dir = temp_dir();
+        let record_len = encode_record(redis_command(&["SET", "foo", "bar"]))
+            .unwrap()
+            .len() as u64;
+        let chain =
+            TransformChainBuilder::new(vec![Box::new(DebugReturner::new(Response::Fail))], "wal");
+        let log = WriteAheadLog::open(&config(&dir, record_len * 2), chain, "test").unwrap();
+        log.append(
+            1,
+            "127.0.0.1:6379".parse().unwrap(),
+            vec![
+                redis_command(&["SET", "foo", "bar"]),
+                redis_command(&["SET", "foo", "bar"]),
+                redis_command(&["SET", "foo", "bar"]),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(log.disk_usage.load(Ordering::Relaxed), record_len * 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+//! Zero downtime upgrades by handing off the listening sockets of every source to a new shotover process.
+//!
+//! When `upgrade_socket` is configured, shotover listens on that unix socket for a new shotover process.
+//! A new process started with the same `upgrade_socket` connects to it at startup and receives the listening sockets
+//! of the old process, so its sources accept connections on the same sockets instead of binding new ones.
+//! Once its sources are running the new process notifies the old process, which stops accepting connections
+//! and shuts down as it would on SIGTERM, draining its existing connections.
+//! The new process then listens on `upgrade_socket` for the next upgrade.
+//!
+//! Listening sockets never close during this process, so clients never have their connection attempts refused.
+//!
+//! Whoever connects to `upgrade_socket` receives every listening socket, so the socket is only accessible to the user running shotover
+//! and both processes refuse a peer running as a different user.
+
+use crate::listen_fds::{self, InheritedListener};
+use anyhow::{anyhow, bail, Context, Result};
+use rustix::net::{
+    RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer,
+    SendAncillaryMessage, SendFlags,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, IoSlice, IoSliceMut, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::UnixListener;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+
+/// The maximum number of file descriptors that can be sent in a single message on linux
+const MAX_FDS: usize = 253;
+
+/// Sent by the new process once its sources are running
+const READY: u8 = 1;
+
+/// How long the new process waits for the old process to send its sockets
+const HANDOFF_TIMEOUT: Duration = Duration::from_secs(10);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The connection to the previous process, held until this process is ready to take over from it.
+static PREVIOUS: Mutex<Option<UnixStream>> = Mutex::new(None);
+
+static HANDED_OFF: Notify = Notify::const_new();
+
+#[derive(Serialize, Deserialize)]
+struct ListenerDescription {
+    name: Option<String>,
+    address: SocketAddr,
+}
+
+/// Returns true if upgrades are configured, in which case sources keep their listening sockets for handing off.
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Receives the listening sockets of the shotover process listening on `path`, if there is one.
+/// Must be called at startup before any sources are created.
+pub(crate) fn take_over(path: &str) -> Result<()> {
+    ENABLED.store(true, Ordering::Relaxed);
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        // No shotover process is running, so this is a regular startup
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(())
+        }
+        Err(err) => {
+            return Err(anyhow!(err).context(format!("Failed to connect to upgrade socket {path}")))
+        }
+    };
+    check_peer(&stream)
+        .with_context(|| format!("Refused to take over from the process listening on {path}"))?;
+    stream.set_read_timeout(Some(HANDOFF_TIMEOUT))?;
+
+    let mut len = [0; 4];
+    let mut space = vec![0; rustix::cmsg_space!(ScmRights(MAX_FDS))];
+    let mut control = RecvAncillaryBuffer::new(&mut space);
+    let received = rustix::net::recvmsg(
+        &stream,
+        &mut [IoSliceMut::new(&mut len)],
+        &mut control,
+        RecvFlags::CMSG_CLOEXEC,
+    )
+    .context("Failed to receive listening sockets from the previous shotover process")?;
+    let fds: Vec<OwnedFd> = control
+        .drain()
+        .flat_map(|message| match message {
+            RecvAncillaryMessage::ScmRights(fds) => fds.collect(),
+            _ => vec![],
+        })
+        .collect();
+    if received.bytes == 0 {
+        bail!("The previous shotover process closed the upgrade socket without sending its listening sockets");
+    }
+    stream.read_exact(&mut len[received.bytes..])?;
+
+    let mut descriptions = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut descriptions)?;
+    let descriptions: Vec<ListenerDescription> = serde_json::from_slice(&descriptions)?;
+    if descriptions.len() != fds.len() {
+        bail!(
+            "The previous shotover process described {} listening sockets but sent {}",
+            descriptions.len(),
+            fds.len()
+        );
+    }
+
+    for (description, fd) in descriptions.into_iter().zip(fds) {
+        info!(
+            "Took over listening socket {} named {:?} from the previous shotover process",
+            description.address, description.name
+        );
+        listen_fds::add(InheritedListener {
+            name: description.name,
+            address: description.address,
+            listener: std::net::TcpListener::from(fd),
+        });
+    }
+    *PREVIOUS.lock().unwrap() = Some(stream);
+    Ok(())
+}
+
+/// Called once every source is running.
+/// Notifies the previous shotover process that it can shut down and starts listening on `path` for the next upgrade.
+pub(crate) fn listen(path: &str) -> Result<()> {
+    // The path may still belong to the previous process, unlinking it does not affect the previous process's socket
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => {
+            return Err(anyhow!(err).context(format!("Failed to remove upgrade socket {path}")))
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind upgrade socket {path}"))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict permissions of upgrade socket {path}"))?;
+
+    if let Some(mut previous) = PREVIOUS.lock().unwrap().take() {
+        previous
+            .write_all(&[READY])
+            .context("Failed to notify the previous shotover process")?;
+        info!("Took over from the previous shotover process, it will now shut down");
+    }
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    error!("Failed to accept connection on the upgrade socket: {err:?}");
+                    return;
+                }
+            };
+            if let Err(err) = check_peer(&stream) {
+                warn!(
+                    "{:?}",
+                    err.context("Refused connection on the upgrade socket")
+                );
+                continue;
+            }
+            info!("A new shotover process connected to the upgrade socket");
+            let result = match stream.into_std() {
+                Ok(stream) => tokio::task::spawn_blocking(move || hand_off(stream))
+                    .await
+                    .map_err(|err| anyhow!(err))
+                    .and_then(|x| x),
+                Err(err) => Err(err.into()),
+            };
+            match result {
+                Ok(true) => {
+                    HANDED_OFF.notify_one();
+                    return;
+                }
+                Ok(false) => warn!("The new shotover process exited before it was ready to take over, this process will keep running"),
+                Err(err) => error!("{:?}", err.context("Failed to hand off listening sockets to the new shotover process")),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Fails unless the process on the other end of the upgrade socket is running as the same user as this process.
+fn check_peer(stream: impl AsFd) -> Result<()> {
+    let peer = rustix::net::sockopt::get_socket_peercred(stream)
+        .context("Failed to get the credentials of the peer")?;
+    let uid = rustix::process::getuid();
+    if peer.uid != uid {
+        bail!(
+            "The peer is running as uid {} but this process is running as uid {}",
+            peer.uid.as_raw(),
+            uid.as_raw()
+        );
+    }
+    Ok(())
+}
+
+/// Sends every listening socket to the new process, returning true once the new process is ready to take over.
+fn hand_off(mut stream: UnixStream) -> Result<bool> {
+    stream.set_nonblocking(false)?;
+    let listeners = listen_fds::all()?;
+    if listeners.len() > MAX_FDS {
+        bail!("Cannot hand off more than {MAX_FDS} listening sockets");
+    }
+    let descriptions = serde_json::to_vec(
+        &listeners
+            .iter()
+            .map(|x| ListenerDescription {
+                name: x.name.clone(),
+                address: x.address,
+            })
+            .collect::<Vec<_>>(),
+    )?;
+    let fds: Vec<BorrowedFd> = listeners.iter().map(|x| x.listener.as_fd()).collect();
+
+    let len = (descriptions.len() as u32).to_be_bytes();
+    let mut space = vec![0; rustix::cmsg_space!(ScmRights(fds.len()))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    if !fds.is_empty() && !control.push(SendAncillaryMessage::ScmRights(&fds)) {
+        bail!("Failed to fit the listening sockets into a single message");
+    }
+    // The file descriptors are attached to the first byte sent, any remaining bytes are sent normally
+    let sent = rustix::net::sendmsg(
+        &stream,
+        &[IoSlice::new(&len)],
+        &mut control,
+        SendFlags::empty(),
+    )?;
+    stream.write_all(&len[sent..])?;
+    stream.write_all(&descriptions)?;
+
+    let mut ready = [0];
+    match stream.read_exact(&mut ready) {
+        Ok(()) => Ok(ready[0] == READY),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Completes once the listening sockets have been handed off to a new shotover process that is ready to take over.
+pub(crate) async fn handed_off() {
+    HANDED_OFF.notified().await
+}
@@ -0,0 +1,151 @@
+//! Reads and writes client connections with io_uring instead of epoll.
+//!
+//! io_uring submits reads and writes to the kernel through a ring buffer shared with it,
+//! avoiding a readiness notification plus a separate read or write syscall for every operation.
+//! This reduces syscall overhead when many connections each send small messages.
+//!
+//! The io_uring driver is single threaded, so this is only used by the runtimes of `--runtime per-core`.
+
+use crate::codec::{CodecBuilder, CodecReadError, CodecWriteError};
+use crate::message::Messages;
+use anyhow::Result;
+use bytes::BytesMut;
+use std::io::ErrorKind;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_uring::net::TcpStream;
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::Instrument;
+use tracing::{debug, error, warn};
+
+/// Matches the initial capacity of the buffer used by `FramedRead`
+const READ_BUFFER_LEN: usize = 8 * 1024;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes all client connections accepted from now on use io_uring.
+/// Must only be called when every source runs on a runtime created by [`runtime`].
+pub(crate) fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub(crate) fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Creates a single threaded runtime that drives io_uring alongside the regular tokio drivers,
+/// so tokio types like timers and `tokio::net` can still be used on it.
+pub(crate) fn runtime() -> Result<tokio_uring::Runtime> {
+    Ok(tokio_uring::Runtime::new(&tokio_uring::builder())?)
+}
+
+/// The io_uring equivalent of [`crate::server::spawn_read_write_tasks`], see there for how the tasks shut down.
+pub(crate) fn spawn_read_write_tasks<C: CodecBuilder + 'static>(
+    codec: C,
+    stream: tokio::net::TcpStream,
+    in_tx: mpsc::Sender<Messages>,
+    mut out_rx: UnboundedReceiver<Messages>,
+    out_tx: UnboundedSender<Messages>,
+) -> Result<()> {
+    let (mut decoder, mut encoder) = codec.build();
+    // Both tasks run on the same thread so can share the stream
+    let stream = Rc::new(TcpStream::from_std(stream.into_std()?));
+
+    // reader task
+    let reader = stream.clone();
+    tokio_uring::spawn(
+        async move {
+            let mut received = BytesMut::new();
+            let mut buf = vec![0; READ_BUFFER_LEN];
+            loop {
+                let (result, read_buf) = tokio::select! {
+                    result = reader.read(buf) => result,
+                    _ = in_tx.closed() => {
+                        // main task has shutdown, this task is no longer needed
+                        return;
+                    }
+                };
+                buf = read_buf;
+                match result {
+                    Ok(0) => {
+                        debug!("client has closed the connection");
+                        return;
+                    }
+                    Ok(len) => received.extend_from_slice(&buf[..len]),
+                    Err(err) => {
+                        if !matches!(err.kind(), ErrorKind::ConnectionReset) {
+                            warn!("failed to receive message on tcp stream: {:?}", err);
+                        }
+                        return;
+                    }
+                }
+
+                loop {
+                    match decoder.decode(&mut received) {
+                        Ok(Some(messages)) => {
+                            if in_tx.send(messages).await.is_err() {
+                                // main task has shutdown, this task is no longer needed
+                                return;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(CodecReadError::RespondAndThenCloseConnection(messages)) => {
+                            if let Err(err) = out_tx.send(messages) {
+                                error!(
+                                    "Failed to send RespondAndThenCloseConnection message: {:?}",
+                                    err
+                                );
+                            }
+                            return;
+                        }
+                        Err(CodecReadError::Parser(err)) => {
+                            warn!("failed to decode message: {:?}", err);
+                            return;
+                        }
+                        Err(CodecReadError::Io(err)) => {
+                            warn!("failed to receive message on tcp stream: {:?}", err);
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    // sender task
+    tokio_uring::spawn(
+        async move {
+            let mut encoded = BytesMut::new();
+            while let Some(messages) = out_rx.recv().await {
+                match encoder.encode(messages, &mut encoded) {
+                    Ok(()) => {}
+                    Err(CodecWriteError::Encoder(err)) => {
+                        error!("failed to encode message destined for client: {err:?}");
+                        continue;
+                    }
+                    Err(CodecWriteError::Io(err)) => {
+                        error!("failed to send message to client: {err:?}");
+                        continue;
+                    }
+                }
+                let (result, _) = stream.write_all(encoded.split().to_vec()).await;
+                if let Err(err) = result {
+                    if matches!(
+                        err.kind(),
+                        ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+                    ) {
+                        debug!("client disconnected before it could receive a response");
+                        return;
+                    } else {
+                        error!("failed to send message to client: {err:?}");
+                    }
+                }
+            }
+        }
+        .in_current_span(),
+    );
+
+    Ok(())
+}
@@ -13,6 +13,7 @@ pub struct ShotoverProcessBuilder {
     log_name: Option<String>,
     cores: Option<String>,
     profile: Option<String>,
+    args: Vec<String>,
     event_matchers: Vec<EventMatcher>,
 }
 
@@ -28,6 +29,7 @@ impl ShotoverProcessBuilder {
             log_name: None,
             cores: None,
             profile: None,
+            args: vec![],
             event_matchers: vec![],
         }
     }
@@ -58,6 +60,12 @@ impl ShotoverProcessBuilder {
         self
     }
 
+    /// Pass additional command line arguments to shotover
+    pub fn with_args(mut self, args: &[&str]) -> Self {
+        self.args = args.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
     /// Force shotover to be compiled with the specified profile
     pub fn with_profile(mut self, profile: Option<&str>) -> Self {
         if let Some(profile) = profile {
@@ -109,6 +117,7 @@ impl ShotoverProcessBuilder {
             .clone()
             .unwrap_or_else(|| "config/config.yaml".to_owned());
         args.extend(["-c", &config_path]);
+        args.extend(self.args.iter().map(|x| x.as_str()));
 
         let log_name = self.log_name.as_deref().unwrap_or("shotover");
 